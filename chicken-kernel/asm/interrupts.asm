@@ -320,3 +320,15 @@ section .text
         ; vector number
         push 33
         jmp interrupt_stub
+
+    ; remaining user defined vector numbers (34-255)
+    %assign vector 34
+    %rep 256 - 34
+    align 16
+    vector_%+vector%+_handler:
+        push 0
+        ; vector number
+        push vector
+        jmp interrupt_stub
+    %assign vector vector + 1
+    %endrep
@@ -0,0 +1,164 @@
+bits 64
+
+; contains code for entering and waking up from the acpi sleep state S3
+
+; cycles to wait for the platform to enter the sleep state, before giving up
+SLEEP_TIMEOUT equ 100000000
+IA32_EFER equ 0xC0000080
+
+section .text
+; wake up trampoline, copied to a page below 1 MiB by the kernel. The firmware jumps to it in real mode with cs pointing at its start.
+; the data block must match TrampolineData in src/base/power/sleep.rs, addresses within the trampoline are assembled as offsets and relocated by the kernel.
+global wake_trampoline_start
+global wake_trampoline_end
+
+bits 16
+wake_trampoline_start:
+    jmp short .real_mode
+    times 6 - ($ - wake_trampoline_start) db 0
+
+.gdt_limit:
+    dw .gdt_end - .gdt - 1
+.gdt_base:
+    dd .gdt - wake_trampoline_start
+    dd 0
+.protected_mode_entry:
+    dd .protected_mode - wake_trampoline_start
+    dw 0x08
+    dw 0
+.long_mode_entry:
+    dd .long_mode - wake_trampoline_start
+    dw 0x18
+    dw 0
+.cr3:
+    dq 0
+.cr4:
+    dq 0
+.efer:
+    dq 0
+.cr0:
+    dq 0
+; virtual address of acpi_wake
+.wake_entry:
+    dq 0
+; virtual address of the context saved by acpi_enter_sleep
+.context:
+    dq 0
+
+.gdt:
+    dq 0
+    ; 0x08: 32-bit code
+    dq 0x00CF9A000000FFFF
+    ; 0x10: data
+    dq 0x00CF92000000FFFF
+    ; 0x18: 64-bit code
+    dq 0x00AF9A000000FFFF
+.gdt_end:
+
+.real_mode:
+    cli
+    cld
+    mov ax, cs
+    mov ds, ax
+    ; keep the physical address of the trampoline in ebx for the following modes
+    movzx ebx, ax
+    shl ebx, 4
+
+    o32 lgdt [.gdt_limit - wake_trampoline_start]
+
+    ; enable protected mode
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+    o32 jmp far [.protected_mode_entry - wake_trampoline_start]
+
+bits 32
+.protected_mode:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; restore the paging configuration of the kernel, the trampoline is identity mapped
+    mov eax, [ebx + .cr4 - wake_trampoline_start]
+    mov cr4, eax
+    mov eax, [ebx + .cr3 - wake_trampoline_start]
+    mov cr3, eax
+
+    mov ecx, IA32_EFER
+    mov eax, [ebx + .efer - wake_trampoline_start]
+    mov edx, [ebx + .efer - wake_trampoline_start + 4]
+    wrmsr
+
+    ; enable paging, which activates long mode
+    mov eax, [ebx + .cr0 - wake_trampoline_start]
+    mov cr0, eax
+    jmp far [ebx + .long_mode_entry - wake_trampoline_start]
+
+bits 64
+.long_mode:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; the upper half of rbx is undefined after the mode switch
+    mov ebx, ebx
+    mov rdi, [rbx + .context - wake_trampoline_start]
+    mov rax, [rbx + .wake_entry - wake_trampoline_start]
+    jmp rax
+wake_trampoline_end:
+
+global acpi_enter_sleep
+
+; saves the callee saved registers to the context in rdi and writes the values in cx and r8w to the pm1a (si) and pm1b (dx, 0 if not present) control registers
+; returns 1 after waking up (via acpi_wake) or 0, if the platform did not enter the sleep state
+acpi_enter_sleep:
+mov [rdi], rbx
+mov [rdi + 8], rbp
+mov [rdi + 16], r12
+mov [rdi + 24], r13
+mov [rdi + 32], r14
+mov [rdi + 40], r15
+mov [rdi + 48], rsp
+
+; memory keeps its content while sleeping, the caches do not
+wbinvd
+
+mov r9w, dx
+mov dx, si
+mov ax, cx
+out dx, ax
+
+test r9w, r9w
+jz .wait
+mov dx, r9w
+mov ax, r8w
+out dx, ax
+
+.wait:
+mov rcx, SLEEP_TIMEOUT
+.spin:
+pause
+dec rcx
+jnz .spin
+
+; return 0
+xor rax, rax
+ret
+
+global acpi_wake
+
+; called by the wake up trampoline with the context saved by acpi_enter_sleep in rdi, returns from acpi_enter_sleep
+acpi_wake:
+mov rbx, [rdi]
+mov rbp, [rdi + 8]
+mov r12, [rdi + 16]
+mov r13, [rdi + 24]
+mov r14, [rdi + 32]
+mov r15, [rdi + 40]
+mov rsp, [rdi + 48]
+
+; return 1
+mov rax, 1
+ret
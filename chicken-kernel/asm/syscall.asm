@@ -0,0 +1,121 @@
+bits 64
+
+; contains the entry point of the syscall instruction
+
+extern syscall_dispatch
+
+; must match USER_ADDRESS_LIMIT in src/base/syscall.rs
+USER_ADDRESS_LIMIT equ 0x0000800000000000
+; user selectors with the requested privilege level 3, must match src/base/gdt.rs
+USER_SS equ 0x18 | 3
+USER_CS equ 0x20 | 3
+
+section .bss
+; top of the kernel stack of the current thread, set by the scheduler on every switch
+syscall_kernel_stack:
+    resq 1
+; stack pointer of the calling thread, while it is switched to the kernel stack
+syscall_user_stack:
+    resq 1
+
+section .text
+global set_syscall_stack
+
+; sets the kernel stack syscalls run on to the address passed to the function
+set_syscall_stack:
+    mov [rel syscall_kernel_stack], rdi
+    ret
+
+global jump_to_user_mode
+
+; starts executing user code at the address in rdi with the stack pointer in rsi and interrupts enabled. does not return.
+jump_to_user_mode:
+    push qword USER_SS
+    push rsi
+    ; interrupts enabled and the reserved bit 1
+    push qword 0x202
+    push qword USER_CS
+    push rdi
+
+    ; no kernel values are passed on to the program. rdx holds no termination function by the System V ABI.
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor edi, edi
+    xor ebp, ebp
+    xor r8d, r8d
+    xor r9d, r9d
+    xor r10d, r10d
+    xor r11d, r11d
+    xor r12d, r12d
+    xor r13d, r13d
+    xor r14d, r14d
+    xor r15d, r15d
+
+    iretq
+
+global syscall_entry
+
+; entered by the syscall instruction with interrupts disabled. rax holds the syscall number, rdi, rsi, rdx, r10, r8 and r9 its arguments.
+; rcx holds the return address and r11 the rflags of the caller, the stack is still the one of the caller.
+; the saved registers must match SyscallFrame in src/base/syscall.rs
+syscall_entry:
+    mov [rel syscall_user_stack], rsp
+    mov rsp, [rel syscall_kernel_stack]
+
+    push qword [rel syscall_user_stack]
+    push rcx
+    push r11
+    push rax
+    push rdi
+    push rsi
+    push rdx
+    push r10
+    push r8
+    push r9
+
+    ; ten registers have been pushed, so the stack is still 16 byte aligned
+    mov rdi, rsp
+    call syscall_dispatch
+
+    ; rax holds the return value, all other argument registers are preserved for the caller
+    pop r9
+    pop r8
+    pop r10
+    pop rdx
+    pop rsi
+    pop rdi
+    ; keep the return value in the slot of the syscall number
+    mov [rsp], rax
+
+    ; sysret raises a general protection fault in ring 0 for a non-canonical return address, while the stack of the caller is already active.
+    ; such calls return with iretq instead, which raises the fault in ring 3.
+    mov rax, USER_ADDRESS_LIMIT
+    cmp [rsp + 16], rax
+    jae .iret
+
+    pop rax
+    pop r11
+    pop rcx
+    pop rsp
+
+    o64 sysret
+
+.iret:
+    ; turn the saved rax, r11, rcx and rsp into rax followed by an interrupt frame with rip, cs, rflags, rsp and ss
+    sub rsp, 16
+    mov rcx, [rsp + 16]
+    mov [rsp], rcx
+    mov rcx, [rsp + 32]
+    mov [rsp + 8], rcx
+    mov r11, [rsp + 24]
+    mov rcx, [rsp + 40]
+    mov qword [rsp + 16], USER_CS
+    mov [rsp + 24], r11
+    mov [rsp + 32], rcx
+    mov qword [rsp + 40], USER_SS
+    pop rax
+
+    iretq
@@ -1,7 +1,13 @@
-use std::{env, fs, path::PathBuf, process::Command};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 fn main() {
     let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    embed_build_info(&manifest_dir);
 
     let out_dir = manifest_dir.join("../target");
     let asm_dir = manifest_dir.join("asm");
@@ -72,3 +78,79 @@ fn main() {
         println!("cargo:rustc-link-search={}", out_dir.display());
     }
 }
+
+/// Passes the git commit and the build time to the kernel as environment variables, see `src/info.rs`.
+fn embed_build_info(manifest_dir: &Path) {
+    let commit = git(manifest_dir, &["rev-parse", "--short", "HEAD"])
+        .map(|commit| {
+            let changes = git(manifest_dir, &["status", "--porcelain", "--untracked-files=no"]);
+            match changes {
+                Some(changes) if !changes.is_empty() => format!("{}-dirty", commit),
+                _ => commit,
+            }
+        })
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=CHICKEN_GIT_COMMIT={}", commit);
+
+    // SOURCE_DATE_EPOCH allows reproducible builds
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0)
+        });
+    println!(
+        "cargo:rustc-env=CHICKEN_BUILD_TIMESTAMP={}",
+        format_timestamp(timestamp)
+    );
+}
+
+/// Runs git with the given arguments and returns its trimmed output, or None if git is not available or fails.
+fn git(directory: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(directory)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|output| output.trim().to_string())
+}
+
+/// Formats seconds since the unix epoch as UTC date and time.
+fn format_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+
+    // converts days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let shifted_days = days + 719_468;
+    let era = shifted_days.div_euclid(146_097);
+    let day_of_era = shifted_days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    )
+}
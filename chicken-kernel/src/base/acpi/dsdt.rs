@@ -0,0 +1,59 @@
+use core::slice;
+
+use crate::base::acpi::sdt::SDTHeader;
+
+/// AML opcodes used to encode the sleep type packages.
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const BYTE_PREFIX: u8 = 0x0A;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ROOT_CHAR: u8 = b'\\';
+
+/// Searches the AML of the mapped DSDT for the `\_Sx_` object of the given sleep state and returns the values for `SLP_TYPa` and `SLP_TYPb`. Only packages of integer constants are supported, which is how firmware usually defines them, as there is no AML interpreter.
+pub(in crate::base) fn sleep_types(dsdt: *const SDTHeader, state: u8) -> Option<(u8, u8)> {
+    let length = unsafe { (*dsdt).length } as usize;
+    let aml = unsafe {
+        slice::from_raw_parts(
+            (dsdt as *const u8).add(size_of::<SDTHeader>()),
+            length.checked_sub(size_of::<SDTHeader>())?,
+        )
+    };
+    let name = [b'_', b'S', b'0' + state, b'_'];
+
+    (0..aml.len().saturating_sub(name.len()))
+        .filter(|&index| aml[index..index + name.len()] == name)
+        .filter(|&index| {
+            // the name must be declared by a name op, optionally relative to the root
+            aml[..index].ends_with(&[NAME_OP]) || aml[..index].ends_with(&[NAME_OP, ROOT_CHAR])
+        })
+        .find_map(|index| parse_package(&aml[index + name.len()..]))
+}
+
+/// Parses the first two elements of a package.
+fn parse_package(aml: &[u8]) -> Option<(u8, u8)> {
+    let (&opcode, aml) = aml.split_first()?;
+    if opcode != PACKAGE_OP {
+        return None;
+    }
+
+    // the two highest bits of the lead byte contain the amount of additional package length bytes
+    let package_length_bytes = 1 + (*aml.first()? >> 6) as usize;
+    // skip the package length and the amount of elements
+    let mut elements = aml.get(package_length_bytes + 1..)?;
+    let sleep_type_a = parse_integer(&mut elements)?;
+    let sleep_type_b = parse_integer(&mut elements)?;
+    Some((sleep_type_a, sleep_type_b))
+}
+
+/// Parses an integer constant, that fits into a byte, and advances the slice past it.
+fn parse_integer(aml: &mut &[u8]) -> Option<u8> {
+    let (value, length) = match *aml.first()? {
+        BYTE_PREFIX => (*aml.get(1)?, 2),
+        ZERO_OP => (0, 1),
+        ONE_OP => (1, 1),
+        _ => return None,
+    };
+    *aml = &aml[length..];
+    Some(value)
+}
@@ -0,0 +1,188 @@
+use core::{mem::MaybeUninit, ptr};
+
+use chicken_util::{
+    memory::{PhysicalAddress, VirtualAddress},
+    BootInfo,
+};
+
+use crate::base::{
+    acpi::{rsd, sdt, sdt::SDTHeader, ACPIError},
+    io::Port,
+};
+
+const FACS_SIGNATURE: [char; 4] = ['F', 'A', 'C', 'S'];
+/// Set in the FADT flags, if the reset register is supported.
+const RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+/// Address space id of generic address structures located in the io port space.
+const SYSTEM_IO_SPACE: u8 = 1;
+
+/// Fixed ACPI Description Table. Only the fields up to the extended DSDT address are declared.
+#[allow(dead_code)] // fields are defined by the ACPI specification
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub(in crate::base) struct Fadt {
+    header: SDTHeader,
+    firmware_control: u32,
+    dsdt: u32,
+    reserved: u8,
+    preferred_power_management_profile: u8,
+    sci_interrupt: u16,
+    smi_command_port: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4_bios_request: u8,
+    pstate_control: u8,
+    pm1a_event_block: u32,
+    pm1b_event_block: u32,
+    pm1a_control_block: u32,
+    pm1b_control_block: u32,
+    pm2_control_block: u32,
+    pm_timer_block: u32,
+    gpe0_block: u32,
+    gpe1_block: u32,
+    pm1_event_length: u8,
+    pm1_control_length: u8,
+    pm2_control_length: u8,
+    pm_timer_length: u8,
+    gpe0_length: u8,
+    gpe1_length: u8,
+    gpe1_base: u8,
+    cstate_control: u8,
+    worst_c2_latency: u16,
+    worst_c3_latency: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alarm: u8,
+    month_alarm: u8,
+    century: u8,
+    boot_architecture_flags: u16,
+    reserved2: u8,
+    flags: u32,
+    /// generic address structure
+    reset_register: [u8; 12],
+    reset_value: u8,
+    arm_boot_architecture_flags: u16,
+    minor_version: u8,
+    x_firmware_control: u64,
+    x_dsdt: u64,
+}
+
+impl Fadt {
+    /// Returns a copy of the FADT or an error, if it could not be found. Fields that are not present in older revisions of the table are zero.
+    pub(in crate::base) fn get(boot_info: &BootInfo) -> Result<Fadt, ACPIError> {
+        let rsd = rsd::Rsd::get(boot_info.rsdp)?;
+        let signature = ['F', 'A', 'C', 'P'];
+        let table = sdt::get(signature, rsd.rsd_table_address())?;
+
+        let length = (unsafe { (*table).length } as usize).min(size_of::<Fadt>());
+        let mut fadt = MaybeUninit::<Fadt>::zeroed();
+        let fadt = unsafe {
+            ptr::copy_nonoverlapping(table as *const u8, fadt.as_mut_ptr() as *mut u8, length);
+            fadt.assume_init()
+        };
+        super::unmap(table as VirtualAddress)?;
+        Ok(fadt)
+    }
+
+    /// Physical address of the Differentiated System Description Table.
+    pub(in crate::base) fn dsdt_address(&self) -> PhysicalAddress {
+        match self.x_dsdt {
+            0 => self.dsdt as PhysicalAddress,
+            address => address,
+        }
+    }
+
+    /// Physical address of the Firmware ACPI Control Structure.
+    pub(in crate::base) fn facs_address(&self) -> PhysicalAddress {
+        match self.x_firmware_control {
+            0 => self.firmware_control as PhysicalAddress,
+            address => address,
+        }
+    }
+
+    /// Ports of the PM1a and the optional PM1b control registers, or None on hardware-reduced platforms.
+    pub(in crate::base) fn pm1_control_ports(&self) -> Option<(Port, Option<Port>)> {
+        Self::port_pair(self.pm1a_control_block, self.pm1b_control_block)
+    }
+
+    /// Ports of the PM1a and the optional PM1b status registers, which are located at the start of the event blocks.
+    pub(in crate::base) fn pm1_status_ports(&self) -> Option<(Port, Option<Port>)> {
+        Self::port_pair(self.pm1a_event_block, self.pm1b_event_block)
+    }
+
+    /// Port and value to switch the platform from legacy mode to ACPI mode, if it supports both.
+    pub(in crate::base) fn acpi_enable_command(&self) -> Option<(Port, u8)> {
+        match (self.smi_command_port, self.acpi_enable) {
+            (0, _) | (_, 0) => None,
+            (port, value) => Some((port as Port, value)),
+        }
+    }
+
+    /// Port and value to reset the system, if the platform provides a reset register in the io port space.
+    pub(in crate::base) fn reset_command(&self) -> Option<(Port, u8)> {
+        let (flags, register) = (self.flags, self.reset_register);
+        let mut address = [0; 8];
+        address.copy_from_slice(&register[4..12]);
+        match u64::from_le_bytes(address) {
+            0 => None,
+            _ if flags & RESET_REGISTER_SUPPORTED == 0 || register[0] != SYSTEM_IO_SPACE => None,
+            port => Some((port as Port, self.reset_value)),
+        }
+    }
+
+    /// Index of the CMOS register holding the century of the RTC, 0 if there is none.
+    pub(in crate::base) fn century_register(&self) -> u8 {
+        self.century
+    }
+
+    fn port_pair(a: u32, b: u32) -> Option<(Port, Option<Port>)> {
+        match (a, b) {
+            (0, _) => None,
+            (a, 0) => Some((a as Port, None)),
+            (a, b) => Some((a as Port, Some(b as Port))),
+        }
+    }
+}
+
+/// Firmware ACPI Control Structure. Located in memory the firmware reads while waking up.
+#[allow(dead_code)] // fields are defined by the ACPI specification
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub(in crate::base) struct Facs {
+    signature: [u8; 4],
+    length: u32,
+    hardware_signature: u32,
+    /// Real mode address the firmware jumps to after waking up.
+    firmware_waking_vector: u32,
+    global_lock: u32,
+    flags: u32,
+    /// Address the firmware jumps to in protected or long mode, takes precedence over the real mode vector if set.
+    x_firmware_waking_vector: u64,
+}
+
+impl Facs {
+    /// Maps the FACS located at the given physical address as writable. The FACS stays mapped. Returns its virtual address.
+    pub(in crate::base) fn map(facs_address: PhysicalAddress) -> Result<VirtualAddress, ACPIError> {
+        let facs = super::map_writable(facs_address, size_of::<Facs>())?;
+        let signature =
+            unsafe { (*(facs as *const Facs)).signature }.map(|character| character as char);
+        if signature != FACS_SIGNATURE {
+            super::unmap(facs)?;
+            return Err(ACPIError::TableNotFound(FACS_SIGNATURE));
+        }
+        Ok(facs)
+    }
+
+    /// Sets the real mode address the firmware jumps to after waking up.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `facs` is the virtual address of a FACS returned by [`Facs::map`].
+    pub(in crate::base) unsafe fn set_waking_vector(facs: VirtualAddress, address: u32) {
+        let facs = facs as *mut Facs;
+        ptr::addr_of_mut!((*facs).firmware_waking_vector).write_volatile(address);
+        ptr::addr_of_mut!((*facs).x_firmware_waking_vector).write_volatile(0);
+    }
+}
@@ -0,0 +1,57 @@
+use core::{mem::MaybeUninit, ptr};
+
+use chicken_util::{
+    memory::{PhysicalAddress, VirtualAddress},
+    BootInfo,
+};
+
+use crate::base::acpi::{rsd, sdt, sdt::SDTHeader, ACPIError};
+
+/// Address space id of generic address structures located in memory.
+const SYSTEM_MEMORY_SPACE: u8 = 0;
+
+/// High Precision Event Timer Description Table.
+#[allow(dead_code)] // fields are defined by the ACPI specification
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub(in crate::base) struct Hpet {
+    header: SDTHeader,
+    /// Hardware revision, amount of comparators and vendor of the first timer block.
+    event_timer_block_id: u32,
+    /// generic address structure
+    base_address: [u8; 12],
+    hpet_number: u8,
+    /// Minimum count in periodic mode without losing interrupts.
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+impl Hpet {
+    /// Returns a copy of the HPET table or an error, if it could not be found.
+    pub(in crate::base) fn get(boot_info: &BootInfo) -> Result<Hpet, ACPIError> {
+        let rsd = rsd::Rsd::get(boot_info.rsdp)?;
+        let signature = ['H', 'P', 'E', 'T'];
+        let table = sdt::get(signature, rsd.rsd_table_address())?;
+
+        let length = (unsafe { (*table).length } as usize).min(size_of::<Hpet>());
+        let mut hpet = MaybeUninit::<Hpet>::zeroed();
+        let hpet = unsafe {
+            ptr::copy_nonoverlapping(table as *const u8, hpet.as_mut_ptr() as *mut u8, length);
+            hpet.assume_init()
+        };
+        super::unmap(table as VirtualAddress)?;
+        Ok(hpet)
+    }
+
+    /// Physical address of the registers of the first timer block, if they are located in memory.
+    pub(in crate::base) fn base_address(&self) -> Option<PhysicalAddress> {
+        let register = self.base_address;
+        let mut address = [0; 8];
+        address.copy_from_slice(&register[4..12]);
+        match u64::from_le_bytes(address) {
+            0 => None,
+            _ if register[0] != SYSTEM_MEMORY_SPACE => None,
+            address => Some(address),
+        }
+    }
+}
@@ -34,6 +34,11 @@ impl IOApic {
     pub(in crate::base) fn io_apic_address(&self) -> VirtualAddress {
         self.io_apic_address as u64
     }
+
+    /// Returns the first GSI handled by the IO APIC.
+    pub(in crate::base) fn gsi_base(&self) -> u32 {
+        self.global_system_interrupt_base
+    }
 }
 
 impl MadtEntry for IOApic {
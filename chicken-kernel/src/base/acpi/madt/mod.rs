@@ -1,9 +1,8 @@
 use alloc::vec::Vec;
 use chicken_util::BootInfo;
-use crate::base::acpi::{rsd, sdt};
+use crate::base::acpi::{rsd, sdt, ACPIError};
 use crate::base::acpi::madt::entry::{MadtEntry, MadtEntryHeader};
 use crate::base::acpi::sdt::SDTHeader;
-use crate::println;
 pub(in crate::base) mod entry;
 
 #[repr(C)]
@@ -16,15 +15,14 @@ pub struct Madt {
 }
 
 impl Madt {
-    /// Returns pointer to MADT
-    pub fn get(boot_info: &BootInfo) -> *const Madt {
-        let rsd = rsd::Rsd::get(boot_info.rsdp).expect("Could not get RSD");
+    /// Returns pointer to MADT or an error, if it could not be found.
+    pub fn get(boot_info: &BootInfo) -> Result<*const Madt, ACPIError> {
+        let rsd = rsd::Rsd::get(boot_info.rsdp)?;
         let signature = ['A', 'P', 'I', 'C'];
-        sdt::get(signature, rsd.rsd_table_address(), &boot_info.memory_map).expect("Could not get MADT")
-            as *const Madt
+        Ok(sdt::get(signature, rsd.rsd_table_address())? as *const Madt)
     }
 
-    /// Prints all entries of Madt Table
+    /// Logs all entries of Madt Table with the trace level
     pub fn print_entries(&self) {
         let madt_start = self as *const _ as *const u8;
         let mut pointer = unsafe { madt_start.add(size_of::<Madt>()) };
@@ -34,7 +32,7 @@ impl Madt {
         while pointer < madt_end {
             let entry = unsafe { *(pointer as *const MadtEntryHeader) };
 
-            println!("found entry {}:{:?}", counter, entry);
+            crate::trace!("found entry {}:{:?}", counter, entry);
 
             pointer = unsafe { pointer.add(entry.record_length as usize) };
             counter += 1;
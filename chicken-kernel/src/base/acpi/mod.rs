@@ -1,15 +1,28 @@
 use core::fmt;
 
+use chicken_util::{
+    memory::{PhysicalAddress, VirtualAddress},
+    PAGE_SIZE,
+};
+
+use crate::memory::{
+    direct_map::{is_direct_map_address, phys_range_to_virt},
+    vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+};
+
+pub(in crate::base) mod dsdt;
+pub(in crate::base) mod fadt;
+pub(in crate::base) mod hpet;
 pub(in crate::base) mod madt;
 pub(in crate::base) mod rsd;
 pub(in crate::base) mod sdt;
 
 #[derive(Copy, Clone)]
-pub enum ACPIError {
+pub(crate) enum ACPIError {
     InvalidRSDAddress,
     InvalidXSDTAddress,
     TableNotFound([char; 4]),
-    InvalidMemoryMap,
+    MemoryMappingFailed(VmmError),
 }
 
 impl fmt::Debug for ACPIError {
@@ -17,10 +30,73 @@ impl fmt::Debug for ACPIError {
         match self {
             ACPIError::InvalidRSDAddress => write!(f, "ACPI Parsing Error: Invalid RSD Address."),
             ACPIError::InvalidXSDTAddress => write!(f, "ACPI Parsing Error: Invalid XSDT Address."),
-            ACPIError::InvalidMemoryMap => write!(f, "ACPI Parsing Error: Invalid Memory Map."),
             ACPIError::TableNotFound(signature) => {
                 write!(f, "ACPI Parsing Error: Table not found: {:?}", signature)
             }
+            ACPIError::MemoryMappingFailed(value) => {
+                write!(f, "ACPI Parsing Error: Memory mapping failed: {}", value)
+            }
         }
     }
 }
+
+impl From<VmmError> for ACPIError {
+    fn from(value: VmmError) -> Self {
+        Self::MemoryMappingFailed(value)
+    }
+}
+
+/// Maps `length` bytes of ACPI memory starting at the given physical address as read-only MMIO, e.g. also for the SMBIOS tables. Returns the virtual address corresponding to `physical_address`.
+pub(in crate::base) fn map(
+    physical_address: PhysicalAddress,
+    length: usize,
+) -> Result<VirtualAddress, ACPIError> {
+    // tables in ACPI memory are already part of the read-only direct map
+    if let Some(virtual_address) = phys_range_to_virt(physical_address, length) {
+        return Ok(virtual_address);
+    }
+    map_with_flags(physical_address, length, VmFlags::MMIO)
+}
+
+/// Maps `length` bytes of ACPI memory starting at the given physical address as writable MMIO, e.g. for tables the firmware reads back. Returns the virtual address corresponding to `physical_address`.
+pub(in crate::base::acpi) fn map_writable(
+    physical_address: PhysicalAddress,
+    length: usize,
+) -> Result<VirtualAddress, ACPIError> {
+    map_with_flags(physical_address, length, VmFlags::MMIO | VmFlags::WRITE)
+}
+
+fn map_with_flags(
+    physical_address: PhysicalAddress,
+    length: usize,
+    flags: VmFlags,
+) -> Result<VirtualAddress, ACPIError> {
+    let page_base = physical_address & !(PAGE_SIZE as u64 - 1);
+    let page_offset = physical_address - page_base;
+
+    let mut binding = VMM.lock();
+    let vmm = binding
+        .get_mut()
+        .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+    let virtual_base = vmm.alloc(
+        page_offset as usize + length,
+        flags,
+        AllocationType::Address(page_base),
+    )?;
+
+    Ok(virtual_base + page_offset)
+}
+
+/// Removes a mapping previously created by [`map`] or [`map_writable`].
+pub(in crate::base) fn unmap(virtual_address: VirtualAddress) -> Result<(), ACPIError> {
+    if is_direct_map_address(virtual_address) {
+        return Ok(());
+    }
+    let mut binding = VMM.lock();
+    let vmm = binding
+        .get_mut()
+        .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+    vmm.free(virtual_address & !(PAGE_SIZE as u64 - 1))?;
+
+    Ok(())
+}
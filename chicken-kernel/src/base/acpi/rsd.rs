@@ -34,8 +34,15 @@ pub(in crate::base) enum Rsd {
 }
 
 impl Rsd {
-    pub(in crate::base) fn get(rsdp: u64) -> Result<Self, ACPIError> {
-        let rsdp = rsdp as *const u8;
+    /// Parses the RSD located at the given physical address. The memory is only mapped temporarily.
+    pub(in crate::base) fn get(rsdp: PhysicalAddress) -> Result<Self, ACPIError> {
+        let rsdp_virtual = super::map(rsdp, size_of::<RsdX>())?;
+        let rsd = Self::parse(rsdp_virtual as *const u8);
+        super::unmap(rsdp_virtual)?;
+        rsd
+    }
+
+    fn parse(rsdp: *const u8) -> Result<Self, ACPIError> {
         // validate rsd pointer
         for (index, character) in RSDP_SIGNATURE.iter().enumerate() {
             if (unsafe { rsdp.add(index).read() } as char) != *character {
@@ -1,7 +1,6 @@
 use core::ptr::read_unaligned;
-use chicken_util::memory::{MemoryMap, MemoryType, PhysicalAddress};
+use chicken_util::memory::PhysicalAddress;
 use crate::base::acpi::ACPIError;
-use crate::memory::get_virtual_offset;
 
 const XSDT_SIGNATURE: [char; 4] = ['X', 'S', 'D', 'T'];
 
@@ -20,42 +19,58 @@ pub struct SDTHeader {
     creator_revision: u32,
 }
 
-/// Returns instance of SDTHeader, if the address is valid, or None, if the signature of the header does not match.
-pub fn get_xsdt(xsdt_header_address: PhysicalAddress, memory_map: &MemoryMap) -> Result<SDTHeader, ACPIError> {
+impl SDTHeader {
+    /// Returns the signature of the table as characters.
+    pub(in crate::base) fn signature(&self) -> [char; 4] {
+        self.signature.map(|character| character as char)
+    }
+
+    /// Temporarily maps the header located at the given physical address and returns a copy of it.
+    fn read(header_address: PhysicalAddress) -> Result<SDTHeader, ACPIError> {
+        let header_virtual_address = super::map(header_address, size_of::<SDTHeader>())?;
+        let header = unsafe { read_unaligned(header_virtual_address as *const SDTHeader) };
+        super::unmap(header_virtual_address)?;
+        Ok(header)
+    }
+}
 
-    // adapt to virtual address
-    let xsdt_header_address = (xsdt_header_address + get_virtual_offset(MemoryType::AcpiData, memory_map).ok_or(ACPIError::InvalidMemoryMap)?) as *const u8;
+/// Returns instance of SDTHeader, if the address is valid, or an error, if the signature of the header does not match.
+pub fn get_xsdt(xsdt_header_address: PhysicalAddress) -> Result<SDTHeader, ACPIError> {
+    let xsdt = SDTHeader::read(xsdt_header_address)?;
 
     // validate main system descriptor table address
-    for (index, character) in XSDT_SIGNATURE.iter().enumerate() {
-        if unsafe { xsdt_header_address.add(index).read() as char } != *character { return Err(ACPIError::InvalidXSDTAddress); }
-    }
-    Ok(unsafe { *(xsdt_header_address as *const SDTHeader) })
+    if xsdt.signature() != XSDT_SIGNATURE { return Err(ACPIError::InvalidXSDTAddress); }
+    Ok(xsdt)
+}
+
+/// Returns either a valid pointer to the system descriptor table matching the given signature or an error, if the retrieving of the table fails. The returned table stays mapped.
+pub fn get(signature: [char; 4], xsdt_header_address: PhysicalAddress) -> Result<*const SDTHeader, ACPIError> {
+    let xsdt = get_xsdt(xsdt_header_address)?;
+    let xsdt_virtual_address = super::map(xsdt_header_address, xsdt.length as usize)?;
+    let table = find(signature, xsdt_virtual_address as *const u8, &xsdt);
+    super::unmap(xsdt_virtual_address)?;
+    table
 }
 
-/// Returns either a valid pointer to the system descriptor table matching the given signature or an error, if the retrieving of the table fails.
-pub fn get(signature: [char; 4], xsdt_header_address: u64, memory_map: &MemoryMap) -> Result<*const SDTHeader, ACPIError> {
-    let xsdt = get_xsdt(xsdt_header_address, memory_map)?;
-    let xsdt_header_address = (xsdt_header_address + get_virtual_offset(MemoryType::AcpiData, memory_map).ok_or(ACPIError::InvalidMemoryMap)?) as *const u8;
+/// Maps the whole table located at the given physical address, e.g. for tables that are not listed in the XSDT like the DSDT. The returned table stays mapped.
+pub fn map_table(header_address: PhysicalAddress) -> Result<*const SDTHeader, ACPIError> {
+    let header = SDTHeader::read(header_address)?;
+    Ok(super::map(header_address, header.length as usize)? as *const SDTHeader)
+}
+
+/// Searches the entries of the mapped XSDT for a table matching the given signature and maps it.
+fn find(signature: [char; 4], xsdt_header_address: *const u8, xsdt: &SDTHeader) -> Result<*const SDTHeader, ACPIError> {
     // amount of remaining u64 pointers to the other tables that fit into the total size of the XSDT
     let entries = (xsdt.length as usize - size_of::<SDTHeader>()) / 8;
 
     let pointer_base = unsafe { xsdt_header_address.add(size_of::<SDTHeader>()) };
     for i in 0..entries {
-        let entry_ptr = unsafe { read_unaligned(pointer_base.add(i * 8) as *const u64) };
-
-        let entry_ptr = (entry_ptr + get_virtual_offset(MemoryType::AcpiData, memory_map).ok_or(ACPIError::InvalidMemoryMap)?) as *const SDTHeader;
-        let sdt_header = unsafe { &*entry_ptr };
-
-        let mut sdt_header_signature: [char; 4] = [0u8 as char; 4];
+        let entry_address = unsafe { read_unaligned(pointer_base.add(i * 8) as *const u64) };
+        let sdt_header = SDTHeader::read(entry_address)?;
 
-        for (index, character) in sdt_header.signature.iter().enumerate() {
-            sdt_header_signature[index] = *character as char;
-        }
-
-        if signature == sdt_header_signature {
-            return Ok(sdt_header);
+        if signature == sdt_header.signature() {
+            return Ok(super::map(entry_address, sdt_header.length as usize)? as *const SDTHeader);
         }
     }
     Err(ACPIError::TableNotFound(signature))
-}
\ No newline at end of file
+}
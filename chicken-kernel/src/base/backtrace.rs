@@ -0,0 +1,125 @@
+use core::{
+    arch::asm,
+    cell::OnceCell,
+    fmt::{Display, Formatter},
+    mem::size_of,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use chicken_util::{memory::VirtualAddress, symbols::KernelSymbols, BootInfo};
+
+use crate::{
+    base::interrupts::{gdb, CpuState},
+    memory::paging::VIRTUAL_PHYSICAL_BASE,
+    scheduling::spin::SpinLock,
+};
+
+/// Maximum amount of frames in a backtrace, so a corrupted chain of frame pointers does not print forever.
+const MAX_FRAMES: usize = 32;
+
+/// Functions of the kernel image handed over by the loader.
+static SYMBOLS: SpinLock<OnceCell<KernelSymbols>> = SpinLock::new(OnceCell::new());
+/// Cpu state of the exception the kernel panics because of, null for any other panic. Not protected by a lock, so it can be set right before panicking.
+static EXCEPTION_STATE: AtomicPtr<CpuState> = AtomicPtr::new(ptr::null_mut());
+
+/// Registers the kernel symbols handed over in the boot info. Returns the amount of symbols.
+pub(crate) fn set_up(boot_info: &BootInfo) -> usize {
+    let symbols = boot_info.kernel_symbols;
+    SYMBOLS.lock().get_or_init(|| symbols);
+    symbols.symbols().len()
+}
+
+/// Records the cpu state of an exception the kernel is about to panic because of, so the backtrace of the panic starts at the faulting instruction instead of the exception handler.
+pub(crate) fn set_exception_state(state: *const CpuState) {
+    EXCEPTION_STATE.store(state as *mut CpuState, Ordering::SeqCst);
+}
+
+/// Chain of return addresses, found by following the frame pointers saved on the stack. The kernel is built with frame pointers, so every function saves the frame pointer of its caller right below its return address.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Backtrace {
+    /// Address of the instruction the backtrace starts at, if it is known.
+    instruction: Option<VirtualAddress>,
+    frame_pointer: u64,
+}
+
+impl Backtrace {
+    /// Returns the backtrace of the caller.
+    #[inline(always)]
+    pub(crate) fn here() -> Self {
+        let frame_pointer: u64;
+        unsafe {
+            asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack, preserves_flags))
+        };
+        Self {
+            instruction: None,
+            frame_pointer,
+        }
+    }
+
+    /// Returns the backtrace of the interrupted code, starting at the interrupted instruction.
+    pub(crate) fn of(state: &CpuState) -> Self {
+        Self {
+            instruction: Some(state.instruction_pointer()),
+            frame_pointer: state.frame_pointer(),
+        }
+    }
+
+    /// Returns the backtrace of the current panic, starting at the faulting instruction, if the kernel panics because of an exception.
+    #[inline(always)]
+    pub(crate) fn of_panic() -> Self {
+        let state = EXCEPTION_STATE.load(Ordering::SeqCst);
+        match unsafe { state.as_ref() } {
+            Some(state) => Self::of(state),
+            None => Self::here(),
+        }
+    }
+
+    /// Returns the addresses of the frames, starting at the innermost frame, and whether they are return addresses. Stops at the first frame pointer that is not a mapped kernel address.
+    fn frames(&self) -> impl Iterator<Item = (VirtualAddress, bool)> + '_ {
+        let mut frame_pointer = self.frame_pointer;
+        let return_addresses = (0..MAX_FRAMES).map_while(move |_| {
+            // each frame holds the frame pointer of the caller followed by the return address
+            if frame_pointer < VIRTUAL_PHYSICAL_BASE
+                || !frame_pointer.is_multiple_of(size_of::<u64>() as u64)
+                || !gdb::is_mapped(frame_pointer, 2 * size_of::<u64>())
+            {
+                return None;
+            }
+            let frame = frame_pointer as *const u64;
+            let (caller_frame_pointer, return_address) =
+                unsafe { (ptr::read_volatile(frame), ptr::read_volatile(frame.add(1))) };
+            // the stack grows downward, so the frames of the callers are above
+            frame_pointer = if caller_frame_pointer > frame_pointer {
+                caller_frame_pointer
+            } else {
+                0
+            };
+            (return_address != 0).then_some((return_address, true))
+        });
+        self.instruction
+            .map(|instruction| (instruction, false))
+            .into_iter()
+            .chain(return_addresses)
+    }
+}
+
+impl Display for Backtrace {
+    /// Formats one frame per line with the function containing its address, if the kernel symbols are available. Does not use the kernel heap, so it can be printed while panicking.
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "backtrace:")?;
+        // the lock is only held while the symbols are registered, so it is free unless the panic occurred meanwhile
+        let binding = SYMBOLS.try_lock();
+        let symbols = binding.as_ref().and_then(|binding| binding.get());
+        for (index, (address, is_return)) in self.frames().enumerate() {
+            write!(f, "  {:>2}: {:#018x}", index, address)?;
+            // the return address may already belong to the next function, if the call is the last instruction of the caller
+            let call = address - is_return as u64;
+            match symbols.and_then(|symbols| symbols.resolve(call)) {
+                Some((name, offset)) => writeln!(f, " {}+{:#x}", name, offset + is_return as u64)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
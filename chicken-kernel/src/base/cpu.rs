@@ -0,0 +1,126 @@
+use core::arch::x86_64::{__cpuid_count, CpuidResult};
+
+use crate::warn;
+
+/// Cpu feature reported by `cpuid`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum CpuFeature {
+    /// Model specific registers.
+    Msr,
+    /// Local APIC.
+    Apic,
+    /// `syscall` and `sysret` instructions.
+    Syscall,
+    /// No-execute bit in page table entries.
+    Nx,
+    /// Time stamp counter, that runs at a constant rate in every power and frequency state.
+    InvariantTsc,
+    /// `rdrand` hardware random number generator.
+    Rdrand,
+}
+
+impl CpuFeature {
+    /// Leaf, register and bit of `cpuid` reporting the feature.
+    const fn location(self) -> (u32, Register, u32) {
+        match self {
+            CpuFeature::Msr => (0x1, Register::Edx, 5),
+            CpuFeature::Apic => (0x1, Register::Edx, 9),
+            CpuFeature::Rdrand => (0x1, Register::Ecx, 30),
+            CpuFeature::Syscall => (0x8000_0001, Register::Edx, 11),
+            CpuFeature::Nx => (0x8000_0001, Register::Edx, 20),
+            CpuFeature::InvariantTsc => (0x8000_0007, Register::Edx, 8),
+        }
+    }
+
+    /// Whether the cpu supports the feature. Features of leaves the cpu does not implement are unsupported.
+    pub(crate) fn present(self) -> bool {
+        let (leaf, register, bit) = self.location();
+        // the highest leaf is reported separately for the basic and the extended leaves
+        let max_leaf = cpuid(leaf & 0x8000_0000).eax;
+        if leaf > max_leaf {
+            return false;
+        }
+        let result = cpuid(leaf);
+        let value = match register {
+            Register::Ecx => result.ecx,
+            Register::Edx => result.edx,
+        };
+        value & (1 << bit) != 0
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Register {
+    Ecx,
+    Edx,
+}
+
+/// Cpu feature the kernel relies on, together with what it does instead, if the cpu lacks it.
+#[derive(Copy, Clone, Debug)]
+struct Requirement {
+    feature: CpuFeature,
+    name: &'static str,
+    /// Whether the kernel, as configured, uses the feature.
+    used: fn() -> bool,
+    fallback: &'static str,
+}
+
+/// Features the kernel uses without checking them in every place. Features it does not use yet, e.g. FSGSBASE or 1 GiB pages, are not listed.
+const REQUIREMENTS: [Requirement; 6] = [
+    Requirement {
+        feature: CpuFeature::Msr,
+        name: "model specific registers",
+        used: || true,
+        fallback: "the APIC, NX, syscalls and the performance counters stay disabled",
+    },
+    Requirement {
+        feature: CpuFeature::Apic,
+        name: "a local APIC",
+        used: || !cfg!(feature = "legacy-pic"),
+        fallback: "interrupts are handled by the legacy PIC and ticks are generated by the PIT",
+    },
+    Requirement {
+        feature: CpuFeature::Syscall,
+        name: "syscall/sysret",
+        used: || true,
+        fallback: "user programs can not enter the kernel with syscalls",
+    },
+    Requirement {
+        feature: CpuFeature::Nx,
+        name: "the no-execute bit",
+        used: || true,
+        fallback: "data pages and stacks stay executable",
+    },
+    Requirement {
+        feature: CpuFeature::InvariantTsc,
+        name: "an invariant time stamp counter",
+        used: || true,
+        fallback: "boot times and scheduling latencies measured in cycles may be skewed, the clock uses the HPET or the timer ticks",
+    },
+    Requirement {
+        feature: CpuFeature::Rdrand,
+        name: "rdrand",
+        used: || true,
+        fallback: "random values, e.g. stack canaries, are derived from the time stamp counter and are predictable",
+    },
+];
+
+/// Warns about every feature the kernel uses, that the cpu lacks, and names the fallback taken instead, so failures on real hardware can be traced back to the cpu. Returns the amount of missing features.
+pub(in crate::base) fn check_features() -> usize {
+    REQUIREMENTS
+        .iter()
+        .filter(|requirement| (requirement.used)() && !requirement.feature.present())
+        .inspect(|requirement| {
+            warn!("CPU lacks {}: {}.", requirement.name, requirement.fallback)
+        })
+        .count()
+}
+
+/// Executes `cpuid` for the leaf with subleaf 0.
+fn cpuid(leaf: u32) -> CpuidResult {
+    // cpuid is only declared safe by newer toolchains
+    #[allow(unused_unsafe)]
+    let result = unsafe { __cpuid_count(leaf, 0) };
+    result
+}
+
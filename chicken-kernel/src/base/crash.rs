@@ -0,0 +1,228 @@
+use alloc::string::String;
+use core::{
+    fmt::Write,
+    hint::spin_loop,
+    mem::size_of,
+    panic::PanicInfo,
+    ptr, slice,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+};
+
+use chicken_util::{number::NumberBuffer, timing::read_tsc, BootInfo, CRASH_DUMP_SIZE};
+
+use crate::{
+    base::{
+        io::{io_wait, timer::pit::get_current_uptime_ms},
+        power::reset,
+    },
+    config,
+    info::GIT_COMMIT,
+    memory::vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+    serial_println, stats, video,
+};
+
+/// Marks a complete crash dump ("CHKNDUMP").
+const CRASH_DUMP_MAGIC: u64 = u64::from_le_bytes(*b"CHKNDUMP");
+/// Maximum length of a panic report in bytes.
+const CRASH_DUMP_CAPACITY: usize = CRASH_DUMP_SIZE - size_of::<CrashDumpHeader>();
+
+/// Seconds to wait before rebooting after a panic, unless set with `panic_timeout=<seconds>`.
+const DEFAULT_PANIC_TIMEOUT: u64 = 10;
+
+/// Virtual address of the crash dump region, 0 if it is unavailable. Not protected by a lock, so a panic can be recorded while any lock is held.
+static CRASH_DUMP: AtomicU64 = AtomicU64::new(0);
+/// What the kernel does after a panic has been reported, stored as [`PanicPolicy`].
+static PANIC_POLICY: AtomicU8 = AtomicU8::new(PanicPolicy::Halt as u8);
+static PANIC_TIMEOUT: AtomicU64 = AtomicU64::new(DEFAULT_PANIC_TIMEOUT);
+/// Cleared by an attached debugger to let a kernel waiting after a panic halt.
+static WAITING_FOR_DEBUGGER: AtomicBool = AtomicBool::new(true);
+
+/// What the kernel does after a panic has been reported, set with `panic=halt|reboot|wait` on the command line.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PanicPolicy {
+    /// Halts the cpu forever.
+    Halt,
+    /// Reboots after `panic_timeout` seconds, so unattended machines recover. The panic report is kept in the crash dump region.
+    Reboot,
+    /// Spins with interrupts disabled until a debugger is attached.
+    Wait,
+}
+
+impl PanicPolicy {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => PanicPolicy::Reboot,
+            2 => PanicPolicy::Wait,
+            _ => PanicPolicy::Halt,
+        }
+    }
+}
+
+/// Applies the panic policy given with `panic=halt|reboot|wait` and the reboot delay given with `panic_timeout=<seconds>`.
+pub(crate) fn set_panic_policy() {
+    let policy = config::with("panic", |value| match value {
+        Some("reboot") => PanicPolicy::Reboot,
+        Some("wait") => PanicPolicy::Wait,
+        _ => PanicPolicy::Halt,
+    });
+    PANIC_POLICY.store(policy as u8, Ordering::SeqCst);
+    let timeout = config::integer("panic_timeout").unwrap_or(DEFAULT_PANIC_TIMEOUT);
+    PANIC_TIMEOUT.store(timeout, Ordering::SeqCst);
+}
+
+/// Reboots or waits for a debugger after a panic has been reported, depending on the panic policy. Returns, if the kernel should halt. Must be called with interrupts disabled.
+pub(crate) fn apply_panic_policy() {
+    match PanicPolicy::from_u8(PANIC_POLICY.load(Ordering::SeqCst)) {
+        PanicPolicy::Halt => {}
+        PanicPolicy::Reboot => {
+            let timeout = PANIC_TIMEOUT.load(Ordering::SeqCst);
+            video::text::panic_print(format_args!("panic: rebooting in {} seconds\n", timeout));
+            serial_println!("panic: rebooting in {} seconds", timeout);
+            delay_ms(timeout.saturating_mul(1000));
+            reset::reboot();
+        }
+        PanicPolicy::Wait => {
+            video::text::panic_print(format_args!("panic: waiting for a debugger\n"));
+            serial_println!("panic: waiting for a debugger");
+            while WAITING_FOR_DEBUGGER.load(Ordering::SeqCst) {
+                spin_loop();
+            }
+        }
+    }
+}
+
+/// Busy waits for the given time. The timer does not tick with interrupts disabled, so the time stamp counter is used instead. If it has not been calibrated yet, each write to the diagnostic port is assumed to take 1 us.
+fn delay_ms(ms: u64) {
+    match stats::cycles_per_ms() {
+        Some(cycles_per_ms) => {
+            let end = read_tsc().saturating_add(ms.saturating_mul(cycles_per_ms));
+            while read_tsc() < end {
+                spin_loop();
+            }
+        }
+        None => {
+            for _ in 0..ms.saturating_mul(1000) {
+                unsafe { io_wait() };
+            }
+        }
+    }
+}
+
+/// Header at the start of the crash dump region, followed by the panic report.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct CrashDumpHeader {
+    magic: u64,
+    length: u64,
+    checksum: u64,
+}
+
+/// Applies the panic policy and maps the crash dump region reserved by the loader. Returns the panic report of the previous boot, if there is one.
+pub(crate) fn set_up(boot_info: &BootInfo) -> Result<Option<String>, VmmError> {
+    set_panic_policy();
+    if boot_info.crash_dump == 0 {
+        return Ok(None);
+    }
+
+    let mut binding = VMM.lock();
+    let vmm = binding
+        .get_mut()
+        .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+    // mapped like mmio, so the vmm neither clears nor frees the memory
+    let address = vmm.alloc(
+        CRASH_DUMP_SIZE,
+        VmFlags::MMIO | VmFlags::WRITE,
+        AllocationType::Address(boot_info.crash_dump),
+    )?;
+
+    let header = address as *mut CrashDumpHeader;
+    let previous = unsafe {
+        let header_ref = header.read_volatile();
+        let report = report_buffer(address);
+
+        let previous = (header_ref.magic == CRASH_DUMP_MAGIC
+            && header_ref.length as usize <= CRASH_DUMP_CAPACITY
+            && header_ref.checksum == checksum(&report[..header_ref.length as usize]))
+        .then(|| String::from_utf8_lossy(&report[..header_ref.length as usize]).into_owned());
+
+        // only report a crash once
+        ptr::addr_of_mut!((*header).magic).write_volatile(0);
+        previous
+    };
+
+    CRASH_DUMP.store(address, Ordering::SeqCst);
+    Ok(previous)
+}
+
+/// Stores the panic report in the crash dump region, so it can be printed after a warm reboot.
+pub(crate) fn record(info: &PanicInfo) {
+    // take the region, so a nested panic does not overwrite the original report
+    let address = CRASH_DUMP.swap(0, Ordering::SeqCst);
+    if address == 0 {
+        return;
+    }
+
+    let header = address as *mut CrashDumpHeader;
+    unsafe {
+        // invalidate old dump while writing the new one
+        ptr::addr_of_mut!((*header).magic).write_volatile(0);
+
+        let mut writer = ReportWriter {
+            buffer: report_buffer(address),
+            length: 0,
+        };
+        // only the panic message itself needs the formatting machinery
+        let mut uptime = NumberBuffer::new();
+        for part in [
+            "panic after ",
+            uptime.decimal(get_current_uptime_ms()),
+            " ms (build ",
+            GIT_COMMIT,
+            "): ",
+        ] {
+            let _ = writer.write_str(part);
+        }
+        let _ = write!(writer, "{}", info);
+        let length = writer.length;
+
+        header.write_volatile(CrashDumpHeader {
+            magic: CRASH_DUMP_MAGIC,
+            length: length as u64,
+            checksum: checksum(&writer.buffer[..length]),
+        });
+    }
+}
+
+/// Returns the part of the crash dump region that contains the report.
+///
+/// # Safety
+/// The caller must ensure that the crash dump region is mapped at the given address.
+unsafe fn report_buffer(address: u64) -> &'static mut [u8] {
+    slice::from_raw_parts_mut(
+        (address as *mut u8).add(size_of::<CrashDumpHeader>()),
+        CRASH_DUMP_CAPACITY,
+    )
+}
+
+/// FNV-1a hash of the report, used to detect reports that were only partially written or corrupted by the firmware.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Writes a report into the crash dump region without allocating, truncating it if it is too long.
+struct ReportWriter {
+    buffer: &'static mut [u8],
+    length: usize,
+}
+
+impl Write for ReportWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let count = s.len().min(self.buffer.len() - self.length);
+        self.buffer[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.length += count;
+        Ok(())
+    }
+}
@@ -1,22 +1,70 @@
-use core::cell::OnceCell;
+use core::{
+    arch::asm,
+    cell::OnceCell,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    ptr,
+};
 
 use bitflags::bitflags;
+use chicken_util::{memory::VirtualAddress, PAGE_SIZE};
 
-use crate::scheduling::spin::SpinLock;
+use crate::{memory::direct_map::virt_to_phys, println, scheduling::spin::SpinLock};
 
 pub(crate) const KERNEL_CS: u16 = 0x08;
 // note: data segments is also used for stack allocation of new kernel processes.
 pub(crate) const KERNEL_DS: u16 = 0x10;
+// note: sysret expects the user data segment right before the user code segment.
+pub(crate) const USER_DS: u16 = 0x18;
+pub(crate) const USER_CS: u16 = 0x20;
+pub(crate) const TSS_SELECTOR: u16 = 0x28;
+/// Interrupt stack table index of the double fault handler, so it runs on a known good stack even if the kernel stack overflowed.
+pub(crate) const DOUBLE_FAULT_IST: u8 = 1;
+
+const IST_STACK_SIZE: usize = PAGE_SIZE * 4;
+/// Amount of 8 byte entries in the GDT. The TSS descriptor occupies two entries.
+const GDT_ENTRY_COUNT: usize = 7;
+/// System segment types of a 64-bit TSS.
+const TSS_AVAILABLE: u8 = 0x9;
+const TSS_BUSY: u8 = 0xB;
+/// Access byte bits holding the segment type.
+const SEGMENT_TYPE_MASK: u8 = 0xF;
 
 static GDT: SpinLock<OnceCell<GlobalDescriptorTable>> = SpinLock::new(OnceCell::new());
+static TSS: SpinLock<OnceCell<TaskStateSegment>> = SpinLock::new(OnceCell::new());
+
+static mut DOUBLE_FAULT_STACK: IstStack = IstStack([0; IST_STACK_SIZE]);
 
 extern "C" {
     fn load_gdt(gdt: *const GdtDescriptor);
 }
 
+/// Sets the stack the cpu switches to, when an interrupt arrives in user mode. The scheduler points it at the kernel stack of each thread it switches to.
+pub(crate) fn set_privileged_stack(top: VirtualAddress) {
+    if let Some(tss) = TSS.lock().get_mut() {
+        tss.privileged_stacks[0] = top;
+    }
+}
+
+/// Loads the GDT and the TSS and asserts that the loaded state matches the expected layout.
 pub(super) fn initialize() {
-    let gdt_lock = GDT.lock();
-    let gdt = gdt_lock.get_or_init(GlobalDescriptorTable::new);
+    reload();
+    if let Err(err) = verify() {
+        panic!("{}", err);
+    }
+}
+
+/// Rebuilds the GDT from the current TSS, loads it and reloads the segment registers and the task register. Has to be called whenever the location or size of the TSS changes and after the descriptor tables were lost, e.g. after waking up from sleep.
+pub(crate) fn reload() {
+    let tss_lock = TSS.lock();
+    let tss = tss_lock.get_or_init(TaskStateSegment::new);
+
+    let mut gdt_lock = GDT.lock();
+    let _ = gdt_lock.get_or_init(|| GlobalDescriptorTable::new(tss));
+    // can safely be unwrapped
+    let gdt = gdt_lock.get_mut().unwrap();
+    // rebuilding the table also clears the busy flag of the TSS descriptor, which would prevent loading the task register again
+    *gdt = GlobalDescriptorTable::new(tss);
 
     let gdt_desc = GdtDescriptor {
         size: (size_of::<GlobalDescriptorTable>() - 1) as u16,
@@ -25,16 +73,158 @@ pub(super) fn initialize() {
 
     unsafe {
         load_gdt(&gdt_desc as *const GdtDescriptor);
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
     }
 }
 
+/// Verifies that the GDT is loaded, the segment registers hold the expected selectors, the TSS descriptor points at the TSS and the interrupt stacks are mapped.
+pub(super) fn verify() -> Result<(), GdtError> {
+    let gdt_lock = GDT.lock();
+    let gdt = gdt_lock.get().ok_or(GdtError::Uninitialized)?;
+    let tss_lock = TSS.lock();
+    let tss = tss_lock.get().ok_or(GdtError::Uninitialized)?;
+
+    let loaded = GdtDescriptor::read();
+    let (offset, size) = (loaded.offset, loaded.size);
+    if offset != gdt as *const _ as u64 || size as usize != size_of::<GlobalDescriptorTable>() - 1 {
+        return Err(GdtError::TableNotLoaded(offset));
+    }
+
+    let selectors = Selectors::read();
+    for (register, expected, found) in [
+        ("cs", KERNEL_CS, selectors.cs),
+        ("ds", KERNEL_DS, selectors.ds),
+        ("ss", KERNEL_DS, selectors.ss),
+        ("tr", TSS_SELECTOR, selectors.tr),
+    ] {
+        if expected != found {
+            return Err(GdtError::UnexpectedSelector {
+                register,
+                expected,
+                found,
+            });
+        }
+    }
+
+    // the cpu sets the accessed flag when a segment is loaded
+    let entries = gdt.entries();
+    let accessed = u64::from(AccessByte::ACCESSED.bits()) << 40;
+    for (selector, expected) in [
+        (KERNEL_CS, SegmentDescriptor::kernel_code()),
+        (KERNEL_DS, SegmentDescriptor::kernel_data()),
+        (USER_DS, SegmentDescriptor::user_data()),
+        (USER_CS, SegmentDescriptor::user_code()),
+    ] {
+        if entries[usize::from(selector) / 8] | accessed != expected.bits() | accessed {
+            return Err(GdtError::UnexpectedDescriptor(selector));
+        }
+    }
+
+    // the task register has been loaded, so the TSS is marked as busy
+    let tss_descriptor = gdt.tss;
+    let tss_address = tss as *const _ as u64;
+    let tss_limit = (size_of::<TaskStateSegment>() - 1) as u32;
+    if tss_descriptor.base() != tss_address
+        || tss_descriptor.low.limit() != tss_limit
+        || tss_descriptor.low.access.bits() & SEGMENT_TYPE_MASK != TSS_BUSY
+    {
+        return Err(GdtError::InvalidTaskStateSegment {
+            base: tss_descriptor.base(),
+            limit: tss_descriptor.low.limit(),
+        });
+    }
+
+    for (index, stack_top) in tss.interrupt_stacks().into_iter().enumerate() {
+        if stack_top != 0
+            && !(is_mapped(stack_top - 1) && is_mapped(stack_top - IST_STACK_SIZE as u64))
+        {
+            return Err(GdtError::InterruptStackUnmapped(index as u8 + 1));
+        }
+    }
+    Ok(())
+}
+
+/// Prints the loaded GDT, the segment selectors and the TSS, e.g. to debug a triple fault after changing the descriptor tables.
+#[allow(dead_code)] // debugging aid
+pub(crate) fn debug_dump() {
+    let loaded = GdtDescriptor::read();
+    let (offset, size) = (loaded.offset, loaded.size);
+    let selectors = Selectors::read();
+    println!(
+        "gdt: base {:#x}, limit {:#x}, cs {:#x}, ds {:#x}, ss {:#x}, tr {:#x}",
+        offset, size, selectors.cs, selectors.ds, selectors.ss, selectors.tr
+    );
+
+    if let Some(gdt) = GDT.lock().get() {
+        for (index, entry) in gdt.entries().iter().enumerate() {
+            let descriptor = SegmentDescriptor::from_bits(*entry);
+            println!(
+                "gdt: {:#04x} {:#018x} base {:#x} limit {:#x} access {:#04x} flags {:#x}",
+                index * 8,
+                entry,
+                descriptor.base(),
+                descriptor.limit(),
+                descriptor.access.bits(),
+                descriptor.granularity >> 4
+            );
+        }
+    }
+    if let Some(tss) = TSS.lock().get() {
+        println!(
+            "tss: address {:#x}, rsp0 {:#x}, ist {:x?}",
+            tss as *const _ as u64,
+            tss.privileged_stack(),
+            tss.interrupt_stacks()
+        );
+    }
+}
+
+/// Returns whether the given kernel address is mapped in the active page table.
+fn is_mapped(address: VirtualAddress) -> bool {
+    // unmapped entries of existing page tables point at physical address 0, which is never handed out
+    virt_to_phys(address).is_some_and(|physical| physical != 0)
+}
+
 #[repr(C, packed)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 struct GdtDescriptor {
     size: u16,
     offset: u64,
 }
 
+impl GdtDescriptor {
+    /// Returns the descriptor of the loaded GDT.
+    fn read() -> Self {
+        let mut descriptor = GdtDescriptor::default();
+        unsafe {
+            asm!("sgdt [{}]", in(reg) &mut descriptor as *mut GdtDescriptor, options(nostack, preserves_flags));
+        }
+        descriptor
+    }
+}
+
+/// Selectors loaded in the segment registers and the task register.
+#[derive(Debug, Copy, Clone)]
+struct Selectors {
+    cs: u16,
+    ds: u16,
+    ss: u16,
+    tr: u16,
+}
+
+impl Selectors {
+    fn read() -> Self {
+        let (cs, ds, ss, tr): (u16, u16, u16, u16);
+        unsafe {
+            asm!("mov {0:x}, cs", out(reg) cs, options(nomem, nostack, preserves_flags));
+            asm!("mov {0:x}, ds", out(reg) ds, options(nomem, nostack, preserves_flags));
+            asm!("mov {0:x}, ss", out(reg) ss, options(nomem, nostack, preserves_flags));
+            asm!("str {0:x}", out(reg) tr, options(nomem, nostack, preserves_flags));
+        }
+        Self { cs, ds, ss, tr }
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone, Default)]
 struct SegmentDescriptor {
@@ -59,6 +249,24 @@ impl SegmentDescriptor {
         }
     }
 
+    fn from_bits(bits: u64) -> Self {
+        unsafe { core::mem::transmute::<u64, Self>(bits) }
+    }
+
+    fn bits(self) -> u64 {
+        unsafe { core::mem::transmute::<Self, u64>(self) }
+    }
+
+    fn base(&self) -> u32 {
+        u32::from(self.base_low)
+            | u32::from(self.base_middle) << 16
+            | u32::from(self.base_high) << 24
+    }
+
+    fn limit(&self) -> u32 {
+        u32::from(self.limit_low) | u32::from(self.granularity & 0x0F) << 16
+    }
+
     fn kernel_code() -> Self {
         SegmentDescriptor::new(
             0,
@@ -79,54 +287,193 @@ impl SegmentDescriptor {
         )
     }
 
-    fn user_code() -> Self {
+    fn user_data() -> Self {
         SegmentDescriptor::new(
             0,
             0xFFFFF,
             AccessByte::PRESENT
                 | AccessByte::DPL
                 | AccessByte::DESCRIPTOR_TYPE
-                | AccessByte::EXECUTABLE
-                | AccessByte::CONFORMING_DIRECTION,
+                | AccessByte::READABLE_WRITEABLE,
             SegmentDescriptorFlags::LONG_MODE | SegmentDescriptorFlags::GRANULARITY,
         )
     }
 
-    fn user_data() -> Self {
+    fn user_code() -> Self {
         SegmentDescriptor::new(
             0,
             0xFFFFF,
             AccessByte::PRESENT
                 | AccessByte::DPL
                 | AccessByte::DESCRIPTOR_TYPE
-                | AccessByte::CONFORMING_DIRECTION,
+                | AccessByte::EXECUTABLE
+                | AccessByte::READABLE_WRITEABLE,
             SegmentDescriptorFlags::LONG_MODE | SegmentDescriptorFlags::GRANULARITY,
         )
     }
 }
 
+/// Descriptor of a system segment like the TSS, which spans two GDT entries in long mode.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, Default)]
+struct SystemSegmentDescriptor {
+    low: SegmentDescriptor,
+    base_upper: u32,
+    reserved: u32,
+}
+
+impl SystemSegmentDescriptor {
+    fn task_state_segment(tss: &TaskStateSegment) -> Self {
+        let base = tss as *const _ as u64;
+        Self {
+            low: SegmentDescriptor::new(
+                base as u32,
+                (size_of::<TaskStateSegment>() - 1) as u32,
+                AccessByte::PRESENT | AccessByte::from_bits_retain(TSS_AVAILABLE),
+                SegmentDescriptorFlags::empty(),
+            ),
+            base_upper: (base >> 32) as u32,
+            reserved: 0,
+        }
+    }
+
+    fn base(&self) -> u64 {
+        u64::from(self.low.base()) | u64::from(self.base_upper) << 32
+    }
+}
+
 #[allow(dead_code)]
-#[repr(align(0x1000))]
+#[repr(C, align(0x1000))]
 #[derive(Copy, Clone, Debug)]
 struct GlobalDescriptorTable {
     null: SegmentDescriptor,
     kernel_code: SegmentDescriptor,
     kernel_data: SegmentDescriptor,
-    user_code: SegmentDescriptor,
     user_data: SegmentDescriptor,
+    user_code: SegmentDescriptor,
+    tss: SystemSegmentDescriptor,
 }
 
 impl GlobalDescriptorTable {
-    fn new() -> Self {
+    fn new(tss: &TaskStateSegment) -> Self {
         GlobalDescriptorTable {
             null: SegmentDescriptor::default(),
             kernel_code: SegmentDescriptor::kernel_code(),
             kernel_data: SegmentDescriptor::kernel_data(),
-            user_code: SegmentDescriptor::user_code(),
             user_data: SegmentDescriptor::user_data(),
+            user_code: SegmentDescriptor::user_code(),
+            tss: SystemSegmentDescriptor::task_state_segment(tss),
         }
     }
+
+    /// Returns the raw 8 byte entries of the table.
+    fn entries(&self) -> &[u64; GDT_ENTRY_COUNT] {
+        unsafe { &*(self as *const Self as *const [u64; GDT_ENTRY_COUNT]) }
+    }
+}
+
+/// 64-bit task state segment. Only holds the stacks the cpu switches to, since hardware task switching is not available in long mode.
+#[repr(C, packed(4))]
+#[derive(Debug, Copy, Clone, Default)]
+struct TaskStateSegment {
+    reserved_0: u32,
+    /// Stack pointers loaded when switching to privilege level 0 to 2.
+    privileged_stacks: [u64; 3],
+    reserved_1: u64,
+    /// Interrupt stack table, the first entry is IST 1.
+    interrupt_stacks: [u64; 7],
+    reserved_2: u64,
+    reserved_3: u16,
+    io_map_base: u16,
+}
+
+impl TaskStateSegment {
+    fn new() -> Self {
+        let mut interrupt_stacks = [0; 7];
+        interrupt_stacks[usize::from(DOUBLE_FAULT_IST) - 1] =
+            ptr::addr_of!(DOUBLE_FAULT_STACK) as u64 + IST_STACK_SIZE as u64;
+        Self {
+            interrupt_stacks,
+            // no io permission bit map
+            io_map_base: size_of::<TaskStateSegment>() as u16,
+            ..Default::default()
+        }
+    }
+
+    fn privileged_stack(&self) -> u64 {
+        self.privileged_stacks[0]
+    }
+
+    fn interrupt_stacks(&self) -> [u64; 7] {
+        self.interrupt_stacks
+    }
+}
+
+#[allow(dead_code)] // the stack is only accessed by the cpu
+#[repr(align(16))]
+struct IstStack([u8; IST_STACK_SIZE]);
+
+#[derive(Copy, Clone)]
+pub(crate) enum GdtError {
+    Uninitialized,
+    /// The table at the given address is loaded instead.
+    TableNotLoaded(u64),
+    UnexpectedSelector {
+        register: &'static str,
+        expected: u16,
+        found: u16,
+    },
+    UnexpectedDescriptor(u16),
+    InvalidTaskStateSegment {
+        base: u64,
+        limit: u32,
+    },
+    InterruptStackUnmapped(u8),
 }
+
+impl Debug for GdtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            GdtError::Uninitialized => write!(f, "GDT Error: GDT has not been initialized."),
+            GdtError::TableNotLoaded(address) => write!(
+                f,
+                "GDT Error: A different GDT is loaded at address: {:#x}.",
+                address
+            ),
+            GdtError::UnexpectedSelector {
+                register,
+                expected,
+                found,
+            } => write!(
+                f,
+                "GDT Error: Register {} holds selector {:#x} instead of {:#x}.",
+                register, found, expected
+            ),
+            GdtError::UnexpectedDescriptor(selector) => write!(
+                f,
+                "GDT Error: Descriptor of selector {:#x} does not match the expected segment.",
+                selector
+            ),
+            GdtError::InvalidTaskStateSegment { base, limit } => write!(
+                f,
+                "GDT Error: TSS descriptor is invalid or not loaded. Base: {:#x}, limit: {:#x}.",
+                base, limit
+            ),
+            GdtError::InterruptStackUnmapped(index) => {
+                write!(f, "GDT Error: Stack of IST {} is not mapped.", index)
+            }
+        }
+    }
+}
+
+impl Display for GdtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for GdtError {}
+
 bitflags! {
     #[derive(Copy, Clone, Debug, Default)]
     struct AccessByte: u8 {
@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+use core::{
+    fmt::{Display, Formatter},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chicken_util::number::NumberBuffer;
+
+use crate::{base::interrupts::irq::Vector, config, println, stats};
+
+/// Time in us an interrupt handler may run by default, before it is reported for exceeding its budget.
+const DEFAULT_BUDGET_US: u64 = 100;
+const VECTOR_COUNT: usize = 256;
+
+/// Time in us an interrupt handler may run. Work that takes longer belongs on the driver workers.
+static BUDGET_US: AtomicU64 = AtomicU64::new(DEFAULT_BUDGET_US);
+/// Amount of times the handler of each vector has run. Recorded in interrupt context, so none of the statistics allocate.
+static CALLS: [AtomicU64; VECTOR_COUNT] = [const { AtomicU64::new(0) }; VECTOR_COUNT];
+/// Amount of times the handler of each vector has exceeded the budget.
+static OVERRUNS: [AtomicU64; VECTOR_COUNT] = [const { AtomicU64::new(0) }; VECTOR_COUNT];
+/// Longest time the handler of each vector has run, in time stamp counter cycles.
+static MAX_CYCLES: [AtomicU64; VECTOR_COUNT] = [const { AtomicU64::new(0) }; VECTOR_COUNT];
+
+/// Applies the budget given with `isr_budget_us=<microseconds>`.
+pub(crate) fn set_up() {
+    set_budget_us(config::integer("isr_budget_us").unwrap_or(DEFAULT_BUDGET_US));
+}
+
+/// Sets the time in us an interrupt handler may run.
+pub(crate) fn set_budget_us(budget: u64) {
+    BUDGET_US.store(budget, Ordering::Relaxed);
+}
+
+/// Records the time in cycles the handler of the vector has run. Warns the first time the handler exceeds the budget, further overruns are only counted, since printing from an interrupt handler is slow itself.
+pub(in crate::base::interrupts) fn record(vector: Vector, cycles: u64) {
+    let index = vector.index() as usize;
+    CALLS[index].fetch_add(1, Ordering::Relaxed);
+    MAX_CYCLES[index].fetch_max(cycles, Ordering::Relaxed);
+
+    // the budget can not be checked until the time stamp counter has been calibrated
+    let Some(cycles_per_ms) = stats::cycles_per_ms() else {
+        return;
+    };
+    let budget = BUDGET_US.load(Ordering::Relaxed);
+    if cycles.saturating_mul(1000) <= budget.saturating_mul(cycles_per_ms) {
+        return;
+    }
+    if OVERRUNS[index].fetch_add(1, Ordering::Relaxed) == 0 {
+        println!(
+            "kernel: Interrupt handler of vector: {} ran for {} us, exceeding its budget of {} us.",
+            NumberBuffer::new().hex(index as u64),
+            NumberBuffer::new().decimal(cycles * 1000 / cycles_per_ms),
+            NumberBuffer::new().decimal(budget)
+        );
+    }
+}
+
+/// Run time statistics of the handler of a single vector.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct HandlerTiming {
+    pub(crate) vector: Vector,
+    pub(crate) calls: u64,
+    pub(crate) overruns: u64,
+    pub(crate) max_cycles: u64,
+    /// Time stamp counter cycles per ms, if the counter could be calibrated yet.
+    pub(crate) cycles_per_ms: Option<u64>,
+}
+
+impl Display for HandlerTiming {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "vector {:#x}: {} calls, {} over budget, ",
+            self.vector.index(),
+            self.calls,
+            self.overruns
+        )?;
+        match self.cycles_per_ms {
+            Some(cycles_per_ms) => write!(f, "max: {} us", self.max_cycles * 1000 / cycles_per_ms),
+            None => write!(f, "max: {} cycles", self.max_cycles),
+        }
+    }
+}
+
+/// Returns the run time statistics of all handlers that have run since boot.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn timings() -> Vec<HandlerTiming> {
+    let cycles_per_ms = stats::cycles_per_ms();
+    (0..VECTOR_COUNT)
+        .filter(|index| CALLS[*index].load(Ordering::Relaxed) != 0)
+        .map(|index| HandlerTiming {
+            vector: Vector::new(index as u8),
+            calls: CALLS[index].load(Ordering::Relaxed),
+            overruns: OVERRUNS[index].load(Ordering::Relaxed),
+            max_cycles: MAX_CYCLES[index].load(Ordering::Relaxed),
+            cycles_per_ms,
+        })
+        .collect()
+}
+
+/// Returns the total amount of times interrupt handlers have exceeded the budget.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn overruns() -> u64 {
+    OVERRUNS
+        .iter()
+        .map(|overruns| overruns.load(Ordering::Relaxed))
+        .sum()
+}
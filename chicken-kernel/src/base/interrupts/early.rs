@@ -0,0 +1,76 @@
+use core::{
+    arch::asm,
+    cell::OnceCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use chicken_util::{
+    exception::{Exception, PAGE_FAULT_VECTOR},
+    number::NumberBuffer,
+};
+
+use crate::{
+    base::interrupts::{disable, idt::InterruptDescriptorTable, CpuState},
+    scheduling::spin::SpinLock,
+    serial_println,
+};
+
+/// Amount of vectors reserved for cpu exceptions (0 - 31).
+pub(in crate::base::interrupts) const EXCEPTION_COUNT: u8 = 32;
+
+static EARLY_IDT: SpinLock<OnceCell<InterruptDescriptorTable>> = SpinLock::new(OnceCell::new());
+/// Whether the early idt is loaded, i.e. the full idt has not been set up yet.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Loads a minimal idt, that only handles exceptions by reporting them on the serial console and halting. Must be the very first step of the kernel, since neither memory management nor the video output are needed for it.
+pub(crate) fn initialize() {
+    // the gdt of the loader is still active, so the handlers must run in its code segment
+    let code_segment: u16;
+    unsafe {
+        asm!("mov {0:x}, cs", out(reg) code_segment, options(nomem, nostack, preserves_flags));
+    }
+
+    let mut idt_lock = EARLY_IDT.lock();
+    let _ = idt_lock.get_or_init(InterruptDescriptorTable::new);
+    // can safely be unwrapped
+    let idt = idt_lock.get_mut().unwrap();
+
+    idt.setup_exception_handlers(code_segment);
+    idt.load();
+    ACTIVE.store(true, Ordering::Release);
+}
+
+/// Marks the early idt as replaced by the full idt.
+pub(in crate::base::interrupts) fn deactivate() {
+    ACTIVE.store(false, Ordering::Release);
+}
+
+pub(in crate::base::interrupts) fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Prints the exception to the serial console and halts. Nothing but the serial port is used, since the exception may have been raised while setting up memory management or the video output.
+pub(in crate::base::interrupts) fn report(state: &CpuState) -> ! {
+    disable();
+    let mut vector = NumberBuffer::new();
+    let mut error_code = NumberBuffer::new();
+    let mut rip = NumberBuffer::new();
+    let mut rsp = NumberBuffer::new();
+    serial_println!(
+        "early exception: {}, vector: {}, error code: {}, rip: {}, rsp: {}",
+        Exception::new(state.vector_number, state.error_code),
+        vector.decimal(state.vector_number),
+        error_code.hex(state.error_code),
+        rip.hex(state.iretq_rip),
+        rsp.hex(state.iretq_rsp)
+    );
+    if state.vector_number == PAGE_FAULT_VECTOR {
+        let cr2: u64;
+        unsafe {
+            asm!("mov {}, cr2", out(reg) cr2);
+        }
+        serial_println!("Faulting page address: {}", NumberBuffer::new().hex(cr2));
+    }
+
+    crate::hlt_loop();
+}
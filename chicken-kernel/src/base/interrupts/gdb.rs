@@ -0,0 +1,530 @@
+use core::{
+    arch::asm,
+    ptr,
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+use chicken_util::{
+    exception::{Exception, BREAKPOINT_VECTOR, DEBUG_VECTOR},
+    memory::{
+        paging::{PageEntryFlags, PageTable},
+        VirtualAddress,
+    },
+};
+
+use crate::{
+    base::{
+        interrupts::{CpuState, RFlags},
+        io::{
+            serial::{self, Uart},
+            IOError, Port,
+        },
+    },
+    config,
+    memory::direct_map::phys_to_virt,
+    scheduling::spin::SpinLock,
+};
+
+/// Opcode of `int3`, which replaces the first byte of an instruction to insert a breakpoint.
+const INT3: u8 = 0xCC;
+/// Maximum length of the data of a packet. Advertised to gdb in hexadecimal.
+const PACKET_SIZE: usize = 1024;
+const MAX_BREAKPOINTS: usize = 32;
+/// Amount of registers in the x86_64 register layout of gdb: 16 general purpose registers, rip, eflags and 6 segment registers.
+const REGISTER_COUNT: usize = 24;
+const RIP_REGISTER: usize = 16;
+const EFLAGS_REGISTER: usize = 17;
+/// Write protect bit of cr0. Cleared while breakpoints are inserted into read-only kernel code.
+const CR0_WRITE_PROTECT: u64 = 1 << 16;
+const PAGE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Serial port the stub communicates with gdb on, 0 if it is disabled.
+static PORT: AtomicU16 = AtomicU16::new(0);
+/// Software breakpoints inserted by gdb and the instruction bytes they replaced.
+static BREAKPOINTS: SpinLock<Breakpoints> = SpinLock::new([None; MAX_BREAKPOINTS]);
+
+type Breakpoints = [Option<(VirtualAddress, u8)>; MAX_BREAKPOINTS];
+
+/// What the stub does after a command of gdb has been executed.
+enum Action {
+    Reply,
+    Resume,
+    /// Sends the reply and resumes execution.
+    Detach,
+}
+
+/// Enables the stub on the serial port given with `gdb=com1|com2`. Returns the port, if the stub is enabled.
+pub(in crate::base) fn set_up() -> Result<Option<Port>, IOError> {
+    let port = config::with("gdb", |value| match value {
+        Some("com1") => Some(serial::COM1),
+        Some("com2") => Some(serial::COM2),
+        _ => None,
+    });
+    let Some(port) = port else {
+        return Ok(None);
+    };
+    Uart::new(port).initialize(serial::DEFAULT_BAUD_RATE)?;
+    // the kernel log would corrupt the packets
+    if serial::console_port() == Some(port) {
+        serial::disable_console();
+    }
+    PORT.store(port, Ordering::SeqCst);
+    Ok(Some(port))
+}
+
+pub(crate) fn is_enabled() -> bool {
+    PORT.load(Ordering::SeqCst) != 0
+}
+
+/// Stops in the debugger, if the stub is enabled. Returns once gdb continues execution.
+pub(crate) fn breakpoint() {
+    if is_enabled() {
+        unsafe { asm!("int3", options(nomem, nostack)) };
+    }
+}
+
+/// Whether the exception is handled by the stub, i.e. it is a breakpoint or a single step and the stub is enabled.
+pub(in crate::base::interrupts) fn handles(vector_number: u64) -> bool {
+    is_enabled() && (vector_number == DEBUG_VECTOR || vector_number == BREAKPOINT_VECTOR)
+}
+
+/// Reports the stop to gdb and executes its commands until it continues execution. Registers changed by gdb are restored from the cpu state, once the interrupt handler returns.
+pub(in crate::base::interrupts) fn handle_exception(state_ptr: *const CpuState) {
+    let port = PORT.load(Ordering::SeqCst);
+    let state = unsafe { &mut *(state_ptr as *mut CpuState) };
+    // single steps are only requested for a single instruction
+    state.iretq_flags.remove(RFlags::TRAP);
+
+    let mut breakpoints = BREAKPOINTS.lock();
+    // int3 is a trap, so rip points behind the breakpoint that has been hit
+    let hit_breakpoint = state.vector_number == BREAKPOINT_VECTOR
+        && breakpoints
+            .iter()
+            .flatten()
+            .any(|(address, _)| *address == state.iretq_rip.wrapping_sub(1));
+    if hit_breakpoint {
+        state.iretq_rip -= 1;
+    }
+
+    let mut packet = [0; PACKET_SIZE];
+    let mut reply = [0; PACKET_SIZE];
+    let mut stop = Reply {
+        buffer: &mut reply,
+        length: 0,
+    };
+    stop_reply(state, hit_breakpoint, &mut stop);
+    send_packet(port, stop.data());
+    loop {
+        let length = receive_packet(port, &mut packet);
+        let mut reply = Reply {
+            buffer: &mut reply,
+            length: 0,
+        };
+        match execute(&packet[..length], state, &mut breakpoints, &mut reply) {
+            Action::Reply => send_packet(port, reply.data()),
+            Action::Resume => return,
+            Action::Detach => {
+                send_packet(port, reply.data());
+                return;
+            }
+        }
+    }
+}
+
+/// Executes a command of gdb. Unsupported commands get an empty reply.
+fn execute(
+    command: &[u8],
+    state: &mut CpuState,
+    breakpoints: &mut Breakpoints,
+    reply: &mut Reply,
+) -> Action {
+    let Some((kind, arguments)) = command.split_first() else {
+        return Action::Reply;
+    };
+
+    match kind {
+        b'?' => stop_reply(state, false, reply),
+        b'g' => {
+            for index in 0..REGISTER_COUNT {
+                let (value, size) = register(state, index);
+                reply.push_hex(&value.to_le_bytes()[..size]);
+            }
+        }
+        b'G' => {
+            let mut offset = 0;
+            for index in 0..REGISTER_COUNT {
+                let size = register(state, index).1 * 2;
+                let Some(value) = arguments.get(offset..offset + size).and_then(parse_le) else {
+                    break;
+                };
+                set_register(state, index, value);
+                offset += size;
+            }
+            reply.push(b"OK");
+        }
+        b'p' => match parse_hex(arguments).filter(|index| *index < REGISTER_COUNT as u64) {
+            Some(index) => {
+                let (value, size) = register(state, index as usize);
+                reply.push_hex(&value.to_le_bytes()[..size]);
+            }
+            None => reply.push(b"E01"),
+        },
+        b'P' => {
+            let register = split(arguments, b'=')
+                .and_then(|(index, value)| Some((parse_hex(index)? as usize, parse_le(value)?)));
+            match register.filter(|(index, _)| *index < REGISTER_COUNT) {
+                Some((index, value)) => {
+                    set_register(state, index, value);
+                    reply.push(b"OK");
+                }
+                None => reply.push(b"E01"),
+            }
+        }
+        b'm' => {
+            let range = split(arguments, b',')
+                .and_then(|(address, length)| Some((parse_hex(address)?, parse_hex(length)?)));
+            match range {
+                Some((address, length)) => {
+                    // the reply is truncated to the packet size, gdb requests the rest separately
+                    let length = (length as usize).min(PACKET_SIZE / 2);
+                    if is_mapped(address, length) {
+                        let data =
+                            unsafe { core::slice::from_raw_parts(address as *const u8, length) };
+                        reply.push_hex(data);
+                    } else {
+                        reply.push(b"E14");
+                    }
+                }
+                None => reply.push(b"E01"),
+            }
+        }
+        b'M' => {
+            let mut data = [0; PACKET_SIZE / 2];
+            let write = split(arguments, b':').and_then(|(range, hex)| {
+                let (address, length) = split(range, b',')?;
+                let length = parse_hex(length)? as usize;
+                (decode_hex(hex, &mut data)? == length).then_some((parse_hex(address)?, length))
+            });
+            match write {
+                Some((address, length)) if is_mapped(address, length) => {
+                    write_memory(address, &data[..length]);
+                    reply.push(b"OK");
+                }
+                Some(_) => reply.push(b"E14"),
+                None => reply.push(b"E01"),
+            }
+        }
+        b'Z' | b'z' => {
+            let mut fields = arguments.split(|byte| *byte == b',');
+            // only software breakpoints are supported
+            if fields.next() != Some(b"0") {
+                return Action::Reply;
+            }
+            let done = match fields.next().and_then(parse_hex) {
+                Some(address) if *kind == b'Z' => insert_breakpoint(breakpoints, address),
+                Some(address) => remove_breakpoint(breakpoints, address),
+                None => false,
+            };
+            reply.push(if done { b"OK" } else { b"E01" });
+        }
+        b'c' | b's' => {
+            if let Some(address) = parse_hex(arguments) {
+                state.iretq_rip = address;
+            }
+            if *kind == b's' {
+                state.iretq_flags.insert(RFlags::TRAP);
+            }
+            return Action::Resume;
+        }
+        b'D' => {
+            remove_all_breakpoints(breakpoints);
+            reply.push(b"OK");
+            return Action::Detach;
+        }
+        b'k' => {
+            remove_all_breakpoints(breakpoints);
+            return Action::Resume;
+        }
+        b'q' if arguments.starts_with(b"Supported") => reply.push(b"PacketSize=400;swbreak+"),
+        b'q' if arguments == b"Attached" => reply.push(b"1"),
+        // there is a single thread from the point of view of gdb
+        b'H' | b'T' => reply.push(b"OK"),
+        _ => {}
+    }
+    Action::Reply
+}
+
+/// Returns the value and the size in bytes of the register with the given index in the register layout of gdb.
+fn register(state: &CpuState, index: usize) -> (u64, usize) {
+    let mut state = *state;
+    if let Some(value) = general_register(&mut state, index) {
+        return (*value, 8);
+    }
+    match index {
+        EFLAGS_REGISTER => (state.iretq_flags.bits(), 4),
+        // cs and ss
+        18 => (state.iretq_cs, 4),
+        19 => (state.iretq_ss, 4),
+        // ds, es, fs and gs are not saved on interrupts
+        _ => (0, 4),
+    }
+}
+
+fn set_register(state: &mut CpuState, index: usize, value: u64) {
+    if let Some(register) = general_register(state, index) {
+        *register = value;
+    } else if index == EFLAGS_REGISTER {
+        state.iretq_flags = RFlags::from_bits_retain(value);
+    }
+    // segment registers are fixed by the gdt
+}
+
+/// Returns the general purpose register or rip with the given index in the register layout of gdb.
+fn general_register(state: &mut CpuState, index: usize) -> Option<&mut u64> {
+    Some(match index {
+        0 => &mut state.rax,
+        1 => &mut state.rbx,
+        2 => &mut state.rcx,
+        3 => &mut state.rdx,
+        4 => &mut state.rsi,
+        5 => &mut state.rdi,
+        6 => &mut state.rbp,
+        7 => &mut state.iretq_rsp,
+        8 => &mut state.r8,
+        9 => &mut state.r9,
+        10 => &mut state.r10,
+        11 => &mut state.r11,
+        12 => &mut state.r12,
+        13 => &mut state.r13,
+        14 => &mut state.r14,
+        15 => &mut state.r15,
+        RIP_REGISTER => &mut state.iretq_rip,
+        _ => return None,
+    })
+}
+
+fn insert_breakpoint(breakpoints: &mut Breakpoints, address: VirtualAddress) -> bool {
+    if breakpoints
+        .iter()
+        .flatten()
+        .any(|(inserted, _)| *inserted == address)
+    {
+        return true;
+    }
+    let Some(slot) = breakpoints.iter_mut().find(|slot| slot.is_none()) else {
+        return false;
+    };
+    if !is_mapped(address, 1) {
+        return false;
+    }
+    let original = unsafe { ptr::read_volatile(address as *const u8) };
+    write_memory(address, &[INT3]);
+    *slot = Some((address, original));
+    true
+}
+
+fn remove_breakpoint(breakpoints: &mut Breakpoints, address: VirtualAddress) -> bool {
+    let Some(slot) = breakpoints
+        .iter_mut()
+        .find(|slot| matches!(slot, Some((inserted, _)) if *inserted == address))
+    else {
+        return false;
+    };
+    if let Some((address, original)) = slot.take() {
+        write_memory(address, &[original]);
+    }
+    true
+}
+
+/// Restores the original instructions, so the kernel keeps running without gdb.
+fn remove_all_breakpoints(breakpoints: &mut Breakpoints) {
+    for (address, original) in breakpoints.iter_mut().filter_map(Option::take) {
+        write_memory(address, &[original]);
+    }
+}
+
+/// Returns whether the whole range is mapped in the active address space. Walks the page tables itself, since the lock of the page table manager may be held by the interrupted code.
+pub(crate) fn is_mapped(address: VirtualAddress, length: usize) -> bool {
+    let Some(end) = address.checked_add(length as u64) else {
+        return false;
+    };
+    let mut page = address;
+    while page < end {
+        let Some(page_size) = mapped_page_size(page) else {
+            return false;
+        };
+        match (page & !(page_size - 1)).checked_add(page_size) {
+            Some(next) => page = next,
+            None => return true,
+        }
+    }
+    true
+}
+
+/// Returns the size of the page the address is mapped to, or None if it is not mapped.
+fn mapped_page_size(address: VirtualAddress) -> Option<u64> {
+    let cr3: u64;
+    unsafe { asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags)) };
+
+    let mut table = cr3 & PAGE_ADDRESS_MASK;
+    // shift of the index into the table of each level, starting at the page map level 4
+    for shift in [39, 30, 21, 12] {
+        let table_address = phys_to_virt(table)? as *const PageTable;
+        let entry = unsafe { (*table_address).entries[((address >> shift) & 0x1FF) as usize] };
+        let flags = entry.flags();
+        if !flags.contains(PageEntryFlags::PRESENT) {
+            return None;
+        }
+        // 1 GiB and 2 MiB pages end the walk early
+        if shift == 12 || (shift != 39 && flags.contains(PageEntryFlags::PAT_PAGE_SIZE)) {
+            return Some(1 << shift);
+        }
+        table = entry.address();
+    }
+    None
+}
+
+/// Writes to mapped memory, even if it is read-only, e.g. to insert a breakpoint into kernel code.
+fn write_memory(address: VirtualAddress, data: &[u8]) {
+    unsafe {
+        let cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        asm!("mov cr0, {}", in(reg) cr0 & !CR0_WRITE_PROTECT, options(nostack, preserves_flags));
+        ptr::copy_nonoverlapping(data.as_ptr(), address as *mut u8, data.len());
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+    }
+}
+
+/// Receives the next packet with a valid checksum and acknowledges it. Returns the length of its data.
+fn receive_packet(port: Port, buffer: &mut [u8]) -> usize {
+    let uart = Uart::new(port);
+    loop {
+        // acknowledgements and anything else outside of packets are skipped
+        while uart.read_byte() != b'$' {}
+
+        let mut length = 0;
+        let mut checksum = 0u8;
+        let mut valid = true;
+        loop {
+            match uart.read_byte() {
+                b'#' => break,
+                byte => {
+                    checksum = checksum.wrapping_add(byte);
+                    match buffer.get_mut(length) {
+                        Some(slot) => *slot = byte,
+                        None => valid = false,
+                    }
+                    length += 1;
+                }
+            }
+        }
+        let expected = [uart.read_byte(), uart.read_byte()];
+
+        if valid && parse_hex(&expected) == Some(checksum as u64) {
+            uart.write_byte(b'+');
+            return length;
+        }
+        uart.write_byte(b'-');
+    }
+}
+
+/// Sends the packet until gdb acknowledges it.
+fn send_packet(port: Port, data: &[u8]) {
+    let uart = Uart::new(port);
+    let checksum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    loop {
+        uart.write_byte(b'$');
+        for byte in data {
+            uart.write_byte(*byte);
+        }
+        uart.write_byte(b'#');
+        uart.write_byte(HEX_DIGITS[(checksum >> 4) as usize]);
+        uart.write_byte(HEX_DIGITS[(checksum & 0xF) as usize]);
+
+        loop {
+            match uart.read_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Builds the stop reply, which tells gdb the signal of the exception that stopped execution and whether it has been a breakpoint.
+fn stop_reply(state: &CpuState, hit_breakpoint: bool, reply: &mut Reply) {
+    reply.push(b"T");
+    reply.push_hex(&[Exception::new(state.vector_number, state.error_code).signal()]);
+    if hit_breakpoint {
+        reply.push(b"swbreak:;");
+    }
+}
+
+/// Data of a reply, built without allocating, since the stub runs in interrupt context.
+struct Reply<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Reply<'_> {
+    /// Appends the bytes, as far as they fit into the packet.
+    fn push(&mut self, data: &[u8]) {
+        let count = data.len().min(self.buffer.len() - self.length);
+        self.buffer[self.length..self.length + count].copy_from_slice(&data[..count]);
+        self.length += count;
+    }
+
+    fn push_hex(&mut self, data: &[u8]) {
+        for byte in data {
+            self.push(&[
+                HEX_DIGITS[(byte >> 4) as usize],
+                HEX_DIGITS[(byte & 0xF) as usize],
+            ]);
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+}
+
+/// Splits the arguments at the first occurrence of the separator.
+fn split(text: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let position = text.iter().position(|byte| *byte == separator)?;
+    Some((&text[..position], &text[position + 1..]))
+}
+
+fn hex_digit(character: u8) -> Option<u8> {
+    (character as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Parses a big endian hexadecimal number, e.g. an address.
+fn parse_hex(text: &[u8]) -> Option<u64> {
+    if text.is_empty() {
+        return None;
+    }
+    text.iter().try_fold(0u64, |value, character| {
+        value
+            .checked_mul(16)?
+            .checked_add(hex_digit(*character)? as u64)
+    })
+}
+
+/// Parses the hexadecimal bytes of a register value, which gdb sends in target byte order, i.e. little endian.
+fn parse_le(text: &[u8]) -> Option<u64> {
+    let mut bytes = [0; 8];
+    let length = decode_hex(text, &mut bytes)?;
+    (length != 0).then(|| u64::from_le_bytes(bytes))
+}
+
+/// Decodes pairs of hexadecimal digits into the buffer. Returns the amount of bytes or None, if the text is invalid or does not fit.
+fn decode_hex(text: &[u8], buffer: &mut [u8]) -> Option<usize> {
+    if !text.len().is_multiple_of(2) || text.len() / 2 > buffer.len() {
+        return None;
+    }
+    for (byte, pair) in buffer.iter_mut().zip(text.chunks_exact(2)) {
+        *byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+    }
+    Some(text.len() / 2)
+}
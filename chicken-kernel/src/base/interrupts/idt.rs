@@ -1,6 +1,12 @@
 use core::cell::OnceCell;
 
-use crate::{base::gdt::KERNEL_CS, scheduling::spin::SpinLock};
+use crate::{
+    base::{
+        gdt::KERNEL_CS,
+        interrupts::{early, irq::Vector},
+    },
+    scheduling::spin::SpinLock,
+};
 
 static IDT: SpinLock<OnceCell<InterruptDescriptorTable>> = SpinLock::new(OnceCell::new());
 
@@ -11,15 +17,9 @@ pub(in crate::base) fn initialize() {
     let idt = idt_lock.get_mut().unwrap();
 
     idt.setup_handlers();
-
-    let idt_desc = IdtDescriptor {
-        size: 0xFFF,
-        offset: idt as *const _ as u64,
-    };
-
-    unsafe {
-        load_idt(&idt_desc as *const IdtDescriptor);
-    }
+    idt.load();
+    // replaces the early idt
+    early::deactivate();
 }
 
 #[repr(align(16))]
@@ -27,24 +27,48 @@ pub(in crate::base) fn initialize() {
 pub(in crate::base::interrupts) struct InterruptDescriptorTable([GateDescriptor; 256]);
 
 impl InterruptDescriptorTable {
-    fn new() -> Self {
+    pub(in crate::base::interrupts) fn new() -> Self {
         Self([GateDescriptor::default(); 256])
     }
 
     pub(in crate::base::interrupts) fn set_handler(
         &mut self,
-        vector: u8,
+        vector: Vector,
         handler_address: u64,
         ist: u8,
         dpl: u8,
     ) {
-        self.0[vector as usize] = GateDescriptor::new(
+        self.set_gate(vector, handler_address, KERNEL_CS, ist, dpl);
+    }
+
+    /// Sets the handler of the vector, that is executed in the given code segment.
+    pub(in crate::base::interrupts) fn set_gate(
+        &mut self,
+        vector: Vector,
+        handler_address: u64,
+        segment_selector: u16,
+        ist: u8,
+        dpl: u8,
+    ) {
+        self.0[vector.index() as usize] = GateDescriptor::new(
             handler_address,
-            KERNEL_CS,
+            segment_selector,
             ist,
             GateFlags::new(GateType::TrapGate, dpl, true),
         );
     }
+
+    /// Loads the table into the idtr. The table must not be moved or dropped while it is loaded.
+    pub(in crate::base::interrupts) fn load(&self) {
+        let idt_desc = IdtDescriptor {
+            size: 0xFFF,
+            offset: self as *const _ as u64,
+        };
+
+        unsafe {
+            load_idt(&idt_desc as *const IdtDescriptor);
+        }
+    }
 }
 
 extern "C" {
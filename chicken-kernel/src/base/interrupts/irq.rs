@@ -0,0 +1,157 @@
+use crate::{
+    base::{
+        acpi::madt::entry::InterruptSourceOverride,
+        interrupts::without_interrupts,
+        io::{self, IOError, KEYBOARD_IRQ, TIMER_IRQ},
+    },
+    scheduling::spin::SpinLock,
+};
+
+/// IDT vector of the first IRQ. Both the PIC and the IO APIC deliver IRQs starting at this vector.
+pub(in crate::base) const IRQ_BASE_VECTOR: Vector = Vector(0x20);
+/// IDT vector threads trigger to give up the rest of their time slice. Unlike the timer IRQ, it neither counts as a tick nor needs to be acknowledged.
+pub(crate) const YIELD_VECTOR: Vector = Vector(0x30);
+/// IDT vector of the local APIC timer. It is not an IRQ, so it is placed above the vectors of the IRQs.
+pub(in crate::base) const LAPIC_TIMER_VECTOR: Vector = Vector(0x40);
+/// IDT vector of spurious interrupts of the local APIC.
+pub(in crate::base) const SPURIOUS_VECTOR: Vector = Vector(0xFF);
+
+/// Interrupt handler of a driver. Runs with interrupts disabled and must not use the kernel heap. The end of interrupt signal is sent afterward.
+pub(crate) type Handler = fn(Irq);
+
+/// Interrupt handlers registered by drivers, indexed by IRQ line.
+static HANDLERS: SpinLock<[Option<Handler>; Irq::COUNT as usize]> =
+    SpinLock::new([None; Irq::COUNT as usize]);
+
+/// Registers the interrupt handler of a driver. The IRQ stays masked until it is unmasked using the returned handle, so the driver can finish its set up first. Dropping the handle masks the IRQ and unregisters the handler again, e.g. if the driver fails to initialize.
+#[allow(dead_code)] // no drivers register interrupt handlers yet
+pub(crate) fn register(irq: Irq, handler: Handler) -> Result<Handle, IOError> {
+    // handled by the kernel itself
+    if irq == TIMER_IRQ || irq == KEYBOARD_IRQ {
+        return Err(IOError::IrqInUse(irq.line()));
+    }
+
+    without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let slot = &mut handlers[irq.line() as usize];
+        if slot.is_some() {
+            return Err(IOError::IrqInUse(irq.line()));
+        }
+        io::mask_irq(irq)?;
+        *slot = Some(handler);
+        Ok(Handle { irq })
+    })
+}
+
+/// Runs the interrupt handler registered for the IRQ. Returns whether there is one.
+pub(in crate::base::interrupts) fn dispatch(irq: Irq) -> bool {
+    // copied, so the handler does not run with the lock held
+    let handler = HANDLERS.lock()[irq.line() as usize];
+    handler.map(|handler| handler(irq)).is_some()
+}
+
+/// Registration of an interrupt handler, returned by [`register`]. Masks the IRQ and unregisters the handler, when it is dropped, so no interrupts are delivered to a driver that is gone.
+#[derive(Debug)]
+pub(crate) struct Handle {
+    irq: Irq,
+}
+
+#[allow(dead_code)] // no drivers register interrupt handlers yet
+impl Handle {
+    pub(crate) fn irq(&self) -> Irq {
+        self.irq
+    }
+
+    /// Prevents interrupts of the IRQ from being delivered to the handler, e.g. while the driver reconfigures its device.
+    pub(crate) fn mask(&self) -> Result<(), IOError> {
+        io::mask_irq(self.irq)
+    }
+
+    /// Allows interrupts of the IRQ to be delivered to the handler.
+    pub(crate) fn unmask(&self) -> Result<(), IOError> {
+        io::unmask_irq(self.irq)
+    }
+
+    /// Delivers interrupts of the IRQ to the cpu with the given local apic id. Only the BSP is running, until SMP is supported.
+    pub(crate) fn set_affinity(&self, lapic_id: u8) -> Result<(), IOError> {
+        io::set_irq_affinity(self.irq, lapic_id)
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // masked first, so the interrupt can not arrive while there is no handler
+        let _ = io::mask_irq(self.irq);
+        without_interrupts(|| HANDLERS.lock()[self.irq.line() as usize] = None);
+    }
+}
+
+/// Index of an entry in the interrupt descriptor table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Vector(u8);
+
+impl Vector {
+    pub(crate) const fn new(index: u8) -> Self {
+        Self(index)
+    }
+
+    pub(crate) const fn index(self) -> u8 {
+        self.0
+    }
+}
+
+/// Legacy ISA interrupt request line (0 - 15), as wired to the PICs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Irq(u8);
+
+impl Irq {
+    /// Amount of legacy IRQ lines.
+    pub(crate) const COUNT: u8 = 16;
+
+    pub(crate) const fn new(line: u8) -> Self {
+        assert!(line < Self::COUNT, "IRQ line must be below 16.");
+        Self(line)
+    }
+
+    pub(crate) const fn line(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the IDT vector the IRQ is delivered to, both by the PIC and the IO APIC.
+    pub(crate) const fn vector(self) -> Vector {
+        Vector(IRQ_BASE_VECTOR.0 + self.0)
+    }
+
+    /// Returns the IRQ that is delivered to the given IDT vector, if there is one.
+    pub(crate) fn from_vector(vector: Vector) -> Option<Self> {
+        vector
+            .0
+            .checked_sub(IRQ_BASE_VECTOR.0)
+            .filter(|line| *line < Self::COUNT)
+            .map(Self)
+    }
+
+    /// Returns an iterator over all legacy IRQs.
+    pub(crate) fn all() -> impl Iterator<Item = Self> {
+        (0..Self::COUNT).map(Self)
+    }
+}
+
+/// Global System Interrupt, i.e. the IO APIC input an interrupt arrives at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Gsi(u32);
+
+impl Gsi {
+    /// Returns the GSI an IRQ arrives at. Applies the interrupt source overrides of the MADT, otherwise IRQs are identity mapped.
+    pub(in crate::base) fn from_irq(irq: Irq, overrides: &[InterruptSourceOverride]) -> Self {
+        overrides
+            .iter()
+            .find(|iso| iso.source() == irq.0)
+            .map(|iso| Self(iso.gsi()))
+            .unwrap_or(Self(irq.0 as u32))
+    }
+
+    pub(crate) const fn number(self) -> u32 {
+        self.0
+    }
+}
@@ -1,15 +1,35 @@
 use core::arch::asm;
+
+use chicken_util::{
+    exception::{
+        Exception, PageFaultErrorCode, DIVIDE_ERROR_VECTOR, DOUBLE_FAULT_VECTOR,
+        GENERAL_PROTECTION_FAULT_VECTOR, INVALID_OPCODE_VECTOR, PAGE_FAULT_VECTOR,
+    },
+    number::NumberBuffer,
+    timing::read_tsc,
+};
+
 use crate::{base::{
-    interrupts::{CpuState, idt::InterruptDescriptorTable},
+    backtrace,
+    gdt::DOUBLE_FAULT_IST,
+    interrupts::{
+        budget,
+        CpuState,
+        early,
+        gdb,
+        idt::InterruptDescriptorTable,
+        irq::{self, Irq, LAPIC_TIMER_VECTOR, SPURIOUS_VECTOR, Vector, YIELD_VECTOR},
+    },
     io,
     io::{
         inb,
         keyboard::KEYBOARD,
-        timer::{pit::PIT, Timer},
+        speaker,
+        timer::{self, pit::get_current_uptime_ms},
+        KEYBOARD_IRQ, PS2_DATA_PORT, TIMER_IRQ,
     },
-}, println};
+}, memory::{address_space, vmm}, println, scheduling::{self, GlobalTaskScheduler}, video::blank};
 use crate::base::interrupts::without_interrupts;
-use crate::base::io::timer::pit::ProgrammableIntervalTimer;
 
 extern "C" {
     fn vector_0_handler();
@@ -17,11 +37,29 @@ extern "C" {
 
 impl InterruptDescriptorTable {
     pub(super) fn setup_handlers(&mut self) {
-        let initial_handler_address = vector_0_handler as *const u8;
         for vector_number in 0..=255u8 {
+            // the double fault handler gets its own stack, so a kernel stack overflow does not escalate to a triple fault
+            let ist = if vector_number as u64 == DOUBLE_FAULT_VECTOR {
+                DOUBLE_FAULT_IST
+            } else {
+                0
+            };
             self.set_handler(
-                vector_number,
-                unsafe { initial_handler_address.add(16 * vector_number as usize) } as u64,
+                Vector::new(vector_number),
+                handler_address(vector_number),
+                ist,
+                0,
+            );
+        }
+    }
+
+    /// Sets up the exception vectors only, executed in the given code segment. Used by the early idt.
+    pub(super) fn setup_exception_handlers(&mut self, segment_selector: u16) {
+        for vector_number in 0..early::EXCEPTION_COUNT {
+            self.set_gate(
+                Vector::new(vector_number),
+                handler_address(vector_number),
+                segment_selector,
                 0,
                 0,
             );
@@ -29,108 +67,207 @@ impl InterruptDescriptorTable {
     }
 }
 
+/// Returns the address of the assembly stub of the vector. The stubs are 16 bytes apart.
+fn handler_address(vector_number: u8) -> u64 {
+    let initial_handler_address = vector_0_handler as *const u8;
+    unsafe { initial_handler_address.add(16 * vector_number as usize) as u64 }
+}
+
 #[no_mangle]
 pub fn interrupt_dispatch(mut state_ptr: *const CpuState) -> *const CpuState {
+    let start = read_tsc();
     let state = unsafe { *state_ptr };
+    if early::is_active() {
+        early::report(&state);
+    }
+    // breakpoints and single steps are reported to gdb, even after a panic
+    if gdb::handles(state.vector_number) {
+        gdb::handle_exception(state_ptr);
+        return state_ptr;
+    }
+    // the panic handler must not fault, report it as a nested panic instead of handling the exception
+    if state.vector_number < early::EXCEPTION_COUNT as u64 && crate::is_panicking() {
+        panic!(
+            "exception: vector: {} while panicking",
+            NumberBuffer::new().decimal(state.vector_number)
+        );
+    }
+    let exception = Exception::new(state.vector_number, state.error_code);
     match state.vector_number {
-        0 => {
-            println!("exception: DIV BY 0");
+        DIVIDE_ERROR_VECTOR | INVALID_OPCODE_VECTOR | GENERAL_PROTECTION_FAULT_VECTOR => {
+            println!("exception: {}", exception);
+            state_ptr = exception_handler(state_ptr, exception);
         }
-        // page fault
-        14 => {
-            println!(
-                "exception: PAGE FAULT. Error code: {:?}",
-                error_code::PageFaultErrorCode::from_bits_truncate(state.error_code as u32)
-            );
+        // aborts, the interrupted context cannot be resumed
+        DOUBLE_FAULT_VECTOR => double_fault(&state),
+        PAGE_FAULT_VECTOR => {
+            let error_code = PageFaultErrorCode::from_bits_truncate(state.error_code as u32);
             // get register containing address of faulting page
             let cr2: u64;
             unsafe {
                 asm!("mov {}, cr2", out(reg) cr2);
             }
-            println!("Faulting page address: {:#x}", cr2);
-        }
-        32 => {
-            state_ptr = pit_handler(state_ptr);
+            // writes to copy-on-write pages are retried once the page has been copied
+            let copied = error_code.contains(PageFaultErrorCode::PRESENT | PageFaultErrorCode::WRITE)
+                && address_space::resolve_copy_on_write(cr2);
+            // accesses to lazy vmm objects are retried once the page has been backed
+            let backed = !copied && vmm::resolve_lazy_fault(cr2, error_code);
+            if !copied && !backed {
+                println!("exception: {}", exception);
+                println!("Faulting page address: {}", NumberBuffer::new().hex(cr2));
+                state_ptr = exception_handler(state_ptr, exception);
+            }
         }
-        33 => keyboard_handler(),
-        _ => {
-            println!(
-                "Interrupt handler has not been set up. vector: {:#x}, error code (if set): {:?}",
-                state.vector_number,
-                error_code::ErrorCode::from_bits_truncate(state.error_code as u32)
-            );
+        vector_number => {
+            let vector = Vector::new(vector_number as u8);
+            match Irq::from_vector(vector) {
+                Some(TIMER_IRQ) => {
+                    state_ptr = timer_handler(state_ptr);
+                }
+                Some(KEYBOARD_IRQ) => keyboard_handler(),
+                // lowest priority lines of the pics, which may receive spurious interrupts
+                Some(irq) if (irq.line() == 7 || irq.line() == 15) && io::is_spurious(irq) => {}
+                Some(irq) if irq::dispatch(irq) => io::eoi(irq),
+                Some(irq) if irq.line() == 7 || irq.line() == 15 => {
+                    println!(
+                        "Unhandled IRQ: {}",
+                        NumberBuffer::new().decimal(irq.line().into())
+                    );
+                    io::eoi(irq);
+                }
+                // spurious interrupt of the lapic, must not be acknowledged
+                None if vector == SPURIOUS_VECTOR => {}
+                None if vector == LAPIC_TIMER_VECTOR => {
+                    state_ptr = timer_handler(state_ptr);
+                }
+                None if vector == YIELD_VECTOR => {
+                    state_ptr = yield_handler(state_ptr);
+                }
+                _ => unhandled(state),
+            }
         }
     }
 
+    // exceptions are not measured, since they are reported and kill the faulting task anyway
+    if state.vector_number >= early::EXCEPTION_COUNT as u64 {
+        budget::record(Vector::new(state.vector_number as u8), read_tsc() - start);
+    }
     state_ptr
 }
 
+/// Reports the register state of a double fault and panics. Runs on the IST stack, since the kernel stack may have overflowed, and only uses lock-free output, since the fault may have occurred while any lock was held.
+fn double_fault(state: &CpuState) -> ! {
+    backtrace::set_exception_state(state);
+    let cr2: u64;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) cr2);
+    }
+    panic!(
+        "exception: DOUBLE FAULT. rip: {:#x}, cs: {:#x}, rflags: {:?}, rsp: {:#x}, ss: {:#x}, cr2: {:#x}\n\
+         rax: {:#x}, rbx: {:#x}, rcx: {:#x}, rdx: {:#x}, rsi: {:#x}, rdi: {:#x}, rbp: {:#x}\n\
+         r8: {:#x}, r9: {:#x}, r10: {:#x}, r11: {:#x}, r12: {:#x}, r13: {:#x}, r14: {:#x}, r15: {:#x}",
+        state.iretq_rip,
+        state.iretq_cs,
+        state.iretq_flags,
+        state.iretq_rsp,
+        state.iretq_ss,
+        cr2,
+        state.rax,
+        state.rbx,
+        state.rcx,
+        state.rdx,
+        state.rsi,
+        state.rdi,
+        state.rbp,
+        state.r8,
+        state.r9,
+        state.r10,
+        state.r11,
+        state.r12,
+        state.r13,
+        state.r14,
+        state.r15
+    );
+}
+
+fn unhandled(state: CpuState) {
+    println!(
+        "Interrupt handler has not been set up. vector: {}, {}",
+        NumberBuffer::new().hex(state.vector_number),
+        Exception::new(state.vector_number, state.error_code)
+    );
+}
+
+/// Kills the task that caused the exception and continues with the next one. Returning to the faulting instruction would only raise the exception again, so exceptions raised by the kernel itself are fatal.
+fn exception_handler(context: *const CpuState, exception: Exception) -> *const CpuState {
+    let thread = GlobalTaskScheduler::active_thread_label();
+    match without_interrupts(|| GlobalTaskScheduler::kill_faulting(context)) {
+        Some((pid, next_context)) => {
+            match thread {
+                Some(thread) => println!(
+                    "kernel: Killed task PID: {} after exception: {} in thread: {}",
+                    NumberBuffer::new().decimal(pid),
+                    exception.name(),
+                    thread
+                ),
+                None => println!(
+                    "kernel: Killed task PID: {} after exception: {}",
+                    NumberBuffer::new().decimal(pid),
+                    exception.name()
+                ),
+            }
+            next_context
+        }
+        None => {
+            backtrace::set_exception_state(context);
+            panic!("Unrecoverable exception in kernel: {}", exception)
+        }
+    }
+}
+
 fn keyboard_handler() {
-    // parse keyboard scancode from port 0x60
-    let scancode = unsafe { inb(0x60) };
+    // parse keyboard scancode from the ps/2 data port
+    let scancode = unsafe { inb(PS2_DATA_PORT) };
+    // the key is handled as usual, even if it wakes up the screen
+    blank::input(get_current_uptime_ms());
 
     let mut binding = KEYBOARD.lock();
     binding.handle(scancode);
+    let debug_requested = binding.take_debug_request();
+    drop(binding);
 
-    // send end of interrupt signal to lapic that sent the interrupt
-    io::apic::lapic::eoi();
+    // send end of interrupt signal to the interrupt controller that sent the interrupt
+    io::eoi(KEYBOARD_IRQ);
+    // ctrl + alt + d stops in the debugger, which is entered after the keyboard has been released
+    if debug_requested {
+        gdb::breakpoint();
+    }
 }
 
-fn pit_handler(context: *const CpuState) -> *const CpuState {
+fn timer_handler(context: *const CpuState) -> *const CpuState {
     without_interrupts(|| {
-        // increment tick counter
-        ProgrammableIntervalTimer::tick();
+        // increment tick counter, or account for the time the idle thread halted until the next deadline
+        unsafe { timer::handle_interrupt() };
 
-        // context switch
-        let binding = PIT.lock();
-        let context = binding.perform_context_switch(context);
+        // stop tones of the pc speaker on time
+        speaker::tick(get_current_uptime_ms());
+        // blank the screen after the configured idle time
+        blank::tick(get_current_uptime_ms());
 
-        // send end of interrupt signal to lapic that sent the interrupt
-        io::apic::lapic::eoi();
+        // context switch, once the active thread has used up its time slice
+        let context = if scheduling::quantum_expired() {
+            timer::perform_context_switch(context)
+        } else {
+            context
+        };
+
+        // send end of interrupt signal to the interrupt controller that sent the interrupt
+        timer::eoi();
         context
     })
 }
 
-mod error_code {
-    use bitflags::bitflags;
-
-    bitflags! {
-        /// Error code for page faults. In addition, the value of the CR2 register is set to the virtual address that causes the fault
-        #[repr(C)]
-        #[derive(Copy, Clone, Debug)]
-        pub(super) struct PageFaultErrorCode: u32 {
-            /// Present: When set, the page fault was caused by a page-protection violation. When not set, it was caused by a non-present page.
-            const PRESENT = 1 << 0;
-            /// Write: When set, the page fault was caused by a write access. When not set, it was caused by a read access.
-            const WRITE = 1 << 1;
-            /// User: When set, the page fault was caused while CPL = 3. This does not necessarily mean that the page fault was a privilege violation.
-            const USER = 1 << 2;
-            /// Reserved Write: When set, one or more page directory entries contain reserved bits which are set to 1. This only applies when the PSE or PAE flags in CR4 are set to 1.
-            const RESERVED_WRITE = 1 << 3;
-            /// Instruction Fetch: When set, the page fault was caused by an instruction fetch. This only applies when the No-Execute bit is supported and enabled.
-            const INSTRUCTION_FETCH = 1 << 4;
-            /// Protection Key: When set, the page fault was caused by a protection-key violation. PKRU register (user-mode accesses) or PKRS MSR (supervisor-mode accesses) specifies protection key rights.
-            const PROTECTION_KEY = 1 << 5;
-            /// Shadow Stack: When set, the page fault was caused by a shadow stack access.
-            const SHADOW_STACK = 1 << 6;
-            // bits 7 - 14 reserved
-            /// Software Guard Extension: When set, the fault was due to an SGX violation. The fault is unrelated to ordinary paging.
-            const SGX = 1 << 15;
-            // bits 16 - 31 reserved
-        }
-
-        #[repr(C)]
-        #[derive(Copy, Clone, Debug)]
-        pub(super) struct ErrorCode: u32 {
-            /// External: If set, means it was a hardware interrupt. Cleared for software interrupts.
-            const EXTERNAL = 1 << 0;
-            /// IDT: Set if this error code refers to the IDT. If cleared it refers to the GDT or LDT.
-            const IDT = 1 << 1;
-            /// Table Index: Set if the error code refers to the LDT, cleared if referring to the GDT.
-            const TABLE_INDEX = 1 << 2;
-            /// Index: The index into the table this error code refers to. This can be seen as a byte offset into the table, much like a GDT selector would.
-            const INDEX = 0b11111111111111111111111111111 << 3;
-        }
-
-    }
+fn yield_handler(context: *const CpuState) -> *const CpuState {
+    // software interrupt, so there is neither a tick to count nor an interrupt controller to acknowledge
+    without_interrupts(|| timer::perform_context_switch(context))
 }
@@ -2,7 +2,11 @@ use core::{arch::asm, fmt::Debug};
 
 use bitflags::bitflags;
 
+pub(crate) mod budget;
+pub(crate) mod early;
+pub(crate) mod gdb;
 pub(super) mod idt;
+pub(crate) mod irq;
 mod isr;
 // control state of interrupts
 
@@ -141,4 +145,20 @@ impl CpuState {
             iretq_ss,
         }
     }
+
+    /// Sets the first integer argument (rdi) the code at `iretq_rip` receives.
+    pub(crate) fn with_argument(mut self, rdi: u64) -> Self {
+        self.rdi = rdi;
+        self
+    }
+
+    /// Address of the interrupted instruction, or of the instruction the context resumes at.
+    pub(crate) fn instruction_pointer(&self) -> u64 {
+        self.iretq_rip
+    }
+
+    /// Frame pointer (rbp) of the interrupted code.
+    pub(crate) fn frame_pointer(&self) -> u64 {
+        self.rbp
+    }
 }
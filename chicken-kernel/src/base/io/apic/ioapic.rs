@@ -1,10 +1,7 @@
 use bitflags::bitflags;
 use chicken_util::memory::VirtualAddress;
 
-/// Interrupt Request (IRQ) for PS/2 keyboard entry index
-pub(super) const KEYBOARD_IRQ: u8 = 1;
-/// Interrupt Request (IRQ) for pit entry index
-pub(super) const TIMER_IRQ: u8 = 0;
+use crate::base::interrupts::irq::Vector;
 
 // I/O APIC Registers for accessing other registers:
 /// I/O Register Select: Is used to select the I/O Register to access
@@ -13,8 +10,12 @@ const IOREGSEL_OFFSET: usize = 0x00;
 const IOWIN_OFFSET: usize = 0x10;
 
 // I/O APIC Registers that are accessed using selection registers mentioned above:
+/// I/O APIC Version: Bits 16 - 23 contain the index of the last redirection entry (read only)
+const IOAPICVER_REGISTER: u8 = 0x01;
 /// I/O APIC Redirection tables: The redirection tables: 0x03 - 0x3f with registers starting from 0x10 (read/write)
 const IOREDTBL_REGISTERS_OFFSET: u8 = 0x10;
+/// Maximum amount of redirection entries, whose registers can be selected with IOREGSEL.
+const MAX_REDIRECTION_ENTRIES: u8 = (u8::MAX - IOREDTBL_REGISTERS_OFFSET) / 2 + 1;
 
 /// Write to the IOAPIC control registers.
 ///
@@ -31,22 +32,37 @@ unsafe fn write(io_apic_base: u64, offset: u8, value: u32) {
     reg_window.write_volatile(value);
 }
 
+/// Read from the IOAPIC control registers.
+///
+/// # Safety
+/// The caller must ensure that the register specified by the address and offset is valid and can be read from.
+unsafe fn read(io_apic_base: u64, offset: u8) -> u32 {
+    let reg_select = (io_apic_base + IOREGSEL_OFFSET as u64) as *mut u32;
+    let reg_window = (io_apic_base + IOWIN_OFFSET as u64) as *const u32;
+
+    // write to IOREGSEL to select the register
+    reg_select.write_volatile(offset as u32);
+
+    // read selected register from IOWIN
+    reg_window.read_volatile()
+}
+
 /// Configure a new redirection entry to handle a hardware interrupt using the specified interrupt handler vector offset.
 ///
 /// # Safety
-/// The caller must ensure that the IO APIC address is valid and mapped.
+/// The caller must ensure that the IO APIC address is valid and mapped and that the entry exists.
 pub(in crate::base::io) unsafe fn configure_redirection_entry(
     io_apic_base: VirtualAddress,
     index: u8,
-    idt_vector_index: u8,
+    vector: Vector,
     destination_lapic_id: u8,
     enable: bool,
 ) {
-    let low_index = IOREDTBL_REGISTERS_OFFSET + (index * 2);
+    let low_index = redirection_entry_register(index);
     let high_index = low_index + 1;
 
     // construct lower register of redirection entry (delivery mode=000, destination mode=physical, pin polarity=active-high, trigger mode=edge
-    let mut lvt = LocalVectorTableEntry::from_bits_truncate(idt_vector_index as u32);
+    let mut lvt = LocalVectorTableEntry::from_bits_truncate(vector.index() as u32);
     if !enable {
         lvt.insert(LocalVectorTableEntry::INTERRUPT_MASK);
     }
@@ -59,6 +75,66 @@ pub(in crate::base::io) unsafe fn configure_redirection_entry(
     write(io_apic_base, high_index, destination);
 }
 
+/// Masks or unmasks an existing redirection entry without changing the rest of its configuration.
+///
+/// # Safety
+/// The caller must ensure that the IO APIC address is valid and mapped and that the entry exists.
+pub(in crate::base::io) unsafe fn set_masked(
+    io_apic_base: VirtualAddress,
+    index: u8,
+    masked: bool,
+) {
+    let low_index = redirection_entry_register(index);
+
+    let mut lvt = LocalVectorTableEntry::from_bits_retain(read(io_apic_base, low_index));
+    lvt.set(LocalVectorTableEntry::INTERRUPT_MASK, masked);
+
+    write(io_apic_base, low_index, lvt.bits());
+}
+
+/// Returns the amount of redirection entries of the IO APIC, that can be accessed.
+///
+/// # Safety
+/// The caller must ensure that the IO APIC address is valid and mapped.
+pub(in crate::base::io) unsafe fn redirection_entry_count(io_apic_base: VirtualAddress) -> u8 {
+    let last_index = ((read(io_apic_base, IOAPICVER_REGISTER) >> 16) & 0xFF) as u8;
+    last_index.min(MAX_REDIRECTION_ENTRIES - 1) + 1
+}
+
+/// Reads the raw value of the redirection entry with the given index, the higher register in the upper 32 bits.
+///
+/// # Safety
+/// The caller must ensure that the IO APIC address is valid and mapped and that the entry exists.
+pub(in crate::base::io) unsafe fn read_redirection_entry(
+    io_apic_base: VirtualAddress,
+    index: u8,
+) -> u64 {
+    let low_index = redirection_entry_register(index);
+    let low = read(io_apic_base, low_index) as u64;
+    let high = read(io_apic_base, low_index + 1) as u64;
+    (high << 32) | low
+}
+
+/// Writes the raw value of the redirection entry with the given index, as returned by [`read_redirection_entry`].
+///
+/// # Safety
+/// The caller must ensure that the IO APIC address is valid and mapped and that the entry exists.
+pub(in crate::base::io) unsafe fn write_redirection_entry(
+    io_apic_base: VirtualAddress,
+    index: u8,
+    value: u64,
+) {
+    let low_index = redirection_entry_register(index);
+    // set the destination first, so the interrupt is not delivered to the wrong cpu once it is unmasked
+    write(io_apic_base, low_index + 1, (value >> 32) as u32);
+    write(io_apic_base, low_index, value as u32);
+}
+
+/// Returns the register of the lower half of the redirection entry with the given index, which must be less than [`redirection_entry_count`].
+fn redirection_entry_register(index: u8) -> u8 {
+    IOREDTBL_REGISTERS_OFFSET + index * 2
+}
+
 bitflags! {
     /// General structure of all LVT entries, except the timer entry (and the thermal sensor and performance entries ignore bits 15:13)
     #[repr(C)]
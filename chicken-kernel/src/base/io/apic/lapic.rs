@@ -1,8 +1,10 @@
+use alloc::vec::Vec;
 use core::sync::atomic::Ordering;
 use chicken_util::{memory::VirtualAddress, PAGE_SIZE};
 
 use crate::{
     base::{
+        interrupts::irq::{Vector, SPURIOUS_VECTOR},
         io::{apic::EOI_POINTER, IOError},
         msr,
         msr::ModelSpecificRegister,
@@ -14,8 +16,52 @@ const SPURIOUS_INTERRUPT_VECTOR_OFFSET: usize = 0xF0;
 const EOI_OFFSET: usize = 0xB0;
 const TASK_PRIORITY_OFFSET: usize = 0x80;
 const LOCAL_APIC_ID_OFFSET: usize = 0x20;
+const VERSION_OFFSET: usize = 0x30;
+const LOGICAL_DESTINATION_OFFSET: usize = 0xD0;
+const DESTINATION_FORMAT_OFFSET: usize = 0xE0;
+const LVT_TIMER_OFFSET: usize = 0x320;
+const LVT_THERMAL_SENSOR_OFFSET: usize = 0x330;
+const LVT_PERFORMANCE_COUNTERS_OFFSET: usize = 0x340;
+const LVT_LINT0_OFFSET: usize = 0x350;
+const LVT_LINT1_OFFSET: usize = 0x360;
+const LVT_ERROR_OFFSET: usize = 0x370;
+const TIMER_INITIAL_COUNT_OFFSET: usize = 0x380;
+const TIMER_CURRENT_COUNT_OFFSET: usize = 0x390;
+const TIMER_DIVIDE_CONFIGURATION_OFFSET: usize = 0x3E0;
+
+/// Registers that lose their configuration when the cpu is powered off, in the order they are restored. The spurious vector register enables the apic, which is necessary for unmasking LVT entries. The initial count starts the timer, so it comes last.
+const SAVED_REGISTERS: [usize; 11] = [
+    SPURIOUS_INTERRUPT_VECTOR_OFFSET,
+    TASK_PRIORITY_OFFSET,
+    LOGICAL_DESTINATION_OFFSET,
+    DESTINATION_FORMAT_OFFSET,
+    TIMER_DIVIDE_CONFIGURATION_OFFSET,
+    LVT_TIMER_OFFSET,
+    LVT_PERFORMANCE_COUNTERS_OFFSET,
+    LVT_THERMAL_SENSOR_OFFSET,
+    LVT_LINT0_OFFSET,
+    LVT_LINT1_OFFSET,
+    LVT_ERROR_OFFSET,
+];
+
+/// Configuration of the local apic that is lost during a suspend.
+#[derive(Debug)]
+pub(in crate::base::io) struct LocalApicState {
+    msr: msr::Apic,
+    /// Offset and value of each saved register.
+    registers: Vec<(usize, u32)>,
+    timer_initial_count: u32,
+}
+
+/// Divides the bus clock by 16 before it drives the timer, so slow timer frequencies still fit into the 32 bit count.
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+/// Masks an LVT entry.
+const LVT_MASKED: u32 = 1 << 16;
+/// Reloads the timer with the initial count, once it has reached 0.
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
 
 /// Control struct for Local Apic of Boot Strap Processor
+#[derive(Copy, Clone, Debug)]
 pub(in crate::base) struct LocalApicControl {
     lapic_address: VirtualAddress,
 }
@@ -43,8 +89,8 @@ impl LocalApicControl {
                 let spurious_vector_register =
                     lapic_registers.add(SPURIOUS_INTERRUPT_VECTOR_OFFSET) as *mut u32;
 
-                // spurious vector value of 0xFF and enable apic software
-                spurious_vector_register.write_volatile(0xFF | (1 << 8));
+                // set spurious vector and enable apic software
+                spurious_vector_register.write_volatile(SPURIOUS_VECTOR.index() as u32 | (1 << 8));
 
                 let task_priority_register = lapic_registers.add(TASK_PRIORITY_OFFSET) as *mut u32;
 
@@ -65,6 +111,77 @@ impl LocalApicControl {
         unsafe { (self.lapic_address as *mut u8).add(EOI_OFFSET) as *mut u32 }
     }
 
+    /// Reads the configuration that is lost when the cpu is powered off during a suspend.
+    pub(in crate::base::io::apic) fn save(&self) -> Result<LocalApicState, IOError> {
+        let msr = msr::Apic::read().ok_or(IOError::ModelSpecificRegisterUnavailable)?;
+        // the thermal sensor and performance counter entries are optional
+        let max_lvt_entry = (unsafe { self.read(VERSION_OFFSET) } >> 16) & 0xFF;
+
+        let registers = SAVED_REGISTERS
+            .into_iter()
+            .filter(|&offset| match offset {
+                LVT_PERFORMANCE_COUNTERS_OFFSET => max_lvt_entry >= 4,
+                LVT_THERMAL_SENSOR_OFFSET => max_lvt_entry >= 5,
+                _ => true,
+            })
+            .map(|offset| (offset, unsafe { self.read(offset) }))
+            .collect();
+
+        Ok(LocalApicState {
+            msr,
+            registers,
+            timer_initial_count: unsafe { self.read(TIMER_INITIAL_COUNT_OFFSET) },
+        })
+    }
+
+    /// Restores the configuration read by [`LocalApicControl::save`].
+    ///
+    /// # Safety
+    /// Must be called with interrupts disabled.
+    pub(in crate::base::io::apic) unsafe fn restore(&self, state: &LocalApicState) {
+        // the registers are only accessible, once the apic is enabled globally
+        state.msr.write();
+
+        for &(offset, value) in &state.registers {
+            self.write(offset, value);
+        }
+        self.write(TIMER_INITIAL_COUNT_OFFSET, state.timer_initial_count);
+    }
+
+    unsafe fn read(&self, offset: usize) -> u32 {
+        ((self.lapic_address as *const u8).add(offset) as *const u32).read_volatile()
+    }
+
+    unsafe fn write(&self, offset: usize, value: u32) {
+        ((self.lapic_address as *mut u8).add(offset) as *mut u32).write_volatile(value)
+    }
+
+    /// Starts the timer with the given count, after which it raises an interrupt at the vector. Periodic timers are reloaded with the count, the others stop. The timer counts at a 16th of the bus clock.
+    ///
+    /// # Safety
+    /// Must not be interrupted by the timer.
+    pub(in crate::base::io) unsafe fn start_timer(&self, vector: Vector, count: u32, periodic: bool) {
+        let mode = if periodic { LVT_TIMER_PERIODIC } else { 0 };
+        self.write(TIMER_DIVIDE_CONFIGURATION_OFFSET, TIMER_DIVIDE_BY_16);
+        self.write(LVT_TIMER_OFFSET, vector.index() as u32 | mode);
+        // writing the initial count starts the timer
+        self.write(TIMER_INITIAL_COUNT_OFFSET, count);
+    }
+
+    /// Stops the timer and masks its interrupt.
+    ///
+    /// # Safety
+    /// Must not be interrupted by the timer.
+    pub(in crate::base::io) unsafe fn stop_timer(&self) {
+        self.write(LVT_TIMER_OFFSET, LVT_MASKED);
+        self.write(TIMER_INITIAL_COUNT_OFFSET, 0);
+    }
+
+    /// Returns the count the timer has left until it raises the next interrupt.
+    pub(in crate::base::io) fn timer_count(&self) -> u32 {
+        unsafe { self.read(TIMER_CURRENT_COUNT_OFFSET) }
+    }
+
     /// Returns the ID of the local apic.
     ///
     pub(in crate::base::io::apic) fn lapic_id(&self) -> u8 {
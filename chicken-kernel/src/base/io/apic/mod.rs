@@ -1,19 +1,26 @@
-use core::sync::atomic::{AtomicPtr, Ordering};
+use alloc::{vec, vec::Vec};
+use core::{
+    cell::OnceCell,
+    sync::atomic::{AtomicPtr, Ordering},
+};
 
-use chicken_util::BootInfo;
+use chicken_util::{memory::VirtualAddress, BootInfo, PAGE_SIZE};
 
-use crate::base::{
-    acpi::madt::{
-        entry::{InterruptSourceOverride, IOApic},
-        Madt,
-    },
-    io::{
-        apic::{
-            ioapic::{KEYBOARD_IRQ, TIMER_IRQ},
-            lapic::LocalApicControl,
+use crate::{
+    base::{
+        acpi::madt::{
+            entry::{InterruptSourceOverride, IOApic},
+            Madt,
+        },
+        interrupts::irq::{Gsi, Irq},
+        io::{
+            apic::lapic::{LocalApicControl, LocalApicState},
+            IOError,
         },
-        IOError,
     },
+    devices::{self, Bus, Resource},
+    memory::vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+    scheduling::spin::SpinLock,
 };
 
 pub(super) mod ioapic;
@@ -21,49 +28,185 @@ pub(in crate::base) mod lapic;
 
 static EOI_POINTER: AtomicPtr<u32> = AtomicPtr::new(0 as *mut u32);
 
-/// Configures APIC and LAPIC of BSP. Also sets up memory mappings for LAPIC registers MMIO.
-pub(super) fn set_up(boot_info: &BootInfo) -> Result<ApicConfig, IOError> {
-    let lapic = LocalApicControl::enable()?;
+static APIC_CONFIG: SpinLock<OnceCell<ApicConfig>> = SpinLock::new(OnceCell::new());
 
-    // store address in atomic pointer
-    EOI_POINTER.store(lapic.eoi_pointer(), Ordering::Relaxed);
+/// Configures APIC and LAPIC of BSP. Also sets up memory mappings for LAPIC and IO APIC registers MMIO. All IRQs remain masked until they are unmasked explicitly.
+pub(super) fn set_up(boot_info: &BootInfo) -> Result<(), IOError> {
+    let lapic = LocalApicControl::enable()?;
 
-    let madt = unsafe { Madt::get(boot_info).as_ref().ok_or(IOError::MadtNotFound)? };
+    let madt = unsafe { Madt::get(boot_info)?.as_ref().ok_or(IOError::MadtNotFound)? };
+    madt.print_entries();
     let overrides = madt.parse_entries::<InterruptSourceOverride>();
-    let keyboard_source = overrides
-        .iter()
-        .find(|iso| iso.source() == KEYBOARD_IRQ)
-        .map(|iso| iso.gsi() as u8)
-        .unwrap_or(KEYBOARD_IRQ);
-
-    let pit_source = overrides
-        .iter()
-        .find(|iso| iso.source() == TIMER_IRQ)
-        .map(|iso| iso.gsi() as u8)
-        .unwrap_or(TIMER_IRQ);
-
-    let io_apic_address = madt
+
+    let io_apic = madt
         .parse_entry_first::<IOApic>()
-        .ok_or(IOError::IOApicEntryNotFound)?
-        .io_apic_address();
+        .ok_or(IOError::IOApicEntryNotFound)?;
+    let io_apic_physical_address = io_apic.io_apic_address();
 
-    let lapic_id = lapic.lapic_id();
+    // map mmio for io apic register interactions
+    let io_apic_address = {
+        let mut binding = VMM.lock();
+        let vmm = binding
+            .get_mut()
+            .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+        vmm.alloc(
+            PAGE_SIZE,
+            VmFlags::WRITE | VmFlags::MMIO,
+            AllocationType::Address(io_apic_physical_address),
+        )?
+    };
 
-    Ok(ApicConfig {
+    let eoi_pointer = lapic.eoi_pointer();
+    let config = ApicConfig {
         io_apic_address,
-        lapic_id,
-        keyboard_source,
-        pit_source,
+        gsi_base: io_apic.gsi_base(),
+        redirection_entry_count: unsafe { ioapic::redirection_entry_count(io_apic_address) },
+        lapic_id: lapic.lapic_id(),
+        overrides,
+        lapic,
+    };
+
+    // route all legacy IRQs to the BSP, but keep them masked for now
+    for irq in Irq::all() {
+        // only a single IO APIC is supported, IRQs routed to another one stay unusable
+        let Ok(index) = config.redirection_entry(irq) else {
+            continue;
+        };
+        unsafe {
+            ioapic::configure_redirection_entry(
+                config.io_apic_address,
+                index,
+                irq.vector(),
+                config.lapic_id,
+                false,
+            );
+        }
+    }
+
+    APIC_CONFIG.lock().get_or_init(|| config);
+    devices::register_bound(
+        "io apic",
+        Bus::Acpi,
+        vec![Resource::Mmio {
+            base: io_apic_physical_address,
+            size: PAGE_SIZE as u64,
+        }],
+        "apic",
+    );
+
+    // store address in atomic pointer, only once the apic is fully usable. Otherwise, the pic keeps handling end of interrupt signals.
+    EOI_POINTER.store(eoi_pointer, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Whether the local apic has been set up and receives end of interrupt signals.
+pub(in crate::base::io) fn is_enabled() -> bool {
+    !EOI_POINTER.load(Ordering::Relaxed).is_null()
+}
+
+/// Returns the local apic of the BSP, e.g. to program its timer.
+pub(in crate::base::io) fn local_apic() -> Result<LocalApicControl, IOError> {
+    let binding = APIC_CONFIG.lock();
+    let config = binding.get().ok_or(IOError::LocalApicUninitialized)?;
+    Ok(config.lapic)
+}
+
+/// Masks or unmasks the redirection entry of the IO APIC that belongs to the given IRQ.
+pub(in crate::base::io) fn set_masked(irq: Irq, masked: bool) -> Result<(), IOError> {
+    let binding = APIC_CONFIG.lock();
+    let config = binding.get().ok_or(IOError::IOApicUninitialized)?;
+    let index = config.redirection_entry(irq)?;
+    unsafe {
+        ioapic::set_masked(config.io_apic_address, index, masked);
+    }
+    Ok(())
+}
+
+/// Changes the local apic the redirection entry of the given IRQ delivers to, without changing the rest of its configuration. Only the BSP is running, so it is the only valid destination for now.
+pub(in crate::base::io) fn set_destination(irq: Irq, lapic_id: u8) -> Result<(), IOError> {
+    let binding = APIC_CONFIG.lock();
+    let config = binding.get().ok_or(IOError::IOApicUninitialized)?;
+    if lapic_id != config.lapic_id {
+        return Err(IOError::InvalidIrqAffinity(lapic_id));
+    }
+
+    let index = config.redirection_entry(irq)?;
+    unsafe {
+        let entry = ioapic::read_redirection_entry(config.io_apic_address, index);
+        let entry = (entry & 0x00FF_FFFF_FFFF_FFFF) | ((lapic_id as u64) << 56);
+        ioapic::write_redirection_entry(config.io_apic_address, index, entry);
+    }
+    Ok(())
+}
+
+/// Configuration of the IO APIC and the local apic of the BSP, saved before a suspend.
+#[derive(Debug)]
+pub(in crate::base) struct ApicState {
+    /// Raw values of all redirection entries of the IO APIC.
+    redirection_entries: Vec<u64>,
+    lapic: LocalApicState,
+}
+
+/// Reads the configuration of the IO APIC and the local apic, which is lost during a suspend.
+pub(in crate::base::io) fn save_state() -> Result<ApicState, IOError> {
+    let binding = APIC_CONFIG.lock();
+    let config = binding.get().ok_or(IOError::IOApicUninitialized)?;
+
+    let redirection_entries = unsafe {
+        (0..config.redirection_entry_count)
+            .map(|index| ioapic::read_redirection_entry(config.io_apic_address, index))
+            .collect()
+    };
+
+    Ok(ApicState {
+        redirection_entries,
+        lapic: config.lapic.save()?,
     })
 }
+
+/// Restores the configuration read by [`save_state`].
+///
+/// # Safety
+/// Must be called with interrupts disabled.
+pub(in crate::base::io) unsafe fn restore_state(state: &ApicState) -> Result<(), IOError> {
+    let binding = APIC_CONFIG.lock();
+    let config = binding.get().ok_or(IOError::IOApicUninitialized)?;
+
+    // the local apic must accept interrupts, before the IO APIC delivers them
+    config.lapic.restore(&state.lapic);
+    for (index, &entry) in state.redirection_entries.iter().enumerate() {
+        ioapic::write_redirection_entry(config.io_apic_address, index as u8, entry);
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
-pub(super) struct ApicConfig {
-    /// Address of IO APIC that is used to handle hardware interrupts.
-    pub(super) io_apic_address: u64,
+struct ApicConfig {
+    /// Virtual address of IO APIC that is used to handle hardware interrupts.
+    io_apic_address: VirtualAddress,
+    /// First GSI handled by the IO APIC, as specified in the MADT.
+    gsi_base: u32,
+    redirection_entry_count: u8,
     /// LAPIC ID of the BSP.
-    pub(super) lapic_id: u8,
-    /// Either the default [`KEYBOARD_IRQ`] or a source override specified in the MADT.
-    pub(super) keyboard_source: u8,
-    /// Either the default [`TIMER_IRQ`] or a source override specified in the MADT.
-    pub(super) pit_source: u8,
+    lapic_id: u8,
+    /// Source overrides specified in the MADT.
+    overrides: Vec<InterruptSourceOverride>,
+    lapic: LocalApicControl,
+}
+
+impl ApicConfig {
+    /// Returns the GSI the given IRQ arrives at.
+    fn gsi(&self, irq: Irq) -> Gsi {
+        Gsi::from_irq(irq, &self.overrides)
+    }
+
+    /// Returns the index of the redirection entry of the IO APIC the given IRQ arrives at.
+    fn redirection_entry(&self, irq: Irq) -> Result<u8, IOError> {
+        let gsi = self.gsi(irq).number();
+        gsi.checked_sub(self.gsi_base)
+            .filter(|index| *index < self.redirection_entry_count as u32)
+            .map(|index| index as u8)
+            .ok_or(IOError::GsiOutOfRange(gsi))
+    }
 }
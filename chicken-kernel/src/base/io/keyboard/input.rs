@@ -0,0 +1,235 @@
+use bitflags::bitflags;
+
+use crate::{base::interrupts::without_interrupts, config, scheduling::spin::SpinLock};
+
+/// Key presses buffered until the console reads them. Further key presses are dropped.
+const EVENT_CAPACITY: usize = 64;
+/// Longest encoding of a key press in vt mode, e.g. `ESC [3;5~` for ctrl + delete.
+const VT_MAX_LENGTH: usize = 6;
+/// Bytes buffered in vt mode, enough for as many key presses as in event mode.
+const BYTE_CAPACITY: usize = EVENT_CAPACITY * VT_MAX_LENGTH;
+/// Escape character, that starts the vt sequences.
+const ESC: u8 = 0x1B;
+
+/// Escape sequences of the keys, that do not produce text, in vt mode. Entries hold the number and final byte of `ESC [<number>;<modifiers><final>`, as sent by xterm. Without modifiers, the parameters are omitted, if the number is 1.
+const VT_SEQUENCES: [(Key, u8, u8); 7] = [
+    (Key::Up, 1, b'A'),
+    (Key::Down, 1, b'B'),
+    (Key::Right, 1, b'C'),
+    (Key::Left, 1, b'D'),
+    (Key::Home, 1, b'H'),
+    (Key::End, 1, b'F'),
+    (Key::Delete, 3, b'~'),
+];
+
+static INPUT: SpinLock<Input> = SpinLock::new(Input::new());
+
+/// Key of a key press, as the console sees it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Key {
+    /// Key producing text, already translated by the keyboard layout.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+}
+
+bitflags! {
+    /// Modifier keys held during a key press. The bits match the xterm modifier parameter minus one.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub(crate) struct Modifiers: u8 {
+        const SHIFT   = 1 << 0;
+        const ALT     = 1 << 1;
+        const CONTROL = 1 << 2;
+    }
+}
+
+/// Key press handed to the console in event mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct InputEvent {
+    pub(crate) key: Key,
+    pub(crate) modifiers: Modifiers,
+}
+
+impl InputEvent {
+    /// Encodes the key press as a vt terminal sends it. Returns the amount of bytes written, which is 0 for combinations without encoding.
+    fn encode_vt(&self, bytes: &mut [u8; VT_MAX_LENGTH]) -> usize {
+        let encoded: &[u8] = match self.key {
+            Key::Char(character) => {
+                // alt prefixes the character with an escape, control maps letters to control codes
+                let mut length = 0;
+                if self.modifiers.contains(Modifiers::ALT) {
+                    bytes[0] = ESC;
+                    length = 1;
+                }
+                if self.modifiers.contains(Modifiers::CONTROL) && character.is_ascii_alphabetic() {
+                    bytes[length] = character.to_ascii_lowercase() as u8 & 0x1F;
+                    return length + 1;
+                }
+                return length + character.encode_utf8(&mut bytes[length..]).len();
+            }
+            Key::Enter => b"\n",
+            Key::Tab => b"\t",
+            Key::Backspace => &[0x7F],
+            key => {
+                let Some((_, number, last)) = VT_SEQUENCES.iter().find(|(entry, ..)| *entry == key)
+                else {
+                    return 0;
+                };
+                let parameter = self.modifiers.bits() + 1;
+                match (number, parameter) {
+                    (1, 1) => &[ESC, b'[', *last],
+                    (_, 1) => &[ESC, b'[', b'0' + number, *last],
+                    _ => &[ESC, b'[', b'0' + number, b';', b'0' + parameter, *last],
+                }
+            }
+        };
+        bytes[..encoded.len()].copy_from_slice(encoded);
+        encoded.len()
+    }
+}
+
+/// Format the console reads key presses in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ConsoleMode {
+    /// Bytes as sent by a vt terminal, navigation keys are translated into escape sequences.
+    Vt,
+    /// Structured key presses, including their modifiers.
+    Events,
+}
+
+impl ConsoleMode {
+    /// Mode selected in the config registry.
+    fn configured() -> Self {
+        config::with("console_input", |value| match value {
+            Some("events") => ConsoleMode::Events,
+            _ => ConsoleMode::Vt,
+        })
+    }
+}
+
+/// Key presses, that have not been read yet, in the format of the console mode.
+#[derive(Debug)]
+struct Input {
+    mode: ConsoleMode,
+    events: Ring<InputEvent, EVENT_CAPACITY>,
+    bytes: Ring<u8, BYTE_CAPACITY>,
+}
+
+impl Input {
+    const fn new() -> Self {
+        Self {
+            mode: ConsoleMode::Vt,
+            events: Ring::new(),
+            bytes: Ring::new(),
+        }
+    }
+
+    fn push(&mut self, event: InputEvent) {
+        match self.mode {
+            ConsoleMode::Events => self.events.push(event),
+            ConsoleMode::Vt => {
+                let mut encoded = [0; VT_MAX_LENGTH];
+                let length = event.encode_vt(&mut encoded);
+                // sequences are never split, so the reader can not misinterpret their rest
+                if self.bytes.free() >= length {
+                    encoded[..length]
+                        .iter()
+                        .for_each(|byte| self.bytes.push(*byte));
+                }
+            }
+        }
+    }
+}
+
+/// Fixed size first-in first-out buffer, so the keyboard interrupt handler can queue key presses without allocating. Items pushed to a full buffer are dropped.
+#[derive(Debug)]
+struct Ring<T: Copy, const N: usize> {
+    items: [Option<T>; N],
+    head: usize,
+    length: usize,
+}
+
+impl<T: Copy, const N: usize> Ring<T, N> {
+    const fn new() -> Self {
+        Self {
+            items: [None; N],
+            head: 0,
+            length: 0,
+        }
+    }
+
+    fn free(&self) -> usize {
+        N - self.length
+    }
+
+    fn push(&mut self, item: T) {
+        if self.length < N {
+            self.items[(self.head + self.length) % N] = Some(item);
+            self.length += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.length == 0 {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.length -= 1;
+        item
+    }
+
+    fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// Queues a key press for the console. Called by the keyboard interrupt handler.
+pub(in crate::base::io) fn push(event: InputEvent) {
+    without_interrupts(|| INPUT.lock().push(event));
+}
+
+/// Switches to the console mode selected in the config registry. Key presses, that have not been read yet, are dropped, since they are in the format of the previous mode.
+pub(crate) fn apply_mode() {
+    let mode = ConsoleMode::configured();
+    without_interrupts(|| {
+        let mut input = INPUT.lock();
+        if input.mode != mode {
+            input.mode = mode;
+            input.events.clear();
+            input.bytes.clear();
+        }
+    });
+}
+
+/// Returns the current console mode.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn mode() -> ConsoleMode {
+    without_interrupts(|| INPUT.lock().mode)
+}
+
+/// Returns the oldest key press, that has not been read yet. Only returns key presses in event mode.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn read_event() -> Option<InputEvent> {
+    without_interrupts(|| INPUT.lock().events.pop())
+}
+
+/// Reads the pending bytes of key presses into the buffer. Only returns bytes in vt mode. Returns the amount of bytes read.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn read_bytes(buffer: &mut [u8]) -> usize {
+    without_interrupts(|| {
+        let mut input = INPUT.lock();
+        buffer
+            .iter_mut()
+            .map_while(|slot| input.bytes.pop().map(|byte| *slot = byte))
+            .count()
+    })
+}
@@ -1,7 +1,15 @@
 use core::marker::PhantomData;
 
-use crate::{base::io::keyboard::qwertz::Qwertz, print, println, scheduling::spin::SpinLock};
+use crate::{
+    base::io::keyboard::{
+        input::{InputEvent, Key, Modifiers},
+        qwertz::Qwertz,
+    },
+    print, println,
+    scheduling::spin::SpinLock,
+};
 
+pub(crate) mod input;
 mod qwertz;
 
 pub(in crate::base) static KEYBOARD: SpinLock<Keyboard<Qwertz>> = SpinLock::new(Keyboard::new());
@@ -30,6 +38,12 @@ where
 {
     is_left_shift: bool,
     is_right_shift: bool,
+    is_control: bool,
+    is_alt: bool,
+    /// Set by the prefix of extended scancodes, until the following scancode has been handled.
+    is_extended: bool,
+    /// Set once ctrl + alt + d has been pressed, until the interrupt handler enters the debugger.
+    debug_requested: bool,
     _marker: PhantomData<T>,
 }
 
@@ -41,31 +55,85 @@ where
         Self {
             is_left_shift: false,
             is_right_shift: false,
+            is_control: false,
+            is_alt: false,
+            is_extended: false,
+            debug_requested: false,
             _marker: PhantomData,
         }
     }
 
     pub(in crate::base) fn handle(&mut self, scancode: u8) {
+        if scancode == T::EXTENDED_PREFIX {
+            self.is_extended = true;
+            return;
+        }
+        if core::mem::take(&mut self.is_extended) {
+            if let Some((_, key)) = T::EXTENDED_KEYS.iter().find(|(code, _)| *code == scancode) {
+                self.send(*key);
+                return;
+            }
+            // some keyboards wrap the navigation keys in fake shift presses, which must not count as held shift
+            if scancode & 0x7F == T::LEFT_SHIFT {
+                return;
+            }
+            // right control and alt gr send the scancodes of their left counterparts, released extended keys are ignored below
+        }
+
         handle_scancode!(self, scancode, T,
             |ascii| {
-                if ascii != '\0' {
-                    print!("{}", ascii)
+                if self.is_control && self.is_alt && ascii == 'd' {
+                    self.debug_requested = true;
+                } else if ascii != '\0' {
+                    print!("{}", ascii);
+                    self.send(Key::Char(ascii));
                 }
             },
             T::LEFT_SHIFT => { self.is_left_shift = true; },
             T::LEFT_SHIFT + 0x80 => { self.is_left_shift = false; },
             T::RIGHT_SHIFT => { self.is_right_shift = true; },
             T::RIGHT_SHIFT + 0x80 => { self.is_right_shift = false; },
-            T::ENTER => println!()
+            T::CONTROL => { self.is_control = true; },
+            T::CONTROL + 0x80 => { self.is_control = false; },
+            T::ALT => { self.is_alt = true; },
+            T::ALT + 0x80 => { self.is_alt = false; },
+            T::BACKSPACE => { self.send(Key::Backspace); },
+            T::TAB => { self.send(Key::Tab); },
+            T::ENTER => { println!(); self.send(Key::Enter); }
         );
     }
+
+    /// Hands the key press to the console, together with the modifiers held.
+    fn send(&self, key: Key) {
+        let mut modifiers = Modifiers::empty();
+        modifiers.set(Modifiers::SHIFT, self.is_left_shift || self.is_right_shift);
+        modifiers.set(Modifiers::CONTROL, self.is_control);
+        modifiers.set(Modifiers::ALT, self.is_alt);
+        input::push(InputEvent { key, modifiers });
+    }
+
+    /// Returns whether the debugger has been requested since the last call.
+    pub(in crate::base) fn take_debug_request(&mut self) -> bool {
+        core::mem::take(&mut self.debug_requested)
+    }
 }
 
 pub(in crate::base) trait KeyboardType {
     const LEFT_SHIFT: u8;
     const RIGHT_SHIFT: u8;
+    /// Left control key. The right one sends the same scancode with a prefix.
+    const CONTROL: u8;
+    /// Left alt key. The right one (alt gr) sends the same scancode with a prefix.
+    const ALT: u8;
 
     const ENTER: u8;
+    const BACKSPACE: u8;
+    const TAB: u8;
+
+    /// First byte of two byte scancodes, e.g. of the navigation keys.
+    const EXTENDED_PREFIX: u8;
+    /// Keys sent with the extended prefix, that do not produce text.
+    const EXTENDED_KEYS: [(u8, Key); 7];
 
     const ASCII_TABLE: [char; 58];
 
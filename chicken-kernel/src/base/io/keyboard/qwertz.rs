@@ -1,4 +1,4 @@
-use crate::base::io::keyboard::KeyboardType;
+use crate::base::io::keyboard::{input::Key, KeyboardType};
 
 #[derive(Debug)]
 pub struct Qwertz;
@@ -6,7 +6,22 @@ pub struct Qwertz;
 impl KeyboardType for Qwertz {
     const LEFT_SHIFT: u8 = 0x2A;
     const RIGHT_SHIFT: u8 = 0x36;
+    const CONTROL: u8 = 0x1D;
+    const ALT: u8 = 0x38;
     const ENTER: u8 = 0x1C;
+    const BACKSPACE: u8 = 0x0E;
+    const TAB: u8 = 0x0F;
+
+    const EXTENDED_PREFIX: u8 = 0xE0;
+    const EXTENDED_KEYS: [(u8, Key); 7] = [
+        (0x48, Key::Up),
+        (0x50, Key::Down),
+        (0x4B, Key::Left),
+        (0x4D, Key::Right),
+        (0x47, Key::Home),
+        (0x4F, Key::End),
+        (0x53, Key::Delete),
+    ];
 
     const ASCII_TABLE: [char; 58] =
         ['\0', '\0', '1', '2', '3', '4', '5', '6', '7', '8',
@@ -4,73 +4,225 @@ use core::{
     fmt::{Debug, Display, Formatter},
 };
 
-use chicken_util::{BootInfo, PAGE_SIZE};
+use chicken_util::BootInfo;
+
+use alloc::vec;
 
 use crate::{
-    base::io::apic::ioapic,
-    memory::vmm::{AllocationType, object::VmFlags, VMM, VmmError},
+    base::{
+        acpi::ACPIError,
+        interrupts::irq::Irq,
+        power::{self, PowerHook},
+    },
+    devices::{self, Bus, Resource},
+    memory::vmm::VmmError,
 };
-use crate::base::io::timer::pit::{PIT, ProgrammableIntervalTimer};
-use crate::base::io::timer::Timer;
+use crate::base::io::timer::pit;
 
 pub(in crate::base) mod apic;
-pub(in crate::base) mod keyboard;
+pub(crate) mod keyboard;
+pub(crate) mod rtc;
+pub(crate) mod serial;
+pub(crate) mod speaker;
 pub(crate) mod timer;
 
 mod pic;
 
-pub(super) fn initialize(boot_info: &BootInfo) {
+/// Interrupt Request (IRQ) for PS/2 keyboard
+pub(in crate::base) const KEYBOARD_IRQ: Irq = Irq::new(1);
+/// Interrupt Request (IRQ) for pit
+pub(in crate::base) const TIMER_IRQ: Irq = Irq::new(0);
+/// Data port of the PS/2 controller, which the keyboard scancodes are read from.
+pub(in crate::base) const PS2_DATA_PORT: Port = 0x60;
+/// Status and command port of the PS/2 controller.
+const PS2_COMMAND_PORT: Port = 0x64;
+
+/// Interrupt controller that delivers hardware interrupts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum InterruptMode {
+    Apic,
+    /// Legacy 8259 PIC, used if selected with the `legacy-pic` feature or if the APIC is unusable.
+    Pic,
+}
+
+/// Sets up hardware interrupts. If the APIC can not be used, the legacy PIC handles the interrupts instead and the APIC error is returned.
+pub(super) fn initialize(boot_info: &BootInfo) -> Result<(), IOError> {
     // remap and disable pics, so they don't influence apic.
     unsafe {
         pic::remap();
         pic::disable();
     }
-    let apic_config = apic::set_up(boot_info).unwrap();
-
-    // map mmio for io apic register interactions
-    let mut binding = VMM.lock();
-    let vmm = binding.get_mut().unwrap();
-    let io_apic_virtual_address = vmm
-        .alloc(
-            PAGE_SIZE,
-            VmFlags::WRITE | VmFlags::MMIO,
-            AllocationType::Address(apic_config.io_apic_address),
-        )
-        .unwrap();
 
-    unsafe {
-        // reconfigure entry for keyboard input
-        ioapic::configure_redirection_entry(
-            io_apic_virtual_address,
-            apic_config.keyboard_source,
-            0x21,
-            apic_config.lapic_id,
-            true,
-        );
-
-        // reconfigure entry for pit timer ticks
-        ioapic::configure_redirection_entry(
-            io_apic_virtual_address,
-            apic_config.pit_source,
-            0x20,
-            apic_config.lapic_id,
-            true,
-        );
-
-        // enable PIT
-        let mut binding = PIT.lock();
-        binding.set_frequency(ProgrammableIntervalTimer::PIT_FREQUENCY);
+    let result = if cfg!(feature = "legacy-pic") {
+        Ok(())
+    } else {
+        apic::set_up(boot_info)
+    };
+
+    // key presses must be queued in the format selected on the command line
+    keyboard::input::apply_mode();
+    unmask_irq(KEYBOARD_IRQ)?;
+    register_devices();
+
+    // a tone must not keep playing while the system is suspended
+    power::register(PowerHook {
+        name: "pc speaker",
+        suspend: || {
+            speaker::stop();
+            Ok(())
+        },
+        resume: || Ok(()),
+    });
+
+    result
+}
+
+/// Registers the legacy devices handled by the kernel itself.
+fn register_devices() {
+    devices::register_bound(
+        "pit",
+        Bus::Isa,
+        vec![
+            Resource::Ports {
+                base: pit::TICK_GENERATOR_PORT,
+                count: pit::PIT_PORT - pit::TICK_GENERATOR_PORT + 1,
+            },
+            Resource::Irq(TIMER_IRQ),
+        ],
+        "pit",
+    );
+    devices::register_bound(
+        "keyboard",
+        Bus::Ps2,
+        vec![
+            Resource::Ports {
+                base: PS2_DATA_PORT,
+                count: 1,
+            },
+            Resource::Ports {
+                base: PS2_COMMAND_PORT,
+                count: 1,
+            },
+            Resource::Irq(KEYBOARD_IRQ),
+        ],
+        "keyboard",
+    );
+    devices::register_bound(
+        "pc speaker",
+        Bus::Isa,
+        vec![Resource::Ports {
+            base: speaker::SPEAKER_PORT,
+            count: 1,
+        }],
+        "speaker",
+    );
+}
+
+/// Returns the interrupt controller that is currently in use.
+pub(crate) fn interrupt_mode() -> InterruptMode {
+    if apic::is_enabled() {
+        InterruptMode::Apic
+    } else {
+        InterruptMode::Pic
+    }
+}
+
+/// Prevents the interrupt controller in use from delivering interrupts of the given IRQ.
+pub(crate) fn mask_irq(irq: Irq) -> Result<(), IOError> {
+    set_irq_masked(irq, true)
+}
+
+/// Allows the interrupt controller in use to deliver interrupts of the given IRQ.
+pub(crate) fn unmask_irq(irq: Irq) -> Result<(), IOError> {
+    set_irq_masked(irq, false)
+}
+
+/// Delivers interrupts of the given IRQ to the cpu with the given local apic id. The legacy PIC always delivers them to the BSP.
+pub(crate) fn set_irq_affinity(irq: Irq, lapic_id: u8) -> Result<(), IOError> {
+    match interrupt_mode() {
+        InterruptMode::Apic => apic::set_destination(irq, lapic_id),
+        InterruptMode::Pic => Err(IOError::InvalidIrqAffinity(lapic_id)),
+    }
+}
+
+fn set_irq_masked(irq: Irq, masked: bool) -> Result<(), IOError> {
+    match interrupt_mode() {
+        InterruptMode::Apic => apic::set_masked(irq, masked),
+        InterruptMode::Pic => {
+            unsafe {
+                if masked {
+                    pic::mask(irq);
+                } else {
+                    pic::unmask(irq);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Configuration of the interrupt controller in use, saved before a suspend.
+#[derive(Debug)]
+pub(in crate::base) enum InterruptControllerState {
+    Apic(apic::ApicState),
+    /// Interrupt masks of the master and the slave chip.
+    Pic(u8, u8),
+}
+
+/// Reads the configuration of the interrupt controller in use, which is lost during a suspend.
+pub(in crate::base) fn save_state() -> Result<InterruptControllerState, IOError> {
+    Ok(match interrupt_mode() {
+        InterruptMode::Apic => InterruptControllerState::Apic(apic::save_state()?),
+        InterruptMode::Pic => {
+            let (master, slave) = unsafe { pic::masks() };
+            InterruptControllerState::Pic(master, slave)
+        }
+    })
+}
+
+/// Restores the configuration of the interrupt controller read by [`save_state`] and of the timer.
+///
+/// # Safety
+/// Must be called with interrupts disabled.
+pub(in crate::base) unsafe fn restore_state(
+    state: &InterruptControllerState,
+) -> Result<(), IOError> {
+    // the pics are reset as well, so they must be remapped in either mode
+    pic::remap();
+    match state {
+        InterruptControllerState::Apic(state) => {
+            pic::disable();
+            apic::restore_state(state)?;
+        }
+        InterruptControllerState::Pic(master, slave) => pic::set_masks(*master, *slave),
+    }
+
+    // the frequency is still known, only the hardware has to be programmed again
+    timer::reprogram();
+    Ok(())
+}
+
+/// Sends the end of interrupt signal for the given IRQ to the interrupt controller that is in use.
+pub(in crate::base) fn eoi(irq: Irq) {
+    match interrupt_mode() {
+        InterruptMode::Apic => apic::lapic::eoi(),
+        InterruptMode::Pic => unsafe { pic::eoi(irq) },
     }
 }
 
-pub(in crate::base::io) type Port = u16;
+/// Checks whether an interrupt on the lowest priority line of one of the PICs (IRQ 7 or 15) is spurious. Spurious interrupts must not be acknowledged.
+pub(in crate::base) fn is_spurious(irq: Irq) -> bool {
+    unsafe { pic::is_spurious(irq) }
+}
+
+pub(crate) type Port = u16;
 
 /// Write 8 bits to the specified port.
 ///
 /// # Safety
 /// Needs IO privileges.
 #[inline]
-pub(in crate::base::io) unsafe fn outb(port: Port, value: u8) {
+pub(in crate::base) unsafe fn outb(port: Port, value: u8) {
     unsafe {
         asm!("out dx, al", in("dx") port, in("al") value);
     }
@@ -87,6 +239,28 @@ pub(in crate::base) unsafe fn inb(port: Port) -> u8 {
     value
 }
 
+/// Write 16 bits to the specified port.
+///
+/// # Safety
+/// Needs IO privileges.
+#[inline]
+pub(crate) unsafe fn outw(port: Port, value: u16) {
+    unsafe {
+        asm!("out dx, ax", in("dx") port, in("ax") value);
+    }
+}
+
+/// Read 16 bits from the specified port.
+///
+/// # Safety
+/// Needs IO privileges.
+#[inline]
+pub(crate) unsafe fn inw(port: Port) -> u16 {
+    let value: u16;
+    asm!("in ax, dx", out("ax") value, in("dx") port);
+    value
+}
+
 /// Older machines may require to wait a cycle before continuing the io pic communication.
 ///
 /// # Safety
@@ -97,11 +271,24 @@ pub unsafe fn io_wait() {
 }
 
 #[derive(Copy, Clone)]
-pub(in crate::base::io) enum IOError {
+pub(crate) enum IOError {
     ModelSpecificRegisterUnavailable,
     MemoryMappingFailed(VmmError),
+    AcpiParsingFailed(ACPIError),
     MadtNotFound,
     IOApicEntryNotFound,
+    IOApicUninitialized,
+    GsiOutOfRange(u32),
+    LocalApicUninitialized,
+    TimerCalibrationFailed,
+    HpetUnusable,
+    InvalidTimerFrequency(u64),
+    InvalidToneFrequency(u64),
+    InvalidRtcRate(u8),
+    IrqInUse(u8),
+    InvalidIrqAffinity(u8),
+    InvalidBaudRate(u32),
+    SerialPortMissing(Port),
 }
 
 impl Debug for IOError {
@@ -113,6 +300,9 @@ impl Debug for IOError {
             IOError::MemoryMappingFailed(value) => {
                 write!(f, "IOError: Memory Mapping failed: {}", value)
             }
+            IOError::AcpiParsingFailed(value) => {
+                write!(f, "IOError: {:?}", value)
+            }
             IOError::MadtNotFound => {
                 write!(
                     f,
@@ -122,6 +312,42 @@ impl Debug for IOError {
             IOError::IOApicEntryNotFound => {
                 write!(f, "IOError: System Descriptor Table with APIC information could be found, but does not contain valid IO APIC entry.")
             }
+            IOError::IOApicUninitialized => {
+                write!(f, "IOError: IO APIC has not been set up.")
+            }
+            IOError::GsiOutOfRange(gsi) => {
+                write!(f, "IOError: GSI {} is not handled by the IO APIC.", gsi)
+            }
+            IOError::LocalApicUninitialized => {
+                write!(f, "IOError: Local APIC has not been set up.")
+            }
+            IOError::TimerCalibrationFailed => {
+                write!(f, "IOError: Timer could not be calibrated against the PIT.")
+            }
+            IOError::HpetUnusable => {
+                write!(f, "IOError: HPET does not provide a usable 64 bit main counter.")
+            }
+            IOError::InvalidTimerFrequency(frequency) => {
+                write!(f, "IOError: Timer can not be set to a frequency of {} Hz.", frequency)
+            }
+            IOError::InvalidToneFrequency(frequency) => {
+                write!(f, "IOError: PC speaker can not play a tone with a frequency of {} Hz.", frequency)
+            }
+            IOError::InvalidRtcRate(rate) => {
+                write!(f, "IOError: RTC periodic interrupt can not be set to a rate of {}.", rate)
+            }
+            IOError::IrqInUse(line) => {
+                write!(f, "IOError: IRQ {} already has an interrupt handler.", line)
+            }
+            IOError::InvalidIrqAffinity(lapic_id) => {
+                write!(f, "IOError: IRQs can not be delivered to the cpu with LAPIC ID: {}.", lapic_id)
+            }
+            IOError::InvalidBaudRate(baud_rate) => {
+                write!(f, "IOError: Serial port can not be set to a baud rate of {}.", baud_rate)
+            }
+            IOError::SerialPortMissing(port) => {
+                write!(f, "IOError: There is no serial port at port: {:#x}.", port)
+            }
         }
     }
 }
@@ -139,3 +365,9 @@ impl From<VmmError> for IOError {
         Self::MemoryMappingFailed(value)
     }
 }
+
+impl From<ACPIError> for IOError {
+    fn from(value: ACPIError) -> Self {
+        Self::AcpiParsingFailed(value)
+    }
+}
@@ -1,7 +1,10 @@
 #![allow(dead_code)] // keeping all command constants for completeness, although, they are not all used
 
 
-use crate::base::io::{inb, io_wait, outb, Port};
+use crate::base::{
+    interrupts::irq::{Irq, IRQ_BASE_VECTOR},
+    io::{inb, io_wait, outb, Port},
+};
 // ports:
 // handled interrupt numbers 0 - 7:
 // control information
@@ -22,6 +25,10 @@ const ICW1_ICW4: u8 = 0x01;
 const ICW1_INIT: u8 = 0x10;
 // 8086/88 (MCS-80/85) mode
 const ICW4_8086: u8 = 0x01;
+// end of interrupt command
+const PIC_EOI: u8 = 0x20;
+// read in-service register command
+const PIC_READ_ISR: u8 = 0x0B;
 
 /// Remaps the pic outputs. The master chip to [`PIC_MASTER_DATA`] and the slave chip to [`PIC_SLAVE_DATA`].
 ///
@@ -42,9 +49,9 @@ pub(super) unsafe fn remap() {
     io_wait();
 
     // set interrupt offsets to avoid collision with interrupt indices
-    outb(PIC_MASTER_DATA, 0x20);
+    outb(PIC_MASTER_DATA, IRQ_BASE_VECTOR.index());
     io_wait();
-    outb(PIC_SLAVE_DATA, 0x28);
+    outb(PIC_SLAVE_DATA, Irq::new(8).vector().index());
     io_wait();
 
     // tell PIC master and slave how they correspond to each other
@@ -74,3 +81,89 @@ pub(super) unsafe fn disable() {
     outb(PIC_MASTER_DATA, 0xFF);
     outb(PIC_SLAVE_DATA, 0xFF);
 }
+
+/// Returns the interrupt masks of the master and the slave chip.
+///
+/// # Safety
+/// Needs IO privileges.
+pub(super) unsafe fn masks() -> (u8, u8) {
+    (inb(PIC_MASTER_DATA), inb(PIC_SLAVE_DATA))
+}
+
+/// Sets the interrupt masks of the master and the slave chip, as returned by [`masks`].
+///
+/// # Safety
+/// Needs IO privileges.
+pub(super) unsafe fn set_masks(master: u8, slave: u8) {
+    outb(PIC_MASTER_DATA, master);
+    io_wait();
+    outb(PIC_SLAVE_DATA, slave);
+    io_wait();
+}
+
+/// Masks the given IRQ line, so the pic no longer delivers its interrupts.
+///
+/// # Safety
+/// Needs IO privileges.
+pub(super) unsafe fn mask(irq: Irq) {
+    let irq = irq.line();
+    let (port, line) = if irq < 8 {
+        (PIC_MASTER_DATA, irq)
+    } else {
+        (PIC_SLAVE_DATA, irq - 8)
+    };
+    let bitmask = inb(port) | (1 << line);
+    outb(port, bitmask);
+}
+
+/// Unmasks the given IRQ line, so the pic delivers its interrupts again.
+///
+/// # Safety
+/// Needs IO privileges.
+pub(super) unsafe fn unmask(irq: Irq) {
+    let irq = irq.line();
+    let (port, line) = if irq < 8 {
+        (PIC_MASTER_DATA, irq)
+    } else {
+        // the slave chip is connected to line 2 of the master chip
+        let cascade = inb(PIC_MASTER_DATA) & !(1 << 2);
+        outb(PIC_MASTER_DATA, cascade);
+        (PIC_SLAVE_DATA, irq - 8)
+    };
+    let bitmask = inb(port) & !(1 << line);
+    outb(port, bitmask);
+}
+
+/// Signals the pic(s) that the interrupt of the given IRQ line has been handled.
+///
+/// # Safety
+/// Needs IO privileges.
+pub(super) unsafe fn eoi(irq: Irq) {
+    if irq.line() >= 8 {
+        outb(PIC_SLAVE_COMMAND, PIC_EOI);
+    }
+    outb(PIC_MASTER_COMMAND, PIC_EOI);
+}
+
+/// Checks the in-service register to determine whether an interrupt of the given IRQ line is spurious. If a spurious interrupt has been sent by the slave chip, the master chip still receives an end of interrupt signal.
+///
+/// # Safety
+/// Needs IO privileges.
+pub(super) unsafe fn is_spurious(irq: Irq) -> bool {
+    let irq = irq.line();
+    let (command_port, line) = if irq < 8 {
+        (PIC_MASTER_COMMAND, irq)
+    } else {
+        (PIC_SLAVE_COMMAND, irq - 8)
+    };
+    outb(command_port, PIC_READ_ISR);
+    let in_service = inb(command_port);
+
+    let spurious = in_service & (1 << line) == 0;
+    if spurious && irq >= 8 {
+        // the master chip does not know that the interrupt of the slave chip was spurious
+        outb(PIC_MASTER_COMMAND, PIC_EOI);
+    }
+
+    spurious
+}
@@ -0,0 +1,354 @@
+use core::{
+    fmt::{Display, Formatter},
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+use alloc::vec;
+use chicken_util::BootInfo;
+
+use crate::{
+    base::{
+        acpi::fadt::Fadt,
+        interrupts::{
+            irq::{self, Handle, Irq},
+            without_interrupts,
+        },
+        io::{inb, io_wait, outb, timer::hpet::nanoseconds_since_boot, IOError, Port},
+        power::{self, PowerHook},
+    },
+    devices::{self, Bus, Resource},
+    scheduling::spin::SpinLock,
+};
+
+/// Selects the CMOS register that is accessed through the data port.
+const INDEX_PORT: Port = 0x70;
+const DATA_PORT: Port = 0x71;
+
+const SECONDS_REGISTER: u8 = 0x00;
+const MINUTES_REGISTER: u8 = 0x02;
+const HOURS_REGISTER: u8 = 0x04;
+const DAY_REGISTER: u8 = 0x07;
+const MONTH_REGISTER: u8 = 0x08;
+const YEAR_REGISTER: u8 = 0x09;
+/// Update in progress flag and rate of the periodic interrupt.
+const STATUS_A_REGISTER: u8 = 0x0A;
+/// Data format and interrupt enable flags.
+const STATUS_B_REGISTER: u8 = 0x0B;
+/// Interrupt flags. Reading it acknowledges the interrupt, otherwise the RTC does not raise another one.
+const STATUS_C_REGISTER: u8 = 0x0C;
+
+/// Set in status register A, while the RTC updates the time. The registers may be inconsistent meanwhile.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Set in status register B, if the values are binary instead of BCD.
+const BINARY_MODE: u8 = 1 << 2;
+/// Set in status register B, if hours are counted from 0 to 23 instead of from 1 to 12.
+const HOURS_24: u8 = 1 << 1;
+/// Enables the periodic interrupt in status register B.
+const PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Set in the hours register in 12 hour mode for the hours after noon.
+const PM: u8 = 1 << 7;
+/// Mask of the rate of the periodic interrupt in status register A.
+const RATE_MASK: u8 = 0x0F;
+
+/// Fastest rate of the periodic interrupt (8192 Hz). Faster rates do not work reliably.
+pub(crate) const MIN_PERIODIC_RATE: u8 = 3;
+/// Slowest rate of the periodic interrupt (2 Hz).
+pub(crate) const MAX_PERIODIC_RATE: u8 = 15;
+/// Frequency in Hz the RTC divides by the rate of the periodic interrupt.
+const BASE_FREQUENCY: u64 = 32768;
+
+/// Interrupt Request (IRQ) of the RTC.
+const RTC_IRQ: Irq = Irq::new(8);
+/// Century assumed, if the FADT does not name a century register.
+const DEFAULT_CENTURY: u16 = 20;
+/// Attempts to read the same time twice in a row, before the last read is used anyway.
+const MAX_READ_ATTEMPTS: usize = 8;
+
+/// Serializes the accesses to the CMOS, since each of them consists of selecting the register and accessing the data port.
+static CMOS: SpinLock<()> = SpinLock::new(());
+/// CMOS register holding the century, as named by the FADT, 0 if there is none.
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+/// Unix time in seconds at which the uptime started counting, 0 until the RTC has been read.
+static BOOT_TIME_S: AtomicU64 = AtomicU64::new(0);
+/// Periodic interrupts raised since they have been started.
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+/// Registration of the handler of the periodic interrupt, while it is enabled.
+static PERIODIC_HANDLE: SpinLock<Option<Handle>> = SpinLock::new(None);
+
+/// Calendar date and time of day in UTC, as kept by the RTC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub(crate) year: u16,
+    pub(crate) month: u8,
+    pub(crate) day: u8,
+    pub(crate) hour: u8,
+    pub(crate) minute: u8,
+    pub(crate) second: u8,
+}
+
+impl DateTime {
+    /// Returns the date and time of the given unix time in seconds.
+    pub(crate) fn from_unix_time(seconds: u64) -> Self {
+        let (days, seconds) = (seconds / 86400, seconds % 86400);
+        // algorithm by Howard Hinnant, with eras of 400 years starting on the 1st of March
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// Returns the seconds since the 1st of January 1970, 0 for earlier dates.
+    pub(crate) fn unix_time(&self) -> u64 {
+        let (month, day) = (self.month as u64, self.day as u64);
+        let year = self.year as u64 - u64::from(month <= 2);
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * 146_097 + day_of_era).saturating_sub(719_468);
+
+        days * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Reads the time of the RTC, from which the wall clock continues with the uptime. Returns the time read.
+pub(in crate::base) fn set_up(boot_info: &BootInfo) -> DateTime {
+    let century_register = Fadt::get(boot_info).map_or(0, |fadt| fadt.century_register());
+    CENTURY_REGISTER.store(century_register, Ordering::Relaxed);
+    let now = synchronize();
+
+    devices::register_bound(
+        "rtc",
+        Bus::Isa,
+        vec![
+            Resource::Ports {
+                base: INDEX_PORT,
+                count: DATA_PORT - INDEX_PORT + 1,
+            },
+            Resource::Irq(RTC_IRQ),
+        ],
+        "rtc",
+    );
+    // the uptime does not advance while the system is suspended, so the time is read again after resuming
+    power::register(PowerHook {
+        name: "rtc",
+        suspend: || Ok(()),
+        resume: || {
+            synchronize();
+            Ok(())
+        },
+    });
+
+    now
+}
+
+/// Returns the current date and time in UTC, or `None` if the RTC has not been read yet. Continues from the time read at boot with the monotonic clock, so it neither accesses the CMOS nor takes a lock.
+#[allow(dead_code)] // no timestamps are recorded yet
+pub(crate) fn wall_clock() -> Option<DateTime> {
+    unix_time().map(DateTime::from_unix_time)
+}
+
+/// Returns the current unix time in seconds, or `None` if the RTC has not been read yet.
+pub(crate) fn unix_time() -> Option<u64> {
+    match BOOT_TIME_S.load(Ordering::Relaxed) {
+        0 => None,
+        boot_time => Some(boot_time + nanoseconds_since_boot() / 1_000_000_000),
+    }
+}
+
+/// Reads the time of the RTC and restarts the wall clock from it. Returns the time read.
+fn synchronize() -> DateTime {
+    let now = read_time();
+    let uptime_s = nanoseconds_since_boot() / 1_000_000_000;
+    BOOT_TIME_S.store(
+        now.unix_time().saturating_sub(uptime_s).max(1),
+        Ordering::Relaxed,
+    );
+    now
+}
+
+/// Reads the date and time of the RTC. The registers are read until the same values are read twice, so an update in between can not tear them.
+pub(crate) fn read_time() -> DateTime {
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        let mut previous = read_registers();
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let current = read_registers();
+            if current == previous {
+                break;
+            }
+            previous = current;
+        }
+
+        let status_b = unsafe { read_register(STATUS_B_REGISTER) };
+        decode(previous, status_b)
+    })
+}
+
+/// Raw values of the time registers, in the order seconds, minutes, hours, day, month, year and century.
+type Registers = [u8; 7];
+
+/// Reads the time registers, once the RTC is not updating them. Requires the CMOS lock.
+fn read_registers() -> Registers {
+    unsafe {
+        while read_register(STATUS_A_REGISTER) & UPDATE_IN_PROGRESS != 0 {
+            core::hint::spin_loop();
+        }
+        let century = match CENTURY_REGISTER.load(Ordering::Relaxed) {
+            0 => 0,
+            register => read_register(register),
+        };
+        [
+            read_register(SECONDS_REGISTER),
+            read_register(MINUTES_REGISTER),
+            read_register(HOURS_REGISTER),
+            read_register(DAY_REGISTER),
+            read_register(MONTH_REGISTER),
+            read_register(YEAR_REGISTER),
+            century,
+        ]
+    }
+}
+
+/// Converts the raw values of the time registers from the data format given in status register B.
+fn decode(registers: Registers, status_b: u8) -> DateTime {
+    let [second, minute, hour, day, month, year, century] = registers;
+    let value = |raw: u8| {
+        if status_b & BINARY_MODE != 0 {
+            raw
+        } else {
+            (raw >> 4) * 10 + (raw & 0x0F)
+        }
+    };
+
+    let mut hour_of_day = value(hour & !PM);
+    if status_b & HOURS_24 == 0 {
+        // 12 am is midnight, 12 pm is noon
+        hour_of_day %= 12;
+        if hour & PM != 0 {
+            hour_of_day += 12;
+        }
+    }
+    let century = match CENTURY_REGISTER.load(Ordering::Relaxed) {
+        0 => DEFAULT_CENTURY,
+        _ => value(century) as u16,
+    };
+
+    DateTime {
+        year: century * 100 + value(year) as u16,
+        month: value(month),
+        day: value(day),
+        hour: hour_of_day,
+        minute: value(minute),
+        second: value(second),
+    }
+}
+
+/// Starts the periodic interrupt of the RTC with the given rate, i.e. at 32768 Hz >> (rate - 1). A periodic interrupt that is already running is restarted with the new rate.
+#[allow(dead_code)] // nothing needs the periodic interrupt yet
+pub(crate) fn start_periodic(rate: u8) -> Result<(), IOError> {
+    if !(MIN_PERIODIC_RATE..=MAX_PERIODIC_RATE).contains(&rate) {
+        return Err(IOError::InvalidRtcRate(rate));
+    }
+    stop_periodic();
+
+    let handle = irq::register(RTC_IRQ, periodic_interrupt)?;
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            let status_a = read_register(STATUS_A_REGISTER);
+            write_register(STATUS_A_REGISTER, (status_a & !RATE_MASK) | rate);
+            let status_b = read_register(STATUS_B_REGISTER);
+            write_register(STATUS_B_REGISTER, status_b | PERIODIC_INTERRUPT);
+            // an interrupt that is still pending would block the next one
+            read_register(STATUS_C_REGISTER);
+        }
+    });
+    PERIODIC_TICKS.store(0, Ordering::Relaxed);
+    handle.unmask()?;
+    without_interrupts(|| *PERIODIC_HANDLE.lock() = Some(handle));
+    Ok(())
+}
+
+/// Stops the periodic interrupt of the RTC, if it is running.
+#[allow(dead_code)] // nothing needs the periodic interrupt yet
+pub(crate) fn stop_periodic() {
+    without_interrupts(|| {
+        // masks the interrupt and unregisters the handler
+        drop(PERIODIC_HANDLE.lock().take());
+        let _cmos = CMOS.lock();
+        unsafe {
+            let status_b = read_register(STATUS_B_REGISTER);
+            write_register(STATUS_B_REGISTER, status_b & !PERIODIC_INTERRUPT);
+        }
+    });
+}
+
+/// Returns the frequency of the periodic interrupt with the given rate in Hz.
+#[allow(dead_code)] // nothing needs the periodic interrupt yet
+pub(crate) const fn periodic_frequency(rate: u8) -> u64 {
+    BASE_FREQUENCY >> (rate - 1)
+}
+
+/// Returns the amount of periodic interrupts raised since they have been started.
+#[allow(dead_code)] // nothing needs the periodic interrupt yet
+pub(crate) fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// Counts a periodic interrupt and acknowledges it to the RTC.
+fn periodic_interrupt(_irq: Irq) {
+    let _cmos = CMOS.lock();
+    unsafe { read_register(STATUS_C_REGISTER) };
+    PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Reads the CMOS register with the given index.
+///
+/// # Safety
+/// Needs IO privileges and the CMOS lock.
+unsafe fn read_register(register: u8) -> u8 {
+    outb(INDEX_PORT, register);
+    io_wait();
+    inb(DATA_PORT)
+}
+
+/// Writes to the CMOS register with the given index.
+///
+/// # Safety
+/// Needs IO privileges and the CMOS lock.
+unsafe fn write_register(register: u8, value: u8) {
+    outb(INDEX_PORT, register);
+    io_wait();
+    outb(DATA_PORT, value);
+}
@@ -0,0 +1,164 @@
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+use crate::{
+    base::io::{inb, outb, IOError, Port},
+    config,
+};
+
+/// First serial port, which QEMU forwards to its console.
+pub(crate) const COM1: Port = 0x3F8;
+/// Second serial port, e.g. used by the gdb stub, so its packets do not mix with the kernel log.
+pub(crate) const COM2: Port = 0x2F8;
+/// Baud rate of the serial console, unless set with `serial=<baud rate>`.
+pub(crate) const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+const DATA: Port = 0;
+const INTERRUPT_ENABLE: Port = 1;
+const FIFO_CONTROL: Port = 2;
+const LINE_CONTROL: Port = 3;
+const MODEM_CONTROL: Port = 4;
+const LINE_STATUS: Port = 5;
+/// Line status bit, that is set once a received byte can be read.
+const DATA_READY: u8 = 1 << 0;
+/// Line status bit, that is set once the transmitter can accept the next byte.
+const TRANSMITTER_EMPTY: u8 = 1 << 5;
+/// Line control bit, that maps the baud rate divisor to the data and interrupt enable registers.
+const DIVISOR_LATCH: u8 = 1 << 7;
+/// Modem control bits: data terminal ready, request to send and the output used to enable interrupts on PCs.
+const MODEM_READY: u8 = 0x0B;
+/// Modem control bit, that connects the transmitter to the receiver, e.g. to test whether the UART exists.
+const LOOPBACK: u8 = 1 << 4;
+const LOOPBACK_TEST_BYTE: u8 = 0xAE;
+/// Frequency of the clock the baud rate is divided from.
+const UART_CLOCK: u32 = 115_200;
+/// Amount of line status polls before a byte is sent anyway, so a missing serial port does not hang the caller.
+const TRANSMIT_TIMEOUT: usize = 100_000;
+
+/// Port of the serial console, 0 if it is disabled. Firmware and QEMU set up the first serial port, so it is used before the console has been set up. Not protected by a lock, so panics can be printed while any lock is held.
+static CONSOLE: AtomicU16 = AtomicU16::new(COM1);
+
+/// Prints to the serial console, followed by a new line. Does neither lock nor allocate, so it can be used at any point during boot and while panicking.
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::base::io::serial::print(format_args!("\n")));
+    ($($arg:tt)*) => ($crate::base::io::serial::print(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+/// 16550 compatible UART.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Uart {
+    port: Port,
+}
+
+impl Uart {
+    pub(crate) const fn new(port: Port) -> Self {
+        Self { port }
+    }
+
+    /// Configures the UART for the given baud rate, 8 data bits, no parity and one stop bit, without interrupts. Fails, if the baud rate can not be divided from the clock of the UART or the UART does not exist.
+    pub(crate) fn initialize(&self, baud_rate: u32) -> Result<(), IOError> {
+        if baud_rate == 0 || !UART_CLOCK.is_multiple_of(baud_rate) {
+            return Err(IOError::InvalidBaudRate(baud_rate));
+        }
+        let [divisor_low, divisor_high] = ((UART_CLOCK / baud_rate) as u16).to_le_bytes();
+
+        unsafe {
+            outb(self.port + INTERRUPT_ENABLE, 0);
+            outb(self.port + LINE_CONTROL, DIVISOR_LATCH);
+            outb(self.port + DATA, divisor_low);
+            outb(self.port + INTERRUPT_ENABLE, divisor_high);
+            // 8 data bits, no parity, one stop bit
+            outb(self.port + LINE_CONTROL, 0x03);
+            // enable and clear the fifos
+            outb(self.port + FIFO_CONTROL, 0xC7);
+
+            // a missing UART does not echo the test byte
+            outb(self.port + MODEM_CONTROL, MODEM_READY | LOOPBACK);
+            outb(self.port + DATA, LOOPBACK_TEST_BYTE);
+            let echo = inb(self.port + DATA);
+            outb(self.port + MODEM_CONTROL, MODEM_READY);
+            if echo != LOOPBACK_TEST_BYTE {
+                return Err(IOError::SerialPortMissing(self.port));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a byte once the transmitter is ready, or after a timeout.
+    pub(crate) fn write_byte(&self, byte: u8) {
+        for _ in 0..TRANSMIT_TIMEOUT {
+            if unsafe { inb(self.port + LINE_STATUS) } & TRANSMITTER_EMPTY != 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        unsafe { outb(self.port + DATA, byte) };
+    }
+
+    /// Waits until a byte has been received and returns it.
+    pub(crate) fn read_byte(&self) -> u8 {
+        while unsafe { inb(self.port + LINE_STATUS) } & DATA_READY == 0 {
+            core::hint::spin_loop();
+        }
+        unsafe { inb(self.port + DATA) }
+    }
+}
+
+impl Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // terminals expect a carriage return before each new line
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Sets up the serial console with the baud rate given with `serial=<baud rate>`. `serial=off` disables it, as does a missing serial port, so printing does not wait for the transmit timeout of every byte.
+pub(crate) fn set_up() -> Result<(), IOError> {
+    let baud_rate = config::with("serial", |value| match value {
+        Some("off") => None,
+        Some(value) => Some(value.parse().unwrap_or(0)),
+        None => Some(DEFAULT_BAUD_RATE),
+    });
+    let Some(baud_rate) = baud_rate else {
+        disable_console();
+        return Ok(());
+    };
+
+    let uart = Uart::new(COM1);
+    let result = match uart.initialize(baud_rate) {
+        // the log is still printed with the default baud rate
+        Err(IOError::InvalidBaudRate(_)) => uart
+            .initialize(DEFAULT_BAUD_RATE)
+            .and(Err(IOError::InvalidBaudRate(baud_rate))),
+        result => result,
+    };
+    if let Err(IOError::SerialPortMissing(_)) = result {
+        disable_console();
+    }
+    result
+}
+
+/// Stops printing to the serial console, e.g. because the port is used by the gdb stub.
+pub(crate) fn disable_console() {
+    CONSOLE.store(0, Ordering::SeqCst);
+}
+
+/// Returns the port of the serial console, if it is enabled.
+pub(crate) fn console_port() -> Option<Port> {
+    Some(CONSOLE.load(Ordering::SeqCst)).filter(|port| *port != 0)
+}
+
+/// Prints to the serial console without taking any locks. Output of interrupt handlers may interleave with other output.
+pub(crate) fn print(args: fmt::Arguments) {
+    if let Some(port) = console_port() {
+        let _ = Uart::new(port).write_fmt(args);
+    }
+}
@@ -0,0 +1,81 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    base::{
+        interrupts::without_interrupts,
+        io::{
+            inb, outb,
+            timer::pit::{get_current_uptime_ms, PIT},
+            IOError, Port,
+        },
+    },
+    scheduling::GlobalTaskScheduler,
+};
+
+/// Controls whether channel 2 of the PIT drives the PC speaker.
+pub(super) const SPEAKER_PORT: Port = 0x61;
+/// Gate of channel 2 and speaker data enable bits.
+pub(super) const SPEAKER_ENABLE: u8 = 0b11;
+
+/// Lowest frequency of a tone in Hz.
+pub(crate) const MIN_TONE_FREQUENCY: u64 = 20;
+/// Highest frequency of a tone in Hz.
+pub(crate) const MAX_TONE_FREQUENCY: u64 = 20_000;
+
+/// Uptime in ms at which the current tone ends, 0 if no tone is playing.
+static TONE_END_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Plays a tone with the specified frequency in Hz on the PC speaker. Returns right away, the tone is stopped by the timer interrupt after `duration_ms`. A tone that is still playing is replaced.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn beep(frequency: u64, duration_ms: u64) -> Result<(), IOError> {
+    if !(MIN_TONE_FREQUENCY..=MAX_TONE_FREQUENCY).contains(&frequency) {
+        return Err(IOError::InvalidToneFrequency(frequency));
+    }
+
+    without_interrupts(|| {
+        unsafe {
+            PIT.lock().set_speaker_frequency(frequency);
+        }
+        TONE_END_MS.store(
+            get_current_uptime_ms() + duration_ms.max(1),
+            Ordering::SeqCst,
+        );
+        unsafe {
+            outb(SPEAKER_PORT, inb(SPEAKER_PORT) | SPEAKER_ENABLE);
+        }
+    });
+    Ok(())
+}
+
+/// Plays a tone with the specified frequency in Hz on the PC speaker and waits until it has ended.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn beep_blocking(frequency: u64, duration_ms: u64) -> Result<(), IOError> {
+    beep(frequency, duration_ms)?;
+    GlobalTaskScheduler::sleep(duration_ms);
+    Ok(())
+}
+
+/// Stops the current tone.
+pub(crate) fn stop() {
+    without_interrupts(|| {
+        TONE_END_MS.store(0, Ordering::SeqCst);
+        unsafe {
+            outb(SPEAKER_PORT, inb(SPEAKER_PORT) & !SPEAKER_ENABLE);
+        }
+    });
+}
+
+/// Uptime in ms at which the current tone ends, if a tone is playing.
+pub(crate) fn tone_end_ms() -> Option<u64> {
+    match TONE_END_MS.load(Ordering::SeqCst) {
+        0 => None,
+        end => Some(end),
+    }
+}
+
+/// Stops the current tone, once it has ended. Called by the timer interrupt.
+pub(in crate::base) fn tick(uptime_ms: u64) {
+    if tone_end_ms().is_some_and(|end| uptime_ms >= end) {
+        stop();
+    }
+}
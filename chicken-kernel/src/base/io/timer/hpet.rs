@@ -0,0 +1,142 @@
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, Ordering},
+};
+
+use alloc::vec;
+use chicken_util::{memory::VirtualAddress, BootInfo};
+
+use crate::{
+    base::{
+        acpi::hpet::Hpet,
+        interrupts::without_interrupts,
+        io::{timer::pit::TIME, IOError},
+        power::{self, PowerHook},
+    },
+    devices::{self, Bus, Resource},
+    memory::vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+};
+
+const CAPABILITIES_OFFSET: usize = 0x0;
+const CONFIGURATION_OFFSET: usize = 0x10;
+const MAIN_COUNTER_OFFSET: usize = 0xF0;
+/// Size of the registers of a timer block.
+const REGISTERS_SIZE: usize = 0x400;
+
+/// Set in the capabilities, if the main counter is 64 bits wide.
+const COUNTER_64_BIT: u64 = 1 << 13;
+/// Starts the main counter.
+const ENABLE: u64 = 1 << 0;
+/// Routes the first comparators to the IRQs of the PIT and the RTC, which would take the ticks away from the PIT.
+const LEGACY_REPLACEMENT: u64 = 1 << 1;
+/// Longest period of the main counter in fs the specification allows (100 ns).
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Registers of the HPET, null until it has been set up. The clock is read without locking, so it can be used from any context.
+static REGISTERS: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+/// Period of the main counter in fs.
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+/// Time in ns at which the main counter has been started, so the clock continues from the uptime counted so far.
+static OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+/// Value of the main counter while the system is suspended, since the HPET loses it.
+static SUSPENDED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Maps the registers of the HPET described by the ACPI table and starts its main counter. Returns the frequency of the main counter in Hz.
+pub(in crate::base) fn set_up(boot_info: &BootInfo) -> Result<u64, IOError> {
+    let table = Hpet::get(boot_info)?;
+    let physical_address = table.base_address().ok_or(IOError::HpetUnusable)?;
+
+    // the mapping is never freed, since the clock is read until shutdown
+    let mut binding = VMM.lock();
+    let vmm = binding
+        .get_mut()
+        .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+    let registers = vmm.alloc(
+        REGISTERS_SIZE,
+        VmFlags::MMIO | VmFlags::WRITE,
+        AllocationType::Address(physical_address),
+    )?;
+
+    let capabilities = unsafe { read(registers, CAPABILITIES_OFFSET) };
+    let period = capabilities >> 32;
+    // a 32 bit main counter overflows within minutes
+    if period == 0 || period > MAX_PERIOD_FS || capabilities & COUNTER_64_BIT == 0 {
+        vmm.free(registers)?;
+        return Err(IOError::HpetUnusable);
+    }
+    drop(binding);
+
+    without_interrupts(|| unsafe {
+        OFFSET_NS.store(TIME.read().uptime_us() * 1000, Ordering::Relaxed);
+        start(registers, 0);
+    });
+    PERIOD_FS.store(period, Ordering::Relaxed);
+    REGISTERS.store(registers as *mut u8, Ordering::Release);
+
+    devices::register_bound(
+        "hpet",
+        Bus::Acpi,
+        vec![Resource::Mmio {
+            base: physical_address,
+            size: REGISTERS_SIZE as u64,
+        }],
+        "hpet",
+    );
+    // the main counter would start at 0 again after resuming
+    power::register(PowerHook {
+        name: "hpet",
+        suspend: || {
+            if let Some(registers) = mapped_registers() {
+                SUSPENDED_COUNT.store(
+                    unsafe { read(registers, MAIN_COUNTER_OFFSET) },
+                    Ordering::Relaxed,
+                );
+            }
+            Ok(())
+        },
+        resume: || {
+            if let Some(registers) = mapped_registers() {
+                unsafe { start(registers, SUSPENDED_COUNT.load(Ordering::Relaxed)) };
+            }
+            Ok(())
+        },
+    });
+
+    Ok(1_000_000_000_000_000 / period)
+}
+
+/// Returns the time since boot in ns, measured by the HPET if it is available and by the ticks of the timer in use otherwise. Never decreases, unlike the time stamp counter it does not depend on the cpu frequency.
+pub(crate) fn nanoseconds_since_boot() -> u64 {
+    let Some(registers) = mapped_registers() else {
+        return TIME.read().uptime_us() * 1000;
+    };
+    let count = unsafe { read(registers, MAIN_COUNTER_OFFSET) };
+    // the period is given in fs, 10^6 of which make up a ns
+    let elapsed = count as u128 * PERIOD_FS.load(Ordering::Relaxed) as u128 / 1_000_000;
+    OFFSET_NS.load(Ordering::Relaxed) + elapsed as u64
+}
+
+fn mapped_registers() -> Option<VirtualAddress> {
+    let registers = REGISTERS.load(Ordering::Acquire);
+    (!registers.is_null()).then_some(registers as VirtualAddress)
+}
+
+/// Stops the main counter, sets it to the given value and starts it again.
+///
+/// # Safety
+/// The registers must be mapped at the given address.
+unsafe fn start(registers: VirtualAddress, count: u64) {
+    let configuration = read(registers, CONFIGURATION_OFFSET) & !(ENABLE | LEGACY_REPLACEMENT);
+    // the main counter may only be written while it is stopped
+    write(registers, CONFIGURATION_OFFSET, configuration);
+    write(registers, MAIN_COUNTER_OFFSET, count);
+    write(registers, CONFIGURATION_OFFSET, configuration | ENABLE);
+}
+
+unsafe fn read(registers: VirtualAddress, offset: usize) -> u64 {
+    ((registers as *const u8).add(offset) as *const u64).read_volatile()
+}
+
+unsafe fn write(registers: VirtualAddress, offset: usize, value: u64) {
+    ((registers as *mut u8).add(offset) as *mut u64).write_volatile(value)
+}
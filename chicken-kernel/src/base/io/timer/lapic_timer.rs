@@ -0,0 +1,171 @@
+use core::cell::OnceCell;
+
+use crate::{
+    base::{
+        interrupts::{irq::LAPIC_TIMER_VECTOR, without_interrupts},
+        io::{
+            apic::lapic::LocalApicControl,
+            timer::{
+                pit::{ProgrammableIntervalTimer, TimeSnapshot, PIT, TIME},
+                Timer,
+            },
+            IOError,
+        },
+    },
+    scheduling::spin::SpinLock,
+};
+
+/// Time in µs the LAPIC timer is measured against channel 2 of the PIT.
+const CALIBRATION_US: u64 = 10_000;
+
+pub(crate) static LAPIC_TIMER: SpinLock<OnceCell<LocalApicTimer>> = SpinLock::new(OnceCell::new());
+
+/// Timer of the local apic of the BSP. It counts at a fraction of the bus clock, which differs between machines, so its rate is measured against the PIT when it is set up.
+#[derive(Debug)]
+pub(crate) struct LocalApicTimer {
+    lapic: LocalApicControl,
+    /// Amount the count decreases by per second.
+    base_frequency: u64,
+    /// Count the periodic timer is reloaded with.
+    count: u32,
+    /// Count the one-shot timer has been started with, if it is active.
+    one_shot: Option<u32>,
+}
+
+impl LocalApicTimer {
+    /// Measures the rate of the timer by letting it count down while channel 2 of the PIT waits for [`CALIBRATION_US`].
+    ///
+    /// # Safety
+    /// Requires IO privileges. Must be called with interrupts disabled.
+    unsafe fn calibrate(lapic: LocalApicControl) -> Result<Self, IOError> {
+        PIT.lock().wait_on_channel_2(CALIBRATION_US, || {
+            lapic.start_timer(LAPIC_TIMER_VECTOR, u32::MAX, false)
+        });
+        let elapsed = u32::MAX - lapic.timer_count();
+        lapic.stop_timer();
+
+        let base_frequency = elapsed as u64 * 1_000_000 / CALIBRATION_US;
+        // the timer has to count at least once per tick of the fastest frequency
+        if base_frequency < ProgrammableIntervalTimer::MAX_FREQUENCY {
+            return Err(IOError::TimerCalibrationFailed);
+        }
+        Ok(Self {
+            lapic,
+            base_frequency,
+            count: u32::MAX,
+            one_shot: None,
+        })
+    }
+
+    /// Starts the periodic ticks with the given count.
+    ///
+    /// # Safety
+    /// Must not be interrupted by a timer tick, otherwise the tick is accounted for with the wrong frequency.
+    unsafe fn set_count(&mut self, count: u32) {
+        self.count = count.max(1);
+        self.one_shot = None;
+        let frequency = self.frequency();
+        // preserve uptime, since the ticks so far happened at the old frequency
+        TIME.write(|time| {
+            time.fold_ticks();
+            time.frequency = frequency;
+        });
+
+        self.lapic.start_timer(LAPIC_TIMER_VECTOR, self.count, true);
+    }
+
+    /// Counts a tick or, if the one-shot timer has fired, accounts for the time the idle thread halted and continues ticking.
+    ///
+    /// # Safety
+    /// Must be called by the timer interrupt with interrupts disabled.
+    pub(in crate::base::io::timer) unsafe fn handle_interrupt(&mut self) {
+        if self.one_shot.is_some() {
+            self.stop_one_shot(true);
+        } else {
+            Self::tick();
+        }
+    }
+
+    /// Stops the periodic ticks and fires a single interrupt after the given duration instead.
+    ///
+    /// # Safety
+    /// Must not be interrupted by a timer tick.
+    pub(in crate::base::io::timer) unsafe fn start_one_shot(&mut self, duration_us: u64) {
+        let count = (duration_us.saturating_mul(self.base_frequency) / 1_000_000)
+            .clamp(1, u32::MAX as u64) as u32;
+
+        // account for the periodic ticks so far
+        TIME.write(TimeSnapshot::fold_ticks);
+
+        self.lapic.start_timer(LAPIC_TIMER_VECTOR, count, false);
+        self.one_shot = Some(count);
+    }
+
+    /// Accounts for the time spent in one-shot mode and switches back to periodic ticks. Does nothing, if the one-shot timer is inactive.
+    ///
+    /// # Safety
+    /// Must not be interrupted by a timer tick.
+    pub(in crate::base::io::timer) unsafe fn stop_one_shot(&mut self, fired: bool) {
+        let Some(count) = self.one_shot else {
+            return;
+        };
+
+        // the current count stays at 0 once the one-shot timer has fired
+        let elapsed = if fired {
+            count
+        } else {
+            count.saturating_sub(self.lapic.timer_count())
+        };
+        let base_frequency = self.base_frequency;
+        TIME.write(|time| time.offset_us += elapsed as u64 * 1_000_000 / base_frequency);
+
+        self.set_count(self.count);
+    }
+
+    /// Programs the timer with the current count again, e.g. after the local apic has lost its configuration during a suspend.
+    ///
+    /// # Safety
+    /// Must not be interrupted by a timer tick.
+    pub(in crate::base::io::timer) unsafe fn reprogram(&mut self) {
+        self.set_count(self.count);
+    }
+}
+
+impl Timer for LocalApicTimer {
+    unsafe fn set_frequency(&mut self, frequency: u64) {
+        if let Some(count) = self.base_frequency.checked_div(frequency) {
+            self.set_count(count.min(u32::MAX as u64) as u32);
+        }
+    }
+
+    fn frequency(&self) -> u64 {
+        self.base_frequency / self.count as u64
+    }
+}
+
+/// Calibrates the LAPIC timer against the PIT. The ticks start once a frequency is set.
+///
+/// # Safety
+/// Requires IO privileges. Must be called with interrupts disabled.
+pub(in crate::base::io::timer) unsafe fn set_up(lapic: LocalApicControl) -> Result<(), IOError> {
+    let timer = LocalApicTimer::calibrate(lapic)?;
+    LAPIC_TIMER.lock().get_or_init(|| timer);
+    Ok(())
+}
+
+/// Changes the frequency of the LAPIC timer at runtime. The uptime is preserved across the change.
+pub(in crate::base::io::timer) fn set_frequency(frequency: u64) -> Result<(), IOError> {
+    if !(ProgrammableIntervalTimer::MIN_FREQUENCY..=ProgrammableIntervalTimer::MAX_FREQUENCY)
+        .contains(&frequency)
+    {
+        return Err(IOError::InvalidTimerFrequency(frequency));
+    }
+
+    // a tick in between would deadlock on the timer lock or be counted with the wrong frequency
+    without_interrupts(|| {
+        let mut binding = LAPIC_TIMER.lock();
+        let timer = binding.get_mut().ok_or(IOError::LocalApicUninitialized)?;
+        unsafe { timer.set_frequency(frequency) };
+        Ok(())
+    })
+}
@@ -1,18 +1,46 @@
-use crate::base::interrupts::CpuState;
+use core::sync::atomic::{AtomicU8, Ordering};
 
+use crate::{
+    base::{
+        interrupts::CpuState,
+        io::{
+            self, apic,
+            timer::{
+                lapic_timer::LAPIC_TIMER,
+                pit::{ProgrammableIntervalTimer, PIT, TIME},
+            },
+            IOError, TIMER_IRQ,
+        },
+    },
+    config,
+    scheduling::{GlobalTaskScheduler, SCHEDULER},
+};
+
+pub(crate) mod hpet;
+pub(crate) mod lapic_timer;
 pub(crate) mod pit;
-// note: For now, only pit is supported; HPET, LAPIC may follow later.
-pub(crate) trait Timer {
-    const BASE_FREQUENCY: u64;
 
-    /// Increment tick counter.
-    fn tick();
+/// Timer in use, stored as [`TimerSource`].
+static SOURCE: AtomicU8 = AtomicU8::new(TimerSource::Pit as u8);
 
-    /// Current uptime since enabling interrupts in ms.
-    fn current_uptime_ms(&self) -> u64;
+/// Timer that drives the scheduler ticks and the uptime, selected with `timer=`.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TimerSource {
+    /// Programmable interval timer, which works in either interrupt mode.
+    Pit,
+    /// Timer of the local apic, calibrated against the PIT. Requires the APIC.
+    LocalApic,
+}
 
-    /// Called when timer interrupt occurs.
-    fn perform_context_switch(&self, context: *const CpuState) -> *const CpuState;
+pub(crate) trait Timer {
+    /// Increment tick counter.
+    ///
+    /// # Safety
+    /// Must be called with interrupts disabled, e.g. by the timer interrupt, since readers of the uptime would wait for it forever otherwise.
+    unsafe fn tick() {
+        TIME.write(|time| time.ticks += 1);
+    }
 
     /// Set frequency of timer. Also enables the timer, if it hasn't been enabled already.
     ///
@@ -23,3 +51,143 @@ pub(crate) trait Timer {
     /// Get frequency of timer.
     fn frequency(&self) -> u64;
 }
+
+/// Returns the timer in use.
+pub(crate) fn source() -> TimerSource {
+    if SOURCE.load(Ordering::Relaxed) == TimerSource::LocalApic as u8 {
+        TimerSource::LocalApic
+    } else {
+        TimerSource::Pit
+    }
+}
+
+/// Starts the timer selected with `timer=` with the frequency given with `timer_frequency=`. If the LAPIC timer is selected but can not be used, the PIT is started instead and the error is returned.
+pub(in crate::base) fn set_up() -> Result<(), IOError> {
+    let lapic_requested = config::with("timer", |value| value == Some("lapic"));
+    let result = if lapic_requested {
+        apic::local_apic().and_then(|lapic| unsafe { lapic_timer::set_up(lapic) })
+    } else {
+        Ok(())
+    };
+
+    if lapic_requested && result.is_ok() {
+        SOURCE.store(TimerSource::LocalApic as u8, Ordering::Relaxed);
+    } else {
+        // the ticks of the PIT are only delivered once its IRQ is unmasked
+        io::unmask_irq(TIMER_IRQ)?;
+    }
+
+    // an invalid frequency on the command line falls back to the default one
+    if apply_frequency().is_err() {
+        set_frequency(ProgrammableIntervalTimer::PIT_FREQUENCY)?;
+    }
+    result
+}
+
+/// Sets the frequency of the timer in use to the one given with `timer_frequency=`, or the default one.
+pub(crate) fn apply_frequency() -> Result<(), IOError> {
+    set_frequency(
+        config::integer("timer_frequency").unwrap_or(ProgrammableIntervalTimer::PIT_FREQUENCY),
+    )
+}
+
+/// Changes the frequency of the timer in use at runtime. The uptime is preserved across the change.
+pub(crate) fn set_frequency(frequency: u64) -> Result<(), IOError> {
+    match source() {
+        TimerSource::Pit => pit::set_frequency(frequency),
+        TimerSource::LocalApic => lapic_timer::set_frequency(frequency),
+    }
+}
+
+/// Counts a tick of the timer in use. If it has fired in one-shot mode instead, the time the idle thread halted is accounted for and the periodic ticks continue.
+///
+/// # Safety
+/// Must be called by the timer interrupt with interrupts disabled.
+pub(in crate::base) unsafe fn handle_interrupt() {
+    match source() {
+        TimerSource::Pit => {
+            let mut binding = PIT.lock();
+            if binding.is_one_shot() {
+                binding.stop_one_shot(true);
+            } else {
+                ProgrammableIntervalTimer::tick();
+            }
+        }
+        TimerSource::LocalApic => {
+            let mut binding = LAPIC_TIMER.lock();
+            if let Some(timer) = binding.get_mut() {
+                timer.handle_interrupt();
+            }
+        }
+    }
+}
+
+/// Sends the end of interrupt signal for the timer in use. The LAPIC timer is not an IRQ, so only the local apic is acknowledged.
+pub(in crate::base) fn eoi() {
+    match source() {
+        TimerSource::Pit => io::eoi(TIMER_IRQ),
+        TimerSource::LocalApic => apic::lapic::eoi(),
+    }
+}
+
+/// Stops the periodic ticks of the timer in use and fires a single interrupt after the given duration instead. The PIT limits the duration to about 54 ms.
+///
+/// # Safety
+/// Requires IO privileges. Must not be interrupted by a timer tick.
+pub(crate) unsafe fn start_one_shot(duration_us: u64) {
+    match source() {
+        TimerSource::Pit => PIT.lock().start_one_shot(duration_us),
+        TimerSource::LocalApic => {
+            if let Some(timer) = LAPIC_TIMER.lock().get_mut() {
+                timer.start_one_shot(duration_us);
+            }
+        }
+    }
+}
+
+/// Accounts for the time spent in one-shot mode and switches the timer in use back to periodic ticks. Does nothing, if the one-shot timer is inactive.
+///
+/// # Safety
+/// Requires IO privileges. Must not be interrupted by a timer tick.
+pub(crate) unsafe fn stop_one_shot(fired: bool) {
+    match source() {
+        TimerSource::Pit => PIT.lock().stop_one_shot(fired),
+        TimerSource::LocalApic => {
+            if let Some(timer) = LAPIC_TIMER.lock().get_mut() {
+                timer.stop_one_shot(fired);
+            }
+        }
+    }
+}
+
+/// Programs the timer in use with its current frequency again, e.g. after the hardware has lost its configuration during a suspend.
+///
+/// # Safety
+/// Requires IO privileges. Must not be interrupted by a timer tick.
+pub(in crate::base::io) unsafe fn reprogram() {
+    match source() {
+        TimerSource::Pit => PIT.lock().reprogram(),
+        TimerSource::LocalApic => {
+            if let Some(timer) = LAPIC_TIMER.lock().get_mut() {
+                timer.reprogram();
+            }
+        }
+    }
+}
+
+/// Switches to the next thread. Called by the timer interrupt, once the time slice of the active thread has expired, and when a thread yields. Does not require the timer to be locked.
+pub(in crate::base) fn perform_context_switch(context: *const CpuState) -> *const CpuState {
+    // ticks still count while preemption is disabled, so sleeping threads wake up on time afterwards
+    if !GlobalTaskScheduler::preemption_enabled() {
+        return context;
+    }
+
+    let now_ns = hpet::nanoseconds_since_boot();
+
+    let mut binding = SCHEDULER.lock();
+    if let Some(scheduler) = binding.get_mut() {
+        scheduler.schedule(context, now_ns)
+    } else {
+        context
+    }
+}
@@ -1,18 +1,62 @@
-use core::sync::atomic::{AtomicU64, Ordering};
-
 use crate::{
     base::{
-        interrupts::CpuState,
-        io::{io_wait, outb, Port, timer::Timer},
-    }
-    ,
-    scheduling::{SCHEDULER, spin::SpinLock},
+        interrupts::without_interrupts,
+        io::{
+            inb, io_wait, outb,
+            speaker::{SPEAKER_ENABLE, SPEAKER_PORT},
+            timer::Timer,
+            IOError, Port,
+        },
+    },
+    scheduling::{seqlock::SeqLock, spin::SpinLock},
 };
 
-const TICK_GENERATOR_PORT: Port = 0x40;
-const PIT_PORT: Port = 0x43;
+pub(in crate::base::io) const TICK_GENERATOR_PORT: Port = 0x40;
+/// Data port of channel 2, which is connected to the PC speaker.
+const SPEAKER_CHANNEL_PORT: Port = 0x42;
+pub(in crate::base::io) const PIT_PORT: Port = 0x43;
+
+/// Gate of channel 2 in the speaker port.
+const CHANNEL_2_GATE: u8 = 0b1;
+/// Output of channel 2 in the speaker port.
+const CHANNEL_2_OUTPUT: u8 = 0b100000;
+
+/// Time state of the timer in use. Kept outside the lock and only written with interrupts disabled, i.e. by the timer interrupt and on frequency changes, so the uptime can be read from any context without locking or disabling interrupts.
+pub(in crate::base::io::timer) static TIME: SeqLock<TimeSnapshot> = SeqLock::new(TimeSnapshot {
+    ticks: 0,
+    offset_us: 0,
+    frequency: ProgrammableIntervalTimer::BASE_FREQUENCY
+        / ProgrammableIntervalTimer::MAX_DIVISOR as u64,
+});
 
-pub(in crate::base) static TICK_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Consistent state of the timer at a point in time, from which the uptime is derived.
+#[derive(Copy, Clone, Debug)]
+pub(in crate::base::io::timer) struct TimeSnapshot {
+    /// Ticks since the last frequency change.
+    pub(in crate::base::io::timer) ticks: u64,
+    /// Uptime in µs that elapsed before the last frequency change or in one-shot mode.
+    pub(in crate::base::io::timer) offset_us: u64,
+    /// Frequency the ticks have been counted with.
+    pub(in crate::base::io::timer) frequency: u64,
+}
+
+impl TimeSnapshot {
+    /// Uptime since enabling interrupts in µs.
+    pub(in crate::base::io::timer) fn uptime_us(&self) -> u64 {
+        self.offset_us + (self.ticks * 1_000_000) / self.frequency
+    }
+
+    /// Uptime since enabling interrupts in ms.
+    fn uptime_ms(&self) -> u64 {
+        self.uptime_us() / 1000
+    }
+
+    /// Adds the ticks so far to the uptime offset, so they are not accounted for with a different frequency later on.
+    pub(in crate::base::io::timer) fn fold_ticks(&mut self) {
+        self.offset_us = self.uptime_us();
+        self.ticks = 0;
+    }
+}
 
 pub(crate) static PIT: SpinLock<ProgrammableIntervalTimer> =
     SpinLock::new(ProgrammableIntervalTimer::new());
@@ -20,65 +64,178 @@ pub(crate) static PIT: SpinLock<ProgrammableIntervalTimer> =
 #[derive(Debug)]
 pub(crate) struct ProgrammableIntervalTimer {
     divisor: u16,
+    /// Count the one-shot timer has been started with, if it is active.
+    one_shot: Option<u16>,
 }
 
 impl ProgrammableIntervalTimer {
+    /// Frequency of the oscillator driving all channels.
+    pub(crate) const BASE_FREQUENCY: u64 = 1193182;
     pub(in crate::base) const MAX_DIVISOR: u16 = 65535;
+    const MIN_DIVISOR: u16 = 100;
     /// Frequency that works well for scheduler and sleeping threads.
     pub(in crate::base) const PIT_FREQUENCY: u64 = 1000;
+    /// Lowest frequency the PIT can be set to.
+    pub(crate) const MIN_FREQUENCY: u64 = Self::BASE_FREQUENCY / Self::MAX_DIVISOR as u64 + 1;
+    /// Highest frequency the PIT can be set to.
+    pub(crate) const MAX_FREQUENCY: u64 = Self::BASE_FREQUENCY / Self::MIN_DIVISOR as u64;
 
     const fn new() -> Self {
         Self {
             divisor: Self::MAX_DIVISOR,
+            one_shot: None,
         }
     }
     /// Set divisor of PIT. Also enables it, if it hasn't been enabled already.
     ///
     /// # Safety
-    /// Requires IO privileges.
+    /// Requires IO privileges. Must not be interrupted by a timer tick, otherwise the tick is accounted for with the wrong frequency.
     unsafe fn set_divisor(&mut self, mut divisor: u16) {
-        if divisor < 100 {
-            divisor = 100;
+        if divisor < Self::MIN_DIVISOR {
+            divisor = Self::MIN_DIVISOR;
         }
 
         self.divisor = divisor;
+        self.one_shot = None;
+        let frequency = self.frequency();
+        // preserve uptime, since the ticks so far happened at the old frequency
+        TIME.write(|time| {
+            time.fold_ticks();
+            time.frequency = frequency;
+        });
 
         // set mode 2 (rate generator)
         outb(PIT_PORT, 0b00110100);
         io_wait();
-        // send lower half of divisor
-        outb(TICK_GENERATOR_PORT, (self.divisor & 0x00ff) as u8);
+        self.write_count(self.divisor);
+    }
+
+    /// Sends a count to channel 0 of the PIT.
+    ///
+    /// # Safety
+    /// Requires IO privileges and the mode of channel 0 to be set right before.
+    unsafe fn write_count(&self, count: u16) {
+        // send lower half of count
+        outb(TICK_GENERATOR_PORT, (count & 0x00ff) as u8);
         io_wait();
-        // send higher half of divisor
-        outb(TICK_GENERATOR_PORT, ((self.divisor & 0xff00) >> 8) as u8);
+        // send higher half of count
+        outb(TICK_GENERATOR_PORT, ((count & 0xff00) >> 8) as u8);
         io_wait();
     }
-}
 
-impl Timer for ProgrammableIntervalTimer {
-    const BASE_FREQUENCY: u64 = 1193182;
+    /// Stops the periodic ticks and fires a single interrupt after the given duration instead. The duration is limited to about 54 ms.
+    ///
+    /// # Safety
+    /// Requires IO privileges. Must not be interrupted by a timer tick.
+    pub(crate) unsafe fn start_one_shot(&mut self, duration_us: u64) {
+        let count = (duration_us.saturating_mul(Self::BASE_FREQUENCY) / 1_000_000)
+            .clamp(1, Self::MAX_DIVISOR as u64) as u16;
 
-    fn tick() {
-        TICK_COUNTER.fetch_add(1, Ordering::Relaxed);
-    }
+        // account for the periodic ticks so far
+        TIME.write(TimeSnapshot::fold_ticks);
 
-    fn current_uptime_ms(&self) -> u64 {
-        let frequency = ProgrammableIntervalTimer::BASE_FREQUENCY / self.frequency();
-        let ticks = TICK_COUNTER.load(Ordering::Relaxed);
-        (ticks * 1000) / frequency
+        // set mode 0 (interrupt on terminal count)
+        outb(PIT_PORT, 0b00110000);
+        io_wait();
+        self.write_count(count);
+        self.one_shot = Some(count);
     }
 
-    fn perform_context_switch(&self, context: *const CpuState) -> *const CpuState {
-        let uptime = self.current_uptime_ms();
+    /// Accounts for the time spent in one-shot mode and switches back to periodic ticks. Does nothing, if the one-shot timer is inactive.
+    ///
+    /// # Safety
+    /// Requires IO privileges. Must not be interrupted by a timer tick.
+    pub(crate) unsafe fn stop_one_shot(&mut self, fired: bool) {
+        let Some(count) = self.one_shot else {
+            return;
+        };
 
-        let mut binding = SCHEDULER.lock();
-        if let Some(scheduler) = binding.get_mut() {
-            scheduler.schedule(context, uptime)
+        // read back status of channel 0, the output is set once the count has been reached
+        let reached = fired || {
+            outb(PIT_PORT, 0b11100010);
+            io_wait();
+            inb(TICK_GENERATOR_PORT) & 0b10000000 != 0
+        };
+
+        let elapsed = if reached {
+            count
         } else {
-            context
+            // latch count of channel 0 and read it
+            outb(PIT_PORT, 0b00000000);
+            io_wait();
+            let low = inb(TICK_GENERATOR_PORT) as u16;
+            let high = inb(TICK_GENERATOR_PORT) as u16;
+            count.saturating_sub((high << 8) | low)
+        };
+        TIME.write(|time| time.offset_us += elapsed as u64 * 1_000_000 / Self::BASE_FREQUENCY);
+
+        self.set_divisor(self.divisor);
+    }
+
+    /// Sets the frequency of the square wave generated by channel 2, which drives the PC speaker.
+    ///
+    /// # Safety
+    /// Requires IO privileges and the frequency must be within [`Self::MIN_FREQUENCY`] and [`Self::BASE_FREQUENCY`].
+    pub(in crate::base::io) unsafe fn set_speaker_frequency(&mut self, frequency: u64) {
+        let divisor = (Self::BASE_FREQUENCY / frequency) as u16;
+
+        // set mode 3 (square wave generator) of channel 2
+        outb(PIT_PORT, 0b10110110);
+        io_wait();
+        outb(SPEAKER_CHANNEL_PORT, (divisor & 0x00ff) as u8);
+        io_wait();
+        outb(SPEAKER_CHANNEL_PORT, ((divisor & 0xff00) >> 8) as u8);
+        io_wait();
+    }
+
+    /// Waits for the given duration using channel 2, e.g. to calibrate another timer against it. `start` is called right before channel 2 starts counting. The duration is limited to about 54 ms. The PC speaker stays silent meanwhile.
+    ///
+    /// # Safety
+    /// Requires IO privileges.
+    pub(in crate::base::io) unsafe fn wait_on_channel_2(
+        &mut self,
+        duration_us: u64,
+        start: impl FnOnce(),
+    ) {
+        let count = (duration_us.saturating_mul(Self::BASE_FREQUENCY) / 1_000_000)
+            .clamp(1, Self::MAX_DIVISOR as u64) as u16;
+
+        // hold the gate low while programming and disconnect the speaker
+        let control = inb(SPEAKER_PORT) & !SPEAKER_ENABLE;
+        outb(SPEAKER_PORT, control);
+
+        // set mode 0 (interrupt on terminal count) of channel 2
+        outb(PIT_PORT, 0b10110000);
+        io_wait();
+        outb(SPEAKER_CHANNEL_PORT, (count & 0x00ff) as u8);
+        io_wait();
+        outb(SPEAKER_CHANNEL_PORT, ((count & 0xff00) >> 8) as u8);
+        io_wait();
+
+        start();
+        // raising the gate starts the count, the output is set once it has been reached
+        outb(SPEAKER_PORT, control | CHANNEL_2_GATE);
+        while inb(SPEAKER_PORT) & CHANNEL_2_OUTPUT == 0 {
+            core::hint::spin_loop();
         }
+        outb(SPEAKER_PORT, control);
+    }
+
+    /// Programs channel 0 with the current divisor again, e.g. after the hardware has lost its configuration during a suspend.
+    ///
+    /// # Safety
+    /// Requires IO privileges. Must not be interrupted by a timer tick.
+    pub(in crate::base::io) unsafe fn reprogram(&mut self) {
+        self.set_divisor(self.divisor);
+    }
+
+    /// Whether the PIT currently fires a single interrupt instead of periodic ticks.
+    pub(crate) fn is_one_shot(&self) -> bool {
+        self.one_shot.is_some()
     }
+}
 
+impl Timer for ProgrammableIntervalTimer {
     unsafe fn set_frequency(&mut self, frequency: u64) {
         if frequency != 0 {
             self.set_divisor((ProgrammableIntervalTimer::BASE_FREQUENCY / frequency) as u16);
@@ -90,8 +247,29 @@ impl Timer for ProgrammableIntervalTimer {
     }
 }
 
-/// Locks PIT to get current uptime.
+/// Get current uptime without locking the timer, so it can not deadlock with the timer interrupt.
 pub(crate) fn get_current_uptime_ms() -> u64 {
-    let pit = PIT.lock();
-    pit.current_uptime_ms()
+    TIME.read().uptime_ms()
+}
+
+/// Changes the frequency of the PIT at runtime. The uptime is preserved across the change.
+pub(in crate::base::io::timer) fn set_frequency(frequency: u64) -> Result<(), IOError> {
+    if !(ProgrammableIntervalTimer::MIN_FREQUENCY..=ProgrammableIntervalTimer::MAX_FREQUENCY)
+        .contains(&frequency)
+    {
+        return Err(IOError::InvalidTimerFrequency(frequency));
+    }
+
+    // a tick in between would deadlock on the PIT lock or be counted with the wrong frequency
+    without_interrupts(|| {
+        let mut binding = PIT.lock();
+        unsafe { binding.set_frequency(frequency) };
+    });
+    Ok(())
+}
+
+/// Get current frequency of the timer ticks.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn frequency() -> u64 {
+    TIME.read().frequency
 }
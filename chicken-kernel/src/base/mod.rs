@@ -1,21 +1,72 @@
 use chicken_util::BootInfo;
 
 use crate::base::interrupts::idt;
-use crate::base::io::timer::pit::PIT;
-use crate::base::io::timer::Timer;
+use crate::base::io::timer;
+use crate::base::io::IOError;
 use crate::println;
 
 mod acpi;
+pub(crate) mod backtrace;
+pub(crate) mod cpu;
+pub(crate) mod crash;
 pub(crate) mod io;
 pub(crate) mod gdt;
 pub(crate) mod interrupts;
 pub(crate) mod msr;
+pub(crate) mod pmc;
+pub(crate) mod power;
+pub(crate) mod random;
+pub(crate) mod smbios;
+pub(crate) mod syscall;
 
-pub(super) fn set_up(boot_info: &BootInfo) {
+/// Sets up the base architecture. Returns an error if hardware interrupts could only be set up in a degraded mode.
+pub(super) fn set_up(boot_info: &BootInfo) -> Result<(), IOError> {
+    let missing_features = cpu::check_features();
+    if missing_features > 0 {
+        println!("kernel: CPU lacks {} features in use, see the warnings above for the fallbacks.", missing_features);
+    }
     gdt::initialize();
     println!("kernel: Set up gdt.");
     idt::initialize();
+    interrupts::budget::set_up();
     println!("kernel: Set up idt.");
-    io::initialize(boot_info);
-    println!("kernel: Set up io, pit frequency: {}.", PIT.lock().frequency());
+    syscall::initialize();
+    println!("kernel: Set up syscalls.");
+    match pmc::set_up() {
+        Ok(true) => println!("kernel: Set up performance counters."),
+        Ok(false) => {}
+        Err(err) => println!("kernel: Performance counters are unavailable: {}", err),
+    }
+    match interrupts::gdb::set_up() {
+        Ok(Some(port)) => println!("kernel: GDB stub listening on serial port {:#x}.", port),
+        Ok(None) => {}
+        Err(err) => println!("kernel: GDB stub is unavailable: {}", err),
+    }
+    let result = io::initialize(boot_info);
+    if let Err(err) = timer::set_up() {
+        println!("kernel: LAPIC timer is unavailable, using the PIT: {}", err);
+    }
+    println!(
+        "kernel: Set up io, interrupt mode: {:?}, timer: {:?}, timer frequency: {}.",
+        io::interrupt_mode(),
+        timer::source(),
+        timer::pit::frequency()
+    );
+    match timer::hpet::set_up(boot_info) {
+        Ok(frequency) => println!("kernel: Set up HPET, counting at {} Hz.", frequency),
+        Err(err) => println!("kernel: HPET is unavailable: {}", err),
+    }
+    println!("kernel: Set up RTC, wall clock: {} UTC.", io::rtc::set_up(boot_info));
+    match power::sleep::set_up(boot_info) {
+        Ok(()) => println!("kernel: Set up S3 sleep."),
+        Err(err) => println!("kernel: S3 sleep is unavailable: {}", err),
+    }
+    if let Err(err) = power::reset::set_up(boot_info) {
+        println!("kernel: ACPI reset is unavailable: {:?}", err);
+    }
+    match smbios::set_up(boot_info) {
+        Ok(info) => println!("kernel: Hardware: {}.", info),
+        Err(err) => println!("kernel: Hardware is unknown: {}", err),
+    }
+    result
 }
@@ -1,9 +1,15 @@
-use core::arch::x86_64::__cpuid;
-
 use bitflags::{bitflags, Flags};
 
+use crate::base::cpu::CpuFeature;
+
 const IA32_EFER: u32 = 0xC000_0080;
 const IA32_APIC: u32 = 0x1B;
+const IA32_STAR: u32 = 0xC000_0081;
+const IA32_LSTAR: u32 = 0xC000_0082;
+const IA32_FMASK: u32 = 0xC000_0084;
+const IA32_FS_BASE: u32 = 0xC000_0100;
+const IA32_FIXED_CTR_CTRL: u32 = 0x38D;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
 
 extern "C" {
     fn cpu_has_msr() -> bool;
@@ -78,7 +84,7 @@ impl ModelSpecificRegister for Efer {
 impl Efer {
     /// Whether the NX feature is available to the CPU
     pub fn nx_available() -> bool {
-        unsafe { __cpuid(0x80000001).edx & (1 << 20) != 0 }
+        CpuFeature::Nx.present()
     }
 }
 
@@ -109,3 +115,131 @@ impl Apic {
         self.bits() & 0b11111111111111111111000000000000
     }
 }
+
+bitflags! {
+    /// Segment selectors loaded by the `syscall` and `sysret` instructions
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug)]
+    pub struct Star: u64 {
+        // bits 0-31 reserved
+        /// Kernel code selector loaded by syscall. The kernel stack selector is the following entry.
+        const SYSCALL_SELECTOR = 0xFFFF << 32;
+        /// Base selector of sysret. The user stack selector is the following entry, the 64-bit user code selector the one after it.
+        const SYSRET_SELECTOR = 0xFFFF << 48;
+    }
+}
+
+impl ModelSpecificRegister for Star {
+    const MSR_INDEX: u32 = IA32_STAR;
+}
+
+impl Star {
+    pub(crate) fn new(syscall_selector: u16, sysret_selector: u16) -> Self {
+        Self::from_bits_retain(
+            (u64::from(syscall_selector) << 32) | (u64::from(sysret_selector) << 48),
+        )
+    }
+}
+
+bitflags! {
+    /// Entry point of the `syscall` instruction in 64-bit mode
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug)]
+    pub struct LStar: u64 {
+        /// Virtual address the syscall instruction jumps to
+        const TARGET = u64::MAX;
+    }
+}
+
+impl ModelSpecificRegister for LStar {
+    const MSR_INDEX: u32 = IA32_LSTAR;
+}
+
+impl LStar {
+    pub(crate) fn new(target: u64) -> Self {
+        Self::from_bits_retain(target)
+    }
+}
+
+bitflags! {
+    /// RFLAGS bits that are cleared by the `syscall` instruction
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug)]
+    pub struct SfMask: u64 {
+        /// Bits 0-31 correspond to the RFLAGS register
+        const MASK = 0xFFFF_FFFF;
+        // bits 32-63 reserved
+    }
+}
+
+impl ModelSpecificRegister for SfMask {
+    const MSR_INDEX: u32 = IA32_FMASK;
+}
+
+bitflags! {
+    /// Base address of the fs segment, e.g. the thread pointer of user programs
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug)]
+    pub struct FsBase: u64 {
+        /// Virtual address fs relative accesses are based on
+        const BASE = u64::MAX;
+    }
+}
+
+impl ModelSpecificRegister for FsBase {
+    const MSR_INDEX: u32 = IA32_FS_BASE;
+}
+
+impl FsBase {
+    pub(crate) fn new(base: u64) -> Self {
+        Self::from_bits_retain(base)
+    }
+}
+
+bitflags! {
+    /// Privilege levels the fixed function performance counters count events in
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug)]
+    pub struct FixedCounterControl: u64 {
+        /// Count instructions retired in ring 0
+        const INSTRUCTIONS_OS = 1 << 0;
+        /// Count instructions retired in ring 3
+        const INSTRUCTIONS_USER = 1 << 1;
+        // bits 2-3 control any thread counting and overflow interrupts
+        /// Count unhalted core cycles in ring 0
+        const CYCLES_OS = 1 << 4;
+        /// Count unhalted core cycles in ring 3
+        const CYCLES_USER = 1 << 5;
+        // bits 6-7 control any thread counting and overflow interrupts
+        /// Count unhalted reference cycles in ring 0
+        const REFERENCE_CYCLES_OS = 1 << 8;
+        /// Count unhalted reference cycles in ring 3
+        const REFERENCE_CYCLES_USER = 1 << 9;
+        // bits 10-63 control further counters or are reserved
+    }
+}
+
+impl ModelSpecificRegister for FixedCounterControl {
+    const MSR_INDEX: u32 = IA32_FIXED_CTR_CTRL;
+}
+
+bitflags! {
+    /// Performance counters that are enabled
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug)]
+    pub struct PerfGlobalControl: u64 {
+        /// Bits 0-31 enable the general purpose counters
+        const GENERAL_PURPOSE = 0xFFFF_FFFF;
+        /// Enables fixed counter 0, which counts instructions retired
+        const FIXED_INSTRUCTIONS = 1 << 32;
+        /// Enables fixed counter 1, which counts unhalted core cycles
+        const FIXED_CYCLES = 1 << 33;
+        /// Enables fixed counter 2, which counts unhalted reference cycles
+        const FIXED_REFERENCE_CYCLES = 1 << 34;
+        // bits 35-63 enable further counters or are reserved
+    }
+}
+
+impl ModelSpecificRegister for PerfGlobalControl {
+    const MSR_INDEX: u32 = IA32_PERF_GLOBAL_CTRL;
+}
@@ -0,0 +1,223 @@
+use core::{
+    arch::{asm, x86_64::__cpuid},
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    ops::AddAssign,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::{
+    base::msr::{FixedCounterControl, ModelSpecificRegister, PerfGlobalControl},
+    config,
+};
+
+/// Cpuid leaf describing the architectural performance monitoring.
+const PERFORMANCE_MONITORING_LEAF: u32 = 0xA;
+/// Version of architectural performance monitoring that introduced the fixed function counters.
+const MIN_VERSION: u32 = 2;
+/// Fixed counters used: instructions retired and unhalted core cycles.
+const FIXED_COUNTERS: u32 = 2;
+/// Bit of the rdpmc index that selects the fixed function counters.
+const FIXED_COUNTER_SELECT: u32 = 1 << 30;
+const INSTRUCTIONS_COUNTER: u32 = FIXED_COUNTER_SELECT;
+const CYCLES_COUNTER: u32 = FIXED_COUNTER_SELECT | 1;
+/// Performance-monitoring counter enable, allows rdpmc outside of ring 0.
+const CR4_PCE: u64 = 1 << 8;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Mask of the bits the fixed counters are wide, so deltas are correct after a counter wraps around.
+static COUNTER_MASK: AtomicU64 = AtomicU64::new(0);
+/// Counter values at the last context switch.
+static LAST_INSTRUCTIONS: AtomicU64 = AtomicU64::new(0);
+static LAST_CYCLES: AtomicU64 = AtomicU64::new(0);
+
+/// Instructions retired and unhalted core cycles, either since boot or of a single thread.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PerfCounters {
+    pub(crate) instructions: u64,
+    pub(crate) cycles: u64,
+}
+
+impl PerfCounters {
+    /// Returns the events counted since the earlier counter values.
+    pub(crate) fn since(&self, earlier: &PerfCounters) -> PerfCounters {
+        let mask = COUNTER_MASK.load(Ordering::Relaxed);
+        PerfCounters {
+            instructions: self.instructions.wrapping_sub(earlier.instructions) & mask,
+            cycles: self.cycles.wrapping_sub(earlier.cycles) & mask,
+        }
+    }
+
+    /// Returns the instructions per cycle in hundredths, if any cycles have been counted.
+    pub(crate) fn ipc_hundredths(&self) -> Option<u64> {
+        (self.cycles != 0).then(|| (self.instructions as u128 * 100 / self.cycles as u128) as u64)
+    }
+}
+
+impl AddAssign for PerfCounters {
+    fn add_assign(&mut self, rhs: Self) {
+        self.instructions = self.instructions.saturating_add(rhs.instructions);
+        self.cycles = self.cycles.saturating_add(rhs.cycles);
+    }
+}
+
+impl Display for PerfCounters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} instructions, {} cycles",
+            self.instructions, self.cycles
+        )?;
+        if let Some(ipc) = self.ipc_hundredths() {
+            write!(f, ", IPC: {}.{:02}", ipc / 100, ipc % 100)?;
+        }
+        Ok(())
+    }
+}
+
+/// Enables the fixed function performance counters, if they are requested with `pmc=on`. Returns whether they are enabled.
+pub(in crate::base) fn set_up() -> Result<bool, PmcError> {
+    if !config::with("pmc", |value| value == Some("on")) {
+        return Ok(false);
+    }
+    let (version, fixed_counters, width) = capabilities();
+    if version < MIN_VERSION || fixed_counters < FIXED_COUNTERS {
+        return Err(PmcError::Unsupported(version));
+    }
+    let mask = u64::BITS
+        .checked_sub(width)
+        .and_then(|shift| u64::MAX.checked_shr(shift))
+        .unwrap_or(u64::MAX);
+    COUNTER_MASK.store(mask, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::SeqCst);
+    initialize()?;
+    Ok(true)
+}
+
+/// Programs the counters, if they are enabled. Has to be called again after waking up from sleep, since the registers are lost. The counters are disabled, if they can not be programmed.
+pub(in crate::base) fn initialize() -> Result<(), PmcError> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    let result = program();
+    if result.is_err() {
+        ENABLED.store(false, Ordering::SeqCst);
+    }
+    result
+}
+
+fn program() -> Result<(), PmcError> {
+    let control = FixedCounterControl::INSTRUCTIONS_OS
+        | FixedCounterControl::INSTRUCTIONS_USER
+        | FixedCounterControl::CYCLES_OS
+        | FixedCounterControl::CYCLES_USER;
+    let global = PerfGlobalControl::read().ok_or(PmcError::MsrUnavailable)?
+        | PerfGlobalControl::FIXED_INSTRUCTIONS
+        | PerfGlobalControl::FIXED_CYCLES;
+    if !control.write() || !global.write() {
+        return Err(PmcError::MsrUnavailable);
+    }
+
+    // user programs read the counters themselves, instead of asking the kernel
+    unsafe {
+        let mut cr4: u64;
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack));
+        cr4 |= CR4_PCE;
+        asm!("mov cr4, {}", in(reg) cr4, options(nomem, nostack));
+    }
+
+    if let Some(counters) = read() {
+        LAST_INSTRUCTIONS.store(counters.instructions, Ordering::Relaxed);
+        LAST_CYCLES.store(counters.cycles, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Returns the current values of the counters, if they are enabled.
+pub(crate) fn read() -> Option<PerfCounters> {
+    if !is_enabled() {
+        return None;
+    }
+    Some(PerfCounters {
+        instructions: rdpmc(INSTRUCTIONS_COUNTER),
+        cycles: rdpmc(CYCLES_COUNTER),
+    })
+}
+
+/// Returns the events counted since the last context switch, without resetting them.
+pub(crate) fn since_switch() -> Option<PerfCounters> {
+    read().map(|counters| counters.since(&last_switch()))
+}
+
+/// Returns the events counted since the last context switch and starts counting for the next thread. Called by the scheduler to charge them to the thread that has been running meanwhile.
+pub(crate) fn take_since_switch() -> Option<PerfCounters> {
+    let counters = read()?;
+    let elapsed = counters.since(&last_switch());
+    LAST_INSTRUCTIONS.store(counters.instructions, Ordering::Relaxed);
+    LAST_CYCLES.store(counters.cycles, Ordering::Relaxed);
+    Some(elapsed)
+}
+
+fn last_switch() -> PerfCounters {
+    PerfCounters {
+        instructions: LAST_INSTRUCTIONS.load(Ordering::Relaxed),
+        cycles: LAST_CYCLES.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the version of architectural performance monitoring, the amount of fixed function counters and their width in bits.
+fn capabilities() -> (u32, u32, u32) {
+    // cpuid is only declared safe by newer toolchains
+    #[allow(unused_unsafe)]
+    let max_leaf = unsafe { __cpuid(0).eax };
+    if max_leaf < PERFORMANCE_MONITORING_LEAF {
+        return (0, 0, 0);
+    }
+    #[allow(unused_unsafe)]
+    let leaf = unsafe { __cpuid(PERFORMANCE_MONITORING_LEAF) };
+    (leaf.eax & 0xFF, leaf.edx & 0x1F, (leaf.edx >> 5) & 0xFF)
+}
+
+fn rdpmc(counter: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("rdpmc", in("ecx") counter, out("eax") low, out("edx") high, options(nomem, nostack))
+    };
+    (u64::from(high) << 32) | u64::from(low)
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum PmcError {
+    /// The cpu does not provide fixed function counters. Contains the version of its architectural performance monitoring.
+    Unsupported(u32),
+    MsrUnavailable,
+}
+
+impl Debug for PmcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PmcError::Unsupported(version) => write!(
+                f,
+                "Performance Counter Error: Fixed function counters are not supported, architectural performance monitoring version: {}.",
+                version
+            ),
+            PmcError::MsrUnavailable => write!(
+                f,
+                "Performance Counter Error: Model specific registers are not available."
+            ),
+        }
+    }
+}
+
+impl Display for PmcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for PmcError {}
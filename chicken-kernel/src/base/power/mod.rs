@@ -0,0 +1,161 @@
+use alloc::vec::Vec;
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
+
+use crate::{
+    base::{
+        acpi::ACPIError,
+        interrupts::are_enabled,
+        io::{self, IOError, InterruptControllerState},
+    },
+    memory::paging::PagingError,
+    println,
+    scheduling::spin::SpinLock,
+};
+
+pub(crate) mod reset;
+pub(crate) mod sleep;
+
+/// Callbacks of a driver, that are invoked before and after a suspend.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct PowerHook {
+    pub(crate) name: &'static str,
+    /// Saves the state of the device. The suspend is aborted, if an error is returned.
+    pub(crate) suspend: fn() -> Result<(), PowerError>,
+    /// Restores the state of the device.
+    pub(crate) resume: fn() -> Result<(), PowerError>,
+}
+
+/// Hooks of all drivers in the order of registration.
+static HOOKS: SpinLock<Vec<PowerHook>> = SpinLock::new(Vec::new());
+/// Configuration of the interrupt controller, while the system is prepared for a suspend.
+static SAVED_STATE: SpinLock<Option<InterruptControllerState>> = SpinLock::new(None);
+
+/// Registers hooks, that are invoked by [`suspend_prepare`] and [`resume`].
+pub(crate) fn register(hook: PowerHook) {
+    HOOKS.lock().push(hook);
+}
+
+/// Saves the state of all drivers and of the interrupt controller, so the system can be suspended. Drivers are suspended in the order they have been registered. If one of them fails, the drivers suspended so far are resumed again.
+///
+/// Must be called with interrupts disabled, which must stay disabled until [`resume`] has been called.
+pub(crate) fn suspend_prepare() -> Result<(), PowerError> {
+    assert!(!are_enabled(), "Interrupts must be disabled during a suspend.");
+    if SAVED_STATE.lock().is_some() {
+        return Err(PowerError::AlreadySuspended);
+    }
+
+    let hooks = HOOKS.lock().clone();
+    for (index, hook) in hooks.iter().enumerate() {
+        if let Err(err) = (hook.suspend)() {
+            resume_hooks(&hooks[..index]);
+            return Err(err);
+        }
+    }
+
+    match io::save_state() {
+        Ok(state) => {
+            *SAVED_STATE.lock() = Some(state);
+            Ok(())
+        }
+        Err(err) => {
+            resume_hooks(&hooks);
+            Err(PowerError::from(err))
+        }
+    }
+}
+
+/// Restores the state of the interrupt controller and of all drivers saved by [`suspend_prepare`]. Drivers are resumed in reverse order.
+///
+/// Must be called with interrupts disabled.
+pub(crate) fn resume() -> Result<(), PowerError> {
+    assert!(!are_enabled(), "Interrupts must be disabled during a resume.");
+    let state = SAVED_STATE.lock().take().ok_or(PowerError::NotSuspended)?;
+
+    unsafe { io::restore_state(&state)? };
+    resume_hooks(&HOOKS.lock().clone());
+    Ok(())
+}
+
+/// Resumes the given drivers in reverse order. Failing drivers do not prevent the others from being resumed.
+fn resume_hooks(hooks: &[PowerHook]) {
+    for hook in hooks.iter().rev() {
+        if let Err(err) = (hook.resume)() {
+            println!("power: Could not resume {}: {}", hook.name, err);
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum PowerError {
+    AlreadySuspended,
+    NotSuspended,
+    #[allow(dead_code)] // no driver can fail to save its state yet
+    DeviceError(&'static str),
+    InterruptControllerError(IOError),
+    SleepUnsupported,
+    SleepTimeout,
+    AcpiParsingFailed(ACPIError),
+    TrampolineMappingFailed(PagingError),
+}
+
+impl Debug for PowerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PowerError::AlreadySuspended => {
+                write!(f, "Power Error: The system has already been prepared for a suspend.")
+            }
+            PowerError::NotSuspended => {
+                write!(f, "Power Error: The system has not been prepared for a suspend.")
+            }
+            PowerError::DeviceError(name) => {
+                write!(f, "Power Error: Device: {} failed to save or restore its state.", name)
+            }
+            PowerError::InterruptControllerError(value) => write!(
+                f,
+                "Power Error: Could not save or restore the interrupt controller: {}",
+                value
+            ),
+            PowerError::SleepUnsupported => {
+                write!(f, "Power Error: The platform does not support the sleep state.")
+            }
+            PowerError::SleepTimeout => {
+                write!(f, "Power Error: The platform did not enter the sleep state.")
+            }
+            PowerError::AcpiParsingFailed(value) => {
+                write!(f, "Power Error: Could not read the ACPI tables: {:?}", value)
+            }
+            PowerError::TrampolineMappingFailed(value) => {
+                write!(f, "Power Error: Could not map the wake up trampoline: {}", value)
+            }
+        }
+    }
+}
+
+impl Display for PowerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for PowerError {}
+
+impl From<IOError> for PowerError {
+    fn from(value: IOError) -> Self {
+        Self::InterruptControllerError(value)
+    }
+}
+
+impl From<ACPIError> for PowerError {
+    fn from(value: ACPIError) -> Self {
+        Self::AcpiParsingFailed(value)
+    }
+}
+
+impl From<PagingError> for PowerError {
+    fn from(value: PagingError) -> Self {
+        Self::TrampolineMappingFailed(value)
+    }
+}
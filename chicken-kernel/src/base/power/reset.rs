@@ -0,0 +1,70 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU16, AtomicU8, Ordering},
+};
+
+use chicken_util::BootInfo;
+
+use crate::base::{
+    acpi::{fadt::Fadt, ACPIError},
+    interrupts,
+    io::{inb, outb, Port},
+};
+
+/// Command and status port of the 8042 keyboard controller.
+const PS2_COMMAND_PORT: Port = 0x64;
+/// Set in the status register, while the controller has not processed the last input yet.
+const PS2_INPUT_FULL: u8 = 1 << 1;
+/// Pulses the reset line of the cpu.
+const PS2_RESET_COMMAND: u8 = 0xFE;
+/// Amount of status reads to wait for the keyboard controller to accept a command.
+const PS2_TIMEOUT: usize = 100_000;
+
+/// Reset register of the FADT, 0 if there is none. Not protected by a lock, so the system can be reset after a panic while any lock is held.
+static ACPI_RESET_PORT: AtomicU16 = AtomicU16::new(0);
+static ACPI_RESET_VALUE: AtomicU8 = AtomicU8::new(0);
+
+/// Reads the reset register from the FADT. Without one, [`reboot`] falls back to the keyboard controller.
+pub(in crate::base) fn set_up(boot_info: &BootInfo) -> Result<(), ACPIError> {
+    if let Some((port, value)) = Fadt::get(boot_info)?.reset_command() {
+        ACPI_RESET_VALUE.store(value, Ordering::SeqCst);
+        ACPI_RESET_PORT.store(port, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// Resets the system after an orderly shutdown. Unlike [`reboot`], which is used after a panic as well, the kernel is still in a consistent state, so the page frames no subsystem accounts for are reported first, if the `leak-check` feature is enabled.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn restart() -> ! {
+    #[cfg(feature = "leak-check")]
+    crate::memory::accounting::report();
+    reboot()
+}
+
+/// Resets the system. Uses the ACPI reset register if there is one, then the keyboard controller and finally triple faults the cpu. Does neither lock nor allocate, so it can be used by the panic handler.
+pub(crate) fn reboot() -> ! {
+    interrupts::disable();
+
+    let port = ACPI_RESET_PORT.load(Ordering::SeqCst);
+    if port != 0 {
+        unsafe { outb(port, ACPI_RESET_VALUE.load(Ordering::SeqCst)) };
+    }
+
+    for _ in 0..PS2_TIMEOUT {
+        if unsafe { inb(PS2_COMMAND_PORT) } & PS2_INPUT_FULL == 0 {
+            break;
+        }
+    }
+    unsafe { outb(PS2_COMMAND_PORT, PS2_RESET_COMMAND) };
+
+    // an exception without any idt entry escalates to a triple fault, which resets the cpu
+    let empty_idt = [0u64; 2];
+    unsafe {
+        asm!(
+            "lidt [{}]",
+            "int3",
+            in(reg) empty_idt.as_ptr(),
+            options(noreturn)
+        )
+    }
+}
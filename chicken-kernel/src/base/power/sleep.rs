@@ -0,0 +1,312 @@
+use core::{arch::asm, cell::OnceCell, hint::spin_loop, ptr};
+
+use chicken_util::{
+    memory::{paging::PageEntryFlags, pmm::audit::FramePurpose, PhysicalAddress, VirtualAddress},
+    BootInfo,
+};
+
+use crate::{
+    base::{
+        acpi::{
+            self, dsdt,
+            fadt::{Facs, Fadt},
+            sdt,
+        },
+        gdt,
+        interrupts::{idt, without_interrupts},
+        io::{inw, outb, outw, Port},
+        msr::{Efer, ModelSpecificRegister},
+        pmc,
+        power::{self, PowerError},
+        syscall,
+    },
+    memory::{
+        direct_map::phys_to_virt,
+        paging::{PagingError, PTM},
+        tlb,
+    },
+    scheduling::{spin::SpinLock, GlobalTaskScheduler},
+};
+
+/// ACPI sleep state S3 (suspend to RAM).
+const S3: u8 = 3;
+/// The firmware jumps to the wake up trampoline in real mode, so it must be located in the first MiB.
+const TRAMPOLINE_LIMIT: PhysicalAddress = 0x10_0000;
+/// Amount of status reads to wait for the platform to switch to ACPI mode.
+const ACPI_ENABLE_TIMEOUT: usize = 1_000_000;
+
+/// Set if the platform is in ACPI mode.
+const SCI_ENABLE: u16 = 1 << 0;
+const SLEEP_TYPE_SHIFT: u16 = 10;
+const SLEEP_TYPE_MASK: u16 = 0b111 << SLEEP_TYPE_SHIFT;
+const SLEEP_ENABLE: u16 = 1 << 13;
+/// Set by the platform after waking up, cleared by writing 1.
+const WAKE_STATUS: u16 = 1 << 15;
+
+static SLEEP_CONFIG: SpinLock<OnceCell<SleepConfig>> = SpinLock::new(OnceCell::new());
+
+extern "C" {
+    static wake_trampoline_start: u8;
+    static wake_trampoline_end: u8;
+
+    fn acpi_enter_sleep(
+        context: *mut SleepContext,
+        pm1a_control: Port,
+        pm1b_control: Port,
+        pm1a_value: u16,
+        pm1b_value: u16,
+    ) -> u64;
+    fn acpi_wake();
+}
+
+/// Registers and ports needed to enter S3, read from the ACPI tables during boot.
+#[derive(Copy, Clone, Debug)]
+struct SleepConfig {
+    pm1a_control: Port,
+    pm1b_control: Option<Port>,
+    pm1a_status: Port,
+    pm1b_status: Option<Port>,
+    sleep_type_a: u8,
+    sleep_type_b: u8,
+    /// Virtual address of the mapped FACS.
+    facs: VirtualAddress,
+    /// Physical address of the page containing the wake up trampoline.
+    trampoline: PhysicalAddress,
+}
+
+/// Callee saved registers and stack pointer of the task entering the sleep state, restored by `acpi_wake`.
+#[repr(C)]
+#[derive(Default, Debug)]
+struct SleepContext {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+}
+
+/// Data block at the start of the wake up trampoline, see `asm/sleep.asm`.
+#[repr(C)]
+#[derive(Debug)]
+struct TrampolineData {
+    jump: [u8; 6],
+    gdt_limit: u16,
+    gdt_base: u32,
+    reserved: u32,
+    protected_mode_entry: FarPointer,
+    long_mode_entry: FarPointer,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    cr0: u64,
+    wake_entry: VirtualAddress,
+    context: VirtualAddress,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct FarPointer {
+    offset: u32,
+    selector: u16,
+    reserved: u16,
+}
+
+/// Reads the sleep configuration from the FADT and DSDT and copies the wake up trampoline to low memory. Returns an error, if the platform does not support S3.
+pub(in crate::base) fn set_up(boot_info: &BootInfo) -> Result<(), PowerError> {
+    let fadt = Fadt::get(boot_info)?;
+    let (pm1a_control, pm1b_control) = fadt
+        .pm1_control_ports()
+        .ok_or(PowerError::SleepUnsupported)?;
+    let (pm1a_status, pm1b_status) = fadt
+        .pm1_status_ports()
+        .ok_or(PowerError::SleepUnsupported)?;
+
+    let dsdt = sdt::map_table(fadt.dsdt_address())?;
+    let sleep_types = dsdt::sleep_types(dsdt, S3);
+    acpi::unmap(dsdt as VirtualAddress)?;
+    let (sleep_type_a, sleep_type_b) = sleep_types.ok_or(PowerError::SleepUnsupported)?;
+
+    if let Some((port, value)) = fadt.acpi_enable_command() {
+        enable_acpi_mode(pm1a_control, port, value)?;
+    }
+
+    let facs = Facs::map(fadt.facs_address())?;
+    let trampoline = copy_trampoline()?;
+
+    SLEEP_CONFIG.lock().get_or_init(|| SleepConfig {
+        pm1a_control,
+        pm1b_control,
+        pm1a_status,
+        pm1b_status,
+        sleep_type_a,
+        sleep_type_b,
+        facs,
+        trampoline,
+    });
+    Ok(())
+}
+
+/// Suspends the system to RAM (ACPI S3) and returns once it has woken up again. Other tasks are parked in the meantime, the state of devices and the interrupt controller is saved and restored by the power hooks.
+#[allow(dead_code)] // only used by the suspend self-test yet
+pub(crate) fn suspend_to_ram() -> Result<(), PowerError> {
+    let config = *SLEEP_CONFIG
+        .lock()
+        .get()
+        .ok_or(PowerError::SleepUnsupported)?;
+
+    // park all other tasks, so nothing runs between saving and restoring the state
+    let preemption = GlobalTaskScheduler::preemption_enabled();
+    GlobalTaskScheduler::set_preemption(false);
+    let result = without_interrupts(|| unsafe { enter_sleep(&config) });
+    GlobalTaskScheduler::set_preemption(preemption);
+    result
+}
+
+/// Saves the state of the system, enters S3 and restores the state after waking up.
+///
+/// # Safety
+///
+/// Must be called with interrupts disabled.
+unsafe fn enter_sleep(config: &SleepConfig) -> Result<(), PowerError> {
+    power::suspend_prepare()?;
+
+    let mut context = SleepContext::default();
+    let result = map_trampoline(config, &mut context).map(|()| {
+        Facs::set_waking_vector(config.facs, config.trampoline as u32);
+
+        outw(config.pm1a_status, WAKE_STATUS);
+        if let Some(port) = config.pm1b_status {
+            outw(port, WAKE_STATUS);
+        }
+
+        let pm1a_value = sleep_control_value(config.pm1a_control, config.sleep_type_a);
+        let pm1b_value = config
+            .pm1b_control
+            .map_or(0, |port| sleep_control_value(port, config.sleep_type_b));
+        acpi_enter_sleep(
+            &mut context,
+            config.pm1a_control,
+            config.pm1b_control.unwrap_or(0),
+            pm1a_value,
+            pm1b_value,
+        ) != 0
+    });
+
+    // the descriptor tables of the trampoline are still loaded after waking up
+    gdt::reload();
+    idt::initialize();
+    // the syscall and performance counter registers are lost as well
+    syscall::initialize();
+    let _ = pmc::initialize();
+    let unmapped = unmap_trampoline(config);
+    power::resume()?;
+
+    unmapped?;
+    match result? {
+        true => Ok(()),
+        false => Err(PowerError::SleepTimeout),
+    }
+}
+
+/// Returns the value of a PM1 control register, that enters the specified sleep type.
+unsafe fn sleep_control_value(port: Port, sleep_type: u8) -> u16 {
+    let sleep_type = (u16::from(sleep_type) << SLEEP_TYPE_SHIFT) & SLEEP_TYPE_MASK;
+    (inw(port) & !SLEEP_TYPE_MASK) | sleep_type | SLEEP_ENABLE
+}
+
+/// Switches the platform from legacy mode to ACPI mode, so the PM1 registers can be used. UEFI firmware has usually done so already.
+fn enable_acpi_mode(pm1a_control: Port, command_port: Port, value: u8) -> Result<(), PowerError> {
+    if unsafe { inw(pm1a_control) } & SCI_ENABLE != 0 {
+        return Ok(());
+    }
+
+    unsafe { outb(command_port, value) };
+    for _ in 0..ACPI_ENABLE_TIMEOUT {
+        if unsafe { inw(pm1a_control) } & SCI_ENABLE != 0 {
+            return Ok(());
+        }
+        spin_loop();
+    }
+    Err(PowerError::SleepUnsupported)
+}
+
+/// Copies the wake up trampoline to a page below [`TRAMPOLINE_LIMIT`]. Returns its physical address.
+fn copy_trampoline() -> Result<PhysicalAddress, PowerError> {
+    let mut binding = PTM.lock();
+    let ptm = binding
+        .get_mut()
+        .ok_or(PagingError::GlobalPageTableManagerUninitialized)?;
+    let trampoline = ptm
+        .pmm()
+        .request_page_below(TRAMPOLINE_LIMIT, FramePurpose::Other)
+        .map_err(PagingError::from)?;
+    let destination = phys_to_virt(trampoline).ok_or(PagingError::InvalidMemoryMap)?;
+
+    unsafe {
+        let start = ptr::addr_of!(wake_trampoline_start);
+        let length = ptr::addr_of!(wake_trampoline_end).offset_from(start) as usize;
+        ptr::copy_nonoverlapping(start, destination as *mut u8, length);
+    }
+    Ok(trampoline)
+}
+
+/// Identity maps the wake up trampoline in the active page table and fills in its data block.
+unsafe fn map_trampoline(
+    config: &SleepConfig,
+    context: &mut SleepContext,
+) -> Result<(), PowerError> {
+    let cr3: u64;
+    let cr4: u64;
+    let cr0: u64;
+    asm!("mov {}, cr3", out(reg) cr3);
+    asm!("mov {}, cr4", out(reg) cr4);
+    asm!("mov {}, cr0", out(reg) cr0);
+    // the trampoline loads the page table in protected mode
+    if cr3 > u32::MAX as u64 {
+        return Err(PowerError::SleepUnsupported);
+    }
+    // long mode active is set by the cpu
+    let efer = Efer::read()
+        .ok_or(PowerError::SleepUnsupported)?
+        .difference(Efer::LMA);
+
+    let mut binding = PTM.lock();
+    let ptm = binding
+        .get_mut()
+        .ok_or(PagingError::GlobalPageTableManagerUninitialized)?;
+    ptm.map_memory(
+        config.trampoline,
+        config.trampoline,
+        PageEntryFlags::PRESENT | PageEntryFlags::READ_WRITE,
+    )
+    .map_err(PagingError::from)?;
+
+    // addresses within the trampoline are relocated using the original in the kernel image
+    let original = &*(ptr::addr_of!(wake_trampoline_start) as *const TrampolineData);
+    let data = phys_to_virt(config.trampoline).ok_or(PagingError::InvalidMemoryMap)?;
+    let data = &mut *(data as *mut TrampolineData);
+    let physical_base = config.trampoline as u32;
+    data.gdt_base = original.gdt_base + physical_base;
+    data.protected_mode_entry.offset = original.protected_mode_entry.offset + physical_base;
+    data.long_mode_entry.offset = original.long_mode_entry.offset + physical_base;
+    data.cr3 = cr3;
+    data.cr4 = cr4;
+    data.efer = efer.bits();
+    data.cr0 = cr0;
+    data.wake_entry = acpi_wake as *const () as VirtualAddress;
+    data.context = context as *mut SleepContext as VirtualAddress;
+    Ok(())
+}
+
+/// Removes the identity mapping of the wake up trampoline.
+fn unmap_trampoline(config: &SleepConfig) -> Result<(), PowerError> {
+    let mut binding = PTM.lock();
+    let ptm = binding
+        .get_mut()
+        .ok_or(PagingError::GlobalPageTableManagerUninitialized)?;
+    ptm.unmap(config.trampoline).map_err(PagingError::from)?;
+    tlb::invalidate_page(config.trampoline);
+    Ok(())
+}
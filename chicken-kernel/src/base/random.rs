@@ -0,0 +1,50 @@
+use core::{
+    arch::x86_64::_rdrand64_step,
+    mem::size_of,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chicken_util::timing::read_tsc;
+
+use crate::base::cpu::CpuFeature;
+
+/// Times a failed `rdrand` is retried, as recommended by Intel, before falling back to the time stamp counter.
+const RDRAND_RETRIES: usize = 10;
+
+/// State of the fallback generator, advanced on every draw.
+static FALLBACK_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Fills the buffer with random bytes, e.g. for stack canaries. Uses the hardware random number generator of the cpu, if it has one, otherwise the bytes are derived from the time stamp counter and must not be used for cryptography.
+pub(crate) fn fill(buffer: &mut [u8]) {
+    for chunk in buffer.chunks_mut(size_of::<u64>()) {
+        let value = next_u64();
+        chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+    }
+}
+
+/// Returns a random value, e.g. to randomize addresses. Has the same quality as the bytes of [`fill`].
+pub(crate) fn next_u64() -> u64 {
+    hardware_random().unwrap_or_else(fallback_random)
+}
+
+/// Draws a random value using `rdrand`. Returns `None`, if the cpu does not support it or has run out of entropy.
+fn hardware_random() -> Option<u64> {
+    if !CpuFeature::Rdrand.present() {
+        return None;
+    }
+    (0..RDRAND_RETRIES).find_map(|_| {
+        let mut value = 0;
+        (unsafe { _rdrand64_step(&mut value) } == 1).then_some(value)
+    })
+}
+
+/// Mixes the time stamp counter into the fallback state using splitmix64.
+fn fallback_random() -> u64 {
+    let state = FALLBACK_STATE
+        .fetch_add(0x9E37_79B9_7F4A_7C15 ^ read_tsc(), Ordering::Relaxed)
+        .wrapping_add(read_tsc());
+    let mut value = state;
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
@@ -0,0 +1,292 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cell::OnceCell,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    slice,
+};
+
+use chicken_util::{memory::PhysicalAddress, BootInfo};
+
+use crate::{
+    base::acpi::{self, ACPIError},
+    devices::{self, Bus},
+    scheduling::spin::SpinLock,
+};
+
+/// Anchor of the 32-bit entry point of SMBIOS 2.
+const ANCHOR_V2: &[u8] = b"_SM_";
+/// Anchor of the 64-bit entry point of SMBIOS 3.
+const ANCHOR_V3: &[u8] = b"_SM3_";
+/// Size of the larger one of both entry points.
+const ENTRY_POINT_SIZE: usize = 0x1F;
+
+const TYPE_BIOS_INFORMATION: u8 = 0;
+const TYPE_SYSTEM_INFORMATION: u8 = 1;
+const TYPE_MEMORY_DEVICE: u8 = 17;
+const TYPE_END_OF_TABLE: u8 = 127;
+
+static SYSTEM_INFO: SpinLock<OnceCell<SystemInfo>> = SpinLock::new(OnceCell::new());
+
+/// Identification of the machine read from the SMBIOS tables, e.g. to be included in bug reports about hardware specific boot failures.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SystemInfo {
+    pub(crate) version: (u8, u8),
+    pub(crate) manufacturer: String,
+    pub(crate) product: String,
+    pub(crate) bios_vendor: String,
+    pub(crate) bios_version: String,
+    pub(crate) bios_date: String,
+    /// Populated memory slots.
+    pub(crate) memory_devices: Vec<MemoryDevice>,
+}
+
+impl Display for SystemInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} {}, BIOS: {} {} ({}), {} memory device(s), {} MiB (SMBIOS {}.{})",
+            self.manufacturer,
+            self.product,
+            self.bios_vendor,
+            self.bios_version,
+            self.bios_date,
+            self.memory_devices.len(),
+            self.memory_devices
+                .iter()
+                .map(|device| device.size_mib)
+                .sum::<u64>(),
+            self.version.0,
+            self.version.1
+        )
+    }
+}
+
+/// Memory module in one of the slots of the machine.
+#[derive(Clone, Debug)]
+pub(crate) struct MemoryDevice {
+    /// Label of the slot, e.g. `DIMM 0`.
+    pub(crate) locator: String,
+    pub(crate) size_mib: u64,
+    /// Maximum speed in MT/s, 0 if it is unknown.
+    pub(crate) speed: u16,
+    pub(crate) manufacturer: String,
+    pub(crate) part_number: String,
+}
+
+impl Display for MemoryDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}: {} MiB", self.locator, self.size_mib)?;
+        if self.speed != 0 {
+            write!(f, " @ {} MT/s", self.speed)?;
+        }
+        write!(f, " {} {}", self.manufacturer, self.part_number)
+    }
+}
+
+/// Parses the SMBIOS tables of the firmware and registers the memory devices with the device registry. Returns the identification of the machine.
+pub(in crate::base) fn set_up(boot_info: &BootInfo) -> Result<SystemInfo, SmbiosError> {
+    if boot_info.smbios == 0 {
+        return Err(SmbiosError::EntryPointNotFound);
+    }
+    let (version, table_address, table_length) = read_entry_point(boot_info.smbios)?;
+
+    let table = acpi::map(table_address, table_length)?;
+    let data = unsafe { slice::from_raw_parts(table as *const u8, table_length) };
+    let mut info = parse_table(data);
+    acpi::unmap(table)?;
+    info.version = version;
+
+    for device in &info.memory_devices {
+        let id = devices::register("memory", Bus::Smbios, Vec::new());
+        let _ = devices::set_details(id, device.to_string());
+    }
+    SYSTEM_INFO.lock().get_or_init(|| info.clone());
+    Ok(info)
+}
+
+/// Returns the identification of the machine, if the SMBIOS tables have been parsed.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn system_info() -> Option<SystemInfo> {
+    SYSTEM_INFO.lock().get().cloned()
+}
+
+/// Reads the entry point at the given address. Returns the SMBIOS version, the physical address and the length of the structure table.
+fn read_entry_point(
+    address: PhysicalAddress,
+) -> Result<((u8, u8), PhysicalAddress, usize), SmbiosError> {
+    let virtual_address = acpi::map(address, ENTRY_POINT_SIZE)?;
+    let entry = unsafe { slice::from_raw_parts(virtual_address as *const u8, ENTRY_POINT_SIZE) };
+
+    let result = if entry.starts_with(ANCHOR_V3) {
+        let length = entry[0x06] as usize;
+        Ok((
+            (entry[0x07], entry[0x08]),
+            read_u64(entry, 0x10),
+            // only the maximum size is known, the end of table structure marks the actual end
+            read_u32(entry, 0x0C) as usize,
+            length,
+        ))
+    } else if entry.starts_with(ANCHOR_V2) {
+        let length = entry[0x05] as usize;
+        Ok((
+            (entry[0x06], entry[0x07]),
+            read_u32(entry, 0x18) as PhysicalAddress,
+            read_u16(entry, 0x16) as usize,
+            length,
+        ))
+    } else {
+        Err(SmbiosError::InvalidEntryPoint)
+    }
+    .and_then(|(version, table_address, table_length, length)| {
+        let valid = length <= ENTRY_POINT_SIZE
+            && entry[..length]
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+                == 0;
+        valid
+            .then_some((version, table_address, table_length))
+            .ok_or(SmbiosError::InvalidEntryPoint)
+    });
+
+    acpi::unmap(virtual_address)?;
+    result
+}
+
+/// Collects the information of the structures in the table, until the end of table structure is reached.
+fn parse_table(table: &[u8]) -> SystemInfo {
+    let mut info = SystemInfo::default();
+    let mut offset = 0;
+
+    while offset + 4 <= table.len() {
+        let kind = table[offset];
+        let length = table[offset + 1] as usize;
+        if kind == TYPE_END_OF_TABLE || length < 4 || offset + length > table.len() {
+            break;
+        }
+        let formatted = &table[offset..offset + length];
+        let strings_start = offset + length;
+        // the string set is terminated by two null bytes
+        let strings_end = table[strings_start..]
+            .windows(2)
+            .position(|window| window == [0, 0])
+            .map_or(table.len(), |position| strings_start + position + 2);
+        let strings = &table[strings_start..strings_end];
+
+        match kind {
+            TYPE_BIOS_INFORMATION => {
+                info.bios_vendor = string_at(formatted, strings, 0x04);
+                info.bios_version = string_at(formatted, strings, 0x05);
+                info.bios_date = string_at(formatted, strings, 0x08);
+            }
+            TYPE_SYSTEM_INFORMATION => {
+                info.manufacturer = string_at(formatted, strings, 0x04);
+                info.product = string_at(formatted, strings, 0x05);
+            }
+            TYPE_MEMORY_DEVICE => {
+                if let Some(device) = parse_memory_device(formatted, strings) {
+                    info.memory_devices.push(device);
+                }
+            }
+            _ => {}
+        }
+        offset = strings_end;
+    }
+    info
+}
+
+/// Parses a memory device structure. Returns `None`, if the slot is empty.
+fn parse_memory_device(formatted: &[u8], strings: &[u8]) -> Option<MemoryDevice> {
+    let size = read_u16(formatted, 0x0C);
+    let size_mib = match size {
+        0 | 0xFFFF => return None,
+        // the actual size is stored in the extended size field
+        0x7FFF => (read_u32(formatted, 0x1C) & 0x7FFF_FFFF) as u64,
+        // granularity of KiB instead of MiB
+        size if size & 0x8000 != 0 => (size & 0x7FFF) as u64 / 1024,
+        size => size as u64,
+    };
+
+    Some(MemoryDevice {
+        locator: string_at(formatted, strings, 0x10),
+        size_mib,
+        speed: read_u16(formatted, 0x15),
+        manufacturer: string_at(formatted, strings, 0x17),
+        part_number: string_at(formatted, strings, 0x1A),
+    })
+}
+
+/// Returns the string referenced by the byte at the given offset of the formatted area. Strings are numbered starting at 1, 0 means that there is none.
+fn string_at(formatted: &[u8], strings: &[u8], offset: usize) -> String {
+    let Some(index) = formatted.get(offset).filter(|index| **index != 0) else {
+        return String::new();
+    };
+    strings
+        .split(|byte| *byte == 0)
+        .nth(*index as usize - 1)
+        .map(|string| String::from_utf8_lossy(string).trim().to_string())
+        .unwrap_or_default()
+}
+
+// fields of older versions are missing in shorter structures, which reads them as 0
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data.get(offset..offset + 2)
+        .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    data.get(offset..offset + 4).map_or(0, |bytes| {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    })
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    data.get(offset..offset + 8).map_or(0, |bytes| {
+        let mut value = [0; 8];
+        value.copy_from_slice(bytes);
+        u64::from_le_bytes(value)
+    })
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum SmbiosError {
+    EntryPointNotFound,
+    InvalidEntryPoint,
+    MappingFailed(ACPIError),
+}
+
+impl Debug for SmbiosError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SmbiosError::EntryPointNotFound => {
+                write!(f, "SMBIOS Error: Firmware does not provide SMBIOS tables.")
+            }
+            SmbiosError::InvalidEntryPoint => {
+                write!(
+                    f,
+                    "SMBIOS Error: Entry point has an invalid anchor or checksum."
+                )
+            }
+            SmbiosError::MappingFailed(value) => {
+                write!(f, "SMBIOS Error: {:?}", value)
+            }
+        }
+    }
+}
+
+impl Display for SmbiosError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SmbiosError {}
+
+impl From<ACPIError> for SmbiosError {
+    fn from(value: ACPIError) -> Self {
+        Self::MappingFailed(value)
+    }
+}
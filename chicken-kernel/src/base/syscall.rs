@@ -0,0 +1,309 @@
+use alloc::{string::String, vec::Vec};
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    mem::size_of,
+    slice, str,
+};
+
+use chicken_util::{memory::VirtualAddress, PAGE_SIZE};
+
+use crate::{
+    base::{
+        gdt::{KERNEL_CS, USER_DS},
+        interrupts::{self, without_interrupts, RFlags},
+        msr::{Efer, LStar, ModelSpecificRegister, SfMask, Star},
+    },
+    memory::paging::PTM,
+    print,
+    scheduling::{
+        task::{
+            self,
+            capability::Capabilities,
+            exec::{self, SpawnError},
+        },
+        GlobalTaskScheduler, SchedulerError,
+    },
+};
+
+/// Addresses above belong to the kernel half of the address space. Must match the entry point, which only returns to lower addresses with sysret.
+const USER_ADDRESS_LIMIT: VirtualAddress = 0x0000_8000_0000_0000;
+/// File descriptors of the standard output and standard error, which are both written to the console.
+const STDOUT: u64 = 1;
+const STDERR: u64 = 2;
+/// Longest path or argument passed to a syscall.
+const MAX_STRING_LENGTH: u64 = PAGE_SIZE as u64;
+/// Most arguments passed to a spawned program.
+const MAX_ARGUMENTS: u64 = 64;
+
+extern "C" {
+    fn syscall_entry();
+    fn set_syscall_stack(top: u64);
+    fn jump_to_user_mode(entry: u64, stack_pointer: u64) -> !;
+}
+
+/// Handlers indexed by their syscall number.
+static SYSCALLS: [SyscallHandler; 7] = [
+    write,
+    exit,
+    sleep,
+    yield_now,
+    get_pid,
+    spawn,
+    drop_capabilities,
+];
+
+/// Syscall numbers, passed in rax.
+#[allow(dead_code)] // only used by user programs
+#[repr(u64)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Syscall {
+    /// `write(fd, buffer, length)`: Writes the buffer to the console. Returns the amount of bytes written.
+    Write = 0,
+    /// `exit(code)`: Terminates the calling thread. Does not return.
+    Exit = 1,
+    /// `sleep(ms)`: Suspends the calling thread for the given time.
+    Sleep = 2,
+    /// `yield()`: Gives up the rest of the time slice.
+    Yield = 3,
+    /// `getpid()`: Returns the pid of the calling process.
+    GetPid = 4,
+    /// `spawn(path, path_length, arguments, argument_count)`: Starts the program at the path of the initrd in a new process. The arguments point at pairs of a string address and its length. Returns the pid of the new process.
+    Spawn = 5,
+    /// `drop_capabilities(capabilities)`: Irrevocably removes the capabilities of the bit mask from the calling process, e.g. once a program has finished its set up.
+    DropCapabilities = 6,
+}
+
+type SyscallHandler = fn(&SyscallFrame) -> Result<u64, SyscallError>;
+
+/// Registers saved by the syscall entry point, in reverse order of being pushed.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SyscallFrame {
+    r9: u64,
+    r8: u64,
+    r10: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    /// Syscall number.
+    rax: u64,
+    /// Flags of the caller, restored by sysret.
+    #[allow(dead_code)] // only restored by the entry point
+    r11: u64,
+    /// Return address of the caller, restored by sysret.
+    #[allow(dead_code)] // only restored by the entry point
+    rcx: u64,
+    /// Stack pointer of the caller.
+    #[allow(dead_code)] // only restored by the entry point
+    rsp: u64,
+}
+
+impl SyscallFrame {
+    /// Returns the arguments in the order of the syscall calling convention.
+    fn args(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+}
+
+/// Enables the syscall instruction and points it at the kernel entry point. Has to be called again after waking up from sleep, since the registers are lost. The kernel stack set by the scheduler is kept.
+pub(in crate::base) fn initialize() {
+    if let Some(efer) = Efer::read() {
+        (efer | Efer::SCE).write();
+    }
+    // sysret adds 8 for the stack and 16 for the code selector to its base selector
+    Star::new(KERNEL_CS, USER_DS - 8).write();
+    LStar::new(syscall_entry as *const () as u64).write();
+    // syscalls start with interrupts disabled, until they have switched to the kernel stack
+    SfMask::from_bits_retain(
+        (RFlags::INTERRUPTS_ENABLED | RFlags::TRAP | RFlags::DIRECTION | RFlags::ACCESS_CONTROL)
+            .bits(),
+    )
+    .write();
+}
+
+/// Sets the kernel stack syscalls run on. The scheduler points it at the kernel stack of every thread it switches to, since threads blocked in a syscall keep their frame on it. There is no stack before the first switch, since no user code runs until then.
+pub(crate) fn set_kernel_stack(top: VirtualAddress) {
+    unsafe { set_syscall_stack(top) };
+}
+
+/// Continues the current thread in user mode at the entry point with the stack pointer. The kernel stack of the thread is reused for its syscalls and interrupts afterward.
+pub(crate) fn enter_user_mode(entry: VirtualAddress, stack_pointer: VirtualAddress) -> ! {
+    unsafe { jump_to_user_mode(entry, stack_pointer) }
+}
+
+/// Runs the handler of the syscall with interrupts enabled. Returns its result, or the negated error code.
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame: *const SyscallFrame) -> u64 {
+    let frame = unsafe { &*frame };
+    let Some(handler) = SYSCALLS.get(frame.rax as usize) else {
+        return SyscallError::UnknownSyscall(frame.rax).into_return_value();
+    };
+
+    interrupts::enable();
+    let result = handler(frame);
+    // the entry point switches back to the stack of the caller before sysret
+    interrupts::disable();
+
+    result.unwrap_or_else(SyscallError::into_return_value)
+}
+
+fn write(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    let [fd, buffer, length, ..] = frame.args();
+    if fd != STDOUT && fd != STDERR {
+        return Err(SyscallError::InvalidFileDescriptor(fd));
+    }
+    let buffer = user_slice(buffer, length)?;
+    print!("{}", String::from_utf8_lossy(buffer));
+    Ok(length)
+}
+
+fn exit(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    // negative statuses are passed in two's complement
+    let [status, ..] = frame.args();
+    task::exit(status as i64)
+}
+
+fn sleep(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    let [duration_ms, ..] = frame.args();
+    GlobalTaskScheduler::sleep(duration_ms);
+    Ok(0)
+}
+
+fn yield_now(_frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    GlobalTaskScheduler::yield_now();
+    Ok(0)
+}
+
+fn get_pid(_frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    Ok(GlobalTaskScheduler::current_pid().unwrap_or_default())
+}
+
+fn spawn(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    let [path, path_length, arguments, argument_count, ..] = frame.args();
+    let path = user_str(path, path_length)?;
+    if argument_count > MAX_ARGUMENTS {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let entries = if argument_count == 0 {
+        &[]
+    } else {
+        user_slice(arguments, argument_count * 2 * size_of::<u64>() as u64)?
+    };
+    let arguments = entries
+        .chunks_exact(2 * size_of::<u64>())
+        .map(|entry| {
+            let (address, length) = entry.split_at(size_of::<u64>());
+            user_str(
+                u64::from_le_bytes(address.try_into().unwrap()),
+                u64::from_le_bytes(length.try_into().unwrap()),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    exec::spawn(path, &arguments)
+        .map(|process| process.pid())
+        .map_err(SyscallError::SpawnFailed)
+}
+
+fn drop_capabilities(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    let [capabilities, ..] = frame.args();
+    let capabilities =
+        Capabilities::from_bits(capabilities).ok_or(SyscallError::InvalidArgument)?;
+    task::drop_capabilities(capabilities);
+    Ok(0)
+}
+
+/// Returns the UTF-8 string of the caller, if it is mapped and not longer than [`MAX_STRING_LENGTH`].
+fn user_str(address: u64, length: u64) -> Result<&'static str, SyscallError> {
+    if length > MAX_STRING_LENGTH {
+        return Err(SyscallError::InvalidArgument);
+    }
+    str::from_utf8(user_slice(address, length)?).map_err(|_| SyscallError::InvalidArgument)
+}
+
+/// Returns the buffer of the caller, if it lies in the user half of the address space and all of its pages are mapped.
+fn user_slice(address: u64, length: u64) -> Result<&'static [u8], SyscallError> {
+    let end = address
+        .checked_add(length)
+        .filter(|end| address != 0 && *end <= USER_ADDRESS_LIMIT)
+        .ok_or(SyscallError::InvalidAddress(address))?;
+    if length == 0 {
+        return Ok(&[]);
+    }
+
+    let first_page = address - address % PAGE_SIZE as u64;
+    let mapped = without_interrupts(|| {
+        let binding = PTM.lock();
+        let Some(ptm) = binding.get() else {
+            return false;
+        };
+        (first_page..end)
+            .step_by(PAGE_SIZE)
+            .all(|page| ptm.get_physical(page).is_some())
+    });
+    if !mapped {
+        return Err(SyscallError::InvalidAddress(address));
+    }
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, length as usize) })
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum SyscallError {
+    UnknownSyscall(u64),
+    InvalidFileDescriptor(u64),
+    InvalidAddress(VirtualAddress),
+    /// A string is too long or not valid UTF-8, too many arguments have been passed or a capability is unknown.
+    InvalidArgument,
+    SpawnFailed(SpawnError),
+}
+
+impl SyscallError {
+    /// Error code returned to the caller, matching the errno values of Linux, so ports of existing programs can use them as is.
+    pub(crate) fn code(&self) -> u64 {
+        match self {
+            SyscallError::UnknownSyscall(_) => 38,
+            SyscallError::InvalidFileDescriptor(_) => 9,
+            SyscallError::InvalidAddress(_) => 14,
+            SyscallError::InvalidArgument => 22,
+            SyscallError::SpawnFailed(SpawnError::ProgramUnavailable(_)) => 2,
+            SyscallError::SpawnFailed(SpawnError::InvalidProgram(_)) => 8,
+            SyscallError::SpawnFailed(SpawnError::SetUpFailed(
+                SchedulerError::MissingCapabilities(..),
+            )) => 1,
+            SyscallError::SpawnFailed(SpawnError::SetUpFailed(_)) => 12,
+        }
+    }
+
+    /// Returns the negated error code, which user programs can tell apart from valid results.
+    fn into_return_value(self) -> u64 {
+        self.code().wrapping_neg()
+    }
+}
+
+impl Debug for SyscallError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SyscallError::UnknownSyscall(number) => {
+                write!(f, "Syscall Error: Unknown syscall number: {}.", number)
+            }
+            SyscallError::InvalidFileDescriptor(fd) => {
+                write!(f, "Syscall Error: Invalid file descriptor: {}.", fd)
+            }
+            SyscallError::InvalidAddress(address) => {
+                write!(f, "Syscall Error: Invalid user address: {:#x}.", address)
+            }
+            SyscallError::InvalidArgument => write!(f, "Syscall Error: Invalid argument."),
+            SyscallError::SpawnFailed(value) => {
+                write!(f, "Syscall Error: Could not spawn program: {}", value)
+            }
+        }
+    }
+}
+
+impl Display for SyscallError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SyscallError {}
@@ -0,0 +1,386 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cell::OnceCell,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
+
+use chicken_util::{cmdline::CommandLine, BootInfo};
+
+use crate::{
+    base::{
+        crash,
+        interrupts::{budget, without_interrupts},
+        io::{
+            keyboard,
+            timer::{self, pit::ProgrammableIntervalTimer},
+        },
+    },
+    log,
+    memory::kheap::slab,
+    scheduling::{self, spin::SpinLock},
+};
+
+/// Command line of the boot entry, copied so options can be read before the kernel heap is available and after the boot info has been moved.
+static COMMAND_LINE: SpinLock<OnceCell<CommandLine>> = SpinLock::new(OnceCell::new());
+/// Values set at runtime, taking precedence over the command line.
+static OVERRIDES: SpinLock<Vec<(&'static str, String)>> = SpinLock::new(Vec::new());
+
+/// Options subsystems read from the registry. Values set at runtime take precedence over the command line, which takes precedence over the default.
+static SETTINGS: [Setting; 14] = [
+    Setting {
+        key: "log",
+        description: "log levels: <level>[,<module>=<level>...]",
+        kind: Kind::Text,
+        default: if cfg!(feature = "verbose-debug") {
+            "debug"
+        } else {
+            "info"
+        },
+        on_change: Some(|| log::apply_options().is_empty()),
+    },
+    Setting {
+        key: "serial",
+        description: "baud rate of the serial console, or off",
+        kind: Kind::Text,
+        default: "115200",
+        on_change: None,
+    },
+    Setting {
+        key: "gdb",
+        description: "serial port of the gdb stub",
+        kind: Kind::Choice(&["off", "com1", "com2"]),
+        default: "off",
+        on_change: None,
+    },
+    Setting {
+        key: "isr_budget_us",
+        description: "time in us an interrupt handler may run",
+        kind: Kind::Integer {
+            min: 1,
+            max: u64::MAX,
+        },
+        default: "100",
+        on_change: Some(|| {
+            budget::set_up();
+            true
+        }),
+    },
+    Setting {
+        key: "panic",
+        description: "what the kernel does after a panic",
+        kind: Kind::Choice(&["halt", "reboot", "wait"]),
+        default: "halt",
+        on_change: Some(|| {
+            crash::set_panic_policy();
+            true
+        }),
+    },
+    Setting {
+        key: "panic_timeout",
+        description: "seconds before rebooting after a panic",
+        kind: Kind::Integer { min: 0, max: 3600 },
+        default: "10",
+        on_change: Some(|| {
+            crash::set_panic_policy();
+            true
+        }),
+    },
+    Setting {
+        key: "timer_frequency",
+        description: "frequency of the timer interrupt in Hz",
+        kind: Kind::Integer {
+            min: ProgrammableIntervalTimer::MIN_FREQUENCY,
+            max: ProgrammableIntervalTimer::MAX_FREQUENCY,
+        },
+        default: "1000",
+        on_change: Some(|| timer::apply_frequency().is_ok()),
+    },
+    Setting {
+        key: "timer",
+        description: "timer driving the scheduler ticks: pit or lapic",
+        kind: Kind::Choice(&["pit", "lapic"]),
+        default: "pit",
+        on_change: None,
+    },
+    Setting {
+        key: "sched_quantum",
+        description: "timer ticks a thread runs before it is preempted",
+        kind: Kind::Integer { min: 1, max: 1000 },
+        default: "1",
+        on_change: Some(|| {
+            scheduling::set_up_quantum();
+            true
+        }),
+    },
+    Setting {
+        key: "slab_prealloc",
+        description: "slabs each object cache takes from the kernel heap in advance",
+        kind: Kind::Integer { min: 0, max: 64 },
+        default: "0",
+        on_change: Some(slab::preallocate),
+    },
+    Setting {
+        key: "slab_growth",
+        description: "slabs an exhausted object cache adds: single or double its size",
+        kind: Kind::Choice(&["single", "double"]),
+        default: "single",
+        on_change: Some(|| {
+            slab::apply_growth_policy();
+            true
+        }),
+    },
+    Setting {
+        key: "pmc",
+        description: "count instructions and cycles per thread",
+        kind: Kind::Choice(&["off", "on"]),
+        default: "off",
+        on_change: None,
+    },
+    Setting {
+        key: "console_input",
+        description: "format key presses are read in: vt escape sequences or structured events",
+        kind: Kind::Choice(&["vt", "events"]),
+        default: "vt",
+        on_change: Some(|| {
+            keyboard::input::apply_mode();
+            true
+        }),
+    },
+    Setting {
+        key: "init",
+        description: "services started at boot: all or <service>[,<service>...]",
+        kind: Kind::Text,
+        default: "all",
+        on_change: None,
+    },
+];
+
+/// Option of the registry.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Setting {
+    pub(crate) key: &'static str,
+    pub(crate) description: &'static str,
+    pub(crate) kind: Kind,
+    pub(crate) default: &'static str,
+    /// Applies a new value set at runtime, by reading it from the registry again. Returns whether it has been applied. Options without one can only be set on the command line.
+    on_change: Option<fn() -> bool>,
+}
+
+impl Setting {
+    pub(crate) fn is_tunable(&self) -> bool {
+        self.on_change.is_some()
+    }
+}
+
+/// Values an option accepts.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Kind {
+    Integer {
+        min: u64,
+        max: u64,
+    },
+    Choice(&'static [&'static str]),
+    /// Any value, checked by the subsystem itself.
+    Text,
+}
+
+impl Kind {
+    fn accepts(&self, value: &str) -> bool {
+        match self {
+            Kind::Integer { min, max } => value
+                .parse::<u64>()
+                .is_ok_and(|value| (*min..=*max).contains(&value)),
+            Kind::Choice(choices) => choices.contains(&value),
+            Kind::Text => true,
+        }
+    }
+}
+
+/// Where the current value of an option comes from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Source {
+    Default,
+    CommandLine,
+    Runtime,
+}
+
+/// Current value of an option, e.g. to list all options.
+#[derive(Clone, Debug)]
+pub(crate) struct SettingValue {
+    pub(crate) setting: Setting,
+    pub(crate) value: String,
+    pub(crate) source: Source,
+}
+
+impl Display for SettingValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}={} ({:?}{}): {}",
+            self.setting.key,
+            self.value,
+            self.source,
+            if self.setting.is_tunable() {
+                ", tunable"
+            } else {
+                ""
+            },
+            self.setting.description
+        )
+    }
+}
+
+/// Copies the command line of the boot entry into the registry. Does not allocate, so it can be called before memory has been set up.
+pub(crate) fn set_up(boot_info: &BootInfo) {
+    without_interrupts(|| {
+        COMMAND_LINE.lock().get_or_init(|| boot_info.command_line);
+    });
+}
+
+/// Runs the closure with the current value of the option. Does not allocate, unless the value has been set at runtime.
+pub(crate) fn with<R>(key: &str, f: impl FnOnce(Option<&str>) -> R) -> R {
+    let overridden = without_interrupts(|| {
+        OVERRIDES
+            .lock()
+            .iter()
+            .find(|(overridden_key, _)| *overridden_key == key)
+            .map(|(_, value)| value.clone())
+    });
+    if let Some(value) = overridden {
+        return f(Some(&value));
+    }
+
+    let command_line =
+        without_interrupts(|| COMMAND_LINE.lock().get().copied()).unwrap_or_default();
+    let default = setting(key).map(|setting| setting.default);
+    f(command_line.value(key).or(default))
+}
+
+/// Returns the current value of an integer option, if it is set and valid.
+pub(crate) fn integer(key: &str) -> Option<u64> {
+    with(key, |value| value.and_then(|value| value.parse().ok()))
+}
+
+/// Returns the current value of the option.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn get(key: &str) -> Result<SettingValue, ConfigError> {
+    let setting = *setting(key).ok_or(ConfigError::UnknownKey)?;
+    let runtime = without_interrupts(|| {
+        OVERRIDES
+            .lock()
+            .iter()
+            .find(|(overridden_key, _)| *overridden_key == key)
+            .map(|(_, value)| value.clone())
+    });
+    let (value, source) = match runtime {
+        Some(value) => (value, Source::Runtime),
+        None => {
+            let command_line =
+                without_interrupts(|| COMMAND_LINE.lock().get().copied()).unwrap_or_default();
+            match command_line.value(key) {
+                Some(value) => (value.to_string(), Source::CommandLine),
+                None => (setting.default.to_string(), Source::Default),
+            }
+        }
+    };
+    Ok(SettingValue {
+        setting,
+        value,
+        source,
+    })
+}
+
+/// Returns the current values of all options.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn settings() -> Vec<SettingValue> {
+    SETTINGS
+        .iter()
+        .filter_map(|setting| get(setting.key).ok())
+        .collect()
+}
+
+/// Sets a runtime tunable option and applies it. The previous value is restored, if the subsystem rejects the new one.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn set(key: &str, value: &str) -> Result<(), ConfigError> {
+    let setting = setting(key).ok_or(ConfigError::UnknownKey)?;
+    let on_change = setting
+        .on_change
+        .ok_or(ConfigError::NotTunable(setting.key))?;
+    if !setting.kind.accepts(value) {
+        return Err(ConfigError::InvalidValue(setting.key));
+    }
+
+    let previous = replace_override(setting.key, Some(value.to_string()));
+    if on_change() {
+        return Ok(());
+    }
+    replace_override(setting.key, previous);
+    on_change();
+    Err(ConfigError::InvalidValue(setting.key))
+}
+
+/// Removes the value set at runtime, so the option falls back to the command line or its default.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn reset(key: &str) -> Result<(), ConfigError> {
+    let setting = setting(key).ok_or(ConfigError::UnknownKey)?;
+    let on_change = setting
+        .on_change
+        .ok_or(ConfigError::NotTunable(setting.key))?;
+    replace_override(setting.key, None);
+    on_change();
+    Ok(())
+}
+
+/// Replaces the value set at runtime. Returns the previous one.
+fn replace_override(key: &'static str, value: Option<String>) -> Option<String> {
+    without_interrupts(|| {
+        let mut overrides = OVERRIDES.lock();
+        let previous = overrides
+            .iter()
+            .position(|(overridden_key, _)| *overridden_key == key)
+            .map(|index| overrides.remove(index).1);
+        if let Some(value) = value {
+            overrides.push((key, value));
+        }
+        previous
+    })
+}
+
+fn setting(key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.key == key)
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum ConfigError {
+    UnknownKey,
+    NotTunable(&'static str),
+    InvalidValue(&'static str),
+}
+
+impl Debug for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ConfigError::UnknownKey => write!(f, "Config Error: Unknown option."),
+            ConfigError::NotTunable(key) => write!(
+                f,
+                "Config Error: Option: {} can only be set on the command line.",
+                key
+            ),
+            ConfigError::InvalidValue(key) => {
+                write!(f, "Config Error: Invalid value for option: {}.", key)
+            }
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ConfigError {}
@@ -0,0 +1,209 @@
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
+
+use chicken_util::memory::PhysicalAddress;
+
+use crate::{
+    base::{interrupts::irq::Irq, io::Port},
+    scheduling::rcu::Rcu,
+};
+
+/// Every device discovered so far, in the order of registration. The index is the id of the device. Listing the devices does not block drivers registering new ones.
+static DEVICES: Rcu<Vec<Device>> = Rcu::new(Vec::new());
+
+/// Bus or mechanism a device has been discovered by.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Bus {
+    /// Legacy device at a fixed location, that is present on every pc.
+    Isa,
+    Ps2,
+    /// Device described by an ACPI table.
+    Acpi,
+    /// Device described by the SMBIOS tables of the firmware, e.g. memory modules.
+    Smbios,
+    #[allow(dead_code)] // no pci bus driver yet
+    Pci,
+}
+
+impl Display for Bus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Bus::Isa => "isa",
+            Bus::Ps2 => "ps/2",
+            Bus::Acpi => "acpi",
+            Bus::Smbios => "smbios",
+            Bus::Pci => "pci",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Hardware resource used by a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Resource {
+    Mmio { base: PhysicalAddress, size: u64 },
+    Ports { base: Port, count: u16 },
+    Irq(Irq),
+}
+
+impl Display for Resource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Resource::Mmio { base, size } => {
+                write!(f, "mmio {:#x}-{:#x}", base, base + size.saturating_sub(1))
+            }
+            Resource::Ports { base, count: 1 } => write!(f, "port {:#x}", base),
+            Resource::Ports { base, count } => {
+                write!(f, "ports {:#x}-{:#x}", base, base + count.saturating_sub(1))
+            }
+            Resource::Irq(irq) => write!(f, "irq {}", irq.line()),
+        }
+    }
+}
+
+/// Handle of a registered device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DeviceId(usize);
+
+#[derive(Clone, Debug)]
+pub(crate) struct Device {
+    pub(crate) id: DeviceId,
+    pub(crate) name: &'static str,
+    pub(crate) bus: Bus,
+    pub(crate) resources: Vec<Resource>,
+    /// Name of the driver that has been bound to the device, if there is one.
+    pub(crate) driver: Option<&'static str>,
+    /// Further description of the device, e.g. its model.
+    pub(crate) details: Option<String>,
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:>3} {:<12} {:<5}", self.id.0, self.name, self.bus)?;
+        for (index, resource) in self.resources.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, resource)?;
+        }
+        if let Some(details) = &self.details {
+            write!(f, " ({})", details)?;
+        }
+        match self.driver {
+            Some(driver) => write!(f, " [{}]", driver),
+            None => write!(f, " [no driver]"),
+        }
+    }
+}
+
+/// Registers a device discovered by a bus. Returns its id, which drivers use to bind to it.
+pub(crate) fn register(name: &'static str, bus: Bus, resources: Vec<Resource>) -> DeviceId {
+    DEVICES.update(|devices| {
+        let id = DeviceId(devices.len());
+        devices.push(Device {
+            id,
+            name,
+            bus,
+            resources,
+            driver: None,
+            details: None,
+        });
+        id
+    })
+}
+
+/// Marks the device as driven by the given driver. Only one driver can be bound to a device at a time.
+pub(crate) fn bind(id: DeviceId, driver: &'static str) -> Result<(), DeviceError> {
+    DEVICES.update(|devices| {
+        let device = devices
+            .get_mut(id.0)
+            .ok_or(DeviceError::DeviceNotFound(id.0))?;
+        if let Some(bound) = device.driver {
+            return Err(DeviceError::AlreadyBound(device.name, bound));
+        }
+        device.driver = Some(driver);
+        Ok(())
+    })
+}
+
+/// Releases the device, e.g. if its driver failed to initialize, so another driver can bind to it.
+#[allow(dead_code)] // no driver releases its device yet
+pub(crate) fn unbind(id: DeviceId) -> Result<(), DeviceError> {
+    DEVICES.update(|devices| {
+        let device = devices
+            .get_mut(id.0)
+            .ok_or(DeviceError::DeviceNotFound(id.0))?;
+        device.driver = None;
+        Ok(())
+    })
+}
+
+/// Attaches a further description to the device, e.g. the model of a memory module.
+pub(crate) fn set_details(id: DeviceId, details: String) -> Result<(), DeviceError> {
+    DEVICES.update(|devices| {
+        let device = devices
+            .get_mut(id.0)
+            .ok_or(DeviceError::DeviceNotFound(id.0))?;
+        device.details = Some(details);
+        Ok(())
+    })
+}
+
+/// Registers a device and binds the driver, that discovered it, to it right away, e.g. for built-in devices handled by the kernel itself.
+pub(crate) fn register_bound(
+    name: &'static str,
+    bus: Bus,
+    resources: Vec<Resource>,
+    driver: &'static str,
+) -> DeviceId {
+    let id = register(name, bus, resources);
+    // the device has just been registered, so it can not be bound already
+    let _ = bind(id, driver);
+    id
+}
+
+/// Returns all registered devices.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn devices() -> Vec<Device> {
+    DEVICES.read().clone()
+}
+
+/// Returns a listing of all registered devices with their resources and drivers, one per line, e.g. for the `lsdev` shell command.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn listing() -> String {
+    DEVICES
+        .read()
+        .iter()
+        .map(|device| format!("{}\n", device))
+        .collect()
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum DeviceError {
+    DeviceNotFound(usize),
+    AlreadyBound(&'static str, &'static str),
+}
+
+impl Debug for DeviceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DeviceError::DeviceNotFound(id) => {
+                write!(f, "Device Error: Could not find device with ID: {}.", id)
+            }
+            DeviceError::AlreadyBound(device, driver) => write!(
+                f,
+                "Device Error: Device: {} is already bound to driver: {}.",
+                device, driver
+            ),
+        }
+    }
+}
+
+impl Display for DeviceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for DeviceError {}
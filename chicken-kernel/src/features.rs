@@ -0,0 +1,41 @@
+use alloc::{string::String, vec::Vec};
+
+/// Optional part of the kernel, selected at compile time using the Cargo feature of the same name. Disabled features are not compiled into the kernel.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Feature {
+    pub(crate) name: &'static str,
+    pub(crate) enabled: bool,
+}
+
+impl Feature {
+    const fn new(name: &'static str, enabled: bool) -> Self {
+        Self { name, enabled }
+    }
+}
+
+/// Every optional feature of the kernel. Must be kept in sync with the features in the manifest.
+pub(crate) const FEATURES: [Feature; 8] = [
+    Feature::new("legacy-pic", cfg!(feature = "legacy-pic")),
+    Feature::new("graphics-compositor", cfg!(feature = "graphics-compositor")),
+    Feature::new("ktest", cfg!(feature = "ktest")),
+    Feature::new("ktest-suspend", cfg!(feature = "ktest-suspend")),
+    Feature::new("verbose-debug", cfg!(feature = "verbose-debug")),
+    Feature::new("boot-audit", cfg!(feature = "boot-audit")),
+    Feature::new("leak-check", cfg!(feature = "leak-check")),
+    Feature::new("page-table-check", cfg!(feature = "page-table-check")),
+];
+
+/// Returns the names of the enabled features separated by commas, e.g. for the boot log.
+pub(crate) fn enabled() -> String {
+    let names = FEATURES
+        .iter()
+        .filter(|feature| feature.enabled)
+        .map(|feature| feature.name)
+        .collect::<Vec<_>>();
+
+    if names.is_empty() {
+        String::from("none")
+    } else {
+        names.join(", ")
+    }
+}
@@ -0,0 +1,44 @@
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
+
+use crate::features;
+
+pub(crate) const KERNEL_NAME: &str = "ChickenOS";
+pub(crate) const KERNEL_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short hash of the git commit the kernel has been built from, with a `-dirty` suffix if there were uncommitted changes. Set by the build script.
+pub(crate) const GIT_COMMIT: &str = env!("CHICKEN_GIT_COMMIT");
+/// UTC date and time of the build. Set by the build script.
+pub(crate) const BUILD_TIMESTAMP: &str = env!("CHICKEN_BUILD_TIMESTAMP");
+
+/// Identifies the exact build of the running kernel, e.g. for bug reports from real hardware.
+#[derive(Clone, Debug)]
+pub(crate) struct KernelInfo {
+    pub(crate) name: &'static str,
+    pub(crate) version: &'static str,
+    pub(crate) commit: &'static str,
+    pub(crate) build_timestamp: &'static str,
+    /// Names of the enabled optional features separated by commas.
+    pub(crate) features: String,
+}
+
+impl Display for KernelInfo {
+    /// Formats the info like `uname -a` as a single line.
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} {} (commit {}, built {}, features: {})",
+            self.name, self.version, self.commit, self.build_timestamp, self.features
+        )
+    }
+}
+
+/// Returns the version and build information of the kernel.
+pub(crate) fn kernel_info() -> KernelInfo {
+    KernelInfo {
+        name: KERNEL_NAME,
+        version: KERNEL_VERSION,
+        commit: GIT_COMMIT,
+        build_timestamp: BUILD_TIMESTAMP,
+        features: features::enabled(),
+    }
+}
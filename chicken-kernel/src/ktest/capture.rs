@@ -0,0 +1,47 @@
+use core::{
+    cell::OnceCell,
+    fmt::{Arguments, Write},
+};
+
+use crate::{
+    base::interrupts::without_interrupts, scheduling::spin::SpinLock, video::history::LogHistory,
+};
+
+/// Amount of console output in bytes captured for the running test.
+const CAPTURE_SIZE: usize = 16 * 1024; // 16 KiB
+
+/// Console output printed since the last test has started. Uninitialized until the self-tests start, so nothing is captured during boot.
+static CAPTURE: SpinLock<OnceCell<LogHistory>> = SpinLock::new(OnceCell::new());
+
+/// Starts capturing the console output. Must be called once the kernel heap is available.
+pub(super) fn start() {
+    without_interrupts(|| {
+        CAPTURE.lock().get_or_init(|| LogHistory::new(CAPTURE_SIZE));
+    });
+}
+
+/// Appends output to the capture, if it has been started. Called by the console with interrupts disabled, so it must not use the kernel heap.
+pub(crate) fn record(args: Arguments) {
+    if let Some(capture) = CAPTURE.lock().get_mut() {
+        let _ = capture.write_fmt(args);
+    }
+}
+
+/// Drops the captured output, e.g. before the next test starts.
+pub(super) fn clear() {
+    without_interrupts(|| {
+        if let Some(capture) = CAPTURE.lock().get_mut() {
+            capture.clear();
+        }
+    });
+}
+
+/// Returns whether the text has been printed onto the console since the capture has been cleared. Only the most recent output is kept, so older output may not be found.
+pub(super) fn printed(text: &str) -> bool {
+    without_interrupts(|| {
+        CAPTURE
+            .lock()
+            .get_mut()
+            .is_some_and(|capture| capture.contains(text))
+    })
+}
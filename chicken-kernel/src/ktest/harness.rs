@@ -0,0 +1,198 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::capture;
+use crate::{
+    base::{interrupts::without_interrupts, io::timer::pit::get_current_uptime_ms},
+    println,
+    scheduling::{spin::SpinLock, task, GlobalTaskScheduler},
+};
+
+/// Interval in ms in which the harness checks whether the running test has finished.
+const POLL_INTERVAL_MS: u64 = 10;
+/// Maximum amount of failed assertions reported per test.
+const MAX_REPORTED_FAILURES: usize = 8;
+
+/// Entry of the test that is currently running.
+static ACTIVE_TEST: SpinLock<Option<fn()>> = SpinLock::new(None);
+/// Whether the entry of the running test has returned.
+static TEST_RETURNED: AtomicBool = AtomicBool::new(false);
+/// Failed assertions of the running test.
+static FAILURES: SpinLock<Vec<String>> = SpinLock::new(Vec::new());
+
+/// Records a failed assertion if the condition is false and continues the test, so all failed assertions of a test are reported.
+#[macro_export]
+macro_rules! kassert {
+    ($condition:expr) => {
+        $crate::kassert!($condition, "{}", stringify!($condition))
+    };
+    ($condition:expr, $($arg:tt)+) => {
+        if !$condition {
+            $crate::ktest::harness::record_failure(file!(), line!(), format_args!($($arg)+));
+        }
+    };
+}
+
+/// Records a failed assertion if both values are not equal and continues the test.
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr) => {
+        match (&$left, &$right) {
+            (left, right) => $crate::kassert!(
+                *left == *right,
+                "{} == {} (left: {:?}, right: {:?})",
+                stringify!($left),
+                stringify!($right),
+                left,
+                right
+            ),
+        }
+    };
+}
+
+/// Result a test must have to pass.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Expectation {
+    /// The test returns without any failed assertion.
+    Pass,
+    /// The test raises a CPU exception, after which its process is killed by the exception handler.
+    Fault,
+}
+
+/// Test run in a separate process, so faults and timeouts only affect the test itself.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct KernelTest {
+    pub(super) name: &'static str,
+    pub(super) entry: fn(),
+    pub(super) expectation: Expectation,
+    /// Time in ms after which the process of the test is killed and the test fails.
+    pub(super) timeout_ms: u64,
+    /// Text that must be printed onto the console while the test runs, by the test or by the kernel, e.g. the report of an exception.
+    pub(super) output: Option<&'static str>,
+}
+
+#[derive(Clone, Debug)]
+enum Outcome {
+    Passed,
+    Failed(String),
+    TimedOut,
+}
+
+#[derive(Clone, Debug)]
+struct TestResult {
+    name: &'static str,
+    outcome: Outcome,
+    duration_ms: u64,
+}
+
+/// Runs the tests one after another and prints a summary table. Returns the amount of passed tests.
+pub(super) fn run_all(tests: &[KernelTest]) -> usize {
+    let results = tests.iter().map(run).collect::<Vec<_>>();
+
+    let name_width = tests.iter().map(|test| test.name.len()).max().unwrap_or(0);
+    println!(
+        "ktest: {:<width$}  RESULT    TIME",
+        "TEST",
+        width = name_width
+    );
+    for result in &results {
+        let outcome = match result.outcome {
+            Outcome::Passed => "ok",
+            Outcome::Failed(_) => "FAILED",
+            Outcome::TimedOut => "TIMEOUT",
+        };
+        println!(
+            "ktest: {:<width$}  {:<8}  {} ms",
+            result.name,
+            outcome,
+            result.duration_ms,
+            width = name_width
+        );
+    }
+
+    let passed = results
+        .iter()
+        .filter(|result| matches!(result.outcome, Outcome::Passed))
+        .count();
+    println!("ktest: {}/{} tests passed.", passed, results.len());
+    passed
+}
+
+/// Runs the test in a new process and waits until it has finished or timed out.
+fn run(test: &KernelTest) -> TestResult {
+    *ACTIVE_TEST.lock() = Some(test.entry);
+    TEST_RETURNED.store(false, Ordering::SeqCst);
+    without_interrupts(|| FAILURES.lock().clear());
+    capture::clear();
+
+    let start = get_current_uptime_ms();
+    let outcome = match task::spawn_process(run_active, Some(test.name.to_string())) {
+        Ok(pid) => wait(test, pid, start),
+        Err(err) => Outcome::Failed(err.to_string()),
+    };
+    let result = TestResult {
+        name: test.name,
+        outcome,
+        duration_ms: get_current_uptime_ms() - start,
+    };
+
+    match &result.outcome {
+        Outcome::Passed => println!("ktest: {} ... ok", test.name),
+        Outcome::Failed(reason) => println!("ktest: {} ... FAILED ({})", test.name, reason),
+        Outcome::TimedOut => println!(
+            "ktest: {} ... FAILED (timed out after {} ms)",
+            test.name, test.timeout_ms
+        ),
+    }
+    result
+}
+
+/// Waits for the process of the test to exit and evaluates the result. Kills it, if it exceeds the timeout.
+fn wait(test: &KernelTest, pid: u64, start: u64) -> Outcome {
+    while GlobalTaskScheduler::task_alive(pid) {
+        if get_current_uptime_ms() - start >= test.timeout_ms {
+            GlobalTaskScheduler::kill(pid);
+            return Outcome::TimedOut;
+        }
+        GlobalTaskScheduler::sleep(POLL_INTERVAL_MS);
+    }
+
+    let failures = without_interrupts(|| core::mem::take(&mut *FAILURES.lock()));
+    if !failures.is_empty() {
+        for failure in failures.iter().take(MAX_REPORTED_FAILURES) {
+            println!("ktest: {}: {}", test.name, failure);
+        }
+        return Outcome::Failed(format!("{} assertion(s) failed", failures.len()));
+    }
+    if let Some(output) = test.output.filter(|output| !capture::printed(output)) {
+        return Outcome::Failed(format!("did not print \"{}\"", output));
+    }
+
+    match (test.expectation, TEST_RETURNED.load(Ordering::SeqCst)) {
+        (Expectation::Pass, true) | (Expectation::Fault, false) => Outcome::Passed,
+        (Expectation::Pass, false) => Outcome::Failed("killed after an exception".to_string()),
+        (Expectation::Fault, true) => {
+            Outcome::Failed("returned without raising an exception".to_string())
+        }
+    }
+}
+
+/// Main thread of the process of a test. Runs the entry of the active test.
+fn run_active() {
+    let entry = *ACTIVE_TEST.lock();
+    if let Some(entry) = entry {
+        entry();
+        TEST_RETURNED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Records a failed assertion of the running test. Use [`kassert`] and [`kassert_eq`] instead.
+pub(crate) fn record_failure(file: &str, line: u32, message: core::fmt::Arguments) {
+    let failure = format!("{}:{}: {}", file, line, message);
+    // the test must not be preempted while holding the lock, since it may be killed afterward
+    without_interrupts(|| FAILURES.lock().push(failure));
+}
@@ -0,0 +1,1527 @@
+use alloc::{
+    alloc::{alloc, dealloc},
+    collections::VecDeque,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    alloc::Layout,
+    arch::asm,
+    hint::black_box,
+    iter,
+    ptr::{self, read_volatile, write_volatile},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use chicken_util::{
+    memory::{
+        paging::{PageEntryFlags, KERNEL_STACK_MAPPING_OFFSET},
+        pmm::audit::FramePurpose,
+        VirtualAddress,
+    },
+    PAGE_SIZE,
+};
+
+use crate::{
+    base::{
+        interrupts::without_interrupts,
+        io::{
+            keyboard::input::{InputEvent, Key, Modifiers},
+            timer::{hpet::nanoseconds_since_boot, pit::get_current_uptime_ms},
+        },
+        syscall::SyscallError,
+    },
+    config,
+    memory::{
+        address_space::AddressSpace,
+        direct_map::{phys_to_virt, virt_to_phys},
+        dma::DmaPool,
+        kheap::{
+            self, slab,
+            stats::{self, SIZE_CLASS_COUNT},
+        },
+        paging::PTM,
+        vmm::{object::VmFlags, AllocationType, VMM},
+    },
+    kassert, kassert_eq,
+    modules::{self, Module, ModuleError},
+    object::{self, KernelObject, ObjectRef, ObjectType},
+    println,
+    shell::{
+        editor::{complete_command, Edit, LineEditor},
+        slabinfo::slabinfo,
+    },
+    scheduling::{
+        hooks::{self, SwitchPhase, MAX_SWITCH_HOOKS},
+        latency,
+        rcu::Rcu,
+        spin::SpinLock,
+        sync::{condvar::Condvar, mutex::Mutex, rwlock::RwLock, semaphore::Semaphore},
+        task,
+        task::{
+            capability::Capabilities,
+            elf::{self, ElfError},
+            exec::{self, SpawnError},
+            process::ProcessExit,
+            startup::ProgramImage,
+            thread::Priority,
+        },
+        GlobalTaskScheduler, SchedulerError,
+    },
+};
+use harness::{Expectation, KernelTest};
+use program::{
+    PIE_POINTER, PIE_POINTER_TARGET, PIE_TLS_ALIGNMENT, PIE_TLS_DATA, PIE_TLS_SIZE, PROGRAM_BASE,
+    SHARED_DATA, SHARED_DATA_OFFSET,
+};
+
+pub(crate) mod capture;
+pub(crate) mod harness;
+mod program;
+
+/// Time in ms a faulting task gets to run, before it must have been killed.
+const FAULT_TIMEOUT_MS: u64 = 100;
+/// Time in ms the heap test gets to run.
+const HEAP_TIMEOUT_MS: u64 = 1000;
+/// Size of an allocation larger than the initial kernel heap, so the heap has to grow.
+const HEAP_EXPANSION_SIZE: usize = 2 * 1024 * 1024;
+/// Time in ms the suspend test gets to run after resuming.
+#[cfg(feature = "ktest-suspend")]
+const SUSPEND_TIMEOUT_MS: u64 = 10_000;
+
+/// Amount of processes spawned by the churn test. Each of them spawns and joins a thread.
+const CHURN_PROCESS_COUNT: usize = 256;
+/// Amount of processes of the churn test that are alive at the same time.
+const CHURN_BATCH_SIZE: usize = 16;
+/// Size in pages of the virtual memory object each churn task allocates.
+const CHURN_OBJECT_PAGES: usize = 4;
+/// Time in ms the scheduler gets to remove the exited churn processes, before leaked page frames are reported.
+const CHURN_SETTLE_MS: u64 = 1000;
+/// Time in ms the churn test gets to run.
+const CHURN_TIMEOUT_MS: u64 = 60_000;
+
+/// Amount of failed spawns and allocations inside the churn tasks.
+static CHURN_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Amount of busy processes competing for the cpu in the fairness test.
+const FAIRNESS_PROCESS_COUNT: usize = 4;
+/// Time in ms each busy process of the fairness test keeps running.
+const FAIRNESS_DURATION_MS: u64 = 500;
+/// Maximum ratio between the most and the least progress of the busy processes.
+const FAIRNESS_MAX_RATIO: u64 = 4;
+/// Time in ms the fairness test gets to run.
+const FAIRNESS_TIMEOUT_MS: u64 = 5000;
+
+/// Index assigned to the next busy process of the fairness test.
+static FAIRNESS_NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+/// Loop iterations completed by each busy process of the fairness test.
+static FAIRNESS_PROGRESS: [AtomicU64; FAIRNESS_PROCESS_COUNT] =
+    [const { AtomicU64::new(0) }; FAIRNESS_PROCESS_COUNT];
+
+/// Times the thread of the context switch hook test yields.
+const SWITCH_HOOK_YIELDS: usize = 10;
+/// Time in ms the context switch hook test gets to run.
+const SWITCH_HOOK_TIMEOUT_MS: u64 = 1000;
+/// Context switches seen by the hooks of the context switch hook test, before and after the address space has been switched.
+static SWITCHES_BEFORE: AtomicUsize = AtomicUsize::new(0);
+static SWITCHES_AFTER: AtomicUsize = AtomicUsize::new(0);
+/// Context switches the hooks have been called for, although the thread has not changed.
+static SWITCHES_TO_SELF: AtomicUsize = AtomicUsize::new(0);
+/// Tid of the next thread seen by the last hook before a context switch, and the switches the hook afterward has seen another one for.
+static SWITCH_NEXT_TID: AtomicU64 = AtomicU64::new(0);
+static SWITCHES_MISMATCHED: AtomicUsize = AtomicUsize::new(0);
+
+/// Pages of the object the lazy backing test allocates.
+const LAZY_PAGES: usize = 4;
+/// Time in ms the lazy backing test gets to run.
+const LAZY_TIMEOUT_MS: u64 = 1000;
+
+/// Loop iterations of the performance counter test.
+const PMC_ITERATIONS: u64 = 1_000_000;
+/// Time in ms the performance counter test gets to run.
+const PMC_TIMEOUT_MS: u64 = 5000;
+
+/// Page in the user half of the fork test process, that is shared copy-on-write with its child.
+const FORK_PAGE: VirtualAddress = 0x1000_0000;
+const FORK_PARENT_VALUE: u64 = 0xA5A5_A5A5;
+const FORK_CHILD_VALUE: u64 = 0x5A5A_5A5A;
+/// Time in ms the fork test gets to run.
+const FORK_TIMEOUT_MS: u64 = 1000;
+
+/// Value the child of the fork test has read from the shared page, before writing to it.
+static FORK_CHILD_READ: AtomicU64 = AtomicU64::new(0);
+
+/// Order of the block requested by the contiguous allocation test, the size of a huge page (2 MiB).
+const CONTIGUOUS_ORDER: usize = 9;
+/// Size in pages of the DMA buffers of the contiguous allocation test, which is not a power of two.
+const CONTIGUOUS_DMA_PAGES: usize = 3;
+/// Time in ms the contiguous allocation test gets to run.
+const CONTIGUOUS_TIMEOUT_MS: u64 = 1000;
+
+/// Time in ms the clock test sleeps, while the clock has to advance.
+const CLOCK_SLEEP_MS: u64 = 20;
+/// Amount of consecutive reads of the clock, none of which may be lower than the one before.
+const CLOCK_READS: usize = 10_000;
+/// Time in ms the clock test gets to run.
+const CLOCK_TIMEOUT_MS: u64 = 1000;
+
+/// Time in ms the read-copy-update test gets to run.
+const RCU_TIMEOUT_MS: u64 = 1000;
+
+/// Time in ms the priority test gets to run.
+const PRIORITY_TIMEOUT_MS: u64 = 1000;
+/// Order in which the threads of the priority test have run.
+static PRIORITY_ORDER: AtomicUsize = AtomicUsize::new(0);
+
+/// Time in ms the join test gets to run.
+const JOIN_TIMEOUT_MS: u64 = 1000;
+/// Exit status of the threads, that exit early in the join test.
+const JOIN_EXIT_STATUS: i64 = -42;
+
+/// Time in ms the line editor test gets to run.
+const LINE_EDITOR_TIMEOUT_MS: u64 = 1000;
+/// Commands completed by the line editor test.
+const LINE_EDITOR_COMMANDS: [&str; 3] = ["help", "hello", "halt"];
+
+/// Time in ms the kernel image test gets to run.
+const KERNEL_IMAGE_TIMEOUT_MS: u64 = 1000;
+/// Read-only data of the kernel image, whose mapping the kernel image test checks.
+static KERNEL_IMAGE_RODATA: [u8; 8] = *b"rodata!!";
+
+/// Amount of threads competing for the locks in the blocking lock test.
+const SYNC_THREAD_COUNT: usize = 4;
+/// Times each thread of the blocking lock test acquires each lock.
+const SYNC_ITERATIONS: u64 = 50;
+/// Time in ms the blocking lock test gets to run.
+const SYNC_TIMEOUT_MS: u64 = 5000;
+/// Counter incremented by the threads of the blocking lock test, which yield while holding the mutex.
+static SYNC_COUNTER: Mutex<u64> = Mutex::new(0);
+/// Pair of values the writers of the blocking lock test keep equal, while the readers check that they never see them differ.
+static SYNC_PAIR: RwLock<(u64, u64)> = RwLock::new((0, 0));
+/// Times a reader of the blocking lock test has seen a half written pair.
+static SYNC_TORN_READS: AtomicUsize = AtomicUsize::new(0);
+
+/// Amount of consumer threads of the semaphore and condition variable test.
+const SIGNAL_CONSUMER_COUNT: usize = 4;
+/// Consumers of the semaphore and condition variable test that may process an item at the same time.
+const SIGNAL_PERMITS: usize = 2;
+/// Items passed to the consumers of the semaphore and condition variable test, numbered from 1 on.
+const SIGNAL_ITEMS: u64 = 100;
+/// Time in ms the semaphore and condition variable test gets to run.
+const SIGNAL_TIMEOUT_MS: u64 = 5000;
+/// Queue of the semaphore and condition variable test, `0` tells a consumer to return.
+static SIGNAL_QUEUE: Mutex<VecDeque<u64>> = Mutex::new(VecDeque::new());
+/// Notified whenever an item has been added to the queue of the semaphore and condition variable test.
+static SIGNAL_ARRIVED: Condvar = Condvar::new();
+/// Limits the consumers of the semaphore and condition variable test processing an item at the same time.
+static SIGNAL_SLOTS: Semaphore = Semaphore::new(SIGNAL_PERMITS);
+/// Consumers of the semaphore and condition variable test processing an item right now, and the most there have been at once.
+static SIGNAL_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+static SIGNAL_MAX_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+/// Sum of the items processed by the consumers of the semaphore and condition variable test.
+static SIGNAL_SUM: AtomicU64 = AtomicU64::new(0);
+
+/// Amount of threads cloning and dropping references in the kernel object test.
+const OBJECT_THREAD_COUNT: usize = 4;
+/// References each thread of the kernel object test clones and drops.
+const OBJECT_ITERATIONS: usize = 1000;
+/// Time in ms the kernel object test gets to run.
+const OBJECT_TIMEOUT_MS: u64 = 5000;
+/// Times the destroy hook of the channel of the kernel object test has run.
+static OBJECT_DESTROYED: AtomicUsize = AtomicUsize::new(0);
+/// Object shared by the threads of the kernel object test.
+static OBJECT_SHARED: SpinLock<Option<ObjectRef<TestChannel>>> = SpinLock::new(None);
+
+/// Channel of the kernel object test, which counts how often it has been destroyed.
+#[derive(Debug)]
+struct TestChannel(u64);
+
+impl KernelObject for TestChannel {
+    const TYPE: ObjectType = ObjectType::Channel;
+
+    fn destroy(&mut self) {
+        OBJECT_DESTROYED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// File of the kernel object test, which a channel must not be downcast to.
+#[derive(Debug)]
+struct TestFile;
+
+impl KernelObject for TestFile {
+    const TYPE: ObjectType = ObjectType::File;
+}
+
+/// Time in ms the allocator statistics test gets to run.
+const ALLOCATOR_STATS_TIMEOUT_MS: u64 = 1000;
+/// Size in bytes of the blocks the allocator statistics test allocates, counted in the size class of 128 bytes.
+const ALLOCATOR_STATS_SIZE: usize = 100;
+/// Amount of blocks the allocator statistics test allocates.
+const ALLOCATOR_STATS_COUNT: u64 = 16;
+/// Slabs the allocator statistics test has each cache preallocate.
+const ALLOCATOR_STATS_SLABS: usize = 2;
+
+/// Name of the module the spawn test registers the echo program as.
+const SPAWN_PROGRAM: &str = "ktest/echo";
+/// Argument the echo program of the spawn test prints.
+const SPAWN_OUTPUT: &str = "ktest: echoed by a user program";
+/// Error codes spawns of missing programs and spawns without the capability are refused with.
+const ENOENT: u64 = 2;
+const EPERM: u64 = 1;
+/// Time in ms the spawn test gets to run.
+const SPAWN_TIMEOUT_MS: u64 = 1000;
+/// Error code the process of the spawn test without the capability to spawn programs has been refused with.
+static SPAWN_REFUSED_CODE: AtomicU64 = AtomicU64::new(0);
+
+/// Time in ms each program loader test gets to run.
+const LOADER_TIMEOUT_MS: u64 = 1000;
+
+/// Kernel self-tests, run in the listed order.
+const TESTS: [KernelTest; 25] = [
+    fault_test("KTEST-DIV-BY-0", divide_by_zero, "exception: DIV BY 0"),
+    fault_test("KTEST-PAGE-FAULT", page_fault, "exception: PAGE FAULT"),
+    fault_test("KTEST-GP-FAULT", general_protection_fault, "exception: GENERAL PROTECTION FAULT"),
+    fault_test("KTEST-INVALID-OPCODE", invalid_opcode, "exception: INVALID OPCODE"),
+    KernelTest {
+        name: "KTEST-PROCESS-CHURN",
+        entry: process_churn,
+        expectation: Expectation::Pass,
+        timeout_ms: CHURN_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-FAIRNESS",
+        entry: fairness,
+        expectation: Expectation::Pass,
+        timeout_ms: FAIRNESS_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-HEAP",
+        entry: heap_allocations,
+        expectation: Expectation::Pass,
+        timeout_ms: HEAP_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-SWITCH-HOOKS",
+        entry: switch_hooks,
+        expectation: Expectation::Pass,
+        timeout_ms: SWITCH_HOOK_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-LAZY",
+        entry: lazy_backing,
+        expectation: Expectation::Pass,
+        timeout_ms: LAZY_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-PMC",
+        entry: performance_counters,
+        expectation: Expectation::Pass,
+        timeout_ms: PMC_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-FORK",
+        entry: fork,
+        expectation: Expectation::Pass,
+        timeout_ms: FORK_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-CONTIGUOUS",
+        entry: contiguous_allocations,
+        expectation: Expectation::Pass,
+        timeout_ms: CONTIGUOUS_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-CLOCK",
+        entry: clock,
+        expectation: Expectation::Pass,
+        timeout_ms: CLOCK_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-RCU",
+        entry: read_copy_update,
+        expectation: Expectation::Pass,
+        timeout_ms: RCU_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-PRIORITY",
+        entry: priorities,
+        expectation: Expectation::Pass,
+        timeout_ms: PRIORITY_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-JOIN",
+        entry: join,
+        expectation: Expectation::Pass,
+        timeout_ms: JOIN_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-LINE-EDITOR",
+        entry: line_editor,
+        expectation: Expectation::Pass,
+        timeout_ms: LINE_EDITOR_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-KERNEL-IMAGE",
+        entry: kernel_image,
+        expectation: Expectation::Pass,
+        timeout_ms: KERNEL_IMAGE_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-SYNC",
+        entry: blocking_locks,
+        expectation: Expectation::Pass,
+        timeout_ms: SYNC_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-SIGNAL",
+        entry: signalling,
+        expectation: Expectation::Pass,
+        timeout_ms: SIGNAL_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-KERNEL-OBJECT",
+        entry: kernel_object,
+        expectation: Expectation::Pass,
+        timeout_ms: OBJECT_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-ALLOCATOR-STATS",
+        entry: allocator_statistics,
+        expectation: Expectation::Pass,
+        timeout_ms: ALLOCATOR_STATS_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-SPAWN",
+        entry: spawn_program,
+        expectation: Expectation::Pass,
+        timeout_ms: SPAWN_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-ELF-SEGMENTS",
+        entry: elf_segments,
+        expectation: Expectation::Pass,
+        timeout_ms: LOADER_TIMEOUT_MS,
+        output: None,
+    },
+    KernelTest {
+        name: "KTEST-ELF-PIE",
+        entry: position_independent_program,
+        expectation: Expectation::Pass,
+        timeout_ms: LOADER_TIMEOUT_MS,
+        output: None,
+    },
+];
+
+/// Test that deliberately raises a CPU exception, which the exception handler must report with the given output.
+const fn fault_test(name: &'static str, entry: fn(), output: &'static str) -> KernelTest {
+    KernelTest {
+        name,
+        entry,
+        expectation: Expectation::Fault,
+        timeout_ms: FAULT_TIMEOUT_MS,
+        output: Some(output),
+    }
+}
+
+/// Runs the kernel self-tests, each in a separate process, and checks that faulting tests only kill their own process, while the kernel and this task keep running.
+pub(crate) fn run() {
+    #[allow(unused_mut)]
+    let mut tests = TESTS.to_vec();
+    #[cfg(feature = "ktest-suspend")]
+    tests.push(KernelTest {
+        name: "KTEST-SUSPEND",
+        entry: suspend_to_ram,
+        expectation: Expectation::Pass,
+        // the timer does not advance while the system is suspended, so this only covers resuming
+        timeout_ms: SUSPEND_TIMEOUT_MS,
+        output: None,
+    });
+
+    capture::start();
+    println!("ktest: Running {} tests.", tests.len());
+    harness::run_all(&tests);
+    // the self-tests are the closest to an orderly shutdown so far
+    #[cfg(feature = "leak-check")]
+    crate::memory::accounting::report();
+}
+
+/// Allocates heap memory of different sizes and alignments and checks that it is usable and aligned as requested. The last allocation does not fit into the initial heap, so the heap has to grow.
+fn heap_allocations() {
+    for size in [1, 7, 64, PAGE_SIZE, 16 * PAGE_SIZE] {
+        let buffer = vec![0xA5u8; size];
+        kassert_eq!(buffer.len(), size);
+        kassert!(
+            buffer.iter().all(|byte| *byte == 0xA5),
+            "buffer of {} bytes does not hold the written value",
+            size
+        );
+    }
+
+    for alignment in [8, 64, PAGE_SIZE] {
+        let Ok(layout) = Layout::from_size_align(alignment, alignment) else {
+            continue;
+        };
+        let address = unsafe { alloc(layout) };
+        kassert!(!address.is_null(), "allocation aligned to {} bytes failed", alignment);
+        if !address.is_null() {
+            kassert_eq!(address as usize % alignment, 0);
+            unsafe { dealloc(address, layout) };
+        }
+    }
+
+    let strings = (0..64).map(|index| index.to_string()).collect::<Vec<_>>();
+    kassert_eq!(strings.concat().len(), 10 + 2 * 54);
+
+    let large = vec![0x5Au8; HEAP_EXPANSION_SIZE];
+    kassert!(
+        large.iter().all(|byte| *byte == 0x5A),
+        "buffer of {} bytes beyond the initial heap does not hold the written value",
+        HEAP_EXPANSION_SIZE
+    );
+}
+
+/// Runs a loop of known length and checks that the instructions retired by the thread are counted across the context switches in between. Passes without checking anything, unless the counters are enabled with `pmc=on`.
+fn performance_counters() {
+    let Some(before) = GlobalTaskScheduler::active_thread_counters() else {
+        println!("ktest: Performance counters are disabled, skipping.");
+        return;
+    };
+    let mut sum = 0u64;
+    for index in 0..PMC_ITERATIONS {
+        sum = black_box(sum.wrapping_add(index));
+    }
+    let after = GlobalTaskScheduler::active_thread_counters();
+    kassert!(after.is_some(), "performance counters have been disabled meanwhile");
+    let Some(after) = after else {
+        return;
+    };
+
+    let elapsed = after.since(&before);
+    kassert!(
+        elapsed.instructions >= PMC_ITERATIONS,
+        "only {} instructions counted for {} iterations",
+        elapsed.instructions,
+        PMC_ITERATIONS
+    );
+    kassert!(elapsed.cycles > 0, "no cycles counted");
+    println!("ktest: Performance counters: {}", elapsed);
+}
+
+/// Checks that the monotonic clock never decreases and advances while the thread sleeps. The sleep is measured by the timer ticks, which may drift from the HPET slightly.
+fn clock() {
+    let mut last = nanoseconds_since_boot();
+    for _ in 0..CLOCK_READS {
+        let now = nanoseconds_since_boot();
+        kassert!(now >= last, "clock went back from {} ns to {} ns", last, now);
+        last = now;
+    }
+
+    let start = nanoseconds_since_boot();
+    GlobalTaskScheduler::sleep(CLOCK_SLEEP_MS);
+    let elapsed_ms = (nanoseconds_since_boot() - start) / 1_000_000;
+    kassert!(
+        elapsed_ms >= CLOCK_SLEEP_MS / 2,
+        "clock advanced by {} ms during a sleep of {} ms",
+        elapsed_ms,
+        CLOCK_SLEEP_MS
+    );
+}
+
+/// Checks that a snapshot of a read-copy-update cell is not modified by updates made while it is held, and that the test process is listed in the process snapshot of the scheduler.
+fn read_copy_update() {
+    let cell = Rcu::new(Vec::new());
+    cell.update(|values| values.push(1));
+    let snapshot = cell.read();
+    cell.update(|values| values.push(2));
+    kassert_eq!(*snapshot, [1]);
+    kassert_eq!(*cell.read(), [1, 2]);
+    drop(snapshot);
+    // frees the snapshot above, once its grace period has passed
+    cell.update(|values| values.clear());
+    kassert!(cell.read().is_empty(), "update after the snapshot has been dropped is lost");
+
+    let pid = GlobalTaskScheduler::current_pid();
+    kassert!(
+        GlobalTaskScheduler::processes()
+            .iter()
+            .any(|process| Some(process.pid) == pid && process.name == "KTEST-RCU"),
+        "process snapshot lacks the test process with pid {:?}",
+        pid
+    );
+}
+
+/// Spawns a low priority thread before a high priority one and checks that the latter runs first, while the former still runs, although the test keeps yielding at normal priority.
+fn priorities() {
+    let low = task::spawn_thread_with_priority(priority_thread, None, Priority::Low).unwrap();
+    let high = task::spawn_thread_with_priority(priority_thread, None, Priority::High).unwrap();
+    let (high, low) = (high.join().unwrap(), low.join().unwrap());
+    kassert!(
+        high < low,
+        "high priority thread ran as {}. after low priority thread ran as {}.",
+        high,
+        low
+    );
+}
+
+/// Returns the position in which it has run.
+fn priority_thread() -> usize {
+    PRIORITY_ORDER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Waits for a thread, that exits with an exit code, and for one, that returns, while blocked.
+fn join() {
+    let exiting = task::spawn_thread(exiting_thread, None).unwrap();
+    kassert_eq!(exiting.wait().unwrap(), JOIN_EXIT_STATUS);
+    let returning = task::spawn_thread(priority_thread, None).unwrap();
+    kassert_eq!(returning.wait().unwrap(), 0);
+
+    // the exit status replaces the return value of threads returning an i64 only
+    let exiting = task::spawn_thread(exiting_status_thread, None).unwrap();
+    kassert_eq!(exiting.join().unwrap(), JOIN_EXIT_STATUS);
+    let exiting = task::spawn_thread(exiting_thread, None).unwrap();
+    let result = exiting.join();
+    kassert!(
+        matches!(result, Err(SchedulerError::ThreadExited(_, _, JOIN_EXIT_STATUS))),
+        "joining a thread, that has exited without a value, returned {:?}",
+        result
+    );
+}
+
+/// Exits with the exit status of the join test, before returning.
+fn exiting_thread() {
+    task::exit(JOIN_EXIT_STATUS)
+}
+
+/// Exits with the exit status of the join test, before returning its own status.
+fn exiting_status_thread() -> i64 {
+    task::exit(JOIN_EXIT_STATUS)
+}
+
+/// Completes a command in the line editor, enters it and recalls it from the history with up and with an incremental search.
+fn line_editor() {
+    let mut editor = LineEditor::new(|line| complete_command(&LINE_EDITOR_COMMANDS, line));
+    let type_text = |editor: &mut LineEditor, text: &str| {
+        text.chars()
+            .for_each(|character| kassert_eq!(editor.handle(key(Key::Char(character))), Edit::Pending))
+    };
+
+    type_text(&mut editor, "he");
+    // completed up to the common prefix first, then listed
+    editor.handle(key(Key::Tab));
+    kassert_eq!(editor.line(), "hel");
+    kassert_eq!(
+        editor.handle(key(Key::Tab)),
+        Edit::Candidates(vec!["help".to_string(), "hello".to_string()])
+    );
+    type_text(&mut editor, "p");
+    editor.handle(key(Key::Tab));
+    kassert_eq!(editor.handle(key(Key::Enter)), Edit::Submit("help ".to_string()));
+
+    type_text(&mut editor, "x");
+    editor.handle(key(Key::Up));
+    kassert_eq!(editor.line(), "help ");
+    editor.handle(key(Key::Down));
+    kassert_eq!(editor.line(), "x");
+
+    editor.handle(key(Key::Home));
+    editor.handle(key(Key::Delete));
+    editor.handle(InputEvent {
+        key: Key::Char('r'),
+        modifiers: Modifiers::CONTROL,
+    });
+    type_text(&mut editor, "el");
+    kassert_eq!(
+        editor.search_prompt(),
+        Some("(reverse-i-search)`el': help ".to_string())
+    );
+    kassert_eq!(editor.handle(key(Key::Enter)), Edit::Submit("help ".to_string()));
+}
+
+/// Key press without modifiers.
+fn key(key: Key) -> InputEvent {
+    InputEvent {
+        key,
+        modifiers: Modifiers::empty(),
+    }
+}
+
+/// Checks that the code, read-only data and writable data of the kernel image are mapped with the permissions of their segments, and that the boot stack has been unmapped.
+fn kernel_image() {
+    let code = kernel_image as fn() as usize as VirtualAddress;
+    let rodata = KERNEL_IMAGE_RODATA.as_ptr() as VirtualAddress;
+    let data = ptr::addr_of!(PRIORITY_ORDER) as VirtualAddress;
+    // (name, address, writable, executable)
+    let pages = [
+        ("code", code, false, true),
+        ("rodata", rodata, false, false),
+        ("data", data, true, false),
+    ];
+    for (name, address, writable, executable) in pages {
+        let flags = without_interrupts(|| {
+            let mut binding = PTM.lock();
+            let entry = binding.get_mut()?.page_entry_mut(address)?;
+            Some((entry.flags(), entry.execute_disabled()))
+        });
+        kassert!(flags.is_some(), "{} at {:#x} is not mapped", name, address);
+        let Some((flags, execute_disabled)) = flags else {
+            continue;
+        };
+        kassert!(
+            flags.contains(PageEntryFlags::READ_WRITE) == writable,
+            "{} at {:#x} is writable: {}",
+            name,
+            address,
+            !writable
+        );
+        kassert!(
+            execute_disabled != executable,
+            "{} at {:#x} is executable: {}",
+            name,
+            address,
+            !executable
+        );
+    }
+
+    let stack_mapped = without_interrupts(|| {
+        PTM.lock()
+            .get()
+            .and_then(|ptm| ptm.get_physical(KERNEL_STACK_MAPPING_OFFSET))
+    });
+    kassert!(stack_mapped.is_none(), "boot stack is still mapped");
+}
+
+/// Lets threads, that yield while holding them, compete for a mutex and a read-write lock. Waiting threads block, so the holders get to run and every increment and write is complete. Checks that a held mutex can not be taken without blocking.
+fn blocking_locks() {
+    *SYNC_COUNTER.lock() = 0;
+    *SYNC_PAIR.write() = (0, 0);
+    SYNC_TORN_READS.store(0, Ordering::SeqCst);
+
+    let handles: Vec<_> = (0..SYNC_THREAD_COUNT)
+        .map(|index| {
+            let entry = if index % 2 == 0 {
+                sync_writer
+            } else {
+                sync_reader
+            };
+            task::spawn_thread(entry, None)
+        })
+        .collect();
+    for handle in handles {
+        kassert!(handle.is_ok(), "{:?}", handle.as_ref().err());
+        if let Ok(handle) = handle {
+            kassert!(
+                handle.join().is_ok(),
+                "blocking lock thread has not returned"
+            );
+        }
+    }
+
+    kassert_eq!(
+        *SYNC_COUNTER.lock(),
+        SYNC_THREAD_COUNT as u64 * SYNC_ITERATIONS
+    );
+    let writers = SYNC_THREAD_COUNT.div_ceil(2) as u64;
+    kassert_eq!(
+        *SYNC_PAIR.read(),
+        (writers * SYNC_ITERATIONS, writers * SYNC_ITERATIONS)
+    );
+    kassert_eq!(SYNC_TORN_READS.load(Ordering::SeqCst), 0);
+
+    let guard = SYNC_COUNTER.try_lock();
+    kassert!(guard.is_some(), "free mutex could not be locked");
+    kassert!(
+        SYNC_COUNTER.try_lock().is_none(),
+        "held mutex has been locked"
+    );
+}
+
+/// Increments the counter and writes the pair of the blocking lock test, yielding halfway through each.
+fn sync_writer() {
+    for _ in 0..SYNC_ITERATIONS {
+        sync_increment();
+        let mut pair = SYNC_PAIR.write();
+        pair.0 += 1;
+        GlobalTaskScheduler::yield_now();
+        pair.1 += 1;
+    }
+}
+
+/// Increments the counter and reads the pair of the blocking lock test, yielding halfway through each.
+fn sync_reader() {
+    for _ in 0..SYNC_ITERATIONS {
+        sync_increment();
+        let pair = SYNC_PAIR.read();
+        let first = pair.0;
+        GlobalTaskScheduler::yield_now();
+        if pair.1 != first {
+            SYNC_TORN_READS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Increments the counter of the blocking lock test in two steps, between which other threads run.
+fn sync_increment() {
+    let mut counter = SYNC_COUNTER.lock();
+    let value = *counter;
+    GlobalTaskScheduler::yield_now();
+    *counter = value + 1;
+}
+
+/// Passes items to consumer threads, which sleep on a condition variable until one arrives and process it while holding a permit of a semaphore. Checks that every item is processed once, that no more consumers than permits process one at a time and that all permits are returned.
+fn signalling() {
+    SIGNAL_QUEUE.lock().clear();
+    SIGNAL_ACTIVE.store(0, Ordering::SeqCst);
+    SIGNAL_MAX_ACTIVE.store(0, Ordering::SeqCst);
+    SIGNAL_SUM.store(0, Ordering::SeqCst);
+
+    let handles: Vec<_> = (0..SIGNAL_CONSUMER_COUNT)
+        .map(|_| task::spawn_thread(signal_consumer, None))
+        .collect();
+    for item in 1..=SIGNAL_ITEMS {
+        SIGNAL_QUEUE.lock().push_back(item);
+        SIGNAL_ARRIVED.notify_one();
+        if item % 8 == 0 {
+            GlobalTaskScheduler::yield_now();
+        }
+    }
+    SIGNAL_QUEUE
+        .lock()
+        .extend(iter::repeat_n(0, SIGNAL_CONSUMER_COUNT));
+    SIGNAL_ARRIVED.notify_all();
+
+    for handle in handles {
+        kassert!(handle.is_ok(), "{:?}", handle.as_ref().err());
+        if let Ok(handle) = handle {
+            kassert!(handle.join().is_ok(), "consumer thread has not returned");
+        }
+    }
+    kassert_eq!(
+        SIGNAL_SUM.load(Ordering::SeqCst),
+        SIGNAL_ITEMS * (SIGNAL_ITEMS + 1) / 2
+    );
+    let max_active = SIGNAL_MAX_ACTIVE.load(Ordering::SeqCst);
+    kassert!(
+        max_active <= SIGNAL_PERMITS,
+        "{} consumers at once",
+        max_active
+    );
+    kassert_eq!(SIGNAL_SLOTS.available(), SIGNAL_PERMITS);
+    kassert!(SIGNAL_QUEUE.lock().is_empty());
+
+    let taken = iter::from_fn(|| SIGNAL_SLOTS.try_acquire().then_some(())).count();
+    kassert_eq!(taken, SIGNAL_PERMITS);
+    for _ in 0..taken {
+        SIGNAL_SLOTS.release();
+    }
+}
+
+/// Takes items from the queue of the semaphore and condition variable test until it is told to return, sleeping while the queue is empty.
+fn signal_consumer() {
+    loop {
+        let item = SIGNAL_ARRIVED
+            .wait_while(SIGNAL_QUEUE.lock(), |queue| queue.is_empty())
+            .pop_front();
+        let Some(item @ 1..) = item else {
+            return;
+        };
+
+        SIGNAL_SLOTS.acquire();
+        let active = SIGNAL_ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+        SIGNAL_MAX_ACTIVE.fetch_max(active, Ordering::SeqCst);
+        GlobalTaskScheduler::yield_now();
+        SIGNAL_ACTIVE.fetch_sub(1, Ordering::SeqCst);
+        SIGNAL_SLOTS.release();
+        SIGNAL_SUM.fetch_add(item, Ordering::SeqCst);
+    }
+}
+
+/// Clones and drops references to a kernel object on several threads and checks that they point to the same object, passes it through a reference of unknown type and checks that it is destroyed and freed exactly once, after the last reference has been dropped.
+fn kernel_object() {
+    OBJECT_DESTROYED.store(0, Ordering::SeqCst);
+    let live = object::live_objects(ObjectType::Channel);
+
+    let channel = ObjectRef::new(TestChannel(42));
+    kassert_eq!(object::live_objects(ObjectType::Channel), live + 1);
+    without_interrupts(|| *OBJECT_SHARED.lock() = Some(channel.clone()));
+    let handles: Vec<_> = (0..OBJECT_THREAD_COUNT)
+        .map(|_| task::spawn_thread(object_churn, None))
+        .collect();
+    for handle in handles {
+        kassert!(handle.is_ok(), "{:?}", handle.as_ref().err());
+        if let Ok(handle) = handle {
+            kassert!(
+                handle.join().is_ok(),
+                "kernel object thread has not returned"
+            );
+        }
+    }
+    let shared = without_interrupts(|| OBJECT_SHARED.lock().take());
+    kassert!(shared.is_some_and(|shared| ObjectRef::ptr_eq(&shared, &channel)));
+    kassert_eq!(ObjectRef::references(&channel), 1);
+
+    let any = ObjectRef::into_any(channel);
+    kassert_eq!(any.object_type(), ObjectType::Channel);
+    let handle_table_entry = any.clone();
+    kassert_eq!(any.references(), 2);
+    let any = match any.downcast::<TestFile>() {
+        Ok(_) => {
+            kassert!(false, "channel has been downcast to a file");
+            return;
+        }
+        Err(any) => any,
+    };
+    let Ok(channel) = any.downcast::<TestChannel>() else {
+        kassert!(false, "channel could not be downcast to its own type");
+        return;
+    };
+    kassert_eq!(channel.0, 42);
+
+    drop(channel);
+    kassert_eq!(OBJECT_DESTROYED.load(Ordering::SeqCst), 0);
+    kassert_eq!(handle_table_entry.references(), 1);
+    drop(handle_table_entry);
+    kassert_eq!(OBJECT_DESTROYED.load(Ordering::SeqCst), 1);
+    kassert_eq!(object::live_objects(ObjectType::Channel), live);
+}
+
+/// Clones and drops references to the object shared by the kernel object test.
+fn object_churn() {
+    let Some(shared) = without_interrupts(|| OBJECT_SHARED.lock().clone()) else {
+        return;
+    };
+    for _ in 0..OBJECT_ITERATIONS {
+        let reference = black_box(shared.clone());
+        kassert!(ObjectRef::references(&reference) >= 2);
+        GlobalTaskScheduler::yield_now();
+    }
+}
+
+/// Allocates blocks of a single size class and checks that the heap counts them, then has the slab caches preallocate slabs through the config registry and checks that `slabinfo` lists them.
+fn allocator_statistics() {
+    let class = (0..SIZE_CLASS_COUNT)
+        .find(|&index| stats::class_size(index).is_some_and(|size| size >= ALLOCATOR_STATS_SIZE));
+    let counters = |class| kheap::statistics().map(|heap| heap.size_classes[class]);
+    let (Some(class), Some(before)) = (class, class.and_then(counters)) else {
+        kassert!(false, "kernel heap statistics unavailable");
+        return;
+    };
+
+    let blocks: Vec<_> = (0..ALLOCATOR_STATS_COUNT)
+        .map(|_| black_box(vec![0u8; ALLOCATOR_STATS_SIZE]))
+        .collect();
+    let allocated = counters(class).unwrap_or_default();
+    drop(blocks);
+    let freed = counters(class).unwrap_or_default();
+    // other threads may allocate in the same size class meanwhile
+    kassert!(allocated.allocations >= before.allocations + ALLOCATOR_STATS_COUNT);
+    kassert!(freed.frees >= before.frees + ALLOCATOR_STATS_COUNT);
+    kassert!(allocated.hit_rate_percent().is_some());
+
+    let set = config::set("slab_prealloc", &ALLOCATOR_STATS_SLABS.to_string());
+    kassert!(set.is_ok(), "{:?}", set.err());
+    for cache in slab::statistics() {
+        kassert!(
+            cache.slabs >= ALLOCATOR_STATS_SLABS,
+            "{} has {} slabs",
+            cache.name,
+            cache.slabs
+        );
+    }
+    kassert!(config::reset("slab_prealloc").is_ok());
+
+    let mut info = String::new();
+    kassert!(slabinfo(&mut info).is_ok());
+    kassert!(
+        info.contains("process") && info.contains("kernel heap:"),
+        "{}",
+        info
+    );
+}
+
+/// Spawns a user program embedded as a module and checks that it prints its argument and exits with the amount of its arguments. Checks that missing programs and processes without the capability to spawn programs are refused with the matching error codes.
+fn spawn_program() {
+    let registered = modules::register(Module {
+        name: SPAWN_PROGRAM,
+        data: program::echo().leak(),
+    });
+    kassert!(registered.is_ok(), "{:?}", registered.err());
+
+    let process = exec::spawn(
+        &format!("/{}", SPAWN_PROGRAM),
+        &[SPAWN_PROGRAM, SPAWN_OUTPUT],
+    );
+    kassert!(process.is_ok(), "{:?}", process.as_ref().err());
+    let Ok(process) = process else {
+        return;
+    };
+    kassert!(process.pid() != 0 && Some(process.pid()) != GlobalTaskScheduler::current_pid());
+    while process.exit().is_none() {
+        GlobalTaskScheduler::yield_now();
+    }
+    kassert_eq!(process.exit(), Some(ProcessExit::Exited(2)));
+    kassert!(
+        capture::printed(SPAWN_OUTPUT),
+        "echo program has not printed its argument"
+    );
+
+    let missing = exec::spawn("/ktest/missing", &[]);
+    kassert!(
+        matches!(
+            missing,
+            Err(SpawnError::ProgramUnavailable(ModuleError::ModuleNotFound))
+        ),
+        "{:?}",
+        missing.as_ref().err()
+    );
+    if let Err(err) = missing {
+        kassert_eq!(SyscallError::SpawnFailed(err).code(), ENOENT);
+    }
+
+    SPAWN_REFUSED_CODE.store(0, Ordering::SeqCst);
+    let pid = task::spawn_restricted_process(
+        spawn_without_capability,
+        Some("KTEST-SPAWN-REFUSED".to_string()),
+        Capabilities::empty(),
+    );
+    kassert!(pid.is_ok(), "{:?}", pid);
+    let Ok(pid) = pid else {
+        return;
+    };
+    while GlobalTaskScheduler::task_alive(pid) {
+        GlobalTaskScheduler::yield_now();
+    }
+    kassert_eq!(SPAWN_REFUSED_CODE.load(Ordering::SeqCst), EPERM);
+}
+
+/// Main thread of the process of the spawn test without the capability to spawn programs. Records the error code its spawn has been refused with.
+fn spawn_without_capability() {
+    let code = match exec::spawn(&format!("/{}", SPAWN_PROGRAM), &[SPAWN_PROGRAM]) {
+        Ok(_) => u64::MAX,
+        Err(err) => SyscallError::SpawnFailed(err).code(),
+    };
+    SPAWN_REFUSED_CODE.store(code, Ordering::SeqCst);
+}
+
+/// Loads a program, whose code and data segments share a page, into a new address space and checks that the shared page gets the permissions of both segments, while the rest of the data segment stays non-executable. Checks that a program overlapping pages the loader has not mapped itself is refused.
+fn elf_segments() {
+    let address_space = AddressSpace::create();
+    kassert!(address_space.is_ok(), "{:?}", address_space.as_ref().err());
+    let Ok(address_space) = address_space else {
+        return;
+    };
+
+    let image = elf::load(&address_space, &program::shared_page());
+    kassert!(image.is_ok(), "{:?}", image.as_ref().err());
+    kassert!(image.is_ok_and(|image| image.entry == PROGRAM_BASE && image.thread_pointer.is_none()));
+    kassert_eq!(
+        user_permissions(&address_space, PROGRAM_BASE),
+        Some((true, true))
+    );
+    kassert_eq!(
+        user_permissions(&address_space, PROGRAM_BASE + PAGE_SIZE as u64),
+        Some((true, false))
+    );
+    kassert_eq!(
+        read_user_u64(&address_space, PROGRAM_BASE + SHARED_DATA_OFFSET),
+        Some(u64::from_le_bytes(SHARED_DATA))
+    );
+
+    // the page of the code segment has been mapped by the first program
+    let overlapping = elf::load(&address_space, &program::echo());
+    kassert!(
+        matches!(overlapping, Err(ElfError::OverlappingSegment(PROGRAM_BASE))),
+        "{:?}",
+        overlapping.as_ref().err()
+    );
+    kassert_eq!(
+        user_permissions(&address_space, PROGRAM_BASE),
+        Some((true, true))
+    );
+
+    let freed = address_space.free();
+    kassert!(freed.is_ok(), "{:?}", freed);
+}
+
+/// Loads a position independent program into two address spaces and checks that it is placed at different addresses, its pointer is relocated by the load bias and its TLS block is laid out as the System V ABI requires.
+fn position_independent_program() {
+    let program = program::position_independent();
+    let mut biases = Vec::new();
+    for _ in 0..2 {
+        let address_space = AddressSpace::create();
+        kassert!(address_space.is_ok(), "{:?}", address_space.as_ref().err());
+        let Ok(address_space) = address_space else {
+            return;
+        };
+        let image = elf::load(&address_space, &program);
+        kassert!(image.is_ok(), "{:?}", image.as_ref().err());
+        if let Ok(image) = image {
+            check_position_independent_image(&address_space, &image);
+            biases.push(image.entry);
+        }
+        let freed = address_space.free();
+        kassert!(freed.is_ok(), "{:?}", freed);
+    }
+    kassert!(
+        biases.len() == 2 && biases[0] != biases[1],
+        "program has been loaded at the same address twice: {:#x?}",
+        biases
+    );
+}
+
+/// Checks the relocated pointer and the TLS block of the position independent program loaded into the address space.
+fn check_position_independent_image(address_space: &AddressSpace, image: &ProgramImage) {
+    // the program is linked at 0 and starts at the start of its code, so its entry point is the load bias
+    let bias = image.entry;
+    kassert!(bias != 0, "program has not been relocated");
+    kassert_eq!(bias % PAGE_SIZE as u64, 0);
+    kassert_eq!(
+        read_user_u64(address_space, bias + PIE_POINTER),
+        Some(bias + PIE_POINTER_TARGET)
+    );
+
+    let Some(thread_pointer) = image.thread_pointer else {
+        kassert!(false, "thread pointer of the TLS segment is missing");
+        return;
+    };
+    kassert_eq!(thread_pointer % PIE_TLS_ALIGNMENT, 0);
+    // variant II: the thread control block starts with a pointer to itself and the TLS block ends right below it
+    kassert_eq!(
+        read_user_u64(address_space, thread_pointer),
+        Some(thread_pointer)
+    );
+    let block_start = thread_pointer - PIE_TLS_SIZE;
+    kassert_eq!(
+        read_user_u64(address_space, block_start),
+        Some(u64::from_le_bytes(PIE_TLS_DATA))
+    );
+    kassert_eq!(read_user_u64(address_space, block_start + 8), Some(0));
+    kassert!(
+        address_space
+            .translate(block_start - PAGE_SIZE as u64)
+            .is_none(),
+        "TLS block is not separated from the image by an unmapped page"
+    );
+}
+
+/// Returns whether the page of the address space is writable and executable by user programs, or `None`, if it is not mapped for them.
+fn user_permissions(address_space: &AddressSpace, page: VirtualAddress) -> Option<(bool, bool)> {
+    address_space
+        .with_temporary_access(|ptm| {
+            ptm.page_entry_mut(page)
+                .filter(|entry| {
+                    entry
+                        .flags()
+                        .contains(PageEntryFlags::PRESENT | PageEntryFlags::USER_SUPER)
+                })
+                .map(|entry| {
+                    (
+                        entry.flags().contains(PageEntryFlags::READ_WRITE),
+                        !entry.execute_disabled(),
+                    )
+                })
+        })
+        .ok()
+        .flatten()
+}
+
+/// Reads the value at the address of the address space through the direct map. The value must not cross a page boundary.
+fn read_user_u64(address_space: &AddressSpace, address: VirtualAddress) -> Option<u64> {
+    let page_offset = address % PAGE_SIZE as u64;
+    let frame = address_space.translate(address - page_offset)?;
+    let value = phys_to_virt(frame + page_offset)?;
+    Some(unsafe { read_volatile(value as *const u64) })
+}
+
+/// Forks the test process and checks that the page both share is copied on the first write, so neither sees the value written by the other.
+fn fork() {
+    let mapped = without_interrupts(|| {
+        let mut binding = PTM.lock();
+        let ptm = binding.get_mut()?;
+        let frame = ptm.pmm().request_page_for(FramePurpose::Other).ok()?;
+        ptm.map_memory(
+            FORK_PAGE,
+            frame,
+            PageEntryFlags::PRESENT | PageEntryFlags::READ_WRITE | PageEntryFlags::USER_SUPER,
+        )
+        .ok()
+    });
+    kassert!(mapped.is_some(), "could not map page at {:#x}", FORK_PAGE);
+    if mapped.is_none() {
+        return;
+    }
+    unsafe { write_volatile(FORK_PAGE as *mut u64, FORK_PARENT_VALUE) };
+    FORK_CHILD_READ.store(0, Ordering::SeqCst);
+
+    let pid = task::fork(fork_child);
+    kassert!(pid.is_ok(), "{:?}", pid);
+    let Ok(pid) = pid else {
+        return;
+    };
+    while GlobalTaskScheduler::task_alive(pid) {
+        GlobalTaskScheduler::yield_now();
+    }
+
+    kassert_eq!(FORK_CHILD_READ.load(Ordering::SeqCst), FORK_PARENT_VALUE);
+    kassert_eq!(
+        unsafe { read_volatile(FORK_PAGE as *const u64) },
+        FORK_PARENT_VALUE
+    );
+    // the frame is copied again or made writable in place, depending on whether the address space of the exited child has been freed yet
+    unsafe { write_volatile(FORK_PAGE as *mut u64, FORK_CHILD_VALUE) };
+    kassert_eq!(
+        unsafe { read_volatile(FORK_PAGE as *const u64) },
+        FORK_CHILD_VALUE
+    );
+}
+
+/// Requests a block of contiguous frames from the physical memory manager and a pool of DMA buffers, and checks that they are aligned and physically contiguous. All frames are returned afterward.
+fn contiguous_allocations() {
+    let free = free_memory();
+    let block = without_interrupts(|| {
+        let mut binding = PTM.lock();
+        let pmm = binding.get_mut()?.pmm();
+        let block = pmm.request_contiguous(CONTIGUOUS_ORDER);
+        let freed = block.map(|block| pmm.free_contiguous(block, CONTIGUOUS_ORDER));
+        Some((block, freed))
+    });
+    kassert!(block.is_some(), "page table manager is not initialized");
+    if let Some((block, freed)) = block {
+        kassert!(block.is_ok(), "{:?}", block);
+        kassert!(freed.is_ok_and(|freed| freed.is_ok()), "{:?}", freed);
+        if let Ok(block) = block {
+            kassert_eq!(block % (PAGE_SIZE << CONTIGUOUS_ORDER) as u64, 0);
+        }
+    }
+    kassert_eq!(free_memory(), free);
+
+    let pool = DmaPool::new(2, CONTIGUOUS_DMA_PAGES * PAGE_SIZE);
+    kassert!(pool.is_ok(), "{:?}", pool);
+    let Ok(mut pool) = pool else {
+        return;
+    };
+    let mut buffers = Vec::new();
+    while let Some(buffer) = pool.acquire() {
+        buffers.push(buffer);
+    }
+    kassert_eq!(buffers.len(), 2);
+    for buffer in &mut buffers {
+        let physical_address = buffer.physical_address();
+        let data = buffer.data_mut();
+        data.fill(0xA5);
+        let virtual_address = data.as_ptr() as VirtualAddress;
+        for page in 0..CONTIGUOUS_DMA_PAGES {
+            let offset = (page * PAGE_SIZE) as u64;
+            kassert_eq!(
+                virt_to_phys(virtual_address + offset),
+                Some(physical_address + offset)
+            );
+        }
+        kassert_eq!(buffer.capacity(), CONTIGUOUS_DMA_PAGES * PAGE_SIZE);
+    }
+    for buffer in buffers {
+        let released = pool.release(buffer);
+        kassert!(released.is_ok(), "{:?}", released);
+    }
+}
+
+/// Main thread of the forked child. Reads the value the parent has written and overwrites it in its own copy of the page.
+fn fork_child() {
+    let value = unsafe { read_volatile(FORK_PAGE as *const u64) };
+    unsafe { write_volatile(FORK_PAGE as *mut u64, FORK_CHILD_VALUE) };
+    FORK_CHILD_READ.store(value, Ordering::SeqCst);
+}
+
+/// Runs busy processes side by side and checks that each of them makes progress at a similar rate, so none is starved. Prints the scheduling latency percentiles measured meanwhile.
+fn fairness() {
+    FAIRNESS_NEXT_INDEX.store(0, Ordering::SeqCst);
+    for progress in &FAIRNESS_PROGRESS {
+        progress.store(0, Ordering::SeqCst);
+    }
+    latency::reset();
+
+    let pids = (0..FAIRNESS_PROCESS_COUNT)
+        .filter_map(|index| {
+            task::spawn_process(busy_process, Some(format!("KTEST-BUSY-{}", index))).ok()
+        })
+        .collect::<Vec<_>>();
+    kassert_eq!(pids.len(), FAIRNESS_PROCESS_COUNT);
+    while pids.iter().any(|pid| GlobalTaskScheduler::task_alive(*pid)) {
+        GlobalTaskScheduler::sleep(FAULT_TIMEOUT_MS);
+    }
+
+    let progress = FAIRNESS_PROGRESS
+        .each_ref()
+        .map(|progress| progress.load(Ordering::SeqCst));
+    let least = progress.iter().copied().min().unwrap_or(0);
+    let most = progress.iter().copied().max().unwrap_or(0);
+    kassert!(least > 0, "a busy process has been starved: {:?}", progress);
+    kassert!(
+        least * FAIRNESS_MAX_RATIO >= most,
+        "busy processes progressed unevenly: {:?}",
+        progress
+    );
+    println!("ktest: Scheduling latency: {}", latency::percentiles());
+}
+
+/// Main thread of a busy process. Counts loop iterations until the duration of the fairness test has passed.
+fn busy_process() {
+    let index = FAIRNESS_NEXT_INDEX.fetch_add(1, Ordering::SeqCst) % FAIRNESS_PROCESS_COUNT;
+    let end = get_current_uptime_ms() + FAIRNESS_DURATION_MS;
+    while get_current_uptime_ms() < end {
+        FAIRNESS_PROGRESS[index].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Spawns and exits hundreds of processes and threads, that allocate and free virtual memory objects, and checks that the amount of free page frames returns to its baseline afterward, so no cpu states, pml4 tables or stacks are leaked.
+fn process_churn() {
+    kassert_eq!(churn(), Ok(()));
+}
+
+fn churn() -> Result<(), String> {
+    // the first batch may grow the kernel heap and vmm bookkeeping, which is not freed again
+    churn_batch(CHURN_BATCH_SIZE)?;
+    settle(None);
+    let baseline = free_memory().ok_or("page frame allocator is not initialized")?;
+    CHURN_ERRORS.store(0, Ordering::Relaxed);
+
+    for _ in 0..CHURN_PROCESS_COUNT / CHURN_BATCH_SIZE {
+        churn_batch(CHURN_BATCH_SIZE)?;
+    }
+
+    let errors = CHURN_ERRORS.load(Ordering::Relaxed);
+    if errors > 0 {
+        return Err(format!("{} spawns or allocations failed", errors));
+    }
+    let free = settle(Some(baseline)).ok_or("page frame allocator is not initialized")?;
+    if free < baseline {
+        return Err(format!(
+            "{} page frames leaked",
+            (baseline - free) / PAGE_SIZE as u64
+        ));
+    }
+    Ok(())
+}
+
+/// Spawns a batch of churn processes and waits until all of them have exited.
+fn churn_batch(count: usize) -> Result<(), String> {
+    let pids = (0..count)
+        .map(|index| task::spawn_process(churn_process, Some(format!("KTEST-CHURN-{}", index))))
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|err| err.to_string())?;
+
+    while pids.iter().any(|pid| GlobalTaskScheduler::task_alive(*pid)) {
+        GlobalTaskScheduler::yield_now();
+    }
+    Ok(())
+}
+
+/// Waits until the scheduler has removed the exited processes, i.e. until the amount of free memory has reached the target or the timeout has passed. Returns the amount of free memory.
+fn settle(target: Option<u64>) -> Option<u64> {
+    // dead processes are only removed, once the scheduler comes across them
+    for _ in 0..CHURN_SETTLE_MS / FAULT_TIMEOUT_MS {
+        GlobalTaskScheduler::sleep(FAULT_TIMEOUT_MS);
+        if target.is_some_and(|target| free_memory().is_some_and(|free| free >= target)) {
+            break;
+        }
+    }
+    free_memory()
+}
+
+fn free_memory() -> Option<u64> {
+    without_interrupts(|| PTM.lock().get_mut().map(|ptm| ptm.pmm().free_memory()))
+}
+
+/// Main thread of a churn process. Spawns a thread, that allocates a virtual memory object as well, and joins it.
+fn churn_process() {
+    let thread = task::spawn_thread(churn_allocation, Some("KTEST-CHURN-THREAD".to_string()));
+    churn_allocation();
+    if thread.and_then(|thread| thread.join()).is_err() {
+        CHURN_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Allocates a virtual memory object, touches every page of it, so frames are actually backing it, and frees it again.
+fn churn_allocation() {
+    let result = without_interrupts(|| {
+        let mut binding = VMM.lock();
+        let vmm = binding.get_mut()?;
+        let size = CHURN_OBJECT_PAGES * PAGE_SIZE;
+        let address = vmm
+            .alloc(size, VmFlags::WRITE, AllocationType::AnyPages)
+            .ok()?;
+        unsafe { ptr::write_bytes(address as *mut u8, 0xCC, size) };
+        vmm.free(address).ok()
+    });
+    if result.is_none() {
+        CHURN_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Suspends the system to RAM and checks that the timer interrupt still switches tasks after resuming.
+/// Registers hooks for both phases of a context switch and checks that they run on the switches to and from a yielding thread and see the same threads in both phases, until they are unregistered. Checks that no more hooks than there are slots can be registered.
+fn switch_hooks() {
+    SWITCHES_BEFORE.store(0, Ordering::SeqCst);
+    SWITCHES_AFTER.store(0, Ordering::SeqCst);
+    SWITCHES_TO_SELF.store(0, Ordering::SeqCst);
+    SWITCHES_MISMATCHED.store(0, Ordering::SeqCst);
+    let before = hooks::register(SwitchPhase::Before, |switch| {
+        SWITCHES_BEFORE.fetch_add(1, Ordering::SeqCst);
+        SWITCH_NEXT_TID.store(switch.next.tid(), Ordering::SeqCst);
+        if ptr::eq(switch.previous, switch.next) {
+            SWITCHES_TO_SELF.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    let after = hooks::register(SwitchPhase::After, |switch| {
+        SWITCHES_AFTER.fetch_add(1, Ordering::SeqCst);
+        if SWITCH_NEXT_TID.load(Ordering::SeqCst) != switch.next.tid() {
+            SWITCHES_MISMATCHED.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    kassert!(before.is_ok() && after.is_ok(), "{:?}, {:?}", before, after);
+
+    let thread = task::spawn_thread(
+        || {
+            for _ in 0..SWITCH_HOOK_YIELDS {
+                GlobalTaskScheduler::yield_now();
+            }
+        },
+        None,
+    );
+    kassert!(thread.and_then(|thread| thread.join()).is_ok());
+    // both phases run within the same switch, which interrupts can not split
+    let (switches_before, switches_after) = without_interrupts(|| {
+        (
+            SWITCHES_BEFORE.load(Ordering::SeqCst),
+            SWITCHES_AFTER.load(Ordering::SeqCst),
+        )
+    });
+    kassert!(switches_before >= 2, "{} context switches", switches_before);
+    kassert_eq!(switches_before, switches_after);
+    kassert_eq!(SWITCHES_TO_SELF.load(Ordering::SeqCst), 0);
+    kassert_eq!(SWITCHES_MISMATCHED.load(Ordering::SeqCst), 0);
+
+    let mut extra = Vec::new();
+    let full = loop {
+        match hooks::register(SwitchPhase::Before, |_| {}) {
+            Ok(handle) => extra.push(handle),
+            Err(err) => break err,
+        }
+    };
+    kassert!(matches!(full, SchedulerError::NoFreeHookSlot), "{:?}", full);
+    kassert_eq!(extra.len(), MAX_SWITCH_HOOKS - 1);
+    drop(extra);
+    drop(before);
+    drop(after);
+
+    let switches = SWITCHES_BEFORE.load(Ordering::SeqCst);
+    GlobalTaskScheduler::sleep(FAULT_TIMEOUT_MS);
+    kassert_eq!(SWITCHES_BEFORE.load(Ordering::SeqCst), switches);
+}
+
+/// Allocates a lazy object and checks that only the pages accessed are backed by zeroed page frames, which are freed along with the object.
+fn lazy_backing() {
+    let free = free_memory();
+    let object = without_interrupts(|| {
+        VMM.lock().get_mut().map(|vmm| {
+            vmm.alloc(
+                LAZY_PAGES * PAGE_SIZE,
+                VmFlags::WRITE | VmFlags::LAZY,
+                AllocationType::AnyPages,
+            )
+        })
+    });
+    let Some(Ok(object)) = object else {
+        kassert!(false, "lazy object could not be allocated: {:?}", object);
+        return;
+    };
+    let page = |index: usize| object + (index * PAGE_SIZE) as u64;
+    let is_mapped = |address: VirtualAddress| {
+        without_interrupts(|| {
+            PTM.lock().get_mut().is_some_and(|ptm| {
+                ptm.page_entry_mut(address)
+                    .is_some_and(|entry| entry.flags().contains(PageEntryFlags::PRESENT))
+            })
+        })
+    };
+    kassert!((0..LAZY_PAGES).all(|index| !is_mapped(page(index))));
+    kassert_eq!(free_memory(), free);
+
+    // the vmm must not be locked while accessing the object, the page fault handler needs it
+    unsafe {
+        ptr::write_volatile(page(1) as *mut u64, 0xC0FFEE);
+        kassert_eq!(ptr::read_volatile(page(1) as *const u64), 0xC0FFEE);
+        kassert_eq!(ptr::read_volatile((page(2) + 8) as *const u64), 0);
+    }
+    kassert!(!is_mapped(page(0)) && !is_mapped(page(3)));
+    kassert!(is_mapped(page(1)) && is_mapped(page(2)));
+    kassert_eq!(free_memory(), free.map(|free| free - 2 * PAGE_SIZE as u64));
+
+    let freed = without_interrupts(|| VMM.lock().get_mut().map(|vmm| vmm.free(object)));
+    kassert!(freed.as_ref().is_some_and(Result::is_ok), "{:?}", freed);
+    kassert_eq!(free_memory(), free);
+}
+
+#[cfg(feature = "ktest-suspend")]
+fn suspend_to_ram() {
+    println!("ktest: Suspending to RAM, press a key or run `system_wakeup` in the QEMU monitor to resume.");
+    let result = crate::base::power::sleep::suspend_to_ram();
+    kassert!(result.is_ok(), "{:?}", result);
+    // times out, if the interrupt controller has not been restored
+    GlobalTaskScheduler::sleep(FAULT_TIMEOUT_MS);
+}
+
+fn divide_by_zero() {
+    unsafe {
+        asm!(
+            "xor edx, edx",
+            "xor ecx, ecx",
+            "div ecx",
+            out("eax") _,
+            out("ecx") _,
+            out("edx") _,
+            options(nomem, nostack)
+        );
+    }
+}
+
+fn page_fault() {
+    // the lower half is not mapped by the kernel
+    unsafe { asm!("mov rax, [0]", out("rax") _, options(readonly, nostack)) }
+}
+
+fn general_protection_fault() {
+    // privileged instructions do not fault in ring 0, but non-canonical addresses do
+    unsafe {
+        asm!(
+            "mov rax, [{}]",
+            in(reg) 0x8000_0000_0000u64,
+            out("rax") _,
+            options(readonly, nostack)
+        );
+    }
+}
+
+fn invalid_opcode() {
+    unsafe { asm!("ud2", options(nomem, nostack)) }
+}
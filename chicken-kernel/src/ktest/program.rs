@@ -0,0 +1,190 @@
+use alloc::vec::Vec;
+
+use chicken_util::PAGE_SIZE;
+use goblin::elf::{
+    dynamic::{DT_NULL, DT_RELA, DT_RELAENT, DT_RELASZ},
+    header::{EM_X86_64, ET_DYN, ET_EXEC},
+    program_header::{PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD, PT_TLS},
+    reloc::R_X86_64_RELATIVE,
+};
+
+/// Address the programs of the self-tests are linked at, unless they are position independent.
+pub(super) const PROGRAM_BASE: u64 = 0x40_0000;
+/// Sizes of the ELF64 file header, a program header and a section header.
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const SECTION_HEADER_SIZE: usize = 64;
+/// Offset of the data segment of the shared page program within the last page of its code segment.
+pub(super) const SHARED_DATA_OFFSET: u64 = 0x800;
+/// Initial contents of the data segment of the shared page program.
+pub(super) const SHARED_DATA: [u8; 8] = *b"shared!!";
+/// Address of the pointer of the position independent program, which is relocated to point at [`PIE_POINTER_TARGET`], i.e. into its code.
+pub(super) const PIE_POINTER: u64 = 0x1000;
+pub(super) const PIE_POINTER_TARGET: u64 = 0x10;
+/// Initialized part of the TLS template of the position independent program, followed by zeroed bytes up to [`PIE_TLS_SIZE`].
+pub(super) const PIE_TLS_DATA: [u8; 8] = *b"tls-data";
+pub(super) const PIE_TLS_SIZE: u64 = 16;
+pub(super) const PIE_TLS_ALIGNMENT: u64 = 16;
+/// Size of a relocation with an addend.
+const RELA_SIZE: u64 = 24;
+
+/// Machine code of the echo program. Writes the string of its first argument to stdout and exits with the amount of its arguments as its status.
+const ECHO_CODE: [u8; 40] = [
+    0x48, 0x8B, 0x74, 0x24, 0x10, // mov rsi, [rsp + 16]    ; argv[1]
+    0x31, 0xD2, //                   xor edx, edx
+    0x80, 0x3C, 0x16, 0x00, //       cmp byte [rsi + rdx], 0 ; measure the argument
+    0x74, 0x05, //                   je +5
+    0x48, 0xFF, 0xC2, //             inc rdx
+    0xEB, 0xF5, //                   jmp -11
+    0xBF, 0x01, 0x00, 0x00, 0x00, // mov edi, 1              ; stdout
+    0x31, 0xC0, //                   xor eax, eax            ; write
+    0x0F, 0x05, //                   syscall
+    0x48, 0x8B, 0x3C, 0x24, //       mov rdi, [rsp]          ; argc
+    0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1              ; exit
+    0x0F, 0x05, //                   syscall
+    0x0F, 0x0B, //                   ud2
+];
+
+/// Segment of a program built by [`build`].
+struct Segment<'a> {
+    kind: u32,
+    flags: u32,
+    address: u64,
+    contents: &'a [u8],
+    /// Size of the segment in memory, the part behind its contents is zeroed.
+    memory_size: u64,
+    alignment: u64,
+}
+
+/// Returns the statically linked echo program.
+pub(super) fn echo() -> Vec<u8> {
+    build(ET_EXEC, PROGRAM_BASE, &[echo_code()])
+}
+
+/// Returns a statically linked program, whose writable data segment starts on the page of its executable code segment and continues on the next page.
+pub(super) fn shared_page() -> Vec<u8> {
+    let data = Segment {
+        kind: PT_LOAD,
+        flags: PF_R | PF_W,
+        address: PROGRAM_BASE + SHARED_DATA_OFFSET,
+        contents: &SHARED_DATA,
+        memory_size: PAGE_SIZE as u64,
+        alignment: PAGE_SIZE as u64,
+    };
+    build(ET_EXEC, PROGRAM_BASE, &[echo_code(), data])
+}
+
+/// Returns a position independent program linked at 0, with a relative relocation of its pointer and a TLS segment. Its entry point is at the start of its code.
+pub(super) fn position_independent() -> Vec<u8> {
+    // the relocation follows the pointer it applies to
+    let mut data_contents = Vec::new();
+    for value in [
+        0,
+        PIE_POINTER,
+        u64::from(R_X86_64_RELATIVE),
+        PIE_POINTER_TARGET,
+    ] {
+        data_contents.extend_from_slice(&value.to_le_bytes());
+    }
+    let mut dynamic_contents = Vec::new();
+    for value in [
+        DT_RELA,
+        PIE_POINTER + 8,
+        DT_RELASZ,
+        RELA_SIZE,
+        DT_RELAENT,
+        RELA_SIZE,
+        DT_NULL,
+        0,
+    ] {
+        dynamic_contents.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut code = echo_code();
+    code.address = 0;
+    let data = Segment {
+        kind: PT_LOAD,
+        flags: PF_R | PF_W,
+        address: PIE_POINTER,
+        contents: &data_contents,
+        memory_size: data_contents.len() as u64,
+        alignment: PAGE_SIZE as u64,
+    };
+    // the loader reads the dynamic section from the file, so it does not need to be part of a loadable segment
+    let dynamic = Segment {
+        kind: PT_DYNAMIC,
+        flags: PF_R,
+        address: 0,
+        contents: &dynamic_contents,
+        memory_size: dynamic_contents.len() as u64,
+        alignment: 8,
+    };
+    let tls = Segment {
+        kind: PT_TLS,
+        flags: PF_R,
+        address: 0,
+        contents: &PIE_TLS_DATA,
+        memory_size: PIE_TLS_SIZE,
+        alignment: PIE_TLS_ALIGNMENT,
+    };
+    build(ET_DYN, 0, &[code, data, dynamic, tls])
+}
+
+/// Returns the code segment of the echo program at [`PROGRAM_BASE`].
+fn echo_code() -> Segment<'static> {
+    Segment {
+        kind: PT_LOAD,
+        flags: PF_R | PF_X,
+        address: PROGRAM_BASE,
+        contents: &ECHO_CODE,
+        memory_size: ECHO_CODE.len() as u64,
+        alignment: PAGE_SIZE as u64,
+    }
+}
+
+/// Builds an ELF64 file for x86_64 of the given type, without any sections. The contents of the segments follow their program headers.
+fn build(elf_type: u16, entry: u64, segments: &[Segment]) -> Vec<u8> {
+    let mut file = Vec::new();
+    // magic, ELF64, little endian, current version
+    file.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1]);
+    file.resize(16, 0);
+    file.extend_from_slice(&elf_type.to_le_bytes());
+    file.extend_from_slice(&EM_X86_64.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&entry.to_le_bytes());
+    file.extend_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    // no section headers and no flags
+    file.extend_from_slice(&0u64.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    for size in [
+        HEADER_SIZE,
+        PROGRAM_HEADER_SIZE,
+        segments.len(),
+        SECTION_HEADER_SIZE,
+        0,
+        0,
+    ] {
+        file.extend_from_slice(&(size as u16).to_le_bytes());
+    }
+
+    let mut offset = HEADER_SIZE + segments.len() * PROGRAM_HEADER_SIZE;
+    for segment in segments {
+        file.extend_from_slice(&segment.kind.to_le_bytes());
+        file.extend_from_slice(&segment.flags.to_le_bytes());
+        for value in [
+            offset as u64,
+            segment.address,
+            segment.address,
+            segment.contents.len() as u64,
+            segment.memory_size,
+            segment.alignment,
+        ] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        offset += segment.contents.len();
+    }
+    for segment in segments {
+        file.extend_from_slice(segment.contents);
+    }
+    file
+}
@@ -0,0 +1,320 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cell::OnceCell,
+    fmt::{Arguments, Display, Formatter, Write},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{
+    base::{interrupts::without_interrupts, io::serial, io::timer::pit::get_current_uptime_ms},
+    config,
+    scheduling::{spin::SpinLock, task::thread::ThreadLabel, GlobalTaskScheduler},
+    video::{self, history::LogHistory},
+};
+
+/// Maximum amount of sinks records are written to.
+const MAX_SINKS: usize = 8;
+/// Amount of formatted records in bytes kept by the ring buffer sink.
+const RING_BUFFER_SIZE: usize = 32 * 1024;
+/// Prefix of the module paths of the kernel, which is omitted in records and filters.
+const CRATE_PREFIX: &str = "chicken_kernel::";
+
+/// Most verbose level logged by modules without a filter, unless set with `log=<level>`.
+const DEFAULT_LEVEL: Level = if cfg!(feature = "verbose-debug") {
+    Level::Debug
+} else {
+    Level::Info
+};
+
+/// Most verbose level that is logged by modules without a filter.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+/// Levels of single modules and their submodules, overriding the global level.
+static FILTERS: SpinLock<Vec<ModuleFilter>> = SpinLock::new(Vec::new());
+/// Destinations records are written to. The serial console and the screen are available from the start.
+static SINKS: SpinLock<[Option<Sink>; MAX_SINKS]> = SpinLock::new({
+    let mut sinks = [None; MAX_SINKS];
+    sinks[0] = Some(Sink {
+        name: "serial",
+        write: write_serial,
+    });
+    sinks[1] = Some(Sink {
+        name: "console",
+        write: write_console,
+    });
+    sinks
+});
+/// Most recent records of all sinks, including those scrolled off the screen.
+static RING_BUFFER: SpinLock<OnceCell<LogHistory>> = SpinLock::new(OnceCell::new());
+
+/// Logs a message with the error level.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Error, module_path!(), format_args!($($arg)*)));
+}
+
+/// Logs a message with the warn level.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Warn, module_path!(), format_args!($($arg)*)));
+}
+
+/// Logs a message with the info level.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Info, module_path!(), format_args!($($arg)*)));
+}
+
+/// Logs a message with the debug level.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Debug, module_path!(), format_args!($($arg)*)));
+}
+
+/// Logs a message with the trace level.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Trace, module_path!(), format_args!($($arg)*)));
+}
+
+/// Severity of a record. More verbose levels are greater.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Parses a level filter. Returns the most verbose level that is logged, 0 if logging is turned off.
+fn parse_level(name: &str) -> Option<u8> {
+    let level = match name {
+        "off" => return Some(0),
+        "error" => Level::Error,
+        "warn" => Level::Warn,
+        "info" => Level::Info,
+        "debug" => Level::Debug,
+        "trace" => Level::Trace,
+        _ => return None,
+    };
+    Some(level as u8)
+}
+
+/// Level of a module and its submodules, e.g. `memory` for `memory::vmm`.
+#[derive(Clone, Debug)]
+struct ModuleFilter {
+    module: String,
+    max_level: u8,
+}
+
+/// Message of a module passed to the sinks.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Record<'a> {
+    pub(crate) level: Level,
+    /// Path of the module that logged the record, without the crate name.
+    pub(crate) module: &'a str,
+    pub(crate) uptime_ms: u64,
+    /// Thread that was running when the record was logged, if it can be determined.
+    pub(crate) thread: Option<ThreadLabel>,
+    pub(crate) args: Arguments<'a>,
+}
+
+impl Display for Record<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:03}] {:<5} {}",
+            self.uptime_ms / 1000,
+            self.uptime_ms % 1000,
+            self.level.name(),
+            self.module
+        )?;
+        if let Some(thread) = self.thread {
+            write!(f, " [{}]", thread)?;
+        }
+        write!(f, ": {}", self.args)
+    }
+}
+
+/// Destination of records. May be called from interrupt handlers, so it must not use the kernel heap.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Sink {
+    pub(crate) name: &'static str,
+    pub(crate) write: fn(&Record),
+}
+
+/// Applies the levels given with `log=` and starts recording into the ring buffer. Must be called once the kernel heap is available. Returns the options that could not be parsed.
+pub(crate) fn set_up() -> Vec<String> {
+    without_interrupts(|| {
+        RING_BUFFER
+            .lock()
+            .get_or_init(|| LogHistory::new(RING_BUFFER_SIZE));
+    });
+    let _ = register_sink(Sink {
+        name: "ring buffer",
+        write: write_ring_buffer,
+    });
+    apply_options()
+}
+
+/// Replaces the current levels with the ones given with `log=<level>,<module>=<level>,...`, e.g. `log=warn,memory=debug`. Returns the options that could not be parsed, the others are applied anyway.
+pub(crate) fn apply_options() -> Vec<String> {
+    MAX_LEVEL.store(DEFAULT_LEVEL as u8, Ordering::Relaxed);
+    without_interrupts(|| FILTERS.lock().clear());
+
+    let options = config::with("log", |value| value.unwrap_or_default().to_string());
+    let mut invalid = Vec::new();
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        let valid = match option.split_once('=') {
+            Some((module, level)) => parse_level(level)
+                .map(|max_level| set_module_level(module, max_level))
+                .is_some(),
+            None => parse_level(option)
+                .map(|max_level| MAX_LEVEL.store(max_level, Ordering::Relaxed))
+                .is_some(),
+        };
+        if !valid {
+            invalid.push(option.to_string());
+        }
+    }
+    invalid
+}
+
+/// Sets the most verbose level logged by modules without a filter.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn set_level(level: Option<Level>) {
+    MAX_LEVEL.store(level.map_or(0, |level| level as u8), Ordering::Relaxed);
+}
+
+/// Sets the most verbose level logged by the module and its submodules, `None` turns logging off for them.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn set_filter(module: &str, level: Option<Level>) {
+    set_module_level(module, level.map_or(0, |level| level as u8));
+}
+
+fn set_module_level(module: &str, max_level: u8) {
+    let module = module.trim_start_matches(CRATE_PREFIX).to_string();
+    without_interrupts(|| {
+        let mut filters = FILTERS.lock();
+        match filters.iter_mut().find(|filter| filter.module == module) {
+            Some(filter) => filter.max_level = max_level,
+            None => filters.push(ModuleFilter { module, max_level }),
+        }
+    });
+}
+
+/// Registers an additional destination for records. Fails, if all slots are taken.
+pub(crate) fn register_sink(sink: Sink) -> Result<(), Sink> {
+    without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        match sinks.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(sink);
+                Ok(())
+            }
+            None => Err(sink),
+        }
+    })
+}
+
+/// Removes the sink with the given name, e.g. to stop drawing records onto the screen. Returns whether it was registered.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn remove_sink(name: &str) -> bool {
+    without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        match sinks
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|sink| sink.name == name))
+        {
+            Some(slot) => {
+                *slot = None;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Passes the record to all sinks, if its level is enabled for the module. Use the [`error`], [`warn`], [`info`], [`debug`] and [`trace`] macros instead.
+pub(crate) fn log(level: Level, module_path: &str, args: Arguments) {
+    let module = module_path
+        .strip_prefix(CRATE_PREFIX)
+        .unwrap_or(module_path);
+    if level as u8 > max_level(module) {
+        return;
+    }
+
+    let record = Record {
+        level,
+        module,
+        uptime_ms: get_current_uptime_ms(),
+        thread: GlobalTaskScheduler::active_thread_label(),
+        args,
+    };
+    // copied, so the sinks do not run with the lock held
+    let sinks = without_interrupts(|| *SINKS.lock());
+    for sink in sinks.iter().flatten() {
+        (sink.write)(&record);
+    }
+}
+
+/// Returns the most verbose level logged by the module. The filter of the closest parent module applies, otherwise the global level.
+fn max_level(module: &str) -> u8 {
+    without_interrupts(|| {
+        FILTERS
+            .lock()
+            .iter()
+            .filter(|filter| {
+                module
+                    .strip_prefix(filter.module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|filter| filter.module.len())
+            .map(|filter| filter.max_level)
+    })
+    .unwrap_or_else(|| MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+fn write_serial(record: &Record) {
+    serial::print(format_args!("{}\n", record));
+}
+
+fn write_console(record: &Record) {
+    video::text::screen_print(format_args!("{}\n", record));
+}
+
+fn write_ring_buffer(record: &Record) {
+    without_interrupts(|| {
+        if let Some(ring_buffer) = RING_BUFFER.lock().get_mut() {
+            let _ = writeln!(ring_buffer, "{}", record);
+        }
+    });
+}
+
+/// Returns the records kept in the ring buffer, e.g. for a `dmesg`-style command.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn dump() -> String {
+    without_interrupts(|| {
+        RING_BUFFER
+            .lock()
+            .get()
+            .map(LogHistory::contents)
+            .unwrap_or_default()
+    })
+}
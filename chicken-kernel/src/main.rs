@@ -3,67 +3,178 @@
 
 extern crate alloc;
 
-use core::{arch::asm, panic::PanicInfo};
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
 
 use chicken_util::BootInfo;
-use qemu_print::qemu_println;
 
 use crate::{
-    base::io::timer::pit::get_current_uptime_ms,
-    scheduling::{task, GlobalTaskScheduler},
+    base::io::serial,
+    scheduling::GlobalTaskScheduler,
+    stats::KernelPhase,
 };
 
 mod base;
+mod config;
+mod devices;
+mod features;
+mod info;
+#[cfg(feature = "ktest")]
+mod ktest;
+mod log;
 mod memory;
+mod modules;
+mod object;
 mod scheduling;
+mod shell;
+mod stats;
 mod video;
 
 #[no_mangle]
 pub extern "sysv64" fn kernel_main(boot_info: &BootInfo) -> ! {
+    // exceptions before the full idt has been set up would otherwise triple fault without any output
+    base::interrupts::early::initialize();
+    // options are read by most subsystems, so they are available first
+    config::set_up(boot_info);
+    // the serial console is available right away, unlike the video output
+    let serial_console = serial::set_up();
+    stats::record(KernelPhase::Entry);
     let boot_info = memory::set_up(boot_info);
+    stats::record(KernelPhase::Memory);
+    let invalid_log_options = log::set_up();
+    stats::set_loader_timestamps(boot_info.loader_timestamps);
+    // modules are registered first, since the splash image is one of them
+    let module_count = modules::set_up(&boot_info);
+    let symbol_count = base::backtrace::set_up(&boot_info);
     video::set_up(&boot_info);
+    stats::record(KernelPhase::Video);
     println!("kernel: Memory Management has been set up successfully.");
     println!("kernel: Video output has been set up successfully.");
-    base::set_up(&boot_info);
-    println!("kernel: Base Architecture has been set up successfully.");
-    scheduling::set_up();
-    println!("kernel: Scheduler set up.");
+    println!("kernel: {} boot modules available.", module_count);
+    println!("kernel: {} kernel symbols available for backtraces.", symbol_count);
+    if let Err(err) = serial_console {
+        println!("kernel: Serial console is unavailable: {}", err);
+    }
+    for option in invalid_log_options {
+        println!("kernel: Ignoring invalid log option: {}", option);
+    }
+    println!("kernel: {}", info::kernel_info());
+    if !boot_info.command_line.as_str().is_empty() {
+        println!("kernel: Command line: {}", boot_info.command_line);
+    }
+    println!(
+        "kernel: Kernel image SHA-256: {} ({}).",
+        boot_info.kernel_measurement.sha256,
+        boot_info.kernel_measurement.verification.name()
+    );
+    match base::crash::set_up(&boot_info) {
+        Ok(Some(report)) => println!("kernel: The previous boot crashed: {}", report),
+        Ok(None) => {}
+        Err(err) => println!("kernel: Crash dumps are unavailable: {}", err),
+    }
+    match base::set_up(&boot_info) {
+        Ok(()) => println!("kernel: Base Architecture has been set up successfully."),
+        Err(err) => println!(
+            "kernel: Base Architecture has been set up in degraded mode (legacy PIC): {}",
+            err
+        ),
+    }
+    stats::record(KernelPhase::Base);
+    // frames allocated so far without an owner belong to the boot process, not to a leak
+    #[cfg(feature = "leak-check")]
+    memory::accounting::set_baseline();
+    match scheduling::set_up() {
+        Ok(()) => println!("kernel: Scheduler set up."),
+        // interrupts are still handled, the timer just does not switch tasks
+        Err(err) => println!("kernel: Scheduler unavailable, continuing without tasks: {}", err),
+    }
+    stats::record(KernelPhase::Scheduler);
     base::interrupts::enable();
-    // is never reached, because task scheduler starts when interrupts are enabled.
+    // is only reached if the scheduler is unavailable, otherwise the task scheduler starts when interrupts are enabled.
     hlt_loop();
 }
 
+/// Entry of the init task. Starts the system services and supervises them afterward.
 pub(crate) fn main_task() {
+    // boot is complete, the console may use the entire screen again
+    video::splash::finish();
     println!("Hello, from main task!");
 
-    fn hello() {
-        println!("Hello");
-
-        GlobalTaskScheduler::sleep(10000);
-
-        println!("Complete");
-
-        GlobalTaskScheduler::kill_active();
+    // the boot code has been left behind by the first context switch
+    match memory::release_boot_stack() {
+        Ok(frames) => println!("kernel: Released {} frames of the boot stack.", frames),
+        Err(err) => println!("kernel: Boot stack could not be released: {}", err),
     }
 
-    let thread_handle = task::spawn_thread(hello, None).unwrap();
+    // system services, e.g. the driver workers that run the deferred work of interrupt handlers
+    let supervisor = scheduling::init::start_services();
 
-    GlobalTaskScheduler::join(thread_handle);
+    #[cfg(feature = "ktest")]
+    ktest::run();
 
     // todo: fix process isolation with separate paging scheme
     // => paging offset (should stay the same)
     // => pml4 virtual address (must change)
 
-    println!("{}", get_current_uptime_ms());
+    supervisor.run()
+}
+
+/// Amount of panics that have occurred. More than one means that the panic handler itself has panicked or faulted.
+static PANIC_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// The first panic, so it can still be reported by a nested panic. Stays valid, since the first panic handler never returns.
+static FIRST_PANIC: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
 
-    GlobalTaskScheduler::kill_active();
+/// Whether the kernel has panicked. Exceptions raised afterward are nested panics.
+pub(crate) fn is_panicking() -> bool {
+    PANIC_COUNT.load(Ordering::SeqCst) != 0
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    qemu_println!("panic: {}", info);
-    println!("panic: {}", info);
+    // other tasks must not continue running on a broken kernel
+    base::interrupts::disable();
+
+    let count = PANIC_COUNT.fetch_add(1, Ordering::SeqCst);
+    match count {
+        0 => {
+            FIRST_PANIC.store(info as *const PanicInfo as *mut (), Ordering::SeqCst);
+            // record first, in case printing faults as well
+            base::crash::record(info);
+            // bypasses the global writer, which may have been locked when the panic occurred
+            let backtrace = base::backtrace::Backtrace::of_panic();
+            video::text::panic_print(format_args!("panic: {}\n{}", info, backtrace));
+            serial::print(format_args!("panic: {}\n{}", info, backtrace));
+            if let Some(thread) = GlobalTaskScheduler::active_thread_label() {
+                video::text::panic_print(format_args!("panic: in thread: {}\n", thread));
+                serial::print(format_args!("panic: in thread: {}\n", thread));
+            }
+        }
+        1 => {
+            // the panic handler has failed, so only lock-free output is used
+            let first =
+                unsafe { (FIRST_PANIC.load(Ordering::SeqCst) as *const PanicInfo).as_ref() };
+            if let Some(first) = first {
+                // only prints, if the first panic failed before printing onto the screen
+                video::text::panic_print(format_args!("panic: {}\n", first));
+                serial::print(format_args!("panic: {}\n", first));
+            }
+            serial::print(format_args!("nested panic: {}\n", info));
+        }
+        // reporting the nested panic has failed as well
+        _ => {}
+    }
 
+    // gdb can inspect the kernel before the policy is applied, if the stub is enabled
+    if count == 0 {
+        base::interrupts::gdb::breakpoint();
+    }
+    // if applying the policy panics as well, the kernel halts
+    if count <= 1 {
+        base::crash::apply_panic_policy();
+    }
     hlt_loop();
 }
 
@@ -0,0 +1,141 @@
+use chicken_util::PAGE_SIZE;
+
+use crate::{
+    base::interrupts::without_interrupts,
+    info,
+    memory::{
+        address_space::{self, FRAME_SHARE_UNIT},
+        kheap::LockedHeap,
+        paging::PTM,
+        vmm::VMM,
+    },
+    scheduling::{spin::SpinLock, GlobalTaskScheduler},
+    warn,
+};
+
+/// Frames owned once the kernel has been set up. Frames allocated at that point without an owner, e.g. the kernel image and the page tables of the loader, are not reported as leaked.
+static BASELINE: SpinLock<Option<FrameUsage>> = SpinLock::new(None);
+
+/// Page frames marked as used by the physical memory manager and the subsystems owning them.
+#[derive(Copy, Clone, Debug, Default)]
+struct FrameUsage {
+    /// Frames marked as used or reserved in the bit map.
+    allocated: u64,
+    /// Frames the counters of the physical memory manager consider used or reserved. Only differs from the bit map, if either of them has been corrupted.
+    counted: u64,
+    heap: u64,
+    /// Pages of virtual memory objects other than thread stacks, including the pml4 tables of the address spaces.
+    vmm: u64,
+    stacks: u64,
+    /// Page tables below the pml4 tables.
+    page_tables: u64,
+    /// Pages mapped into the user half of the address spaces. Pages shared by several of them are counted once.
+    user_pages: u64,
+}
+
+impl FrameUsage {
+    fn owners(&self) -> [(&'static str, u64); 5] {
+        [
+            ("heap", self.heap),
+            ("vmm", self.vmm),
+            ("stacks", self.stacks),
+            ("page tables", self.page_tables),
+            ("user pages", self.user_pages),
+        ]
+    }
+
+    /// Returns the allocated frames no subsystem owns. Negative, if more frames are owned than allocated, e.g. because a freed frame is still mapped.
+    fn unaccounted(&self) -> i64 {
+        let owned = self.owners().iter().map(|(_, frames)| frames).sum::<u64>();
+        self.allocated as i64 - owned as i64
+    }
+}
+
+/// Records the frames owned once the kernel has been set up. Must be called before any tasks are started, so the frames allocated by the boot process are part of it.
+pub(crate) fn set_baseline() {
+    let usage = collect();
+    without_interrupts(|| *BASELINE.lock() = usage);
+}
+
+/// Removes frames from the baseline, that the boot process has owned and freed after set up, e.g. the boot stack, so they are not mistaken for frames owned by a subsystem.
+pub(crate) fn forget_boot_frames(frames: u64) {
+    without_interrupts(|| {
+        if let Some(baseline) = BASELINE.lock().as_mut() {
+            baseline.allocated = baseline.allocated.saturating_sub(frames);
+            baseline.counted = baseline.counted.saturating_sub(frames);
+        }
+    });
+}
+
+/// Walks the bit map of the physical memory manager and logs the frames owned by each subsystem, as well as the allocated frames none of them owns compared to the baseline. Called at an orderly shutdown, the records are the same on every boot without leaks, so they can be compared between boots.
+pub(crate) fn report() {
+    let Some(usage) = collect() else {
+        warn!("leak check: Physical memory manager is unavailable.");
+        return;
+    };
+    let baseline = without_interrupts(|| *BASELINE.lock()).unwrap_or_default();
+
+    info!("leak check: page frames owned at shutdown (after set up):");
+    for ((owner, frames), (_, initial)) in usage.owners().into_iter().zip(baseline.owners()) {
+        info!(
+            "leak check:   {:<12} {:>6} frames ({:>6})",
+            owner, frames, initial
+        );
+    }
+    info!(
+        "leak check:   {:<12} {:>6} frames ({:>6})",
+        "allocated", usage.allocated, baseline.allocated
+    );
+
+    if usage.allocated != usage.counted {
+        warn!(
+            "leak check: The bit map marks {} frames as used, but the counters {} frames.",
+            usage.allocated, usage.counted
+        );
+    }
+    let leaked = usage.unaccounted() - baseline.unaccounted();
+    if leaked == 0 {
+        info!("leak check: No page frames have leaked.");
+    } else {
+        warn!(
+            "leak check: {} page frames are not accounted for by any subsystem.",
+            leaked
+        );
+    }
+}
+
+/// Counts the frames owned by each subsystem. Runs with interrupts disabled, so the allocations do not change meanwhile.
+fn collect() -> Option<FrameUsage> {
+    without_interrupts(|| {
+        let heap = LockedHeap::page_count() as u64;
+        let vmm = VMM.lock().get().map_or(0, |vmm| vmm.backed_pages()) as u64;
+
+        let mut stacks = 0;
+        let mut page_tables = 0;
+        let mut page_shares = 0;
+        // the scheduler is locked before the page table manager, like when switching processes
+        GlobalTaskScheduler::for_each_process(|address_space, stack_pages| {
+            stacks += stack_pages as u64;
+            if let Some(ptm) = PTM.lock().get_mut() {
+                let frames = address_space.user_frames(ptm);
+                page_tables += frames.page_tables;
+                page_shares += frames.page_shares;
+            }
+        });
+
+        let mut binding = PTM.lock();
+        let ptm = binding.get_mut()?;
+        page_tables += address_space::kernel_page_tables(ptm);
+        let pmm = ptm.pmm();
+        Some(FrameUsage {
+            allocated: pmm.allocated_frames(),
+            counted: (pmm.used_memory() + pmm.reserved_memory()) / PAGE_SIZE as u64,
+            heap,
+            vmm: vmm.saturating_sub(stacks),
+            stacks,
+            page_tables,
+            // each share has been rounded down, so the sum is rounded up again
+            user_pages: page_shares.div_ceil(FRAME_SHARE_UNIT),
+        })
+    })
+}
@@ -0,0 +1,425 @@
+use core::{ops::Range, ptr};
+
+use chicken_util::{
+    memory::{
+        paging::{manager::PageTableManager, PageEntry, PageEntryFlags, PageTable},
+        pmm::audit::FramePurpose,
+        PhysicalAddress, VirtualAddress,
+    },
+    PAGE_SIZE,
+};
+
+use crate::{
+    base::interrupts::without_interrupts,
+    memory::{
+        direct_map::{phys_to_virt, virt_to_phys},
+        paging::{self, PagingError, PTM},
+        tlb,
+        vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+    },
+};
+
+/// Pml4 entries of the lower half, which holds the mappings of the process itself. The upper half is shared by all address spaces.
+const USER_PML4_ENTRIES: Range<usize> = 0..256;
+/// Pml4 entries of the higher half, which holds the kernel mappings.
+pub(in crate::memory) const KERNEL_PML4_ENTRIES: Range<usize> = 256..512;
+/// Fraction of a page frame pages of the user half are counted in. Pages shared by several address spaces count as one frame divided by its references in each of them, so their shares add up to whole frames.
+#[cfg(feature = "leak-check")]
+pub(crate) const FRAME_SHARE_UNIT: u64 = 1 << 32;
+
+/// Page tables of a virtual address space, e.g. of a process. Page tables of address spaces other than the active one are accessed through the direct map, so they can be modified without switching to them.
+#[derive(Debug)]
+pub(crate) struct AddressSpace {
+    /// Virtual address of the pml4 table.
+    pml4: *mut PageTable,
+    /// Physical address of the pml4 table, resolved once on creation, so switching to the address space does not walk the page tables.
+    pml4_physical: PhysicalAddress,
+}
+
+impl AddressSpace {
+    /// Allocates a new address space, that contains the kernel mappings of the active one. The caller is responsible for freeing it using [`AddressSpace::free`].
+    pub(crate) fn create() -> Result<Self, VmmError> {
+        let active_pml4 = without_interrupts(|| {
+            PTM.lock()
+                .get()
+                .map(PageTableManager::pml4_virtual)
+                .ok_or(PagingError::GlobalPageTableManagerUninitialized)
+        })?;
+
+        let pml4 = {
+            let mut binding = VMM.lock();
+            let vmm = binding
+                .get_mut()
+                .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+            vmm.alloc(PAGE_SIZE, VmFlags::WRITE, AllocationType::AnyPages)? as *mut PageTable
+        };
+        let pml4_physical = virt_to_phys(pml4 as VirtualAddress)
+            .ok_or(PagingError::GlobalPageTableManagerUninitialized)?;
+
+        let address_space = Self {
+            pml4,
+            pml4_physical,
+        };
+        unsafe {
+            address_space.clear_user_half();
+            address_space.copy_mappings_from(active_pml4);
+        }
+        Ok(address_space)
+    }
+
+    /// Frees the page tables of the address space. The page frames mapped into the user half are released, so they are only freed once no other address space shares them. The address space must not be active or used afterward.
+    pub(crate) fn free(&self) -> Result<(), VmmError> {
+        without_interrupts(|| {
+            let mut binding = PTM.lock();
+            let ptm = binding
+                .get_mut()
+                .ok_or(PagingError::GlobalPageTableManagerUninitialized)?;
+            unsafe {
+                self.walk_user_half(&mut |visited| {
+                    let _ = match visited {
+                        Visited::Page(_, entry) => {
+                            ptm.pmm().release_frame(entry.address()).map(|_| ())
+                        }
+                        Visited::Table(table) => ptm.pmm().free_frame(table),
+                    };
+                });
+                self.clear_user_half();
+            }
+            // the address space may have been active until recently, so translations to the released frames may still be cached
+            tlb::flush_all();
+            Ok::<(), PagingError>(())
+        })?;
+
+        let mut binding = VMM.lock();
+        let vmm = binding
+            .get_mut()
+            .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+        vmm.free(self.pml4 as VirtualAddress)
+    }
+
+    /// Copies the kernel mappings of the given page table manager into the address space, e.g. after the kernel has mapped new memory in another address space.
+    pub(crate) fn sync_kernel_mappings(&self, ptm: &PageTableManager) {
+        unsafe { self.copy_mappings_from(ptm.pml4_virtual()) };
+    }
+
+    /// Loads the address space into cr3 and points the page table manager at it.
+    ///
+    /// # Safety
+    /// The caller must ensure that the kernel mappings of the address space are up-to-date, since the kernel continues running in it.
+    pub(crate) unsafe fn activate(&self, ptm: &mut PageTableManager) {
+        paging::enable(self.pml4_physical);
+        ptm.update_pml4(self.pml4_physical);
+        ptm.update_pml4_virtual(self.pml4 as VirtualAddress);
+    }
+
+    /// Copies the kernel entries of the given pml4 table into the one of the address space. The user half is left as is, since it belongs to the process.
+    ///
+    /// # Safety
+    /// The caller must ensure that the pointer is mapped and points to a valid page table.
+    unsafe fn copy_mappings_from(&self, pml4: *const PageTable) {
+        let (target, source) = (&mut *self.pml4, &*pml4);
+        target.entries[KERNEL_PML4_ENTRIES].copy_from_slice(&source.entries[KERNEL_PML4_ENTRIES]);
+    }
+
+    /// Removes all entries of the user half from the pml4 table.
+    ///
+    /// # Safety
+    /// The caller must ensure that the page tables they point to have been freed or are still referenced elsewhere.
+    unsafe fn clear_user_half(&self) {
+        let pml4 = &mut *self.pml4;
+        pml4.entries[USER_PML4_ENTRIES].fill(PageEntry::new(0, PageEntryFlags::empty()));
+    }
+
+    /// Visits every page mapped into the user half and every page table below the pml4, after its entries have been visited.
+    ///
+    /// # Safety
+    /// The caller must ensure that the page tables are not modified meanwhile, e.g. by holding the lock of the page table manager.
+    unsafe fn walk_user_half(&self, visit: &mut impl FnMut(Visited)) {
+        for index in USER_PML4_ENTRIES {
+            let entry = &mut (*self.pml4).entries[index];
+            walk_table(entry, 3, (index as u64) << 39, visit);
+        }
+    }
+
+    /// Counts the page tables of the user half and the pages mapped into it. The pml4 itself is a virtual memory object.
+    #[cfg(feature = "leak-check")]
+    pub(crate) fn user_frames(&self, ptm: &mut PageTableManager) -> UserFrames {
+        let mut frames = UserFrames::default();
+        unsafe {
+            self.walk_user_half(&mut |visited| match visited {
+                Visited::Page(_, entry) => {
+                    let references = ptm.pmm().references(entry.address()).unwrap_or(1);
+                    frames.page_shares += FRAME_SHARE_UNIT / references.max(1);
+                }
+                Visited::Table(_) => frames.page_tables += 1,
+            });
+        }
+        frames
+    }
+
+    /// Duplicates the user half of the address space. Writable pages are shared as read-only copy-on-write pages by both address spaces, so they are only copied once either of them writes to them. The caller is responsible for freeing the copy using [`AddressSpace::free`].
+    pub(crate) fn fork(&self) -> Result<Self, VmmError> {
+        let child = Self::create()?;
+        let result = child.with_temporary_access(|ptm| unsafe {
+            let mut result = Ok(());
+            self.walk_user_half(&mut |visited| {
+                let Visited::Page(address, entry) = visited else {
+                    return;
+                };
+                if result.is_err() {
+                    return;
+                }
+                let mut flags = entry.flags();
+                if flags.contains(PageEntryFlags::READ_WRITE) {
+                    flags = flags.difference(PageEntryFlags::READ_WRITE)
+                        | PageEntryFlags::COPY_ON_WRITE;
+                    entry.set_flags(flags);
+                }
+                result = ptm
+                    .pmm()
+                    .share_frame(entry.address())
+                    .and_then(|()| ptm.map_memory(address, entry.address(), flags));
+            });
+            result
+        });
+        // the parent may be the active address space, whose writable pages have become read-only
+        tlb::flush_all();
+
+        match result.and_then(|result| result.map_err(PagingError::from)) {
+            Ok(()) => Ok(child),
+            Err(err) => {
+                // the pages shared so far are released again, the parent keeps them as copy-on-write pages
+                child.free()?;
+                Err(err.into())
+            }
+        }
+    }
+}
+
+/// Page frames owned by the user half of an address space.
+#[cfg(feature = "leak-check")]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct UserFrames {
+    pub(crate) page_tables: u64,
+    /// Mapped pages in units of [`FRAME_SHARE_UNIT`].
+    pub(crate) page_shares: u64,
+}
+
+/// Counts the page tables of the kernel half of the active address space. They are shared by all address spaces.
+#[cfg(feature = "leak-check")]
+pub(crate) fn kernel_page_tables(ptm: &mut PageTableManager) -> u64 {
+    let mut tables = 0;
+    let pml4 = unsafe { &mut *ptm.pml4_virtual() };
+    for index in KERNEL_PML4_ENTRIES {
+        unsafe {
+            walk_table(&mut pml4.entries[index], 3, (index as u64) << 39, &mut |visited| {
+                if let Visited::Table(_) = visited {
+                    tables += 1;
+                }
+            })
+        };
+    }
+    tables
+}
+
+/// Page or page table visited by [`walk_table`].
+enum Visited<'a> {
+    /// Virtual address and entry of a mapped page.
+    Page(VirtualAddress, &'a mut PageEntry),
+    /// Physical address of a page table.
+    Table(PhysicalAddress),
+}
+
+/// Visits the entries of the page table the entry points to. `level` is 3 for page directory pointer tables, 1 for page tables. Huge pages are skipped, since they are never mapped into the user half.
+unsafe fn walk_table(
+    entry: &mut PageEntry,
+    level: u32,
+    base: VirtualAddress,
+    visit: &mut impl FnMut(Visited),
+) {
+    if !entry.flags().contains(PageEntryFlags::PRESENT) {
+        return;
+    }
+    let Some(next) = phys_to_virt(entry.address()) else {
+        return;
+    };
+    let next = &mut *(next as *mut PageTable);
+    for (index, child) in next.entries.iter_mut().enumerate() {
+        let address = base | ((index as u64) << (12 + 9 * (level - 1)));
+        let flags = child.flags();
+        if !flags.contains(PageEntryFlags::PRESENT) {
+            continue;
+        }
+        if level == 1 {
+            visit(Visited::Page(address, child));
+        } else if !flags.contains(PageEntryFlags::PAT_PAGE_SIZE) {
+            walk_table(child, level - 1, address, visit);
+        }
+    }
+    visit(Visited::Table(entry.address()));
+}
+
+/// Resolves a write to a copy-on-write page of the active address space, by giving it a private copy of the page frame. Returns whether the page was a copy-on-write page and is writable now. Called by the page fault handler, so it must not use the kernel heap.
+pub(crate) fn resolve_copy_on_write(address: VirtualAddress) -> bool {
+    without_interrupts(|| {
+        // the faulting code may hold the lock itself, e.g. when the kernel writes to a copy-on-write page
+        let Some(mut binding) = PTM.try_lock() else {
+            return false;
+        };
+        let Some(ptm) = binding.get_mut() else {
+            return false;
+        };
+        let page = address - address % PAGE_SIZE as u64;
+        break_copy_on_write(ptm, page).is_ok_and(|resolved| resolved)
+    })
+}
+
+/// Makes the copy-on-write page writable in the address space the page table manager points at. The page frame is copied, unless no other address space shares it anymore. Returns whether the page was a copy-on-write page.
+fn break_copy_on_write(
+    ptm: &mut PageTableManager,
+    page: VirtualAddress,
+) -> Result<bool, PagingError> {
+    let Some(entry) = ptm.page_entry_mut(page) else {
+        return Ok(false);
+    };
+    let flags = entry.flags();
+    if !flags.contains(PageEntryFlags::PRESENT | PageEntryFlags::COPY_ON_WRITE) {
+        return Ok(false);
+    }
+    let shared = entry.address();
+    let flags = flags.difference(PageEntryFlags::COPY_ON_WRITE) | PageEntryFlags::READ_WRITE;
+
+    let frame = if ptm.pmm().references(shared)? > 1 {
+        let copy = ptm.pmm().request_page_for(FramePurpose::Other)?;
+        let source = phys_to_virt(shared).ok_or(PagingError::AddressNotMapped(page))?;
+        let destination = phys_to_virt(copy).ok_or(PagingError::AddressNotMapped(page))?;
+        unsafe { ptr::copy_nonoverlapping(source as *const u8, destination as *mut u8, PAGE_SIZE) };
+        ptm.pmm().release_frame(shared)?;
+        copy
+    } else {
+        // the other address spaces have written to the page or have exited already
+        shared
+    };
+
+    if let Some(entry) = ptm.page_entry_mut(page) {
+        entry.set_address(frame);
+        entry.set_flags(flags);
+    }
+    tlb::invalidate_page(page);
+    Ok(true)
+}
+
+#[allow(dead_code)] // no programs are loaded into other address spaces yet
+impl AddressSpace {
+    /// Runs the closure with the global page table manager pointing at the page tables of the address space. The active address space stays loaded in cr3 and the page table manager is restored afterward.
+    pub(crate) fn with_temporary_access<R>(
+        &self,
+        f: impl FnOnce(&mut PageTableManager) -> R,
+    ) -> Result<R, PagingError> {
+        without_interrupts(|| {
+            let mut binding = PTM.lock();
+            let ptm = binding
+                .get_mut()
+                .ok_or(PagingError::GlobalPageTableManagerUninitialized)?;
+
+            let previous_physical = ptm.pml4_physical() as PhysicalAddress;
+            let previous_virtual = ptm.pml4_virtual() as VirtualAddress;
+            unsafe {
+                ptm.update_pml4(self.pml4_physical);
+                ptm.update_pml4_virtual(self.pml4 as VirtualAddress);
+            }
+            let result = f(ptm);
+            unsafe {
+                ptm.update_pml4(previous_physical);
+                ptm.update_pml4_virtual(previous_virtual);
+            }
+            Ok(result)
+        })
+    }
+
+    /// Maps the virtual address of the address space to the physical address.
+    pub(crate) fn map(
+        &self,
+        virtual_address: VirtualAddress,
+        physical_address: PhysicalAddress,
+        flags: PageEntryFlags,
+    ) -> Result<(), PagingError> {
+        self.with_temporary_access(|ptm| ptm.map_memory(virtual_address, physical_address, flags))?
+            .map_err(PagingError::from)
+    }
+
+    /// Removes the mapping of the virtual address of the address space. Returns the physical address it was mapped to.
+    pub(crate) fn unmap(
+        &self,
+        virtual_address: VirtualAddress,
+    ) -> Result<PhysicalAddress, PagingError> {
+        let physical_address = self
+            .with_temporary_access(|ptm| ptm.unmap(virtual_address))?
+            .map_err(PagingError::from)?;
+        // the address space may be the active one
+        tlb::invalidate_page(virtual_address);
+        Ok(physical_address)
+    }
+
+    /// Returns the physical address the virtual address of the address space is mapped to.
+    pub(crate) fn translate(&self, virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
+        self.with_temporary_access(|ptm| ptm.get_physical(virtual_address))
+            .ok()
+            .flatten()
+    }
+
+    /// Adds the write and execute permissions of the flags to the mapped page of the address space. Permissions the page has already are kept.
+    pub(crate) fn widen_permissions(
+        &self,
+        page: VirtualAddress,
+        flags: PageEntryFlags,
+    ) -> Result<(), PagingError> {
+        self.with_temporary_access(|ptm| {
+            let entry = ptm
+                .page_entry_mut(page)
+                .ok_or(PagingError::AddressNotMapped(page))?;
+            let mut merged = entry.flags() | (flags & PageEntryFlags::READ_WRITE);
+            // the execute disable bit is not part of the lower flags, so it is only kept if both forbid execution
+            if entry.execute_disabled() && flags.contains(PageEntryFlags::EXECUTE_DISABLE) {
+                merged |= PageEntryFlags::EXECUTE_DISABLE;
+            }
+            *entry = PageEntry::new(entry.address(), merged);
+            Ok::<_, PagingError>(())
+        })??;
+        tlb::invalidate_page(page);
+        Ok(())
+    }
+
+    /// Returns the page frame the page of the address space is mapped to. Copy-on-write pages are copied first, since the kernel writes to them through the direct map.
+    fn writable_frame(&self, page: VirtualAddress) -> Result<Option<PhysicalAddress>, PagingError> {
+        self.with_temporary_access(|ptm| {
+            break_copy_on_write(ptm, page)?;
+            Ok(ptm.get_physical(page))
+        })?
+    }
+
+    /// Copies the data to the virtual address of the address space. Every page of the range must be mapped.
+    pub(crate) fn copy_range(
+        &self,
+        destination: VirtualAddress,
+        data: &[u8],
+    ) -> Result<(), PagingError> {
+        let mut copied = 0;
+        while copied < data.len() {
+            let address = destination + copied as u64;
+            let page_offset = (address % PAGE_SIZE as u64) as usize;
+            let length = (PAGE_SIZE - page_offset).min(data.len() - copied);
+
+            let page = self
+                .writable_frame(address - page_offset as u64)?
+                .ok_or(PagingError::AddressNotMapped(address))?;
+            let target = phys_to_virt(page + page_offset as u64)
+                .ok_or(PagingError::AddressNotMapped(address))?;
+            unsafe {
+                ptr::copy_nonoverlapping(data[copied..].as_ptr(), target as *mut u8, length);
+            }
+            copied += length;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use core::{
+    ptr, slice,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use chicken_util::memory::{
+    MemoryDescriptor, MemoryMap, MemoryType, PhysicalAddress, VirtualAddress,
+};
+
+use crate::memory::paging::{PTM, VIRTUAL_PHYSICAL_BASE};
+
+/// Descriptors of the memory map, set once paging has been set up. The memory map does not change afterwards, so it is read without locking.
+static DESCRIPTORS: AtomicPtr<MemoryDescriptor> = AtomicPtr::new(ptr::null_mut());
+static DESCRIPTORS_LEN: AtomicUsize = AtomicUsize::new(0);
+/// Whether the boot stack has been released to the physical memory manager, so its frames are part of the direct map like usable memory.
+static BOOT_STACK_RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the direct map helpers available. The descriptors of the memory map must be accessible via their virtual address.
+pub(super) fn init(memory_map: &MemoryMap) {
+    DESCRIPTORS_LEN.store(memory_map.descriptors_len as usize, Ordering::Release);
+    DESCRIPTORS.store(memory_map.descriptors, Ordering::Release);
+}
+
+/// Adds the frames of the boot stack to the direct map helpers. They must have been mapped into the direct map before.
+pub(super) fn include_boot_stack() {
+    BOOT_STACK_RELEASED.store(true, Ordering::Release);
+}
+
+/// Returns the direct map address of the given physical address or None, if it is not part of the direct map, e.g. MMIO or reserved memory.
+pub(crate) fn phys_to_virt(physical_address: PhysicalAddress) -> Option<VirtualAddress> {
+    phys_range_to_virt(physical_address, 1)
+}
+
+/// Returns the direct map address of `length` bytes starting at the given physical address or None, if the range is not part of the direct map as a whole.
+pub(crate) fn phys_range_to_virt(
+    physical_address: PhysicalAddress,
+    length: usize,
+) -> Option<VirtualAddress> {
+    let end = physical_address.checked_add(length.max(1) as u64)?;
+    descriptors()
+        .iter()
+        .any(|desc| {
+            let direct_mapped = match desc.r#type {
+                MemoryType::Available | MemoryType::AcpiData => true,
+                MemoryType::KernelStack => BOOT_STACK_RELEASED.load(Ordering::Acquire),
+                _ => false,
+            };
+            direct_mapped && desc.phys_start <= physical_address && end <= desc.phys_end
+        })
+        .then_some(physical_address + VIRTUAL_PHYSICAL_BASE)
+}
+
+/// Returns the physical address of the given virtual address. Addresses in the direct map are translated without locking, all other addresses are looked up in the active page table.
+///
+/// Must not be called while the page table manager is locked, unless the address is known to be in the direct map.
+pub(crate) fn virt_to_phys(virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
+    direct_map_physical(virtual_address).or_else(|| {
+        PTM.lock()
+            .get()
+            .and_then(|ptm| ptm.get_physical(virtual_address))
+    })
+}
+
+/// Returns whether the virtual address belongs to the direct map, so it must not be unmapped or freed.
+pub(crate) fn is_direct_map_address(virtual_address: VirtualAddress) -> bool {
+    direct_map_physical(virtual_address).is_some()
+}
+
+fn direct_map_physical(virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
+    let physical_address = virtual_address.checked_sub(VIRTUAL_PHYSICAL_BASE)?;
+    phys_to_virt(physical_address).map(|_| physical_address)
+}
+
+/// Descriptors of the memory map, empty until paging has been set up.
+pub(super) fn descriptors() -> &'static [MemoryDescriptor] {
+    let descriptors = DESCRIPTORS.load(Ordering::Acquire);
+    if descriptors.is_null() {
+        return &[];
+    }
+    unsafe { slice::from_raw_parts(descriptors, DESCRIPTORS_LEN.load(Ordering::Acquire)) }
+}
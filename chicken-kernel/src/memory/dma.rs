@@ -0,0 +1,226 @@
+use alloc::vec::Vec;
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chicken_util::{
+    memory::{PhysicalAddress, VirtualAddress},
+    PAGE_SIZE,
+};
+
+use crate::{
+    base::interrupts::without_interrupts,
+    memory::{
+        address_space::AddressSpace,
+        direct_map::virt_to_phys,
+        paging::PagingError,
+        vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+    },
+};
+
+/// Id assigned to the next pool, so buffers can not be returned to the wrong pool.
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Pool of pre-mapped buffers a device driver can hand to the hardware for DMA. Buffers are passed between the driver and its users as [`DmaBuffer`] handles, which own the buffer, so data is never copied inside the kernel. It is only copied at the boundary to user space, see [`DmaBuffer::copy_to`].
+#[derive(Debug)]
+pub(crate) struct DmaPool {
+    id: usize,
+    /// Size of each buffer in bytes, rounded up to whole pages.
+    buffer_size: usize,
+    /// Virtual and physical address of every buffer of the pool.
+    buffers: Vec<(VirtualAddress, PhysicalAddress)>,
+    /// Indices of the buffers that are not handed out.
+    free: Vec<usize>,
+}
+
+impl DmaPool {
+    /// Allocates a pool of the given amount of buffers of the given size in bytes, e.g. a page for an ethernet frame. Each buffer is backed by physically contiguous frames, so a device can access the whole buffer by its physical address, and is surrounded by guard pages, so a device or driver overrunning it faults instead of corrupting other memory.
+    #[allow(dead_code)] // no device drivers use DMA yet
+    pub(crate) fn new(count: usize, buffer_size: usize) -> Result<Self, DmaError> {
+        let buffer_size = buffer_size.next_multiple_of(PAGE_SIZE);
+        let mut pool = Self {
+            id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            buffer_size,
+            buffers: Vec::with_capacity(count),
+            free: Vec::with_capacity(count),
+        };
+        for _ in 0..count {
+            let virtual_address = without_interrupts(|| {
+                let mut binding = VMM.lock();
+                let vmm = binding
+                    .get_mut()
+                    .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+                vmm.alloc(
+                    buffer_size,
+                    VmFlags::WRITE | VmFlags::GUARDED,
+                    AllocationType::Contiguous,
+                )
+            })?;
+            let physical_address = virt_to_phys(virtual_address)
+                .ok_or(PagingError::AddressNotMapped(virtual_address))?;
+            // buffers allocated so far are freed again by dropping the pool, if an allocation fails
+            pool.free.push(pool.buffers.len());
+            pool.buffers.push((virtual_address, physical_address));
+        }
+        Ok(pool)
+    }
+
+    /// Hands out a free buffer. Returns `None`, if all buffers are in use.
+    #[allow(dead_code)] // no device drivers use DMA yet
+    pub(crate) fn acquire(&mut self) -> Option<DmaBuffer> {
+        let index = self.free.pop()?;
+        let (virtual_address, physical_address) = self.buffers[index];
+        Some(DmaBuffer {
+            pool: self.id,
+            index,
+            virtual_address,
+            physical_address,
+            capacity: self.buffer_size,
+            length: 0,
+        })
+    }
+
+    /// Returns the buffer to the pool, so it can be handed out again.
+    #[allow(dead_code)] // no device drivers use DMA yet
+    pub(crate) fn release(&mut self, buffer: DmaBuffer) -> Result<(), DmaError> {
+        if buffer.pool != self.id {
+            return Err(DmaError::ForeignBuffer(buffer.physical_address));
+        }
+        self.free.push(buffer.index);
+        Ok(())
+    }
+
+    /// Amount of buffers that are not handed out.
+    #[allow(dead_code)] // no device drivers use DMA yet
+    pub(crate) fn available(&self) -> usize {
+        self.free.len()
+    }
+}
+
+impl Drop for DmaPool {
+    fn drop(&mut self) {
+        // buffers that are still handed out may still be accessed by a device, so they are leaked
+        if self.free.len() != self.buffers.len() {
+            return;
+        }
+        without_interrupts(|| {
+            if let Some(vmm) = VMM.lock().get_mut() {
+                for (virtual_address, _) in &self.buffers {
+                    let _ = vmm.free(*virtual_address);
+                }
+            }
+        });
+    }
+}
+
+/// Buffer of a [`DmaPool`]. The handle owns the buffer: it can not be cloned and is passed by value between the device driver and the protocol stack or filesystem, until it is returned to its pool.
+#[derive(Debug)]
+pub(crate) struct DmaBuffer {
+    pool: usize,
+    index: usize,
+    virtual_address: VirtualAddress,
+    physical_address: PhysicalAddress,
+    /// Size of the buffer in bytes.
+    capacity: usize,
+    /// Amount of valid bytes, e.g. the size of a received frame.
+    length: usize,
+}
+
+#[allow(dead_code)] // no device drivers use DMA yet
+impl DmaBuffer {
+    /// Physical address of the buffer, that is handed to the device.
+    pub(crate) fn physical_address(&self) -> PhysicalAddress {
+        self.physical_address
+    }
+
+    /// Amount of valid bytes.
+    pub(crate) fn len(&self) -> usize {
+        self.length
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Size of the buffer in bytes.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Sets the amount of valid bytes, e.g. after the device has written a frame. Fails, if it exceeds the capacity.
+    pub(crate) fn set_len(&mut self, length: usize) -> Result<(), DmaError> {
+        if length > self.capacity {
+            return Err(DmaError::LengthTooLarge(length, self.capacity));
+        }
+        self.length = length;
+        Ok(())
+    }
+
+    /// Valid bytes of the buffer.
+    pub(crate) fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.virtual_address as *const u8, self.length) }
+    }
+
+    /// Whole buffer, e.g. to fill in a frame before setting its length.
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.virtual_address as *mut u8, self.capacity) }
+    }
+
+    /// Copies the valid bytes into the address space of a process. This is the only copy of the data between the device and user space.
+    pub(crate) fn copy_to(
+        &self,
+        address_space: &AddressSpace,
+        destination: VirtualAddress,
+    ) -> Result<(), PagingError> {
+        address_space.copy_range(destination, self.data())
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum DmaError {
+    AllocationFailed(VmmError),
+    ForeignBuffer(PhysicalAddress),
+    /// Contains the length and the capacity of the buffer.
+    LengthTooLarge(usize, usize),
+}
+
+impl Debug for DmaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DmaError::AllocationFailed(value) => write!(f, "DMA Error: {}", value),
+            DmaError::ForeignBuffer(address) => write!(
+                f,
+                "DMA Error: Buffer at physical address: {:#x} belongs to another pool.",
+                address
+            ),
+            DmaError::LengthTooLarge(length, capacity) => write!(
+                f,
+                "DMA Error: Length: {} exceeds the buffer size of {} bytes.",
+                length, capacity
+            ),
+        }
+    }
+}
+
+impl Display for DmaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for DmaError {}
+
+impl From<VmmError> for DmaError {
+    fn from(value: VmmError) -> Self {
+        Self::AllocationFailed(value)
+    }
+}
+
+impl From<PagingError> for DmaError {
+    fn from(value: PagingError) -> Self {
+        Self::AllocationFailed(VmmError::from(value))
+    }
+}
@@ -2,15 +2,19 @@ use alloc::alloc::GlobalAlloc;
 use core::{alloc::Layout, ptr, ptr::NonNull};
 
 use chicken_util::{
-    memory::{paging::PageEntryFlags, VirtualAddress},
+    memory::{paging::PageEntryFlags, pmm::audit::FramePurpose, VirtualAddress},
     PAGE_SIZE,
 };
 
 use crate::{
     memory::{
         align_up,
-        kheap::{HeapError, MAX_KERNEL_HEAP_PAGE_COUNT},
+        kheap::{
+            stats::{size_class, HeapStatistics, SizeClassCounters, SIZE_CLASS_COUNT},
+            HeapError, MAX_KERNEL_HEAP_PAGE_COUNT,
+        },
         paging::{PagingError, PTM},
+        tlb,
     },
 };
 use crate::memory::kheap::LockedHeap;
@@ -28,6 +32,8 @@ pub(super) struct LinkedListAllocator {
     heap_size: usize,
     heap_start: VirtualAddress,
     head: Option<NonNull<ListNode>>,
+    /// Allocations by power-of-two size class of their requested size.
+    size_classes: [SizeClassCounters; SIZE_CLASS_COUNT],
 }
 
 impl LinkedListAllocator {
@@ -50,12 +56,52 @@ impl LinkedListAllocator {
                 heap_size,
                 heap_start,
                 head: Some(start_node),
+                size_classes: [SizeClassCounters::default(); SIZE_CLASS_COUNT],
             })
         }
     }
 }
 
 impl LinkedListAllocator {
+    /// Returns the amount of pages mapped for the heap.
+    #[cfg(feature = "leak-check")]
+    pub(super) fn page_count(&self) -> usize {
+        self.heap_size.div_ceil(PAGE_SIZE)
+    }
+
+    /// Returns the allocations by size class and the free blocks of the heap.
+    pub(super) fn statistics(&self) -> HeapStatistics {
+        let mut statistics = HeapStatistics {
+            size_classes: self.size_classes,
+            heap_size: self.heap_size,
+            free_bytes: 0,
+            free_blocks: 0,
+            largest_free_block: 0,
+        };
+        let mut current = self.head;
+        while let Some(node) = current {
+            let node = unsafe { node.as_ref() };
+            if node.free {
+                statistics.free_bytes += node.size;
+                statistics.free_blocks += 1;
+                statistics.largest_free_block = statistics.largest_free_block.max(node.size);
+            }
+            current = node.next;
+        }
+        statistics
+    }
+
+    /// Counts an allocation of the requested size. `hit` tells whether it has been served without expanding the heap.
+    fn record_allocation(&mut self, size: usize, succeeded: bool, hit: bool) {
+        let counters = &mut self.size_classes[size_class(size)];
+        if succeeded {
+            counters.allocations += 1;
+            counters.hits += u64::from(hit);
+        } else {
+            counters.failures += 1;
+        }
+    }
+
     /// Tries to find a fitting list node in the linked list to home a new block of allocated memory.
     fn find_fit(&mut self, size: usize) -> Result<NonNull<ListNode>, HeapError> {
         let mut current = self.head;
@@ -73,6 +119,8 @@ impl LinkedListAllocator {
 
     /// Splits a list node into two in order to allocate new memory on the heap. May fail if the size if too large.
     fn split_block(&mut self, mut node: NonNull<ListNode>, size: usize) -> Result<(), HeapError> {
+        // the block behind starts right after this one, so it is aligned without any padding, that would not be accounted for in the sizes
+        let size = align_up(size as u64, align_of::<ListNode>()) as usize;
         unsafe {
             let node_ref = node.as_mut();
             let remaining_size = node_ref
@@ -80,10 +128,8 @@ impl LinkedListAllocator {
                 .checked_sub(size)
                 .ok_or(HeapError::InvalidBlockSize(node_ref.size))?;
             if remaining_size >= size_of::<ListNode>() {
-                let new_node_ptr = align_up(
-                    node.as_ptr() as u64 + (size_of::<ListNode>() + size) as u64,
-                    align_of::<ListNode>(),
-                ) as *mut ListNode;
+                let new_node_ptr =
+                    (node.as_ptr() as u64 + (size_of::<ListNode>() + size) as u64) as *mut ListNode;
 
                 let new_node = NonNull::new_unchecked(new_node_ptr);
 
@@ -143,88 +189,118 @@ impl LinkedListAllocator {
         }
     }
 
-    /// Attempts to expand the memory mapped for the heap allocator.
+    /// Maps additional page frames behind the end of the heap, so a block of the given size fits, and adds them to the free list. Fails, if the heap would exceed [`MAX_KERNEL_HEAP_PAGE_COUNT`] pages. Pages mapped before running out of page frames are kept as part of the heap.
     fn expand(&mut self, size: usize) -> Result<(), HeapError> {
-        let old_heap_page_count = (self.heap_size + PAGE_SIZE - 1) / PAGE_SIZE;
-        let new_heap_page_count = (size + PAGE_SIZE - 1) / PAGE_SIZE + old_heap_page_count;
-
-        // check if expansion is valid
-        if new_heap_page_count > MAX_KERNEL_HEAP_PAGE_COUNT {
+        let heap_page_count = self.heap_size / PAGE_SIZE;
+        // the new region needs a list node of its own, unless it is merged into a free last block
+        let additional_page_count = (size + size_of::<ListNode>()).div_ceil(PAGE_SIZE);
+        if heap_page_count + additional_page_count > MAX_KERNEL_HEAP_PAGE_COUNT {
             return Err(HeapError::OutOfMemory);
         }
-        let mut ptm = PTM.lock();
-        let page_table_manager = ptm.get_mut();
-        if let Some(page_table_manager) = page_table_manager {
-            for page in old_heap_page_count..new_heap_page_count {
-                // allocate new physical frames for heap
-                let physical_address = page_table_manager
-                    .pmm()
-                    .request_page()
-                    .map_err(|_| HeapError::OutOfMemory)?;
-
-                // map newly allocated frames to virtual heap offset
-                page_table_manager
-                    .map_memory(
-                        self.heap_start + (page * PAGE_SIZE) as u64,
-                        physical_address,
-                        PageEntryFlags::default_nx(),
-                    )
-                    .map_err(|_| HeapError::OutOfMemory)?;
-            }
 
-            // find last free list node and expand it
-            let current = self.head;
-            while let Some(mut node) = current {
-                let node_ref = unsafe { node.as_mut() };
-                // last free node
-                if node_ref.free && node_ref.next.is_none() {
-                    node_ref.size += size;
-                    break;
+        let region_start = self.heap_start + self.heap_size as u64;
+        let mut mapped_page_count = 0;
+        let result = map_pages(region_start, additional_page_count, &mut mapped_page_count);
+        tlb::invalidate_range(region_start, mapped_page_count);
+        if mapped_page_count > 0 {
+            unsafe { self.append_region(region_start, mapped_page_count * PAGE_SIZE) };
+        }
+        result
+    }
+
+    /// Adds a region directly behind the end of the heap to the free list. It is merged into the last block, if that one is free, otherwise a new block is created.
+    ///
+    /// # Safety
+    /// Caller has to ensure that the region is mapped and at least as large as a `ListNode`.
+    unsafe fn append_region(&mut self, start: VirtualAddress, size: usize) {
+        let mut last = self.head;
+        while let Some(next) = last.and_then(|node| node.as_ref().next) {
+            last = Some(next);
+        }
+
+        match last {
+            Some(mut last) if last.as_ref().free => last.as_mut().size += size,
+            _ => {
+                let new_node = NonNull::new_unchecked(start as *mut ListNode);
+                new_node.write(ListNode {
+                    size: size - size_of::<ListNode>(),
+                    free: true,
+                    next: None,
+                    prev: last,
+                });
+                match last {
+                    Some(mut last) => last.as_mut().next = Some(new_node),
+                    None => self.head = Some(new_node),
                 }
             }
+        }
+        self.heap_size += size;
+    }
+}
 
-            self.heap_size += size;
-
-            Ok(())
-        } else {
-            Err(HeapError::PageTableManagerError(
-                PagingError::GlobalPageTableManagerUninitialized,
-            ))
+/// Requests page frames for the heap and maps them one after another, starting at the given address. Counts the pages mapped so far, so they are not lost, if a later one can not be mapped.
+fn map_pages(
+    start: VirtualAddress,
+    page_count: usize,
+    mapped_page_count: &mut usize,
+) -> Result<(), HeapError> {
+    let mut ptm = PTM.lock();
+    let page_table_manager = ptm.get_mut().ok_or(HeapError::PageTableManagerError(
+        PagingError::GlobalPageTableManagerUninitialized,
+    ))?;
+    for page in 0..page_count {
+        let physical_address = page_table_manager
+            .pmm()
+            .request_page_for(FramePurpose::Heap)?;
+        if let Err(err) = page_table_manager.map_memory(
+            start + (page * PAGE_SIZE) as u64,
+            physical_address,
+            PageEntryFlags::default_nx(),
+        ) {
+            let _ = page_table_manager.pmm().free_frame(physical_address);
+            return Err(err.into());
         }
+        *mapped_page_count += 1;
     }
+    Ok(())
 }
 
 unsafe impl GlobalAlloc for LockedHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let heap = &mut self.lock();
+        // heap has not been initialized
+        let Some(heap) = heap.get_mut() else {
+            return ptr::null_mut();
+        };
 
-        if let Some(heap) = heap.get_mut() {
-            let size = align_up(layout.size() as u64, layout.align()) as usize;
-            if let Ok(fit_node) = heap.find_fit(size) {
-                if heap.split_block(fit_node, size).is_ok() {
-                    return fit_node.as_ptr().add(1) as *mut u8;
-                }
-            } else {
-                // expand heap
-                if heap.expand(size).is_ok() {
-                    if let Ok(fit_node) = heap.find_fit(size) {
-                        if heap.split_block(fit_node, size).is_ok() {
-                            return fit_node.as_ptr().add(1) as *mut u8;
-                        }
-                    }
-                }
+        let size = align_up(layout.size() as u64, layout.align()) as usize;
+        let (block, hit) = match heap.find_fit(size) {
+            Ok(fit_node) => (
+                heap.split_block(fit_node, size).ok().map(|_| fit_node),
+                true,
+            ),
+            // expand heap
+            Err(_) => {
+                let block = heap
+                    .expand(size)
+                    .ok()
+                    .and_then(|_| heap.find_fit(size).ok())
+                    .filter(|fit_node| heap.split_block(*fit_node, size).is_ok());
+                (block, false)
             }
-        }
-        // heap has not been initialized or OOM
-        ptr::null_mut()
+        };
+        heap.record_allocation(layout.size(), block.is_some(), hit);
+        // OOM
+        block.map_or(ptr::null_mut(), |node| node.as_ptr().add(1) as *mut u8)
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         if ptr.is_null() {
             return;
         }
         let mut heap = self.lock();
         if let Some(heap) = heap.get_mut() {
+            heap.size_classes[size_class(layout.size())].frees += 1;
             let node_ptr = (ptr as *mut ListNode).sub(1);
 
             let mut node = NonNull::new_unchecked(node_ptr);
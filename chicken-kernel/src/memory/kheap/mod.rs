@@ -5,13 +5,18 @@ use core::{
 };
 
 use chicken_util::{
-    memory::{paging::PageEntryFlags, pmm::PageFrameAllocatorError, VirtualAddress},
+    memory::{
+        paging::PageEntryFlags,
+        pmm::{audit::FramePurpose, PageFrameAllocatorError},
+        VirtualAddress,
+    },
     PAGE_SIZE,
 };
 
 use crate::{
+    base::interrupts::without_interrupts,
     memory::{
-        kheap::linked_list::LinkedListAllocator,
+        kheap::{linked_list::LinkedListAllocator, stats::HeapStatistics},
         paging::{PagingError, PTM}
         ,
     },
@@ -21,6 +26,8 @@ use crate::{
 mod bump;
 
 mod linked_list;
+pub(crate) mod slab;
+pub(crate) mod stats;
 
 pub(in crate::memory) const VIRTUAL_KERNEL_HEAP_BASE: u64 = 0xFFFF_FFFF_F000_0000;
 
@@ -57,7 +64,7 @@ impl LockedHeap {
             for page in 0..heap_page_count {
                 let physical_address = page_table_manager
                     .pmm()
-                    .request_page()
+                    .request_page_for(FramePurpose::Heap)
                     .map_err(HeapError::from)?;
 
                 page_table_manager
@@ -78,11 +85,23 @@ impl LockedHeap {
         }
     }
 
+    /// Returns the amount of pages mapped for the kernel heap, 0 if it has not been initialized.
+    #[cfg(feature = "leak-check")]
+    pub(in crate::memory) fn page_count() -> usize {
+        ALLOCATOR.lock().get().map_or(0, LinkedListAllocator::page_count)
+    }
+
     fn lock(&self) -> Guard<OnceCell<LinkedListAllocator>> {
         self.inner.lock()
     }
 }
 
+/// Returns the allocations by size class and the fragmentation of the kernel heap, `None` if it has not been initialized.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn statistics() -> Option<HeapStatistics> {
+    without_interrupts(|| ALLOCATOR.lock().get().map(LinkedListAllocator::statistics))
+}
+
 #[derive(Copy, Clone)]
 pub(in crate::memory) enum HeapError {
     InvalidBlockSize(usize),
@@ -0,0 +1,270 @@
+use alloc::alloc::alloc;
+use core::{
+    alloc::Layout,
+    fmt::{Display, Formatter},
+    marker::PhantomData,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use chicken_util::PAGE_SIZE;
+
+use crate::{
+    base::interrupts::without_interrupts,
+    config,
+    memory::vmm::object::VmObject,
+    scheduling::{
+        spin::SpinLock,
+        task::{process::Process, thread::Thread},
+    },
+};
+
+/// Size in bytes of the memory a cache takes from the kernel heap at once, unless a single object is larger.
+const SLAB_SIZE: usize = PAGE_SIZE;
+/// Most slabs a cache takes from the kernel heap at once, when it doubles its size.
+const MAX_GROWTH_SLABS: usize = 16;
+
+/// Whether a cache that runs out of objects doubles its size, instead of taking a single slab, as set with `slab_growth=`.
+static DOUBLE_ON_GROWTH: AtomicBool = AtomicBool::new(false);
+
+pub(crate) static PROCESS_CACHE: SlabCache<Process> = SlabCache::new("process");
+pub(crate) static THREAD_CACHE: SlabCache<Thread> = SlabCache::new("thread");
+pub(in crate::memory) static VM_OBJECT_CACHE: SlabCache<VmObject> = SlabCache::new("vm object");
+
+/// Objects of a single type, carved out of slabs taken from the kernel heap. Freed objects are kept on a free list for the next allocation, so allocating them does not go through the linked list allocator, except once per slab. Slabs are never returned to the heap.
+pub(crate) struct SlabCache<T> {
+    name: &'static str,
+    inner: SpinLock<SlabState>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T> Send for SlabCache<T> {}
+unsafe impl<T> Sync for SlabCache<T> {}
+
+#[derive(Debug)]
+struct SlabState {
+    free: Option<NonNull<FreeObject>>,
+    objects_in_use: usize,
+    slabs: usize,
+    allocations: u64,
+    /// Allocations served by the free list, without taking a new slab from the kernel heap.
+    hits: u64,
+}
+
+/// Free object, which holds the link to the next free object instead of its contents.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+impl<T> SlabCache<T> {
+    /// Layout of each object within a slab, large enough to hold the link of the free list as well.
+    const OBJECT_LAYOUT: Layout = {
+        let size = if size_of::<T>() > size_of::<FreeObject>() {
+            size_of::<T>()
+        } else {
+            size_of::<FreeObject>()
+        };
+        let align = if align_of::<T>() > align_of::<FreeObject>() {
+            align_of::<T>()
+        } else {
+            align_of::<FreeObject>()
+        };
+        match Layout::from_size_align(size.next_multiple_of(align), align) {
+            Ok(layout) => layout,
+            Err(_) => panic!("Invalid slab object layout."),
+        }
+    };
+    /// Amount of objects allocated from the kernel heap at once.
+    const OBJECTS_PER_SLAB: usize = if SLAB_SIZE / Self::OBJECT_LAYOUT.size() > 1 {
+        SLAB_SIZE / Self::OBJECT_LAYOUT.size()
+    } else {
+        1
+    };
+
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            inner: SpinLock::new(SlabState {
+                free: None,
+                objects_in_use: 0,
+                slabs: 0,
+                allocations: 0,
+                hits: 0,
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Moves the value into a free object of the cache. Takes a new slab from the kernel heap, if there are no free objects left. Returns `None`, if the kernel heap is out of memory.
+    pub(crate) fn alloc(&self, value: T) -> Option<NonNull<T>> {
+        let object = match self.pop(true) {
+            Some(object) => object,
+            None => {
+                self.grow()?;
+                self.pop(false)?
+            }
+        };
+        let object = object.cast::<T>();
+        unsafe { object.write(value) };
+        Some(object)
+    }
+
+    /// Drops the object and returns its memory to the free list of the cache.
+    ///
+    /// # Safety
+    /// The caller must ensure that the object has been allocated by this cache and is not used afterward.
+    pub(crate) unsafe fn free(&self, object: NonNull<T>) {
+        object.drop_in_place();
+        let object = object.cast::<FreeObject>();
+        without_interrupts(|| {
+            let mut state = self.inner.lock();
+            object.write(FreeObject { next: state.free });
+            state.free = Some(object);
+            state.objects_in_use -= 1;
+        });
+    }
+
+    /// Returns the amount of objects in use and of slabs taken from the kernel heap.
+    pub(crate) fn statistics(&self) -> SlabStatistics {
+        without_interrupts(|| {
+            let state = self.inner.lock();
+            SlabStatistics {
+                name: self.name,
+                object_size: Self::OBJECT_LAYOUT.size(),
+                objects_in_use: state.objects_in_use,
+                slabs: state.slabs,
+                objects_per_slab: Self::OBJECTS_PER_SLAB,
+                allocations: state.allocations,
+                hits: state.hits,
+            }
+        })
+    }
+
+    /// Takes an object from the free list and counts the allocation. `hit` tells whether the cache has had a free object without growing.
+    fn pop(&self, hit: bool) -> Option<NonNull<FreeObject>> {
+        without_interrupts(|| {
+            let mut state = self.inner.lock();
+            let object = state.free?;
+            state.free = unsafe { object.as_ref().next };
+            state.objects_in_use += 1;
+            state.allocations += 1;
+            state.hits += u64::from(hit);
+            Some(object)
+        })
+    }
+
+    /// Takes new slabs from the kernel heap, one or as many as the cache has already, depending on the growth policy. Succeeds, if at least one slab has been added.
+    fn grow(&self) -> Option<()> {
+        let slabs = without_interrupts(|| self.inner.lock().slabs);
+        let count = if DOUBLE_ON_GROWTH.load(Ordering::Relaxed) {
+            slabs.clamp(1, MAX_GROWTH_SLABS)
+        } else {
+            1
+        };
+        let added = (0..count).take_while(|_| self.add_slab().is_some()).count();
+        (added > 0).then_some(())
+    }
+
+    /// Takes slabs from the kernel heap, until the cache has at least the given amount. Returns `None`, if the heap is out of memory.
+    fn reserve(&self, slabs: usize) -> Option<()> {
+        while without_interrupts(|| self.inner.lock().slabs) < slabs {
+            self.add_slab()?;
+        }
+        Some(())
+    }
+
+    /// Takes a new slab from the kernel heap and adds its objects to the free list. The cache is not locked meanwhile, since the heap may have to grow.
+    fn add_slab(&self) -> Option<()> {
+        let layout = Layout::from_size_align(
+            Self::OBJECT_LAYOUT.size() * Self::OBJECTS_PER_SLAB,
+            Self::OBJECT_LAYOUT.align(),
+        )
+        .ok()?;
+        let slab = NonNull::new(unsafe { alloc(layout) })?;
+
+        without_interrupts(|| {
+            let mut state = self.inner.lock();
+            for index in 0..Self::OBJECTS_PER_SLAB {
+                let object =
+                    unsafe { slab.add(index * Self::OBJECT_LAYOUT.size()) }.cast::<FreeObject>();
+                unsafe { object.write(FreeObject { next: state.free }) };
+                state.free = Some(object);
+            }
+            state.slabs += 1;
+        });
+        Some(())
+    }
+}
+
+/// Usage of a slab cache, e.g. to find the kernel objects that take up the heap.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct SlabStatistics {
+    pub(crate) name: &'static str,
+    /// Size in bytes of each object, including its padding.
+    pub(crate) object_size: usize,
+    pub(crate) objects_in_use: usize,
+    pub(crate) slabs: usize,
+    pub(crate) objects_per_slab: usize,
+    pub(crate) allocations: u64,
+    /// Allocations served by the free list, without taking a new slab from the kernel heap.
+    pub(crate) hits: u64,
+}
+
+impl SlabStatistics {
+    /// Share of the allocations in percent, that have been served by the free list. `None`, if there have not been any.
+    pub(crate) fn hit_rate_percent(&self) -> Option<u64> {
+        (self.allocations != 0).then(|| self.hits * 100 / self.allocations)
+    }
+
+    /// Share of the objects of the slabs in percent, that are not in use. Slabs are never returned to the heap, so this is the memory a cache holds on to.
+    pub(crate) fn unused_percent(&self) -> u64 {
+        let capacity = self.slabs * self.objects_per_slab;
+        if capacity == 0 {
+            return 0;
+        }
+        ((capacity - self.objects_in_use) * 100 / capacity) as u64
+    }
+}
+
+impl Display for SlabStatistics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}: {}/{} objects of {} bytes in use, {} slabs, {} allocations",
+            self.name,
+            self.objects_in_use,
+            self.slabs * self.objects_per_slab,
+            self.object_size,
+            self.slabs,
+            self.allocations
+        )?;
+        match self.hit_rate_percent() {
+            Some(hit_rate) => write!(f, ", {}% hits", hit_rate),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the usage of every slab cache.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn statistics() -> [SlabStatistics; 3] {
+    [
+        PROCESS_CACHE.statistics(),
+        THREAD_CACHE.statistics(),
+        VM_OBJECT_CACHE.statistics(),
+    ]
+}
+
+/// Sets the growth policy of the caches to the one given with `slab_growth=`.
+pub(crate) fn apply_growth_policy() {
+    let double = config::with("slab_growth", |value| value == Some("double"));
+    DOUBLE_ON_GROWTH.store(double, Ordering::Relaxed);
+}
+
+/// Takes as many slabs from the kernel heap for every cache, as given with `slab_prealloc=`, so the first allocations do not have to grow the caches. Caches that have more slabs already keep them. Returns whether the heap has had enough memory.
+pub(crate) fn preallocate() -> bool {
+    let slabs = config::integer("slab_prealloc").unwrap_or(0) as usize;
+    PROCESS_CACHE.reserve(slabs).is_some()
+        && THREAD_CACHE.reserve(slabs).is_some()
+        && VM_OBJECT_CACHE.reserve(slabs).is_some()
+}
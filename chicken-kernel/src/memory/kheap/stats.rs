@@ -0,0 +1,61 @@
+/// Shift of the smallest power-of-two size class of the kernel heap statistics, 16 bytes. Smaller allocations are counted in it as well.
+const MIN_CLASS_SHIFT: u32 = 4;
+/// Shift of the largest power-of-two size class, a page. Larger allocations are counted together in one more class.
+const MAX_CLASS_SHIFT: u32 = 12;
+/// Amount of size classes, including the one of allocations larger than a page.
+pub(crate) const SIZE_CLASS_COUNT: usize = (MAX_CLASS_SHIFT - MIN_CLASS_SHIFT) as usize + 2;
+
+/// Allocations of the kernel heap in a single size class.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct SizeClassCounters {
+    pub(crate) allocations: u64,
+    /// Allocations served by a free block, without expanding the heap.
+    pub(crate) hits: u64,
+    pub(crate) frees: u64,
+    /// Allocations that have failed, because the heap could not be expanded.
+    pub(crate) failures: u64,
+}
+
+impl SizeClassCounters {
+    pub(crate) fn in_use(&self) -> u64 {
+        self.allocations.saturating_sub(self.frees)
+    }
+
+    /// Share of the allocations in percent, that have been served without expanding the heap. `None`, if there have not been any.
+    pub(crate) fn hit_rate_percent(&self) -> Option<u64> {
+        (self.allocations != 0).then(|| self.hits * 100 / self.allocations)
+    }
+}
+
+/// Returns the index of the size class of an allocation of the given size in bytes.
+pub(super) fn size_class(size: usize) -> usize {
+    let shift = size.max(1).next_power_of_two().trailing_zeros();
+    (shift.clamp(MIN_CLASS_SHIFT, MAX_CLASS_SHIFT + 1) - MIN_CLASS_SHIFT) as usize
+}
+
+/// Returns the largest allocation in bytes counted in the size class, `None` for the class of allocations larger than a page.
+pub(crate) fn class_size(index: usize) -> Option<usize> {
+    let shift = MIN_CLASS_SHIFT + index as u32;
+    (shift <= MAX_CLASS_SHIFT).then(|| 1 << shift)
+}
+
+/// Usage of the kernel heap, e.g. to tell whether allocations fail because it is full or because its free memory is scattered.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct HeapStatistics {
+    pub(crate) size_classes: [SizeClassCounters; SIZE_CLASS_COUNT],
+    /// Size in bytes of the memory mapped for the heap.
+    pub(crate) heap_size: usize,
+    pub(crate) free_bytes: usize,
+    pub(crate) free_blocks: usize,
+    pub(crate) largest_free_block: usize,
+}
+
+impl HeapStatistics {
+    /// External fragmentation in percent, the share of free memory outside of the largest free block, which no allocation larger than the other blocks can use.
+    pub(crate) fn fragmentation_percent(&self) -> u64 {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        ((self.free_bytes - self.largest_free_block) * 100 / self.free_bytes) as u64
+    }
+}
@@ -1,30 +1,51 @@
 use chicken_util::{
     BootInfo,
-    memory::{
-        MemoryMap,
-        MemoryType,
-        paging::{KERNEL_MAPPING_OFFSET, KERNEL_STACK_MAPPING_OFFSET}, pmm::PageFrameAllocator, VirtualAddress,
-    },
+    memory::{paging::is_canonical, pmm::PageFrameAllocator},
 };
 
 use crate::memory::{
     kheap::{KERNEL_HEAP_PAGE_COUNT, LockedHeap, VIRTUAL_KERNEL_HEAP_BASE},
-    paging::{GlobalPageTableManager, smallest_address, VIRTUAL_DATA_BASE, VIRTUAL_PHYSICAL_BASE},
+    paging::{GlobalPageTableManager, PagingError, VIRTUAL_DATA_BASE, VIRTUAL_PHYSICAL_BASE},
     vmm::{
         AllocationType, GlobalVirtualMemoryManager, object::VmFlags, VIRTUAL_VMM_BASE, VMM,
         VMM_PAGE_COUNT, VmmError,
     },
 };
+use crate::serial_println;
 
+#[cfg(feature = "leak-check")]
+pub(crate) mod accounting;
+pub(crate) mod address_space;
+pub(crate) mod direct_map;
+pub(crate) mod dma;
 pub(crate) mod paging;
 
-mod kheap;
+pub(crate) mod kheap;
+mod requirements;
+#[cfg(feature = "page-table-check")]
+pub(crate) mod sanitizer;
+pub(crate) mod tlb;
 pub(crate) mod vmm;
 
+// the page tables only have 4 levels, so the fixed virtual bases must fit into 48 bits
+const _: () = assert!(
+    is_canonical(VIRTUAL_PHYSICAL_BASE)
+        && is_canonical(VIRTUAL_DATA_BASE)
+        && is_canonical(VIRTUAL_KERNEL_HEAP_BASE)
+        && is_canonical(VIRTUAL_VMM_BASE)
+);
+
 /// Sets up memory management and returns Boot info with proper virtual address pointers
 pub(super) fn set_up(boot_info: &BootInfo) -> BootInfo {
     // get physical memory manager
-    let pmm = unsafe { (boot_info.pmm_address as *const PageFrameAllocator).read() };
+    let mut pmm = unsafe { (boot_info.pmm_address as *const PageFrameAllocator).read() };
+    // only audit the allocations of the kernel, the page tables of the loader are replaced below
+    pmm.reset_audit();
+
+    // fail with a clear message instead of running out of page frames halfway through the set up
+    if let Err(err) = requirements::check(&boot_info.memory_map, &pmm) {
+        panic!("{}", err);
+    }
 
     // set up paging
     let (manager, mut boot_info) = paging::setup(pmm, boot_info).unwrap();
@@ -36,8 +57,15 @@ pub(super) fn set_up(boot_info: &BootInfo) -> BootInfo {
     // initialize static global page table manager
     GlobalPageTableManager::init(manager);
 
+    // the memory map is accessible via its virtual address now
+    direct_map::init(&boot_info.memory_map);
+
     // initialize kernel heap
     LockedHeap::init(VIRTUAL_KERNEL_HEAP_BASE, KERNEL_HEAP_PAGE_COUNT).unwrap();
+    kheap::slab::apply_growth_policy();
+    if !kheap::slab::preallocate() {
+        serial_println!("Memory: could not preallocate slabs, the kernel heap is too small.");
+    }
 
     // initialize static global vmm
     GlobalVirtualMemoryManager::init(VIRTUAL_VMM_BASE, VMM_PAGE_COUNT);
@@ -52,9 +80,81 @@ pub(super) fn set_up(boot_info: &BootInfo) -> BootInfo {
         .unwrap();
     vmm.free(page_sized_buffer).unwrap();
 
+    #[cfg(feature = "boot-audit")]
+    print_frame_audit(&boot_info);
+
+    if boot_info.memory_map_dump {
+        print_memory_map(&boot_info);
+    }
+
     boot_info
 }
 
+/// Frees the boot stack, once the scheduler has switched to the tasks, which run on stacks of their own. Returns the amount of page frames released.
+pub(crate) fn release_boot_stack() -> Result<usize, PagingError> {
+    let frames = paging::release_boot_stack()?;
+    // the frames have been owned by the boot process, when the baseline has been recorded
+    #[cfg(feature = "leak-check")]
+    accounting::forget_boot_frames(frames as u64);
+    Ok(frames)
+}
+
+/// Prints the memory map handed over by the loader to the serial console, in the same format the loader prints it before exiting, so type conversion and handoff bugs show up in a diff of both.
+fn print_memory_map(boot_info: &BootInfo) {
+    for descriptor in boot_info.memory_map.descriptors() {
+        serial_println!("mmap kernel: {}", descriptor);
+    }
+    serial_println!("mmap kernel: {}", boot_info.memory_map);
+}
+
+/// Logs the amount of page frames the kernel has allocated during memory set up for each purpose, so the early boot memory usage can be compared between builds.
+#[cfg(feature = "boot-audit")]
+fn print_frame_audit(boot_info: &BootInfo) {
+    use chicken_util::{
+        memory::{pmm::audit::FramePurpose, MemoryType},
+        PAGE_SIZE,
+    };
+
+    use crate::info;
+
+    let Some(audit) = paging::PTM.lock().get_mut().map(|ptm| ptm.pmm().audit()) else {
+        return;
+    };
+    let kib = |frames: u64| frames * PAGE_SIZE as u64 / 1024;
+
+    info!("boot audit: page frames allocated during memory set up:");
+    for purpose in FramePurpose::ALL {
+        let frames = audit.frames(purpose);
+        info!(
+            "boot audit:   {:<12} {:>6} frames {:>8} KiB",
+            purpose.name(),
+            frames,
+            kib(frames)
+        );
+    }
+    info!(
+        "boot audit:   {:<12} {:>6} frames {:>8} KiB",
+        "total",
+        audit.total(),
+        kib(audit.total())
+    );
+
+    // the kernel stack is not allocated by the kernel itself
+    let stack_frames = boot_info
+        .memory_map
+        .descriptors()
+        .iter()
+        .filter(|desc| desc.r#type == MemoryType::KernelStack)
+        .map(|desc| desc.num_pages)
+        .sum::<u64>();
+    info!(
+        "boot audit:   {:<12} {:>6} frames {:>8} KiB (reserved by the loader)",
+        "stack",
+        stack_frames,
+        kib(stack_frames)
+    );
+}
+
 /// Aligns a given number to the specified alignment.
 pub(in crate::memory) fn align_up(number: u64, align: usize) -> u64 {
     let align = align as u64;
@@ -80,24 +180,3 @@ fn mmio(boot_info: &mut BootInfo) -> Result<(), VmmError> {
         Err(VmmError::GlobalVirtualMemoryManagerUninitialized)
     }
 }
-
-/// Returns the virtual memory offset used in the direct mapping for the given memory type. Returns None if the memory type does not get mapped or if the memory map is invalid/empty.
-pub(crate) fn get_virtual_offset(
-    memory_type: MemoryType,
-    memory_map: &MemoryMap,
-) -> Option<VirtualAddress> {
-    match memory_type {
-        MemoryType::Available => Some(VIRTUAL_PHYSICAL_BASE),
-        MemoryType::Reserved => None,
-        MemoryType::KernelCode => Some(KERNEL_MAPPING_OFFSET),
-        MemoryType::KernelStack => Some(
-            KERNEL_STACK_MAPPING_OFFSET
-                - smallest_address(&[MemoryType::KernelStack], memory_map).ok()?,
-        ),
-        MemoryType::KernelData | MemoryType::AcpiData => Some(
-            VIRTUAL_DATA_BASE
-                - smallest_address(&[MemoryType::KernelData, MemoryType::AcpiData], memory_map)
-                    .ok()?,
-        ),
-    }
-}
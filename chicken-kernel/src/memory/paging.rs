@@ -10,24 +10,35 @@ use chicken_util::{
     graphics::font::Font,
     memory::{
         paging::{
-            manager::PageTableManager, PageEntryFlags, PageTable, KERNEL_MAPPING_OFFSET,
-            KERNEL_STACK_MAPPING_OFFSET,
+            manager::PageTableManager, PageEntryFlags, PageTable, HUGE_PAGE_SIZE,
+            KERNEL_MAPPING_OFFSET, KERNEL_STACK_MAPPING_OFFSET,
         },
         pmm::{PageFrameAllocator, PageFrameAllocatorError},
-        MemoryDescriptor, MemoryMap, MemoryType, PhysicalAddress,
+        MemoryDescriptor, MemoryMap, MemoryType, PhysicalAddress, VirtualAddress,
     },
+    module::ModuleDescriptor,
+    segments::{KernelSegments, SegmentFlags},
+    symbols::KernelSymbol,
     BootInfo, PAGE_SIZE,
 };
 
 use crate::{
-    base::msr::{Efer, ModelSpecificRegister},
+    base::{
+        interrupts::without_interrupts,
+        msr::{Efer, ModelSpecificRegister},
+    },
+    memory::{direct_map, tlb},
     scheduling::spin::{Guard, SpinLock},
 };
 
 pub(crate) static PTM: GlobalPageTableManager = GlobalPageTableManager::new();
 
-pub(super) const VIRTUAL_PHYSICAL_BASE: u64 = 0xFFFF_8000_0000_0000;
+pub(crate) const VIRTUAL_PHYSICAL_BASE: u64 = 0xFFFF_8000_0000_0000;
 pub(super) const VIRTUAL_DATA_BASE: u64 = 0xFFFF_FFFF_7000_0000;
+/// Amount of pages a huge page of the direct map covers.
+const HUGE_PAGE_PAGES: u64 = (HUGE_PAGE_SIZE / PAGE_SIZE) as u64;
+/// Write protect bit of cr0, which makes read-only pages read-only for the kernel as well.
+const CR0_WRITE_PROTECT: u64 = 1 << 16;
 #[derive(Debug)]
 pub(crate) struct GlobalPageTableManager {
     inner: SpinLock<OnceCell<PageTableManager<'static>>>,
@@ -50,6 +61,10 @@ impl GlobalPageTableManager {
     pub(crate) fn lock(&self) -> Guard<OnceCell<PageTableManager<'static>>> {
         self.inner.lock()
     }
+    /// Locks the page table manager, unless it is locked already, e.g. by the code an exception interrupted.
+    pub(crate) fn try_lock(&self) -> Option<Guard<'_, OnceCell<PageTableManager<'static>>>> {
+        self.inner.try_lock()
+    }
     pub(crate) fn unlock(&self) {
         self.inner.unlock();
     }
@@ -71,8 +86,8 @@ impl GlobalPageTableManager {
 // 0xffff'ffff'8000'0000   --+ <- Kernel code and data segment (Higher half kernel)
 //                           |    Maps to the physical memory containing the kernel image
 //                           |
-// 0xffff'ffff'7000'0000   --+ <- Kernel data (Contains boot info and memory map)
-//                           |    Maps to the physical memory containing kernel data
+// 0xffff'ffff'7000'0000   --+ <- Kernel data (Contains boot info, memory map, font and modules)
+//                           |    Maps to the handoff region of the loader
 //                           |
 // 0xffff'ffff'6000'0000   --+ <- Kernel stack
 //                           |    Maps to the stack pages in physical memory
@@ -81,6 +96,7 @@ impl GlobalPageTableManager {
 //                           |
 // 0xffff'8000'0000'0000   --+ <- Direct-mapped physical memory
 //                           |    Every physical address has a corresponding virtual address
+//                           |    Mapped with 2 MiB pages where possible
 //                           |
 //                           |
 // 0x0000'0000'0000'0000   --+ <- Start of virtual address space
@@ -99,9 +115,12 @@ pub(super) fn setup<'a>(
 
     let mut manager: PageTableManager = PageTableManager::new(pml4_table, frame_allocator);
 
-    let smallest_kernel_stack_addr = smallest_address(&[MemoryType::KernelStack], &memory_map)?;
-    let smallest_kernel_data_addr =
-        smallest_address(&[MemoryType::KernelData, MemoryType::AcpiData], &memory_map)?;
+    // the loader allocates the stack and the handoff region as one unit each
+    unique_descriptor(MemoryType::KernelStack, &memory_map)?;
+    let handoff_address = unique_descriptor(MemoryType::KernelData, &memory_map)?.phys_start;
+    let to_virtual = |physical_address: PhysicalAddress| {
+        physical_address - handoff_address + VIRTUAL_DATA_BASE
+    };
 
     memory_map.descriptors().iter().try_for_each(|desc| {
         let (virtual_base, physical_base, page_entry_flags) = match desc.r#type {
@@ -112,34 +131,49 @@ pub(super) fn setup<'a>(
             ),
             // don't map reserved memory
             MemoryType::Reserved => return Ok::<(), PagingError>(()),
+            // the flags are chosen per page by the segments of the kernel image below
             MemoryType::KernelCode => (
                 KERNEL_MAPPING_OFFSET,
                 desc.phys_start,
                 PageEntryFlags::default(),
             ),
-            MemoryType::KernelStack => (
-                KERNEL_STACK_MAPPING_OFFSET,
-                desc.phys_start - smallest_kernel_stack_addr,
-                PageEntryFlags::default_nx(),
-            ),
-            MemoryType::KernelData => (
-                VIRTUAL_DATA_BASE,
-                desc.phys_start - smallest_kernel_data_addr,
-                PageEntryFlags::default_nx(),
-            ),
+            MemoryType::KernelStack => {
+                (KERNEL_STACK_MAPPING_OFFSET, 0, PageEntryFlags::default_nx())
+            }
+            MemoryType::KernelData => (VIRTUAL_DATA_BASE, 0, PageEntryFlags::default_nx()),
             MemoryType::AcpiData => (
-                VIRTUAL_DATA_BASE,
-                desc.phys_start - smallest_kernel_data_addr,
+                VIRTUAL_PHYSICAL_BASE,
+                desc.phys_start,
                 PageEntryFlags::PRESENT,
             ),
         };
 
-        for page in 0..desc.num_pages {
+        // the direct map is never remapped page by page, so it uses huge pages wherever the descriptor covers them entirely
+        let huge_pages = virtual_base == VIRTUAL_PHYSICAL_BASE;
+        let mut page = 0;
+        while page < desc.num_pages {
             let physical_address = desc.phys_start + page * PAGE_SIZE as u64;
             let virtual_address = virtual_base + physical_base + page * PAGE_SIZE as u64;
+            if huge_pages
+                && physical_address.is_multiple_of(HUGE_PAGE_SIZE as u64)
+                && page + HUGE_PAGE_PAGES <= desc.num_pages
+            {
+                manager
+                    .map_huge_2mb(virtual_address, physical_address, page_entry_flags)
+                    .map_err(PagingError::from)?;
+                page += HUGE_PAGE_PAGES;
+                continue;
+            }
+            let page_entry_flags = match desc.r#type {
+                MemoryType::KernelCode => {
+                    kernel_image_flags(&old_boot_info.kernel_segments, virtual_address)
+                }
+                _ => page_entry_flags,
+            };
             manager
                 .map_memory(virtual_address, physical_address, page_entry_flags)
                 .map_err(PagingError::from)?;
+            page += 1;
         }
 
         Ok(())
@@ -150,21 +184,41 @@ pub(super) fn setup<'a>(
         efer.insert(Efer::NXE);
         efer.write();
     }
+    // writes of the kernel to read-only pages fault as well, so copy-on-write pages are copied before the kernel writes to them
+    unsafe {
+        let cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        asm!("mov cr0, {}", in(reg) cr0 | CR0_WRITE_PROTECT, options(nostack, preserves_flags));
+    }
+
+    // update module addresses, the descriptors are still accessible via their physical address
+    let mut modules = old_boot_info.modules;
+    for module in modules.descriptors_mut() {
+        module.address = to_virtual(module.address);
+    }
+    if !modules.descriptors.is_null() {
+        modules.descriptors = to_virtual(modules.descriptors as u64) as *mut ModuleDescriptor;
+    }
+
+    let mut kernel_symbols = old_boot_info.kernel_symbols;
+    if !kernel_symbols.symbols.is_null() {
+        kernel_symbols.symbols = to_virtual(kernel_symbols.symbols as u64) as *mut KernelSymbol;
+        kernel_symbols.strings = to_virtual(kernel_symbols.strings as u64) as *mut u8;
+    }
 
     let old_font = old_boot_info.font;
     // update boot info
     let boot_info = BootInfo {
         memory_map: MemoryMap {
-            descriptors: (memory_map.descriptors as u64 - smallest_kernel_data_addr
-                + VIRTUAL_DATA_BASE) as *mut MemoryDescriptor,
+            descriptors: to_virtual(memory_map.descriptors as u64) as *mut MemoryDescriptor,
             ..memory_map
         },
         font: Font {
-            glyph_buffer_address: (old_font.glyph_buffer_address as u64 - smallest_kernel_data_addr
-                + VIRTUAL_DATA_BASE) as *const u8,
+            glyph_buffer_address: to_virtual(old_font.glyph_buffer_address as u64) as *const u8,
             ..old_font
         },
-        rsdp: old_boot_info.rsdp - smallest_kernel_data_addr + VIRTUAL_DATA_BASE,
+        modules,
+        kernel_symbols,
         ..*old_boot_info
     };
 
@@ -174,7 +228,7 @@ pub(super) fn setup<'a>(
     unsafe {
         manager.pmm().update(
             old_pmm_bit_map_buffer_address + VIRTUAL_PHYSICAL_BASE,
-            memory_map.descriptors as u64 - smallest_kernel_data_addr + VIRTUAL_DATA_BASE,
+            to_virtual(memory_map.descriptors as u64),
         );
     }
 
@@ -193,6 +247,61 @@ pub(super) fn setup<'a>(
     Ok((manager, boot_info))
 }
 
+/// Returns the flags of the page of the kernel image at the virtual address, as given by the segments containing it. Pages outside of every segment are only readable. Without segments from the loader, the whole image stays writable and executable.
+fn kernel_image_flags(
+    segments: &KernelSegments,
+    virtual_address: VirtualAddress,
+) -> PageEntryFlags {
+    if segments.segments().is_empty() {
+        return PageEntryFlags::default();
+    }
+    let segment_flags = segments.page_flags(virtual_address).unwrap_or_default();
+    let mut flags = PageEntryFlags::PRESENT;
+    if segment_flags.contains(SegmentFlags::WRITE) {
+        flags |= PageEntryFlags::READ_WRITE;
+    }
+    if !segment_flags.contains(SegmentFlags::EXECUTE) {
+        flags |= PageEntryFlags::EXECUTE_DISABLE;
+    }
+    flags
+}
+
+/// Unmaps the boot stack and returns its page frames to the physical memory manager. Must only be called once no code runs on the boot stack anymore, i.e. after the first context switch. The stack region stays unmapped, so stray accesses, e.g. through references to locals of the boot code, fault. Returns the amount of frames released.
+pub(crate) fn release_boot_stack() -> Result<usize, PagingError> {
+    let stack = direct_map::descriptors()
+        .iter()
+        .find(|desc| desc.r#type == MemoryType::KernelStack)
+        .copied()
+        .ok_or(PagingError::InvalidMemoryMap)?;
+    let page_count = stack.num_pages as usize;
+
+    without_interrupts(|| {
+        let mut binding = PTM.lock();
+        let ptm = binding
+            .get_mut()
+            .ok_or(PagingError::GlobalPageTableManagerUninitialized)?;
+        for page in 0..stack.num_pages {
+            let physical_address = stack.phys_start + page * PAGE_SIZE as u64;
+            ptm.unmap(KERNEL_STACK_MAPPING_OFFSET + page * PAGE_SIZE as u64)
+                .map_err(PagingError::from)?;
+            // the frames are handed out like usable memory, which the kernel accesses through the direct map
+            ptm.map_memory(
+                VIRTUAL_PHYSICAL_BASE + physical_address,
+                physical_address,
+                PageEntryFlags::default_nx(),
+            )
+            .map_err(PagingError::from)?;
+        }
+        tlb::invalidate_range(KERNEL_STACK_MAPPING_OFFSET, page_count);
+        direct_map::include_boot_stack();
+        ptm.pmm()
+            .free_reserved_frames(stack.phys_start, page_count)
+            .map_err(PagingError::from)
+    })?;
+
+    Ok(page_count)
+}
+
 /// Switches to the new paging scheme specified by the pml4 address.
 ///
 /// # Safety
@@ -207,6 +316,8 @@ pub(crate) enum PagingError {
     Pml4PointerMisaligned,
     InvalidMemoryMap,
     GlobalPageTableManagerUninitialized,
+    #[allow(dead_code)] // only returned by cross address space copies so far
+    AddressNotMapped(VirtualAddress),
 }
 
 impl Debug for PagingError {
@@ -225,6 +336,11 @@ impl Debug for PagingError {
                 f,
                 "Paging Error: Global page table manager has not been initialized."
             ),
+            PagingError::AddressNotMapped(address) => write!(
+                f,
+                "Paging Error: Virtual address: {:#x} is not mapped.",
+                address
+            ),
         }
     }
 }
@@ -243,16 +359,17 @@ impl From<PageFrameAllocatorError> for PagingError {
     }
 }
 
-/// Returns the smallest physical address that matches the given descriptor type(s) or an error, if the memory map is invalid and does not contain any descriptors matching the specified type(s).
-pub(super) fn smallest_address(
-    match_memory_types: &[MemoryType],
+/// Returns the only descriptor of the given type or an error, if the memory map is invalid and does not contain exactly one descriptor of that type.
+pub(super) fn unique_descriptor(
+    memory_type: MemoryType,
     memory_map: &MemoryMap,
-) -> Result<PhysicalAddress, PagingError> {
-    memory_map
+) -> Result<MemoryDescriptor, PagingError> {
+    let mut descriptors = memory_map
         .descriptors()
         .iter()
-        .filter(|desc| matches!(desc.r#type, t if match_memory_types.contains(&t)))
-        .map(|desc| desc.phys_start)
-        .min()
-        .ok_or(PagingError::InvalidMemoryMap)
+        .filter(|desc| desc.r#type == memory_type);
+    match (descriptors.next(), descriptors.next()) {
+        (Some(descriptor), None) => Ok(*descriptor),
+        _ => Err(PagingError::InvalidMemoryMap),
+    }
 }
@@ -0,0 +1,176 @@
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
+
+use chicken_util::{
+    memory::{pmm::PageFrameAllocator, MemoryDescriptor, MemoryMap, MemoryType},
+    PAGE_SIZE,
+};
+
+use crate::memory::kheap::KERNEL_HEAP_PAGE_COUNT;
+
+/// Page frames backing virtual memory objects allocated during boot, e.g. the stacks of the first tasks.
+const BOOT_VMM_PAGE_COUNT: usize = 0x40; // 256 KiB
+/// Page table entries per page table.
+const ENTRIES_PER_TABLE: u64 = 512;
+/// Additional page tables for the upper paging levels and the fixed kernel mappings.
+const PAGE_TABLE_SLACK: u64 = 16;
+
+/// Amount of memory the kernel reserves for a specific purpose during boot.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct MemoryRequirement {
+    pub(super) purpose: &'static str,
+    pub(super) size: u64,
+}
+
+/// Returns the memory the kernel reserves during boot for the given memory map.
+fn requirements(memory_map: &MemoryMap) -> [MemoryRequirement; 3] {
+    [
+        MemoryRequirement {
+            purpose: "heap",
+            size: (KERNEL_HEAP_PAGE_COUNT * PAGE_SIZE) as u64,
+        },
+        MemoryRequirement {
+            purpose: "page tables",
+            size: page_table_page_count(memory_map) * PAGE_SIZE as u64,
+        },
+        MemoryRequirement {
+            purpose: "vmm",
+            size: (BOOT_VMM_PAGE_COUNT * PAGE_SIZE) as u64,
+        },
+    ]
+}
+
+/// Validates the memory map and checks that there is enough free memory for the fixed reservations of the kernel. Must be called before memory management is set up, so small systems fail early instead of running out of page frames halfway.
+pub(super) fn check(
+    memory_map: &MemoryMap,
+    pmm: &PageFrameAllocator,
+) -> Result<(), MemoryCheckError> {
+    validate(memory_map)?;
+
+    let requirements = requirements(memory_map);
+    let available = pmm.free_memory();
+    if total(&requirements) > available {
+        return Err(MemoryCheckError::InsufficientMemory {
+            requirements,
+            available,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that every descriptor is page aligned, matches its page count and that mapped descriptors do not overlap.
+fn validate(memory_map: &MemoryMap) -> Result<(), MemoryCheckError> {
+    let descriptors = memory_map.descriptors();
+    if descriptors.is_empty() {
+        return Err(MemoryCheckError::EmptyMemoryMap);
+    }
+
+    for (index, desc) in descriptors.iter().enumerate() {
+        if desc.phys_start % PAGE_SIZE as u64 != 0
+            || desc.phys_end < desc.phys_start
+            || desc.size() != desc.num_pages * PAGE_SIZE as u64
+        {
+            return Err(MemoryCheckError::InvalidDescriptor(*desc));
+        }
+        // reserved memory is never handed out or mapped, so firmware quirks there do not matter
+        if desc.r#type == MemoryType::Reserved {
+            continue;
+        }
+        if let Some(other) = descriptors[index + 1..].iter().find(|other| {
+            other.r#type != MemoryType::Reserved
+                && other.phys_start < desc.phys_end
+                && desc.phys_start < other.phys_end
+        }) {
+            return Err(MemoryCheckError::OverlappingDescriptors(*desc, *other));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the sum of the given requirements in bytes.
+fn total(requirements: &[MemoryRequirement]) -> u64 {
+    requirements
+        .iter()
+        .map(|requirement| requirement.size)
+        .sum()
+}
+
+/// Estimates the page tables needed to map all non-reserved memory and the heap. Every descriptor may start a new page table.
+fn page_table_page_count(memory_map: &MemoryMap) -> u64 {
+    let mapped = memory_map
+        .descriptors()
+        .iter()
+        .filter(|desc| desc.r#type != MemoryType::Reserved)
+        .map(|desc| desc.num_pages.div_ceil(ENTRIES_PER_TABLE) + 1)
+        .sum::<u64>();
+    mapped + (KERNEL_HEAP_PAGE_COUNT as u64).div_ceil(ENTRIES_PER_TABLE) + PAGE_TABLE_SLACK
+}
+
+#[derive(Copy, Clone)]
+pub(super) enum MemoryCheckError {
+    EmptyMemoryMap,
+    InvalidDescriptor(MemoryDescriptor),
+    OverlappingDescriptors(MemoryDescriptor, MemoryDescriptor),
+    InsufficientMemory {
+        requirements: [MemoryRequirement; 3],
+        available: u64,
+    },
+}
+
+impl Debug for MemoryCheckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MemoryCheckError::EmptyMemoryMap => {
+                write!(
+                    f,
+                    "Memory Check Error: Memory map does not contain any descriptors."
+                )
+            }
+            MemoryCheckError::InvalidDescriptor(desc) => {
+                write!(
+                    f,
+                    "Memory Check Error: Invalid descriptor in memory map: {}.",
+                    desc
+                )
+            }
+            MemoryCheckError::OverlappingDescriptors(first, second) => write!(
+                f,
+                "Memory Check Error: Overlapping descriptors in memory map: {} and {}.",
+                first, second
+            ),
+            MemoryCheckError::InsufficientMemory {
+                requirements,
+                available,
+            } => {
+                write!(
+                    f,
+                    "Memory Check Error: Not enough memory to boot. Required:"
+                )?;
+                for requirement in requirements {
+                    write!(
+                        f,
+                        " {} {} KiB,",
+                        requirement.purpose,
+                        requirement.size / 1024
+                    )?;
+                }
+                write!(
+                    f,
+                    " total {} KiB. Available: {} KiB.",
+                    total(requirements) / 1024,
+                    available / 1024
+                )
+            }
+        }
+    }
+}
+
+impl Display for MemoryCheckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for MemoryCheckError {}
@@ -0,0 +1,337 @@
+use chicken_util::{
+    memory::{
+        paging::{
+            manager::PageTableManager, PageEntry, PageEntryFlags, PageTable, KERNEL_MAPPING_OFFSET,
+            KERNEL_STACK_MAPPING_OFFSET,
+        },
+        PhysicalAddress, VirtualAddress,
+    },
+    PAGE_SIZE,
+};
+
+use crate::{
+    base::{
+        interrupts::without_interrupts,
+        msr::{Efer, ModelSpecificRegister},
+    },
+    info,
+    memory::{
+        address_space::KERNEL_PML4_ENTRIES,
+        direct_map::{phys_range_to_virt, phys_to_virt},
+        kheap::VIRTUAL_KERNEL_HEAP_BASE,
+        paging::{PTM, VIRTUAL_DATA_BASE, VIRTUAL_PHYSICAL_BASE},
+        vmm::{object::VmFlags, VirtualMemoryManager, VIRTUAL_VMM_BASE, VMM},
+    },
+    scheduling::GlobalTaskScheduler,
+    warn,
+};
+
+/// Time in ms between two walks of the kernel page tables.
+const CHECK_INTERVAL_MS: u64 = 5000;
+/// Bits set in the upper 16 bits of every address of the kernel half.
+const KERNEL_HALF_SIGN_EXTENSION: VirtualAddress = 0xFFFF_0000_0000_0000;
+
+/// Subsystem owning a region of the kernel half, determined by the fixed virtual bases of the memory layout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Owner {
+    DirectMap,
+    KernelStack,
+    BootData,
+    KernelImage,
+    Vmm,
+    Heap,
+}
+
+impl Owner {
+    /// Owners by the base address of their region, in ascending order.
+    const ALL: [(VirtualAddress, Owner); 6] = [
+        (VIRTUAL_PHYSICAL_BASE, Owner::DirectMap),
+        (KERNEL_STACK_MAPPING_OFFSET, Owner::KernelStack),
+        (VIRTUAL_DATA_BASE, Owner::BootData),
+        (KERNEL_MAPPING_OFFSET, Owner::KernelImage),
+        (VIRTUAL_VMM_BASE, Owner::Vmm),
+        (VIRTUAL_KERNEL_HEAP_BASE, Owner::Heap),
+    ];
+
+    /// Returns the owner of the region the address of the kernel half belongs to.
+    fn of(address: VirtualAddress) -> Owner {
+        Self::ALL
+            .iter()
+            .rev()
+            .find(|(base, _)| *base <= address)
+            .map_or(Owner::DirectMap, |(_, owner)| *owner)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Owner::DirectMap => "direct map",
+            Owner::KernelStack => "kernel stack",
+            Owner::BootData => "boot data",
+            Owner::KernelImage => "kernel image",
+            Owner::Vmm => "vmm",
+            Owner::Heap => "heap",
+        }
+    }
+}
+
+/// Invariant of the kernel page tables.
+#[derive(Copy, Clone, Debug)]
+enum Violation {
+    UserAccessible,
+    WritableExecutable,
+    /// A page of the direct map is not mapped to its physical address, or to memory the memory map does not describe as usable or ACPI data.
+    DirectMapMismatch,
+    /// A page outside of the direct map is mapped to a page frame of usable memory that is free, e.g. after its owner has freed it without unmapping it.
+    FreeFrameMapped,
+    /// A page of the vmm region is mapped, although it is not part of an object or is one of its guard pages.
+    StrayVmmPage,
+    VmmFlagsMismatch,
+    /// A page of a vm object is not mapped.
+    MissingVmmPage,
+}
+
+impl Violation {
+    const ALL: [Violation; 7] = [
+        Violation::UserAccessible,
+        Violation::WritableExecutable,
+        Violation::DirectMapMismatch,
+        Violation::FreeFrameMapped,
+        Violation::StrayVmmPage,
+        Violation::VmmFlagsMismatch,
+        Violation::MissingVmmPage,
+    ];
+
+    fn description(&self) -> &'static str {
+        match self {
+            Violation::UserAccessible => "are accessible from user mode",
+            Violation::WritableExecutable => "are writable and executable",
+            Violation::DirectMapMismatch => "are not mapped to their physical address",
+            Violation::FreeFrameMapped => "are mapped to free page frames",
+            Violation::StrayVmmPage => "are mapped outside of any vm object",
+            Violation::VmmFlagsMismatch => "are mapped with other permissions than their vm object",
+            Violation::MissingVmmPage => "of vm objects are not mapped",
+        }
+    }
+}
+
+/// Pages violating each invariant per owner, in 4 KiB pages, and the first address of each of them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Report {
+    pages: [[u64; Violation::ALL.len()]; Owner::ALL.len()],
+    first: [[VirtualAddress; Violation::ALL.len()]; Owner::ALL.len()],
+}
+
+impl Report {
+    fn record(&mut self, owner: Owner, violation: Violation, address: VirtualAddress, pages: u64) {
+        let (owner, violation) = (owner as usize, violation as usize);
+        if self.pages[owner][violation] == 0 {
+            self.first[owner][violation] = address;
+        }
+        self.pages[owner][violation] += pages;
+    }
+
+    fn log(&self) {
+        let mut clean = true;
+        for (_, owner) in Owner::ALL {
+            for violation in Violation::ALL {
+                let pages = self.pages[owner as usize][violation as usize];
+                if pages == 0 {
+                    continue;
+                }
+                clean = false;
+                warn!(
+                    "page table check: {}: {} pages {}, the first at {:#x}.",
+                    owner.name(),
+                    pages,
+                    violation.description(),
+                    self.first[owner as usize][violation as usize]
+                );
+            }
+        }
+        if clean {
+            info!("page table check: The kernel page tables are consistent.");
+        }
+    }
+}
+
+/// Access to a page in effect, which depends on the entries of every level of the page tables.
+#[derive(Copy, Clone, Debug)]
+struct Access {
+    user: bool,
+    writable: bool,
+    executable: bool,
+}
+
+impl Access {
+    /// Restricts the access by the entry of the next level. Pages are only user accessible or writable, if every level allows it, and executable, unless any level forbids it.
+    fn restrict(self, entry: &PageEntry) -> Self {
+        let flags = entry.flags();
+        Self {
+            user: self.user && flags.contains(PageEntryFlags::USER_SUPER),
+            writable: self.writable && flags.contains(PageEntryFlags::READ_WRITE),
+            executable: self.executable && !entry.execute_disabled(),
+        }
+    }
+}
+
+/// Page mapped into the kernel half, either a page or a huge page.
+#[derive(Copy, Clone, Debug)]
+struct Page {
+    address: VirtualAddress,
+    physical: PhysicalAddress,
+    size: u64,
+    access: Access,
+}
+
+/// Walks the kernel page tables every few seconds and logs the violated invariants, whenever they differ from the last walk. Runs as a system service, so the checks happen while the other tasks use the memory managers.
+pub(crate) fn run() {
+    let mut reported = None;
+    loop {
+        match check() {
+            Some(report) if reported != Some(report) => {
+                report.log();
+                reported = Some(report);
+            }
+            Some(_) => {}
+            None => warn!("page table check: Memory managers are unavailable."),
+        }
+        GlobalTaskScheduler::sleep(CHECK_INTERVAL_MS);
+    }
+}
+
+/// Walks the kernel half of the active page tables and checks each mapped page. Runs with interrupts disabled and the memory managers locked, so the mappings do not change meanwhile.
+fn check() -> Option<Report> {
+    // without no-execute support, every page is executable
+    let nx_enabled = Efer::read().is_some_and(|efer| efer.contains(Efer::NXE));
+
+    without_interrupts(|| {
+        // the vmm is locked before the page table manager, like when allocating objects
+        let binding = VMM.lock();
+        let vmm = binding.get()?;
+        let mut binding = PTM.lock();
+        let ptm = binding.get_mut()?;
+
+        let mut report = Report::default();
+        let pml4 = unsafe { &*ptm.pml4_virtual() };
+        let access = Access {
+            user: true,
+            writable: true,
+            executable: true,
+        };
+        for index in KERNEL_PML4_ENTRIES {
+            let base = KERNEL_HALF_SIGN_EXTENSION | (index as u64) << 39;
+            unsafe {
+                walk(&pml4.entries[index], 3, base, access, &mut |page| {
+                    check_page(page, ptm, vmm, nx_enabled, &mut report)
+                })
+            };
+        }
+
+        for (range, flags) in vmm.mapped_ranges() {
+            // backed once they are accessed
+            if flags.contains(VmFlags::LAZY) {
+                continue;
+            }
+            for address in range.step_by(PAGE_SIZE) {
+                let mapped = ptm
+                    .page_entry_mut(address)
+                    .is_some_and(|entry| entry.flags().contains(PageEntryFlags::PRESENT));
+                if !mapped {
+                    report.record(Owner::Vmm, Violation::MissingVmmPage, address, 1);
+                }
+            }
+        }
+        Some(report)
+    })
+}
+
+fn check_page(
+    page: Page,
+    ptm: &mut PageTableManager,
+    vmm: &VirtualMemoryManager,
+    nx_enabled: bool,
+    report: &mut Report,
+) {
+    let owner = Owner::of(page.address);
+    let pages = page.size / PAGE_SIZE as u64;
+    let mut record = |violation| report.record(owner, violation, page.address, pages);
+
+    if page.access.user {
+        record(Violation::UserAccessible);
+    }
+    if nx_enabled && page.access.writable && page.access.executable {
+        record(Violation::WritableExecutable);
+    }
+
+    if owner == Owner::DirectMap {
+        if page.physical != page.address - VIRTUAL_PHYSICAL_BASE
+            || phys_range_to_virt(page.physical, page.size as usize).is_none()
+        {
+            record(Violation::DirectMapMismatch);
+        }
+    } else {
+        // frames of the kernel image, stack and boot data as well as mmio are not part of the direct map
+        let mut frame_free = |frame: PhysicalAddress| {
+            phys_to_virt(frame).is_some() && ptm.pmm().references(frame).is_ok_and(|refs| refs == 0)
+        };
+        if (0..pages).any(|index| frame_free(page.physical + index * PAGE_SIZE as u64)) {
+            record(Violation::FreeFrameMapped);
+        }
+    }
+
+    if owner == Owner::Vmm {
+        let object = vmm
+            .mapped_ranges()
+            .find(|(range, _)| range.contains(&page.address));
+        match object {
+            None => record(Violation::StrayVmmPage),
+            Some((_, flags)) => {
+                let expected = PageEntryFlags::from(flags);
+                if expected.contains(PageEntryFlags::READ_WRITE) != page.access.writable
+                    || expected.contains(PageEntryFlags::USER_SUPER) != page.access.user
+                {
+                    record(Violation::VmmFlagsMismatch);
+                }
+            }
+        }
+    }
+}
+
+/// Visits the pages mapped below the entry with the access in effect. `level` is 3 for page directory pointer tables, 1 for page tables. Entries of the upper levels with the page size bit set map huge pages.
+///
+/// # Safety
+/// The caller must ensure that the page tables are not modified meanwhile, e.g. by holding the lock of the page table manager.
+unsafe fn walk(
+    entry: &PageEntry,
+    level: u32,
+    base: VirtualAddress,
+    access: Access,
+    visit: &mut impl FnMut(Page),
+) {
+    if !entry.flags().contains(PageEntryFlags::PRESENT) {
+        return;
+    }
+    let Some(next) = phys_to_virt(entry.address()) else {
+        return;
+    };
+    let access = access.restrict(entry);
+    let next = &*(next as *const PageTable);
+    let size = 1 << (12 + 9 * (level - 1));
+    for (index, child) in next.entries.iter().enumerate() {
+        let address = base | (index as u64 * size);
+        let flags = child.flags();
+        if !flags.contains(PageEntryFlags::PRESENT) {
+            continue;
+        }
+        if level == 1 || flags.contains(PageEntryFlags::PAT_PAGE_SIZE) {
+            visit(Page {
+                address,
+                physical: child.address(),
+                size,
+                access: access.restrict(child),
+            });
+        } else {
+            walk(child, level - 1, address, access, visit);
+        }
+    }
+}
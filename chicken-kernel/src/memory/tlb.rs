@@ -0,0 +1,36 @@
+use core::arch::asm;
+
+use chicken_util::{memory::VirtualAddress, PAGE_SIZE};
+
+/// Amount of pages above which invalidating a range flushes the entire TLB instead, since refilling it is cheaper than invalidating each page.
+const FULL_FLUSH_THRESHOLD: usize = 32;
+
+// The kernel only runs on the bootstrap processor, so invalidating the TLB of the current cpu is sufficient. Once other processors are started, these functions have to send shootdown IPIs as well.
+
+/// Removes the cached translation of the page containing the virtual address, e.g. after it has been unmapped or its flags have changed.
+pub(crate) fn invalidate_page(address: VirtualAddress) {
+    unsafe { asm!("invlpg [{}]", in(reg) address, options(nostack, preserves_flags)) };
+}
+
+/// Removes the cached translations of `page_count` pages starting at the page containing the virtual address. Large ranges flush the entire TLB.
+pub(crate) fn invalidate_range(start: VirtualAddress, page_count: usize) {
+    if page_count > FULL_FLUSH_THRESHOLD {
+        flush_all();
+        return;
+    }
+    for page in 0..page_count {
+        invalidate_page(start + (page * PAGE_SIZE) as u64);
+    }
+}
+
+/// Removes all cached translations by reloading cr3, e.g. after remapping many pages at once. The kernel does not map global pages, so none of them survive.
+pub(crate) fn flush_all() {
+    unsafe {
+        asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags)
+        )
+    };
+}
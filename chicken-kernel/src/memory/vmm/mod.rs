@@ -1,21 +1,30 @@
-use alloc::alloc::dealloc;
 use core::{
-    alloc::Layout,
     cell::OnceCell,
     error::Error,
     fmt::{Debug, Display, Formatter},
     ptr::NonNull,
 };
+#[cfg(feature = "page-table-check")]
+use core::{iter, ops::Range};
 
 use chicken_util::{
-    memory::{paging::PageEntryFlags, pmm::PageFrameAllocatorError, VirtualAddress},
+    exception::PageFaultErrorCode,
+    memory::{
+        paging::{manager::PageTableManager, PageEntryFlags},
+        pmm::{audit::FramePurpose, PageFrameAllocatorError},
+        VirtualAddress,
+    },
     PAGE_SIZE,
 };
 
 use crate::{
+    base::interrupts::without_interrupts,
     memory::{
         align_up,
+        direct_map::phys_to_virt,
+        kheap::slab::VM_OBJECT_CACHE,
         paging::{PagingError, PTM},
+        tlb,
         vmm::object::{VmFlags, VmObject},
     },
     scheduling::spin::{Guard, SpinLock},
@@ -86,7 +95,10 @@ impl VirtualMemoryManager {
         let mut ptm = PTM.lock();
         if let Some(ptm) = ptm.get_mut() {
             // align length to next valid page size
-            let length = align_up(length as u64, PAGE_SIZE) as usize;
+            let mapped_length = align_up(length as u64, PAGE_SIZE) as usize;
+            // guard pages take up address space, but are never mapped
+            let guard_size = flags.guard_size();
+            let length = mapped_length + 2 * guard_size;
             let mut base = 0;
             let mut current = self.head;
 
@@ -95,6 +107,31 @@ impl VirtualMemoryManager {
                 return Err(VmmError::OutOfMemory);
             }
 
+            // physical address the object is mapped to, unless its pages are backed by any frames. Contiguous frames are requested before the object is created, so a failed request leaves nothing behind.
+            let page_count = mapped_length / PAGE_SIZE;
+            let first_frame = match allocation_type {
+                AllocationType::AnyPages => None,
+                AllocationType::Address(address) => Some(address),
+                AllocationType::Contiguous => {
+                    let order = page_count.next_power_of_two().trailing_zeros() as usize;
+                    let block = ptm
+                        .pmm()
+                        .request_contiguous_for(order, FramePurpose::Vmm)
+                        .map_err(VmmError::from)?;
+                    // the block is rounded up to a power of two, the frames behind the object are returned right away. The others are freed one by one along with the object.
+                    ptm.pmm()
+                        .free_frames(block + mapped_length as u64, (1 << order) - page_count)
+                        .map_err(VmmError::from)?;
+                    Some(block)
+                }
+            };
+
+            // pages of lazy objects are backed by the page fault handler, once they are accessed
+            let lazy = flags.contains(VmFlags::LAZY)
+                && first_frame.is_none()
+                && !flags.contains(VmFlags::MMIO);
+            let resident_pages = if lazy { 0 } else { page_count };
+
             // allocate first object
             if current.is_some() {
                 // allocate new vm object struct on heap
@@ -109,8 +146,16 @@ impl VirtualMemoryManager {
                         if new_base + (length as u64) < current_ref.base {
                             base = new_base;
                             let new_object = unsafe {
-                                VmObject::alloc_new(base, length, flags, current, current_ref.prev)
-                            };
+                                VmObject::alloc_new(
+                                    base,
+                                    length,
+                                    flags,
+                                    resident_pages,
+                                    current,
+                                    current_ref.prev,
+                                )
+                            }
+                            .ok_or(VmmError::OutOfMemory)?;
 
                             prev_ref.next = Some(new_object);
                             current_ref.prev = Some(new_object);
@@ -120,8 +165,17 @@ impl VirtualMemoryManager {
                         // allocate new object before the first one, if possible
                         if (length as u64) < current_ref.base {
                             base = 0;
-                            let new_object =
-                                unsafe { VmObject::alloc_new(base, length, flags, current, None) };
+                            let new_object = unsafe {
+                                VmObject::alloc_new(
+                                    base,
+                                    length,
+                                    flags,
+                                    resident_pages,
+                                    current,
+                                    None,
+                                )
+                            }
+                            .ok_or(VmmError::OutOfMemory)?;
                             current_ref.prev = Some(new_object);
                             break;
                         }
@@ -130,8 +184,10 @@ impl VirtualMemoryManager {
                     // allocate after last object
                     if current_ref.next.is_none() {
                         base = current_ref.base + current_ref.length as u64;
-                        let new_object =
-                            unsafe { VmObject::alloc_new(base, length, flags, None, current) };
+                        let new_object = unsafe {
+                            VmObject::alloc_new(base, length, flags, resident_pages, None, current)
+                        }
+                        .ok_or(VmmError::OutOfMemory)?;
                         current_ref.next = Some(new_object);
                         break;
                     }
@@ -139,18 +195,26 @@ impl VirtualMemoryManager {
                     current = current_ref.next;
                 }
             } else {
-                let new_object = unsafe { VmObject::alloc_new(base, length, flags, None, None) };
+                let new_object =
+                    unsafe { VmObject::alloc_new(base, length, flags, resident_pages, None, None) }
+                        .ok_or(VmmError::OutOfMemory)?;
                 self.head = Some(new_object);
             }
 
             // map pages for newly allocated vm object
-            let page_count = length / PAGE_SIZE;
-            self.pages_allocated += page_count;
+            self.pages_allocated += length / PAGE_SIZE;
+            let base = base + guard_size as u64;
+            if lazy {
+                return Ok(self.vmm_start + base);
+            }
             // immediate backing
             for page in 0..page_count {
-                let physical_address = match allocation_type {
-                    AllocationType::AnyPages => ptm.pmm().request_page().map_err(VmmError::from)?,
-                    AllocationType::Address(address) => address + (page * PAGE_SIZE) as u64,
+                let physical_address = match first_frame {
+                    Some(address) => address + (page * PAGE_SIZE) as u64,
+                    None => ptm
+                        .pmm()
+                        .request_page_for(FramePurpose::Vmm)
+                        .map_err(VmmError::from)?,
                 };
                 let virtual_address = self.vmm_start + base + (page * PAGE_SIZE) as u64;
                 ptm.map_memory(
@@ -175,6 +239,37 @@ impl VirtualMemoryManager {
         }
     }
 
+    /// Returns the amount of pages backed by page frames, i.e. of all objects except MMIO ones without their guard pages.
+    #[cfg(feature = "leak-check")]
+    pub(in crate::memory) fn backed_pages(&self) -> usize {
+        let mut pages = 0;
+        let mut current = self.head;
+        while let Some(object) = current {
+            let object = unsafe { object.as_ref() };
+            if !object.flags.contains(VmFlags::MMIO) {
+                pages += object.resident_pages;
+            }
+            current = object.next;
+        }
+        pages
+    }
+
+    /// Returns the pages of each object backed by page frames or MMIO, i.e. without its guard pages, and the flags they are mapped with. Pages of lazy objects may not have been backed yet.
+    #[cfg(feature = "page-table-check")]
+    pub(in crate::memory) fn mapped_ranges(
+        &self,
+    ) -> impl Iterator<Item = (Range<VirtualAddress>, VmFlags)> + '_ {
+        iter::successors(self.head, |object| unsafe { object.as_ref() }.next).map(|object| {
+            let object = unsafe { object.as_ref() };
+            let guard_size = object.flags.guard_size();
+            let start = self.vmm_start + object.base + guard_size as u64;
+            (
+                start..start + (object.length - 2 * guard_size) as u64,
+                object.flags,
+            )
+        })
+    }
+
     pub(crate) fn free(&mut self, address: VirtualAddress) -> Result<(), VmmError> {
         assert!(address >= self.vmm_start, "Invalid VMM object address");
         let mut ptm = PTM.lock();
@@ -183,15 +278,23 @@ impl VirtualMemoryManager {
             while let Some(current_ref) = current {
                 let current_ref = unsafe { current_ref.as_ref() };
 
-                // check for requested object
-                if current_ref.base == address - self.vmm_start {
-                    let page_count = current_ref.length / PAGE_SIZE;
+                // check for requested object, its address is behind the leading guard page
+                let guard_size = current_ref.flags.guard_size();
+                if current_ref.base + guard_size as u64 == address - self.vmm_start {
+                    let mapped_page_count = (current_ref.length - 2 * guard_size) / PAGE_SIZE;
                     // free regions in vmm memory segment
-                    for page in 0..page_count {
+                    for page in 0..mapped_page_count {
+                        let virtual_address = address + (page * PAGE_SIZE) as u64;
+                        // pages of lazy objects, that have never been accessed, are not backed
+                        let present = ptm
+                            .page_entry_mut(virtual_address)
+                            .is_some_and(|entry| entry.flags().contains(PageEntryFlags::PRESENT));
+                        if !present {
+                            continue;
+                        }
                         // unmap virtual address
-                        let physical_address = ptm
-                            .unmap(address + (page * PAGE_SIZE) as u64)
-                            .map_err(VmmError::from)?;
+                        let physical_address =
+                            ptm.unmap(virtual_address).map_err(VmmError::from)?;
 
                         // free physical page frames
                         if !current_ref.flags.contains(VmFlags::MMIO) {
@@ -200,8 +303,10 @@ impl VirtualMemoryManager {
                                 .map_err(VmmError::from)?;
                         }
                     }
+                    // the frames can not be reused before the translations are invalidated, since the page table manager is still locked
+                    tlb::invalidate_range(address, mapped_page_count);
 
-                    self.pages_allocated -= page_count;
+                    self.pages_allocated -= current_ref.length / PAGE_SIZE;
 
                     // remove object from linked list
                     let heap_ptr = if let Some(mut prev) = current_ref.prev {
@@ -221,9 +326,9 @@ impl VirtualMemoryManager {
                         next_ref.prev = current_ref.prev;
                     }
 
-                    // deallocate vmm struct from heap
+                    // return vmm struct to its cache
                     unsafe {
-                        dealloc(heap_ptr as *mut u8, Layout::new::<VmObject>());
+                        VM_OBJECT_CACHE.free(NonNull::new_unchecked(heap_ptr));
                     }
 
                     return Ok(());
@@ -241,11 +346,92 @@ impl VirtualMemoryManager {
     }
 }
 
+/// Backs the page of a lazy object of the vmm, that has been accessed for the first time, with a zeroed page frame. Returns whether the access is allowed by the object and can be retried. Called by the page fault handler, so it must not use the kernel heap.
+pub(crate) fn resolve_lazy_fault(address: VirtualAddress, error_code: PageFaultErrorCode) -> bool {
+    if error_code.contains(PageFaultErrorCode::PRESENT) {
+        return false;
+    }
+    without_interrupts(|| {
+        // the faulting code may hold the locks itself, e.g. while allocating another object
+        let Some(mut vmm) = VMM.inner.try_lock() else {
+            return false;
+        };
+        let Some(vmm) = vmm.get_mut() else {
+            return false;
+        };
+        let Some(mut binding) = PTM.try_lock() else {
+            return false;
+        };
+        let Some(ptm) = binding.get_mut() else {
+            return false;
+        };
+        let page = address - address % PAGE_SIZE as u64;
+        vmm.back_lazy_page(ptm, page, error_code)
+            .is_ok_and(|backed| backed)
+    })
+}
+
+impl VirtualMemoryManager {
+    /// Backs the page of a lazy object with a zeroed page frame. Returns whether the page belongs to a lazy object, that allows the access.
+    fn back_lazy_page(
+        &mut self,
+        ptm: &mut PageTableManager,
+        page: VirtualAddress,
+        error_code: PageFaultErrorCode,
+    ) -> Result<bool, VmmError> {
+        let Some(offset) = page.checked_sub(self.vmm_start) else {
+            return Ok(false);
+        };
+        let mut current = self.head;
+        while let Some(mut object) = current {
+            let object = unsafe { object.as_mut() };
+            current = object.next;
+
+            let guard_size = object.flags.guard_size() as u64;
+            let start = object.base + guard_size;
+            let end = object.base + object.length as u64 - guard_size;
+            if !(start..end).contains(&offset) {
+                continue;
+            }
+            let allowed = object.flags.contains(VmFlags::LAZY)
+                && (!error_code.contains(PageFaultErrorCode::WRITE)
+                    || object.flags.contains(VmFlags::WRITE))
+                && (!error_code.contains(PageFaultErrorCode::USER)
+                    || object.flags.contains(VmFlags::USER))
+                && (!error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+                    || object.flags.contains(VmFlags::EXECUTABLE));
+            if !allowed {
+                return Ok(false);
+            }
+
+            let frame = ptm
+                .pmm()
+                .request_page_for(FramePurpose::Vmm)
+                .map_err(VmmError::from)?;
+            // zeroed through the direct map, since the page may not be writable
+            let Some(zeroed) = phys_to_virt(frame) else {
+                ptm.pmm().free_frame(frame).map_err(VmmError::from)?;
+                return Ok(false);
+            };
+            unsafe { (zeroed as *mut u8).write_bytes(0, PAGE_SIZE) };
+            if let Err(err) = ptm.map_memory(page, frame, PageEntryFlags::from(object.flags)) {
+                ptm.pmm().free_frame(frame).map_err(VmmError::from)?;
+                return Err(VmmError::from(err));
+            }
+            object.resident_pages += 1;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
 /// Specifies the type of allocation for the virtual memory object
 #[derive(Copy, Clone, Debug)]
 pub(crate) enum AllocationType {
     AnyPages,
     Address(VirtualAddress),
+    /// Physically contiguous page frames, e.g. for buffers a device accesses by their physical address.
+    Contiguous,
 }
 
 #[derive(Copy, Clone)]
@@ -1,22 +1,29 @@
-use alloc::boxed::Box;
 use core::ptr::NonNull;
 
 use bitflags::bitflags;
 
-use chicken_util::memory::{paging::PageEntryFlags, VirtualAddress};
+use chicken_util::{
+    memory::{paging::PageEntryFlags, VirtualAddress},
+    PAGE_SIZE,
+};
+
+use crate::memory::kheap::slab::VM_OBJECT_CACHE;
 
 #[allow(dead_code)] // otherwise, clippy complains about the flags field being 'unused'
 #[derive(Debug)]
-pub(super) struct VmObject {
+pub(in crate::memory) struct VmObject {
     pub(super) base: VirtualAddress,
+    /// Length in bytes including the guard pages, if the object is guarded.
     pub(super) length: usize,
     pub(super) flags: VmFlags,
+    /// Pages backed by page frames. Lazy objects start without any, the page fault handler backs them one by one.
+    pub(super) resident_pages: usize,
     pub(super) next: Option<NonNull<VmObject>>,
     pub(super) prev: Option<NonNull<VmObject>>,
 }
 
 impl VmObject {
-    /// Allocates new `VmObject` struct on the heap. Returns a non-null pointer to the object.
+    /// Allocates new `VmObject` struct from its slab cache. Returns a non-null pointer to the object or `None`, if the kernel heap is out of memory.
     ///
     /// # Safety
     ///
@@ -25,17 +32,18 @@ impl VmObject {
         base: VirtualAddress,
         length: usize,
         flags: VmFlags,
+        resident_pages: usize,
         next: Option<NonNull<VmObject>>,
         prev: Option<NonNull<VmObject>>,
-    ) -> NonNull<VmObject> {
-        let new_object = Box::into_raw(Box::new(VmObject {
+    ) -> Option<NonNull<VmObject>> {
+        VM_OBJECT_CACHE.alloc(VmObject {
             base,
             length,
             flags,
+            resident_pages,
             next,
             prev,
-        }));
-        NonNull::new_unchecked(new_object)
+        })
     }
 }
 
@@ -50,6 +58,21 @@ bitflags! {
         const USER = 1 << 2;
         /// If set, the objects is mapped to MMIO and therefore does not need to request pages when allocated.
         const MMIO = 1 << 3;
+        /// If set, the object is surrounded by an unmapped guard page on each side, so overflowing it causes a page fault instead of silently corrupting its neighbours.
+        const GUARDED = 1 << 4;
+        /// If set, the pages of the object are backed by page frames once they are first accessed, instead of right away. Only applies to objects backed by any page frames.
+        const LAZY = 1 << 5;
+    }
+}
+
+impl VmFlags {
+    /// Size in bytes of the unmapped region on each side of an object with these flags.
+    pub(super) fn guard_size(&self) -> usize {
+        if self.contains(VmFlags::GUARDED) {
+            PAGE_SIZE
+        } else {
+            0
+        }
     }
 }
 
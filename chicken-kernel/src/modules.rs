@@ -0,0 +1,133 @@
+use alloc::vec::Vec;
+use core::{
+    cell::OnceCell,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    slice,
+};
+
+use chicken_util::{module::ModuleDescriptor, BootInfo};
+
+use crate::scheduling::spin::SpinLock;
+
+static MODULES: SpinLock<OnceCell<ModuleRegistry>> = SpinLock::new(OnceCell::new());
+
+/// File loaded by the loader as listed in the boot config.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Module {
+    pub(crate) name: &'static str,
+    pub(crate) data: &'static [u8],
+}
+
+/// Keeps track of the modules handed over by the loader and whether they have been claimed by a subsystem.
+#[derive(Debug)]
+struct ModuleRegistry {
+    modules: Vec<(Module, bool)>,
+}
+
+/// Registers the modules handed over in the boot info. Returns the amount of modules.
+pub(super) fn set_up(boot_info: &BootInfo) -> usize {
+    let modules = boot_info
+        .modules
+        .descriptors()
+        .iter()
+        .map(|descriptor| {
+            // module memory is mapped as kernel data and never freed
+            let descriptor: &'static ModuleDescriptor = unsafe { &*(descriptor as *const _) };
+            let data = unsafe {
+                slice::from_raw_parts(descriptor.address as *const u8, descriptor.size as usize)
+            };
+            (
+                Module {
+                    name: descriptor.name(),
+                    data,
+                },
+                false,
+            )
+        })
+        .collect::<Vec<_>>();
+    let count = modules.len();
+
+    MODULES.lock().get_or_init(|| ModuleRegistry { modules });
+    count
+}
+
+/// Returns all modules, including the claimed ones.
+#[allow(dead_code)] // no module consumers yet
+pub(crate) fn modules() -> Vec<Module> {
+    MODULES
+        .lock()
+        .get()
+        .map(|registry| registry.modules.iter().map(|(module, _)| *module).collect())
+        .unwrap_or_default()
+}
+
+/// Adds a module that has not been handed over by the loader, e.g. a program embedded into the kernel. Fails, if a module of the same name exists already.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn register(module: Module) -> Result<(), ModuleError> {
+    let mut binding = MODULES.lock();
+    let registry = binding.get_mut().ok_or(ModuleError::ModulesUninitialized)?;
+    if registry
+        .modules
+        .iter()
+        .any(|(existing, _)| existing.name == module.name)
+    {
+        return Err(ModuleError::ModuleAlreadyRegistered);
+    }
+    registry.modules.push((module, false));
+    Ok(())
+}
+
+/// Claims the module with the given name, so no other subsystem can claim it.
+pub(crate) fn claim(name: &str) -> Result<Module, ModuleError> {
+    let mut binding = MODULES.lock();
+    let registry = binding.get_mut().ok_or(ModuleError::ModulesUninitialized)?;
+    let (module, claimed) = registry
+        .modules
+        .iter_mut()
+        .find(|(module, _)| module.name == name)
+        .ok_or(ModuleError::ModuleNotFound)?;
+
+    if *claimed {
+        return Err(ModuleError::ModuleAlreadyClaimed);
+    }
+    *claimed = true;
+    Ok(*module)
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum ModuleError {
+    ModulesUninitialized,
+    ModuleNotFound,
+    ModuleAlreadyClaimed,
+    // only used by the kernel self-tests so far
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))]
+    ModuleAlreadyRegistered,
+}
+
+impl Debug for ModuleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ModuleError::ModulesUninitialized => {
+                write!(f, "Module Error: Modules have not been set up.")
+            }
+            ModuleError::ModuleNotFound => {
+                write!(f, "Module Error: Module has not been loaded.")
+            }
+            ModuleError::ModuleAlreadyClaimed => {
+                write!(f, "Module Error: Module has already been claimed.")
+            }
+            ModuleError::ModuleAlreadyRegistered => {
+                write!(f, "Module Error: Module has already been registered.")
+            }
+        }
+    }
+}
+
+impl Display for ModuleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ModuleError {}
@@ -0,0 +1,238 @@
+use alloc::boxed::Box;
+use core::{
+    any::TypeId,
+    fmt::{Debug, Formatter},
+    ops::Deref,
+    ptr::NonNull,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+/// Most references a kernel object may have, so the count can not overflow even if references are leaked.
+const MAX_REFERENCES: usize = isize::MAX as usize;
+
+/// Kernel objects alive right now, indexed by [`ObjectType`].
+static LIVE_OBJECTS: [AtomicUsize; ObjectType::COUNT] =
+    [const { AtomicUsize::new(0) }; ObjectType::COUNT];
+
+/// Type tag of a kernel object, which handle tables check before handing out a reference of a certain type.
+#[allow(dead_code)] // only processes are kernel objects so far
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ObjectType {
+    /// Stands for a process, which stays owned by the scheduler and is freed as soon as it has been removed. The object outlives it.
+    Process,
+    File,
+    Socket,
+    SharedMemory,
+    Channel,
+}
+
+impl ObjectType {
+    /// Must follow the last variant.
+    const COUNT: usize = ObjectType::Channel as usize + 1;
+}
+
+/// Value that lives on the kernel heap as a reference-counted kernel object, e.g. a file or a channel referenced by the handle tables of several processes.
+pub(crate) trait KernelObject: Send + Sync + 'static {
+    const TYPE: ObjectType;
+
+    /// Releases what the object holds outside the kernel heap, e.g. pages or device state, once the last reference has been dropped. Runs right before the value is dropped.
+    fn destroy(&mut self) {}
+}
+
+/// Part of every kernel object shared by all types, so references can be counted and dropped without knowing the type of the value.
+#[derive(Debug)]
+struct ObjectHeader {
+    references: AtomicUsize,
+    object_type: ObjectType,
+    /// Rust type of the value, since several of them may share a type tag.
+    type_id: TypeId,
+    /// Runs the destroy hook and frees the allocation of the object, including the header.
+    release: unsafe fn(NonNull<ObjectHeader>),
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct ObjectInner<T> {
+    /// Placed first, so a pointer to the header points to the whole object.
+    header: ObjectHeader,
+    value: T,
+}
+
+/// Counted reference to a kernel object of a known type, like an `Arc`. The object is destroyed and freed once the last reference has been dropped.
+pub(crate) struct ObjectRef<T: KernelObject> {
+    inner: NonNull<ObjectInner<T>>,
+}
+
+unsafe impl<T: KernelObject> Send for ObjectRef<T> {}
+unsafe impl<T: KernelObject> Sync for ObjectRef<T> {}
+
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+impl<T: KernelObject> ObjectRef<T> {
+    /// Moves the value to the kernel heap as a new kernel object with a single reference.
+    pub(crate) fn new(value: T) -> Self {
+        let inner = Box::new(ObjectInner {
+            header: ObjectHeader {
+                references: AtomicUsize::new(1),
+                object_type: T::TYPE,
+                type_id: TypeId::of::<T>(),
+                release: release::<T>,
+            },
+            value,
+        });
+        LIVE_OBJECTS[T::TYPE as usize].fetch_add(1, Ordering::Relaxed);
+        Self {
+            inner: NonNull::from(Box::leak(inner)),
+        }
+    }
+
+    /// Returns the amount of references to the object, which may have changed by the time the caller looks at it.
+    pub(crate) fn references(this: &Self) -> usize {
+        this.header().references.load(Ordering::Acquire)
+    }
+
+    /// Returns whether both references point to the same object.
+    pub(crate) fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.inner == other.inner
+    }
+
+    /// Turns the reference into one that does not know the type of the object, e.g. to store it in a handle table.
+    pub(crate) fn into_any(this: Self) -> AnyObjectRef {
+        let header = this.inner.cast();
+        core::mem::forget(this);
+        AnyObjectRef { header }
+    }
+
+    fn header(&self) -> &ObjectHeader {
+        unsafe { &self.inner.as_ref().header }
+    }
+}
+
+impl<T: KernelObject> Deref for ObjectRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &self.inner.as_ref().value }
+    }
+}
+
+impl<T: KernelObject> Clone for ObjectRef<T> {
+    fn clone(&self) -> Self {
+        unsafe { acquire(self.inner.cast()) };
+        Self { inner: self.inner }
+    }
+}
+
+impl<T: KernelObject> Drop for ObjectRef<T> {
+    fn drop(&mut self) {
+        unsafe { drop_reference(self.inner.cast()) };
+    }
+}
+
+impl<T: KernelObject + Debug> Debug for ObjectRef<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ObjectRef")
+            .field("references", &Self::references(self))
+            .field("value", &**self)
+            .finish()
+    }
+}
+
+/// Counted reference to a kernel object of any type, e.g. an entry of a handle table. Turned back into a typed reference with [`AnyObjectRef::downcast`], which checks the type of the value.
+pub(crate) struct AnyObjectRef {
+    header: NonNull<ObjectHeader>,
+}
+
+unsafe impl Send for AnyObjectRef {}
+unsafe impl Sync for AnyObjectRef {}
+
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+impl AnyObjectRef {
+    pub(crate) fn object_type(&self) -> ObjectType {
+        self.header().object_type
+    }
+
+    /// Returns the amount of references to the object, which may have changed by the time the caller looks at it.
+    pub(crate) fn references(&self) -> usize {
+        self.header().references.load(Ordering::Acquire)
+    }
+
+    /// Turns the reference into one of the given type, or returns it unchanged, if the object has another type.
+    pub(crate) fn downcast<T: KernelObject>(self) -> Result<ObjectRef<T>, Self> {
+        if self.header().type_id != TypeId::of::<T>() {
+            return Err(self);
+        }
+        let inner = self.header.cast();
+        core::mem::forget(self);
+        Ok(ObjectRef { inner })
+    }
+
+    fn header(&self) -> &ObjectHeader {
+        unsafe { self.header.as_ref() }
+    }
+}
+
+impl Clone for AnyObjectRef {
+    fn clone(&self) -> Self {
+        unsafe { acquire(self.header) };
+        Self {
+            header: self.header,
+        }
+    }
+}
+
+impl Drop for AnyObjectRef {
+    fn drop(&mut self) {
+        unsafe { drop_reference(self.header) };
+    }
+}
+
+impl Debug for AnyObjectRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AnyObjectRef")
+            .field("object_type", &self.object_type())
+            .field("references", &self.references())
+            .finish()
+    }
+}
+
+/// Returns the amount of kernel objects of the given type alive right now, e.g. to find leaked references.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn live_objects(object_type: ObjectType) -> usize {
+    LIVE_OBJECTS[object_type as usize].load(Ordering::Relaxed)
+}
+
+/// Adds a reference to the object.
+///
+/// # Safety
+/// The header must belong to a live object, that the caller holds a reference to.
+unsafe fn acquire(header: NonNull<ObjectHeader>) {
+    // the caller holds a reference, so no ordering with the release of the object is needed
+    let previous = header.as_ref().references.fetch_add(1, Ordering::Relaxed);
+    if previous >= MAX_REFERENCES {
+        panic!("kernel object: reference count overflow");
+    }
+}
+
+/// Drops a reference to the object and releases it, if it has been the last one.
+///
+/// # Safety
+/// The header must belong to a live object and the reference must not be used afterward.
+unsafe fn drop_reference(header: NonNull<ObjectHeader>) {
+    if header.as_ref().references.fetch_sub(1, Ordering::Release) != 1 {
+        return;
+    }
+    // every access through the other references happens before the object is released
+    fence(Ordering::Acquire);
+    (header.as_ref().release)(header);
+}
+
+/// Runs the destroy hook of the object and frees it.
+///
+/// # Safety
+/// The header must belong to an object of type `T` without any references left.
+unsafe fn release<T: KernelObject>(header: NonNull<ObjectHeader>) {
+    let mut inner = Box::from_raw(header.cast::<ObjectInner<T>>().as_ptr());
+    inner.value.destroy();
+    drop(inner);
+    LIVE_OBJECTS[T::TYPE as usize].fetch_sub(1, Ordering::Relaxed);
+}
@@ -0,0 +1,70 @@
+use crate::{
+    base::interrupts::without_interrupts,
+    scheduling::{spin::SpinLock, task::thread::Thread, SchedulerError},
+};
+
+/// Amount of hooks that can be registered for each phase of a context switch. Fixed, so running them takes the same time on every switch.
+pub(crate) const MAX_SWITCH_HOOKS: usize = 4;
+
+/// Hook run on every context switch to another thread. Runs in the scheduler with interrupts disabled, so it must neither use the kernel heap nor the scheduler.
+pub(crate) type SwitchHook = fn(&ContextSwitch);
+
+/// Hooks registered for each phase, indexed by [`SwitchPhase`].
+static HOOKS: SpinLock<[[Option<SwitchHook>; MAX_SWITCH_HOOKS]; 2]> =
+    SpinLock::new([[None; MAX_SWITCH_HOOKS]; 2]);
+
+/// Point of a context switch a hook runs at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SwitchPhase {
+    /// Before the address space of the next thread is activated, e.g. to save state of the previous thread.
+    Before,
+    /// After the address space of the next thread has been activated, right before it resumes, e.g. to load its state.
+    After,
+}
+
+/// Threads taking part in a context switch, handed to the hooks.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ContextSwitch<'a> {
+    /// Thread, that has been running. It may have exited, but it is only removed after the switch.
+    pub(crate) previous: &'a Thread,
+    /// Thread, that runs next.
+    pub(crate) next: &'a Thread,
+}
+
+/// Registers a hook run on every context switch, e.g. for a profiler. Dropping the returned handle unregisters it again.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn register(phase: SwitchPhase, hook: SwitchHook) -> Result<HookHandle, SchedulerError> {
+    without_interrupts(|| {
+        let mut hooks = HOOKS.lock();
+        let (slot, entry) = hooks[phase as usize]
+            .iter_mut()
+            .enumerate()
+            .find(|(_, entry)| entry.is_none())
+            .ok_or(SchedulerError::NoFreeHookSlot)?;
+        *entry = Some(hook);
+        Ok(HookHandle { phase, slot })
+    })
+}
+
+/// Runs the hooks registered for the phase. Called by the scheduler with interrupts disabled.
+pub(in crate::scheduling) fn run(phase: SwitchPhase, switch: &ContextSwitch) {
+    // copied, so the hooks do not run with the lock held
+    let hooks = HOOKS.lock()[phase as usize];
+    for hook in hooks.iter().flatten() {
+        hook(switch);
+    }
+}
+
+/// Registration of a context switch hook, returned by [`register`]. Unregisters the hook, when it is dropped.
+#[derive(Debug)]
+pub(crate) struct HookHandle {
+    phase: SwitchPhase,
+    slot: usize,
+}
+
+impl Drop for HookHandle {
+    fn drop(&mut self) {
+        without_interrupts(|| HOOKS.lock()[self.phase as usize][self.slot] = None);
+    }
+}
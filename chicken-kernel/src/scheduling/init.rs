@@ -0,0 +1,301 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    str,
+};
+
+use crate::{
+    config, info,
+    modules::{self, ModuleError},
+    scheduling::{worker, GlobalTaskScheduler, SchedulerError},
+    warn,
+};
+
+/// Module listing the services to start, one per line. Takes precedence over `init=` on the command line.
+const BOOT_LIST_MODULE_NAME: &str = "init.cfg";
+/// Time in ms between checks whether the supervised services are still alive.
+const SUPERVISE_INTERVAL_MS: u64 = 500;
+/// Amount of times a crashed service is restarted, before the init task gives up on it.
+const MAX_RESTARTS: u32 = 3;
+
+/// System services the init task can start. A service is started after the services it depends on.
+static SERVICES: &[Service] = &[
+    Service {
+        name: "workers",
+        depends_on: &[],
+        // the pool keeps the pids of its workers, so they are not supervised individually
+        start: || worker::set_up().map(|_| None).map_err(InitError::from),
+        restart: false,
+    },
+    #[cfg(feature = "graphics-compositor")]
+    Service {
+        name: "compositor",
+        depends_on: &[],
+        start: || {
+            crate::scheduling::task::spawn_process(
+                crate::video::compositor::run,
+                Some("COMPOSITOR".to_string()),
+            )
+            .map(Some)
+            .map_err(InitError::from)
+        },
+        restart: true,
+    },
+    #[cfg(feature = "page-table-check")]
+    Service {
+        name: "page-table-check",
+        depends_on: &[],
+        start: || {
+            crate::scheduling::task::spawn_process(
+                crate::memory::sanitizer::run,
+                Some("PT-CHECK".to_string()),
+            )
+            .map(Some)
+            .map_err(InitError::from)
+        },
+        restart: true,
+    },
+];
+
+/// System service started by the init task.
+#[derive(Debug)]
+struct Service {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+    /// Starts the service. Returns the pid of its task, if it is supervised.
+    start: fn() -> Result<Option<u64>, InitError>,
+    /// Whether the service is started again, once its task has died.
+    restart: bool,
+}
+
+/// Service whose task is checked by the init task.
+#[derive(Debug)]
+struct Supervised {
+    service: &'static Service,
+    pid: u64,
+    restarts: u32,
+}
+
+/// Services started by the init task, that are restarted once they crash.
+#[derive(Debug)]
+pub(crate) struct Supervisor {
+    supervised: Vec<Supervised>,
+}
+
+/// Starts the services of the boot list in dependency order. Services whose dependencies could not be started are skipped.
+pub(crate) fn start_services() -> Supervisor {
+    let mut supervisor = Supervisor {
+        supervised: Vec::new(),
+    };
+    let order = match boot_order() {
+        Ok(order) => order,
+        Err(err) => {
+            warn!("Could not determine the services to start: {}", err);
+            return supervisor;
+        }
+    };
+
+    let mut failed: Vec<&str> = Vec::new();
+    for service in order {
+        if let Some(dependency) = service
+            .depends_on
+            .iter()
+            .find(|dependency| failed.contains(dependency))
+        {
+            warn!(
+                "Not starting service: {}, since its dependency: {} has failed.",
+                service.name, dependency
+            );
+            failed.push(service.name);
+            continue;
+        }
+
+        match (service.start)() {
+            Ok(pid) => {
+                info!("Started service: {}.", service.name);
+                if let Some(pid) = pid {
+                    supervisor.supervised.push(Supervised {
+                        service,
+                        pid,
+                        restarts: 0,
+                    });
+                }
+            }
+            Err(err) => {
+                warn!("Could not start service: {}: {}", service.name, err);
+                failed.push(service.name);
+            }
+        }
+    }
+    supervisor
+}
+
+impl Supervisor {
+    /// Checks periodically whether the supervised services are still alive and restarts the crashed ones, up to [`MAX_RESTARTS`] times each.
+    pub(crate) fn run(mut self) -> ! {
+        loop {
+            GlobalTaskScheduler::sleep(SUPERVISE_INTERVAL_MS);
+            self.supervised.retain_mut(|supervised| {
+                if GlobalTaskScheduler::task_alive(supervised.pid) {
+                    return true;
+                }
+                let name = supervised.service.name;
+                if !supervised.service.restart || supervised.restarts >= MAX_RESTARTS {
+                    warn!("Service: {} has died and is not restarted.", name);
+                    return false;
+                }
+
+                supervised.restarts += 1;
+                match (supervised.service.start)() {
+                    Ok(Some(pid)) => {
+                        warn!(
+                            "Service: {} has died, restarted it ({}/{}).",
+                            name, supervised.restarts, MAX_RESTARTS
+                        );
+                        supervised.pid = pid;
+                        true
+                    }
+                    Ok(None) => false,
+                    Err(err) => {
+                        warn!("Could not restart service: {}: {}", name, err);
+                        false
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Returns the services of the boot list, each after the services it depends on. Dependencies missing from the boot list are started as well, unknown services are skipped.
+fn boot_order() -> Result<Vec<&'static Service>, InitError> {
+    let mut order = Vec::new();
+    for name in boot_list()? {
+        match service(&name) {
+            Ok(service) => visit(service, &mut Vec::new(), &mut order)?,
+            Err(err) => warn!("Skipping service: {}", err),
+        }
+    }
+    Ok(order)
+}
+
+/// Adds the service to the order after its dependencies. Services on the path are being visited, so finding one of them again is a cycle.
+fn visit(
+    service: &'static Service,
+    path: &mut Vec<&'static str>,
+    order: &mut Vec<&'static Service>,
+) -> Result<(), InitError> {
+    if order.iter().any(|started| started.name == service.name) {
+        return Ok(());
+    }
+    if path.contains(&service.name) {
+        return Err(InitError::DependencyCycle(service.name));
+    }
+
+    path.push(service.name);
+    for dependency in service.depends_on {
+        visit(self::service(dependency)?, path, order)?;
+    }
+    path.pop();
+    order.push(service);
+    Ok(())
+}
+
+/// Returns the names of the services to start, read from the boot list module or the command line. `all` starts every service.
+fn boot_list() -> Result<Vec<String>, InitError> {
+    let list = match modules::claim(BOOT_LIST_MODULE_NAME) {
+        Ok(module) => str::from_utf8(module.data)
+            .map_err(|_| InitError::InvalidBootList)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        Err(ModuleError::ModuleNotFound) => config::with("init", |value| {
+            value
+                .unwrap_or_default()
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(ToString::to_string)
+                .collect()
+        }),
+        Err(err) => return Err(InitError::BootListUnavailable(err)),
+    };
+
+    if list.iter().any(|name| name == "all") {
+        return Ok(SERVICES
+            .iter()
+            .map(|service| service.name.to_string())
+            .collect());
+    }
+    Ok(list)
+}
+
+fn service(name: &str) -> Result<&'static Service, InitError> {
+    SERVICES
+        .iter()
+        .find(|service| service.name == name)
+        .ok_or_else(|| InitError::UnknownService(name.to_string()))
+}
+
+#[derive(Clone)]
+pub(crate) enum InitError {
+    UnknownService(String),
+    DependencyCycle(&'static str),
+    InvalidBootList,
+    BootListUnavailable(ModuleError),
+    SpawnFailed(SchedulerError),
+    WorkersUnavailable(worker::WorkerError),
+}
+
+impl Debug for InitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InitError::UnknownService(name) => {
+                write!(f, "Init Error: Unknown service: {}.", name)
+            }
+            InitError::DependencyCycle(name) => write!(
+                f,
+                "Init Error: Service: {} depends on itself through its dependencies.",
+                name
+            ),
+            InitError::InvalidBootList => write!(
+                f,
+                "Init Error: Boot list: {} is not valid UTF-8.",
+                BOOT_LIST_MODULE_NAME
+            ),
+            InitError::BootListUnavailable(value) => {
+                write!(f, "Init Error: Could not read boot list: {}", value)
+            }
+            InitError::SpawnFailed(value) => {
+                write!(f, "Init Error: Could not spawn service: {}", value)
+            }
+            InitError::WorkersUnavailable(value) => {
+                write!(f, "Init Error: Could not start driver workers: {}", value)
+            }
+        }
+    }
+}
+
+impl Display for InitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for InitError {}
+
+impl From<SchedulerError> for InitError {
+    fn from(value: SchedulerError) -> Self {
+        Self::SpawnFailed(value)
+    }
+}
+
+impl From<worker::WorkerError> for InitError {
+    fn from(value: worker::WorkerError) -> Self {
+        Self::WorkersUnavailable(value)
+    }
+}
@@ -0,0 +1,94 @@
+use core::{
+    fmt::{Display, Formatter},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::stats;
+
+/// Amount of histogram buckets. Bucket `n` counts latencies of less than `2^n` cycles.
+const BUCKET_COUNT: usize = 64;
+
+/// Histogram of the time processes waited for their next time slice, in time stamp counter cycles. Recorded by the timer interrupt, so it must not allocate.
+static BUCKETS: [AtomicU64; BUCKET_COUNT] = [const { AtomicU64::new(0) }; BUCKET_COUNT];
+/// Longest time a process has waited for its next time slice, in cycles.
+static MAX_LATENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Records the time in cycles a process has waited between two of its time slices.
+pub(in crate::scheduling) fn record(cycles: u64) {
+    let bucket = (u64::BITS - cycles.leading_zeros()) as usize;
+    BUCKETS[bucket.min(BUCKET_COUNT - 1)].fetch_add(1, Ordering::Relaxed);
+    MAX_LATENCY.fetch_max(cycles, Ordering::Relaxed);
+}
+
+/// Percentiles of the scheduling latency, i.e. of the time processes waited for their next time slice. Percentiles are upper bounds, since the latencies are recorded in power of two buckets.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct LatencyPercentiles {
+    pub(crate) samples: u64,
+    pub(crate) p50: u64,
+    pub(crate) p90: u64,
+    pub(crate) p99: u64,
+    pub(crate) max: u64,
+    /// Time stamp counter cycles per ms, if the counter could be calibrated yet.
+    pub(crate) cycles_per_ms: Option<u64>,
+}
+
+impl Display for LatencyPercentiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} samples", self.samples)?;
+        for (name, cycles) in [
+            ("p50", self.p50),
+            ("p90", self.p90),
+            ("p99", self.p99),
+            ("max", self.max),
+        ] {
+            match self.cycles_per_ms {
+                Some(cycles_per_ms) => {
+                    write!(f, ", {}: {} us", name, cycles * 1000 / cycles_per_ms)?
+                }
+                None => write!(f, ", {}: {} cycles", name, cycles)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the percentiles of the scheduling latency since boot or the last reset.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn percentiles() -> LatencyPercentiles {
+    let counts = BUCKETS
+        .each_ref()
+        .map(|bucket| bucket.load(Ordering::Relaxed));
+    let samples = counts.iter().sum::<u64>();
+    let max = MAX_LATENCY.load(Ordering::Relaxed);
+
+    let percentile = |percent: u64| {
+        let rank = (samples * percent).div_ceil(100);
+        let mut seen = 0;
+        counts
+            .iter()
+            .position(|count| {
+                seen += count;
+                seen >= rank
+            })
+            // upper bound of the bucket, but never more than the longest recorded latency
+            .map_or(0, |bucket| ((1u64 << bucket.min(63)) - 1).min(max))
+    };
+
+    LatencyPercentiles {
+        samples,
+        p50: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+        max,
+        cycles_per_ms: stats::cycles_per_ms(),
+    }
+}
+
+/// Clears the recorded latencies, e.g. before measuring a specific workload.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn reset() {
+    for bucket in &BUCKETS {
+        bucket.store(0, Ordering::Relaxed);
+    }
+    MAX_LATENCY.store(0, Ordering::Relaxed);
+}
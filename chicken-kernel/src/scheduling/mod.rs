@@ -1,38 +1,77 @@
 use alloc::{
-    alloc::dealloc,
     format,
     string::{String, ToString},
-    vec,
+    vec::Vec,
 };
 use core::{
-    alloc::Layout,
     cell::OnceCell,
     error::Error,
     fmt::{Debug, Display, Formatter},
     ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
 };
 use core::arch::asm;
-use chicken_util::memory::{paging::PageTable, VirtualAddress};
 
-use crate::{base::interrupts::{CpuState, without_interrupts}, hlt_loop, main_task, memory::{
-    paging,
+use chicken_util::timing::read_tsc;
+
+use crate::{base::{gdt, interrupts::{CpuState, without_interrupts}, msr::{FsBase, ModelSpecificRegister}, pmc::{self, PerfCounters}, syscall}, config, debug, main_task, memory::{
+    address_space::AddressSpace,
+    kheap::slab::PROCESS_CACHE,
     paging::{PagingError, PTM},
-    vmm::{VMM, VmmError},
-}, scheduling::{
+    vmm::VmmError,
+}, object::ObjectRef, scheduling::{
+    hooks::{ContextSwitch, SwitchPhase},
+    queue::{ReadyQueues, SleepQueue},
+    rcu::{Rcu, RcuGuard},
     spin::{Guard, SpinLock},
     task::{
-        JoinHandle,
-        process::{copy_higher_half_mappings, NextThread, Process, TaskStatus},
+        capability::Capabilities,
+        process::{Process, ProcessInfo, ProcessObject, TaskStatus},
+        thread::{ExitValue, Thread, ThreadLabel, ThreadMain},
     },
 }};
-use crate::base::io::timer::pit::get_current_uptime_ms;
+use crate::base::interrupts::irq::YIELD_VECTOR;
+use crate::base::io::speaker;
+use crate::base::io::timer::{self, hpet::nanoseconds_since_boot, pit::get_current_uptime_ms};
 use crate::scheduling::task::thread::ThreadStatus;
+pub(crate) mod hooks;
+pub(crate) mod init;
+pub(crate) mod latency;
+pub(crate) mod queue;
+pub(crate) mod rcu;
+pub(crate) mod seqlock;
 pub(crate) mod spin;
+pub(crate) mod sync;
 pub(crate) mod task;
+pub(crate) mod worker;
 
 pub(crate) static SCHEDULER: GlobalTaskScheduler = GlobalTaskScheduler::new();
-pub(super) fn set_up() {
-    GlobalTaskScheduler::init();
+/// Every process that has not been removed yet, in the order of the task list. Kept next to the task list, so it can be listed without the scheduler lock.
+static PROCESSES: Rcu<Vec<ProcessInfo>> = Rcu::new(Vec::new());
+/// Whether the timer interrupt may switch to another task.
+static PREEMPTION: AtomicBool = AtomicBool::new(true);
+/// Timer ticks a thread runs before it is preempted, unless set with `sched_quantum=`.
+const DEFAULT_QUANTUM_TICKS: u64 = 1;
+/// Timer ticks a thread runs before it is preempted.
+static QUANTUM_TICKS: AtomicU64 = AtomicU64::new(DEFAULT_QUANTUM_TICKS);
+/// Timer ticks the active thread has been running since it has been scheduled.
+static SLICE_TICKS: AtomicU64 = AtomicU64::new(0);
+pub(super) fn set_up() -> Result<(), SchedulerError> {
+    set_up_quantum();
+    GlobalTaskScheduler::init()
+}
+
+/// Sets the time slice of threads to the one given with `sched_quantum=`, or the default one.
+pub(crate) fn set_up_quantum() {
+    let quantum = config::integer("sched_quantum")
+        .filter(|quantum| *quantum != 0)
+        .unwrap_or(DEFAULT_QUANTUM_TICKS);
+    QUANTUM_TICKS.store(quantum, Ordering::Relaxed);
+}
+
+/// Counts a timer tick towards the time slice of the active thread. Returns whether it has used up its time slice.
+pub(crate) fn quantum_expired() -> bool {
+    SLICE_TICKS.fetch_add(1, Ordering::Relaxed) + 1 >= QUANTUM_TICKS.load(Ordering::Relaxed)
 }
 
 #[derive(Debug)]
@@ -50,61 +89,27 @@ impl GlobalTaskScheduler {
         }
     }
 
-    /// Initialize Global Task Scheduler.
-    pub(super) fn init() {
+    /// Initialize Global Task Scheduler. Returns an error, if the initial tasks could not be created.
+    pub(super) fn init() -> Result<(), SchedulerError> {
         let scheduler = SCHEDULER.inner.lock();
-        scheduler.get_or_init(|| TaskScheduler::try_new().unwrap());
+        if scheduler.get().is_none() {
+            let _ = scheduler.set(TaskScheduler::try_new()?);
+        }
+        Ok(())
     }
 
     pub(crate) fn lock(&self) -> Guard<OnceCell<TaskScheduler>> {
         self.inner.lock()
     }
 
-    /// Mark currently active thread as dead.
-    pub(crate) fn kill_active() {
-        // loop in case of interrupt during function call
-        loop {
-            without_interrupts(|| {
-                let mut binding = SCHEDULER.lock();
-                if let Some(scheduler) = binding.get_mut() {
-                    assert!(
-                        scheduler.active_task.is_some(),
-                        "Global task scheduler must have at least one active task (IDLE)."
-                    );
-                    let active = unsafe { scheduler.active_task.unwrap().as_mut() };
-                    let thread = unsafe { active.active_thread_ref() };
-                    let mut can_die = true;
-
-                    // check for any joins
-                    if let Some(ref joins) = thread.joins {
-                        // loop through each thread of active process and check if it has been joined & is alive
-                        let mut current_thread = active.main_thread;
-
-                        while let Some(current_thread_ptr) = current_thread {
-                            let thread_ref = unsafe { current_thread_ptr.as_ref() };
-
-                            if thread_ref.tid != thread.tid
-                                && thread_ref.status != ThreadStatus::Dead
-                                && joins.iter().copied().any(|id| id == thread_ref.tid)
-                            {
-                                can_die = false;
-                            }
-
-                            current_thread = thread_ref.next;
-                        }
-                    }
-                    let thread = unsafe { active.active_thread_mut() };
-
-                    if can_die && thread.status != ThreadStatus::Dead {
-                        thread.status = ThreadStatus::Dead;
-                    }
-                }
-            });
-        }
+    /// Terminates the active thread without an exit value.
+    #[allow(dead_code)] // the init task never exits
+    pub(crate) fn kill_active() -> ! {
+        Self::exit(None)
     }
 
-    /// Join the thread specified by the handle to the current one.
-    pub(crate) fn join(handle: JoinHandle) {
+    /// Marks the active thread as dead and stores its exit value until it is collected. The exit value of detached threads is dropped right away.
+    pub(in crate::scheduling) fn exit(exit_value: Option<ExitValue>) -> ! {
         without_interrupts(|| {
             let mut binding = SCHEDULER.lock();
             if let Some(scheduler) = binding.get_mut() {
@@ -113,25 +118,169 @@ impl GlobalTaskScheduler {
                     "Global task scheduler must have at least one active task (IDLE)."
                 );
                 let active = unsafe { scheduler.active_task.unwrap().as_mut() };
-                assert!(
-                    active.active_thread.is_some(),
-                    "Each active task must have at least one active thread (MAIN)."
-                );
                 let thread = unsafe { active.active_thread_mut() };
 
-                if let Some(ref mut joins) = thread.joins {
-                    joins.push(handle.into_inner());
-                } else {
-                    thread.joins = Some(vec![handle.into_inner()]);
+                thread.status = ThreadStatus::Dead;
+                if !thread.detached {
+                    thread.exit_value = exit_value;
+                }
+                if let Some((pid, tid)) = thread.joiner.take() {
+                    scheduler.unblock(pid, tid);
                 }
             }
         });
+
+        // dead threads are never scheduled again, so the cpu is handed on for good
+        Self::yield_now();
+        unreachable!("Dead thread has been scheduled again.")
+    }
+
+    /// Terminates the process that caused a CPU exception and returns its pid together with the context of the next task. Returns `None`, if the exception can not be attributed to a task other than IDLE, e.g. because the scheduler has not started yet.
+    pub(crate) fn kill_faulting(context: *const CpuState) -> Option<(u64, *const CpuState)> {
+        let mut binding = SCHEDULER.lock();
+        let scheduler = binding.get_mut()?;
+        let mut active_task = scheduler.active_task?;
+        if scheduler.head == Some(active_task) {
+            return None;
+        }
+
+        // the faulting instruction must not be executed again, so none of the threads of the process may run anymore
+        let active_ref = unsafe { active_task.as_mut() };
+        active_ref.status = TaskStatus::Dead;
+        let pid = active_ref.pid;
+
+        Some((pid, scheduler.schedule(context, nanoseconds_since_boot())))
+    }
+
+    /// Terminates the task with the specified pid, e.g. if it has exceeded its time limit. Locks held by its threads are never released, so this is only meant for tasks that are known to be stuck. The idle task and the active task can not be killed. Returns whether the task has been killed.
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+    pub(crate) fn kill(pid: u64) -> bool {
+        without_interrupts(|| {
+            let mut binding = SCHEDULER.lock();
+            let Some(scheduler) = binding.get_mut() else {
+                return false;
+            };
+            let is_protected = |task: Option<NonNull<Process>>| {
+                task.is_some_and(|task| unsafe { task.as_ref().pid } == pid)
+            };
+            if is_protected(scheduler.head) || is_protected(scheduler.active_task) {
+                return false;
+            }
+            let killed = scheduler
+                .process_mut(pid)
+                .map(|process| process.status = TaskStatus::Dead)
+                .is_some();
+            scheduler.dead_processes |= killed;
+            killed
+        })
+    }
+
+    /// Returns the pid of the active task.
+    #[allow(dead_code)] // only used by the compositor so far
+    pub(crate) fn current_pid() -> Option<u64> {
+        without_interrupts(|| {
+            let binding = SCHEDULER.lock();
+            let active_task = binding.get()?.active_task?;
+            Some(unsafe { active_task.as_ref().pid })
+        })
+    }
+
+    /// Returns the label of the active thread. Returns `None`, if the scheduler is locked, e.g. because the scheduler itself is logging or has panicked, since waiting for it could deadlock.
+    pub(crate) fn active_thread_label() -> Option<ThreadLabel> {
+        without_interrupts(|| {
+            let binding = SCHEDULER.inner.try_lock()?;
+            let active_task = binding.get()?.active_task?;
+            let active_task = unsafe { active_task.as_ref() };
+            active_task
+                .active_thread
+                .map(|thread| ThreadLabel::new(unsafe { thread.as_ref() }))
+        })
+    }
+
+    /// Returns the instructions and cycles the active thread has been running for. Returns `None`, if the performance counters are disabled.
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+    pub(crate) fn active_thread_counters() -> Option<PerfCounters> {
+        without_interrupts(|| {
+            let binding = SCHEDULER.lock();
+            let active_task = binding.get()?.active_task?;
+            let mut counters = unsafe { active_task.as_ref().active_thread_ref().counters };
+            counters += pmc::since_switch()?;
+            Some(counters)
+        })
+    }
+
+    /// Runs the closure with the address space of every process and the amount of pages of its thread stacks, e.g. to account for the page frames they own.
+    #[cfg(feature = "leak-check")]
+    pub(crate) fn for_each_process(mut f: impl FnMut(&crate::memory::address_space::AddressSpace, usize)) {
+        without_interrupts(|| {
+            let binding = SCHEDULER.lock();
+            let Some(scheduler) = binding.get() else {
+                return;
+            };
+            let mut current = scheduler.head;
+            while let Some(process) = current {
+                let process = unsafe { process.as_ref() };
+                f(&process.address_space, process.stack_pages());
+                current = process.next;
+            }
+        })
+    }
+
+    /// Returns a snapshot of the processes, that have not been removed yet. Neither takes the scheduler lock nor disables interrupts, e.g. to list the processes in the `ps` shell command.
+    #[allow(dead_code)] // no shell available yet
+    pub(crate) fn processes() -> RcuGuard<'static, Vec<ProcessInfo>> {
+        PROCESSES.read()
+    }
+
+    /// Whether the task with the specified pid is still alive.
+    pub(crate) fn task_alive(pid: u64) -> bool {
+        without_interrupts(|| {
+            let mut binding = SCHEDULER.lock();
+            binding
+                .get_mut()
+                .and_then(|scheduler| scheduler.process_mut(pid))
+                .is_some_and(|process| process.status != TaskStatus::Dead)
+        })
+    }
+
+    /// Gives up the rest of the current time slice.
+    pub(crate) fn yield_now() {
+        unsafe { asm!("int {}", const YIELD_VECTOR.index()) }
+    }
+
+    /// Enables or disables preemptive task switching, e.g. to debug long critical sections. While disabled, the active thread keeps running until preemption is enabled again.
+    pub(crate) fn set_preemption(enabled: bool) {
+        // ticks that are already being handled still use the previous setting
+        PREEMPTION.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Whether the timer interrupt may switch to another task.
+    pub(crate) fn preemption_enabled() -> bool {
+        PREEMPTION.load(Ordering::SeqCst)
     }
 
     /// Set the current thread to sleep mode for the provided duration in milliseconds.
     pub(crate) fn sleep(duration_ms: u64) {
+        Self::sleep_until_ns(nanoseconds_since_boot() + duration_ms * 1_000_000);
+    }
+
+    /// Set the current thread to sleep mode until the uptime has reached the provided time in milliseconds. Sleeping threads are parked in the sleep queue, so they cost nothing until they are woken up.
+    #[allow(dead_code)] // no deadlines needed outside of the scheduler yet
+    pub(crate) fn sleep_until(uptime_ms: u64) {
+        Self::sleep_until_ns(uptime_ms * 1_000_000);
+    }
+
+    /// Set the current thread to sleep mode until the monotonic clock has reached the deadline in ns. Deadlines do not depend on the timer ticks, so they stay valid if the timer frequency or source changes meanwhile.
+    fn sleep_until_ns(deadline_ns: u64) {
+        if !Self::preemption_enabled() {
+            // no other thread can run, so wait for the deadline on the current one
+            while nanoseconds_since_boot() < deadline_ns {
+                core::hint::spin_loop();
+            }
+            return;
+        }
+
         without_interrupts(|| {
-            let uptime = get_current_uptime_ms();
             let mut binding = SCHEDULER.lock();
             if let Some(scheduler) = binding.get_mut() {
                 assert!(
@@ -140,11 +289,47 @@ impl GlobalTaskScheduler {
                 );
                 let active = unsafe { scheduler.active_task.unwrap().as_mut() };
                 let thread = unsafe { active.active_thread_mut() };
-                thread.status = ThreadStatus::Sleep(uptime + duration_ms);
+                // parked by the scheduler, once it switches away from the thread
+                thread.status = ThreadStatus::Sleep(deadline_ns);
             }
         });
         // cause context switch
-        unsafe { asm!("int 20h") }
+        Self::yield_now();
+    }
+
+    /// Blocks the active thread until it is unblocked with [`GlobalTaskScheduler::unblock`], e.g. by the thread releasing a lock it waits for. Takes effect once the thread yields. Returns its pid and tid, or `None`, if it can not block, because the scheduler is unavailable or the active thread is the idle thread, which must stay runnable, or preemption is disabled, so no other thread could unblock it.
+    pub(in crate::scheduling) fn block_current() -> Option<(u64, u64)> {
+        if !Self::preemption_enabled() {
+            return None;
+        }
+        without_interrupts(|| {
+            let mut binding = SCHEDULER.lock();
+            let scheduler = binding.get_mut()?;
+            if scheduler.active_task.is_none() || scheduler.active_task == scheduler.head {
+                return None;
+            }
+            scheduler.block_active();
+            scheduler.active_ids()
+        })
+    }
+
+    /// Makes the blocked thread with the specified pid and tid ready again. Threads that are not blocked, e.g. because they have been killed meanwhile, are left as they are. Returns whether the thread has been blocked.
+    pub(in crate::scheduling) fn unblock(pid: u64, tid: u64) -> bool {
+        without_interrupts(|| {
+            SCHEDULER
+                .lock()
+                .get_mut()
+                .is_some_and(|scheduler| scheduler.unblock(pid, tid))
+        })
+    }
+
+    /// Wakes up the sleeping threads of the task with the specified pid before their wake up time, e.g. once there is work for them. Can be called from interrupt handlers.
+    pub(crate) fn wake(pid: u64) {
+        without_interrupts(|| {
+            if let Some(scheduler) = SCHEDULER.lock().get_mut() {
+                scheduler.wake_process(pid);
+            }
+        })
     }
 }
 
@@ -153,6 +338,12 @@ pub(crate) struct TaskScheduler {
     head: Option<NonNull<Process>>,
     active_task: Option<NonNull<Process>>,
     id_counter: u64,
+    /// Threads that are ready to run, apart from the active one and the idle thread.
+    ready: ReadyQueues,
+    /// Sleeping threads, ordered by their wake up time.
+    sleeping: SleepQueue,
+    /// Whether a process has died, that has not been removed yet.
+    dead_processes: bool,
 }
 
 impl TaskScheduler {
@@ -162,219 +353,384 @@ impl TaskScheduler {
             head: None,
             active_task: None,
             id_counter: 0,
+            ready: ReadyQueues::new(),
+            sleeping: SleepQueue::new(),
+            dead_processes: false,
         };
 
-        instance.add_task(Some("IDLE-TASK".to_string()), idle)?;
-        instance.add_task(Some("MAIN-TASK".to_string()), main_task)?;
+        // the initial tasks are part of the kernel
+        instance.add_task(Some("IDLE-TASK".to_string()), idle, Capabilities::all())?;
+        instance.add_task(
+            Some("INIT-TASK".to_string()),
+            main_task,
+            Capabilities::all(),
+        )?;
 
         Ok(instance)
     }
 }
 
-fn idle() {
-    hlt_loop();
-}
+/// Minimum time until the next wake up in us, for which the idle task stops the periodic timer ticks.
+const TICKLESS_MIN_IDLE_US: u64 = 2000;
 
-impl TaskScheduler {
-    pub(crate) fn schedule(&mut self, context: *const CpuState, uptime: u64) -> *const CpuState {
-        if let Some(mut active_task) = self.active_task {
-            let active_task = unsafe { active_task.as_mut() };
-            match active_task.get_next_thread(uptime) {
-                // switch to next process
-                NextThread::None => {
-                    // store state of previously active thread
-                    let currently_active_thread = unsafe { active_task.active_thread_mut() };
-                    if currently_active_thread.status == ThreadStatus::Running {
-                        currently_active_thread.status = ThreadStatus::Ready;
-                    }
-                    currently_active_thread.context = context;
+/// Halts until the next interrupt. If all other threads are sleeping, the timer only fires once the first of them needs to wake up.
+fn idle() {
+    loop {
+        without_interrupts(|| {
+            let wake_up = SCHEDULER.lock().get().and_then(TaskScheduler::next_wake_up);
+            let idle_us = wake_up.map(|wake_up| wake_up.saturating_sub(nanoseconds_since_boot()) / 1000);
+            // a tone of the pc speaker must be stopped on time as well, its end is given in ms of uptime
+            let idle_us = idle_us.map(|idle_us| {
+                speaker::tone_end_ms().map_or(idle_us, |end| {
+                    idle_us.min(end.saturating_sub(get_current_uptime_ms()) * 1000)
+                })
+            });
 
-                    // set active thread to main thread
-                    active_task.active_thread = active_task.main_thread;
-                    unsafe {
-                        active_task.active_thread_mut().status = ThreadStatus::Running;
-                    }
+            if let Some(idle_us) = idle_us {
+                if idle_us >= TICKLESS_MIN_IDLE_US {
+                    unsafe { timer::start_one_shot(idle_us) };
                 }
-                // switch to next process
-                NextThread::TaskDead => {
-                    // mark task as dead, so it gets removed later.
-                    active_task.status = TaskStatus::Dead;
-                }
-                // execute next ready thread in current process
-                NextThread::Found(next_thread) => {
-                    // save state of previously active thread
-                    let active_thread = unsafe { active_task.active_thread_mut() };
-                    if active_thread.status != ThreadStatus::Dead {
-                        active_thread.context = context;
-                        active_thread.status = ThreadStatus::Ready;
-                    }
+            }
+        });
 
-                    // set active thread to found thread
-                    active_task.active_thread = next_thread;
-                    unsafe {
-                        active_task.active_thread_mut().status = ThreadStatus::Running;
-                    }
+        // sti only takes effect after the next instruction, so the timer can not fire before halting
+        unsafe { asm!("sti", "hlt", options(nomem, nostack)) }
 
-                    // return context of next thread
-                    return unsafe { active_task.active_thread_ref().context };
-                }
-            }
-            // no threads are ready in the current process
-            self.switch_processes(active_task, context)
+        // woken up by another interrupt before the timer fired
+        without_interrupts(|| unsafe { timer::stop_one_shot(false) });
+    }
+}
+
+/// Runs the context switch hooks of the phase for the switch between the threads. The references only live as long as the hooks run, since the scheduler modifies the threads in between.
+fn run_hooks(phase: SwitchPhase, previous: NonNull<Thread>, next: NonNull<Thread>) {
+    let switch = unsafe {
+        ContextSwitch {
+            previous: previous.as_ref(),
+            next: next.as_ref(),
+        }
+    };
+    hooks::run(phase, &switch);
+}
+
+impl TaskScheduler {
+    /// Switches from the active thread to the next ready thread of the highest priority. Returns the context of the thread to continue with.
+    pub(crate) fn schedule(&mut self, context: *const CpuState, now_ns: u64) -> *const CpuState {
+        // the next thread starts with a full time slice, even if it is the same one
+        SLICE_TICKS.store(0, Ordering::Relaxed);
+        let Some(mut active_task) = self.active_task else {
+            return self.start_idle();
+        };
+        let active_ref = unsafe { active_task.as_mut() };
+        // charge the events counted meanwhile to the thread that has been running
+        if let Some(elapsed) = pmc::take_since_switch() {
+            unsafe { active_ref.active_thread_mut().counters += elapsed };
+        }
+
+        // store state of previously active thread
+        let active_thread = active_ref.active_thread.unwrap();
+        unsafe { (*active_thread.as_ptr()).context = context };
+        if active_ref.status == TaskStatus::Dead || active_ref.is_dead() {
+            // none of the threads of a dead process may run anymore
+            active_ref.status = TaskStatus::Dead;
+            self.dead_processes = true;
         } else {
-            // first time context switch is called. start with IDLE task
-            let idle = self.head;
-            assert!(idle.is_some(), "Head Process must be idle task");
-            let idle_ref = unsafe { idle.unwrap().as_mut() };
-            idle_ref.status = TaskStatus::Running;
-
-            idle_ref.active_thread = idle_ref.main_thread;
-            unsafe {
-                idle_ref.active_thread_mut().status = ThreadStatus::Running;
+            // remove detached threads that have exited, apart from the one that has just been running
+            active_ref.reap_detached().unwrap();
+            match unsafe { active_thread.as_ref().status } {
+                ThreadStatus::Running => self.make_ready(active_thread),
+                ThreadStatus::Sleep(deadline_ns) => self.sleeping.push(deadline_ns, active_thread),
+                ThreadStatus::Ready | ThreadStatus::Dead | ThreadStatus::Blocked => {}
             }
+        }
 
-            self.active_task = idle;
-            unsafe { idle_ref.active_thread_mut().context }
+        self.wake_sleeping(now_ns);
+        let next_thread = self.next_ready_thread();
+        if self.dead_processes {
+            self.remove_dead_tasks();
         }
+
+        if active_thread == next_thread {
+            return self.switch_to(next_thread);
+        }
+        run_hooks(SwitchPhase::Before, active_thread, next_thread);
+        let context = self.switch_to(next_thread);
+        let next_ref = unsafe { next_thread.as_ref() };
+        // user programs address their thread local storage relative to fs
+        FsBase::new(next_ref.fs_base).write();
+        // interrupts and syscalls arriving in user mode continue on the kernel stack of the thread
+        gdt::set_privileged_stack(next_ref.kernel_stack_top());
+        syscall::set_kernel_stack(next_ref.kernel_stack_top());
+        run_hooks(SwitchPhase::After, active_thread, next_thread);
+        context
     }
 
-    fn switch_processes(
-        &mut self,
-        active_task: &mut Process,
-        context: *const CpuState,
-    ) -> *const CpuState {
-        let next_active_task = self.get_next_process(active_task);
-
-        // set up new next task and remove old one if it's dead
-        if let Some(mut next_active_task) = next_active_task {
-            let next_active_task_ref = unsafe { next_active_task.as_mut() };
-
-            // save currently active state if task is not dead
-            if active_task.status != TaskStatus::Dead {
-                // only one active task left => short circuit
-                if active_task.pid == next_active_task_ref.pid {
-                   return context;
-                }
+    /// Starts the idle task. Called by the first context switch.
+    fn start_idle(&mut self) -> *const CpuState {
+        let idle = self.head;
+        assert!(idle.is_some(), "Head Process must be idle task");
+        let idle_ref = unsafe { idle.unwrap().as_mut() };
+        idle_ref.status = TaskStatus::Running;
 
-                active_task.status = TaskStatus::Ready;
-            }
+        idle_ref.active_thread = idle_ref.main_thread;
+        unsafe {
+            idle_ref.active_thread_mut().status = ThreadStatus::Running;
+        }
 
-            // update new active task
-            next_active_task_ref.status = TaskStatus::Running;
-            self.active_task = Some(next_active_task);
+        self.active_task = idle;
+        unsafe { idle_ref.active_thread_mut().context }
+    }
+
+    /// Marks the thread as ready and appends it to the ready queue of its priority. The idle thread is never queued, it runs whenever no other thread is ready.
+    fn make_ready(&mut self, mut thread: NonNull<Thread>) {
+        let thread_ref = unsafe { thread.as_mut() };
+        if let ThreadStatus::Sleep(deadline_ns) = thread_ref.status {
+            self.sleeping.remove(deadline_ns, thread);
+        }
+        thread_ref.status = ThreadStatus::Ready;
+        if thread_ref.process != self.head {
+            self.ready.push(thread);
+        }
+    }
 
-            // switch to other paging scheme
-            let mut binding = PTM.lock();
-            assert!(
-                binding.get().is_some(),
-                "PTM must be set up when calling scheduler."
-            );
-            let manager = binding.get_mut().unwrap();
+    /// Queues the sleeping threads, whose deadline has passed.
+    fn wake_sleeping(&mut self, now_ns: u64) {
+        while let Some(thread) = self.sleeping.pop_due(now_ns) {
+            self.make_ready(thread);
+        }
+    }
 
-            // copy higher half page tables if kernel mappings have been changed by current process
-            if active_task.update_kernel_mappings {
-                unsafe {
-                    copy_higher_half_mappings(
-                        manager.pml4_virtual(),
-                        next_active_task_ref.page_table_mappings as *mut PageTable,
-                    )
-                    .unwrap();
-                }
+    /// Takes the next thread to run from the ready queues, skipping the threads of processes that have died meanwhile. Returns the idle thread, if no other thread is ready.
+    fn next_ready_thread(&mut self) -> NonNull<Thread> {
+        while let Some(thread) = self.ready.pop() {
+            let process = unsafe { thread.as_ref().process.unwrap().as_ref() };
+            if process.status != TaskStatus::Dead {
+                return thread;
             }
-            let new_mappings_virtual = next_active_task_ref.page_table_mappings as VirtualAddress;
-            let new_mappings_physical =
-                manager.get_physical(next_active_task_ref.page_table_mappings as VirtualAddress);
-
-            assert!(
-                new_mappings_physical.is_some(),
-                "Page table mappings of each process must be set up."
-            );
-            let new_mappings_physical = new_mappings_physical.unwrap();
-            unsafe {
-                paging::enable(new_mappings_physical);
-            }
-            let ptm = binding.get_mut().unwrap();
-            unsafe {
-                ptm.update_pml4(new_mappings_physical);
-                ptm.update_pml4_virtual(new_mappings_virtual);
-            }
-            PTM.unlock();
-            unsafe { next_active_task_ref.main_thread.unwrap().as_ref().context }
-        } else {
-            context
         }
+        unsafe { self.head.unwrap().as_ref().main_thread.unwrap() }
     }
 
-    fn get_next_process(&mut self, active_task: &mut Process) -> Option<NonNull<Process>> {
-        // remove dead tasks from the list and get next active task
-        let mut next_active_task = if active_task.next.is_some() {
-            active_task.next
-        } else {
-            self.head
-        };
+    /// Makes the thread the active one and switches to the address space of its process, if it belongs to another process than the active one. Returns the context of the thread.
+    fn switch_to(&mut self, mut next_thread: NonNull<Thread>) -> *const CpuState {
+        let next_thread_ref = unsafe { next_thread.as_mut() };
+        next_thread_ref.status = ThreadStatus::Running;
+        let mut next_task = next_thread_ref.process.unwrap();
+        let next_task_ref = unsafe { next_task.as_mut() };
+        next_task_ref.active_thread = Some(next_thread);
+
+        let mut active_task = self.active_task.unwrap();
+        if active_task == next_task {
+            return next_thread_ref.context;
+        }
 
-        while let Some(current_task) = next_active_task {
-            let current_ref = unsafe { current_task.as_ref() };
-            // could not find valid task
-            if current_ref.pid == active_task.pid {
-                break;
+        let active_ref = unsafe { active_task.as_mut() };
+        if active_ref.status != TaskStatus::Dead {
+            active_ref.status = TaskStatus::Ready;
+            active_ref.ready_since = read_tsc();
+        }
+
+        // the idle task is always ready, so it would only skew the latencies
+        if self.head != Some(next_task) {
+            latency::record(read_tsc().saturating_sub(next_task_ref.ready_since));
+        }
+
+        // update new active task
+        next_task_ref.status = TaskStatus::Running;
+        self.active_task = Some(next_task);
+
+        // switch to other paging scheme
+        let mut binding = PTM.lock();
+        assert!(
+            binding.get().is_some(),
+            "PTM must be set up when calling scheduler."
+        );
+        let manager = binding.get_mut().unwrap();
+
+        // copy higher half page tables if kernel mappings have been changed by current process
+        if active_ref.update_kernel_mappings {
+            next_task_ref.address_space.sync_kernel_mappings(manager);
+        }
+        unsafe {
+            next_task_ref.address_space.activate(manager);
+        }
+        PTM.unlock();
+        next_thread_ref.context
+    }
+
+    /// Returns the earliest deadline of a sleeping thread in ns, if all threads apart from the idle task are sleeping, blocked or dead. Returns `None`, if a thread is ready or none of them has a deadline, so the periodic ticks keep running.
+    fn next_wake_up(&self) -> Option<u64> {
+        if !self.ready.is_empty() {
+            return None;
+        }
+        self.sleeping.next_wake_up()
+    }
+
+    /// Get mutable reference to the process with the specified pid.
+    pub(in crate::scheduling) fn process_mut(&mut self, pid: u64) -> Option<&mut Process> {
+        let mut current = self.head;
+
+        while let Some(mut current_task) = current {
+            let current_ref = unsafe { current_task.as_mut() };
+            if current_ref.pid == pid {
+                return Some(current_ref);
             }
-            match current_ref.status {
-                // found valid next task
-                TaskStatus::Ready => break,
-                // remove dead task
-                TaskStatus::Dead => self.remove_task(current_ref.pid).unwrap(),
-                TaskStatus::Running => {}
+            current = current_ref.next;
+        }
+
+        None
+    }
 
+    /// Marks the sleeping threads of the process with the specified pid as ready.
+    fn wake_process(&mut self, pid: u64) {
+        let mut current = self.process_mut(pid).and_then(|process| process.main_thread);
+        while let Some(thread) = current {
+            let thread_ref = unsafe { thread.as_ref() };
+            current = thread_ref.next;
+            if let ThreadStatus::Sleep(_) = thread_ref.status {
+                self.make_ready(thread);
             }
+        }
+    }
 
-            // round-robin
-            if current_ref.next.is_some() {
-                next_active_task = current_ref.next;
+    /// Returns the pid and tid of the active thread.
+    pub(in crate::scheduling) fn active_ids(&self) -> Option<(u64, u64)> {
+        let active = unsafe { self.active_task?.as_ref() };
+        let thread = unsafe { active.active_thread_ref() };
+        Some((thread.pid, thread.tid))
+    }
+
+    /// Blocks the active thread until it is unblocked, e.g. by a thread it joins. Takes effect with the next context switch.
+    pub(in crate::scheduling) fn block_active(&mut self) {
+        let active = unsafe { self.active_task.unwrap().as_mut() };
+        unsafe { active.active_thread_mut() }.status = ThreadStatus::Blocked;
+    }
+
+    /// Makes the blocked thread with the specified pid and tid ready again. Threads that have been removed meanwhile are ignored. Returns whether the thread has been blocked.
+    fn unblock(&mut self, pid: u64, tid: u64) -> bool {
+        let thread = self
+            .process_mut(pid)
+            .and_then(|process| process.thread_mut(tid))
+            .filter(|thread| thread.status == ThreadStatus::Blocked)
+            .map(NonNull::from);
+        if let Some(thread) = thread {
+            self.make_ready(thread);
+        }
+        thread.is_some()
+    }
+
+    /// Removes the processes that have died, apart from the active one, whose stack is still in use until another process runs.
+    fn remove_dead_tasks(&mut self) {
+        let mut remaining = false;
+        let mut current = self.head;
+
+        while let Some(task) = current {
+            let task_ref = unsafe { task.as_ref() };
+            current = task_ref.next;
+            if task_ref.status != TaskStatus::Dead {
+                continue;
+            }
+            if self.active_task == Some(task) {
+                remaining = true;
             } else {
-                next_active_task = self.head;
+                self.remove_task(task_ref.pid).unwrap();
             }
         }
 
-        next_active_task
+        self.dead_processes = remaining;
     }
 }
 
 impl TaskScheduler {
-    /// Appends a task to the list of tasks.
-    fn add_task(&mut self, name: Option<String>, entry: fn()) -> Result<(), SchedulerError> {
-        let mut current = self.head;
+    /// Appends a task to the list of tasks. Returns its pid.
+    fn add_task(
+        &mut self,
+        name: Option<String>,
+        entry: fn(),
+        capabilities: Capabilities,
+    ) -> Result<u64, SchedulerError> {
+        // every task ever created has a unique ID
+        self.id_counter += 1;
+
+        let task_ptr = Process::create(
+            name.unwrap_or(format!("TASK-{}", self.id_counter)),
+            entry,
+            self.id_counter,
+            capabilities,
+        )?;
+        self.append_task(task_ptr);
+        Ok(self.id_counter)
+    }
+
+    /// Appends a task, whose main thread runs the main function in the address space, e.g. one a user program has been loaded into. Returns its kernel object.
+    fn add_task_in(
+        &mut self,
+        name: String,
+        main: ThreadMain,
+        capabilities: Capabilities,
+        address_space: AddressSpace,
+    ) -> Result<ObjectRef<ProcessObject>, SchedulerError> {
+        // every task ever created has a unique ID
+        self.id_counter += 1;
+
+        let task_ptr = Process::with_address_space(
+            name,
+            main,
+            self.id_counter,
+            capabilities,
+            address_space,
+        )?;
+        let object = task_ptr
+            .map(|task| unsafe { task.as_ref() }.object.clone())
+            .ok_or(SchedulerError::TaskNotFound(self.id_counter))?;
+        self.append_task(task_ptr);
+        Ok(object)
+    }
+
+    /// Adds a copy of the active task, that runs the entry function in a copy-on-write duplicate of its address space. Returns the pid of the copy.
+    fn fork_task(&mut self, entry: fn()) -> Result<u64, SchedulerError> {
+        let active_task = self.active_task.ok_or(SchedulerError::TaskNotFound(0))?;
 
         // every task ever created has a unique ID
         self.id_counter += 1;
 
+        let task_ptr = unsafe { active_task.as_ref() }.fork(entry, self.id_counter)?;
+        self.append_task(task_ptr);
+        Ok(self.id_counter)
+    }
+
+    /// Appends the task to the end of the list and queues its main thread.
+    fn append_task(&mut self, task_ptr: Option<NonNull<Process>>) {
+        if let Some(task) = task_ptr {
+            let task = unsafe { task.as_ref() };
+            PROCESSES.update(|processes| {
+                processes.push(ProcessInfo {
+                    pid: task.pid,
+                    name: task.name.clone(),
+                })
+            });
+        }
+
+        let mut current = self.head;
+
         if current.is_none() {
-            let task_ptr = Process::create(
-                name.unwrap_or(format!("TASK-{}", self.id_counter)),
-                entry,
-                self.id_counter,
-            )?;
             self.head = task_ptr;
-            return Ok(());
+            return;
         }
 
         while let Some(mut current_task) = current {
             let current_task = unsafe { current_task.as_mut() };
             if current_task.next.is_none() {
-                let task_ptr = Process::create(
-                    name.unwrap_or(format!("TASK-{}", self.id_counter)),
-                    entry,
-                    self.id_counter,
-                )?;
                 let task = unsafe { task_ptr.unwrap().as_mut() };
                 task.prev = current;
 
                 current_task.next = task_ptr;
-                return Ok(());
+                if let Some(main_thread) = task.main_thread {
+                    self.make_ready(main_thread);
+                }
+                return;
             }
             current = current_task.next;
         }
-        Ok(())
     }
 
     /// Removes the specified task from the list. Returns whether the action succeeds. The task to be removed must not be the currently active one.
@@ -416,30 +772,39 @@ impl TaskScheduler {
                     next_ref.prev = current_ref.prev;
                 }
 
+                // references to the process object find out how it has ended
+                current_ref.object.set_exit(current_ref.exit());
+
                 // remove all threads of the process
                 let mut current_thread = current_ref.main_thread;
 
-                while let Some(mut thread) = current_thread {
-                    let thread_ref = unsafe { thread.as_mut() };
-                    current_ref.remove_thread(thread_ref.tid, true)?;
-                    current_thread = thread_ref.next;
+                while let Some(thread) = current_thread {
+                    // the thread is freed by removing it, so it must not be queued anymore
+                    self.ready.remove(thread);
+                    // a thread joining it in another process finds it missing
+                    if let Some((pid, tid)) = unsafe { thread.as_ref().joiner } {
+                        self.unblock(pid, tid);
+                    }
+                    if let ThreadStatus::Sleep(deadline_ns) = unsafe { thread.as_ref().status } {
+                        self.sleeping.remove(deadline_ns, thread);
+                    }
+                    let (tid, next) = unsafe { (thread.as_ref().tid, thread.as_ref().next) };
+                    current_ref.remove_thread(tid, true)?;
+                    current_thread = next;
                 }
 
-                // deallocate the process
+                // free the process's page tables, before the process itself is deallocated
+                current_ref
+                    .address_space
+                    .free()
+                    .map_err(SchedulerError::from)?;
+
+                // return the process to its cache, which drops its name as well
                 unsafe {
-                    dealloc(heap_ptr as *mut u8, Layout::new::<Process>());
+                    PROCESS_CACHE.free(NonNull::new_unchecked(heap_ptr));
                 }
-
-                let mut binding = VMM.lock();
-                let vmm = binding
-                    .get_mut()
-                    .ok_or(SchedulerError::MemoryAllocationError(
-                        VmmError::GlobalVirtualMemoryManagerUninitialized,
-                    ))?;
-
-                // free the process's page tables
-                let pml4_address = current_ref.page_table_mappings as u64;
-                vmm.free(pml4_address).map_err(SchedulerError::from)?;
+                PROCESSES.update(|processes| processes.retain(|process| process.pid != id));
+                debug!("Removed task PID: {}", id);
 
                 return Ok(());
             }
@@ -454,8 +819,16 @@ impl TaskScheduler {
 pub(crate) enum SchedulerError {
     TaskNotFound(u64),
     ThreadNotFound(u64, u64),
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // threads are only joined by the kernel self-tests so far
+    ThreadKilled(u64, u64),
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // threads are only joined by the kernel self-tests so far
+    ThreadExited(u64, u64, i64),
     MemoryAllocationError(VmmError),
     PageTableManagerError(PagingError),
+    MissingCapabilities(u64, Capabilities),
+    /// Every slot for context switch hooks of the phase is taken.
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+    NoFreeHookSlot,
 }
 
 impl Debug for SchedulerError {
@@ -471,12 +844,32 @@ impl Debug for SchedulerError {
                 "Scheduler Error: Could not find thread with TID: {} in task: PID: {}.",
                 tid, pid
             ),
+            SchedulerError::ThreadKilled(pid, tid) => write!(
+                f,
+                "Scheduler Error: Thread with TID: {} in task: PID: {} was killed before returning a value.",
+                tid, pid
+            ),
+            SchedulerError::ThreadExited(pid, tid, status) => write!(
+                f,
+                "Scheduler Error: Thread with TID: {} in task: PID: {} exited with status: {} before returning a value.",
+                tid, pid, status
+            ),
             SchedulerError::MemoryAllocationError(value) => {
                 write!(f, "Scheduler Error: Memory allocation failed: {}", value)
             }
             SchedulerError::PageTableManagerError(value) => {
                 write!(f, "Scheduler Error: Memory mapping failed: {}", value)
             }
+            SchedulerError::MissingCapabilities(pid, capabilities) => write!(
+                f,
+                "Scheduler Error: Task with PID: {} lacks capabilities: {:?}.",
+                pid, capabilities
+            ),
+            SchedulerError::NoFreeHookSlot => write!(
+                f,
+                "Scheduler Error: All {} context switch hook slots are taken.",
+                hooks::MAX_SWITCH_HOOKS
+            ),
         }
     }
 }
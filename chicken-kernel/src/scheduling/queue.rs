@@ -0,0 +1,183 @@
+use alloc::collections::BTreeSet;
+use core::ptr::NonNull;
+
+use crate::scheduling::task::thread::{Priority, Thread};
+
+/// Times a non-empty ready queue may be passed over in favour of a higher priority, before its first thread is raised to the next priority.
+const AGING_ROUNDS: u32 = 16;
+
+/// Threads that are ready to run, one first-in first-out queue per priority. The queues are linked through the threads, so queueing never allocates, e.g. in the timer interrupt.
+#[derive(Debug)]
+pub(in crate::scheduling) struct ReadyQueues {
+    queues: [ThreadQueue; Priority::COUNT],
+    /// Times each queue has been passed over in a row, while it held threads.
+    passed_over: [u32; Priority::COUNT],
+}
+
+impl ReadyQueues {
+    pub(in crate::scheduling) const fn new() -> Self {
+        Self {
+            queues: [const { ThreadQueue::new() }; Priority::COUNT],
+            passed_over: [0; Priority::COUNT],
+        }
+    }
+
+    /// Appends the thread to the queue of its priority. Threads that are queued already keep their place.
+    pub(in crate::scheduling) fn push(&mut self, mut thread: NonNull<Thread>) {
+        let thread_ref = unsafe { thread.as_mut() };
+        if thread_ref.queued_at.is_none() {
+            let level = thread_ref.priority.level();
+            self.queues[level].push(thread, level);
+        }
+    }
+
+    /// Removes the first thread of the highest priority queue, that holds any. Lower queues, that have been passed over for too long, hand their first thread on to the next higher queue.
+    pub(in crate::scheduling) fn pop(&mut self) -> Option<NonNull<Thread>> {
+        let level = (0..Priority::COUNT)
+            .rev()
+            .find(|level| !self.queues[*level].is_empty())?;
+        let thread = self.queues[level].pop();
+        self.passed_over[level] = 0;
+
+        for lower in 0..level {
+            if self.queues[lower].is_empty() {
+                self.passed_over[lower] = 0;
+                continue;
+            }
+            self.passed_over[lower] += 1;
+            if self.passed_over[lower] >= AGING_ROUNDS {
+                self.passed_over[lower] = 0;
+                if let Some(aged) = self.queues[lower].pop() {
+                    self.queues[lower + 1].push(aged, lower + 1);
+                }
+            }
+        }
+
+        thread
+    }
+
+    /// Removes the thread from the queue it is waiting in, e.g. before it is freed. Returns whether it has been queued.
+    pub(in crate::scheduling) fn remove(&mut self, thread: NonNull<Thread>) -> bool {
+        match unsafe { thread.as_ref() }.queued_at {
+            Some(level) => self.queues[level].remove(thread),
+            None => false,
+        }
+    }
+
+    /// Whether no thread is ready to run.
+    pub(in crate::scheduling) fn is_empty(&self) -> bool {
+        self.queues.iter().all(ThreadQueue::is_empty)
+    }
+}
+
+/// Queue of threads, linked through [`Thread::queue_next`].
+#[derive(Debug)]
+struct ThreadQueue {
+    head: Option<NonNull<Thread>>,
+    tail: Option<NonNull<Thread>>,
+}
+
+impl ThreadQueue {
+    const fn new() -> Self {
+        Self {
+            head: None,
+            tail: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    fn push(&mut self, mut thread: NonNull<Thread>, level: usize) {
+        let thread_ref = unsafe { thread.as_mut() };
+        thread_ref.queued_at = Some(level);
+        thread_ref.queue_next = None;
+
+        match self.tail {
+            Some(mut tail) => unsafe { tail.as_mut().queue_next = Some(thread) },
+            None => self.head = Some(thread),
+        }
+        self.tail = Some(thread);
+    }
+
+    fn pop(&mut self) -> Option<NonNull<Thread>> {
+        let mut head = self.head?;
+        let head_ref = unsafe { head.as_mut() };
+        self.head = head_ref.queue_next.take();
+        if self.head.is_none() {
+            self.tail = None;
+        }
+        head_ref.queued_at = None;
+        Some(head)
+    }
+
+    fn remove(&mut self, mut thread: NonNull<Thread>) -> bool {
+        let mut previous: Option<NonNull<Thread>> = None;
+        let mut current = self.head;
+
+        while let Some(current_thread) = current {
+            let next = unsafe { current_thread.as_ref().queue_next };
+            if current_thread == thread {
+                match previous {
+                    Some(mut previous) => unsafe { previous.as_mut().queue_next = next },
+                    None => self.head = next,
+                }
+                if self.tail == Some(thread) {
+                    self.tail = previous;
+                }
+                let thread_ref = unsafe { thread.as_mut() };
+                thread_ref.queued_at = None;
+                thread_ref.queue_next = None;
+                return true;
+            }
+            previous = Some(current_thread);
+            current = next;
+        }
+
+        false
+    }
+}
+
+/// Sleeping threads, ordered by their deadline in ns of the monotonic clock, so the scheduler only looks at the threads that are due.
+#[derive(Debug)]
+pub(in crate::scheduling) struct SleepQueue {
+    /// Deadline and thread, the thread breaks ties between equal deadlines.
+    threads: BTreeSet<(u64, NonNull<Thread>)>,
+}
+
+impl SleepQueue {
+    pub(in crate::scheduling) const fn new() -> Self {
+        Self {
+            threads: BTreeSet::new(),
+        }
+    }
+
+    /// Parks the thread until the deadline.
+    pub(in crate::scheduling) fn push(&mut self, deadline_ns: u64, thread: NonNull<Thread>) {
+        self.threads.insert((deadline_ns, thread));
+    }
+
+    /// Removes the thread, that sleeps until the deadline, e.g. if it is woken up early. Returns whether it has been parked.
+    pub(in crate::scheduling) fn remove(
+        &mut self,
+        deadline_ns: u64,
+        thread: NonNull<Thread>,
+    ) -> bool {
+        self.threads.remove(&(deadline_ns, thread))
+    }
+
+    /// Removes the thread with the earliest deadline, if the monotonic clock has reached it.
+    pub(in crate::scheduling) fn pop_due(&mut self, now_ns: u64) -> Option<NonNull<Thread>> {
+        let (deadline_ns, _) = self.threads.first()?;
+        if *deadline_ns > now_ns {
+            return None;
+        }
+        self.threads.pop_first().map(|(_, thread)| thread)
+    }
+
+    /// Earliest deadline of any thread.
+    pub(in crate::scheduling) fn next_wake_up(&self) -> Option<u64> {
+        self.threads.first().map(|(deadline_ns, _)| *deadline_ns)
+    }
+}
@@ -0,0 +1,139 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    ops::Deref,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::{base::interrupts::without_interrupts, scheduling::spin::SpinLock};
+
+/// Epochs a retired snapshot has to wait for, before it is freed. Readers may still hold it during the epoch it has been retired in and the one before.
+const GRACE_EPOCHS: usize = 2;
+
+/// Read-copy-update cell for lists that are read far more often than written, e.g. the registered devices. Readers neither lock nor disable interrupts, so they can be used from interrupt handlers and may be preempted. Writers replace the whole value with an updated copy, the previous one is freed once no reader can hold it anymore.
+#[derive(Debug)]
+pub(crate) struct Rcu<T> {
+    /// Value until the first update, so the cell can be created without the heap.
+    initial: T,
+    /// Current snapshot on the heap, `null` until the first update.
+    current: AtomicPtr<T>,
+    /// Incremented by writers, once every reader of the previous epoch has left.
+    epoch: AtomicUsize,
+    /// Readers that have entered during an even or odd epoch and not left yet.
+    readers: [AtomicUsize; 2],
+    /// Snapshots that have been replaced, together with the epoch they have been retired in.
+    retired: SpinLock<Vec<(usize, *mut T)>>,
+}
+
+unsafe impl<T> Sync for Rcu<T> where T: Send + Sync {}
+unsafe impl<T> Send for Rcu<T> where T: Send {}
+
+impl<T: Clone> Rcu<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            initial: value,
+            current: AtomicPtr::new(ptr::null_mut()),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            retired: SpinLock::new(Vec::new()),
+        }
+    }
+
+    /// Returns the current snapshot. It is not modified while the guard is held, but updates made meanwhile are not visible through it either.
+    pub(crate) fn read(&self) -> RcuGuard<'_, T> {
+        let slot = loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let slot = epoch % 2;
+            self.readers[slot].fetch_add(1, Ordering::SeqCst);
+            // the writer may have waited for the slot to drain before the reader has entered it
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                break slot;
+            }
+            self.readers[slot].fetch_sub(1, Ordering::SeqCst);
+        };
+
+        let current = self.current.load(Ordering::Acquire);
+        let value = if current.is_null() {
+            &self.initial
+        } else {
+            unsafe { &*current }
+        };
+        RcuGuard {
+            rcu: self,
+            slot,
+            value,
+        }
+    }
+
+    /// Replaces the value with a copy modified by the closure and returns the result of the closure. Snapshots replaced earlier are freed, if their readers have left. Interrupts are disabled meanwhile, so writers never interrupt each other.
+    pub(crate) fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        without_interrupts(|| {
+            let mut retired = self.retired.lock();
+            let mut value = Box::new(self.read().clone());
+            let result = f(&mut value);
+
+            let previous = self.current.swap(Box::into_raw(value), Ordering::AcqRel);
+            if !previous.is_null() {
+                retired.push((self.epoch.load(Ordering::SeqCst), previous));
+            }
+            self.reclaim(&mut retired);
+            result
+        })
+    }
+
+    /// Advances the epoch as far as the readers allow and frees the retired snapshots, whose grace period has passed.
+    fn reclaim(&self, retired: &mut Vec<(usize, *mut T)>) {
+        for _ in 0..GRACE_EPOCHS {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            // the slot of the next epoch is still used by readers of the previous one
+            if self.readers[(epoch + 1) % 2].load(Ordering::SeqCst) != 0 {
+                break;
+            }
+            self.epoch.store(epoch + 1, Ordering::SeqCst);
+        }
+
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        retired.retain(|(retired_epoch, snapshot)| {
+            let expired = epoch - retired_epoch >= GRACE_EPOCHS;
+            if expired {
+                drop(unsafe { Box::from_raw(*snapshot) });
+            }
+            !expired
+        });
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        // nobody can hold a guard anymore
+        let current = *self.current.get_mut();
+        if !current.is_null() {
+            drop(unsafe { Box::from_raw(current) });
+        }
+        for (_, snapshot) in self.retired.lock().drain(..) {
+            drop(unsafe { Box::from_raw(snapshot) });
+        }
+    }
+}
+
+/// Snapshot of the value of an [`Rcu`], that stays valid until the guard is dropped.
+#[derive(Debug)]
+pub(crate) struct RcuGuard<'a, T> {
+    rcu: &'a Rcu<T>,
+    slot: usize,
+    value: &'a T,
+}
+
+impl<T> Deref for RcuGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<T> Drop for RcuGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rcu.readers[self.slot].fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,54 @@
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ptr,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+/// Lock for small values that are read far more often than written, e.g. the uptime. Readers never block the writer and neither lock nor disable interrupts, they read again instead, if the value has been written meanwhile.
+#[derive(Debug)]
+pub(crate) struct SeqLock<T> {
+    /// Incremented before and after each write, so it is odd while the value is being written.
+    sequence: AtomicU64,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SeqLock<T> where T: Send {}
+
+impl<T: Copy> SeqLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a copy of the value, that has not been torn by a write.
+    pub(crate) fn read(&self) -> T {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence & 1 == 0 {
+                let value = unsafe { ptr::read_volatile(self.value.get()) };
+                fence(Ordering::Acquire);
+                if self.sequence.load(Ordering::Relaxed) == sequence {
+                    return value;
+                }
+            }
+            spin_loop();
+        }
+    }
+
+    /// Modifies the value.
+    ///
+    /// # Safety
+    /// The caller must ensure that there is no other writer meanwhile and that the write is not interrupted by a reader, which would wait for it forever, e.g. by writing with interrupts disabled.
+    pub(crate) unsafe fn write(&self, f: impl FnOnce(&mut T)) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence
+            .store(sequence.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        f(&mut *self.value.get());
+        self.sequence
+            .store(sequence.wrapping_add(2), Ordering::Release);
+    }
+}
@@ -29,6 +29,11 @@ impl<T> SpinLock<T> {
         Guard { lock: self }
     }
 
+    /// Acquires the lock, if it is free. Used where waiting could deadlock, e.g. while panicking.
+    pub(crate) fn try_lock(&self) -> Option<Guard<'_, T>> {
+        (!self.locked.swap(true, Acquire)).then(|| Guard { lock: self })
+    }
+
     pub(crate) fn unlock(&self) {
         self.locked.store(false, Release);
     }
@@ -0,0 +1,63 @@
+use crate::{
+    base::interrupts::without_interrupts,
+    scheduling::{
+        spin::SpinLock,
+        sync::{
+            mutex::MutexGuard,
+            wait_queue::{self, WaitQueue},
+        },
+    },
+};
+
+/// Condition variable, lets threads block until a value protected by a [`Mutex`](super::mutex::Mutex) changes, e.g. until data has arrived in a queue. Waiting threads may wake up without having been notified, so they check the condition again, which [`Condvar::wait_while`] does. Must not be waited on in interrupt handlers, which can not block.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+#[derive(Debug)]
+pub(crate) struct Condvar {
+    waiters: SpinLock<WaitQueue>,
+}
+
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+impl Condvar {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new(WaitQueue::new()),
+        }
+    }
+
+    /// Releases the mutex and blocks the current thread until it is notified, then acquires the mutex again. The thread is queued before the mutex is released, so a notification sent right after is not lost.
+    pub(crate) fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let blocked = without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            let blocked = waiters.block_current();
+            drop(guard);
+            blocked
+        });
+        wait_queue::wait(blocked);
+        mutex.lock()
+    }
+
+    /// Blocks the current thread as long as the condition holds for the value protected by the mutex, releasing the mutex while it waits.
+    pub(crate) fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes up the thread, that has been waiting the longest. Never blocks, so interrupt handlers may notify.
+    pub(crate) fn notify_one(&self) {
+        without_interrupts(|| {
+            self.waiters.lock().wake_one();
+        })
+    }
+
+    /// Wakes up every waiting thread.
+    pub(crate) fn notify_all(&self) {
+        without_interrupts(|| self.waiters.lock().wake_all())
+    }
+}
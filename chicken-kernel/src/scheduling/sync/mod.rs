@@ -0,0 +1,5 @@
+pub(crate) mod condvar;
+pub(crate) mod mutex;
+pub(crate) mod rwlock;
+pub(crate) mod semaphore;
+pub(in crate::scheduling) mod wait_queue;
@@ -0,0 +1,104 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    base::interrupts::without_interrupts,
+    scheduling::{
+        spin::SpinLock,
+        sync::wait_queue::{self, WaitQueue},
+    },
+};
+
+/// Lock for values that may be held for long, e.g. across allocations or device accesses. Threads that find it locked block until it is released, instead of spinning with interrupts disabled, so the other threads keep running meanwhile. Must not be locked in interrupt handlers, which can not block.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+#[derive(Debug)]
+pub(crate) struct Mutex<T> {
+    state: SpinLock<MutexState>,
+    value: UnsafeCell<T>,
+}
+
+#[derive(Debug)]
+struct MutexState {
+    locked: bool,
+    /// Threads waiting for the mutex to be released. Woken up one at a time, each of them tries again.
+    waiters: WaitQueue,
+}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+unsafe impl<T> Send for Mutex<T> where T: Send {}
+
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+impl<T> Mutex<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            state: SpinLock::new(MutexState {
+                locked: false,
+                waiters: WaitQueue::new(),
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the mutex, blocking the current thread until it is released.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            match self.try_acquire(true) {
+                Ok(guard) => return guard,
+                Err(blocked) => wait_queue::wait(blocked),
+            }
+        }
+    }
+
+    /// Acquires the mutex, if it is free.
+    pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.try_acquire(false).ok()
+    }
+
+    /// Acquires the mutex, if it is free. Otherwise blocks the current thread in the wait queue, if `block` is set, and returns whether it has blocked.
+    fn try_acquire(&self, block: bool) -> Result<MutexGuard<'_, T>, bool> {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            if !state.locked {
+                state.locked = true;
+                return Ok(MutexGuard { mutex: self });
+            }
+            Err(block && state.waiters.block_current())
+        })
+    }
+
+    fn unlock(&self) {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            state.locked = false;
+            state.waiters.wake_one();
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MutexGuard<'a, T> {
+    /// Locked mutex, which a [`Condvar`](super::condvar::Condvar) acquires again after waiting.
+    pub(super) mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,158 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    base::interrupts::without_interrupts,
+    scheduling::{
+        spin::SpinLock,
+        sync::wait_queue::{self, WaitQueue},
+    },
+};
+
+/// Lock for values that are mostly read and may be held for long. Any amount of readers or a single writer hold it at a time, threads that have to wait block until it is released. Waiting writers go first, so a steady stream of readers can not starve them. Must not be locked in interrupt handlers, which can not block.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+#[derive(Debug)]
+pub(crate) struct RwLock<T> {
+    state: SpinLock<RwLockState>,
+    value: UnsafeCell<T>,
+}
+
+#[derive(Debug)]
+struct RwLockState {
+    readers: usize,
+    writer: bool,
+    /// Writers waiting for the lock, including those that spin instead of blocking. New readers wait as long as there are any.
+    waiting_writers: usize,
+    blocked_readers: WaitQueue,
+    blocked_writers: WaitQueue,
+}
+
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+unsafe impl<T> Send for RwLock<T> where T: Send {}
+
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+impl<T> RwLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            state: SpinLock::new(RwLockState {
+                readers: 0,
+                writer: false,
+                waiting_writers: 0,
+                blocked_readers: WaitQueue::new(),
+                blocked_writers: WaitQueue::new(),
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the lock for reading, blocking the current thread while a writer holds or waits for it.
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            let blocked = without_interrupts(|| {
+                let mut state = self.state.lock();
+                if !state.writer && state.waiting_writers == 0 {
+                    state.readers += 1;
+                    return None;
+                }
+                Some(state.blocked_readers.block_current())
+            });
+            match blocked {
+                None => return RwLockReadGuard { lock: self },
+                Some(blocked) => wait_queue::wait(blocked),
+            }
+        }
+    }
+
+    /// Acquires the lock for writing, blocking the current thread while any reader or writer holds it.
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let mut waiting = false;
+        loop {
+            let blocked = without_interrupts(|| {
+                let mut state = self.state.lock();
+                if !state.writer && state.readers == 0 {
+                    state.writer = true;
+                    if waiting {
+                        state.waiting_writers -= 1;
+                    }
+                    return None;
+                }
+                if !waiting {
+                    state.waiting_writers += 1;
+                    waiting = true;
+                }
+                Some(state.blocked_writers.block_current())
+            });
+            match blocked {
+                None => return RwLockWriteGuard { lock: self },
+                Some(blocked) => wait_queue::wait(blocked),
+            }
+        }
+    }
+
+    fn read_unlock(&self) {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            state.readers -= 1;
+            if state.readers == 0 {
+                state.blocked_writers.wake_one();
+            }
+        })
+    }
+
+    fn write_unlock(&self) {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            state.writer = false;
+            // the readers keep waiting for the next writer anyway
+            if !state.blocked_writers.wake_one() {
+                state.blocked_readers.wake_all();
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
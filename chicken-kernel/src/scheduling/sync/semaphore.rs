@@ -0,0 +1,74 @@
+use crate::{
+    base::interrupts::without_interrupts,
+    scheduling::{
+        spin::SpinLock,
+        sync::wait_queue::{self, WaitQueue},
+    },
+};
+
+/// Counting semaphore, e.g. for the amount of items in a queue filled by an interrupt handler. Threads that find no permit left block until one is released, instead of polling. Releasing never blocks, so interrupt handlers may do it, acquiring must not be done in them.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+#[derive(Debug)]
+pub(crate) struct Semaphore {
+    state: SpinLock<SemaphoreState>,
+}
+
+#[derive(Debug)]
+struct SemaphoreState {
+    permits: usize,
+    /// Threads waiting for a permit. Woken up one per released permit, each of them tries again.
+    waiters: WaitQueue,
+}
+
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+impl Semaphore {
+    pub(crate) const fn new(permits: usize) -> Self {
+        Self {
+            state: SpinLock::new(SemaphoreState {
+                permits,
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Takes a permit, blocking the current thread until one is available.
+    pub(crate) fn acquire(&self) {
+        loop {
+            match self.try_take(true) {
+                Ok(()) => return,
+                Err(blocked) => wait_queue::wait(blocked),
+            }
+        }
+    }
+
+    /// Takes a permit, if one is available.
+    pub(crate) fn try_acquire(&self) -> bool {
+        self.try_take(false).is_ok()
+    }
+
+    /// Takes a permit, if one is available. Otherwise blocks the current thread in the wait queue, if `block` is set, and returns whether it has blocked.
+    fn try_take(&self, block: bool) -> Result<(), bool> {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.permits > 0 {
+                state.permits -= 1;
+                return Ok(());
+            }
+            Err(block && state.waiters.block_current())
+        })
+    }
+
+    /// Adds a permit and wakes up a thread waiting for one.
+    pub(crate) fn release(&self) {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            state.permits += 1;
+            state.waiters.wake_one();
+        })
+    }
+
+    /// Returns the amount of permits available, which may have changed by the time the caller looks at it.
+    pub(crate) fn available(&self) -> usize {
+        without_interrupts(|| self.state.lock().permits)
+    }
+}
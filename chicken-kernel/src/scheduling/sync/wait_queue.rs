@@ -0,0 +1,54 @@
+use alloc::collections::VecDeque;
+use core::hint::spin_loop;
+
+use crate::scheduling::GlobalTaskScheduler;
+
+/// Threads blocked until a condition holds, e.g. until a lock has been released. The queue is protected by the spin lock of the primitive it belongs to, which is held while checking the condition and blocking, so no wake up is lost in between.
+#[derive(Debug)]
+pub(in crate::scheduling) struct WaitQueue {
+    /// Pid and tid of the blocked threads, in the order they have blocked.
+    waiters: VecDeque<(u64, u64)>,
+}
+
+impl WaitQueue {
+    pub(in crate::scheduling) const fn new() -> Self {
+        Self {
+            waiters: VecDeque::new(),
+        }
+    }
+
+    /// Blocks the active thread in the queue. Takes effect once it yields, which the caller does with [`wait`] after releasing the spin lock. Returns whether the thread has blocked, threads that can not block spin instead, e.g. while preemption is disabled.
+    pub(in crate::scheduling) fn block_current(&mut self) -> bool {
+        match GlobalTaskScheduler::block_current() {
+            Some(waiter) => {
+                self.waiters.push_back(waiter);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Unblocks the thread, that has been waiting the longest. Threads that have been killed meanwhile are skipped. Returns whether a thread has been unblocked.
+    pub(in crate::scheduling) fn wake_one(&mut self) -> bool {
+        while let Some((pid, tid)) = self.waiters.pop_front() {
+            if GlobalTaskScheduler::unblock(pid, tid) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Unblocks every waiting thread.
+    pub(in crate::scheduling) fn wake_all(&mut self) {
+        while self.wake_one() {}
+    }
+}
+
+/// Waits after a failed attempt to acquire a primitive, before trying again. Yields, if the thread has blocked in a wait queue, or spins otherwise.
+pub(in crate::scheduling) fn wait(blocked: bool) {
+    if blocked {
+        GlobalTaskScheduler::yield_now();
+    } else {
+        spin_loop();
+    }
+}
@@ -0,0 +1,16 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Privileges of a process, checked before it may perform the corresponding operation. A new process inherits at most the capabilities of the process spawning it. Capabilities can be dropped, but never regained.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub(crate) struct Capabilities: u64 {
+        /// Access io ports and device memory directly.
+        const RAW_IO    = 1 << 0;
+        /// Spawn new processes.
+        const SPAWN     = 1 << 1;
+        /// Use network devices.
+        const NET       = 1 << 2;
+        /// Modify files.
+        const FS_WRITE  = 1 << 3;
+    }
+}
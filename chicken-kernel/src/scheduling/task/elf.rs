@@ -0,0 +1,350 @@
+use alloc::vec::Vec;
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    mem::size_of,
+    ptr,
+};
+
+use chicken_util::{
+    memory::{paging::PageEntryFlags, pmm::audit::FramePurpose, VirtualAddress},
+    PAGE_SIZE,
+};
+use goblin::elf::{
+    header::{EM_X86_64, ET_DYN, ET_EXEC},
+    program_header::{PF_W, PF_X, PT_LOAD, PT_PHDR, PT_TLS},
+    reloc::{R_X86_64_NONE, R_X86_64_RELATIVE},
+    Elf, ProgramHeader,
+};
+
+use crate::{
+    base::{interrupts::without_interrupts, random},
+    memory::{
+        address_space::AddressSpace,
+        direct_map::phys_to_virt,
+        paging::{PagingError, PTM},
+        vmm::object::VmFlags,
+    },
+    scheduling::task::startup::ProgramImage,
+};
+
+/// Lowest address position independent programs are loaded at. The first pages stay unmapped, so null pointer accesses fault.
+const PIE_LOAD_BASE: VirtualAddress = 0x40_0000;
+/// Size of the range above [`PIE_LOAD_BASE`] the load address of position independent programs is randomly chosen from, so their addresses can not be predicted.
+const PIE_RANDOM_RANGE: u64 = 1 << 40;
+/// Size of the thread control block the thread pointer points at. Compilers expect the pointer to itself at offset 0 and the stack protector canary at [`STACK_CANARY_OFFSET`].
+const TCB_SIZE: usize = 0x30;
+const STACK_CANARY_OFFSET: usize = 0x28;
+/// Addresses above belong to the kernel half of the address space.
+pub(in crate::scheduling::task) const USER_ADDRESS_LIMIT: VirtualAddress = 0x0000_8000_0000_0000;
+
+/// Loads the ELF64 program into the user half of the address space. Each loadable segment is mapped with the permissions of its flags, position independent programs are relocated to a random address and the initial TLS block is set up. Returns the location of the loaded image, e.g. to build the auxiliary vector of the program.
+pub(crate) fn load(address_space: &AddressSpace, data: &[u8]) -> Result<ProgramImage, ElfError> {
+    let elf = Elf::parse(data).map_err(|_| ElfError::InvalidElf)?;
+    if !elf.is_64 || !elf.little_endian || elf.header.e_machine != EM_X86_64 {
+        return Err(ElfError::UnsupportedArchitecture(elf.header.e_machine));
+    }
+    let load_bias = match elf.header.e_type {
+        ET_EXEC => 0,
+        ET_DYN => random_load_bias(&elf),
+        other => return Err(ElfError::UnsupportedType(other)),
+    };
+
+    // pages are released again, if the program can not be loaded completely
+    let mut mapped = Vec::new();
+    let result = load_segments(address_space, &elf, data, load_bias, &mut mapped)
+        .and_then(|()| relocate(address_space, &elf, load_bias))
+        .and_then(|()| load_tls(address_space, &elf, data, load_bias, &mut mapped));
+    let thread_pointer = match result {
+        Ok(thread_pointer) => thread_pointer,
+        Err(err) => {
+            unmap_pages(address_space, &mapped);
+            return Err(err);
+        }
+    };
+
+    Ok(ProgramImage {
+        entry: elf.entry + load_bias,
+        program_headers: program_headers_address(&elf).wrapping_add(load_bias),
+        program_header_count: u64::from(elf.header.e_phnum),
+        program_header_size: u64::from(elf.header.e_phentsize),
+        thread_pointer,
+    })
+}
+
+/// Returns a random load bias for a position independent program, which keeps the alignment of all of its loadable segments.
+fn random_load_bias(elf: &Elf) -> VirtualAddress {
+    let alignment = elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD && header.p_align.is_power_of_two())
+        .map(|header| header.p_align)
+        .fold(PAGE_SIZE as u64, u64::max)
+        .min(PIE_RANDOM_RANGE);
+    PIE_LOAD_BASE + random::next_u64() % (PIE_RANDOM_RANGE / alignment) * alignment
+}
+
+/// Maps the pages of all loadable segments and copies their contents. The rest of each segment stays zeroed.
+fn load_segments(
+    address_space: &AddressSpace,
+    elf: &Elf,
+    data: &[u8],
+    load_bias: VirtualAddress,
+    mapped: &mut Vec<VirtualAddress>,
+) -> Result<(), ElfError> {
+    for header in elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+    {
+        let start = header
+            .p_vaddr
+            .checked_add(load_bias)
+            .ok_or(ElfError::InvalidSegment(header.p_vaddr))?;
+        let end = start
+            .checked_add(header.p_memsz)
+            .filter(|end| *end <= USER_ADDRESS_LIMIT)
+            .ok_or(ElfError::InvalidSegment(header.p_vaddr))?;
+        let contents = segment_contents(header, data)?;
+
+        let flags = PageEntryFlags::from(segment_flags(header));
+        let first_page = start - start % PAGE_SIZE as u64;
+        for page in (first_page..end).step_by(PAGE_SIZE) {
+            if address_space.translate(page).is_none() {
+                map_zeroed_page(address_space, page, flags)?;
+                mapped.push(page);
+            } else if mapped.contains(&page) {
+                // segments may share a page, which gets the permissions of all of them
+                address_space.widen_permissions(page, flags)?;
+            } else {
+                return Err(ElfError::OverlappingSegment(page));
+            }
+        }
+        address_space.copy_range(start, contents)?;
+    }
+    Ok(())
+}
+
+/// Returns the part of the file the segment is initialized with.
+fn segment_contents<'a>(header: &ProgramHeader, data: &'a [u8]) -> Result<&'a [u8], ElfError> {
+    usize::try_from(header.p_offset)
+        .ok()
+        .zip(usize::try_from(header.p_filesz).ok())
+        .and_then(|(offset, size)| data.get(offset..offset.checked_add(size)?))
+        .filter(|_| header.p_filesz <= header.p_memsz)
+        .ok_or(ElfError::InvalidSegment(header.p_vaddr))
+}
+
+/// Maps the initial TLS block of the program behind its image and copies the template of the TLS segment into it. Uses the layout of the System V ABI, where the TLS block ends at the thread control block the thread pointer points at. Returns the thread pointer, if the program has a TLS segment.
+fn load_tls(
+    address_space: &AddressSpace,
+    elf: &Elf,
+    data: &[u8],
+    load_bias: VirtualAddress,
+    mapped: &mut Vec<VirtualAddress>,
+) -> Result<Option<VirtualAddress>, ElfError> {
+    let Some(header) = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == PT_TLS)
+    else {
+        return Ok(None);
+    };
+    let invalid = ElfError::InvalidSegment(header.p_vaddr);
+    let template = segment_contents(header, data)?;
+    let alignment = header.p_align.max(size_of::<u64>() as u64);
+    if !alignment.is_power_of_two() {
+        return Err(invalid);
+    }
+
+    // the loadable segments have been checked to end below the user address limit already
+    let image_end = elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+        .map(|header| header.p_vaddr + header.p_memsz + load_bias)
+        .max()
+        .unwrap_or(load_bias);
+    // an unmapped page separates the block from the image, so overflowing the image does not silently corrupt it
+    let block_start = align_up(image_end, PAGE_SIZE as u64)
+        .and_then(|end| align_up(end + PAGE_SIZE as u64, alignment))
+        .ok_or(invalid)?;
+    let thread_pointer = align_up(header.p_memsz, alignment)
+        .and_then(|size| block_start.checked_add(size))
+        .ok_or(invalid)?;
+    let end = thread_pointer
+        .checked_add(TCB_SIZE as u64)
+        .filter(|end| *end <= USER_ADDRESS_LIMIT)
+        .ok_or(invalid)?;
+
+    let flags = PageEntryFlags::from(VmFlags::USER | VmFlags::WRITE);
+    for page in (block_start..end).step_by(PAGE_SIZE) {
+        if address_space.translate(page).is_some() {
+            return Err(ElfError::OverlappingSegment(page));
+        }
+        map_zeroed_page(address_space, page, flags)?;
+        mapped.push(page);
+    }
+    // the block is aligned, so the template starts at the end of the block minus its aligned size
+    let template_start = thread_pointer - align_up(header.p_memsz, alignment).ok_or(invalid)?;
+    address_space.copy_range(template_start, template)?;
+
+    let mut control_block = [0; TCB_SIZE];
+    control_block[..size_of::<u64>()].copy_from_slice(&thread_pointer.to_le_bytes());
+    random::fill(&mut control_block[STACK_CANARY_OFFSET..]);
+    address_space.copy_range(thread_pointer, &control_block)?;
+    Ok(Some(thread_pointer))
+}
+
+/// Rounds the value up to the next multiple of the alignment, which must be a power of two.
+fn align_up(value: u64, alignment: u64) -> Option<u64> {
+    value
+        .checked_add(alignment - 1)
+        .map(|value| value & !(alignment - 1))
+}
+
+/// Returns the permissions of the segment. Segments are always accessible by the program itself.
+fn segment_flags(header: &ProgramHeader) -> VmFlags {
+    let mut flags = VmFlags::USER;
+    if header.p_flags & PF_W != 0 {
+        flags |= VmFlags::WRITE;
+    }
+    if header.p_flags & PF_X != 0 {
+        flags |= VmFlags::EXECUTABLE;
+    }
+    flags
+}
+
+/// Allocates a zeroed page frame and maps it to the virtual address of the address space.
+pub(in crate::scheduling::task) fn map_zeroed_page(
+    address_space: &AddressSpace,
+    page: VirtualAddress,
+    flags: PageEntryFlags,
+) -> Result<(), PagingError> {
+    let frame = without_interrupts(|| {
+        PTM.lock()
+            .get_mut()
+            .ok_or(PagingError::GlobalPageTableManagerUninitialized)?
+            .pmm()
+            .request_page_for(FramePurpose::Other)
+            .map_err(PagingError::from)
+    })?;
+    let frame_virtual = phys_to_virt(frame).ok_or(PagingError::AddressNotMapped(page))?;
+    unsafe { ptr::write_bytes(frame_virtual as *mut u8, 0, PAGE_SIZE) };
+    address_space.map(page, frame, flags)
+}
+
+/// Unmaps the pages from the address space and frees their page frames.
+fn unmap_pages(address_space: &AddressSpace, pages: &[VirtualAddress]) {
+    for page in pages {
+        let Ok(frame) = address_space.unmap(*page) else {
+            continue;
+        };
+        without_interrupts(|| {
+            if let Some(ptm) = PTM.lock().get_mut() {
+                let _ = ptm.pmm().free_frame(frame);
+            }
+        });
+    }
+}
+
+/// Applies the relocations of a position independent program. Statically linked programs only contain relative relocations, symbols can not be resolved, since there is no dynamic linker.
+fn relocate(
+    address_space: &AddressSpace,
+    elf: &Elf,
+    load_bias: VirtualAddress,
+) -> Result<(), ElfError> {
+    for relocation in elf.dynrelas.iter().chain(elf.pltrelocs.iter()) {
+        match relocation.r_type {
+            R_X86_64_NONE => {}
+            R_X86_64_RELATIVE => {
+                let value = load_bias.wrapping_add_signed(relocation.r_addend.unwrap_or_default());
+                let address = relocation.r_offset.wrapping_add(load_bias);
+                address_space.copy_range(address, &value.to_le_bytes())?;
+            }
+            other => return Err(ElfError::UnsupportedRelocation(other)),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the address of the program headers within the loaded image, before the load bias is added. Returns 0, if they are not part of any loadable segment.
+fn program_headers_address(elf: &Elf) -> VirtualAddress {
+    if let Some(header) = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == PT_PHDR)
+    {
+        return header.p_vaddr;
+    }
+    let offset = elf.header.e_phoff;
+    elf.program_headers
+        .iter()
+        .find(|header| {
+            header.p_type == PT_LOAD
+                && (header.p_offset..header.p_offset.saturating_add(header.p_filesz))
+                    .contains(&offset)
+        })
+        .map_or(0, |header| header.p_vaddr + (offset - header.p_offset))
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum ElfError {
+    InvalidElf,
+    UnsupportedArchitecture(u16),
+    UnsupportedType(u16),
+    /// The segment at the given virtual address does not fit into the file or the user half of the address space.
+    InvalidSegment(VirtualAddress),
+    /// The segment overlaps the page at the given address, which was not mapped by the loader.
+    OverlappingSegment(VirtualAddress),
+    UnsupportedRelocation(u32),
+    MappingFailed(PagingError),
+}
+
+impl Debug for ElfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ElfError::InvalidElf => write!(f, "Elf Error: Data is not a valid ELF file."),
+            ElfError::UnsupportedArchitecture(machine) => write!(
+                f,
+                "Elf Error: Only little endian ELF64 programs for x86_64 are supported, found machine: {}.",
+                machine
+            ),
+            ElfError::UnsupportedType(elf_type) => write!(
+                f,
+                "Elf Error: Only executables and position independent executables are supported, found type: {}.",
+                elf_type
+            ),
+            ElfError::InvalidSegment(address) => {
+                write!(f, "Elf Error: Invalid segment at address: {:#x}.", address)
+            }
+            ElfError::OverlappingSegment(page) => write!(
+                f,
+                "Elf Error: Segment overlaps existing mapping at address: {:#x}.",
+                page
+            ),
+            ElfError::UnsupportedRelocation(relocation_type) => write!(
+                f,
+                "Elf Error: Unsupported relocation type: {}.",
+                relocation_type
+            ),
+            ElfError::MappingFailed(value) => {
+                write!(f, "Elf Error: Could not map program: {}", value)
+            }
+        }
+    }
+}
+
+impl Display for ElfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ElfError {}
+
+impl From<PagingError> for ElfError {
+    fn from(value: PagingError) -> Self {
+        ElfError::MappingFailed(value)
+    }
+}
@@ -0,0 +1,173 @@
+use alloc::{boxed::Box, string::ToString, vec::Vec};
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
+
+use chicken_util::{
+    memory::{paging::PageEntryFlags, VirtualAddress},
+    PAGE_SIZE,
+};
+
+use crate::{
+    base::{interrupts::without_interrupts, syscall},
+    memory::{
+        address_space::AddressSpace,
+        paging::PagingError,
+        vmm::{object::VmFlags, VmmError},
+    },
+    modules::{self, Module, ModuleError},
+    object::ObjectRef,
+    scheduling::{
+        spin::SpinLock,
+        task::{
+            self,
+            capability::Capabilities,
+            elf::{self, ElfError, USER_ADDRESS_LIMIT},
+            process::ProcessObject,
+            startup::{self, ProgramArguments},
+            thread::ThreadMain,
+        },
+        SchedulerError,
+    },
+};
+
+/// Top of the stack of the main thread of user programs. The highest page of the user half stays unmapped, so the stack does not end right at the non-canonical addresses.
+const USER_STACK_TOP: VirtualAddress = USER_ADDRESS_LIMIT - PAGE_SIZE as u64;
+const USER_STACK_SIZE: usize = PAGE_SIZE * 16;
+
+/// Programs claimed from the boot modules by earlier spawns, so they can be started more than once.
+static PROGRAMS: SpinLock<Vec<Module>> = SpinLock::new(Vec::new());
+
+/// Loads the program at the path of the initrd into a new process and starts it in user mode with the arguments. The process inherits the capabilities of the current process. Requires the [`Capabilities::SPAWN`] capability. Returns its kernel object, which tells how it has ended.
+pub(crate) fn spawn(
+    path: &str,
+    arguments: &[&str],
+) -> Result<ObjectRef<ProcessObject>, SpawnError> {
+    // checked before the program is loaded, so unprivileged processes can not make the kernel parse files
+    task::require_capabilities(Capabilities::SPAWN)?;
+    let data = program(path)?;
+
+    let address_space = AddressSpace::create()?;
+    let start = load(&address_space, data, arguments);
+    let (entry, stack_pointer, thread_pointer) = match start {
+        Ok(start) => start,
+        Err(err) => {
+            let _ = address_space.free();
+            return Err(err);
+        }
+    };
+
+    let main: ThreadMain = Box::new(move || {
+        task::set_fs_base(thread_pointer.unwrap_or_default());
+        syscall::enter_user_mode(entry, stack_pointer)
+    });
+    Ok(task::spawn_in_address_space(
+        path.to_string(),
+        main,
+        address_space,
+    )?)
+}
+
+/// Returns the contents of the program at the path of the initrd. The module is claimed on the first spawn and kept for later ones.
+fn program(path: &str) -> Result<&'static [u8], ModuleError> {
+    // modules are named by their path relative to the root of the initrd
+    let name = path.trim_start_matches('/');
+    without_interrupts(|| {
+        let mut programs = PROGRAMS.lock();
+        if let Some(module) = programs.iter().find(|module| module.name == name) {
+            return Ok(module.data);
+        }
+        let module = modules::claim(name)?;
+        programs.push(module);
+        Ok(module.data)
+    })
+}
+
+/// Loads the program into the address space and builds the initial stack of its main thread. Returns the entry point, the stack pointer and the thread pointer to start it with.
+fn load(
+    address_space: &AddressSpace,
+    data: &[u8],
+    arguments: &[&str],
+) -> Result<(VirtualAddress, VirtualAddress, Option<VirtualAddress>), SpawnError> {
+    let image = elf::load(address_space, data)?;
+
+    let flags = PageEntryFlags::from(VmFlags::USER | VmFlags::WRITE);
+    for page in (USER_STACK_TOP - USER_STACK_SIZE as u64..USER_STACK_TOP).step_by(PAGE_SIZE) {
+        elf::map_zeroed_page(address_space, page, flags)?;
+    }
+
+    let auxiliary = startup::auxiliary_vector(&image);
+    let stack_pointer = startup::build_initial_stack(
+        address_space,
+        USER_STACK_TOP,
+        &ProgramArguments {
+            arguments,
+            environment: &[],
+            auxiliary: &auxiliary,
+        },
+    )?;
+    Ok((image.entry, stack_pointer, image.thread_pointer))
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum SpawnError {
+    ProgramUnavailable(ModuleError),
+    InvalidProgram(ElfError),
+    /// The address space or the process could not be set up, e.g. due to a lack of memory or capabilities.
+    SetUpFailed(SchedulerError),
+}
+
+impl Debug for SpawnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SpawnError::ProgramUnavailable(value) => {
+                write!(f, "Spawn Error: Program is not available: {}", value)
+            }
+            SpawnError::InvalidProgram(value) => {
+                write!(f, "Spawn Error: Program can not be loaded: {}", value)
+            }
+            SpawnError::SetUpFailed(value) => {
+                write!(f, "Spawn Error: Process could not be set up: {}", value)
+            }
+        }
+    }
+}
+
+impl Display for SpawnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SpawnError {}
+
+impl From<ModuleError> for SpawnError {
+    fn from(value: ModuleError) -> Self {
+        SpawnError::ProgramUnavailable(value)
+    }
+}
+
+impl From<ElfError> for SpawnError {
+    fn from(value: ElfError) -> Self {
+        SpawnError::InvalidProgram(value)
+    }
+}
+
+impl From<SchedulerError> for SpawnError {
+    fn from(value: SchedulerError) -> Self {
+        SpawnError::SetUpFailed(value)
+    }
+}
+
+impl From<VmmError> for SpawnError {
+    fn from(value: VmmError) -> Self {
+        SpawnError::SetUpFailed(value.into())
+    }
+}
+
+impl From<PagingError> for SpawnError {
+    fn from(value: PagingError) -> Self {
+        SpawnError::SetUpFailed(value.into())
+    }
+}
@@ -1,36 +1,174 @@
-use alloc::string::String;
+use alloc::{boxed::Box, string::String};
+use core::{any::Any, marker::PhantomData, mem::ManuallyDrop, ptr::NonNull};
+
+use chicken_util::memory::VirtualAddress;
 
 use crate::{
-    base::interrupts::without_interrupts,
-    scheduling::{SCHEDULER, SchedulerError},
+    base::{
+        interrupts::without_interrupts,
+        msr::{FsBase, ModelSpecificRegister},
+    },
+    memory::address_space::AddressSpace,
+    object::ObjectRef,
+    scheduling::{
+        GlobalTaskScheduler, SCHEDULER, SchedulerError,
+        task::{
+            capability::Capabilities,
+            process::{Process, ProcessObject},
+            thread::{ExitValue, Priority, ThreadMain, ThreadStatus},
+        },
+    },
 };
 
+pub(crate) mod capability;
+pub(crate) mod elf;
+pub(crate) mod exec;
 pub(crate) mod process;
+pub(crate) mod startup;
 pub(crate) mod thread;
 
+/// Handle to a spawned thread. The thread's return value can be collected using [`JoinHandle::join`]. Dropping the handle detaches the thread.
 #[derive(Debug)]
-pub(crate) struct JoinHandle {
+pub(crate) struct JoinHandle<T> {
+    pid: u64,
     tid: u64,
+    _return_type: PhantomData<T>,
 }
 
-impl JoinHandle {
-    fn try_new(tid: Result<u64, SchedulerError>) -> Result<JoinHandle, SchedulerError> {
-        let tid = tid?;
-        Ok(JoinHandle { tid })
+impl<T: 'static> JoinHandle<T> {
+    /// Waits until the thread has exited and returns its return value. Threads returning an `i64` hand out the exit status passed to [`exit`] as well, others report it as an error. The thread is removed afterward.
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+    pub(crate) fn join(self) -> Result<T, SchedulerError> {
+        let (pid, tid) = (self.pid, self.tid);
+        let exit_value = self.collect()?.ok_or(SchedulerError::ThreadKilled(pid, tid))?;
+        match exit_value.downcast::<T>() {
+            Ok(value) => Ok(*value),
+            Err(exit_value) => Err(exit_value
+                .downcast::<i64>()
+                .map_or(SchedulerError::ThreadKilled(pid, tid), |status| {
+                    SchedulerError::ThreadExited(pid, tid, *status)
+                })),
+        }
+    }
+
+    /// Waits until the thread has exited and returns its exit status, which is the status passed to [`exit`], the return value of threads returning an `i64`, or 0 if the thread has returned anything else. The thread is removed afterward.
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+    pub(crate) fn wait(self) -> Result<i64, SchedulerError> {
+        let (pid, tid) = (self.pid, self.tid);
+        let exit_value = self.collect()?.ok_or(SchedulerError::ThreadKilled(pid, tid))?;
+        Ok(exit_value.downcast::<i64>().map_or(0, |status| *status))
+    }
+
+    /// Blocks the current thread until the thread has exited, then removes it. Returns its exit value, which is missing if it has been killed.
+    fn collect(self) -> Result<Option<ExitValue>, SchedulerError> {
+        // the thread is removed below, so it must not be detached when the handle goes out of scope
+        let handle = ManuallyDrop::new(self);
+        let (pid, tid) = (handle.pid, handle.tid);
+
+        loop {
+            // outer option: whether the thread has exited, inner option: whether it returned a value
+            let exit_value = without_interrupts(|| -> Result<Option<Option<ExitValue>>, SchedulerError> {
+                let mut binding = SCHEDULER.lock();
+                let scheduler = binding
+                    .get_mut()
+                    .ok_or(SchedulerError::ThreadNotFound(pid, tid))?;
+                let joiner = scheduler.active_ids();
+                let process = scheduler
+                    .process_mut(pid)
+                    .ok_or(SchedulerError::ThreadNotFound(pid, tid))?;
+                let active_tid = unsafe { process.active_thread_ref().tid };
+                let thread = process
+                    .thread_mut(tid)
+                    .ok_or(SchedulerError::ThreadNotFound(pid, tid))?;
+
+                if thread.status != ThreadStatus::Dead {
+                    // woken up by the thread, once it exits
+                    thread.joiner = joiner;
+                    scheduler.block_active();
+                    return Ok(None);
+                }
+                // the thread stays active until its process has been scheduled again
+                if thread.tid == active_tid {
+                    return Ok(None);
+                }
+
+                let exit_value = thread.exit_value.take();
+                process.remove_thread(tid, false)?;
+                Ok(Some(exit_value))
+            })?;
+
+            match exit_value {
+                Some(exit_value) => return Ok(exit_value),
+                None => GlobalTaskScheduler::yield_now(),
+            }
+        }
     }
 
-    pub(in crate::scheduling) fn into_inner(self) -> u64  {
-        self.tid
+    /// Lets the thread run on its own. It is removed as soon as it exits.
+    #[allow(dead_code)] // equivalent to dropping the handle, only states the intent
+    pub(crate) fn detach(self) {}
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            let mut binding = SCHEDULER.lock();
+            if let Some(thread) = binding
+                .get_mut()
+                .and_then(|scheduler| scheduler.process_mut(self.pid))
+                .and_then(|process| process.thread_mut(self.tid))
+            {
+                thread.detached = true;
+                // nobody is going to collect it anymore
+                let _ = thread.exit_value.take();
+            }
+        });
     }
 }
 
-/// Spawns a new thread to the current process.
-/// todo: Automate adding of [`crate::scheduling::GlobalTaskScheduler::kill_active`]
-pub(crate) fn spawn_thread(
-    entry: fn(),
+/// Spawns a new thread to the current process. Its return value can be collected using the returned handle.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn spawn_thread<T: Send + 'static>(
+    entry: fn() -> T,
+    name: Option<String>,
+) -> Result<JoinHandle<T>, SchedulerError> {
+    spawn_thread_with_priority(entry, name, Priority::default())
+}
+
+/// Spawns a new thread with the given priority to the current process. Ready threads of a higher priority always run first, but threads that have been passed over for long are raised step by step, so they do not starve.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn spawn_thread_with_priority<T: Send + 'static>(
+    entry: fn() -> T,
+    name: Option<String>,
+    priority: Priority,
+) -> Result<JoinHandle<T>, SchedulerError> {
+    let main: ThreadMain = Box::new(move || Box::new(entry()) as Box<dyn Any + Send>);
+    let (pid, tid) = add_thread(name, main, false, priority)?;
+    Ok(JoinHandle {
+        pid,
+        tid,
+        _return_type: PhantomData,
+    })
+}
+
+/// Spawns a new thread to the current process, that is removed as soon as it exits.
+#[allow(dead_code)] // no detached threads needed yet
+pub(crate) fn spawn_detached(entry: fn(), name: Option<String>) -> Result<(), SchedulerError> {
+    let main: ThreadMain = Box::new(move || {
+        entry();
+        Box::new(())
+    });
+    add_thread(name, main, true, Priority::default()).map(|_| ())
+}
+
+/// Adds a new thread to the current process. Returns its pid and tid.
+fn add_thread(
     name: Option<String>,
-) -> Result<JoinHandle, SchedulerError> {
-    without_interrupts(|| -> Result<JoinHandle, SchedulerError> {
+    main: ThreadMain,
+    detached: bool,
+    priority: Priority,
+) -> Result<(u64, u64), SchedulerError> {
+    without_interrupts(|| -> Result<(u64, u64), SchedulerError> {
         let mut scheduler = SCHEDULER.lock();
         assert!(
             scheduler.get_mut().is_some(),
@@ -42,19 +180,129 @@ pub(crate) fn spawn_thread(
             "Scheduler must have at least one active task (IDLE)"
         );
         let active = unsafe { scheduler.active_task.unwrap().as_mut() };
-        JoinHandle::try_new(active.add_thread(name, entry))
+        let tid = active.add_thread(name, main, priority)?;
+        // mark before the thread can run, so it can not exit without being reaped
+        if let Some(thread) = active.thread_mut(tid) {
+            thread.detached = detached;
+            scheduler.make_ready(NonNull::from(thread));
+        }
+        Ok((active.pid, tid))
+    })
+}
+
+/// Spawns a new process, that inherits the capabilities of the current process. Returns its pid.
+pub(crate) fn spawn_process(entry: fn(), name: Option<String>) -> Result<u64, SchedulerError> {
+    spawn_restricted_process(entry, name, Capabilities::all())
+}
+
+/// Spawns a new process with the capabilities of the current process limited to the given ones, e.g. to sandbox a user program. Requires the [`Capabilities::SPAWN`] capability. Returns its pid.
+pub(crate) fn spawn_restricted_process(
+    entry: fn(),
+    name: Option<String>,
+    capabilities: Capabilities,
+) -> Result<u64, SchedulerError> {
+    without_interrupts(|| -> Result<u64, SchedulerError> {
+        let mut scheduler = SCHEDULER.lock();
+        assert!(
+            scheduler.get_mut().is_some(),
+            "Tasks can only be spawned after global task scheduler has been initialized."
+        );
+        let scheduler = scheduler.get_mut().unwrap();
+        // a process can only pass on capabilities it holds itself
+        let capabilities = match scheduler.active_task {
+            Some(active) => {
+                let active = unsafe { active.as_ref() };
+                active.require(Capabilities::SPAWN)?;
+                active.capabilities & capabilities
+            }
+            None => capabilities,
+        };
+        scheduler.add_task(name, entry, capabilities)
     })
 }
 
-/// Spawns a new process.
-pub(crate) fn spawn_process(entry: fn(), name: Option<String>) -> Result<(), SchedulerError> {
-    without_interrupts(|| -> Result<(), SchedulerError> {
+/// Spawns a new process, whose main thread runs the main function in the address space, e.g. one a user program has been loaded into. The process inherits the capabilities of the current process. Requires the [`Capabilities::SPAWN`] capability. Returns its kernel object.
+pub(in crate::scheduling) fn spawn_in_address_space(
+    name: String,
+    main: ThreadMain,
+    address_space: AddressSpace,
+) -> Result<ObjectRef<ProcessObject>, SchedulerError> {
+    without_interrupts(|| -> Result<ObjectRef<ProcessObject>, SchedulerError> {
         let mut scheduler = SCHEDULER.lock();
         assert!(
             scheduler.get_mut().is_some(),
             "Tasks can only be spawned after global task scheduler has been initialized."
         );
         let scheduler = scheduler.get_mut().unwrap();
-        scheduler.add_task(name, entry)
+        let capabilities = match scheduler.active_task {
+            Some(active) => {
+                let active = unsafe { active.as_ref() };
+                active.require(Capabilities::SPAWN)?;
+                active.capabilities
+            }
+            None => Capabilities::all(),
+        };
+        scheduler.add_task_in(name, main, capabilities, address_space)
+    })
+}
+
+/// Creates a copy of the current process, whose address space is a copy-on-write duplicate of the current one. The copy inherits the capabilities of the current process and runs the entry function. Requires the [`Capabilities::SPAWN`] capability. Returns its pid.
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+pub(crate) fn fork(entry: fn()) -> Result<u64, SchedulerError> {
+    without_interrupts(|| -> Result<u64, SchedulerError> {
+        let mut scheduler = SCHEDULER.lock();
+        assert!(
+            scheduler.get_mut().is_some(),
+            "Tasks can only be forked after global task scheduler has been initialized."
+        );
+        let scheduler = scheduler.get_mut().unwrap();
+        if let Some(active) = scheduler.active_task {
+            unsafe { active.as_ref() }.require(Capabilities::SPAWN)?;
+        }
+        scheduler.fork_task(entry)
+    })
+}
+
+/// Returns an error, if the current process lacks any of the given capabilities. Privileged operations requested by a process check their capability with this first.
+pub(crate) fn require_capabilities(capabilities: Capabilities) -> Result<(), SchedulerError> {
+    with_active_process(|process| process.require(capabilities))
+}
+
+/// Irrevocably removes the given capabilities from the current process, e.g. once a program has finished its set up. Processes spawned afterward do not get them either.
+pub(crate) fn drop_capabilities(capabilities: Capabilities) {
+    with_active_process(|process| process.capabilities.remove(capabilities));
+}
+
+/// Terminates the current thread with the given exit status, e.g. on behalf of a program that has finished. Negative statuses report failures by convention. The status is collected with [`JoinHandle::wait`].
+pub(crate) fn exit(status: i64) -> ! {
+    GlobalTaskScheduler::exit(Some(Box::new(status)))
+}
+
+/// Sets the fs base of the current thread, e.g. to the thread pointer of a user program. It is restored whenever the thread is switched to.
+pub(crate) fn set_fs_base(base: VirtualAddress) {
+    without_interrupts(|| {
+        with_active_process(|process| unsafe { process.active_thread_mut() }.fs_base = base);
+        FsBase::new(base).write();
+    });
+}
+
+/// Renames the current thread, e.g. so a long-running kernel worker can be told apart in log messages, fault messages and panics.
+pub(crate) fn set_name(name: String) {
+    with_active_process(|process| unsafe { process.active_thread_mut() }.name = name);
+}
+
+fn with_active_process<R>(f: impl FnOnce(&mut Process) -> R) -> R {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        assert!(
+            scheduler.get_mut().is_some(),
+            "Capabilities can only be used after global task scheduler has been initialized."
+        );
+        let scheduler = scheduler.get_mut().unwrap();
+        assert!(
+            scheduler.active_task.is_some(),
+            "Scheduler must have at least one active task (IDLE)"
+        );
+        f(unsafe { scheduler.active_task.unwrap().as_mut() })
     })
 }
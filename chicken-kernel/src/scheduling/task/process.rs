@@ -1,23 +1,23 @@
 use alloc::{
-    alloc::dealloc,
     boxed::Box,
     format,
     string::{String, ToString},
 };
-use core::{alloc::Layout, ptr, ptr::NonNull};
+use core::{
+    fmt::{Display, Formatter},
+    ptr::NonNull,
+};
 
-use chicken_util::{memory::paging::PageTable, PAGE_SIZE};
+use chicken_util::timing::read_tsc;
 
-use crate::{memory::{
-    paging::{PagingError, PTM},
-    vmm::{AllocationType, object::VmFlags, VMM, VmmError},
-}, scheduling::{SchedulerError, task::thread::Thread}};
+use crate::{base::interrupts::without_interrupts, memory::{address_space::AddressSpace, kheap::slab::{PROCESS_CACHE, THREAD_CACHE}, vmm::{VMM, VmmError}}, object::{KernelObject, ObjectRef, ObjectType}, scheduling::{SchedulerError, spin::SpinLock, task::{capability::Capabilities, thread::{Priority, Thread, ThreadMain}}}};
 use crate::scheduling::task::thread::ThreadStatus;
 
 const MAIN_THREAD_NAME: &str = "MAIN-";
 #[derive(Debug)]
 pub(crate) struct Process {
-    pub(in crate::scheduling) page_table_mappings: *const PageTable,
+    pub(in crate::scheduling) address_space: AddressSpace,
+    pub(in crate::scheduling) capabilities: Capabilities,
     // whether the kernel page mappings should be copied when switching from one process to another. For now always true.
     pub(in crate::scheduling) update_kernel_mappings: bool,
 
@@ -26,8 +26,12 @@ pub(crate) struct Process {
     pub(in crate::scheduling) active_thread: Option<NonNull<Thread>>,
 
     pub(in crate::scheduling) pid: u64,
+    /// Kernel object standing for the process, which outlives it.
+    pub(in crate::scheduling) object: ObjectRef<ProcessObject>,
     pub(in crate::scheduling) status: TaskStatus,
     pub(in crate::scheduling) name: String,
+    /// Time stamp counter value at which the process has been created or last been switched away from. Used to measure how long it waits for its next time slice.
+    pub(in crate::scheduling) ready_since: u64,
 
     pub(in crate::scheduling) next: Option<NonNull<Process>>,
     pub(in crate::scheduling) prev: Option<NonNull<Process>>,
@@ -40,36 +44,86 @@ impl Process {
         name: String,
         entry: fn(),
         pid: u64,
+        capabilities: Capabilities,
     ) -> Result<Option<NonNull<Self>>, SchedulerError> {
         // set up new page table mappings
-        let pml4 = allocate_page_mappings()?;
+        let address_space = AddressSpace::create()?;
+        Self::with_address_space(name, entry_main(entry), pid, capabilities, address_space)
+    }
+
+    /// Creates a copy of the process, whose address space is a copy-on-write duplicate of this one. The copy starts with a single thread running the entry function, since kernel threads can not continue at the point of the fork. Returns the new task or an error code if the initialization failed.
+    pub(in crate::scheduling) fn fork(
+        &self,
+        entry: fn(),
+        pid: u64,
+    ) -> Result<Option<NonNull<Self>>, SchedulerError> {
+        let address_space = self.address_space.fork()?;
+        Self::with_address_space(
+            self.name.clone(),
+            entry_main(entry),
+            pid,
+            self.capabilities,
+            address_space,
+        )
+    }
 
+    /// Allocates a new process from its slab cache using the address space and initializes it. Its main thread runs the main function.
+    pub(in crate::scheduling) fn with_address_space(
+        name: String,
+        main: ThreadMain,
+        pid: u64,
+        capabilities: Capabilities,
+        address_space: AddressSpace,
+    ) -> Result<Option<NonNull<Self>>, SchedulerError> {
         // initialize new process
-        let default = Process::empty();
-        let process = NonNull::new(Box::into_raw(Box::new(default)));
-        let process_ref = unsafe { process.unwrap().as_mut() };
+        let default = Process::empty(address_space, ObjectRef::new(ProcessObject::new(pid)));
+        let mut process = PROCESS_CACHE
+            .alloc(default)
+            .ok_or(SchedulerError::MemoryAllocationError(VmmError::OutOfMemory))?;
+        let process_ref = unsafe { process.as_mut() };
 
         process_ref.name = name;
         process_ref.pid = pid;
         process_ref.status = TaskStatus::Ready;
-        process_ref.page_table_mappings = pml4;
+        process_ref.capabilities = capabilities;
+        process_ref.ready_since = read_tsc();
 
         // set up main thread
-        process_ref.add_thread(Some(format!("{}{}", MAIN_THREAD_NAME, pid)), entry)?;
+        process_ref.add_thread(
+            Some(format!("{}{}", MAIN_THREAD_NAME, pid)),
+            main,
+            Priority::default(),
+        )?;
+
+        Ok(Some(process))
+    }
 
-        Ok(process)
+    /// Returns an error, if the process lacks any of the given capabilities.
+    pub(in crate::scheduling) fn require(
+        &self,
+        capabilities: Capabilities,
+    ) -> Result<(), SchedulerError> {
+        let missing = capabilities.difference(self.capabilities);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(SchedulerError::MissingCapabilities(self.pid, missing))
+        }
     }
 
-    fn empty() -> Self {
+    fn empty(address_space: AddressSpace, object: ObjectRef<ProcessObject>) -> Self {
         Self {
             status: TaskStatus::Dead,
             next: None,
             prev: None,
             pid: 0,
-            page_table_mappings: ptr::null_mut(),
+            object,
+            address_space,
+            capabilities: Capabilities::empty(),
             thread_id_counter: 0,
             active_thread: None,
             name: "".to_string(),
+            ready_since: 0,
             main_thread: None,
             // always update higher half mappings when switching processes
             // note: may be exchanged by a more efficient approach, that only updates the mappings if necessary, in the future.
@@ -78,6 +132,14 @@ impl Process {
     }
 }
 
+/// Returns the main function of a thread running the entry function.
+fn entry_main(entry: fn()) -> ThreadMain {
+    Box::new(move || {
+        entry();
+        Box::new(())
+    })
+}
+
 impl Process {
     /// Get mutable reference to active thread.
     ///
@@ -94,11 +156,21 @@ impl Process {
         unsafe { self.active_thread.unwrap().as_ref() }
     }
 
-    /// Adds the thread to the list of threads of the process. Returns the tid for the new thread or an error.
+    /// Returns how the process has ended, judging by the exit value of its main thread, which is missing if it has been killed.
+    pub(in crate::scheduling) fn exit(&self) -> ProcessExit {
+        self.main_thread
+            .and_then(|thread| unsafe { thread.as_ref() }.exit_value.as_ref())
+            .map_or(ProcessExit::Killed, |exit_value| {
+                ProcessExit::Exited(exit_value.downcast_ref::<i64>().copied().unwrap_or(0))
+            })
+    }
+
+    /// Adds the thread to the list of threads of the process. Returns the tid for the new thread or an error. The thread only runs, once the scheduler has queued it.
     pub(in crate::scheduling) fn add_thread(
         &mut self,
         name: Option<String>,
-        entry: fn(),
+        main: ThreadMain,
+        priority: Priority,
     ) -> Result<u64, SchedulerError> {
         let mut current = self.main_thread;
 
@@ -109,9 +181,10 @@ impl Process {
         if current.is_none() {
             let thread_ptr = Thread::create(
                 name.unwrap_or(format!("MAIN-{}", self.thread_id_counter)),
-                entry,
+                main,
                 self.thread_id_counter,
-                self.pid,
+                self,
+                priority,
             )?;
             self.main_thread = thread_ptr;
             self.active_thread = self.main_thread;
@@ -124,9 +197,10 @@ impl Process {
             if current_thread.next.is_none() {
                 let thread_ptr = Thread::create(
                     name.unwrap_or(format!("THREAD-{}", self.thread_id_counter)),
-                    entry,
+                    main,
                     self.thread_id_counter,
-                    self.pid,
+                    self,
+                    priority,
                 )?;
                 let thread = unsafe { thread_ptr.unwrap().as_mut() };
                 thread.prev = current;
@@ -181,12 +255,10 @@ impl Process {
                     next_ref.prev = current_ref.prev;
                 }
 
-                // free vec of joins
-                let _ = current_ref.joins.take();
-
-                // deallocate thread
+                // the thread is dropped along with an exit value that has not been collected
+                let stack_address = current_ref.stack_start;
                 unsafe {
-                    dealloc(heap_ptr as *mut u8, Layout::new::<Thread>());
+                    THREAD_CACHE.free(NonNull::new_unchecked(heap_ptr));
                 }
 
                 let mut binding = VMM.lock();
@@ -197,7 +269,6 @@ impl Process {
                     ))?;
 
                 // free thread's stack
-                let stack_address = current_ref.stack_start;
                 vmm.free(stack_address).map_err(SchedulerError::from)?;
 
                 return Ok(());
@@ -208,45 +279,64 @@ impl Process {
         Err(SchedulerError::ThreadNotFound(self.pid, tid))
     }
 
-    /// Gets the next ready thread information of the process. Returns whether the task has any alive threads, if all threads have been run for one iteration or the next ready thread.
-    pub(in crate::scheduling) fn get_next_thread(&self, uptime: u64) -> NextThread {
-        // mark task as dead.
-        if self.is_dead() {
-            return NextThread::TaskDead;
+    /// Returns the amount of pages of the stacks of all threads, including dead ones that have not been removed yet.
+    #[cfg(feature = "leak-check")]
+    pub(in crate::scheduling) fn stack_pages(&self) -> usize {
+        let mut threads = 0;
+        let mut current = self.main_thread;
+        while let Some(thread) = current {
+            threads += 1;
+            current = unsafe { thread.as_ref() }.next;
         }
+        threads * crate::scheduling::task::thread::STACK_PAGE_COUNT
+    }
+
+    /// Get mutable reference to the thread with the specified tid, if it belongs to the process.
+    pub(in crate::scheduling) fn thread_mut(&mut self, tid: u64) -> Option<&mut Thread> {
+        let mut current = self.main_thread;
 
-        let mut next_thread = unsafe { self.active_thread_ref().next };
+        while let Some(mut current_thread) = current {
+            let current_ref = unsafe { current_thread.as_mut() };
+            if current_ref.tid == tid {
+                return Some(current_ref);
+            }
+            current = current_ref.next;
+        }
 
-        // get next thread that is ready
-        while let Some(mut thread) = next_thread {
-            let thread_ref = unsafe { thread.as_mut() };
+        None
+    }
 
-            if let ThreadStatus::Sleep(wake_time_ms) = thread_ref.status {
-                if uptime >= wake_time_ms {
-                    thread_ref.status = ThreadStatus::Ready;
+    /// Removes all dead detached threads apart from the active one, which is removed once another thread has become active.
+    pub(in crate::scheduling) fn reap_detached(&mut self) -> Result<(), SchedulerError> {
+        let active_tid = unsafe { self.active_thread_ref().tid };
+
+        loop {
+            let mut dead = None;
+            let mut current = self.main_thread;
+
+            while let Some(current_thread) = current {
+                let current_ref = unsafe { current_thread.as_ref() };
+                if current_ref.detached
+                    && current_ref.status == ThreadStatus::Dead
+                    && current_ref.tid != active_tid
+                {
+                    dead = Some(current_ref.tid);
+                    break;
                 }
+                current = current_ref.next;
             }
 
-            if thread_ref.status == ThreadStatus::Ready {
-                break;
+            match dead {
+                Some(tid) => self.remove_thread(tid, false)?,
+                None => return Ok(()),
             }
-
-            next_thread = thread_ref.next;
-        }
-
-        // all threads of the current process have been run once, switch to the next process.
-        if next_thread.is_none() {
-            NextThread::None
-        }
-        // run the next thread in the current process.
-        else {
-            NextThread::Found(next_thread)
         }
     }
 }
 
 impl Process {
-    fn is_dead(&self) -> bool {
+    /// Whether the process has been killed, its main thread has exited or all of its threads have exited.
+    pub(in crate::scheduling) fn is_dead(&self) -> bool {
         if self.status == TaskStatus::Dead {
             return true;
         }
@@ -276,69 +366,66 @@ impl Process {
     }
 }
 
-/// Copies higher half mappings from one page-table manager to another.
-///
-/// # Safety
-/// The caller must ensure that both addresses are mapped and point to valid page tables.
-pub(in crate::scheduling) unsafe fn copy_higher_half_mappings(
-    src_pml4: *mut PageTable,
-    dst_pml4: *mut PageTable,
-) -> Result<(), SchedulerError> {
-    let src = src_pml4
-        .as_mut()
-        .ok_or(SchedulerError::PageTableManagerError(
-            PagingError::Pml4PointerMisaligned,
-        ))?;
-    let dst = dst_pml4
-        .as_mut()
-        .ok_or(SchedulerError::PageTableManagerError(
-            PagingError::Pml4PointerMisaligned,
-        ))?;
-    dst.entries.copy_from_slice(src.entries.as_slice());
-
-    Ok(())
+/// Pid and name of a process, copied into the process list, that can be read without the scheduler lock.
+#[derive(Clone, Debug)]
+pub(crate) struct ProcessInfo {
+    pub(crate) pid: u64,
+    pub(crate) name: String,
 }
 
-/// Allocate new page table mappings. Copies the higher half mappings from the global page table manager. Returns the address to the new pml4 table or an error value. The caller is responsible fpr freeing the memory allocated.
-fn allocate_page_mappings() -> Result<*const PageTable, SchedulerError> {
-    // get page table size
-    let current_pml4 = {
-        let mut binding = PTM.lock();
-        if let Some(ptm) = binding.get_mut() {
-            Ok(ptm.pml4_virtual())
-        } else {
-            Err(SchedulerError::PageTableManagerError(
-                PagingError::GlobalPageTableManagerUninitialized,
-            ))
-        }
-    }?;
+impl Display for ProcessInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:>5} {}", self.pid, self.name)
+    }
+}
 
-    let mut binding = VMM.lock();
-    if let Some(vmm) = binding.get_mut() {
-        let new_pml4 =
-            vmm.alloc(PAGE_SIZE, VmFlags::WRITE, AllocationType::AnyPages)? as *mut PageTable;
+/// Kernel object standing for a process, e.g. for the process that has spawned it. References to it outlive the process, so they can tell how it has ended.
+#[derive(Debug)]
+pub(crate) struct ProcessObject {
+    pid: u64,
+    exit: SpinLock<Option<ProcessExit>>,
+}
 
-        unsafe {
-            copy_higher_half_mappings(current_pml4, new_pml4)?;
+impl ProcessObject {
+    fn new(pid: u64) -> Self {
+        Self {
+            pid,
+            exit: SpinLock::new(None),
         }
-        Ok(new_pml4)
-    } else {
-        Err(SchedulerError::MemoryAllocationError(
-            VmmError::GlobalVirtualMemoryManagerUninitialized,
-        ))
+    }
+
+    pub(crate) fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    /// Returns how the process has ended, or `None` while it has not been removed yet.
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+    pub(crate) fn exit(&self) -> Option<ProcessExit> {
+        without_interrupts(|| *self.exit.lock())
+    }
+
+    /// Records how the process has ended. Called by the scheduler, when it removes the process.
+    pub(in crate::scheduling) fn set_exit(&self, exit: ProcessExit) {
+        without_interrupts(|| *self.exit.lock() = Some(exit));
     }
 }
 
+impl KernelObject for ProcessObject {
+    const TYPE: ObjectType = ObjectType::Process;
+}
+
+/// How a process has ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ProcessExit {
+    /// Its main thread has exited with the status passed to [`exit`](super::exit), or 0 if it has returned.
+    Exited(i64),
+    /// It has been killed, e.g. after a CPU exception.
+    Killed,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub(crate) enum TaskStatus {
     Ready,
     Running,
     Dead,
 }
-
-#[derive(Debug)]
-pub(in crate::scheduling) enum NextThread {
-    None,
-    TaskDead,
-    Found(Option<NonNull<Thread>>),
-}
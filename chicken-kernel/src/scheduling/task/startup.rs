@@ -0,0 +1,133 @@
+use alloc::{vec, vec::Vec};
+use core::mem::size_of;
+
+use chicken_util::{memory::VirtualAddress, PAGE_SIZE};
+
+use crate::{
+    base::{io::timer::pit, random},
+    memory::{address_space::AddressSpace, paging::PagingError},
+};
+
+/// Type of the entry that terminates the auxiliary vector.
+pub(crate) const AT_NULL: u64 = 0;
+/// Address of the program headers of the program image.
+pub(crate) const AT_PHDR: u64 = 3;
+/// Size of a program header entry.
+pub(crate) const AT_PHENT: u64 = 4;
+/// Amount of program headers.
+pub(crate) const AT_PHNUM: u64 = 5;
+/// Size of a page in bytes.
+pub(crate) const AT_PAGESZ: u64 = 6;
+/// Entry point of the program image.
+pub(crate) const AT_ENTRY: u64 = 9;
+/// Frequency of the clock ticks in Hz.
+pub(crate) const AT_CLKTCK: u64 = 17;
+/// Address of 16 random bytes, e.g. to initialize stack canaries.
+pub(crate) const AT_RANDOM: u64 = 25;
+
+/// Alignment of the stack pointer at program entry required by the System V ABI.
+const STACK_ALIGNMENT: u64 = 16;
+/// Amount of random bytes [`AT_RANDOM`] points to.
+const RANDOM_BYTES: usize = 16;
+
+/// Entry of the auxiliary vector, which passes information about the system to the runtime of a program.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AuxiliaryEntry {
+    pub(crate) key: u64,
+    pub(crate) value: u64,
+}
+
+/// Location of a loaded program image, as reported to its runtime in the auxiliary vector.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ProgramImage {
+    pub(crate) entry: VirtualAddress,
+    pub(crate) program_headers: VirtualAddress,
+    pub(crate) program_header_count: u64,
+    pub(crate) program_header_size: u64,
+    /// Value of the fs base of the main thread, which points at the thread control block after the TLS block of the program. Missing, if it has no TLS segment.
+    pub(crate) thread_pointer: Option<VirtualAddress>,
+}
+
+/// Returns the auxiliary vector describing the program image and the system. The [`AT_RANDOM`] entry is added by [`build_initial_stack`], since the random bytes are stored on the stack.
+pub(crate) fn auxiliary_vector(image: &ProgramImage) -> [AuxiliaryEntry; 6] {
+    let entry = |key, value| AuxiliaryEntry { key, value };
+    [
+        entry(AT_PHDR, image.program_headers),
+        entry(AT_PHENT, image.program_header_size),
+        entry(AT_PHNUM, image.program_header_count),
+        entry(AT_PAGESZ, PAGE_SIZE as u64),
+        entry(AT_ENTRY, image.entry),
+        entry(AT_CLKTCK, pit::frequency()),
+    ]
+}
+
+/// Arguments, environment and auxiliary vector passed to a new program on its initial stack.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ProgramArguments<'a> {
+    pub(crate) arguments: &'a [&'a str],
+    /// Environment variables in the form `KEY=value`.
+    pub(crate) environment: &'a [&'a str],
+    /// Auxiliary entries without the [`AT_RANDOM`] and the terminating [`AT_NULL`] entry, e.g. as returned by [`auxiliary_vector`].
+    pub(crate) auxiliary: &'a [AuxiliaryEntry],
+}
+
+/// Builds the initial stack of a program as specified by the System V ABI below the stack top of the address space and returns the stack pointer to start the program with.
+///
+/// From the stack pointer upward, the stack contains argc, the null terminated argv and envp arrays, the auxiliary vector, the strings they point to and the random bytes of [`AT_RANDOM`]. The stack pages must already be mapped.
+pub(crate) fn build_initial_stack(
+    address_space: &AddressSpace,
+    stack_top: VirtualAddress,
+    program: &ProgramArguments,
+) -> Result<VirtualAddress, PagingError> {
+    let strings_size = program
+        .arguments
+        .iter()
+        .chain(program.environment)
+        .map(|string| string.len() + 1)
+        .sum::<usize>() as u64;
+    let random_start = stack_top - RANDOM_BYTES as u64;
+    let strings_start = random_start - strings_size;
+
+    // argc, argv and envp with their null pointers and the auxiliary vector with its random and terminating entry
+    let word_count = 1
+        + program.arguments.len()
+        + 1
+        + program.environment.len()
+        + 1
+        + (program.auxiliary.len() + 2) * 2;
+    let stack_pointer =
+        (strings_start - (word_count * size_of::<u64>()) as u64) & !(STACK_ALIGNMENT - 1);
+
+    let mut words = Vec::with_capacity(word_count);
+    let mut image = vec![0u8; (stack_top - stack_pointer) as usize];
+    let random_offset = (random_start - stack_pointer) as usize;
+    random::fill(&mut image[random_offset..random_offset + RANDOM_BYTES]);
+
+    let mut string_address = strings_start;
+    let mut push_strings = |words: &mut Vec<u64>, strings: &[&str]| {
+        for string in strings {
+            let offset = (string_address - stack_pointer) as usize;
+            // the terminating null byte is already part of the zeroed image
+            image[offset..offset + string.len()].copy_from_slice(string.as_bytes());
+            words.push(string_address);
+            string_address += string.len() as u64 + 1;
+        }
+        words.push(0);
+    };
+
+    words.push(program.arguments.len() as u64);
+    push_strings(&mut words, program.arguments);
+    push_strings(&mut words, program.environment);
+    for entry in program.auxiliary {
+        words.extend([entry.key, entry.value]);
+    }
+    words.extend([AT_RANDOM, random_start, AT_NULL, 0]);
+
+    for (index, word) in words.iter().enumerate() {
+        let offset = index * size_of::<u64>();
+        image[offset..offset + size_of::<u64>()].copy_from_slice(&word.to_le_bytes());
+    }
+
+    address_space.copy_range(stack_pointer, &image)?;
+    Ok(stack_pointer)
+}
@@ -1,9 +1,13 @@
 use alloc::{
     boxed::Box,
     string::{String, ToString},
-    vec::Vec,
 };
-use core::{ptr, ptr::NonNull};
+use core::{
+    any::Any,
+    fmt::{Display, Formatter},
+    ptr,
+    ptr::NonNull,
+};
 
 use chicken_util::{memory::VirtualAddress, PAGE_SIZE};
 
@@ -11,86 +15,161 @@ use crate::{
     base::{
         gdt::{KERNEL_CS, KERNEL_DS},
         interrupts::{CpuState, RFlags},
+        pmc::PerfCounters,
+    },
+    memory::{
+        kheap::slab::THREAD_CACHE,
+        vmm::{AllocationType, object::VmFlags, VMM, VmmError},
     },
-    memory::vmm::{AllocationType, object::VmFlags, VMM, VmmError},
-    scheduling::SchedulerError,
+    scheduling::{task::process::Process, GlobalTaskScheduler, SchedulerError},
 };
 
 /// Size of stack for new threads.
 const THREAD_STACK_SIZE: usize = PAGE_SIZE * 4;
+/// Pages backing the stack of each thread, excluding its guard pages.
+#[cfg(feature = "leak-check")]
+pub(in crate::scheduling) const STACK_PAGE_COUNT: usize = THREAD_STACK_SIZE.div_ceil(PAGE_SIZE);
+/// Maximum length of a thread name in a [`ThreadLabel`]. Longer names are truncated.
+const LABEL_NAME_LENGTH: usize = 32;
+
+/// Value returned by a thread, kept until it is collected by [`crate::scheduling::task::JoinHandle::join`].
+pub(in crate::scheduling) type ExitValue = Box<dyn Any + Send>;
+/// Function run by a new thread. Its return value becomes the exit value of the thread.
+pub(in crate::scheduling) type ThreadMain = Box<dyn FnOnce() -> ExitValue + Send>;
 
 #[derive(Debug)]
 pub(crate) struct Thread {
     pub(in crate::scheduling) context: *const CpuState,
     pub(in crate::scheduling) stack_start: VirtualAddress,
+    /// Base of the fs segment, e.g. the thread pointer of a user program. Loaded whenever the thread is switched to.
+    pub(in crate::scheduling) fs_base: VirtualAddress,
 
     pub(in crate::scheduling) tid: u64,
     pub(in crate::scheduling) pid: u64,
     pub(in crate::scheduling) status: ThreadStatus,
     pub(in crate::scheduling) name: String,
+    /// Priority of the ready queue the thread is appended to, whenever it becomes ready.
+    pub(in crate::scheduling) priority: Priority,
+    /// Process the thread belongs to.
+    pub(in crate::scheduling) process: Option<NonNull<Process>>,
+
+    // detached threads are removed as soon as they exit, nobody collects their exit value.
+    pub(in crate::scheduling) detached: bool,
+    pub(in crate::scheduling) exit_value: Option<ExitValue>,
+    /// Pid and tid of the thread blocked in joining this one, woken up once this one exits.
+    pub(in crate::scheduling) joiner: Option<(u64, u64)>,
 
-    pub(in crate::scheduling) joins: Option<Vec<u64>>,
+    /// Events counted while the thread was running, up to its last context switch.
+    pub(in crate::scheduling) counters: PerfCounters,
 
     pub(in crate::scheduling) next: Option<NonNull<Thread>>,
     pub(in crate::scheduling) prev: Option<NonNull<Thread>>,
+
+    /// Level of the ready queue the thread is waiting in, if it is ready. Above its priority, if it has been raised for waiting too long.
+    pub(in crate::scheduling) queued_at: Option<usize>,
+    /// Next thread in the same ready queue.
+    pub(in crate::scheduling) queue_next: Option<NonNull<Thread>>,
 }
 
 impl Thread {
     pub(crate) fn create(
         name: String,
-        entry: fn(),
+        main: ThreadMain,
         tid: u64,
-        pid: u64,
+        process: &Process,
+        priority: Priority,
     ) -> Result<Option<NonNull<Thread>>, SchedulerError> {
         // set up new cpu state
-        let (stack_start, rsp) = allocate_stack()?;
-        let cpu_state = Box::into_raw(Box::new(CpuState::basic(
-            KERNEL_DS as u64,
-            rsp,
-            RFlags::RESERVED_1 | RFlags::INTERRUPTS_ENABLED,
-            KERNEL_CS as u64,
-            entry as usize as u64,
-            0,
-        )));
+        let (stack_start, stack_top) = allocate_stack()?;
+        // the entry is jumped to instead of called, so align the stack as if a return address had been pushed
+        let rsp = (stack_top & !0xF) - 8;
+        // box again, since trait objects can not be passed through a single register
+        let main = Box::into_raw(Box::new(main));
+        let start: extern "sysv64" fn(*mut ThreadMain) -> ! = thread_start;
+        let cpu_state = Box::into_raw(Box::new(
+            CpuState::basic(
+                KERNEL_DS as u64,
+                rsp,
+                RFlags::RESERVED_1 | RFlags::INTERRUPTS_ENABLED,
+                KERNEL_CS as u64,
+                start as usize as u64,
+                0,
+            )
+            .with_argument(main as u64),
+        ));
 
         // initialize new thread
         let default = Thread::empty();
-        let thread = NonNull::new(Box::into_raw(Box::new(default)));
+        let mut thread = THREAD_CACHE
+            .alloc(default)
+            .ok_or(SchedulerError::MemoryAllocationError(VmmError::OutOfMemory))?;
 
-        let thread_ref = unsafe { thread.unwrap().as_mut() };
+        let thread_ref = unsafe { thread.as_mut() };
 
         thread_ref.context = cpu_state;
         thread_ref.stack_start = stack_start;
 
         thread_ref.tid = tid;
-        thread_ref.pid = pid;
+        thread_ref.pid = process.pid;
+        thread_ref.process = Some(NonNull::from(process));
         thread_ref.name = name;
+        thread_ref.priority = priority;
         thread_ref.status = ThreadStatus::Ready;
 
-        Ok(thread)
+        Ok(Some(thread))
+    }
+
+    #[cfg_attr(not(feature = "ktest"), allow(dead_code))] // only used by the kernel self-tests so far
+    pub(crate) fn tid(&self) -> u64 {
+        self.tid
+    }
+
+    /// Returns the top of the kernel stack of the thread, which interrupts and syscalls arriving in user mode start on.
+    pub(in crate::scheduling) fn kernel_stack_top(&self) -> VirtualAddress {
+        self.stack_start + THREAD_STACK_SIZE as u64
     }
 
     fn empty() -> Self {
         Self {
             context: ptr::null_mut(),
             stack_start: 0,
+            fs_base: 0,
             tid: 0,
             pid: 0,
             status: ThreadStatus::Dead,
             name: "".to_string(),
+            priority: Priority::default(),
+            process: None,
             next: None,
             prev: None,
-            joins: None,
+            queued_at: None,
+            queue_next: None,
+            detached: false,
+            exit_value: None,
+            joiner: None,
+            counters: PerfCounters::default(),
         }
     }
 }
 
+/// Entry point of every thread. Runs the thread's main function and exits the thread with its return value.
+extern "sysv64" fn thread_start(main: *mut ThreadMain) -> ! {
+    let main = unsafe { Box::from_raw(main) };
+    let exit_value = main();
+    GlobalTaskScheduler::exit(Some(exit_value))
+}
+
 /// Allocate a stack of [`THREAD_STACK_SIZE`] for a new process. Returns the pointer to the stack bottom and the top of the stack or an error value. The caller is responsible fpr freeing the memory allocated.
 fn allocate_stack() -> Result<(VirtualAddress, VirtualAddress), SchedulerError> {
     let mut binding = VMM.lock();
     if let Some(vmm) = binding.get_mut() {
         let stack_bottom = vmm
-            .alloc(THREAD_STACK_SIZE, VmFlags::WRITE, AllocationType::AnyPages)
+            .alloc(
+                THREAD_STACK_SIZE,
+                // a stack overflow faults instead of overwriting the neighbouring object
+                VmFlags::WRITE | VmFlags::GUARDED,
+                AllocationType::AnyPages,
+            )
             .map_err(SchedulerError::from)?;
         Ok((stack_bottom, stack_bottom + THREAD_STACK_SIZE as u64 - 1))
     } else {
@@ -100,10 +179,71 @@ fn allocate_stack() -> Result<(VirtualAddress, VirtualAddress), SchedulerError>
     }
 }
 
+/// Name, pid and tid of a thread, e.g. to attribute log messages or faults to it. Copied out of the thread, so it can be printed without holding the scheduler lock or using the heap.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ThreadLabel {
+    pid: u64,
+    tid: u64,
+    name: [u8; LABEL_NAME_LENGTH],
+    name_length: usize,
+}
+
+impl ThreadLabel {
+    pub(in crate::scheduling) fn new(thread: &Thread) -> Self {
+        let mut name_length = thread.name.len().min(LABEL_NAME_LENGTH);
+        while !thread.name.is_char_boundary(name_length) {
+            name_length -= 1;
+        }
+        let mut name = [0; LABEL_NAME_LENGTH];
+        name[..name_length].copy_from_slice(&thread.name.as_bytes()[..name_length]);
+        Self {
+            pid: thread.pid,
+            tid: thread.tid,
+            name,
+            name_length,
+        }
+    }
+}
+
+impl Display for ThreadLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        // only truncated at char boundaries
+        let name = core::str::from_utf8(&self.name[..self.name_length]).unwrap_or_default();
+        write!(f, "{} (PID: {}, TID: {})", name, self.pid, self.tid)
+    }
+}
+
+/// Priority of a thread. Ready threads of a higher priority run first, threads of the same priority take turns. Threads that have been passed over for long are raised temporarily, so they are not starved.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(not(feature = "ktest"), allow(dead_code))] // other priorities are only used by the kernel self-tests so far
+pub(crate) enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// Reserved for threads that must react quickly, e.g. the driver workers.
+    #[allow(dead_code)] // no driver workers run at it yet
+    Realtime,
+}
+
+impl Priority {
+    /// Amount of priorities, each of which has a ready queue.
+    pub(crate) const COUNT: usize = 4;
+
+    /// Index of the ready queue of the priority, higher priorities have higher levels.
+    pub(in crate::scheduling) const fn level(self) -> usize {
+        self as usize
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub(crate) enum ThreadStatus {
     Ready,
     Running,
     Dead,
+    /// Sleeping until the deadline in ns of the monotonic clock.
     Sleep(u64),
+    /// Waiting for another thread to exit, which makes it ready again.
+    Blocked,
 }
@@ -0,0 +1,209 @@
+use alloc::{collections::VecDeque, format, string::ToString, vec::Vec};
+use core::{
+    cell::OnceCell,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
+
+use crate::{
+    base::interrupts::without_interrupts,
+    scheduling::{spin::SpinLock, task, GlobalTaskScheduler, SchedulerError},
+};
+
+/// Amount of kernel worker threads shared by all drivers.
+const WORKER_COUNT: usize = 2;
+/// Time in ms idle workers sleep before checking the queues again, in case a wake up was missed.
+const IDLE_POLL_MS: u64 = 100;
+
+/// Deferred part of an interrupt handler, e.g. processing a received packet. Receives the argument it has been queued with.
+///
+/// Plain functions are used instead of boxed closures, since the kernel heap must not be used in interrupt handlers.
+pub(crate) type Work = fn(u64);
+
+static POOL: SpinLock<OnceCell<WorkerPool>> = SpinLock::new(OnceCell::new());
+
+/// Handle of a driver registered with the worker pool.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DriverId(usize);
+
+#[derive(Debug)]
+struct Driver {
+    name: &'static str,
+    /// Maximum amount of work items of the driver that run at the same time.
+    max_concurrency: usize,
+    /// Amount of work items of the driver that are currently running.
+    running: usize,
+    /// Queued work items. The capacity is reserved on registration, so queueing never allocates.
+    queue: VecDeque<(Work, u64)>,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct WorkerPool {
+    drivers: Vec<Driver>,
+    /// Pids of the worker tasks.
+    workers: [u64; WORKER_COUNT],
+    /// Driver the next worker starts looking for work at, so every driver gets its turn.
+    next_driver: usize,
+}
+
+impl WorkerPool {
+    /// Removes the next work item that may run without exceeding the concurrency limit of its driver.
+    fn take(&mut self) -> Option<(usize, Work, u64)> {
+        let count = self.drivers.len();
+        for offset in 0..count {
+            let index = (self.next_driver + offset) % count;
+            let driver = &mut self.drivers[index];
+            if driver.running >= driver.max_concurrency {
+                continue;
+            }
+            if let Some((work, argument)) = driver.queue.pop_front() {
+                driver.running += 1;
+                self.next_driver = (index + 1) % count;
+                return Some((index, work, argument));
+            }
+        }
+        None
+    }
+}
+
+/// Spawns the worker tasks. Must be called after the task scheduler has been set up.
+pub(crate) fn set_up() -> Result<(), WorkerError> {
+    let mut workers = [0; WORKER_COUNT];
+    for (index, pid) in workers.iter_mut().enumerate() {
+        *pid = task::spawn_process(run, Some(format!("WORKER-{}", index)))?;
+    }
+
+    without_interrupts(|| {
+        POOL.lock()
+            .set(WorkerPool {
+                drivers: Vec::new(),
+                workers,
+                next_driver: 0,
+            })
+            .map_err(|_| WorkerError::AlreadyRunning)
+    })
+}
+
+/// Registers a driver with the worker pool. At most `max_concurrency` of its work items run at the same time and at most `capacity` may be queued.
+#[allow(dead_code)] // no drivers defer work yet
+pub(crate) fn register(
+    name: &'static str,
+    max_concurrency: usize,
+    capacity: usize,
+) -> Result<DriverId, WorkerError> {
+    if max_concurrency == 0 || capacity == 0 {
+        return Err(WorkerError::InvalidLimits(name));
+    }
+    let queue = VecDeque::with_capacity(capacity);
+
+    without_interrupts(|| {
+        let mut binding = POOL.lock();
+        let pool = binding.get_mut().ok_or(WorkerError::PoolUninitialized)?;
+        pool.drivers.push(Driver {
+            name,
+            max_concurrency,
+            running: 0,
+            queue,
+            capacity,
+        });
+        Ok(DriverId(pool.drivers.len() - 1))
+    })
+}
+
+/// Queues work of the driver to be run by one of the worker threads. Does not allocate, so it can be called from interrupt handlers.
+#[allow(dead_code)] // no drivers defer work yet
+pub(crate) fn queue(driver: DriverId, work: Work, argument: u64) -> Result<(), WorkerError> {
+    let workers = without_interrupts(|| {
+        let mut binding = POOL.lock();
+        let pool = binding.get_mut().ok_or(WorkerError::PoolUninitialized)?;
+        let driver = pool
+            .drivers
+            .get_mut(driver.0)
+            .ok_or(WorkerError::DriverNotFound(driver.0))?;
+        if driver.queue.len() >= driver.capacity {
+            return Err(WorkerError::QueueFull(driver.name));
+        }
+        driver.queue.push_back((work, argument));
+        Ok(pool.workers)
+    })?;
+
+    for pid in workers {
+        GlobalTaskScheduler::wake(pid);
+    }
+    Ok(())
+}
+
+/// Entry of the worker tasks. Runs queued work items and sleeps while there are none.
+fn run() {
+    task::set_name("DRIVER-WORKER".to_string());
+    loop {
+        let next = without_interrupts(|| POOL.lock().get_mut().and_then(WorkerPool::take));
+        let Some((driver, work, argument)) = next else {
+            // woken up early as soon as work is queued
+            GlobalTaskScheduler::sleep(IDLE_POLL_MS);
+            continue;
+        };
+
+        work(argument);
+
+        without_interrupts(|| {
+            if let Some(pool) = POOL.lock().get_mut() {
+                pool.drivers[driver].running -= 1;
+            }
+        });
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum WorkerError {
+    PoolUninitialized,
+    AlreadyRunning,
+    DriverNotFound(usize),
+    InvalidLimits(&'static str),
+    QueueFull(&'static str),
+    SpawnFailed(SchedulerError),
+}
+
+impl Debug for WorkerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WorkerError::PoolUninitialized => {
+                write!(f, "Worker Error: Worker pool has not been initialized.")
+            }
+            WorkerError::AlreadyRunning => {
+                write!(f, "Worker Error: Worker pool is already running.")
+            }
+            WorkerError::DriverNotFound(id) => write!(
+                f,
+                "Worker Error: Could not find driver with ID: {} in worker pool.",
+                id
+            ),
+            WorkerError::InvalidLimits(name) => write!(
+                f,
+                "Worker Error: Concurrency limit and queue capacity of driver: {} must be at least one.",
+                name
+            ),
+            WorkerError::QueueFull(name) => {
+                write!(f, "Worker Error: Work queue of driver: {} is full.", name)
+            }
+            WorkerError::SpawnFailed(value) => {
+                write!(f, "Worker Error: Could not spawn worker: {}", value)
+            }
+        }
+    }
+}
+
+impl Display for WorkerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for WorkerError {}
+
+impl From<SchedulerError> for WorkerError {
+    fn from(value: SchedulerError) -> Self {
+        Self::SpawnFailed(value)
+    }
+}
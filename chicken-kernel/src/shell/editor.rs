@@ -0,0 +1,267 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    base::io::keyboard::input::{InputEvent, Key, Modifiers},
+    shell::history::{History, HISTORY_SIZE},
+};
+
+/// Returns the candidates for the last word of the line, which is passed up to the cursor, e.g. command names for the first word and later paths for the others.
+#[allow(dead_code)] // no shell available yet
+pub(crate) type Completer = fn(&str) -> Vec<String>;
+
+/// Result of handing a key press to the line editor.
+#[allow(dead_code)] // no shell available yet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Edit {
+    /// The line, the cursor or the search has changed, or nothing at all.
+    Pending,
+    /// The line has been entered and recorded in the history.
+    Submit(String),
+    /// The line has been discarded with ctrl + c.
+    Cancel,
+    /// Tab has found several candidates, that share no longer prefix than the word. The shell lists them.
+    Candidates(Vec<String>),
+}
+
+/// Incremental search through the history, started with ctrl + r.
+#[derive(Debug)]
+struct Search {
+    pattern: String,
+    /// Index of the history line matching the pattern.
+    found: Option<usize>,
+}
+
+/// Line editor of the shell, fed with key presses in event mode. Moves the cursor with the navigation keys, recalls entered lines with up and down, searches them incrementally with ctrl + r and completes the word before the cursor with tab.
+#[allow(dead_code)] // no shell available yet
+#[derive(Debug)]
+pub(crate) struct LineEditor {
+    line: Vec<char>,
+    /// Index of the character in front of the cursor.
+    cursor: usize,
+    history: History,
+    /// Index of the history line being edited, `None` while editing a new line.
+    recalled: Option<usize>,
+    /// New line, that has been edited before recalling a history line.
+    draft: Vec<char>,
+    search: Option<Search>,
+    completer: Completer,
+}
+
+#[allow(dead_code)] // no shell available yet
+impl LineEditor {
+    pub(crate) fn new(completer: Completer) -> Self {
+        Self {
+            line: Vec::new(),
+            cursor: 0,
+            history: History::new(HISTORY_SIZE),
+            recalled: None,
+            draft: Vec::new(),
+            search: None,
+            completer,
+        }
+    }
+
+    /// Returns the line being edited.
+    pub(crate) fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    /// Returns the position of the cursor in characters.
+    pub(crate) fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Returns the prompt to show instead of the line during an incremental search, including the matching history line.
+    pub(crate) fn search_prompt(&self) -> Option<String> {
+        self.search.as_ref().map(|search| {
+            let found = search
+                .found
+                .and_then(|index| self.history.get(index))
+                .unwrap_or_default();
+            format!("(reverse-i-search)`{}': {}", search.pattern, found)
+        })
+    }
+
+    /// Applies the key press to the line.
+    pub(crate) fn handle(&mut self, event: InputEvent) -> Edit {
+        if self.search.is_some() {
+            if let Some(edit) = self.handle_search(event) {
+                return edit;
+            }
+        }
+
+        match event.key {
+            Key::Char(character) if event.modifiers.contains(Modifiers::CONTROL) => {
+                match character.to_ascii_lowercase() {
+                    'r' => {
+                        self.search = Some(Search {
+                            pattern: String::new(),
+                            found: None,
+                        })
+                    }
+                    'c' => {
+                        self.reset();
+                        return Edit::Cancel;
+                    }
+                    // other control combinations are not bound
+                    _ => {}
+                }
+            }
+            Key::Char(character) => {
+                self.line.insert(self.cursor, character);
+                self.cursor += 1;
+            }
+            Key::Enter => {
+                let line = self.line();
+                self.history.push(&line);
+                self.reset();
+                return Edit::Submit(line);
+            }
+            Key::Tab => return self.complete(),
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                }
+            }
+            Key::Delete => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                }
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Up => self.recall(self.recalled.map_or(0, |index| index + 1)),
+            Key::Down => match self.recalled {
+                Some(0) => {
+                    self.line = core::mem::take(&mut self.draft);
+                    self.cursor = self.line.len();
+                    self.recalled = None;
+                }
+                Some(index) => self.recall(index - 1),
+                None => {}
+            },
+        }
+        Edit::Pending
+    }
+
+    /// Handles a key press during an incremental search. Returns `None`, if the key press has ended the search and is handled as usual.
+    fn handle_search(&mut self, event: InputEvent) -> Option<Edit> {
+        let search = self.search.as_mut()?;
+        match event.key {
+            Key::Char(character) if event.modifiers.contains(Modifiers::CONTROL) => {
+                match character.to_ascii_lowercase() {
+                    // find the next older match
+                    'r' => {
+                        let start = search.found.map_or(0, |index| index + 1);
+                        if let Some(index) = self.history.search(&search.pattern, start) {
+                            search.found = Some(index);
+                        }
+                    }
+                    // leave the line as it has been before the search
+                    'c' | 'g' => self.search = None,
+                    _ => {}
+                }
+            }
+            Key::Char(character) => {
+                search.pattern.push(character);
+                // the current match is kept, as long as it still matches
+                let start = search.found.unwrap_or(0);
+                search.found = self.history.search(&search.pattern, start);
+            }
+            Key::Backspace => {
+                search.pattern.pop();
+                search.found = self.history.search(&search.pattern, 0);
+            }
+            _ => {
+                // take over the match for editing, then handle the key as usual
+                let found = self.search.take().and_then(|search| search.found);
+                if let Some(index) = found {
+                    self.recall(index);
+                }
+                return None;
+            }
+        }
+        Some(Edit::Pending)
+    }
+
+    /// Replaces the line with the history line at the index, if there is one. The new line is kept as draft.
+    fn recall(&mut self, index: usize) {
+        let Some(line) = self.history.get(index) else {
+            return;
+        };
+        let line = line.chars().collect();
+        if self.recalled.is_none() {
+            self.draft = core::mem::replace(&mut self.line, line);
+        } else {
+            self.line = line;
+        }
+        self.cursor = self.line.len();
+        self.recalled = Some(index);
+    }
+
+    /// Completes the word before the cursor with the candidates of the completer. A single candidate is inserted followed by a space, several ones are completed up to their common prefix.
+    fn complete(&mut self) -> Edit {
+        let before: String = self.line[..self.cursor].iter().collect();
+        let word_start = self.line[..self.cursor]
+            .iter()
+            .rposition(|character| character.is_whitespace())
+            .map_or(0, |index| index + 1);
+        let word: String = self.line[word_start..self.cursor].iter().collect();
+        let candidates: Vec<String> = (self.completer)(&before)
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(&word))
+            .collect();
+
+        match candidates.as_slice() {
+            [] => {}
+            [candidate] => self.replace_word(word_start, &format!("{} ", candidate)),
+            [first, others @ ..] => {
+                let prefix = others.iter().fold(first.as_str(), |prefix, candidate| {
+                    let length = prefix
+                        .char_indices()
+                        .zip(candidate.chars())
+                        .find(|((_, a), b)| a != b)
+                        .map_or(prefix.len().min(candidate.len()), |((index, _), _)| index);
+                    &prefix[..length]
+                });
+                if prefix.len() <= word.len() {
+                    return Edit::Candidates(candidates);
+                }
+                let prefix = String::from(prefix);
+                self.replace_word(word_start, &prefix);
+            }
+        }
+        Edit::Pending
+    }
+
+    /// Replaces the characters from the start up to the cursor with the text and moves the cursor behind it.
+    fn replace_word(&mut self, start: usize, text: &str) {
+        self.line.splice(start..self.cursor, text.chars());
+        self.cursor = start + text.chars().count();
+    }
+
+    /// Starts a new empty line.
+    fn reset(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.recalled = None;
+        self.draft.clear();
+        self.search = None;
+    }
+}
+
+/// Completer for the first word of a line, which is a command name. Later words are left to other completers, e.g. for paths.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn complete_command(commands: &[&str], line: &str) -> Vec<String> {
+    if line.contains(char::is_whitespace) {
+        return Vec::new();
+    }
+    commands
+        .iter()
+        .filter(|command| command.starts_with(line))
+        .map(|command| String::from(*command))
+        .collect()
+}
@@ -0,0 +1,49 @@
+use alloc::{collections::VecDeque, string::String};
+
+/// Amount of lines kept in the history of the shell, older ones are dropped.
+#[allow(dead_code)] // no shell available yet
+pub(crate) const HISTORY_SIZE: usize = 100;
+
+/// Lines entered in the shell, newest first. Kept in memory for as long as the shell runs.
+#[allow(dead_code)] // no shell available yet
+#[derive(Debug)]
+pub(crate) struct History {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+#[allow(dead_code)] // no shell available yet
+impl History {
+    pub(crate) const fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Records an entered line. Blank lines and repetitions of the newest line are skipped.
+    pub(crate) fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.get(0) == Some(line) {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_back();
+        }
+        self.lines.push_front(String::from(line));
+    }
+
+    /// Returns the line entered `index` lines before the newest one.
+    pub(crate) fn get(&self, index: usize) -> Option<&str> {
+        self.lines.get(index).map(String::as_str)
+    }
+
+    /// Searches the lines from `start` on towards older ones for one containing the pattern. Returns its index.
+    pub(crate) fn search(&self, pattern: &str, start: usize) -> Option<usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find(|(_, line)| line.contains(pattern))
+            .map(|(index, _)| index)
+    }
+}
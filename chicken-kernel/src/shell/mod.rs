@@ -0,0 +1,3 @@
+pub(crate) mod editor;
+pub(crate) mod history;
+pub(crate) mod slabinfo;
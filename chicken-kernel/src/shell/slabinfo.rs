@@ -0,0 +1,70 @@
+use alloc::{format, string::String};
+use core::fmt::{self, Write};
+
+use crate::memory::kheap::{self, slab, stats};
+
+/// Prints the usage of the slab caches and the size classes of the kernel heap, like `slabinfo`, so the allocator can be tuned with `slab_prealloc=` and `slab_growth=`.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn slabinfo(out: &mut impl Write) -> fmt::Result {
+    writeln!(
+        out,
+        "{:<10} {:>6} {:>8} {:>6} {:>6} {:>10} {:>5} {:>7}",
+        "cache", "size", "in use", "total", "slabs", "allocs", "hits", "unused"
+    )?;
+    for cache in slab::statistics() {
+        writeln!(
+            out,
+            "{:<10} {:>6} {:>8} {:>6} {:>6} {:>10} {:>5} {:>6}%",
+            cache.name,
+            cache.object_size,
+            cache.objects_in_use,
+            cache.slabs * cache.objects_per_slab,
+            cache.slabs,
+            cache.allocations,
+            percent(cache.hit_rate_percent()),
+            cache.unused_percent()
+        )?;
+    }
+
+    let Some(heap) = kheap::statistics() else {
+        return writeln!(out, "kernel heap: not initialized");
+    };
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{:<10} {:>8} {:>10} {:>10} {:>5} {:>8}",
+        "class", "in use", "allocs", "frees", "hits", "failures"
+    )?;
+    for (index, class) in heap.size_classes.iter().enumerate() {
+        if class.allocations == 0 {
+            continue;
+        }
+        match stats::class_size(index) {
+            Some(size) => write!(out, "<={:<8}", size)?,
+            None => write!(out, "{:<10}", "larger")?,
+        }
+        writeln!(
+            out,
+            " {:>8} {:>10} {:>10} {:>5} {:>8}",
+            class.in_use(),
+            class.allocations,
+            class.frees,
+            percent(class.hit_rate_percent()),
+            class.failures
+        )?;
+    }
+    writeln!(
+        out,
+        "kernel heap: {} KiB, {} KiB free in {} blocks, largest {} KiB, {}% fragmented",
+        heap.heap_size / 1024,
+        heap.free_bytes / 1024,
+        heap.free_blocks,
+        heap.largest_free_block / 1024,
+        heap.fragmentation_percent()
+    )
+}
+
+/// Formats a hit rate, `-` if there have not been any allocations.
+fn percent(rate: Option<u64>) -> String {
+    rate.map_or_else(|| String::from("-"), |rate| format!("{}%", rate))
+}
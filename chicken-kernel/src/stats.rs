@@ -0,0 +1,150 @@
+use alloc::vec::Vec;
+use core::{
+    cell::OnceCell,
+    fmt::{Display, Formatter},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chicken_util::timing::{read_tsc, LoaderTimestamps};
+
+use crate::{base::io::timer::pit::get_current_uptime_ms, scheduling::spin::SpinLock};
+
+/// Minimum uptime in ms needed to calibrate the time stamp counter against the timer.
+const CALIBRATION_MIN_UPTIME_MS: u64 = 10;
+
+/// Boot phase timestamps of the loader, handed over in the boot info.
+static LOADER_TIMESTAMPS: SpinLock<OnceCell<LoaderTimestamps>> = SpinLock::new(OnceCell::new());
+/// Time stamp counter values recorded by the kernel at the end of each init phase, indexed by [`KernelPhase`].
+static KERNEL_TIMESTAMPS: [AtomicU64; KernelPhase::COUNT] =
+    [const { AtomicU64::new(0) }; KernelPhase::COUNT];
+
+/// Init phases of the kernel. Each phase is recorded once it has been completed.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum KernelPhase {
+    /// Entry of the kernel.
+    Entry,
+    Memory,
+    Video,
+    Base,
+    /// Last phase before interrupts are enabled and the timer starts counting the uptime.
+    Scheduler,
+}
+
+impl KernelPhase {
+    const COUNT: usize = 5;
+
+    fn name(&self) -> &'static str {
+        match self {
+            KernelPhase::Entry => "enter kernel",
+            KernelPhase::Memory => "set up memory management",
+            KernelPhase::Video => "set up video output",
+            KernelPhase::Base => "set up base architecture",
+            KernelPhase::Scheduler => "set up scheduler",
+        }
+    }
+}
+
+/// Stores the boot phase timestamps of the loader.
+pub(crate) fn set_loader_timestamps(timestamps: LoaderTimestamps) {
+    LOADER_TIMESTAMPS.lock().get_or_init(|| timestamps);
+}
+
+/// Records the completion of a kernel init phase.
+pub(crate) fn record(phase: KernelPhase) {
+    KERNEL_TIMESTAMPS[phase as usize].store(read_tsc(), Ordering::Relaxed);
+}
+
+/// Duration of a single boot phase in time stamp counter cycles.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct BootPhase {
+    pub(crate) name: &'static str,
+    pub(crate) cycles: u64,
+}
+
+/// Breakdown of the boot time from the loader entry up to enabling interrupts in the kernel.
+#[derive(Clone, Debug)]
+pub(crate) struct BootTimes {
+    pub(crate) phases: Vec<BootPhase>,
+    /// Time stamp counter cycles per ms, if the counter could be calibrated yet.
+    pub(crate) cycles_per_ms: Option<u64>,
+}
+
+impl BootTimes {
+    /// Total boot time in time stamp counter cycles.
+    pub(crate) fn total_cycles(&self) -> u64 {
+        self.phases.iter().map(|phase| phase.cycles).sum()
+    }
+}
+
+impl Display for BootTimes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let format_cycles = |f: &mut Formatter<'_>, name: &str, cycles: u64| match self
+            .cycles_per_ms
+        {
+            Some(cycles_per_ms) => writeln!(f, "{}: {} us", name, cycles * 1000 / cycles_per_ms),
+            None => writeln!(f, "{}: {} cycles", name, cycles),
+        };
+
+        for phase in &self.phases {
+            format_cycles(f, phase.name, phase.cycles)?;
+        }
+        format_cycles(f, "total", self.total_cycles())
+    }
+}
+
+/// Returns the boot time breakdown of the loader and the kernel. The time stamp counter is calibrated against the uptime, so durations are only available in cycles shortly after boot.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn boot_times() -> BootTimes {
+    let loader = LOADER_TIMESTAMPS
+        .lock()
+        .get()
+        .copied()
+        .unwrap_or_default();
+    let kernel = KERNEL_TIMESTAMPS
+        .each_ref()
+        .map(|timestamp| timestamp.load(Ordering::Relaxed));
+
+    let timestamps = [
+        ("load kernel file", loader.kernel_file_loaded),
+        ("parse kernel elf", loader.kernel_elf_parsed),
+        ("exit boot services", loader.boot_services_exited),
+        ("set up address space", loader.address_space_set_up),
+    ]
+    .into_iter()
+    .chain(
+        [
+            KernelPhase::Entry,
+            KernelPhase::Memory,
+            KernelPhase::Video,
+            KernelPhase::Base,
+            KernelPhase::Scheduler,
+        ]
+        .map(|phase| (phase.name(), kernel[phase as usize])),
+    );
+
+    let mut previous = loader.start;
+    let phases = timestamps
+        .map(|(name, timestamp)| {
+            // phases that have not been recorded take no time
+            let cycles = timestamp.saturating_sub(previous);
+            previous = previous.max(timestamp);
+            BootPhase { name, cycles }
+        })
+        .collect();
+
+    BootTimes {
+        phases,
+        cycles_per_ms: cycles_per_ms(),
+    }
+}
+
+/// Returns the time stamp counter cycles per ms, calibrated against the uptime. Returns `None` shortly after boot, while the uptime is too short for the calibration.
+pub(crate) fn cycles_per_ms() -> Option<u64> {
+    // the uptime starts counting, once interrupts are enabled right after the scheduler has been set up
+    let uptime = get_current_uptime_ms();
+    let interrupts_enabled =
+        KERNEL_TIMESTAMPS[KernelPhase::Scheduler as usize].load(Ordering::Relaxed);
+    (interrupts_enabled != 0 && uptime >= CALIBRATION_MIN_UPTIME_MS)
+        .then(|| read_tsc().saturating_sub(interrupts_enabled) / uptime)
+        .filter(|cycles_per_ms| *cycles_per_ms != 0)
+}
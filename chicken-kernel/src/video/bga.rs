@@ -0,0 +1,61 @@
+use crate::base::io::{inw, outw, Port};
+
+// Bochs graphics adapter, as emulated by QEMU (std vga), Bochs and VirtualBox. Unlike the GOP, it can still be programmed after boot services have been exited.
+const INDEX_PORT: Port = 0x1CE;
+const DATA_PORT: Port = 0x1CF;
+
+const INDEX_ID: u16 = 0;
+const INDEX_X_RESOLUTION: u16 = 1;
+const INDEX_Y_RESOLUTION: u16 = 2;
+const INDEX_BPP: u16 = 3;
+const INDEX_ENABLE: u16 = 4;
+const INDEX_VIRTUAL_WIDTH: u16 = 6;
+
+/// Range of known adapter versions.
+const ID_MIN: u16 = 0xB0C0;
+const ID_MAX: u16 = 0xB0C5;
+
+const DISABLED: u16 = 0x00;
+const ENABLED: u16 = 0x01;
+/// Use the linear framebuffer, which stays at the same physical address.
+const LFB_ENABLED: u16 = 0x40;
+
+/// Bits per pixel, matching [`chicken_util::graphics::framebuffer::BPP`].
+const BITS_PER_PIXEL: u16 = 32;
+
+/// Whether a Bochs graphics adapter is available.
+pub(super) fn is_present() -> bool {
+    let id = unsafe { read_register(INDEX_ID) };
+    (ID_MIN..=ID_MAX).contains(&id)
+}
+
+/// Switches to the specified resolution. Returns the amount of pixels per scanline of the new mode.
+///
+/// # Safety
+/// Needs IO privileges. The adapter must be present and the caller must stop accessing the framebuffer until it has been updated to the new mode.
+pub(super) unsafe fn set_resolution(width: u16, height: u16) -> usize {
+    unsafe {
+        // registers can only be changed while the display is disabled
+        write_register(INDEX_ENABLE, DISABLED);
+        write_register(INDEX_X_RESOLUTION, width);
+        write_register(INDEX_Y_RESOLUTION, height);
+        write_register(INDEX_BPP, BITS_PER_PIXEL);
+        write_register(INDEX_ENABLE, ENABLED | LFB_ENABLED);
+
+        read_register(INDEX_VIRTUAL_WIDTH) as usize
+    }
+}
+
+unsafe fn write_register(index: u16, value: u16) {
+    unsafe {
+        outw(INDEX_PORT, index);
+        outw(DATA_PORT, value);
+    }
+}
+
+unsafe fn read_register(index: u16) -> u16 {
+    unsafe {
+        outw(INDEX_PORT, index);
+        inw(DATA_PORT)
+    }
+}
@@ -0,0 +1,78 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{
+    base::interrupts::without_interrupts,
+    video::{
+        text::{self, WRITER},
+        BACKGROUND_COLOR,
+    },
+};
+
+const MS_PER_MINUTE: u64 = 60 * 1000;
+
+/// Milliseconds without input after which the screen is blanked, 0 if it is never blanked.
+static TIMEOUT_MS: AtomicU64 = AtomicU64::new(0);
+/// Uptime of the last input event.
+static LAST_INPUT_MS: AtomicU64 = AtomicU64::new(0);
+static BLANKED: AtomicBool = AtomicBool::new(false);
+
+/// Sets the minutes without input after which the screen is blanked. 0 disables blanking and wakes the screen up.
+pub(crate) fn set_timeout(minutes: u64) {
+    TIMEOUT_MS.store(minutes.saturating_mul(MS_PER_MINUTE), Ordering::Relaxed);
+    if minutes == 0 {
+        wake();
+    }
+}
+
+/// Returns the minutes without input after which the screen is blanked, 0 if it is never blanked.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn timeout() -> u64 {
+    TIMEOUT_MS.load(Ordering::Relaxed) / MS_PER_MINUTE
+}
+
+/// Returns whether the screen is blanked. Console output is only recorded in the log history until it wakes up again.
+pub(crate) fn is_blanked() -> bool {
+    BLANKED.load(Ordering::Acquire)
+}
+
+/// Blanks the screen, once there has not been any input for the configured time. Called on every timer tick.
+pub(crate) fn tick(uptime_ms: u64) {
+    let timeout = TIMEOUT_MS.load(Ordering::Relaxed);
+    if timeout == 0 || is_blanked() {
+        return;
+    }
+    if uptime_ms.saturating_sub(LAST_INPUT_MS.load(Ordering::Relaxed)) >= timeout {
+        blank();
+    }
+}
+
+/// Resets the idle time. Wakes the screen up, if it is blanked. Called on every input event.
+pub(crate) fn input(uptime_ms: u64) {
+    LAST_INPUT_MS.store(uptime_ms, Ordering::Relaxed);
+    wake();
+}
+
+/// Fills the screen with the background color.
+fn blank() {
+    without_interrupts(|| {
+        BLANKED.store(true, Ordering::Release);
+        // the console is only one of the surfaces, so the compositor blanks the entire screen
+        #[cfg(feature = "graphics-compositor")]
+        if crate::video::compositor::blank() {
+            return;
+        }
+        if let Some(writer) = WRITER.lock().get_mut() {
+            writer.framebuffer().fill(BACKGROUND_COLOR);
+        }
+    })
+}
+
+/// Restores the screen from the log history.
+fn wake() {
+    if !BLANKED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    #[cfg(feature = "graphics-compositor")]
+    without_interrupts(crate::video::compositor::damage_all);
+    text::rerender();
+}
@@ -0,0 +1,92 @@
+use alloc::vec::Vec;
+
+use chicken_util::graphics::Color;
+
+use crate::video::VideoError;
+
+const BMP_SIGNATURE: &[u8; 2] = b"BM";
+/// Size of the file header and the smallest supported info header (BITMAPINFOHEADER).
+const MIN_HEADER_SIZE: usize = 14 + 40;
+
+/// Uncompressed pixels.
+const COMPRESSION_RGB: u32 = 0;
+/// Uncompressed pixels with color masks. Only the default BGRA layout is supported.
+const COMPRESSION_BITFIELDS: u32 = 3;
+
+/// Decoded image with pixels stored row by row, starting at the top left.
+#[derive(Debug)]
+pub(crate) struct Bitmap {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) pixels: Vec<Color>,
+}
+
+impl Bitmap {
+    /// Decodes an uncompressed 24 or 32 bit BMP file.
+    pub(crate) fn parse(data: &[u8]) -> Result<Self, VideoError> {
+        if data.len() < MIN_HEADER_SIZE || &data[..2] != BMP_SIGNATURE {
+            return Err(VideoError::InvalidImage);
+        }
+
+        let pixel_offset = read_u32(data, 10) as usize;
+        let width = read_u32(data, 18) as i32;
+        let height = read_u32(data, 22) as i32;
+        let bits_per_pixel = read_u16(data, 28);
+        let compression = read_u32(data, 30);
+
+        let bytes_per_pixel = match (bits_per_pixel, compression) {
+            (24, COMPRESSION_RGB) => 3,
+            (32, COMPRESSION_RGB | COMPRESSION_BITFIELDS) => 4,
+            _ => return Err(VideoError::UnsupportedImageFormat),
+        };
+        if width <= 0 || height == 0 {
+            return Err(VideoError::InvalidImage);
+        }
+
+        // rows are stored bottom-up, unless the height is negative
+        let top_down = height < 0;
+        let width = width as usize;
+        let height = height.unsigned_abs() as usize;
+        // rows are padded to 4 bytes
+        let row_size = (width * bytes_per_pixel).next_multiple_of(4);
+
+        let end = row_size
+            .checked_mul(height)
+            .and_then(|size| size.checked_add(pixel_offset))
+            .ok_or(VideoError::InvalidImage)?;
+        if end > data.len() {
+            return Err(VideoError::InvalidImage);
+        }
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let stored_row = if top_down { row } else { height - 1 - row };
+            let row_data = &data[pixel_offset + stored_row * row_size..][..width * bytes_per_pixel];
+
+            pixels.extend(row_data.chunks_exact(bytes_per_pixel).map(|pixel| Color {
+                red: pixel[2],
+                green: pixel[1],
+                blue: pixel[0],
+            }));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
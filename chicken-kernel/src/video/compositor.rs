@@ -0,0 +1,442 @@
+use alloc::{vec, vec::Vec};
+use core::{cell::OnceCell, mem, ptr};
+
+use chicken_util::graphics::{
+    framebuffer::{FrameBufferMetadata, BPP},
+    Color,
+};
+
+use crate::{
+    base::interrupts::without_interrupts,
+    memory::vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+    println, serial_println,
+    scheduling::{spin::SpinLock, GlobalTaskScheduler},
+    video::{
+        framebuffer::{RawFrameBuffer, Rect},
+        blank,
+        text::{self, WRITER},
+        VideoError, BACKGROUND_COLOR,
+    },
+};
+
+/// Time between two frames of the compositor in ms.
+const FRAME_INTERVAL_MS: u64 = 16;
+/// Z-order of the console. Surfaces with a higher z-order are drawn on top of it.
+const CONSOLE_Z: i32 = 0;
+
+static COMPOSITOR: SpinLock<OnceCell<Compositor>> = SpinLock::new(OnceCell::new());
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SurfaceId(u64);
+
+/// Rectangular area of pixels owned by a task. Surfaces are drawn onto the screen by the compositor, ordered by their z-order.
+#[derive(Debug)]
+struct Surface {
+    id: SurfaceId,
+    /// PID of the task owning the surface. The surface is removed once the task has died.
+    owner: u64,
+    /// Position and size on the screen.
+    rect: Rect,
+    z: i32,
+    /// Pixels in the pixel format of the screen.
+    buffer: RawFrameBuffer,
+}
+
+#[derive(Debug)]
+struct Compositor {
+    screen: RawFrameBuffer,
+    /// Surfaces ordered from bottom to top.
+    surfaces: Vec<Surface>,
+    /// Area of the screen that has to be redrawn.
+    damage: Option<Rect>,
+    id_counter: u64,
+    console: Option<SurfaceId>,
+}
+
+impl Compositor {
+    fn new(screen: RawFrameBuffer) -> Self {
+        let mut instance = Self {
+            screen,
+            surfaces: Vec::new(),
+            damage: None,
+            id_counter: 0,
+            console: None,
+        };
+        instance.damage_screen(instance.screen.rect());
+        instance
+    }
+
+    fn create_surface(&mut self, owner: u64, rect: Rect, z: i32) -> Result<SurfaceId, VideoError> {
+        let buffer = allocate_buffer(rect.width, rect.height, self.screen.meta_data.is_rgb)?;
+
+        // every surface ever created has a unique ID
+        self.id_counter += 1;
+        let id = SurfaceId(self.id_counter);
+
+        // new surfaces are drawn on top of the ones with the same z-order
+        let index = self.surfaces.partition_point(|surface| surface.z <= z);
+        self.surfaces.insert(
+            index,
+            Surface {
+                id,
+                owner,
+                rect,
+                z,
+                buffer,
+            },
+        );
+        self.damage_screen(rect);
+
+        Ok(id)
+    }
+
+    fn remove_surface(&mut self, index: usize) -> Result<(), VideoError> {
+        let surface = self.surfaces.remove(index);
+        if self.console == Some(surface.id) {
+            self.console = None;
+        }
+        self.damage_screen(surface.rect);
+        free_buffer(&surface.buffer)
+    }
+
+    /// Removes the surfaces of tasks that have died.
+    fn remove_orphans(&mut self) -> Result<(), VideoError> {
+        while let Some(index) = self
+            .surfaces
+            .iter()
+            .position(|surface| !GlobalTaskScheduler::task_alive(surface.owner))
+        {
+            self.remove_surface(index)?;
+        }
+        Ok(())
+    }
+
+    fn index(&self, id: SurfaceId) -> Result<usize, VideoError> {
+        self.surfaces
+            .iter()
+            .position(|surface| surface.id == id)
+            .ok_or(VideoError::SurfaceNotFound(id.0))
+    }
+
+    /// Marks an area of the screen to be redrawn.
+    fn damage_screen(&mut self, rect: Rect) {
+        let Some(rect) = rect.intersection(&self.screen.rect()) else {
+            return;
+        };
+        self.damage = Some(match self.damage {
+            Some(damage) => damage.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Marks an area of a surface, relative to its origin, to be redrawn.
+    fn damage_surface(&mut self, index: usize, rect: Rect) {
+        let surface_rect = self.surfaces[index].rect;
+        if let Some(rect) = rect
+            .offset(surface_rect.x, surface_rect.y)
+            .intersection(&surface_rect)
+        {
+            self.damage_screen(rect);
+        }
+    }
+
+    /// Draws a row of the screen starting at `x`. The row is composed in the provided buffer first, so the screen does not flicker.
+    fn compose_row(&self, x: usize, y: usize, row: &mut [u8]) {
+        let Some(span) = Rect::new(x, y, row.len() / BPP, 1).intersection(&self.screen.rect())
+        else {
+            return;
+        };
+        let row = &mut row[..span.width * BPP];
+
+        let background = self.screen.encode(BACKGROUND_COLOR);
+        for pixel in row.chunks_exact_mut(BPP) {
+            pixel.copy_from_slice(&background);
+        }
+
+        // draw from bottom to top
+        for surface in &self.surfaces {
+            if let Some(overlap) = span.intersection(&surface.rect) {
+                let destination = &mut row[(overlap.x - span.x) * BPP..][..overlap.width * BPP];
+                unsafe {
+                    let source = surface
+                        .buffer
+                        .row_ptr(y - surface.rect.y)
+                        .add((overlap.x - surface.rect.x) * BPP);
+                    ptr::copy_nonoverlapping(source, destination.as_mut_ptr(), destination.len());
+                }
+            }
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                row.as_ptr(),
+                self.screen.row_ptr(y).add(span.x * BPP),
+                row.len(),
+            );
+        }
+    }
+}
+
+/// Allocates zeroed memory for the pixels of a surface.
+fn allocate_buffer(
+    width: usize,
+    height: usize,
+    is_rgb: bool,
+) -> Result<RawFrameBuffer, VideoError> {
+    if width == 0 || height == 0 {
+        return Err(VideoError::EmptySurface);
+    }
+
+    let size = width * height * BPP;
+    let mut binding = VMM.lock();
+    let vmm = binding
+        .get_mut()
+        .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+    let base = vmm.alloc(size, VmFlags::WRITE, AllocationType::AnyPages)?;
+
+    Ok(RawFrameBuffer::from(FrameBufferMetadata {
+        base,
+        size,
+        width,
+        height,
+        stride: width,
+        is_rgb,
+    }))
+}
+
+fn free_buffer(buffer: &RawFrameBuffer) -> Result<(), VideoError> {
+    let mut binding = VMM.lock();
+    let vmm = binding
+        .get_mut()
+        .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+    vmm.free(buffer.meta_data.base)?;
+    Ok(())
+}
+
+/// Runs a function on the compositor with interrupts disabled.
+fn with_compositor<R>(
+    f: impl FnOnce(&mut Compositor) -> Result<R, VideoError>,
+) -> Result<R, VideoError> {
+    without_interrupts(|| {
+        let mut binding = COMPOSITOR.lock();
+        let compositor = binding.get_mut().ok_or(VideoError::VideoUninitialized)?;
+        f(compositor)
+    })
+}
+
+/// Creates a new blank surface owned by the current task. The surface is placed on top of all surfaces with the same or a lower z-order.
+#[allow(dead_code)] // no graphical applications yet
+pub(crate) fn create_surface(rect: Rect, z: i32) -> Result<SurfaceId, VideoError> {
+    let owner = GlobalTaskScheduler::current_pid().ok_or(VideoError::VideoUninitialized)?;
+    with_compositor(|compositor| compositor.create_surface(owner, rect, z))
+}
+
+/// Removes a surface from the screen and frees its memory.
+#[allow(dead_code)] // no graphical applications yet
+pub(crate) fn destroy_surface(id: SurfaceId) -> Result<(), VideoError> {
+    with_compositor(|compositor| {
+        let index = compositor.index(id)?;
+        compositor.remove_surface(index)
+    })
+}
+
+/// Moves a surface to the specified position on the screen.
+#[allow(dead_code)] // no graphical applications yet
+pub(crate) fn move_surface(id: SurfaceId, x: usize, y: usize) -> Result<(), VideoError> {
+    with_compositor(|compositor| {
+        let index = compositor.index(id)?;
+        let previous = compositor.surfaces[index].rect;
+        let rect = Rect::new(x, y, previous.width, previous.height);
+        compositor.surfaces[index].rect = rect;
+
+        compositor.damage_screen(previous);
+        compositor.damage_screen(rect);
+        Ok(())
+    })
+}
+
+/// Changes the z-order of a surface. The surface is placed on top of all surfaces with the same or a lower z-order.
+#[allow(dead_code)] // no graphical applications yet
+pub(crate) fn set_z_order(id: SurfaceId, z: i32) -> Result<(), VideoError> {
+    with_compositor(|compositor| {
+        let index = compositor.index(id)?;
+        let mut surface = compositor.surfaces.remove(index);
+        surface.z = z;
+        let rect = surface.rect;
+
+        let index = compositor
+            .surfaces
+            .partition_point(|surface| surface.z <= z);
+        compositor.surfaces.insert(index, surface);
+        compositor.damage_screen(rect);
+        Ok(())
+    })
+}
+
+/// Fills an area of a surface, relative to its origin, with a color.
+#[allow(dead_code)] // no graphical applications yet
+pub(crate) fn fill(id: SurfaceId, rect: Rect, color: Color) -> Result<(), VideoError> {
+    with_compositor(|compositor| {
+        let index = compositor.index(id)?;
+        let buffer = &compositor.surfaces[index].buffer;
+        let Some(rect) = rect.intersection(&buffer.rect()) else {
+            return Ok(());
+        };
+
+        let pixel = buffer.encode(color);
+        for y in rect.y..rect.bottom() {
+            let row = unsafe { buffer.row_ptr(y).add(rect.x * BPP) };
+            for x in 0..rect.width {
+                unsafe { ptr::copy_nonoverlapping(pixel.as_ptr(), row.add(x * BPP), BPP) };
+            }
+        }
+
+        compositor.damage_surface(index, rect);
+        Ok(())
+    })
+}
+
+/// Copies an image with the specified width onto a surface at `x`, `y` relative to its origin. Pixels outside the surface are skipped.
+#[allow(dead_code)] // no graphical applications yet
+pub(crate) fn blit(
+    id: SurfaceId,
+    x: usize,
+    y: usize,
+    width: usize,
+    pixels: &[Color],
+) -> Result<(), VideoError> {
+    with_compositor(|compositor| {
+        let index = compositor.index(id)?;
+        if let Some(rect) = compositor.surfaces[index].buffer.blit(x, y, width, pixels) {
+            compositor.damage_surface(index, rect);
+        }
+        Ok(())
+    })
+}
+
+/// Marks an area of the console to be redrawn. Does nothing, if the compositor is not running. Must be called with interrupts disabled.
+pub(super) fn damage_console(rect: Rect) {
+    if let Some(compositor) = COMPOSITOR.lock().get_mut() {
+        if let Some(index) = compositor
+            .console
+            .and_then(|console| compositor.index(console).ok())
+        {
+            compositor.damage_surface(index, rect);
+        }
+    }
+}
+
+/// Fills the screen with the background color. Returns false, if the compositor is not running. Must be called with interrupts disabled.
+pub(super) fn blank() -> bool {
+    let mut binding = COMPOSITOR.lock();
+    let Some(compositor) = binding.get_mut() else {
+        return false;
+    };
+    compositor.screen.fill(BACKGROUND_COLOR);
+    true
+}
+
+/// Marks the entire screen to be redrawn, e.g. after it has been blanked. Must be called with interrupts disabled.
+pub(super) fn damage_all() {
+    if let Some(compositor) = COMPOSITOR.lock().get_mut() {
+        let rect = compositor.screen.rect();
+        compositor.damage_screen(rect);
+    }
+}
+
+/// Updates the screen after the video mode has changed. The console is resized to cover the new screen. Returns the new framebuffer of the console, if the compositor is running. Must be called with interrupts disabled.
+pub(super) fn set_screen(screen: RawFrameBuffer) -> Result<Option<RawFrameBuffer>, VideoError> {
+    let mut binding = COMPOSITOR.lock();
+    let Some(compositor) = binding.get_mut() else {
+        return Ok(None);
+    };
+
+    compositor.screen = screen;
+    compositor.damage = None;
+    compositor.damage_screen(compositor.screen.rect());
+
+    let Some(console) = compositor.console else {
+        return Ok(None);
+    };
+    let index = compositor.index(console)?;
+    let rect = compositor.screen.rect();
+    let buffer = allocate_buffer(rect.width, rect.height, compositor.screen.meta_data.is_rgb)?;
+
+    let surface = &mut compositor.surfaces[index];
+    surface.rect = rect;
+    let previous = mem::replace(&mut surface.buffer, buffer);
+    free_buffer(&previous)?;
+
+    Ok(Some(surface.buffer.clone()))
+}
+
+/// Entry of the compositor task. Moves the console onto a surface and periodically draws the damaged areas of all surfaces onto the screen. Takes over the surfaces of a previous compositor task, if it has been restarted after a crash.
+pub(crate) fn run() {
+    let started = without_interrupts(|| -> Result<(), VideoError> {
+        let mut binding = WRITER.lock();
+        let writer = binding.get_mut().ok_or(VideoError::VideoUninitialized)?;
+        let owner = GlobalTaskScheduler::current_pid().ok_or(VideoError::VideoUninitialized)?;
+
+        if let Some(compositor) = COMPOSITOR.lock().get_mut() {
+            // the console surface would otherwise be removed along with the crashed task
+            if let Some(console) = compositor.console {
+                let index = compositor.index(console)?;
+                compositor.surfaces[index].owner = owner;
+            }
+            compositor.damage_screen(compositor.screen.rect());
+            return Ok(());
+        }
+
+        // the console covers the entire screen
+        let mut compositor = Compositor::new(writer.framebuffer().clone());
+        let console = compositor.create_surface(owner, compositor.screen.rect(), CONSOLE_Z)?;
+        let index = compositor.index(console)?;
+        writer.set_framebuffer(compositor.surfaces[index].buffer.clone());
+        compositor.console = Some(console);
+
+        COMPOSITOR
+            .lock()
+            .set(compositor)
+            .map_err(|_| VideoError::CompositorAlreadyRunning)
+    });
+    if let Err(err) = started {
+        println!("video: Could not start compositor: {}", err);
+        return;
+    }
+
+    // draw the console output onto its surface
+    text::rerender();
+
+    loop {
+        let damage = without_interrupts(|| {
+            let mut binding = COMPOSITOR.lock();
+            let compositor = binding.get_mut()?;
+            if let Err(err) = compositor.remove_orphans() {
+                serial_println!("video: Could not remove surface: {}", err);
+            }
+            // the damage is kept until the screen wakes up
+            if blank::is_blanked() {
+                return None;
+            }
+            compositor.damage.take()
+        });
+
+        if let Some(damage) = damage {
+            let mut row = vec![0u8; damage.width * BPP];
+            // surfaces may change in between rows, those changes are drawn in the next frame
+            for y in damage.y..damage.bottom() {
+                without_interrupts(|| {
+                    // the entire screen is redrawn once it wakes up
+                    if blank::is_blanked() {
+                        return;
+                    }
+                    if let Some(compositor) = COMPOSITOR.lock().get() {
+                        compositor.compose_row(damage.x, y, &mut row);
+                    }
+                });
+            }
+        }
+
+        GlobalTaskScheduler::sleep(FRAME_INTERVAL_MS);
+    }
+}
@@ -1,4 +1,4 @@
-use core::{fmt::Debug, ptr::write_volatile};
+use core::{fmt::Debug, ptr, ptr::write_volatile};
 
 use chicken_util::graphics::{
     font::Font,
@@ -55,20 +55,21 @@ impl RawFrameBuffer {
 }
 
 impl RawFrameBuffer {
-    pub(in crate::video) fn draw_char(
+    /// Draws the glyph with the given index of the font. Characters are mapped to their glyph using [`crate::video::glyph::GlyphMap`].
+    pub(in crate::video) fn draw_glyph(
         &self,
-        character: char,
+        glyph: usize,
         x_offset: usize,
         y_offset: usize,
         foreground_color: Color,
         background_color: Color,
         font: Font,
     ) -> Result<(), VideoError> {
-        if character as usize >= font.glyphs().len() {
+        if glyph >= font.glyphs().len() {
             return Err(VideoError::UnsupportedCharacter);
         }
 
-        let character_offset = character as usize * font.glyph_bytes();
+        let character_offset = glyph * font.glyph_bytes();
         let character_ptr = unsafe { font.glyph_buffer_address.add(character_offset) };
 
         let glyph_height = font.glyph_height();
@@ -95,6 +96,65 @@ impl RawFrameBuffer {
 }
 
 impl RawFrameBuffer {
+    /// Area covered by the framebuffer, with its origin at 0, 0.
+    pub(in crate::video) fn rect(&self) -> Rect {
+        Rect::new(0, 0, self.meta_data.width, self.meta_data.height)
+    }
+
+    /// Pointer to the first byte of the specified scanline.
+    pub(in crate::video) fn row_ptr(&self, y: usize) -> *mut u8 {
+        unsafe { (self.meta_data.base as *mut u8).add(self.meta_data.stride * BPP * y) }
+    }
+
+    /// Returns a framebuffer covering `height` scanlines of this one, starting at `y`.
+    pub(in crate::video) fn region(&self, y: usize, height: usize) -> RawFrameBuffer {
+        let y = y.min(self.meta_data.height);
+        let height = height.min(self.meta_data.height - y);
+        let pitch = self.meta_data.stride * BPP;
+
+        RawFrameBuffer::from(FrameBufferMetadata {
+            base: self.meta_data.base + (pitch * y) as u64,
+            size: pitch * height,
+            height,
+            ..self.meta_data
+        })
+    }
+
+    /// Copies an image with the specified width to `x`, `y`. Pixels outside the framebuffer are skipped. Returns the area drawn onto.
+    pub(in crate::video) fn blit(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        pixels: &[Color],
+    ) -> Option<Rect> {
+        if width == 0 {
+            return None;
+        }
+        let image = Rect::new(x, y, width, pixels.len() / width);
+        let rect = image.intersection(&self.rect())?;
+
+        for row in rect.y..rect.y + rect.height {
+            let source = &pixels[(row - y) * width + (rect.x - x)..][..rect.width];
+            let destination = unsafe { self.row_ptr(row).add(rect.x * BPP) };
+            for (column, color) in source.iter().enumerate() {
+                let pixel = self.encode(*color);
+                unsafe { ptr::copy_nonoverlapping(pixel.as_ptr(), destination.add(column * BPP), BPP) };
+            }
+        }
+
+        Some(rect)
+    }
+
+    /// Bytes of a pixel with the specified color in the pixel format of the framebuffer.
+    pub(in crate::video) fn encode(&self, color: Color) -> [u8; BPP] {
+        if self.meta_data.is_rgb {
+            [color.red, color.green, color.blue, 0]
+        } else {
+            [color.blue, color.green, color.red, 0]
+        }
+    }
+
     /// Whether a point is within the framebuffer vram
     fn in_bounds(&self, x: usize, y: usize) -> bool {
         x < self.meta_data.width && y < self.meta_data.height
@@ -106,3 +166,56 @@ impl From<FrameBufferMetadata> for RawFrameBuffer {
         Self { meta_data: value }
     }
 }
+
+/// Rectangular area in pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Rect {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl Rect {
+    pub(crate) const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub(in crate::video) fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub(in crate::video) fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    /// Returns the area covered by both rectangles, if they overlap.
+    pub(crate) fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        (right > x && bottom > y).then(|| Rect::new(x, y, right - x, bottom - y))
+    }
+
+    /// Returns the smallest rectangle containing both rectangles.
+    pub(crate) fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    #[allow(dead_code)] // only used by the compositor
+    pub(in crate::video) fn offset(&self, x: usize, y: usize) -> Rect {
+        Rect::new(self.x + x, self.y + y, self.width, self.height)
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::vec::Vec;
+
+use chicken_util::graphics::font::Font;
+
+/// Character drawn in place of characters the font has no glyph for.
+const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+/// Drawn instead, if the font does not have a glyph for the replacement character either.
+const FALLBACK_CHARACTER: char = '?';
+
+/// Maps characters to the glyphs of a font. Uses the unicode table of the font, if it has one, otherwise characters are drawn with the glyph at the index of their code point.
+#[derive(Debug)]
+pub(super) struct GlyphMap {
+    /// Characters and their glyph index, sorted by character. Empty, if the font does not have a unicode table.
+    mappings: Vec<(char, usize)>,
+    glyph_count: usize,
+    /// Glyph drawn for characters that are not supported by the font.
+    replacement: usize,
+}
+
+impl GlyphMap {
+    pub(super) fn new(font: &Font) -> Self {
+        let mut mappings = font
+            .unicode_mappings()
+            .filter(|(_, glyph)| *glyph < font.glyph_buffer_size)
+            .collect::<Vec<_>>();
+        // the first glyph listed for a character is used
+        mappings.sort_by_key(|(character, _)| *character);
+        mappings.dedup_by_key(|(character, _)| *character);
+
+        let mut map = Self {
+            mappings,
+            glyph_count: font.glyph_buffer_size,
+            replacement: 0,
+        };
+        map.replacement = map
+            .lookup(REPLACEMENT_CHARACTER)
+            .or_else(|| map.lookup(FALLBACK_CHARACTER))
+            .unwrap_or(0);
+        map
+    }
+
+    /// Returns the index of the glyph the character is drawn with. Characters the font does not support are drawn with the replacement glyph.
+    pub(super) fn glyph(&self, character: char) -> usize {
+        self.lookup(character).unwrap_or(self.replacement)
+    }
+
+    fn lookup(&self, character: char) -> Option<usize> {
+        if self.mappings.is_empty() {
+            return Some(character as usize).filter(|glyph| *glyph < self.glyph_count);
+        }
+        self.mappings
+            .binary_search_by_key(&character, |(character, _)| *character)
+            .ok()
+            .map(|index| self.mappings[index].1)
+    }
+}
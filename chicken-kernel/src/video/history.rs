@@ -0,0 +1,122 @@
+use alloc::{collections::VecDeque, string::String};
+use core::{cell::OnceCell, fmt::Write};
+
+use crate::scheduling::spin::SpinLock;
+
+/// Amount of console output in bytes that is kept in the log history.
+pub(crate) const LOG_HISTORY_SIZE: usize = 16 * 1024; // 16 KiB
+
+pub(crate) static HISTORY: SpinLock<OnceCell<LogHistory>> = SpinLock::new(OnceCell::new());
+
+/// Bounded buffer containing the most recent console output. It is independent of the framebuffer, so the screen can be re-rendered after the video output changes.
+#[derive(Debug)]
+pub(crate) struct LogHistory {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl LogHistory {
+    /// Creates a new log history that keeps at most `capacity` bytes. The memory is allocated up front, so recording output never allocates.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Appends a string to the history, dropping the oldest output if the capacity is exceeded.
+    pub(crate) fn record(&mut self, s: &str) {
+        let bytes = s.as_bytes();
+        // only the tail of oversized strings fits into the buffer
+        let bytes = &bytes[bytes.len().saturating_sub(self.capacity)..];
+
+        let overflow = (self.buffer.len() + bytes.len()).saturating_sub(self.capacity);
+        self.buffer.drain(..overflow);
+        self.buffer.extend(bytes);
+
+        // never start with a partial utf-8 character
+        while self
+            .buffer
+            .front()
+            .is_some_and(|byte| byte & 0b1100_0000 == 0b1000_0000)
+        {
+            self.buffer.pop_front();
+        }
+    }
+
+    /// Drops the recorded output, keeping the memory.
+    #[cfg(feature = "ktest")]
+    pub(crate) fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Returns whether the text is part of the recorded output. Does not allocate, so it can be called while output is recorded.
+    #[cfg(feature = "ktest")]
+    pub(crate) fn contains(&mut self, text: &str) -> bool {
+        let text = text.as_bytes();
+        text.is_empty()
+            || self
+                .buffer
+                .make_contiguous()
+                .windows(text.len())
+                .any(|window| window == text)
+    }
+
+    /// Returns the recorded output as a string.
+    pub(crate) fn contents(&self) -> String {
+        let (front, back) = self.buffer.as_slices();
+        let mut contents = String::with_capacity(self.buffer.len());
+        contents.push_str(&String::from_utf8_lossy(front));
+        contents.push_str(&String::from_utf8_lossy(back));
+        contents
+    }
+
+    /// Returns the last `rows` lines of the recorded output, assuming lines are wrapped after `columns` characters.
+    pub(crate) fn tail(&self, rows: usize, columns: usize) -> String {
+        let contents = self.contents();
+        let columns = columns.max(1);
+        let mut remaining_rows = rows;
+        let mut start = contents.len();
+
+        for line in contents.rsplit('\n') {
+            let line_rows = line.chars().count().div_ceil(columns).max(1);
+            if line_rows > remaining_rows {
+                // only the end of the line fits onto the screen
+                let skip = (line_rows - remaining_rows) * columns;
+                let line_start = start - line.len();
+                start = line
+                    .char_indices()
+                    .nth(skip)
+                    .map(|(index, _)| line_start + index)
+                    .unwrap_or(start);
+                break;
+            }
+            remaining_rows -= line_rows;
+            start -= line.len();
+            if remaining_rows == 0 {
+                break;
+            }
+            // include separating newline
+            start = start.saturating_sub(1);
+        }
+
+        String::from(&contents[start..])
+    }
+}
+
+impl Write for LogHistory {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.record(s);
+        Ok(())
+    }
+}
+
+/// Returns the recorded console output, e.g. for a `dmesg`-style command.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn dump() -> String {
+    HISTORY
+        .lock()
+        .get()
+        .map(LogHistory::contents)
+        .unwrap_or_default()
+}
@@ -1,25 +1,47 @@
-// note: for now just using qemu_println, will later be changed to custom implementation.
-
 use core::{
+    cell::OnceCell,
     error::Error,
     fmt::{Debug, Display, Formatter},
 };
 
-use chicken_util::{graphics::Color, BootInfo};
+use chicken_util::{
+    graphics::{
+        font::Font,
+        framebuffer::{FrameBufferMetadata, VideoModeList, BPP},
+        Color,
+    },
+    BootInfo,
+};
 
 use crate::{
+    base::interrupts::without_interrupts,
+    memory::{
+        direct_map::virt_to_phys,
+        vmm::{object::VmFlags, AllocationType, VmmError, VMM},
+    },
     println,
+    scheduling::spin::SpinLock,
     video::{
         framebuffer::RawFrameBuffer,
-        text::{Writer, WRITER},
+        history::{LogHistory, HISTORY, LOG_HISTORY_SIZE},
+        text::{set_panic_writer, Writer, WRITER},
     },
 };
 
+mod bga;
+pub(crate) mod blank;
+mod bmp;
+#[cfg(feature = "graphics-compositor")]
+pub(crate) mod compositor;
 pub(super) mod framebuffer;
+mod glyph;
+pub(crate) mod history;
+pub(crate) mod splash;
 pub mod text;
 
 const FOREGROUND_COLOR: Color = Color::white();
 const BACKGROUND_COLOR: Color = Color::black();
+const PANIC_BACKGROUND_COLOR: Color = Color::red();
 
 const CHICKEN_OS: &str = r#"
    _____ _     _      _               ____   _____
@@ -30,28 +52,161 @@ const CHICKEN_OS: &str = r#"
   \_____|_| |_|_|\___|_|\_\___|_| |_|\____/|_____/
                                                    "#;
 
+/// Video modes recorded by the loader.
+static VIDEO_MODES: SpinLock<OnceCell<VideoModeList>> = SpinLock::new(OnceCell::new());
+
 pub(super) fn set_up(boot_info: &BootInfo) {
+    VIDEO_MODES.lock().get_or_init(|| boot_info.video_modes);
+
     // initialize framebuffer
     let framebuffer = RawFrameBuffer::from(boot_info.framebuffer_metadata);
     framebuffer.fill(Color::black());
 
+    blank::set_timeout(boot_info.screen_blank_minutes);
+
+    // the console must work even if the loader could not hand over a font
+    let font = if boot_info.font.is_usable() {
+        boot_info.font
+    } else {
+        Font::builtin()
+    };
+
+    // initialize log history, so output can be re-rendered later on
+    HISTORY
+        .lock()
+        .get_or_init(|| LogHistory::new(LOG_HISTORY_SIZE));
+
+    // panic output is drawn over the top of the screen by a separate writer
+    set_panic_writer(Writer::new(
+        font,
+        framebuffer.clone(),
+        FOREGROUND_COLOR,
+        PANIC_BACKGROUND_COLOR,
+    ));
+
+    // boot progress is printed below the splash image
+    let splash = splash::show(&framebuffer, font.glyph_height());
+    let console = match splash {
+        Ok(Some(ref console)) => console.clone(),
+        _ => framebuffer,
+    };
+
     // initialize global writer
     WRITER.lock().get_or_init(|| {
         Writer::new(
-            boot_info.font,
-            framebuffer,
+            font,
+            console,
             FOREGROUND_COLOR,
             BACKGROUND_COLOR,
         )
     });
 
-    println!("{}", CHICKEN_OS);
+    match splash {
+        Ok(Some(_)) => {}
+        Ok(None) => println!("{}", CHICKEN_OS),
+        Err(err) => {
+            println!("{}", CHICKEN_OS);
+            println!("video: Could not display splash image: {}", err);
+        }
+    }
+}
+
+/// Switches the framebuffer to the specified resolution. The framebuffer is remapped and the console output is redrawn to fit the new size.
+#[allow(dead_code)] // no shell available yet
+pub(crate) fn set_mode(width: usize, height: usize) -> Result<(), VideoError> {
+    // the splash screen layout does not fit the new resolution
+    splash::finish();
+
+    let mode = VIDEO_MODES
+        .lock()
+        .get()
+        .and_then(|modes| modes.find(width, height))
+        .ok_or(VideoError::UnsupportedMode(width, height))?;
+
+    // the gop is no longer available after boot services have been exited
+    if !bga::is_present() {
+        return Err(VideoError::ModeSwitchUnsupported);
+    }
+
+    without_interrupts(|| -> Result<(), VideoError> {
+        let mut binding = WRITER.lock();
+        let writer = binding.get_mut().ok_or(VideoError::VideoUninitialized)?;
+        let previous = writer.framebuffer().meta_data;
+
+        // the linear framebuffer stays at the same physical address
+        let physical_base =
+            virt_to_phys(previous.base).ok_or(VideoError::VideoUninitialized)?;
+
+        let framebuffer = {
+            let mut vmm = VMM.lock();
+            let vmm = vmm
+                .get_mut()
+                .ok_or(VmmError::GlobalVirtualMemoryManagerUninitialized)?;
+
+            let stride = unsafe { bga::set_resolution(width as u16, height as u16) };
+            let size = stride * height * BPP;
+            let base = vmm
+                .alloc(
+                    size,
+                    VmFlags::MMIO | VmFlags::WRITE,
+                    AllocationType::Address(physical_base),
+                )
+                .inspect_err(|_| {
+                    // keep using the current mode
+                    unsafe {
+                        bga::set_resolution(previous.width as u16, previous.height as u16);
+                    }
+                })?;
+
+            let framebuffer = RawFrameBuffer::from(FrameBufferMetadata {
+                base,
+                size,
+                width,
+                height,
+                stride,
+                is_rgb: mode.is_rgb,
+            });
+
+            set_panic_writer(Writer::new(
+                writer.font(),
+                framebuffer.clone(),
+                FOREGROUND_COLOR,
+                PANIC_BACKGROUND_COLOR,
+            ));
+            // the compositor only accesses the screen with interrupts enabled
+            vmm.free(previous.base)?;
+            framebuffer
+        };
+
+        // the console draws onto its own surface while the compositor is running
+        #[cfg(feature = "graphics-compositor")]
+        let framebuffer = compositor::set_screen(framebuffer.clone())?.unwrap_or(framebuffer);
+        writer.set_framebuffer(framebuffer);
+        Ok(())
+    })?;
+
+    text::rerender();
+    println!("video: Switched to {}x{}.", width, height);
+    Ok(())
 }
 
 #[derive(Copy, Clone)]
-enum VideoError {
+pub(crate) enum VideoError {
     CoordinatesOutOfBounds(usize, usize),
     UnsupportedCharacter,
+    UnsupportedMode(usize, usize),
+    ModeSwitchUnsupported,
+    VideoUninitialized,
+    MappingError(VmmError),
+    #[cfg(feature = "graphics-compositor")]
+    SurfaceNotFound(u64),
+    #[cfg(feature = "graphics-compositor")]
+    EmptySurface,
+    #[cfg(feature = "graphics-compositor")]
+    CompositorAlreadyRunning,
+    InvalidImage,
+    UnsupportedImageFormat,
+    ImageTooLarge(usize, usize),
 }
 
 impl Debug for VideoError {
@@ -63,6 +218,45 @@ impl Debug for VideoError {
                 x, y
             ),
             VideoError::UnsupportedCharacter => write!(f, "Video Error: Unsupported character."),
+            VideoError::UnsupportedMode(width, height) => write!(
+                f,
+                "Video Error: The graphics device does not support a video mode with resolution: {}x{}.",
+                width, height
+            ),
+            VideoError::ModeSwitchUnsupported => write!(
+                f,
+                "Video Error: The video mode can not be changed on this graphics device."
+            ),
+            VideoError::VideoUninitialized => {
+                write!(f, "Video Error: Video output has not been initialized.")
+            }
+            VideoError::MappingError(value) => {
+                write!(f, "Video Error: Framebuffer mapping failed: {}", value)
+            }
+            #[cfg(feature = "graphics-compositor")]
+            VideoError::SurfaceNotFound(id) => write!(
+                f,
+                "Video Error: Could not find surface with ID: {} in surface list.",
+                id
+            ),
+            #[cfg(feature = "graphics-compositor")]
+            VideoError::EmptySurface => {
+                write!(f, "Video Error: Surfaces must be at least one pixel in size.")
+            }
+            #[cfg(feature = "graphics-compositor")]
+            VideoError::CompositorAlreadyRunning => {
+                write!(f, "Video Error: The compositor is already running.")
+            }
+            VideoError::InvalidImage => write!(f, "Video Error: Invalid or truncated image."),
+            VideoError::UnsupportedImageFormat => write!(
+                f,
+                "Video Error: Unsupported image format. Only uncompressed 24 and 32 bit BMP images are supported."
+            ),
+            VideoError::ImageTooLarge(width, height) => write!(
+                f,
+                "Video Error: Image with size: {}x{} does not fit onto the screen.",
+                width, height
+            ),
         }
     }
 }
@@ -74,3 +268,9 @@ impl Display for VideoError {
 }
 
 impl Error for VideoError {}
+
+impl From<VmmError> for VideoError {
+    fn from(value: VmmError) -> Self {
+        Self::MappingError(value)
+    }
+}
@@ -0,0 +1,68 @@
+use chicken_util::module::SPLASH_MODULE_NAME;
+
+use crate::{
+    base::interrupts::without_interrupts,
+    modules,
+    scheduling::spin::SpinLock,
+    video::{
+        bmp::Bitmap,
+        framebuffer::RawFrameBuffer,
+        text::{self, WRITER},
+        VideoError,
+    },
+};
+
+/// Space between the splash image and the boot progress output in pixels.
+const SPLASH_MARGIN: usize = 16;
+/// Amount of text rows that must fit below the splash image.
+const MIN_CONSOLE_ROWS: usize = 4;
+
+/// Entire screen, while the console is restricted to the area below the splash image.
+static SCREEN: SpinLock<Option<RawFrameBuffer>> = SpinLock::new(None);
+
+/// Draws the splash image centered on the screen, if it has been loaded. Returns the area below the image, which the console should use until [`finish`] is called.
+pub(super) fn show(
+    screen: &RawFrameBuffer,
+    glyph_height: usize,
+) -> Result<Option<RawFrameBuffer>, VideoError> {
+    // the loader only hands over the splash image, if it is enabled in the boot config
+    let Ok(module) = modules::claim(SPLASH_MODULE_NAME) else {
+        return Ok(None);
+    };
+    let image = Bitmap::parse(module.data)?;
+    let min_console_height = MIN_CONSOLE_ROWS * glyph_height;
+
+    let screen_width = screen.meta_data.width;
+    let screen_height = screen.meta_data.height;
+    if image.width > screen_width
+        || image.height + SPLASH_MARGIN + min_console_height > screen_height
+    {
+        return Err(VideoError::ImageTooLarge(image.width, image.height));
+    }
+
+    let x = (screen_width - image.width) / 2;
+    let y = (screen_height - image.height) / 2;
+    screen.blit(x, y, image.width, &image.pixels);
+
+    *SCREEN.lock() = Some(screen.clone());
+
+    let console_y = (y + image.height + SPLASH_MARGIN).min(screen_height - min_console_height);
+    Ok(Some(screen.region(console_y, screen_height - console_y)))
+}
+
+/// Removes the splash image and hands the entire screen back to the console, which is redrawn. Does nothing, if no splash image is displayed.
+pub(crate) fn finish() {
+    let restored = without_interrupts(|| {
+        let Some(screen) = SCREEN.lock().take() else {
+            return false;
+        };
+        if let Some(writer) = WRITER.lock().get_mut() {
+            writer.set_framebuffer(screen);
+        }
+        true
+    });
+
+    if restored {
+        text::rerender();
+    }
+}
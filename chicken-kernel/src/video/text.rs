@@ -1,18 +1,128 @@
+use alloc::boxed::Box;
 use core::{
     cell::OnceCell,
     fmt::{Debug, Write},
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
 };
 
 use chicken_util::graphics::{font::Font, Color};
 
 use crate::{
-    base::interrupts::without_interrupts,
+    base::{
+        interrupts::{are_enabled, without_interrupts},
+        io::{serial, timer::pit::get_current_uptime_ms},
+    },
     scheduling::spin::SpinLock,
-    video::{framebuffer::RawFrameBuffer, VideoError},
+    video::{
+        framebuffer::{RawFrameBuffer, Rect},
+        blank,
+        glyph::GlyphMap,
+        history::{LogHistory, HISTORY},
+    },
 };
 
+/// Amount of bytes tasks may print per ms, before their output is no longer drawn onto the screen.
+const CONSOLE_RATE_LIMIT: u64 = 64;
+/// Amount of bytes tasks may print at once.
+const CONSOLE_BURST_LIMIT: u64 = 8 * 1024;
+/// Maximum amount of characters drawn with interrupts disabled.
+const DRAW_BATCH_SIZE: usize = 64;
+/// Size in bytes of the stack buffer output is formatted into, before it is passed on.
+const PRINT_BUFFER_SIZE: usize = 256;
+
 pub static WRITER: SpinLock<OnceCell<Writer>> = SpinLock::new(OnceCell::new());
 
+/// Writer reserved for panic output. Not protected by a lock, so a panic can be printed while the global writer is locked.
+static PANIC_WRITER: AtomicPtr<Writer> = AtomicPtr::new(ptr::null_mut());
+
+static RATE_LIMITER: SpinLock<RateLimiter> = SpinLock::new(RateLimiter::new());
+
+/// Limits the amount of output drawn onto the screen, so a task printing in a loop does not starve the others.
+#[derive(Debug)]
+struct RateLimiter {
+    /// Amount of bytes that may currently be drawn.
+    tokens: u64,
+    last_refill_ms: u64,
+    /// Amount of bytes that have not been drawn since the last time output was allowed.
+    suppressed: u64,
+}
+
+impl RateLimiter {
+    const fn new() -> Self {
+        Self {
+            tokens: CONSOLE_BURST_LIMIT,
+            last_refill_ms: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns whether output of the given length may be drawn and the amount of bytes suppressed before it.
+    fn acquire(&mut self, length: u64, uptime: u64) -> (bool, u64) {
+        let elapsed = uptime.saturating_sub(self.last_refill_ms);
+        self.tokens = (self.tokens + elapsed * CONSOLE_RATE_LIMIT).min(CONSOLE_BURST_LIMIT);
+        self.last_refill_ms = uptime;
+
+        if length > self.tokens {
+            self.suppressed += length;
+            return (false, 0);
+        }
+        self.tokens -= length;
+        (true, core::mem::take(&mut self.suppressed))
+    }
+}
+
+/// Formats output into a buffer on the stack and passes it on in chunks, whenever the buffer is full, so printing does not allocate.
+struct ChunkWriter<F: FnMut(&str)> {
+    buffer: [u8; PRINT_BUFFER_SIZE],
+    length: usize,
+    output: F,
+}
+
+impl<F: FnMut(&str)> ChunkWriter<F> {
+    fn new(output: F) -> Self {
+        Self {
+            buffer: [0; PRINT_BUFFER_SIZE],
+            length: 0,
+            output,
+        }
+    }
+
+    /// Passes on the buffered output.
+    fn flush(&mut self) {
+        // only whole characters are buffered
+        if let Ok(chunk) = core::str::from_utf8(&self.buffer[..self.length]) {
+            if !chunk.is_empty() {
+                (self.output)(chunk);
+            }
+        }
+        self.length = 0;
+    }
+}
+
+impl<F: FnMut(&str)> Write for ChunkWriter<F> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let mut rest = s;
+        while !rest.is_empty() {
+            let free = PRINT_BUFFER_SIZE - self.length;
+            // split at the last character boundary that fits
+            let mut end = rest.len().min(free);
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == 0 {
+                self.flush();
+                continue;
+            }
+            let (chunk, remaining) = rest.split_at(end);
+            self.buffer[self.length..self.length + end].copy_from_slice(chunk.as_bytes());
+            self.length += end;
+            rest = remaining;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Writer {
     row: usize,
@@ -21,6 +131,9 @@ pub(crate) struct Writer {
     background_color: Color,
     framebuffer: RawFrameBuffer,
     font: Font,
+    glyphs: GlyphMap,
+    // region drawn onto, which has not been composited yet
+    damage: Option<Rect>,
 }
 impl Writer {
     pub(super) fn new(
@@ -34,10 +147,28 @@ impl Writer {
             col: 0,
             foreground_color,
             background_color,
+            glyphs: GlyphMap::new(&font),
             font,
             framebuffer,
+            damage: None,
         }
     }
+
+    pub(super) fn framebuffer(&self) -> &RawFrameBuffer {
+        &self.framebuffer
+    }
+
+    pub(super) fn font(&self) -> Font {
+        self.font
+    }
+
+    /// Replaces the framebuffer, e.g. after the video mode has changed. The screen should be re-rendered afterward.
+    pub(super) fn set_framebuffer(&mut self, framebuffer: RawFrameBuffer) {
+        self.framebuffer = framebuffer;
+        self.row = 0;
+        self.col = 0;
+        self.damage = None;
+    }
 }
 
 impl Writer {
@@ -49,7 +180,7 @@ impl Writer {
             '\n' => {
                 if (y + 1) * self.font.glyph_height() >= self.framebuffer.meta_data.height {
                     // looping terminal
-                    self.framebuffer.fill(self.background_color);
+                    self.clear();
                     y = 0;
                 } else {
                     y += 1
@@ -60,7 +191,7 @@ impl Writer {
                 if x * self.font.glyph_width() >= self.framebuffer.meta_data.width {
                     if (y + 1) * self.font.glyph_height() >= self.framebuffer.meta_data.height {
                         // looping terminal
-                        self.framebuffer.fill(self.background_color);
+                        self.clear();
                         y = 0;
                     } else {
                         y += 1
@@ -68,32 +199,27 @@ impl Writer {
                     x = 0;
                 }
 
-                if let Err(err) = self.framebuffer.draw_char(
-                    character,
+                // unsupported characters are mapped to the replacement glyph, so drawing does not fail
+                if self
+                    .framebuffer
+                    .draw_glyph(
+                        self.glyphs.glyph(character),
+                        x * self.font.glyph_width(),
+                        y * self.font.glyph_height(),
+                        self.foreground_color,
+                        self.background_color,
+                        self.font,
+                    )
+                    .is_err()
+                {
+                    return;
+                }
+                self.mark_damaged(Rect::new(
                     x * self.font.glyph_width(),
                     y * self.font.glyph_height(),
-                    self.foreground_color,
-                    self.background_color,
-                    self.font,
-                ) {
-                    match err {
-                        // should never happen
-                        VideoError::CoordinatesOutOfBounds(_, _) => return,
-                        // print ? instead
-                        VideoError::UnsupportedCharacter => {
-                            self.framebuffer
-                                .draw_char(
-                                    '?',
-                                    x * self.font.glyph_width(),
-                                    y * self.font.glyph_height(),
-                                    self.foreground_color,
-                                    self.background_color,
-                                    self.font,
-                                )
-                                .unwrap();
-                        }
-                    }
-                }
+                    self.font.glyph_width(),
+                    self.font.glyph_height(),
+                ));
                 x += 1;
             }
         }
@@ -101,6 +227,36 @@ impl Writer {
         self.row = y;
     }
 
+    /// Fills the entire framebuffer with the background color.
+    fn clear(&mut self) {
+        self.framebuffer.fill(self.background_color);
+        self.mark_damaged(self.framebuffer.rect());
+    }
+
+    fn mark_damaged(&mut self, rect: Rect) {
+        self.damage = Some(match self.damage {
+            Some(damage) => damage.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Returns the region drawn onto since the last call, so the compositor can update it on the screen.
+    pub(super) fn take_damage(&mut self) -> Option<Rect> {
+        self.damage.take()
+    }
+
+    /// Clears the screen and redraws the most recent output of the log history that fits onto it.
+    pub(crate) fn rerender(&mut self, history: &LogHistory) {
+        self.clear();
+        self.row = 0;
+        self.col = 0;
+
+        let rows = self.framebuffer.meta_data.height / self.font.glyph_height();
+        let columns = self.framebuffer.meta_data.width / self.font.glyph_width();
+        self._write_str(&history.tail(rows, columns));
+    }
+
+    /// Draws the characters of the string. Strings are always valid UTF-8, so multibyte characters are decoded into a single character and drawn with one glyph.
     fn _write_str(&mut self, s: &str) {
         for character in s.chars() {
             self.write_char(character);
@@ -126,11 +282,142 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
-#[doc(hidden)]
-pub fn _print(args: core::fmt::Arguments) {
+/// Re-renders the screen using the log history, e.g. after the resolution or font has changed. Does nothing while the screen is blanked.
+pub(crate) fn rerender() {
     without_interrupts(|| {
-        if let Some(writer) = WRITER.lock().get_mut() {
-            writer.write_fmt(args).unwrap();
+        if blank::is_blanked() {
+            return;
+        }
+        let history = HISTORY.lock();
+        if let (Some(history), Some(writer)) = (history.get(), WRITER.lock().get_mut()) {
+            writer.rerender(history);
         }
+        drop(history);
+        report_damage();
     })
 }
+
+/// Passes the region the global writer has drawn onto to the compositor. Must be called with interrupts disabled.
+#[cfg(feature = "graphics-compositor")]
+fn report_damage() {
+    let damage = WRITER.lock().get_mut().and_then(Writer::take_damage);
+    if let Some(damage) = damage {
+        crate::video::compositor::damage_console(damage);
+    }
+}
+
+/// Discards the region the global writer has drawn onto, since it draws onto the screen directly. Must be called with interrupts disabled.
+#[cfg(not(feature = "graphics-compositor"))]
+fn report_damage() {
+    if let Some(writer) = WRITER.lock().get_mut() {
+        writer.take_damage();
+    }
+}
+
+/// Sets up the writer used for panic output.
+pub(super) fn set_panic_writer(writer: Writer) {
+    let previous = PANIC_WRITER.swap(Box::into_raw(Box::new(writer)), Ordering::SeqCst);
+    if !previous.is_null() {
+        drop(unsafe { Box::from_raw(previous) });
+    }
+}
+
+/// Prints panic output directly onto the screen, bypassing the global writer, its lock and the log history. Only the first call prints anything, so a panic while printing does not recurse.
+pub(crate) fn panic_print(args: core::fmt::Arguments) {
+    let writer = PANIC_WRITER.swap(ptr::null_mut(), Ordering::SeqCst);
+    if let Some(writer) = unsafe { writer.as_mut() } {
+        let _ = writer.write_fmt(args);
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    if !are_enabled() {
+        serial::print(args);
+        screen_print(args);
+        return;
+    }
+
+    // the serial console receives the whole log, only the screen is rate limited
+    let mut output = ChunkWriter::new(|chunk: &str| {
+        serial::print(format_args!("{}", chunk));
+        record_and_draw(chunk);
+    });
+    let _ = output.write_fmt(args);
+    output.flush();
+}
+
+/// Prints onto the screen and into the log history, but not to the serial console. Used by the console sink of the kernel log, since the serial console has a sink of its own.
+pub(crate) fn screen_print(args: core::fmt::Arguments) {
+    // interrupts are disabled during boot and in interrupt handlers, that output is always drawn right away
+    if !are_enabled() {
+        if let Some(history) = HISTORY.lock().get_mut() {
+            history.write_fmt(args).unwrap();
+        }
+        #[cfg(feature = "ktest")]
+        crate::ktest::capture::record(args);
+        // blanked output is drawn from the log history once the screen wakes up
+        if !blank::is_blanked() {
+            if let Some(writer) = WRITER.lock().get_mut() {
+                writer.write_fmt(args).unwrap();
+            }
+            report_damage();
+        }
+        return;
+    }
+
+    let mut output = ChunkWriter::new(record_and_draw);
+    let _ = output.write_fmt(args);
+    output.flush();
+}
+
+/// Records the output in the log history and draws it, unless tasks have exceeded the rate limit of the screen.
+fn record_and_draw(output: &str) {
+    let (allowed, suppressed) = without_interrupts(|| {
+        if let Some(history) = HISTORY.lock().get_mut() {
+            history.record(output);
+        }
+        #[cfg(feature = "ktest")]
+        crate::ktest::capture::record(format_args!("{}", output));
+        RATE_LIMITER
+            .lock()
+            .acquire(output.len() as u64, get_current_uptime_ms())
+    });
+
+    if suppressed != 0 {
+        let mut notice = ChunkWriter::new(draw);
+        let _ = writeln!(
+            notice,
+            "[console: {} bytes of output suppressed]",
+            suppressed
+        );
+        notice.flush();
+    }
+    if allowed {
+        draw(output);
+    }
+}
+
+/// Draws output in small batches, so interrupts are not disabled for too long. Output of other tasks may be drawn in between batches.
+fn draw(output: &str) {
+    let mut rest = output;
+    while !rest.is_empty() {
+        let end = rest
+            .char_indices()
+            .nth(DRAW_BATCH_SIZE)
+            .map_or(rest.len(), |(index, _)| index);
+        let (batch, remaining) = rest.split_at(end);
+
+        without_interrupts(|| {
+            // the screen may have been blanked in between batches
+            if blank::is_blanked() {
+                return;
+            }
+            if let Some(writer) = WRITER.lock().get_mut() {
+                writer._write_str(batch);
+            }
+            report_damage();
+        });
+        rest = remaining;
+    }
+}
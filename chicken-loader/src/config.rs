@@ -0,0 +1,239 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use chicken_util::hash::Sha256Digest;
+use uefi::{prelude::BootServices, Handle};
+
+use crate::{file, BOOT_CONFIG_FILE_NAME, KERNEL_FILE_NAME};
+
+/// Name of the boot entry used, if the boot config does not list any.
+const DEFAULT_ENTRY_NAME: &str = "Chicken OS";
+/// Seconds the boot menu waits for input before booting the default entry.
+const DEFAULT_MENU_TIMEOUT: u64 = 5;
+
+/// Kernel that can be selected in the boot menu, started by `entry=<name>`. The `kernel`, `cmdline` and `kernel_sha256` keys following it belong to the entry.
+#[derive(Clone, Debug)]
+pub(super) struct BootEntry {
+    /// Name displayed in the boot menu
+    pub(super) name: String,
+    /// File name of the kernel, set by `kernel=<file name>`
+    pub(super) kernel: String,
+    /// Command line passed to the kernel, set by `cmdline=<command line>`
+    pub(super) command_line: String,
+    /// Expected SHA-256 of the kernel file, overrides the hash of the boot config for this entry
+    pub(super) kernel_sha256: Option<Sha256Digest>,
+}
+
+impl BootEntry {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kernel: KERNEL_FILE_NAME.to_string(),
+            command_line: String::new(),
+            kernel_sha256: None,
+        }
+    }
+}
+
+/// Options read from the boot config file. Each line contains a `key=value` pair, lines starting with `#` are comments.
+#[derive(Clone, Debug)]
+pub(super) struct BootConfig {
+    /// Kernels listed in the boot menu. Contains a single entry booting the default kernel, if the boot config does not list any.
+    pub(super) entries: Vec<BootEntry>,
+    /// Index of the entry booted when the boot menu times out, set by `default=<number>` starting at 1
+    pub(super) default_entry: usize,
+    /// Seconds the boot menu waits for input, set by `timeout=<seconds>`. The default entry is booted right away, if it is 0.
+    pub(super) menu_timeout: u64,
+    /// File names of additional modules, listed as `module=<file name>`
+    pub(super) modules: Vec<String>,
+    /// Whether the splash image is displayed during boot, set by `splash=on|off`
+    pub(super) splash: bool,
+    /// Expected SHA-256 of the kernel file, set by `kernel_sha256=<64 hex digits>`
+    pub(super) kernel_sha256: Option<Sha256Digest>,
+    /// Whether the kernel is booted anyway if its hash does not match, set by `kernel_hash_mismatch=halt|warn`
+    pub(super) warn_on_hash_mismatch: bool,
+    /// Minutes without input after which the kernel blanks the screen, set by `screen_blank=<minutes>|off`
+    pub(super) screen_blank_minutes: u64,
+    /// Whether the UEFI memory map and the memory map handed over to the kernel are printed to the serial console, set by `memory_map_dump=on|off`
+    pub(super) memory_map_dump: bool,
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            default_entry: 0,
+            menu_timeout: DEFAULT_MENU_TIMEOUT,
+            modules: Vec::new(),
+            splash: false,
+            kernel_sha256: None,
+            warn_on_hash_mismatch: false,
+            screen_blank_minutes: 0,
+            memory_map_dump: false,
+        }
+    }
+}
+
+impl BootConfig {
+    /// Parses the contents of a boot config file.
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| format!("Boot config line {} is not a key=value pair.", index + 1))?;
+
+            match key {
+                "entry" if !value.is_empty() => config.entries.push(BootEntry::new(value)),
+                "entry" => {
+                    return Err(format!("Boot config line {}: entry name is empty.", index + 1))
+                }
+                "kernel" | "cmdline" if config.entries.is_empty() => {
+                    return Err(format!(
+                        "Boot config line {}: {} must follow an entry.",
+                        index + 1,
+                        key
+                    ))
+                }
+                "kernel" if !value.is_empty() => {
+                    if let Some(entry) = config.entries.last_mut() {
+                        entry.kernel = value.to_string();
+                    }
+                }
+                "kernel" => {
+                    return Err(format!("Boot config line {}: kernel name is empty.", index + 1))
+                }
+                "cmdline" => {
+                    if let Some(entry) = config.entries.last_mut() {
+                        entry.command_line = value.to_string();
+                    }
+                }
+                "default" => {
+                    config.default_entry = value
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|number| number.checked_sub(1))
+                        .ok_or_else(|| {
+                            format!(
+                                "Boot config line {}: default must be the number of an entry.",
+                                index + 1
+                            )
+                        })?
+                }
+                "timeout" => {
+                    config.menu_timeout = value.parse().map_err(|_| {
+                        format!(
+                            "Boot config line {}: timeout must be a number of seconds.",
+                            index + 1
+                        )
+                    })?
+                }
+                "module" if !value.is_empty() => config.modules.push(value.to_string()),
+                "module" => {
+                    return Err(format!("Boot config line {}: module name is empty.", index + 1))
+                }
+                "splash" => {
+                    config.splash = match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => {
+                            return Err(format!(
+                                "Boot config line {}: splash must be either on or off.",
+                                index + 1
+                            ))
+                        }
+                    }
+                }
+                "kernel_sha256" => {
+                    let hash = Some(Sha256Digest::from_hex(value).ok_or_else(|| {
+                        format!(
+                            "Boot config line {}: kernel_sha256 must be 64 hexadecimal digits.",
+                            index + 1
+                        )
+                    })?);
+                    // applies to all entries, if it is not part of one
+                    match config.entries.last_mut() {
+                        Some(entry) => entry.kernel_sha256 = hash,
+                        None => config.kernel_sha256 = hash,
+                    }
+                }
+                "kernel_hash_mismatch" => {
+                    config.warn_on_hash_mismatch = match value {
+                        "halt" => false,
+                        "warn" => true,
+                        _ => {
+                            return Err(format!(
+                                "Boot config line {}: kernel_hash_mismatch must be either halt or warn.",
+                                index + 1
+                            ))
+                        }
+                    }
+                }
+                "screen_blank" => {
+                    config.screen_blank_minutes = match value {
+                        "off" => 0,
+                        minutes => minutes.parse().map_err(|_| {
+                            format!(
+                                "Boot config line {}: screen_blank must be a number of minutes or off.",
+                                index + 1
+                            )
+                        })?,
+                    }
+                }
+                "memory_map_dump" => {
+                    config.memory_map_dump = match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => {
+                            return Err(format!(
+                                "Boot config line {}: memory_map_dump must be either on or off.",
+                                index + 1
+                            ))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "Boot config line {}: unknown key: {}",
+                        index + 1,
+                        key
+                    ))
+                }
+            }
+        }
+
+        if config.entries.is_empty() {
+            config.entries.push(BootEntry::new(DEFAULT_ENTRY_NAME));
+        }
+        if config.default_entry >= config.entries.len() {
+            return Err(format!(
+                "Boot config: default entry {} does not exist, there are {} entries.",
+                config.default_entry + 1,
+                config.entries.len()
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// Loads the boot config from the filesystem. If there is no boot config file, the default config is used.
+pub(super) fn load(image_handle: Handle, bt: &BootServices) -> Result<BootConfig, String> {
+    match file::get_optional_file_data(image_handle, bt, BOOT_CONFIG_FILE_NAME)? {
+        Some(data) => {
+            let contents = String::from_utf8(data)
+                .map_err(|_| "Boot config is not valid utf-8.".to_string())?;
+            BootConfig::parse(&contents)
+        }
+        None => BootConfig::parse(""),
+    }
+}
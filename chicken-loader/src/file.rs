@@ -3,22 +3,30 @@ use alloc::{
     string::{String, ToString},
     vec::Vec,
 };
-use core::slice;
+use core::{fmt::Write, slice};
 
 use chicken_util::{
     memory::{PhysicalAddress, VirtualAddress},
+    segments::{KernelSegment, KernelSegments, SegmentFlags},
+    symbols::KernelSymbol,
     PAGE_SIZE,
 };
 use goblin::{elf::Elf, elf32::program_header::PT_LOAD};
 use uefi::{fs::FileSystem, prelude::BootServices, table::boot::AllocateType, CString16, Handle};
 use uefi::table::boot::MemoryType;
 
-/// Gets data of a file from the filesystem
+use crate::network;
+
+/// Gets data of a file from the filesystem or the TFTP server, if the loader has been booted via PXE.
 pub(super) fn get_file_data(
     image_handle: Handle,
     boot_services: &BootServices,
     filename: &str,
 ) -> Result<Vec<u8>, String> {
+    if let Some(device) = network::pxe_boot_device(image_handle, boot_services) {
+        return network::get_optional_file_data(device, boot_services, filename)?
+            .ok_or_else(|| format!("Unable to read file with name: {filename}"));
+    }
     let mut file_system = FileSystem::new(
         boot_services
             .get_image_file_system(image_handle)
@@ -33,17 +41,65 @@ pub(super) fn get_file_data(
         .map_err(|_| format!("Unable to read file with name: {filename}"))
 }
 
-/// Allocates the file data in memory and returns entry point, file base address and number of pages
+/// Gets data of a file from the filesystem or the TFTP server, if the loader has been booted via PXE. Returns `None`, if the file does not exist.
+pub(super) fn get_optional_file_data(
+    image_handle: Handle,
+    boot_services: &BootServices,
+    filename: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    if let Some(device) = network::pxe_boot_device(image_handle, boot_services) {
+        return network::get_optional_file_data(device, boot_services, filename);
+    }
+    let mut file_system = FileSystem::new(
+        boot_services
+            .get_image_file_system(image_handle)
+            .map_err(|_| "Cannot get filesystem protocol".to_string())?,
+    );
+    let path = CString16::try_from(filename).map_err(|_| format!("Invalid filename: {filename}"))?;
+
+    if !file_system
+        .try_exists(path.as_ref())
+        .map_err(|_| format!("Unable to check if file exists: {filename}"))?
+    {
+        return Ok(None);
+    }
+    file_system
+        .read(path.as_ref())
+        .map(Some)
+        .map_err(|_| format!("Unable to read file with name: {filename}"))
+}
+
+/// Module file read from the filesystem or the TFTP server. It is copied into the handoff region right before the kernel is started.
+pub(super) struct ModuleFile {
+    pub(super) name: String,
+    pub(super) data: Vec<u8>,
+}
+
+/// Reads a file, so the kernel can use it as a module.
+pub(super) fn load_module(
+    image_handle: Handle,
+    boot_services: &BootServices,
+    filename: &str,
+) -> Result<ModuleFile, String> {
+    let data = get_file_data(image_handle, boot_services, filename)?;
+    Ok(ModuleFile {
+        name: filename.to_string(),
+        data,
+    })
+}
+
+/// Allocates the file data in memory and returns entry point, file base address, number of pages and the loadable segments, so the kernel can map each of them with its own permissions
 pub(super) fn parse_elf(
     data: Vec<u8>,
     boot_services: &BootServices,
-) -> Result<(VirtualAddress, PhysicalAddress, usize), String> {
+) -> Result<(VirtualAddress, PhysicalAddress, usize, KernelSegments), String> {
     let data = data.as_slice();
     let elf =
         Elf::parse(data).map_err(|_| "Unable to parse file to elf!".to_string())?;
 
     let mut dest_start = u64::MAX;
     let mut dest_end = 0;
+    let mut segments = KernelSegments::new();
 
     if !elf.is_64 {
         return Err("Invalid elf format.".to_string());
@@ -58,6 +114,13 @@ pub(super) fn parse_elf(
 
         dest_start = dest_start.min(pheader.p_paddr);
         dest_end = dest_end.max(pheader.p_paddr + pheader.p_memsz);
+        segments
+            .push(KernelSegment {
+                virtual_address: pheader.p_vaddr,
+                size: pheader.p_memsz,
+                flags: SegmentFlags::from_bits_truncate(pheader.p_flags),
+            })
+            .map_err(|_| "The kernel has too many loadable segments.".to_string())?;
     }
 
     let num_pages = (dest_end as usize - dest_start as usize + PAGE_SIZE - 1) / PAGE_SIZE;
@@ -83,5 +146,38 @@ pub(super) fn parse_elf(
         dest[size_in_file..].fill(0);
     }
 
-    Ok((elf.entry, dest_start, num_pages))
+    Ok((elf.entry, dest_start, num_pages, segments))
+}
+
+/// Functions of the kernel file sorted by address and the string table with their demangled names. They are copied into the handoff region right before the kernel is started.
+#[derive(Default)]
+pub(super) struct SymbolTable {
+    pub(super) symbols: Vec<KernelSymbol>,
+    pub(super) strings: String,
+}
+
+/// Reads the function symbols of the kernel file, so the kernel can resolve addresses in backtraces. The table is empty, if the file has been stripped.
+pub(super) fn read_symbols(data: &[u8]) -> Result<SymbolTable, String> {
+    let elf = Elf::parse(data).map_err(|_| "Unable to parse file to elf!".to_string())?;
+
+    let mut table = SymbolTable::default();
+    for symbol in elf.syms.iter() {
+        if !symbol.is_function() || symbol.st_value == 0 {
+            continue;
+        }
+        let Some(name) = elf.strtab.get_at(symbol.st_name) else {
+            continue;
+        };
+        let name_offset = table.strings.len();
+        // the alternate format omits the hash of legacy symbol names
+        let _ = write!(table.strings, "{:#}", rustc_demangle::demangle(name));
+        table.symbols.push(KernelSymbol {
+            address: symbol.st_value,
+            size: symbol.st_size,
+            name_offset: name_offset as u32,
+            name_length: (table.strings.len() - name_offset) as u32,
+        });
+    }
+    table.symbols.sort_unstable_by_key(|symbol| symbol.address);
+    Ok(table)
 }
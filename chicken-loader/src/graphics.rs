@@ -1,26 +1,25 @@
-use alloc::{format, string::String};
+use alloc::{format, string::String, vec::Vec};
 
 use uefi::{
     Handle,
     prelude::BootServices,
     proto::console::gop::{GraphicsOutput, PixelFormat},
 };
-use uefi::table::boot::MemoryType;
 
-use chicken_util::{
-    graphics::{
-        font::{PSF1_MAGIC, PSF1Header, PSF2_MAGIC, PSF2Header, PSFHeader},
-        framebuffer::FrameBufferMetadata,
+use chicken_util::graphics::{
+    font::{
+        BUILTIN_FONT, PSF1_MAGIC, PSF1_MODE_HAS_TABLE, PSF1Header, PSF2_HAS_UNICODE_TABLE,
+        PSF2_MAGIC, PSF2Header, PSFHeader,
     },
-    memory::PhysicalAddress,
+    framebuffer::{FrameBufferMetadata, VideoMode, VideoModeList},
 };
 
 use crate::{file, FONT_FILE_NAME};
 
-/// Initialize framebuffer (GOP)
+/// Initialize framebuffer (GOP). Returns the framebuffer metadata and the video modes with a supported pixel format.
 pub(super) fn initialize_framebuffer(
     boot_services: &BootServices,
-) -> Result<FrameBufferMetadata, String> {
+) -> Result<(FrameBufferMetadata, VideoModeList), String> {
     let gop_handle = boot_services
         .get_handle_for_protocol::<GraphicsOutput>()
         .map_err(|error| format!("Could not get handle for GOP: {error}."))?;
@@ -43,21 +42,45 @@ pub(super) fn initialize_framebuffer(
     let (width, height) = info.resolution();
     let stride = info.stride();
 
-    Ok(FrameBufferMetadata {
-        base,
-        size,
-        width,
-        height,
-        stride,
-        is_rgb,
-    })
+    // record modes the kernel may switch to later on
+    let mut video_modes = VideoModeList::default();
+    for mode in gop.modes(boot_services) {
+        let info = mode.info();
+        let is_rgb = match info.pixel_format() {
+            PixelFormat::Rgb => true,
+            PixelFormat::Bgr => false,
+            PixelFormat::Bitmask | PixelFormat::BltOnly => continue,
+        };
+        let (width, height) = info.resolution();
+        if !video_modes.push(VideoMode {
+            width,
+            height,
+            stride: info.stride(),
+            is_rgb,
+        }) {
+            break;
+        }
+    }
+
+    Ok((
+        FrameBufferMetadata {
+            base,
+            size,
+            width,
+            height,
+            stride,
+            is_rgb,
+        },
+        video_modes,
+    ))
 }
-/// Load PSF2 font into memory. Returns font header, the address of the font in memory and the number of glyphs in the buffer.
+/// Load PSF font from the filesystem. Falls back to the font built into the loader, if there is no font file. Returns font header, the glyph data followed by the unicode table, the number of glyphs in the buffer and the size of the unicode table. The glyph data is copied into the handoff region later on.
 pub(super) fn load_font(
     image_handle: Handle,
     bt: &BootServices,
-) -> Result<(PSFHeader, PhysicalAddress, usize), String> {
-    let font_data = file::get_file_data(image_handle, bt, FONT_FILE_NAME)?;
+) -> Result<(PSFHeader, Vec<u8>, usize, usize), String> {
+    let mut font_data = file::get_optional_file_data(image_handle, bt, FONT_FILE_NAME)?
+        .unwrap_or_else(|| BUILTIN_FONT.to_vec());
     let font_data_ptr = font_data.as_ptr(); // points to first byte of font data
 
     if font_data.len() < size_of::<PSF1Header>() {
@@ -69,44 +92,26 @@ pub(super) fn load_font(
     // check for psf1 header magic
     if magic == PSF1_MAGIC {
         let header = unsafe { *(font_data_ptr as *const PSF1Header) };
-        let glyph_buffer_length = if header.font_mode == 1 { 512 } else { 256 };
+        let glyph_buffer_length = header.glyph_count();
         let glyph_buffer_size = glyph_buffer_length * header.character_size as usize;
 
-        if font_data.len() < size_of::<PSF1Header>() + glyph_buffer_size {
-            return Err("Insufficient font data for PSF1 font.".into());
-        }
-
-        // allocate memory for entire font data
         let total_size = size_of::<PSF1Header>() + glyph_buffer_size;
-        let font_address = bt
-            .allocate_pool(MemoryType::LOADER_DATA, total_size)
-            .map_err(|error| format!("Could not allocate pool for PSF1 font: {error}."))?
-            .as_ptr() as u64;
-
-        // copy header data to allocated memory
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                font_data_ptr,
-                font_address as *mut u8,
-                size_of::<PSF1Header>(),
-            );
+        if font_data.len() < total_size {
+            return Err("Insufficient font data for PSF1 font.".into());
         }
 
-        let glyph_buffer_ptr = unsafe { (font_address as *mut u8).add(size_of::<PSF1Header>()) };
-
-        // copy font data to allocated memory
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                font_data_ptr.add(size_of::<PSF1Header>()),
-                glyph_buffer_ptr,
-                glyph_buffer_size,
-            );
+        // only keep the glyphs and the unicode table
+        if header.font_mode & PSF1_MODE_HAS_TABLE == 0 {
+            font_data.truncate(total_size);
         }
+        let unicode_table_size = font_data.len() - total_size;
+        font_data.drain(..size_of::<PSF1Header>());
 
         return Ok((
             PSFHeader::Version1(header),
-            glyph_buffer_ptr as u64,
+            font_data,
             glyph_buffer_length,
+            unicode_table_size,
         ));
     } else {
         // check for psf2 header magic
@@ -123,34 +128,18 @@ pub(super) fn load_font(
                 return Err("Insufficient font data for PSF1 font.".into());
             }
 
-            let font_address = bt
-                .allocate_pool(MemoryType::LOADER_DATA, total_size)
-                .map_err(|error| format!("Could not allocate pool for PSF2 font: {error}."))?
-                .as_ptr() as u64;
-
-            // copy header data to allocated memory
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    font_data_ptr,
-                    font_address as *mut u8,
-                    size_of::<PSF2Header>(),
-                );
-            }
-
-            let glyph_buffer_ptr = unsafe { (font_address as *mut u8).add(header_size) };
-            // copy font data to allocated memory
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    font_data_ptr.add(header_size),
-                    glyph_buffer_ptr,
-                    glyph_buffer_size,
-                );
+            // only keep the glyphs and the unicode table
+            if header.flags & PSF2_HAS_UNICODE_TABLE == 0 {
+                font_data.truncate(total_size);
             }
+            let unicode_table_size = font_data.len() - total_size;
+            font_data.drain(..header_size);
 
             return Ok((
                 PSFHeader::Version2(header),
-                glyph_buffer_ptr as u64,
+                font_data,
                 header.length as usize,
+                unicode_table_size,
             ));
         }
     }
@@ -1,4 +1,3 @@
-#![feature(vec_into_raw_parts)]
 #![no_std]
 #![no_main]
 
@@ -17,24 +16,44 @@ use uefi::{
 
 use chicken_util::{
     BootInfo,
+    cmdline::CommandLine,
     graphics::font::Font,
-    memory::{paging::KERNEL_MAPPING_OFFSET, pmm::PageFrameAllocator}, PAGE_SIZE,
+    hash::{self, HashVerification, KernelMeasurement},
+    memory::{
+        paging::{self, KERNEL_MAPPING_OFFSET},
+        pmm::PageFrameAllocator,
+    },
+    module::SPLASH_MODULE_NAME,
+    PAGE_SIZE,
+    timing::{LoaderTimestamps, read_tsc},
 };
 
-use crate::memory::{allocate_boot_info, allocate_kernel_stack, KernelInfo, set_up_address_space};
+use crate::file::SymbolTable;
+use crate::memory::{
+    allocate_crash_dump, allocate_handoff, allocate_kernel_stack, KernelInfo,
+    set_up_address_space, HANDOFF_MEMORY_TYPE, KERNEL_STACK_MEMORY_TYPE,
+};
 
+mod config;
 mod file;
 mod graphics;
 mod memory;
+mod menu;
+mod network;
 
 const KERNEL_FILE_NAME: &str = "kernel.elf";
 const FONT_FILE_NAME: &str = "font.psf";
+const BOOT_CONFIG_FILE_NAME: &str = "boot.cfg";
 
 const KERNEL_STACK_SIZE: usize = 1024 * 1024; // 1 MB
 
 /// Entry point of uefi application (bootloader)
 #[entry]
 fn main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
+    let mut timestamps = LoaderTimestamps {
+        start: read_tsc(),
+        ..Default::default()
+    };
     uefi::helpers::init(&mut system_table).unwrap();
     let stdout = system_table.stdout();
 
@@ -46,17 +65,55 @@ fn main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
 
     println!(stdout);
 
+    print!("boot: Reading boot config", stdout);
+
+    let boot_config = config::load(image_handle, system_table.boot_services());
+    let stdout = system_table.stdout();
+
+    validate!(boot_config, stdout);
+    let boot_config = boot_config.unwrap();
+
+    // the menu clears the screen, if it is displayed
+    let entry = &boot_config.entries[menu::select_entry(&mut system_table, &boot_config)];
+    let stdout = system_table.stdout();
+    if boot_config.entries.len() > 1 {
+        println!("CHICKEN OS", stdout, Color::Yellow);
+        println!(stdout);
+        println!(format!("boot: Booting entry: {}", entry.name).as_str(), stdout);
+    }
+
     // get kernel file data in bytes
     print!("boot: Egg-quiring kernel file from filesystem", stdout);
-    let file = file::get_file_data(image_handle, system_table.boot_services(), KERNEL_FILE_NAME);
+    let file = file::get_file_data(image_handle, system_table.boot_services(), &entry.kernel);
     let stdout = system_table.stdout();
 
     validate!(file, stdout);
     let file = file.unwrap();
+    timestamps.kernel_file_loaded = read_tsc();
     println!(
         format!("boot: Kernel file size: {} bytes", file.len()).as_str(),
         stdout
     );
+    // measured before the file is parsed, since the elf data is consumed
+    let kernel_sha256 = hash::sha256(&file);
+    println!(
+        format!("boot: Kernel SHA-256: {}", kernel_sha256).as_str(),
+        stdout
+    );
+
+    print!("boot: Reading kernel symbols", stdout);
+    // backtraces of the kernel only lack function names without them, so this is not validated
+    let kernel_symbols = match file::read_symbols(&file) {
+        Ok(symbols) => {
+            println!(" [success] ", stdout, Color::Green);
+            symbols
+        }
+        Err(error_message) => {
+            println!(" [unavailable] ", stdout, Color::Yellow);
+            println!(error_message.as_str(), stdout);
+            SymbolTable::default()
+        }
+    };
 
     // allocate pages and load kernel file data into memory
     print!("boot: Loading kernel image into memory", stdout);
@@ -64,7 +121,9 @@ fn main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
     let stdout = system_table.stdout();
 
     validate!(kernel_elf, stdout);
-    let (kernel_entry_addr, kernel_file_start_addr, kernel_file_num_pages) = kernel_elf.unwrap();
+    let (kernel_entry_addr, kernel_file_start_addr, kernel_file_num_pages, kernel_segments) =
+        kernel_elf.unwrap();
+    timestamps.kernel_elf_parsed = read_tsc();
     println!(
         format!("boot: Kernel entry address: {:#x}", kernel_entry_addr).as_str(),
         stdout
@@ -86,24 +145,85 @@ fn main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
     validate!(kernel_stack_info, stdout);
     let (kernel_stack_start_addr, kernel_stack_num_pages) = kernel_stack_info.unwrap();
 
-    print!(
-        "boot: Allocating memory for kernel boot information",
-        stdout
-    );
+    print!("boot: Loading framebuffer font", stdout);
 
-    let kernel_boot_info = allocate_boot_info(system_table.boot_services());
+    let font_info = graphics::load_font(image_handle, system_table.boot_services());
     let stdout = system_table.stdout();
 
-    validate!(kernel_boot_info, stdout);
-    let (kernel_boot_info_addr, mmap_descriptors) = kernel_boot_info.unwrap();
+    validate!(font_info, stdout);
+    let (font_header, font_glyphs, font_buffer_size, font_unicode_table_size) =
+        font_info.unwrap();
+
+    let verification = match entry.kernel_sha256.or(boot_config.kernel_sha256) {
+        None => HashVerification::Unverified,
+        Some(expected) => {
+            print!("boot: Verifying kernel hash", stdout);
+            if expected == kernel_sha256 {
+                println!(" [success] ", stdout, Color::Green);
+                HashVerification::Matched
+            } else if boot_config.warn_on_hash_mismatch {
+                println!(" [warning] ", stdout, Color::Yellow);
+                println!(format!("Expected SHA-256: {}", expected).as_str(), stdout);
+                HashVerification::Mismatched
+            } else {
+                println!(" [error] ", stdout, Color::Red);
+                println!(format!("Expected SHA-256: {}", expected).as_str(), stdout);
+                println!("The kernel file does not match the hash in the boot config.", stdout);
+                return Status::SECURITY_VIOLATION;
+            }
+        }
+    };
 
-    print!("boot: Allocating memory for framebuffer font", stdout);
+    let mut modules = Vec::with_capacity(boot_config.modules.len());
+    for module_name in boot_config.modules.iter() {
+        let stdout = system_table.stdout();
+        print!(format!("boot: Loading module {}", module_name).as_str(), stdout);
 
-    let font_info = graphics::load_font(image_handle, system_table.boot_services());
+        let module = file::load_module(image_handle, system_table.boot_services(), module_name);
+        let stdout = system_table.stdout();
+
+        validate!(module, stdout);
+        modules.push(module.unwrap());
+    }
+    if boot_config.splash {
+        let stdout = system_table.stdout();
+        print!("boot: Loading splash image", stdout);
+
+        // the kernel displays the splash image, if it is handed over as a module
+        match file::load_module(image_handle, system_table.boot_services(), SPLASH_MODULE_NAME) {
+            Ok(module) => {
+                let stdout = system_table.stdout();
+                println!(" [success] ", stdout, Color::Green);
+                modules.push(module);
+            }
+            Err(error_message) => {
+                let stdout = system_table.stdout();
+                println!(" [unavailable] ", stdout, Color::Yellow);
+                println!(error_message.as_str(), stdout);
+            }
+        }
+    }
     let stdout = system_table.stdout();
 
-    validate!(font_info, stdout);
-    let (font_header, font_buffer_addr, font_buffer_size) = font_info.unwrap();
+    print!(
+        "boot: Allocating handoff region for kernel boot information",
+        stdout
+    );
+
+    let handoff = allocate_handoff(
+        system_table.boot_services(),
+        &font_glyphs,
+        &modules,
+        &kernel_symbols,
+    );
+    let stdout = system_table.stdout();
+
+    validate!(handoff, stdout);
+    let handoff = handoff.unwrap();
+    // the data has been copied into the handoff region
+    drop(font_glyphs);
+    drop(modules);
+    drop(kernel_symbols);
 
     print!("boot: Retrieving root system descriptor pointer", stdout);
 
@@ -112,6 +232,35 @@ fn main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
 
     validate!(rsdp, stdout);
     let rsdp = rsdp.unwrap();
+    // optional, only used to identify the hardware
+    let smbios = memory::get_smbios(&system_table);
+    let stdout = system_table.stdout();
+
+    print!("boot: Checking paging mode", stdout);
+
+    let paging_mode = memory::check_paging_mode();
+    validate!(paging_mode, stdout);
+    if paging::five_level_paging_supported() {
+        println!("boot: The cpu supports 5-level paging, 4-level paging is used.", stdout);
+    }
+
+    print!("boot: Reserving memory for crash dumps", stdout);
+
+    // the kernel can boot without crash dumps, so this is not validated
+    let crash_dump = match allocate_crash_dump(system_table.boot_services()) {
+        Ok(address) => {
+            let stdout = system_table.stdout();
+            println!(" [success] ", stdout, Color::Green);
+            address
+        }
+        Err(error_message) => {
+            let stdout = system_table.stdout();
+            println!(" [unavailable] ", stdout, Color::Yellow);
+            println!(error_message.as_str(), stdout);
+            0
+        }
+    };
+    let stdout = system_table.stdout();
 
     // Exit boot services and handover control to kernel
     println!(
@@ -127,16 +276,23 @@ fn main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
 
     // text mode may still be enabled if operation failed
     validate!(fb_metadata, stdout);
-    let fb_metadata = fb_metadata.unwrap();
+    let (fb_metadata, video_modes) = fb_metadata.unwrap();
     let kernel_info = KernelInfo {
         kernel_code_address: kernel_file_start_addr,
         kernel_code_page_count: kernel_file_num_pages,
         kernel_stack_address: kernel_stack_start_addr,
         kernel_stack_page_count: kernel_stack_num_pages,
-        kernel_boot_info_address: kernel_boot_info_addr,
+        handoff_address: handoff.region.address(),
+        handoff_page_count: handoff.region.page_count(),
     };
 
-    let (_runtime, mmap) = drop_boot_services(system_table, mmap_descriptors, &kernel_info);
+    let (_runtime, mmap) = drop_boot_services(
+        system_table,
+        handoff.memory_map_buffer,
+        &kernel_info,
+        boot_config.memory_map_dump,
+    );
+    timestamps.boot_services_exited = read_tsc();
 
     // set up basic memory management and the virtual address space for the higher half kernel
     let address_space_info = set_up_address_space(&mmap, kernel_info);
@@ -144,17 +300,33 @@ fn main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
     // note: validate is no longer available after switching to graphics mode
     let (pml4_address, virtual_rsp, kernel_boot_info_virtual_address, pmm) =
         address_space_info.unwrap();
+    timestamps.address_space_set_up = read_tsc();
 
-    let boot_info = unsafe { &mut *(kernel_boot_info_addr as *mut BootInfo) };
+    let boot_info = unsafe { &mut *(handoff.boot_info_address as *mut BootInfo) };
     boot_info.memory_map = mmap;
     boot_info.framebuffer_metadata = fb_metadata;
+    boot_info.video_modes = video_modes;
     boot_info.font = Font {
         header: font_header,
-        glyph_buffer_address: font_buffer_addr as *const u8,
+        glyph_buffer_address: handoff.glyph_buffer_address as *const u8,
         glyph_buffer_size: font_buffer_size,
+        unicode_table_size: font_unicode_table_size,
     };
     boot_info.pmm_address = &pmm as *const PageFrameAllocator as u64;
     boot_info.rsdp = rsdp;
+    boot_info.smbios = smbios;
+    boot_info.crash_dump = crash_dump;
+    boot_info.loader_timestamps = timestamps;
+    boot_info.kernel_measurement = KernelMeasurement {
+        sha256: kernel_sha256,
+        verification,
+    };
+    boot_info.modules = handoff.modules;
+    boot_info.kernel_symbols = handoff.kernel_symbols;
+    boot_info.kernel_segments = kernel_segments;
+    boot_info.screen_blank_minutes = boot_config.screen_blank_minutes;
+    boot_info.memory_map_dump = boot_config.memory_map_dump;
+    boot_info.command_line = CommandLine::new(&entry.command_line);
 
     unsafe {
         asm!(
@@ -181,14 +353,16 @@ type ChickenMemoryMap = chicken_util::memory::MemoryMap;
 type ChickenMemoryDescriptor = chicken_util::memory::MemoryDescriptor;
 type ChickenMemoryType = chicken_util::memory::MemoryType;
 
-/// Drops boot services and returns converted memory map and runtime system table
+/// Drops boot services and returns converted memory map and runtime system table. The memory map is written to the given buffer in the handoff region. If `dump` is set, both memory maps are printed to the serial console, the text output is gone by then.
 fn drop_boot_services(
     system_table: SystemTable<Boot>,
-    mut descriptors: Vec<ChickenMemoryDescriptor>,
+    descriptors: &'static mut [ChickenMemoryDescriptor],
     kernel_info: &KernelInfo,
+    dump: bool,
 ) -> (SystemTable<Runtime>, ChickenMemoryMap) {
     // drop boot services
     let (runtime, uefi_mmap) = unsafe { system_table.exit_boot_services(MemoryType::LOADER_DATA) };
+    let mut descriptors_len = 0;
     let mut first_addr = u64::MAX;
     let mut first_available_addr = u64::MAX;
     let mut last_addr = u64::MIN;
@@ -231,47 +405,60 @@ fn drop_boot_services(
                     + (kernel_info.kernel_code_page_count * PAGE_SIZE) as u64
         {
             ChickenMemoryType::KernelCode
-        }
-        // mark stack as kernel stack
-        else if descriptor.phys_start <= kernel_info.kernel_stack_address
-            && phys_end
-                >= kernel_info.kernel_stack_address
-                    + (kernel_info.kernel_stack_page_count * PAGE_SIZE) as u64
-        {
-            ChickenMemoryType::KernelStack
         } else {
             // Determine the core memory type based on the UEFI memory type
             match descriptor.ty {
                 MemoryType::CONVENTIONAL
                 | MemoryType::BOOT_SERVICES_DATA
                 | MemoryType::BOOT_SERVICES_CODE => ChickenMemoryType::Available,
-                // mark mmap data, boot info, font data, ... as kernel data
-                MemoryType::LOADER_DATA => ChickenMemoryType::KernelData,
+                KERNEL_STACK_MEMORY_TYPE => ChickenMemoryType::KernelStack,
+                // the handoff region contains the boot info, memory map, font data, kernel symbols and modules
+                HANDOFF_MEMORY_TYPE => ChickenMemoryType::KernelData,
                 MemoryType::ACPI_RECLAIM | MemoryType::ACPI_NON_VOLATILE  => ChickenMemoryType::AcpiData,
                 _ => ChickenMemoryType::Reserved,
             }
         };
 
-        descriptors.push(ChickenMemoryDescriptor {
+        if dump {
+            qemu_println!(
+                "mmap uefi: phys_start: {:#x}, phys_end: {:#x}, num_pages: {}, type: {:?} -> {:?}",
+                descriptor.phys_start,
+                phys_end,
+                descriptor.page_count,
+                descriptor.ty,
+                r#type
+            );
+        }
+
+        assert!(
+            descriptors_len < descriptors.len(),
+            "memory map buffer in the handoff region is too small"
+        );
+        descriptors[descriptors_len] = ChickenMemoryDescriptor {
             phys_start: descriptor.phys_start,
             phys_end,
             num_pages: descriptor.page_count,
             r#type,
-        });
+        };
+        descriptors_len += 1;
     });
 
-    let (ptr, len, _cap) = descriptors.into_raw_parts();
-    (
-        runtime,
-        ChickenMemoryMap {
-            descriptors: ptr as *mut ChickenMemoryDescriptor,
-            descriptors_len: len as u64,
-            first_addr,
-            first_available_addr,
-            last_addr,
-            last_available_addr,
-        },
-    )
+    let mmap = ChickenMemoryMap {
+        descriptors: descriptors.as_mut_ptr(),
+        descriptors_len: descriptors_len as u64,
+        first_addr,
+        first_available_addr,
+        last_addr,
+        last_available_addr,
+    };
+    if dump {
+        // the kernel prints the memory map it receives in the same format, so both can be diffed
+        mmap.descriptors()
+            .iter()
+            .for_each(|descriptor| qemu_println!("mmap loader: {}", descriptor));
+        qemu_println!("mmap loader: {}", mmap);
+    }
+    (runtime, mmap)
 }
 
 #[panic_handler]
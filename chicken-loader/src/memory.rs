@@ -3,29 +3,45 @@ use alloc::{
     string::{String, ToString},
     vec::Vec,
 };
-use core::ptr;
+use core::{ptr, slice};
 
 use uefi::{
     prelude::BootServices,
     table::{
-        boot::{AllocateType::AnyPages, MemoryType},
+        boot::{AllocateType, AllocateType::AnyPages, MemoryType},
         Boot,
-        cfg::{ACPI2_GUID, ACPI_GUID}, SystemTable,
+        cfg::{ACPI2_GUID, ACPI_GUID, SMBIOS3_GUID, SMBIOS_GUID}, SystemTable,
     },
 };
 
 use chicken_util::{
     memory::{
         paging::{
-            KERNEL_STACK_MAPPING_OFFSET, manager::PageTableManager, PageEntryFlags, PageTable,
+            self, KERNEL_STACK_MAPPING_OFFSET, manager::PageTableManager, PageEntryFlags, PageTable,
         },
         PhysicalAddress,
         pmm::{PageFrameAllocator, PageFrameAllocatorError}, VirtualAddress,
     },
-    PAGE_SIZE,
+    module::{ModuleDescriptor, ModuleList},
+    symbols::{KernelSymbol, KernelSymbols},
+    BootInfo, CRASH_DUMP_SIZE, PAGE_SIZE,
 };
 
-use crate::{ChickenMemoryDescriptor, ChickenMemoryMap, KERNEL_MAPPING_OFFSET, KERNEL_STACK_SIZE};
+use crate::{
+    file::{ModuleFile, SymbolTable}, ChickenMemoryDescriptor, ChickenMemoryMap, KERNEL_MAPPING_OFFSET,
+    KERNEL_STACK_SIZE,
+};
+
+/// Physical address of the crash dump region. It has to stay the same across boots, so the kernel finds the dump of the previous boot.
+const CRASH_DUMP_ADDRESS: PhysicalAddress = 0x0400_0000;
+/// OS-defined memory type, so the crash dump region is reported as reserved and never handed out by the physical memory manager.
+const CRASH_DUMP_MEMORY_TYPE: MemoryType = MemoryType::custom(0x8000_0000);
+/// OS-defined memory type of the handoff region, so it shows up as a single descriptor in the memory map.
+pub(super) const HANDOFF_MEMORY_TYPE: MemoryType = MemoryType::custom(0x8000_0001);
+/// OS-defined memory type of the kernel stack, so it is never merged with other loader allocations in the memory map.
+pub(super) const KERNEL_STACK_MEMORY_TYPE: MemoryType = MemoryType::custom(0x8000_0002);
+/// Additional memory map entries to reserve, since the memory map changes between allocating the handoff region and exiting boot services.
+const MEMORY_MAP_SLACK: usize = 32;
 
 #[derive(Copy, Clone, Debug)]
 pub(super) struct KernelInfo {
@@ -33,14 +49,90 @@ pub(super) struct KernelInfo {
     pub(super) kernel_code_page_count: usize,
     pub(super) kernel_stack_address: PhysicalAddress,
     pub(super) kernel_stack_page_count: usize,
-    pub(super) kernel_boot_info_address: PhysicalAddress,
+    pub(super) handoff_address: PhysicalAddress,
+    pub(super) handoff_page_count: usize,
+}
+
+/// Contiguous region that contains all data handed over to the kernel (boot info, memory map, font, kernel symbols and modules), so the kernel can map it as one unit. Every item starts at a page boundary.
+#[derive(Debug)]
+pub(super) struct HandoffRegion {
+    address: PhysicalAddress,
+    page_count: usize,
+    /// Amount of pages already handed out.
+    used_page_count: usize,
+}
+
+impl HandoffRegion {
+    /// Allocates a zeroed region that is large enough for items of the given sizes in bytes.
+    fn allocate(bt: &BootServices, item_sizes: &[usize]) -> Result<Self, String> {
+        let page_count = item_sizes.iter().copied().map(Self::page_count_of).sum();
+        let address = bt
+            .allocate_pages(AnyPages, HANDOFF_MEMORY_TYPE, page_count)
+            .map_err(|error| {
+                format!(
+                    "Could not allocate {} pages for the handoff region: {}.",
+                    page_count, error
+                )
+            })?;
+        unsafe { ptr::write_bytes(address as *mut u8, 0, page_count * PAGE_SIZE) };
+
+        Ok(Self {
+            address,
+            page_count,
+            used_page_count: 0,
+        })
+    }
+
+    /// Reserves zeroed memory for an item of the given size in bytes. Returns its physical address.
+    fn reserve(&mut self, size: usize) -> PhysicalAddress {
+        let page_count = Self::page_count_of(size);
+        assert!(
+            self.used_page_count + page_count <= self.page_count,
+            "handoff region is too small"
+        );
+        let address = self.address + (self.used_page_count * PAGE_SIZE) as u64;
+        self.used_page_count += page_count;
+        address
+    }
+
+    /// Copies the given data into the region. Returns its physical address.
+    fn copy(&mut self, data: &[u8]) -> PhysicalAddress {
+        let address = self.reserve(data.len());
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), address as *mut u8, data.len()) };
+        address
+    }
+
+    /// Every item occupies at least one page, so empty items still get a valid address.
+    fn page_count_of(size: usize) -> usize {
+        size.div_ceil(PAGE_SIZE).max(1)
+    }
+
+    pub(super) fn address(&self) -> PhysicalAddress {
+        self.address
+    }
+
+    pub(super) fn page_count(&self) -> usize {
+        self.page_count
+    }
+}
+
+/// Kernel handoff data placed in the handoff region. All addresses are physical.
+pub(super) struct Handoff {
+    pub(super) region: HandoffRegion,
+    /// Boot info at the start of the region.
+    pub(super) boot_info_address: PhysicalAddress,
+    /// Buffer for the memory map, filled in after exiting boot services.
+    pub(super) memory_map_buffer: &'static mut [ChickenMemoryDescriptor],
+    pub(super) glyph_buffer_address: PhysicalAddress,
+    pub(super) modules: ModuleList,
+    pub(super) kernel_symbols: KernelSymbols,
 }
 
 /// Allocate pages for kernel stack. Returns physical address of allocated stack and amount of pages allocated.
 pub(super) fn allocate_kernel_stack(bt: &BootServices) -> Result<(PhysicalAddress, usize), String> {
     let num_pages = (KERNEL_STACK_SIZE + PAGE_SIZE - 1) / PAGE_SIZE + 1; // + 1 to ENSURE sufficient size
     let start_addr = bt
-        .allocate_pages(AnyPages, MemoryType::LOADER_DATA, num_pages)
+        .allocate_pages(AnyPages, KERNEL_STACK_MEMORY_TYPE, num_pages)
         .map_err(|_| {
             format!(
                 "Could not allocate {} pages for the kernel stack.",
@@ -50,31 +142,111 @@ pub(super) fn allocate_kernel_stack(bt: &BootServices) -> Result<(PhysicalAddres
     Ok((start_addr, num_pages))
 }
 
-/// Allocate a single page to store the boot information in
-pub(super) fn allocate_boot_info(
+/// Allocates the handoff region and copies the font glyphs and the modules into it. Space for the boot info and the memory map is reserved, both are filled in after exiting boot services.
+pub(super) fn allocate_handoff(
     bt: &BootServices,
-) -> Result<(PhysicalAddress, Vec<ChickenMemoryDescriptor>), String> {
-    let boot_info_addr = bt
-        .allocate_pages(AnyPages, MemoryType::LOADER_DATA, 1)
-        .map_err(|_| "Could not allocate page for kernel boot information.".to_string())?;
-
-    // get uefi mmap meta data to allocate enough later for custom memory map in `drop_boot_services`
+    glyphs: &[u8],
+    modules: &[ModuleFile],
+    symbols: &SymbolTable,
+) -> Result<Handoff, String> {
+    // get uefi mmap meta data to reserve enough space for the custom memory map in `drop_boot_services`
     let uefi_memory_map_meta = bt
         .memory_map(MemoryType::LOADER_DATA)
         .map_err(|error| format!("Could not get uefi memory map: {error}"))?
         .as_raw()
         .1;
+    // the map grows by the allocations made until boot services are exited
+    let memory_map_capacity = uefi_memory_map_meta.entry_count() * 2 + MEMORY_MAP_SLACK;
+
+    let symbols_size = symbols.symbols.len() * size_of::<KernelSymbol>();
+    let mut item_sizes = Vec::with_capacity(modules.len() + 6);
+    item_sizes.push(size_of::<BootInfo>());
+    item_sizes.push(memory_map_capacity * size_of::<ChickenMemoryDescriptor>());
+    item_sizes.push(modules.len() * size_of::<ModuleDescriptor>());
+    item_sizes.push(glyphs.len());
+    item_sizes.push(symbols_size);
+    item_sizes.push(symbols.strings.len());
+    item_sizes.extend(modules.iter().map(|module| module.data.len()));
+
+    let mut region = HandoffRegion::allocate(bt, &item_sizes)?;
 
-    // allocate enough memory for the map
-    let sufficient_memory_map_size = uefi_memory_map_meta.map_size;
+    let boot_info_address = region.reserve(size_of::<BootInfo>());
+    let memory_map_buffer = unsafe {
+        slice::from_raw_parts_mut(
+            region.reserve(memory_map_capacity * size_of::<ChickenMemoryDescriptor>())
+                as *mut ChickenMemoryDescriptor,
+            memory_map_capacity,
+        )
+    };
+    let descriptors = region.reserve(modules.len() * size_of::<ModuleDescriptor>())
+        as *mut ModuleDescriptor;
+    let glyph_buffer_address = region.copy(glyphs);
+    let symbols_address = region.copy(unsafe {
+        slice::from_raw_parts(symbols.symbols.as_ptr() as *const u8, symbols_size)
+    });
+    let strings_address = region.copy(symbols.strings.as_bytes());
 
-    // allocate descriptors in memory
-    let descriptors = Vec::with_capacity(sufficient_memory_map_size);
+    for (index, module) in modules.iter().enumerate() {
+        let address = region.copy(&module.data);
+        unsafe {
+            descriptors.add(index).write(ModuleDescriptor::new(
+                &module.name,
+                address,
+                module.data.len() as u64,
+            ));
+        }
+    }
+
+    Ok(Handoff {
+        region,
+        boot_info_address,
+        memory_map_buffer,
+        glyph_buffer_address,
+        modules: ModuleList {
+            // the kernel treats a null pointer as an empty list
+            descriptors: if modules.is_empty() {
+                ptr::null_mut()
+            } else {
+                descriptors
+            },
+            descriptors_len: modules.len() as u64,
+        },
+        // the kernel treats null pointers as an empty table as well
+        kernel_symbols: if symbols.symbols.is_empty() {
+            KernelSymbols {
+                symbols: ptr::null_mut(),
+                symbols_len: 0,
+                strings: ptr::null_mut(),
+                strings_size: 0,
+            }
+        } else {
+            KernelSymbols {
+                symbols: symbols_address as *mut KernelSymbol,
+                symbols_len: symbols.symbols.len() as u64,
+                strings: strings_address as *mut u8,
+                strings_size: symbols.strings.len() as u64,
+            }
+        },
+    })
+}
 
-    Ok((boot_info_addr, descriptors))
+/// Reserve the crash dump region at its fixed address. The memory is not cleared, since it may contain the crash dump of the previous boot.
+pub(super) fn allocate_crash_dump(bt: &BootServices) -> Result<PhysicalAddress, String> {
+    let num_pages = CRASH_DUMP_SIZE.div_ceil(PAGE_SIZE);
+    bt.allocate_pages(
+        AllocateType::Address(CRASH_DUMP_ADDRESS),
+        CRASH_DUMP_MEMORY_TYPE,
+        num_pages,
+    )
+    .map_err(|error| {
+        format!(
+            "Could not reserve memory for crash dumps at {:#x}: {}",
+            CRASH_DUMP_ADDRESS, error
+        )
+    })
 }
 
-/// Sets up paging that includes mappings for higher half kernel and higher half stack. Returns address pointing to page table manager, stack pointer, boot info as well as the initialized physical memory manager.
+/// Sets up paging that includes mappings for higher half kernel, higher half stack and the handoff region right above the stack. Returns address pointing to page table manager, stack pointer, boot info as well as the initialized physical memory manager.
 // note: currently all page entry flags are set to the default value, may change to set up nx capability in bootloader already
 pub(super) fn set_up_address_space(
     memory_map: &ChickenMemoryMap,
@@ -93,7 +265,8 @@ pub(super) fn set_up_address_space(
         kernel_code_page_count,
         kernel_stack_address,
         kernel_stack_page_count,
-        kernel_boot_info_address,
+        handoff_address,
+        handoff_page_count,
     } = kernel_info;
 
     // set up physical memory manager
@@ -140,14 +313,14 @@ pub(super) fn set_up_address_space(
         manager.map_memory(virtual_address, physical_address, PageEntryFlags::default())?;
     }
 
-    // map boot info page to higher half right above stack
+    // map handoff region to higher half right above stack, the boot info is located at its start
     let kernel_boot_info_virtual_address =
         KERNEL_STACK_MAPPING_OFFSET + (kernel_stack_page_count * PAGE_SIZE) as u64;
-    manager.map_memory(
-        kernel_boot_info_virtual_address,
-        kernel_boot_info_address,
-        PageEntryFlags::default(),
-    )?;
+    for page in 0..handoff_page_count {
+        let physical_address = ((page * PAGE_SIZE) as u64) + handoff_address;
+        let virtual_address = kernel_boot_info_virtual_address + (page * PAGE_SIZE) as u64;
+        manager.map_memory(virtual_address, physical_address, PageEntryFlags::default())?;
+    }
 
     let pmm: PageFrameAllocator = manager.into();
 
@@ -159,6 +332,17 @@ pub(super) fn set_up_address_space(
     ))
 }
 
+/// Checks that the firmware runs with 4-level paging, since the page tables set up for the kernel have 4 levels. 5-level paging can not be disabled in long mode, so the kernel can not be booted, if the firmware has enabled it.
+pub(super) fn check_paging_mode() -> Result<(), String> {
+    if paging::five_level_paging_enabled() {
+        return Err(
+            "The firmware has enabled 5-level paging (LA57), but the kernel only supports 4-level paging."
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
 /// Get root system descriptor pointer address
 pub(super) fn get_rsdp(st: &SystemTable<Boot>) -> Result<u64, String> {
     let mut config_entries = st.config_table().iter();
@@ -169,3 +353,14 @@ pub(super) fn get_rsdp(st: &SystemTable<Boot>) -> Result<u64, String> {
     rsdp.map(|entry| entry.address as u64)
         .ok_or("Could not find RSDP.".to_string())
 }
+
+/// Get SMBIOS entry point address. Returns 0, if the firmware does not provide SMBIOS tables.
+pub(super) fn get_smbios(st: &SystemTable<Boot>) -> u64 {
+    let config_entries = st.config_table();
+    // prefer the 64-bit entry point of SMBIOS 3
+    config_entries
+        .iter()
+        .find(|entry| matches!(entry.guid, SMBIOS3_GUID))
+        .or_else(|| config_entries.iter().find(|entry| matches!(entry.guid, SMBIOS_GUID)))
+        .map_or(0, |entry| entry.address as u64)
+}
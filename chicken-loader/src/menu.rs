@@ -0,0 +1,125 @@
+use alloc::format;
+use core::fmt::Write;
+
+use uefi::{
+    proto::console::text::{Color, Key, Output, ScanCode},
+    table::{Boot, SystemTable},
+};
+
+use crate::config::BootConfig;
+
+/// Interval in which the keyboard is polled in microseconds.
+const POLL_INTERVAL: usize = 50_000;
+const POLLS_PER_SECOND: u64 = 1_000_000 / POLL_INTERVAL as u64;
+/// Width the lines of the menu are padded to, so shorter lines overwrite longer ones.
+const LINE_WIDTH: usize = 79;
+
+/// Displays the boot menu and returns the index of the selected entry. Entries are selected with the arrow keys and booted with enter, pressing the number of an entry boots it right away. The default entry is booted once the timeout expires without any key being pressed. If there is only one entry or the timeout is 0, the menu is skipped.
+pub(super) fn select_entry(system_table: &mut SystemTable<Boot>, config: &BootConfig) -> usize {
+    let entry_count = config.entries.len();
+    if entry_count == 1 || config.menu_timeout == 0 {
+        return config.default_entry;
+    }
+
+    let mut selected = config.default_entry;
+    // seconds until the default entry is booted, None once a key has been pressed
+    let mut remaining_seconds = Some(config.menu_timeout);
+    let mut polls = 0;
+
+    let stdout = system_table.stdout();
+    stdout
+        .clear()
+        .expect("Standard Output Protocol Error: Could not clear screen for stdout.");
+    // not every console supports hiding the cursor
+    let _ = stdout.enable_cursor(false);
+    draw(stdout, config, selected, remaining_seconds);
+
+    loop {
+        if let Ok(Some(key)) = system_table.stdin().read_key() {
+            remaining_seconds = None;
+            match key {
+                Key::Special(ScanCode::UP) => {
+                    selected = selected.checked_sub(1).unwrap_or(entry_count - 1)
+                }
+                Key::Special(ScanCode::DOWN) => selected = (selected + 1) % entry_count,
+                Key::Printable(character) => match char::from(character) {
+                    '\r' | '\n' => break,
+                    digit => {
+                        let number = digit.to_digit(10).unwrap_or(0) as usize;
+                        if (1..=entry_count).contains(&number) {
+                            selected = number - 1;
+                            break;
+                        }
+                    }
+                },
+                _ => {}
+            }
+            draw(system_table.stdout(), config, selected, remaining_seconds);
+            continue;
+        }
+
+        system_table.boot_services().stall(POLL_INTERVAL);
+        if let Some(seconds) = remaining_seconds {
+            polls += 1;
+            if polls < POLLS_PER_SECOND {
+                continue;
+            }
+            polls = 0;
+            if seconds <= 1 {
+                break;
+            }
+            remaining_seconds = Some(seconds - 1);
+            draw(system_table.stdout(), config, selected, remaining_seconds);
+        }
+    }
+
+    let stdout = system_table.stdout();
+    stdout
+        .clear()
+        .expect("Standard Output Protocol Error: Could not clear screen for stdout.");
+    let _ = stdout.enable_cursor(true);
+    selected
+}
+
+/// Draws the menu over the previous one at the top of the screen.
+fn draw(stdout: &mut Output, config: &BootConfig, selected: usize, remaining_seconds: Option<u64>) {
+    stdout
+        .set_cursor_position(0, 0)
+        .expect("Standard Output Protocol Error: Could not set cursor position.");
+
+    write_line(stdout, "CHICKEN OS", Color::Yellow, Color::Black);
+    write_line(stdout, "", Color::White, Color::Black);
+    for (index, entry) in config.entries.iter().enumerate() {
+        let line = format!(" {}. {}", index + 1, entry.name);
+        if index == selected {
+            write_line(stdout, &line, Color::Black, Color::LightGray);
+        } else {
+            write_line(stdout, &line, Color::White, Color::Black);
+        }
+    }
+    write_line(stdout, "", Color::White, Color::Black);
+
+    let status = match remaining_seconds {
+        Some(seconds) => format!(
+            "Booting {} in {} seconds...",
+            config.entries[selected].name, seconds
+        ),
+        None => "Select an entry with the arrow keys or its number, boot it with enter.".into(),
+    };
+    write_line(stdout, &status, Color::White, Color::Black);
+}
+
+fn write_line(stdout: &mut Output, line: &str, foreground: Color, background: Color) {
+    stdout
+        .set_color(foreground, background)
+        .expect("Standard Output Protocol Error: Could not set color.");
+    stdout
+        .write_str(&format!("{:<width$}", line, width = LINE_WIDTH))
+        .expect("Standard Output Protocol Error: Could not write text to screen.");
+    stdout
+        .set_color(Color::White, Color::Black)
+        .expect("Standard Output Protocol Error: Could not set color.");
+    stdout
+        .write_char('\n')
+        .expect("Standard Output Protocol Error: Could not write next line character to screen.");
+}
@@ -0,0 +1,108 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use uefi::{
+    prelude::BootServices,
+    proto::{
+        loaded_image::LoadedImage,
+        network::{
+            pxe::{BaseCode, DhcpV4Packet},
+            IpAddress,
+        },
+    },
+    table::boot::OpenProtocolParams,
+    CStr8, Handle, Status,
+};
+
+/// TFTP error code of a file that does not exist on the server.
+const TFTP_FILE_NOT_FOUND: u8 = 1;
+
+/// Returns the network device the loader has been booted from via PXE, or `None`, if it has been loaded from a filesystem.
+pub(super) fn pxe_boot_device(image_handle: Handle, bt: &BootServices) -> Option<Handle> {
+    let device = bt
+        .open_protocol_exclusive::<LoadedImage>(image_handle)
+        .ok()?
+        .device()?;
+    bt.test_protocol::<BaseCode>(OpenProtocolParams {
+        handle: device,
+        agent: image_handle,
+        controller: None,
+    })
+    .ok()
+    .map(|_| device)
+}
+
+/// Reads a file from the TFTP server the loader has been booted from. Paths are relative to the directory of the boot file. Returns `None`, if the server does not have the file.
+pub(super) fn get_optional_file_data(
+    device: Handle,
+    bt: &BootServices,
+    filename: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    let mut base_code = bt
+        .open_protocol_exclusive::<BaseCode>(device)
+        .map_err(|_| "Cannot get PXE base code protocol".to_string())?;
+    let (server_ip, path) = {
+        let mode = base_code.mode();
+        if !mode.dhcp_ack_received {
+            return Err("Network boot device has not been configured via DHCP.".to_string());
+        }
+        if mode.using_ipv6 {
+            return Err("Network boot is only supported via IPv4.".to_string());
+        }
+        // the boot server is announced by a proxy dhcp server, if there is one
+        let packet: &DhcpV4Packet = if mode.proxy_offer_received {
+            mode.proxy_offer.as_ref()
+        } else {
+            mode.dhcp_ack.as_ref()
+        };
+        (
+            IpAddress::new_v4(packet.bootp_si_addr),
+            server_path(&packet.bootp_boot_file, filename),
+        )
+    };
+    let mut path = path.into_bytes();
+    path.push(0);
+    let path =
+        CStr8::from_bytes_with_nul(&path).map_err(|_| format!("Invalid filename: {filename}"))?;
+
+    let size = match base_code.tftp_get_file_size(&server_ip, path) {
+        Ok(size) => size,
+        Err(error)
+            if error.status() == Status::TFTP_ERROR
+                && base_code.mode().tftp_error_received
+                && base_code.mode().tftp_error.error_code == TFTP_FILE_NOT_FOUND =>
+        {
+            return Ok(None)
+        }
+        Err(error) => {
+            return Err(format!(
+                "Unable to get size of file with name: {filename} via TFTP: {error}"
+            ))
+        }
+    };
+
+    // an empty buffer cannot be passed to the firmware
+    if size == 0 {
+        return Ok(Some(Vec::new()));
+    }
+    let mut data = vec![0u8; size as usize];
+    let read = base_code
+        .tftp_read_file(&server_ip, path, Some(&mut data))
+        .map_err(|error| format!("Unable to read file with name: {filename} via TFTP: {error}"))?;
+    data.truncate(read as usize);
+    Ok(Some(data))
+}
+
+/// Returns the path of the file on the TFTP server, which is located in the same directory as the boot file.
+fn server_path(boot_file: &[u8], filename: &str) -> String {
+    let boot_file = boot_file.split(|byte| *byte == 0).next().unwrap_or(&[]);
+    let boot_file = core::str::from_utf8(boot_file).unwrap_or("");
+    match boot_file.rfind(['/', '\\']) {
+        Some(index) => format!("{}{}", &boot_file[..=index], filename),
+        None => filename.to_string(),
+    }
+}
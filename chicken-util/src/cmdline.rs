@@ -0,0 +1,66 @@
+use core::{
+    fmt::{Debug, Display, Formatter},
+    str,
+};
+
+/// Maximum length of the kernel command line in bytes.
+pub const COMMAND_LINE_LENGTH: usize = 256;
+
+/// Command line of the boot entry selected in the loader, passed on to the kernel.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CommandLine {
+    /// Command line, padded with zeros
+    pub buffer: [u8; COMMAND_LINE_LENGTH],
+}
+
+impl CommandLine {
+    /// Creates a new command line. Command lines longer than [`COMMAND_LINE_LENGTH`] are truncated.
+    pub fn new(command_line: &str) -> Self {
+        let mut buffer = [0; COMMAND_LINE_LENGTH];
+        // truncate at a character boundary, so the command line stays valid utf-8
+        let mut length = command_line.len().min(COMMAND_LINE_LENGTH);
+        while !command_line.is_char_boundary(length) {
+            length -= 1;
+        }
+        buffer[..length].copy_from_slice(&command_line.as_bytes()[..length]);
+        Self { buffer }
+    }
+
+    pub fn as_str(&self) -> &str {
+        let length = self
+            .buffer
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(COMMAND_LINE_LENGTH);
+        str::from_utf8(&self.buffer[..length]).unwrap_or_default()
+    }
+
+    /// Returns the value of the first `key=value` option with the given key. Options are separated by whitespace.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.as_str().split_whitespace().find_map(|option| {
+            option
+                .split_once('=')
+                .filter(|(option_key, _)| *option_key == key)
+                .map(|(_, value)| value)
+        })
+    }
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl Display for CommandLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for CommandLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Command Line {{ {} }}", self.as_str())
+    }
+}
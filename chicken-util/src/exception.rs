@@ -0,0 +1,232 @@
+use core::fmt::{Display, Formatter};
+
+use bitflags::bitflags;
+
+pub const DIVIDE_ERROR_VECTOR: u64 = 0;
+pub const DEBUG_VECTOR: u64 = 1;
+pub const BREAKPOINT_VECTOR: u64 = 3;
+pub const INVALID_OPCODE_VECTOR: u64 = 6;
+pub const DOUBLE_FAULT_VECTOR: u64 = 8;
+pub const GENERAL_PROTECTION_FAULT_VECTOR: u64 = 13;
+pub const PAGE_FAULT_VECTOR: u64 = 14;
+/// Amount of vectors reserved for cpu exceptions.
+pub const EXCEPTION_COUNT: u64 = 32;
+
+/// Signals reported to debuggers, as defined by gdb.
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+bitflags! {
+    /// Error code for page faults. In addition, the value of the CR2 register is set to the virtual address that causes the fault
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct PageFaultErrorCode: u32 {
+        /// Present: When set, the page fault was caused by a page-protection violation. When not set, it was caused by a non-present page.
+        const PRESENT = 1 << 0;
+        /// Write: When set, the page fault was caused by a write access. When not set, it was caused by a read access.
+        const WRITE = 1 << 1;
+        /// User: When set, the page fault was caused while CPL = 3. This does not necessarily mean that the page fault was a privilege violation.
+        const USER = 1 << 2;
+        /// Reserved Write: When set, one or more page directory entries contain reserved bits which are set to 1. This only applies when the PSE or PAE flags in CR4 are set to 1.
+        const RESERVED_WRITE = 1 << 3;
+        /// Instruction Fetch: When set, the page fault was caused by an instruction fetch. This only applies when the No-Execute bit is supported and enabled.
+        const INSTRUCTION_FETCH = 1 << 4;
+        /// Protection Key: When set, the page fault was caused by a protection-key violation. PKRU register (user-mode accesses) or PKRS MSR (supervisor-mode accesses) specifies protection key rights.
+        const PROTECTION_KEY = 1 << 5;
+        /// Shadow Stack: When set, the page fault was caused by a shadow stack access.
+        const SHADOW_STACK = 1 << 6;
+        // bits 7 - 14 reserved
+        /// Software Guard Extension: When set, the fault was due to an SGX violation. The fault is unrelated to ordinary paging.
+        const SGX = 1 << 15;
+        // bits 16 - 31 reserved
+    }
+
+    /// Error code of exceptions related to a segment selector, e.g. general protection faults. It is 0, if the exception is not caused by a selector.
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct SelectorErrorCode: u32 {
+        /// External: If set, means it was a hardware interrupt. Cleared for software interrupts.
+        const EXTERNAL = 1 << 0;
+        /// IDT: Set if this error code refers to the IDT. If cleared it refers to the GDT or LDT.
+        const IDT = 1 << 1;
+        /// Table Index: Set if the error code refers to the LDT, cleared if referring to the GDT.
+        const TABLE_INDEX = 1 << 2;
+        /// Index: The index into the table this error code refers to, bits 3 - 15 of the selector.
+        const INDEX = 0x1FFF << 3;
+    }
+}
+
+impl PageFaultErrorCode {
+    /// Returns what the faulting access tried to do, e.g. `write`.
+    pub fn access(&self) -> &'static str {
+        if self.contains(Self::INSTRUCTION_FETCH) {
+            "instruction fetch"
+        } else if self.contains(Self::SHADOW_STACK) {
+            "shadow stack access"
+        } else if self.contains(Self::WRITE) {
+            "write"
+        } else {
+            "read"
+        }
+    }
+
+    /// Returns why the access has faulted, e.g. `non-present page`.
+    pub fn cause(&self) -> &'static str {
+        if self.contains(Self::SGX) {
+            "SGX violation"
+        } else if self.contains(Self::RESERVED_WRITE) {
+            "reserved bit set in a page table entry"
+        } else if self.contains(Self::PROTECTION_KEY) {
+            "protection key violation"
+        } else if self.contains(Self::PRESENT) {
+            "protection violation"
+        } else {
+            "non-present page"
+        }
+    }
+}
+
+impl Display for PageFaultErrorCode {
+    /// Summarizes the page fault, e.g. `user write: non-present page`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mode = if self.contains(Self::USER) {
+            "user"
+        } else {
+            "kernel"
+        };
+        write!(f, "{} {}: {}", mode, self.access(), self.cause())
+    }
+}
+
+/// Descriptor table a selector error code refers to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+impl SelectorErrorCode {
+    /// Returns the table the selector refers to.
+    pub fn table(&self) -> DescriptorTable {
+        if self.contains(Self::IDT) {
+            DescriptorTable::Idt
+        } else if self.contains(Self::TABLE_INDEX) {
+            DescriptorTable::Ldt
+        } else {
+            DescriptorTable::Gdt
+        }
+    }
+
+    /// Returns the index of the entry in the table the selector refers to.
+    pub fn index(&self) -> u16 {
+        (self.bits() >> 3) as u16 & 0x1FFF
+    }
+}
+
+impl Display for SelectorErrorCode {
+    /// Formats the entry the selector refers to, e.g. `GDT index 5`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no selector");
+        }
+        write!(f, "{:?} index {}", self.table(), self.index())?;
+        if self.contains(Self::EXTERNAL) {
+            write!(f, ", external")?;
+        }
+        Ok(())
+    }
+}
+
+/// Cpu exception, as raised at its vector with the error code pushed by the cpu.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Exception {
+    pub vector: u64,
+    /// Error code pushed by the cpu, 0 for exceptions without one.
+    pub error_code: u64,
+}
+
+impl Exception {
+    pub fn new(vector: u64, error_code: u64) -> Self {
+        Self { vector, error_code }
+    }
+
+    /// Returns the name of the exception, as printed in exception reports.
+    pub fn name(&self) -> &'static str {
+        match self.vector {
+            DIVIDE_ERROR_VECTOR => "DIV BY 0",
+            DEBUG_VECTOR => "DEBUG",
+            2 => "NON-MASKABLE INTERRUPT",
+            BREAKPOINT_VECTOR => "BREAKPOINT",
+            4 => "OVERFLOW",
+            5 => "BOUND RANGE EXCEEDED",
+            INVALID_OPCODE_VECTOR => "INVALID OPCODE",
+            7 => "DEVICE NOT AVAILABLE",
+            DOUBLE_FAULT_VECTOR => "DOUBLE FAULT",
+            9 => "COPROCESSOR SEGMENT OVERRUN",
+            10 => "INVALID TSS",
+            11 => "SEGMENT NOT PRESENT",
+            12 => "STACK-SEGMENT FAULT",
+            GENERAL_PROTECTION_FAULT_VECTOR => "GENERAL PROTECTION FAULT",
+            PAGE_FAULT_VECTOR => "PAGE FAULT",
+            16 => "X87 FLOATING-POINT EXCEPTION",
+            17 => "ALIGNMENT CHECK",
+            18 => "MACHINE CHECK",
+            19 => "SIMD FLOATING-POINT EXCEPTION",
+            20 => "VIRTUALIZATION EXCEPTION",
+            21 => "CONTROL PROTECTION EXCEPTION",
+            28 => "HYPERVISOR INJECTION EXCEPTION",
+            29 => "VMM COMMUNICATION EXCEPTION",
+            30 => "SECURITY EXCEPTION",
+            vector if vector < EXCEPTION_COUNT => "RESERVED EXCEPTION",
+            _ => "INTERRUPT",
+        }
+    }
+
+    /// Whether the cpu pushes an error code for the exception.
+    pub fn has_error_code(&self) -> bool {
+        matches!(self.vector, 8 | 10..=14 | 17 | 21 | 29 | 30)
+    }
+
+    /// Returns the error code of the exception, if it refers to a segment selector.
+    pub fn selector_error_code(&self) -> Option<SelectorErrorCode> {
+        matches!(self.vector, 10..=13)
+            .then(|| SelectorErrorCode::from_bits_truncate(self.error_code as u32))
+    }
+
+    /// Returns the error code of the exception, if it is a page fault.
+    pub fn page_fault_error_code(&self) -> Option<PageFaultErrorCode> {
+        (self.vector == PAGE_FAULT_VECTOR)
+            .then(|| PageFaultErrorCode::from_bits_truncate(self.error_code as u32))
+    }
+
+    /// Returns the signal a debugger reports for the exception.
+    pub fn signal(&self) -> u8 {
+        match self.vector {
+            DIVIDE_ERROR_VECTOR | 16 | 19 => SIGFPE,
+            INVALID_OPCODE_VECTOR => SIGILL,
+            17 => SIGBUS,
+            10..=14 => SIGSEGV,
+            _ => SIGTRAP,
+        }
+    }
+}
+
+impl Display for Exception {
+    /// Formats the name of the exception followed by its decoded error code, e.g. `PAGE FAULT (kernel read: non-present page)`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(error_code) = self.page_fault_error_code() {
+            write!(f, " ({})", error_code)
+        } else if let Some(error_code) = self.selector_error_code() {
+            write!(f, " ({})", error_code)
+        } else if self.has_error_code() && self.error_code != 0 {
+            write!(f, " (error code: {:#x})", self.error_code)
+        } else {
+            Ok(())
+        }
+    }
+}
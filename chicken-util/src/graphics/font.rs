@@ -1,11 +1,19 @@
 use core::{
     fmt,
     fmt::{Debug, Formatter},
+    mem::size_of,
     slice,
 };
 
 pub const PSF1_MAGIC: u16 = 0x0436;
 pub const PSF2_MAGIC: u32 = 0x864ab572;
+/// PSF1 font modes indicating that the glyphs are followed by a unicode table.
+pub const PSF1_MODE_HAS_TABLE: u8 = 0x02 | 0x04;
+/// PSF2 flag indicating that the glyphs are followed by a unicode table.
+pub const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+
+/// PSF1 font compiled into the loader and the kernel, so there is a working console even if no font file is available.
+pub const BUILTIN_FONT: &[u8] = include_bytes!("../../fonts/light16.psf");
 
 #[derive(Copy, Clone, Debug)]
 pub struct Font {
@@ -15,9 +23,63 @@ pub struct Font {
     pub glyph_buffer_address: *const u8,
     /// Size of glyph buffer
     pub glyph_buffer_size: usize,
+    /// Size in bytes of the unicode table stored right after the glyphs, 0 if the font does not have one.
+    pub unicode_table_size: usize,
 }
 
 impl Font {
+    /// Returns the built-in font. The glyphs are read directly from [`BUILTIN_FONT`], so it is available without any memory being allocated.
+    pub fn builtin() -> Self {
+        let header = PSF1Header {
+            magic: u16::from_le_bytes([BUILTIN_FONT[0], BUILTIN_FONT[1]]),
+            font_mode: BUILTIN_FONT[2],
+            character_size: BUILTIN_FONT[3],
+        };
+        let glyphs_size = header.glyph_count() * header.character_size as usize;
+        let unicode_table_size = if header.font_mode & PSF1_MODE_HAS_TABLE != 0 {
+            BUILTIN_FONT.len() - size_of::<PSF1Header>() - glyphs_size
+        } else {
+            0
+        };
+        Self {
+            header: PSFHeader::Version1(header),
+            glyph_buffer_address: BUILTIN_FONT[size_of::<PSF1Header>()..].as_ptr(),
+            glyph_buffer_size: header.glyph_count(),
+            unicode_table_size,
+        }
+    }
+
+    /// Returns the unicode table, which maps code points to glyphs. Empty, if the font does not have one.
+    pub fn unicode_table(&self) -> &[u8] {
+        if self.unicode_table_size == 0 {
+            return &[];
+        }
+        unsafe {
+            slice::from_raw_parts(
+                self.glyph_buffer_address
+                    .add(self.glyph_buffer_size * self.glyph_bytes()),
+                self.unicode_table_size,
+            )
+        }
+    }
+
+    /// Returns an iterator over the code points of the unicode table together with the index of the glyph they are drawn with. Sequences of multiple code points are skipped.
+    pub fn unicode_mappings(&self) -> UnicodeMappings<'_> {
+        UnicodeMappings {
+            table: self.unicode_table(),
+            is_version2: matches!(self.header, PSFHeader::Version2(_)),
+            glyph: 0,
+            in_sequence: false,
+        }
+    }
+
+    /// Whether the font has any glyphs to draw. The font handed over by the loader is unusable, if it could not be loaded.
+    pub fn is_usable(&self) -> bool {
+        !self.glyph_buffer_address.is_null()
+            && self.glyph_buffer_size != 0
+            && self.glyph_bytes() != 0
+    }
+
     pub fn glyphs(&self) -> &[u8] {
         unsafe { slice::from_raw_parts(self.glyph_buffer_address, self.glyph_buffer_size) }
     }
@@ -52,6 +114,7 @@ pub enum PSFHeader {
     Version1(PSF1Header),
     Version2(PSF2Header),
 }
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct PSF1Header {
@@ -63,6 +126,17 @@ pub struct PSF1Header {
     pub character_size: u8,
 }
 
+impl PSF1Header {
+    /// Returns the amount of glyphs, either 256 or 512 depending on the font mode.
+    pub fn glyph_count(&self) -> usize {
+        if self.font_mode & 1 == 1 {
+            512
+        } else {
+            256
+        }
+    }
+}
+
 impl Debug for PSF1Header {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!(
@@ -101,3 +175,71 @@ impl Debug for PSF2Header {
         ))
     }
 }
+
+/// Iterator over the entries of the unicode table of a font, returned by [`Font::unicode_mappings`].
+///
+/// The table lists the code points of each glyph in order, terminated by 0xFFFF (PSF1) or 0xFF (PSF2). Code points are stored as 16-bit values in PSF1 fonts and as UTF-8 in PSF2 fonts. Sequences of code points, that are drawn with a single glyph, start with 0xFFFE (PSF1) or 0xFE (PSF2).
+#[derive(Clone, Debug)]
+pub struct UnicodeMappings<'a> {
+    table: &'a [u8],
+    is_version2: bool,
+    glyph: usize,
+    in_sequence: bool,
+}
+
+impl Iterator for UnicodeMappings<'_> {
+    type Item = (char, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (entry, length) = if self.is_version2 {
+                match *self.table.first()? {
+                    0xFF => (TableEntry::GlyphEnd, 1),
+                    0xFE => (TableEntry::SequenceStart, 1),
+                    lead => {
+                        let length = match lead {
+                            0xF0..=0xFF => 4,
+                            0xE0..=0xEF => 3,
+                            0xC0..=0xDF => 2,
+                            _ => 1,
+                        };
+                        let bytes = &self.table[..length.min(self.table.len())];
+                        let character = core::str::from_utf8(bytes)
+                            .ok()
+                            .and_then(|string| string.chars().next());
+                        (TableEntry::CodePoint(character), bytes.len())
+                    }
+                }
+            } else {
+                let bytes = self.table.get(..2)?;
+                match u16::from_le_bytes([bytes[0], bytes[1]]) {
+                    0xFFFF => (TableEntry::GlyphEnd, 2),
+                    0xFFFE => (TableEntry::SequenceStart, 2),
+                    value => (TableEntry::CodePoint(char::from_u32(value as u32)), 2),
+                }
+            };
+            self.table = &self.table[length..];
+
+            match entry {
+                TableEntry::GlyphEnd => {
+                    self.glyph += 1;
+                    self.in_sequence = false;
+                }
+                TableEntry::SequenceStart => self.in_sequence = true,
+                // invalid code points are skipped
+                TableEntry::CodePoint(Some(character)) if !self.in_sequence => {
+                    return Some((character, self.glyph));
+                }
+                TableEntry::CodePoint(_) => {}
+            }
+        }
+    }
+}
+
+/// Entry of the unicode table of a font.
+enum TableEntry {
+    /// Remaining code points belong to the next glyph.
+    GlyphEnd,
+    SequenceStart,
+    CodePoint(Option<char>),
+}
@@ -23,3 +23,48 @@ impl Debug for FrameBufferMetadata {
         ))
     }
 }
+
+/// Maximum amount of video modes recorded by the loader.
+pub const MAX_VIDEO_MODES: usize = 32;
+
+/// Video mode supported by the graphics device, as reported by the GOP.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VideoMode {
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize, // pixels per scanline
+    pub is_rgb: bool,
+}
+
+/// Video modes with a supported pixel format, recorded before boot services are exited.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VideoModeList {
+    pub modes: [VideoMode; MAX_VIDEO_MODES],
+    pub modes_len: usize,
+}
+
+impl VideoModeList {
+    pub fn modes(&self) -> &[VideoMode] {
+        &self.modes[..self.modes_len.min(MAX_VIDEO_MODES)]
+    }
+
+    /// Appends a mode to the list. Returns false, if the list is already full.
+    pub fn push(&mut self, mode: VideoMode) -> bool {
+        if self.modes_len >= MAX_VIDEO_MODES {
+            return false;
+        }
+        self.modes[self.modes_len] = mode;
+        self.modes_len += 1;
+        true
+    }
+
+    /// Returns the mode with the specified resolution, if the graphics device supports it.
+    pub fn find(&self, width: usize, height: usize) -> Option<VideoMode> {
+        self.modes()
+            .iter()
+            .copied()
+            .find(|mode| mode.width == width && mode.height == height)
+    }
+}
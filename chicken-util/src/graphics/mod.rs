@@ -2,6 +2,7 @@ use core::fmt::Debug;
 
 pub mod font;
 pub mod framebuffer;
+pub mod vbe;
 
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Color {
@@ -0,0 +1,95 @@
+use crate::graphics::framebuffer::{FrameBufferMetadata, VideoMode, BPP};
+
+/// Mode supports a linear framebuffer.
+const MODE_ATTRIBUTE_LINEAR_FRAMEBUFFER: u16 = 1 << 7;
+/// Memory model with red, green and blue fields per pixel.
+const MEMORY_MODEL_DIRECT_COLOR: u8 = 6;
+
+/// Mode information returned by `int 0x10, ax=0x4F01` on legacy BIOS systems. Only the fields up to the VBE 3.0 linear framebuffer fields are named.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub struct ModeInfoBlock {
+    pub mode_attributes: u16,
+    pub window_a_attributes: u8,
+    pub window_b_attributes: u8,
+    pub window_granularity: u16,
+    pub window_size: u16,
+    pub window_a_segment: u16,
+    pub window_b_segment: u16,
+    pub window_function: u32,
+    pub bytes_per_scanline: u16,
+    pub width: u16,
+    pub height: u16,
+    pub char_width: u8,
+    pub char_height: u8,
+    pub planes: u8,
+    pub bits_per_pixel: u8,
+    pub banks: u8,
+    pub memory_model: u8,
+    pub bank_size: u8,
+    pub image_pages: u8,
+    pub reserved0: u8,
+    pub red_mask_size: u8,
+    pub red_field_position: u8,
+    pub green_mask_size: u8,
+    pub green_field_position: u8,
+    pub blue_mask_size: u8,
+    pub blue_field_position: u8,
+    pub reserved_mask_size: u8,
+    pub reserved_field_position: u8,
+    pub direct_color_mode_info: u8,
+    /// Physical address of the linear framebuffer.
+    pub framebuffer: u32,
+    pub reserved1: u32,
+    pub reserved2: u16,
+    /// Bytes per scanline in linear framebuffer modes, since VBE 3.0.
+    pub linear_bytes_per_scanline: u16,
+    pub reserved3: [u8; 204],
+}
+
+impl ModeInfoBlock {
+    /// Returns the video mode, if the kernel supports its pixel format: a linear framebuffer of 32 bit pixels with 8 bit color fields in RGB or BGR order.
+    pub fn video_mode(&self) -> Option<VideoMode> {
+        let mode_attributes = self.mode_attributes;
+        if mode_attributes & MODE_ATTRIBUTE_LINEAR_FRAMEBUFFER == 0
+            || self.memory_model != MEMORY_MODEL_DIRECT_COLOR
+            || self.bits_per_pixel as usize != BPP * 8
+            || self.red_mask_size != 8
+            || self.green_mask_size != 8
+            || self.blue_mask_size != 8
+            || self.green_field_position != 8
+        {
+            return None;
+        }
+        let is_rgb = match (self.red_field_position, self.blue_field_position) {
+            (0, 16) => true,
+            (16, 0) => false,
+            _ => return None,
+        };
+
+        // older BIOSes only report the bytes per scanline of the banked mode
+        let bytes_per_scanline = match self.linear_bytes_per_scanline {
+            0 => self.bytes_per_scanline,
+            bytes => bytes,
+        };
+        Some(VideoMode {
+            width: self.width as usize,
+            height: self.height as usize,
+            stride: bytes_per_scanline as usize / BPP,
+            is_rgb,
+        })
+    }
+
+    /// Returns the metadata of the framebuffer of the mode, once it has been set with `int 0x10, ax=0x4F02`.
+    pub fn framebuffer_metadata(&self) -> Option<FrameBufferMetadata> {
+        let mode = self.video_mode()?;
+        Some(FrameBufferMetadata {
+            base: self.framebuffer as u64,
+            size: mode.stride * mode.height * BPP,
+            width: mode.width,
+            height: mode.height,
+            stride: mode.stride,
+            is_rgb: mode.is_rgb,
+        })
+    }
+}
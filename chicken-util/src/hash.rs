@@ -0,0 +1,166 @@
+use core::fmt::{Debug, Display, Formatter};
+
+/// Size of a SHA-256 digest in bytes.
+pub const SHA256_SIZE: usize = 32;
+
+/// Initial hash values, the first 32 bits of the fractional parts of the square roots of the first 8 primes.
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Round constants, the first 32 bits of the fractional parts of the cube roots of the first 64 primes.
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const BLOCK_SIZE: usize = 64;
+
+/// SHA-256 digest of a file, e.g. the kernel image.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub struct Sha256Digest(pub [u8; SHA256_SIZE]);
+
+impl Sha256Digest {
+    /// Parses a digest written as 64 hexadecimal digits.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != SHA256_SIZE * 2 || !hex.is_ascii() {
+            return None;
+        }
+
+        let mut digest = [0; SHA256_SIZE];
+        for (byte, digits) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            // the digits are ascii, so they are valid utf-8
+            *byte = u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()?;
+        }
+        Some(Self(digest))
+    }
+}
+
+impl Display for Sha256Digest {
+    /// Formats the digest as lowercase hexadecimal digits, like `sha256sum`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl Debug for Sha256Digest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Sha256Digest({})", self)
+    }
+}
+
+/// Computes the SHA-256 digest of the given data.
+pub fn sha256(data: &[u8]) -> Sha256Digest {
+    let mut state = INITIAL_STATE;
+
+    let mut blocks = data.chunks_exact(BLOCK_SIZE);
+    for block in blocks.by_ref() {
+        compress(&mut state, block);
+    }
+
+    // pad the remaining data with a single 1 bit, zeros and the length of the data in bits
+    let remainder = blocks.remainder();
+    let mut padding = [0; BLOCK_SIZE * 2];
+    padding[..remainder.len()].copy_from_slice(remainder);
+    padding[remainder.len()] = 0x80;
+    let padding_length = if remainder.len() < BLOCK_SIZE - 8 {
+        BLOCK_SIZE
+    } else {
+        BLOCK_SIZE * 2
+    };
+    let bit_length = (data.len() as u64).wrapping_mul(8);
+    padding[padding_length - 8..padding_length].copy_from_slice(&bit_length.to_be_bytes());
+    for block in padding[..padding_length].chunks_exact(BLOCK_SIZE) {
+        compress(&mut state, block);
+    }
+
+    let mut digest = [0; SHA256_SIZE];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    Sha256Digest(digest)
+}
+
+/// Processes a single 64 byte block.
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut schedule = [0u32; 64];
+    for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for i in 16..64 {
+        let s0 = schedule[i - 15].rotate_right(7)
+            ^ schedule[i - 15].rotate_right(18)
+            ^ (schedule[i - 15] >> 3);
+        let s1 = schedule[i - 2].rotate_right(17)
+            ^ schedule[i - 2].rotate_right(19)
+            ^ (schedule[i - 2] >> 10);
+        schedule[i] = schedule[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(schedule[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for (constant, word) in ROUND_CONSTANTS.iter().zip(schedule) {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let temp1 = h
+            .wrapping_add(s1)
+            .wrapping_add(choice)
+            .wrapping_add(*constant)
+            .wrapping_add(word);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(majority);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+
+    for (value, new) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *value = value.wrapping_add(new);
+    }
+}
+
+/// Result of comparing the kernel image against the hash in the boot config.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum HashVerification {
+    /// No expected hash has been configured.
+    #[default]
+    Unverified,
+    Matched,
+    /// The kernel has been booted anyway, since the boot config only asks for a warning.
+    Mismatched,
+}
+
+impl HashVerification {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashVerification::Unverified => "not verified",
+            HashVerification::Matched => "verified",
+            HashVerification::Mismatched => "MISMATCH",
+        }
+    }
+}
+
+/// Hash of the kernel image measured by the loader before jumping to the kernel.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct KernelMeasurement {
+    pub sha256: Sha256Digest,
+    pub verification: HashVerification,
+}
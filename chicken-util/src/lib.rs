@@ -1,19 +1,57 @@
 #![no_std]
 
+use crate::cmdline::CommandLine;
 use crate::graphics::font::Font;
-use crate::graphics::framebuffer::FrameBufferMetadata;
+use crate::graphics::framebuffer::{FrameBufferMetadata, VideoModeList};
+use crate::hash::KernelMeasurement;
 use crate::memory::{MemoryMap, PhysicalAddress};
+use crate::module::ModuleList;
+use crate::segments::KernelSegments;
+use crate::symbols::KernelSymbols;
+use crate::timing::LoaderTimestamps;
 
 pub mod memory;
+pub mod cmdline;
+pub mod exception;
 pub mod graphics;
+pub mod hash;
+pub mod module;
+pub mod number;
+pub mod segments;
+pub mod symbols;
+pub mod timing;
 
 pub const PAGE_SIZE: usize = 4096;
+/// Size of the memory region reserved for the crash dump of the last kernel panic.
+pub const CRASH_DUMP_SIZE: usize = PAGE_SIZE;
 
 #[derive(Clone, Debug)]
 pub struct BootInfo {
     pub memory_map: MemoryMap,
     pub framebuffer_metadata: FrameBufferMetadata,
+    /// Video modes the framebuffer can be switched to.
+    pub video_modes: VideoModeList,
     pub font: Font,
     pub pmm_address: PhysicalAddress,
     pub rsdp: u64,
+    /// Physical address of the SMBIOS entry point, 0 if the firmware does not provide one.
+    pub smbios: PhysicalAddress,
+    /// Physical address of the crash dump region, 0 if it could not be reserved.
+    pub crash_dump: PhysicalAddress,
+    /// Boot phase timestamps of the loader.
+    pub loader_timestamps: LoaderTimestamps,
+    /// Additional files loaded as listed in the boot config.
+    pub modules: ModuleList,
+    /// Hash of the kernel file measured by the loader.
+    pub kernel_measurement: KernelMeasurement,
+    /// Minutes without input after which the screen is blanked, 0 if it is never blanked.
+    pub screen_blank_minutes: u64,
+    /// Command line of the boot entry selected in the loader.
+    pub command_line: CommandLine,
+    /// Functions of the kernel image, empty if the kernel file has no symbol table.
+    pub kernel_symbols: KernelSymbols,
+    /// Loadable segments of the kernel image with their permissions, empty if the loader has not passed them on.
+    pub kernel_segments: KernelSegments,
+    /// Whether the kernel prints the memory map it has received to the serial console, like the loader did before handing it over.
+    pub memory_map_dump: bool,
 }
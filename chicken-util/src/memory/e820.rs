@@ -0,0 +1,179 @@
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
+
+use crate::{
+    memory::{MemoryDescriptor, MemoryMap, MemoryType, PhysicalAddress},
+    PAGE_SIZE,
+};
+
+/// Usable RAM.
+pub const E820_USABLE: u32 = 1;
+pub const E820_RESERVED: u32 = 2;
+/// ACPI tables, which can be reclaimed once they have been parsed.
+pub const E820_ACPI_RECLAIMABLE: u32 = 3;
+pub const E820_ACPI_NVS: u32 = 4;
+pub const E820_BAD_MEMORY: u32 = 5;
+
+/// Entry of the memory map returned by `int 0x15, eax=0xE820` on legacy BIOS systems. Unlike the UEFI memory map, entries are not page aligned and may overlap.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct E820Entry {
+    pub base: u64,
+    pub length: u64,
+    pub r#type: u32,
+    /// ACPI 3.0 extended attributes, only present if the BIOS returned 24 byte entries.
+    pub extended_attributes: u32,
+}
+
+impl E820Entry {
+    /// Whether the entry should be ignored, as indicated by bit 0 of the extended attributes being cleared. Entries without extended attributes are stored with the bit set.
+    pub fn is_ignored(&self) -> bool {
+        self.extended_attributes & 1 == 0
+    }
+
+    /// Returns the memory type of the range in the chicken memory map.
+    pub fn memory_type(&self) -> MemoryType {
+        match self.r#type {
+            E820_USABLE => MemoryType::Available,
+            E820_ACPI_RECLAIMABLE | E820_ACPI_NVS => MemoryType::AcpiData,
+            _ => MemoryType::Reserved,
+        }
+    }
+
+    /// Returns the page aligned range of the entry. Usable ranges are rounded inward, any other range outward, so a partially usable page is never handed out.
+    fn page_range(&self) -> (PhysicalAddress, PhysicalAddress) {
+        let page_size = PAGE_SIZE as u64;
+        let start = self.base;
+        let end = self.base.saturating_add(self.length);
+        if self.r#type == E820_USABLE {
+            (start.next_multiple_of(page_size), end & !(page_size - 1))
+        } else {
+            (start & !(page_size - 1), end.next_multiple_of(page_size))
+        }
+    }
+}
+
+/// Converts the E820 memory map into the chicken memory map, the same way the UEFI loader converts its memory map. The descriptors are written to the given buffer and sorted by address.
+///
+/// `loaded` are the regions the BIOS stage has allocated from usable memory, e.g. the kernel code, the kernel stack and the handoff region, with their memory type. They are cut out of the usable ranges. Reserved ranges are cut out as well, since BIOSes may report them overlapping usable ones.
+pub fn convert(
+    entries: &[E820Entry],
+    loaded: &[MemoryDescriptor],
+    descriptors: &'static mut [MemoryDescriptor],
+) -> Result<MemoryMap, E820Error> {
+    let mut descriptors_len = 0;
+    let mut push = |phys_start: PhysicalAddress, phys_end: PhysicalAddress, r#type| {
+        if phys_end <= phys_start {
+            return Ok(());
+        }
+        let descriptor = descriptors
+            .get_mut(descriptors_len)
+            .ok_or(E820Error::BufferTooSmall(entries.len() + loaded.len()))?;
+        *descriptor = MemoryDescriptor {
+            phys_start,
+            phys_end,
+            num_pages: (phys_end - phys_start) / PAGE_SIZE as u64,
+            // the first page is never used, like on uefi systems
+            r#type: if phys_start < PAGE_SIZE as u64 {
+                MemoryType::Reserved
+            } else {
+                r#type
+            },
+        };
+        descriptors_len += 1;
+        Ok(())
+    };
+
+    let entries = || entries.iter().filter(|entry| !entry.is_ignored());
+    // ranges that are not usable, although they may be covered by a usable entry
+    let holes = || {
+        entries()
+            .filter(|entry| entry.r#type != E820_USABLE)
+            .map(E820Entry::page_range)
+            .chain(loaded.iter().map(|desc| (desc.phys_start, desc.phys_end)))
+    };
+
+    for entry in entries() {
+        let (start, end) = entry.page_range();
+        if entry.r#type != E820_USABLE {
+            push(start, end, entry.memory_type())?;
+            continue;
+        }
+        // split the usable range at the holes within it, starting with the lowest one
+        let mut start = start;
+        while start < end {
+            let hole = holes()
+                .filter(|(hole_start, hole_end)| *hole_start < end && *hole_end > start)
+                .min_by_key(|(hole_start, _)| *hole_start);
+            match hole {
+                Some((hole_start, hole_end)) => {
+                    push(start, hole_start, MemoryType::Available)?;
+                    start = hole_end;
+                }
+                None => {
+                    push(start, end, MemoryType::Available)?;
+                    break;
+                }
+            }
+        }
+    }
+    for desc in loaded {
+        push(desc.phys_start, desc.phys_end, desc.r#type)?;
+    }
+
+    let descriptors = &mut descriptors[..descriptors_len];
+    descriptors.sort_unstable_by_key(|desc| desc.phys_start);
+    let available = || {
+        descriptors
+            .iter()
+            .filter(|desc| desc.r#type == MemoryType::Available)
+    };
+    if available().next().is_none() {
+        return Err(E820Error::NoUsableMemory);
+    }
+
+    Ok(MemoryMap {
+        first_addr: descriptors.first().map_or(0, |desc| desc.phys_start),
+        last_addr: descriptors
+            .iter()
+            .map(|desc| desc.phys_end)
+            .max()
+            .unwrap_or(0),
+        first_available_addr: available().map(|desc| desc.phys_start).min().unwrap_or(0),
+        last_available_addr: available().map(|desc| desc.phys_end).max().unwrap_or(0),
+        descriptors: descriptors.as_mut_ptr(),
+        descriptors_len: descriptors_len as u64,
+    })
+}
+
+#[derive(Copy, Clone)]
+pub enum E820Error {
+    /// The buffer cannot hold the descriptors of the specified amount of entries and loaded regions.
+    BufferTooSmall(usize),
+    NoUsableMemory,
+}
+
+impl Debug for E820Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            E820Error::BufferTooSmall(count) => write!(
+                f,
+                "E820 Error: Memory map buffer is too small for {} entries.",
+                count
+            ),
+            E820Error::NoUsableMemory => {
+                write!(f, "E820 Error: Memory map does not contain usable memory.")
+            }
+        }
+    }
+}
+
+impl Display for E820Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for E820Error {}
@@ -1,6 +1,7 @@
 use core::fmt::{Debug, Display, Formatter};
 use core::slice;
 
+pub mod e820;
 pub mod paging;
 pub mod pmm;
 pub type VirtualAddress = u64;
@@ -29,6 +30,21 @@ impl MemoryMap {
     }
 }
 
+impl Display for MemoryMap {
+    /// Formats the bounds of the memory map, but not its descriptors.
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Memory Map {{ descriptors: {}, first_addr: {:#x}, last_addr: {:#x}, first_available_addr: {:#x}, last_available_addr: {:#x} }}",
+            self.descriptors_len,
+            self.first_addr,
+            self.last_addr,
+            self.first_available_addr,
+            self.last_available_addr
+        )
+    }
+}
+
 
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -68,7 +84,7 @@ pub enum MemoryType {
     KernelCode = 2,
     /// kernel stack
     KernelStack = 3,
-    /// boot info, memory map
+    /// handoff region of the loader: boot info, memory map, font and modules
     KernelData = 4,
     /// acpi tables
     AcpiData,
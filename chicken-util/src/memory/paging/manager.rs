@@ -1,8 +1,6 @@
-use core::arch::asm;
-
 use crate::memory::{
-    paging::{index::PageMapIndexer, PageEntryFlags, PageTable},
-    pmm::{PageFrameAllocator, PageFrameAllocatorError},
+    paging::{index::PageMapIndexer, PageEntry, PageEntryFlags, PageTable, HUGE_PAGE_SIZE},
+    pmm::{audit::FramePurpose, PageFrameAllocator, PageFrameAllocatorError},
     PhysicalAddress, VirtualAddress,
 };
 
@@ -53,6 +51,10 @@ impl<'a> PageTableManager<'a> {
         let page_map_level3 = self.get_next_table(page_map_level4, indexer.pdp_i())?;
         // Map Level 2
         let page_map_level2 = self.get_next_table(page_map_level3, indexer.pd_i())?;
+        let directory_entry = unsafe { &*page_map_level2 }.entries[indexer.pt_i() as usize];
+        if is_huge_page(&directory_entry) {
+            return Some(directory_entry.address() + virtual_address % HUGE_PAGE_SIZE as u64);
+        }
         // Map Level 1
         let page_map_level1 = self.get_next_table(page_map_level2, indexer.pt_i())?;
 
@@ -60,6 +62,20 @@ impl<'a> PageTableManager<'a> {
         Some(page_entry.address())
     }
 
+    /// Returns the page table entry of the provided virtual address. May return None if the page tables of the address do not exist or it is part of a huge page.
+    pub fn page_entry_mut(&mut self, virtual_address: VirtualAddress) -> Option<&mut PageEntry> {
+        let indexer = PageMapIndexer::new(virtual_address);
+        let page_map_level4 = self.pml4_virtual();
+        // Map Level 3
+        let page_map_level3 = self.get_next_table(page_map_level4, indexer.pdp_i())?;
+        // Map Level 2
+        let page_map_level2 = self.get_next_table(page_map_level3, indexer.pd_i())?;
+        // Map Level 1
+        let page_map_level1 = self.get_next_table(page_map_level2, indexer.pt_i())?;
+
+        Some(&mut unsafe { &mut *page_map_level1 }.entries[indexer.p_i() as usize])
+    }
+
     /// Used to switch to a different page table mapping.
     ///
     /// # Safety
@@ -94,11 +110,14 @@ impl<'a> PageTableManager<'a> {
         let indexer = PageMapIndexer::new(virtual_memory);
         let page_map_level4 = self.pml4_virtual();
         // Map Level 3
-        let page_map_level3 = self.get_or_create_next_table(page_map_level4, indexer.pdp_i())?;
+        let page_map_level3 =
+            self.get_or_create_next_table(page_map_level4, indexer.pdp_i(), flags)?;
         // Map Level 2
-        let page_map_level2 = self.get_or_create_next_table(page_map_level3, indexer.pd_i())?;
+        let page_map_level2 =
+            self.get_or_create_next_table(page_map_level3, indexer.pd_i(), flags)?;
         // Map Level 1
-        let page_map_level1 = self.get_or_create_next_table(page_map_level2, indexer.pt_i())?;
+        let page_map_level1 =
+            self.get_or_create_next_table(page_map_level2, indexer.pt_i(), flags)?;
 
         let page_entry = &mut unsafe { &mut *page_map_level1 }.entries[indexer.p_i() as usize];
 
@@ -108,7 +127,44 @@ impl<'a> PageTableManager<'a> {
         Ok(())
     }
 
+    /// Maps the given 2 MiB aligned virtual address to a 2 MiB aligned physical address with a single huge page, which needs no page table of its own. Fails, if part of the range is mapped by a page table already.
+    pub fn map_huge_2mb(
+        &mut self,
+        virtual_memory: VirtualAddress,
+        physical_memory: PhysicalAddress,
+        flags: PageEntryFlags,
+    ) -> Result<(), PageFrameAllocatorError> {
+        for address in [virtual_memory, physical_memory] {
+            if !address.is_multiple_of(HUGE_PAGE_SIZE as u64) {
+                return Err(PageFrameAllocatorError::MisalignedBlock(address));
+            }
+        }
+        let indexer = PageMapIndexer::new(virtual_memory);
+        let page_map_level4 = self.pml4_virtual();
+        // Map Level 3
+        let page_map_level3 =
+            self.get_or_create_next_table(page_map_level4, indexer.pdp_i(), flags)?;
+        // Map Level 2
+        let page_map_level2 =
+            self.get_or_create_next_table(page_map_level3, indexer.pd_i(), flags)?;
+
+        let directory_entry =
+            &mut unsafe { &mut *page_map_level2 }.entries[indexer.pt_i() as usize];
+        if directory_entry.flags().contains(PageEntryFlags::PRESENT)
+            && !is_huge_page(directory_entry)
+        {
+            return Err(PageFrameAllocatorError::MappingConflict);
+        }
+
+        directory_entry.set_address(physical_memory);
+        directory_entry.set_flags(flags | PageEntryFlags::PAT_PAGE_SIZE);
+
+        Ok(())
+    }
+
     /// Removes the mapping for given virtual address. Returns the physical address the virtual address previously pointed to.
+    ///
+    /// The translation may still be cached in the TLB afterward, so the caller has to invalidate it, which allows invalidating many unmapped pages at once.
     pub fn unmap(
         &mut self,
         virtual_memory: VirtualAddress,
@@ -116,11 +172,23 @@ impl<'a> PageTableManager<'a> {
         let indexer = PageMapIndexer::new(virtual_memory);
         let page_map_level4 = self.pml4_virtual();
         // Map Level 3
-        let page_map_level3 = self.get_or_create_next_table(page_map_level4, indexer.pdp_i())?;
+        let page_map_level3 = self.get_or_create_next_table(
+            page_map_level4,
+            indexer.pdp_i(),
+            PageEntryFlags::empty(),
+        )?;
         // Map Level 2
-        let page_map_level2 = self.get_or_create_next_table(page_map_level3, indexer.pd_i())?;
+        let page_map_level2 = self.get_or_create_next_table(
+            page_map_level3,
+            indexer.pd_i(),
+            PageEntryFlags::empty(),
+        )?;
         // Map Level 1
-        let page_map_level1 = self.get_or_create_next_table(page_map_level2, indexer.pt_i())?;
+        let page_map_level1 = self.get_or_create_next_table(
+            page_map_level2,
+            indexer.pt_i(),
+            PageEntryFlags::empty(),
+        )?;
 
         let page_entry = &mut unsafe { &mut *page_map_level1 }.entries[indexer.p_i() as usize];
         let physical_address = page_entry.address();
@@ -128,41 +196,39 @@ impl<'a> PageTableManager<'a> {
         page_entry.set_address(0);
         page_entry.set_flags(PageEntryFlags::empty());
 
-        unsafe { self.invalidate_tlb_entry(physical_address) };
-
         Ok(physical_address)
     }
 
-    /// Used to update cache when unmapping addresses
-    ///
-    /// # Safety
-    ///
-    /// The caller has to ensure that the address is the appropriate one and no longer mapped.
-    pub unsafe fn invalidate_tlb_entry(&self, virtual_address: VirtualAddress) {
-        asm!("invlpg [{}]", in(reg) virtual_address as *const u8);
-    }
-
+    /// Gets pointer to next table. Returns None, if it does not exist or the entry maps a huge page instead.
     fn get_next_table(&self, current_table: *mut PageTable, index: u64) -> Option<*mut PageTable> {
         let entry = &mut unsafe { &mut *current_table }.entries[index as usize];
-        if entry.flags().contains(PageEntryFlags::PRESENT) {
+        if entry.flags().contains(PageEntryFlags::PRESENT) && !is_huge_page(entry) {
             Some((entry.address() + self.offset) as *mut PageTable)
         } else {
             None
         }
     }
 
-    /// Gets pointer to next table or creates it if it does not exist yet.
+    /// Gets pointer to next table or creates it if it does not exist yet. Fails, if the entry maps a huge page instead, since its pages can not be mapped individually. Tables of user pages, as given by the flags of the mapping, are made accessible from user mode.
     fn get_or_create_next_table(
         &mut self,
         current_table: *mut PageTable,
         index: u64,
+        flags: PageEntryFlags,
     ) -> Result<*mut PageTable, PageFrameAllocatorError> {
         let entry = &mut unsafe { &mut *current_table }.entries[index as usize];
+        // the permissions of all levels apply, so the pages themselves still decide about user access
+        let user = flags & PageEntryFlags::USER_SUPER;
 
-        if entry.flags().contains(PageEntryFlags::PRESENT) {
+        if is_huge_page(entry) {
+            Err(PageFrameAllocatorError::MappingConflict)
+        } else if entry.flags().contains(PageEntryFlags::PRESENT) {
+            entry.set_flags(entry.flags() | user);
             Ok((entry.address() + self.offset) as *mut PageTable)
         } else {
-            let new_page = self.page_frame_allocator.request_page()?;
+            let new_page = self
+                .page_frame_allocator
+                .request_page_for(FramePurpose::PageTable)?;
             let new_table = (new_page + self.offset) as *mut PageTable;
             unsafe {
                 // Zero out the new table
@@ -170,9 +236,16 @@ impl<'a> PageTableManager<'a> {
             }
 
             entry.set_address(new_page);
-            entry.set_flags(PageEntryFlags::PRESENT | PageEntryFlags::READ_WRITE);
+            entry.set_flags(PageEntryFlags::PRESENT | PageEntryFlags::READ_WRITE | user);
 
             Ok(new_table)
         }
     }
 }
+
+/// Whether the page directory (pointer) entry maps a huge page instead of pointing to the next table.
+fn is_huge_page(entry: &PageEntry) -> bool {
+    entry
+        .flags()
+        .contains(PageEntryFlags::PRESENT | PageEntryFlags::PAT_PAGE_SIZE)
+}
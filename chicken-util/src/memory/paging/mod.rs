@@ -1,10 +1,49 @@
+use core::arch::{
+    asm,
+    x86_64::{__cpuid, __cpuid_count},
+};
+
 use bitflags::bitflags;
 
+use crate::memory::VirtualAddress;
+
 pub mod index;
 pub mod manager;
 
 pub const KERNEL_MAPPING_OFFSET: u64 = 0xFFFF_FFFF_8000_0000;
 pub const KERNEL_STACK_MAPPING_OFFSET: u64 = 0xFFFF_FFFF_6000_0000;
+/// Size of a huge page mapped by a page directory entry.
+pub const HUGE_PAGE_SIZE: usize = 0x20_0000; // 2 MiB
+
+// the page tables only have 4 levels, so the fixed virtual bases must fit into 48 bits
+const _: () =
+    assert!(is_canonical(KERNEL_MAPPING_OFFSET) && is_canonical(KERNEL_STACK_MAPPING_OFFSET));
+
+/// Amount of bits of a virtual address translated by 4-level paging.
+pub const VIRTUAL_ADDRESS_BITS: u32 = 48;
+/// Bit of the CR4 register that enables 5-level paging.
+const CR4_LA57: u64 = 1 << 12;
+
+/// Whether the address is canonical under 4-level paging, i.e. whether bits 63 to 47 are all equal.
+pub const fn is_canonical(address: VirtualAddress) -> bool {
+    let upper_bits = address >> (VIRTUAL_ADDRESS_BITS - 1);
+    upper_bits == 0 || upper_bits == (1 << (u64::BITS - VIRTUAL_ADDRESS_BITS + 1)) - 1
+}
+
+/// Whether the cpu supports 5-level paging (LA57), which extends virtual addresses to 57 bits.
+pub fn five_level_paging_supported() -> bool {
+    // cpuid is only declared safe by newer toolchains
+    #[allow(unused_unsafe)]
+    let supported = unsafe { __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ecx & (1 << 16) != 0 };
+    supported
+}
+
+/// Whether 5-level paging is enabled, i.e. whether the root page table is a PML5 instead of a PML4. It can only be switched while paging is disabled, which is impossible in long mode.
+pub fn five_level_paging_enabled() -> bool {
+    let cr4: u64;
+    unsafe { asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags)) };
+    cr4 & CR4_LA57 != 0
+}
 
 bitflags! {
     #[derive(Copy, Clone, Debug)]
@@ -34,6 +73,8 @@ bitflags! {
         /// For Page Table Entry: Global: Tells the processor not to invalidate the TLB entry corresponding to the page upon a MOV to CR3 instruction.
         const GLOBAL_AVL        = 1 << 8;
         const AVAILABLE_MASK = 0b111 << 9;
+        /// Available bit used by the kernel: The page frame is shared with another address space after a fork and is copied on the first write. The entry is read-only until then.
+        const COPY_ON_WRITE = 1 << 9;
         /// For Page Directory (Pointer) Entry / PML4: Available for use
         ///
         /// For Page Table Entry: Protection Key: The protection key is a 4-bit corresponding to each virtual address that is used to control user-mode and supervisor-mode memory accesses.
@@ -89,6 +130,11 @@ impl PageEntry {
     pub fn flags(&self) -> PageEntryFlags {
         PageEntryFlags::from_bits_truncate(self.0 & 0xfff) // Mask to get only the lower 12 bits for flags
     }
+
+    /// Whether instructions may not be fetched from the page. Not part of [`PageEntry::flags`], which only covers the lower 12 bits.
+    pub fn execute_disabled(&self) -> bool {
+        self.0 & PageEntryFlags::EXECUTE_DISABLE.bits() != 0
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -0,0 +1,51 @@
+/// Purpose of a page frame allocation. Used to track the memory usage during boot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FramePurpose {
+    /// Allocations that have not been tagged with a purpose.
+    Other,
+    PageTable,
+    Heap,
+    /// Memory objects backed by the virtual memory manager.
+    Vmm,
+}
+
+impl FramePurpose {
+    pub const COUNT: usize = 4;
+    pub const ALL: [FramePurpose; FramePurpose::COUNT] = [
+        FramePurpose::PageTable,
+        FramePurpose::Heap,
+        FramePurpose::Vmm,
+        FramePurpose::Other,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FramePurpose::Other => "other",
+            FramePurpose::PageTable => "page tables",
+            FramePurpose::Heap => "heap",
+            FramePurpose::Vmm => "vmm",
+        }
+    }
+}
+
+/// Amount of page frames that have been allocated for each purpose. Frames that have been freed again are still counted.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameAudit {
+    frames: [u64; FramePurpose::COUNT],
+}
+
+impl FrameAudit {
+    pub(super) fn record(&mut self, purpose: FramePurpose) {
+        self.frames[purpose as usize] += 1;
+    }
+
+    /// Returns the amount of frames allocated for the specified purpose.
+    pub fn frames(&self, purpose: FramePurpose) -> u64 {
+        self.frames[purpose as usize]
+    }
+
+    /// Returns the amount of frames allocated for any purpose.
+    pub fn total(&self) -> u64 {
+        self.frames.iter().sum()
+    }
+}
@@ -38,6 +38,14 @@ impl<'a> BitMap<'a> {
         Ok(())
     }
 
+    /// Returns the amount of bits that are set
+    pub fn count_set(&self) -> u64 {
+        self.buffer
+            .iter()
+            .map(|byte| u64::from(byte.count_ones()))
+            .sum()
+    }
+
     pub fn pages(&self) -> usize {
         (size_of::<BitMap>() + PAGE_SIZE - 1) / PAGE_SIZE
     }
@@ -0,0 +1,215 @@
+use crate::{
+    memory::{
+        pmm::{audit::FramePurpose, PageFrameAllocator, PageFrameAllocatorError},
+        PhysicalAddress,
+    },
+    PAGE_SIZE,
+};
+
+/// Largest order of a contiguous block, i.e. 1024 frames (4 MiB). Huge pages of 2 MiB are blocks of order 9.
+pub const MAX_ORDER: usize = 10;
+/// Order of frames, that are not the first frame of a free block.
+const NO_BLOCK: u8 = u8::MAX;
+/// Marks the end of a free list.
+const NO_FRAME: u32 = u32::MAX;
+
+/// Neighbours of a free block in the free list of its order. Indexed by the first frame of the block.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FreeLink {
+    previous: u32,
+    next: u32,
+}
+
+/// Free lists of the buddy allocator, holding blocks of `2^order` frames aligned to their size. Every free frame of the available memory apart from the first frame belongs to exactly one block, so the lists always agree with the bit map. A freed block is merged with its buddy, i.e. the other half of the block of the next order, as soon as both are free.
+#[derive(Debug)]
+pub struct FreeLists<'a> {
+    heads: [u32; MAX_ORDER + 1],
+    /// Stored behind the reference counts of the bit map.
+    pub(super) links: &'a mut [FreeLink],
+    /// Order of the free block starting at each frame, or [`NO_BLOCK`]. Stored behind the links.
+    pub(super) orders: &'a mut [u8],
+}
+
+impl<'a> FreeLists<'a> {
+    /// Creates empty free lists using the buffers, which hold an entry for each frame.
+    pub(super) fn new(links: &'a mut [FreeLink], orders: &'a mut [u8]) -> Self {
+        orders.fill(NO_BLOCK);
+        Self {
+            heads: [NO_FRAME; MAX_ORDER + 1],
+            links,
+            orders,
+        }
+    }
+
+    /// Adds the free frames from the first frame up to the end frame as the largest aligned blocks they form.
+    pub(super) fn insert_range(&mut self, first: u64, end: u64) {
+        // the first frame is never handed out
+        let mut frame = first.max(1);
+        while frame < end {
+            let mut order = (frame.trailing_zeros() as usize).min(MAX_ORDER);
+            while frame + (1 << order) > end {
+                order -= 1;
+            }
+            self.insert(frame as u32, order);
+            frame += 1 << order;
+        }
+    }
+
+    /// Adds the free block and merges it with its buddy for as long as the buddy is free as well.
+    pub(super) fn insert(&mut self, mut frame: u32, mut order: usize) {
+        // the bit map may have a few bits more than there are frames
+        if frame == 0 || frame as usize >= self.orders.len() {
+            return;
+        }
+        while order < MAX_ORDER {
+            let buddy = frame ^ (1 << order);
+            if self.orders.get(buddy as usize) != Some(&(order as u8)) {
+                break;
+            }
+            self.remove(buddy, order);
+            frame = frame.min(buddy);
+            order += 1;
+        }
+        self.push(frame, order);
+    }
+
+    /// Removes a block of the order from the lists, splitting a larger block, if there is no free block of the order. Returns its first frame.
+    pub(super) fn take_block(&mut self, order: usize) -> Option<u32> {
+        let mut block_order = (order..=MAX_ORDER).find(|order| self.heads[*order] != NO_FRAME)?;
+        let frame = self.heads[block_order];
+        self.remove(frame, block_order);
+        // the upper halves stay free
+        while block_order > order {
+            block_order -= 1;
+            self.push(frame + (1 << block_order), block_order);
+        }
+        Some(frame)
+    }
+
+    /// Removes the single frame from the block containing it, e.g. when it is allocated by its address. The rest of the block stays free in smaller blocks. Returns whether the frame was part of a free block.
+    pub(super) fn take_frame(&mut self, frame: u32) -> bool {
+        let Some((mut block, mut order)) = (0..=MAX_ORDER).find_map(|order| {
+            let block = frame & !((1 << order) - 1);
+            (self.orders.get(block as usize) == Some(&(order as u8))).then_some((block, order))
+        }) else {
+            return false;
+        };
+        self.remove(block, order);
+        while order > 0 {
+            order -= 1;
+            let half = 1 << order;
+            // the half without the frame stays free
+            if frame < block + half {
+                self.push(block + half, order);
+            } else {
+                self.push(block, order);
+                block += half;
+            }
+        }
+        true
+    }
+
+    /// Moves the buffers of the lists by the offset, e.g. when the bit map is moved. The offset wraps around to move them down.
+    ///
+    /// # Safety
+    /// The caller has to ensure that the moved buffers are mapped and hold the same data.
+    pub(super) unsafe fn relocate(&mut self, offset: u64) {
+        let links = (self.links.as_mut_ptr() as u64).wrapping_add(offset);
+        self.links = core::slice::from_raw_parts_mut(links as *mut FreeLink, self.links.len());
+        let orders = (self.orders.as_mut_ptr() as u64).wrapping_add(offset);
+        self.orders = core::slice::from_raw_parts_mut(orders as *mut u8, self.orders.len());
+    }
+
+    fn push(&mut self, frame: u32, order: usize) {
+        let next = self.heads[order];
+        if next != NO_FRAME {
+            self.links[next as usize].previous = frame;
+        }
+        self.links[frame as usize] = FreeLink {
+            previous: NO_FRAME,
+            next,
+        };
+        self.orders[frame as usize] = order as u8;
+        self.heads[order] = frame;
+    }
+
+    fn remove(&mut self, frame: u32, order: usize) {
+        let FreeLink { previous, next } = self.links[frame as usize];
+        if previous == NO_FRAME {
+            self.heads[order] = next;
+        } else {
+            self.links[previous as usize].next = next;
+        }
+        if next != NO_FRAME {
+            self.links[next as usize].previous = previous;
+        }
+        self.orders[frame as usize] = NO_BLOCK;
+    }
+}
+
+/// Contiguous allocations are blocks of `2^order` frames, aligned to their own size, taken from the free lists of the buddy allocator. Single frames are still allocated by their address in the bit map, which splits the block containing them.
+impl PageFrameAllocator<'_> {
+    /// Returns the physical address of `2^order` contiguous free frames, aligned to their size.
+    pub fn request_contiguous(
+        &mut self,
+        order: usize,
+    ) -> Result<PhysicalAddress, PageFrameAllocatorError> {
+        self.request_contiguous_for(order, FramePurpose::Other)
+    }
+
+    /// Returns the physical address of `2^order` contiguous free frames, aligned to their size, and records them with the specified purpose.
+    pub fn request_contiguous_for(
+        &mut self,
+        order: usize,
+        purpose: FramePurpose,
+    ) -> Result<PhysicalAddress, PageFrameAllocatorError> {
+        if order > MAX_ORDER {
+            return Err(PageFrameAllocatorError::InvalidOrder(order));
+        }
+        let first = self
+            .free_lists
+            .take_block(order)
+            .ok_or(PageFrameAllocatorError::NoMoreFreePages)?;
+        for index in u64::from(first)..u64::from(first) + (1 << order) {
+            self.mark_allocated(index)?;
+            self.audit.record(purpose);
+        }
+        Ok(u64::from(first) * PAGE_SIZE as u64)
+    }
+
+    /// Frees a block returned by [`PageFrameAllocator::request_contiguous`] of the same order.
+    pub fn free_contiguous(
+        &mut self,
+        address: PhysicalAddress,
+        order: usize,
+    ) -> Result<(), PageFrameAllocatorError> {
+        if order > MAX_ORDER {
+            return Err(PageFrameAllocatorError::InvalidOrder(order));
+        }
+        let page_count = 1 << order;
+        if !address.is_multiple_of((PAGE_SIZE * page_count) as u64) {
+            return Err(PageFrameAllocatorError::MisalignedBlock(address));
+        }
+        let first = address / PAGE_SIZE as u64;
+        // the first frame is never handed out, and a partially free block would be added to the free lists twice
+        if first == 0 || !self.is_allocated(first, page_count)? {
+            return Err(PageFrameAllocatorError::FrameNotAllocated);
+        }
+        for index in first..first + page_count as u64 {
+            self.mark_free(index)?;
+        }
+        self.free_lists.insert(first as u32, order);
+        Ok(())
+    }
+
+    /// Whether all of the frames starting at the frame index are allocated.
+    fn is_allocated(&self, first: u64, page_count: usize) -> Result<bool, PageFrameAllocatorError> {
+        for index in first..first + page_count as u64 {
+            if !self.bit_map.get(index)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
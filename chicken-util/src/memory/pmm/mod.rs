@@ -8,22 +8,29 @@ use core::{
 use crate::{
     memory::{
         MemoryDescriptor, MemoryMap, MemoryType, paging::manager::PageTableManager,
-        PhysicalAddress, pmm::bit_map::BitMap,
+        PhysicalAddress, pmm::{audit::{FrameAudit, FramePurpose}, bit_map::BitMap, buddy::{FreeLink, FreeLists}},
     },
     PAGE_SIZE,
 };
 
+pub mod audit;
 pub mod bit_map;
+pub mod buddy;
 
 #[derive(Debug)]
 pub struct PageFrameAllocator<'a> {
     memory_map: MemoryMap,
     bit_map: BitMap<'a>,
+    /// References to each frame in addition to the first one, e.g. of address spaces sharing it after a fork. Stored behind the bit map.
+    references: &'a mut [u16],
+    /// Free blocks of the buddy allocator, stored behind the reference counts.
+    free_lists: FreeLists<'a>,
     current_descriptor_index: usize,
     current_address: PhysicalAddress,
     free_memory: u64,
     used_memory: u64,
     reserved_memory: u64,
+    audit: FrameAudit,
 }
 
 impl<'a> PageFrameAllocator<'a> {
@@ -41,6 +48,16 @@ impl<'a> PageFrameAllocator<'a> {
         // total memory size in bytes => / PAGE_SIZE is the amount of pages. In the bitmap each page is one bit => /8 gives out the amount of bits
         let total_pages = (memory_map.last_addr as usize + PAGE_SIZE - 1) / PAGE_SIZE;
         let bit_map_size = (total_pages + 7) / 8;
+        // the reference counts are stored behind the bit map, followed by the links and orders of the free lists, each aligned to their size
+        let references_offset = bit_map_size.next_multiple_of(size_of::<u16>());
+        let links_offset = (references_offset + total_pages * size_of::<u16>())
+            .next_multiple_of(align_of::<FreeLink>());
+        let orders_offset = links_offset + total_pages * size_of::<FreeLink>();
+        let metadata_size = orders_offset + total_pages;
+        // the free lists link frames by their 32-bit index
+        if metadata_size as u64 > largest_memory_area.size() || total_pages >= u32::MAX as usize {
+            return Err(PageFrameAllocatorError::InvalidMemoryMap);
+        }
 
         let bit_map_buffer = unsafe {
             slice_from_raw_parts_mut(largest_memory_area_ptr, bit_map_size)
@@ -54,19 +71,59 @@ impl<'a> PageFrameAllocator<'a> {
         let bit_map = BitMap {
             buffer: bit_map_buffer,
         };
+        let references = unsafe {
+            slice_from_raw_parts_mut(
+                largest_memory_area_ptr.add(references_offset) as *mut u16,
+                total_pages,
+            )
+            .as_mut()
+            .ok_or(PageFrameAllocatorError::InvalidMemoryMap)?
+        };
+        references.fill(0);
+        let free_lists = unsafe {
+            FreeLists::new(
+                slice_from_raw_parts_mut(
+                    largest_memory_area_ptr.add(links_offset) as *mut FreeLink,
+                    total_pages,
+                )
+                .as_mut()
+                .ok_or(PageFrameAllocatorError::InvalidMemoryMap)?,
+                slice_from_raw_parts_mut(largest_memory_area_ptr.add(orders_offset), total_pages)
+                    .as_mut()
+                    .ok_or(PageFrameAllocatorError::InvalidMemoryMap)?,
+            )
+        };
         let free_memory = total_available_memory(&memory_map);
 
         let mut instance = Self {
             memory_map,
             bit_map,
+            references,
+            free_lists,
             current_descriptor_index: 0,
             current_address: 0,
             free_memory,
             used_memory: 0,
             reserved_memory: 0,
+            audit: FrameAudit::default(),
         };
-        // reserve frames for bitmap
-        instance.reserve_frames(largest_memory_area_ptr as u64, instance.bit_map.pages())?;
+        // all available frames start out free, reserving frames takes them out of the free lists again
+        for desc in memory_map
+            .descriptors()
+            .iter()
+            .filter(|desc| desc.r#type == MemoryType::Available)
+        {
+            instance.free_lists.insert_range(
+                desc.phys_start.div_ceil(PAGE_SIZE as u64),
+                desc.phys_end / PAGE_SIZE as u64,
+            );
+        }
+
+        // reserve frames for bitmap, reference counts and free lists
+        instance.reserve_frames(
+            largest_memory_area_ptr as u64,
+            metadata_size.div_ceil(PAGE_SIZE),
+        )?;
 
         // reserve reserved memory descriptors (including kernel code, data, stack)
         let mmap = instance.memory_map;
@@ -95,7 +152,12 @@ impl<'a> PageFrameAllocator<'a> {
         self.reserved_memory
     }
 
-    /// Used when switching to a new paging setup. Updates page frame allocator's memory map descriptors address and bit map buffer address.
+    /// Returns the amount of frames marked as used or reserved in the bit map. Walks the entire bit map instead of relying on the counters, so both can be compared.
+    pub fn allocated_frames(&self) -> u64 {
+        self.bit_map.count_set()
+    }
+
+    /// Used when switching to a new paging setup. Updates page frame allocator's memory map descriptors address and bit map buffer address. The reference counts and free lists are moved along with the bit map.
     ///
     /// # Safety
     /// The caller has to ensure that the addresses are valid and mapped.
@@ -104,7 +166,18 @@ impl<'a> PageFrameAllocator<'a> {
         bit_map_buffer_address: u64,
         memory_map_descriptors_address: u64,
     ) {
-        // update bit map buffer address
+        // update bit map buffer, reference counts and free lists address
+        self.free_lists
+            .relocate(bit_map_buffer_address.wrapping_sub(self.bit_map.buffer.as_ptr() as u64));
+        let references_offset =
+            self.references.as_ptr() as u64 - self.bit_map.buffer.as_ptr() as u64;
+        let references_length = self.references.len();
+        self.references = slice_from_raw_parts_mut(
+            (bit_map_buffer_address + references_offset) as *mut u16,
+            references_length,
+        )
+        .as_mut()
+        .unwrap();
         let bit_map_buffer_size = self.bit_map.buffer.len();
         self.bit_map.buffer =
             slice_from_raw_parts_mut(bit_map_buffer_address as *mut u8, bit_map_buffer_size)
@@ -119,11 +192,29 @@ impl<'a> PageFrameAllocator<'a> {
     pub fn bit_map_buffer_address(&self) -> u64 {
         self.bit_map.buffer.as_ptr() as u64
     }
+
+    /// Returns the amount of requested pages for each purpose since the last reset.
+    pub fn audit(&self) -> FrameAudit {
+        self.audit
+    }
+
+    /// Clears the recorded amount of requested pages, e.g. to ignore allocations of a previous boot stage.
+    pub fn reset_audit(&mut self) {
+        self.audit = FrameAudit::default();
+    }
 }
 
 impl<'a> PageFrameAllocator<'a> {
     /// Returns any available free page
     pub fn request_page(&mut self) -> Result<PhysicalAddress, PageFrameAllocatorError> {
+        self.request_page_for(FramePurpose::Other)
+    }
+
+    /// Returns any available free page and records it with the specified purpose.
+    pub fn request_page_for(
+        &mut self,
+        purpose: FramePurpose,
+    ) -> Result<PhysicalAddress, PageFrameAllocatorError> {
         for desc_index in self.current_descriptor_index..self.memory_map.descriptors().len() {
             let desc = &self.memory_map.descriptors()[desc_index];
             if desc.r#type == MemoryType::Available {
@@ -133,6 +224,7 @@ impl<'a> PageFrameAllocator<'a> {
                     let index = addr / PAGE_SIZE as u64;
                     if !self.bit_map.get(index)? {
                         self.allocate_frame(addr)?;
+                        self.audit.record(purpose);
                         self.current_descriptor_index = desc_index;
                         self.current_address = addr + PAGE_SIZE as u64;
                         return Ok(addr);
@@ -147,6 +239,33 @@ impl<'a> PageFrameAllocator<'a> {
         // todo: page frame swap
         Err(PageFrameAllocatorError::NoMoreFreePages)
     }
+
+    /// Returns a free page that ends below the specified physical address, e.g. for code that has to run in real mode. The first page is never returned.
+    pub fn request_page_below(
+        &mut self,
+        limit: PhysicalAddress,
+        purpose: FramePurpose,
+    ) -> Result<PhysicalAddress, PageFrameAllocatorError> {
+        let memory_map = self.memory_map;
+        for desc in memory_map.descriptors() {
+            if desc.r#type != MemoryType::Available {
+                continue;
+            }
+            let start = desc.phys_start.max(PAGE_SIZE as u64);
+            let end = desc.phys_end.min(limit);
+            for addr in (start..end).step_by(PAGE_SIZE) {
+                if addr + PAGE_SIZE as u64 > end {
+                    break;
+                }
+                if !self.bit_map.get(addr / PAGE_SIZE as u64)? {
+                    self.allocate_frame(addr)?;
+                    self.audit.record(purpose);
+                    return Ok(addr);
+                }
+            }
+        }
+        Err(PageFrameAllocatorError::NoMoreFreePages)
+    }
 }
 
 impl PageFrameAllocator<'_> {
@@ -160,6 +279,14 @@ impl PageFrameAllocator<'_> {
             return Ok(());
         }
 
+        self.mark_allocated(index)?;
+        self.free_lists.take_frame(index as u32);
+
+        Ok(())
+    }
+
+    /// Marks the free frame with the index as used, without taking it out of the free lists.
+    fn mark_allocated(&mut self, index: u64) -> Result<(), PageFrameAllocatorError> {
         self.bit_map.set(index, true)?;
         self.free_memory -= PAGE_SIZE as u64;
         self.used_memory += PAGE_SIZE as u64;
@@ -186,13 +313,74 @@ impl PageFrameAllocator<'_> {
             return Ok(());
         }
 
+        self.mark_free(index)?;
+        self.free_lists.insert(index as u32, 0);
+
+        Ok(())
+    }
+
+    /// Marks the used frame with the index as free, without adding it to the free lists.
+    fn mark_free(&mut self, index: u64) -> Result<(), PageFrameAllocatorError> {
         self.bit_map.set(index, false)?;
+        if let Some(references) = self.references.get_mut(index as usize) {
+            *references = 0;
+        }
         self.free_memory += PAGE_SIZE as u64;
         self.used_memory -= PAGE_SIZE as u64;
 
         Ok(())
     }
 
+    /// Adds a reference to an allocated frame, e.g. when it is mapped into another address space as well. The frame is only freed by [`PageFrameAllocator::release_frame`] once all references have been released.
+    pub fn share_frame(&mut self, address: PhysicalAddress) -> Result<(), PageFrameAllocatorError> {
+        let index = address / PAGE_SIZE as u64;
+        if !self.bit_map.get(index)? {
+            return Err(PageFrameAllocatorError::FrameNotAllocated);
+        }
+        let references = self
+            .references
+            .get_mut(index as usize)
+            .ok_or(PageFrameAllocatorError::InvalidBitMapIndex)?;
+        *references = references
+            .checked_add(1)
+            .ok_or(PageFrameAllocatorError::TooManyReferences)?;
+
+        Ok(())
+    }
+
+    /// Returns the amount of references to the frame, 0 if it is free.
+    pub fn references(&self, address: PhysicalAddress) -> Result<u64, PageFrameAllocatorError> {
+        let index = address / PAGE_SIZE as u64;
+        if !self.bit_map.get(index)? {
+            return Ok(0);
+        }
+        let references = self
+            .references
+            .get(index as usize)
+            .ok_or(PageFrameAllocatorError::InvalidBitMapIndex)?;
+
+        Ok(u64::from(*references) + 1)
+    }
+
+    /// Releases a reference to the frame and frees it, once no other references are left. Returns whether the frame has been freed.
+    pub fn release_frame(
+        &mut self,
+        address: PhysicalAddress,
+    ) -> Result<bool, PageFrameAllocatorError> {
+        let index = address / PAGE_SIZE as u64;
+        let references = self
+            .references
+            .get_mut(index as usize)
+            .ok_or(PageFrameAllocatorError::InvalidBitMapIndex)?;
+        if *references > 0 {
+            *references -= 1;
+            return Ok(false);
+        }
+
+        self.free_frame(address)?;
+        Ok(true)
+    }
+
     pub fn free_frames(
         &mut self,
         start_address: PhysicalAddress,
@@ -216,6 +404,7 @@ impl PageFrameAllocator<'_> {
         }
 
         self.bit_map.set(index, true)?;
+        self.free_lists.take_frame(index as u32);
         self.free_memory -= PAGE_SIZE as u64;
         self.reserved_memory += PAGE_SIZE as u64;
 
@@ -245,6 +434,8 @@ impl PageFrameAllocator<'_> {
         }
 
         self.bit_map.set(index, false)?;
+        // e.g. the boot stack, which is handed out like available memory afterward
+        self.free_lists.insert(index as u32, 0);
         self.free_memory += PAGE_SIZE as u64;
         self.reserved_memory -= PAGE_SIZE as u64;
 
@@ -284,6 +475,14 @@ pub enum PageFrameAllocatorError {
     InvalidBitMapIndex,
     InvalidMemoryMap,
     NoMoreFreePages,
+    FrameNotAllocated,
+    TooManyReferences,
+    /// Contiguous blocks are at most [`buddy::MAX_ORDER`] large.
+    InvalidOrder(usize),
+    /// The address of a contiguous block or huge page is not aligned to its size.
+    MisalignedBlock(PhysicalAddress),
+    /// A page is mapped into a huge page, or a huge page over a page table.
+    MappingConflict,
 }
 
 impl Display for PageFrameAllocatorError {
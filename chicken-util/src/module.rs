@@ -0,0 +1,89 @@
+use core::{
+    fmt::{Debug, Formatter},
+    slice, str,
+};
+
+use crate::memory::PhysicalAddress;
+
+/// Maximum length of a module name in bytes.
+pub const MODULE_NAME_LENGTH: usize = 64;
+/// File name of the splash image, which is loaded as a module if the splash screen is enabled.
+pub const SPLASH_MODULE_NAME: &str = "splash.bmp";
+
+/// Additional file loaded by the loader, e.g. an initrd, a user program or a configuration file.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ModuleDescriptor {
+    /// File name of the module, padded with zeros
+    pub name: [u8; MODULE_NAME_LENGTH],
+    /// Page aligned address of the module data
+    pub address: PhysicalAddress,
+    /// Size of the module data in bytes
+    pub size: u64,
+}
+
+impl ModuleDescriptor {
+    /// Creates a new module descriptor. Names longer than [`MODULE_NAME_LENGTH`] are truncated.
+    pub fn new(name: &str, address: PhysicalAddress, size: u64) -> Self {
+        let mut buffer = [0; MODULE_NAME_LENGTH];
+        // truncate at a character boundary, so the name stays valid utf-8
+        let mut length = name.len().min(MODULE_NAME_LENGTH);
+        while !name.is_char_boundary(length) {
+            length -= 1;
+        }
+        buffer[..length].copy_from_slice(&name.as_bytes()[..length]);
+        Self {
+            name: buffer,
+            address,
+            size,
+        }
+    }
+
+    /// File name of the module.
+    pub fn name(&self) -> &str {
+        let length = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(MODULE_NAME_LENGTH);
+        str::from_utf8(&self.name[..length]).unwrap_or_default()
+    }
+}
+
+impl Debug for ModuleDescriptor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Module Descriptor {{ name: {}, address: {:#x}, size: {} }}",
+            self.name(),
+            self.address,
+            self.size
+        )
+    }
+}
+
+/// List of modules loaded by the loader.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ModuleList {
+    /// Pointer to module descriptors
+    pub descriptors: *mut ModuleDescriptor,
+    /// Amount of module descriptors
+    pub descriptors_len: u64,
+}
+
+impl ModuleList {
+    pub fn descriptors(&self) -> &[ModuleDescriptor] {
+        if self.descriptors.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.descriptors, self.descriptors_len as usize) }
+    }
+
+    pub fn descriptors_mut(&mut self) -> &mut [ModuleDescriptor] {
+        if self.descriptors.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(self.descriptors, self.descriptors_len as usize) }
+    }
+}
@@ -0,0 +1,83 @@
+use core::str;
+
+/// Digits of the largest `u64` in decimal notation.
+const MAX_DECIMAL_LENGTH: usize = 20;
+/// Sign and digits of the smallest `i64` in decimal notation.
+const MAX_SIGNED_LENGTH: usize = MAX_DECIMAL_LENGTH + 1;
+/// Prefix and digits of the largest `u64` in hexadecimal notation.
+const MAX_HEX_LENGTH: usize = 2 + 16;
+/// Large enough for every supported notation.
+const BUFFER_LENGTH: usize = MAX_SIGNED_LENGTH;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Stack buffer to format integers without the `core::fmt` machinery, which is large and slow for interrupt handlers and panics. Similar to the `itoa` crate.
+///
+/// The returned strings borrow the buffer, so a buffer can be reused once the previous string is no longer needed.
+#[derive(Copy, Clone, Debug)]
+pub struct NumberBuffer {
+    bytes: [u8; BUFFER_LENGTH],
+}
+
+impl NumberBuffer {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; BUFFER_LENGTH],
+        }
+    }
+
+    /// Formats the value in decimal notation, like `{}`.
+    pub fn decimal(&mut self, value: u64) -> &str {
+        let start = self.write_decimal(value, MAX_DECIMAL_LENGTH);
+        self.as_str(start, MAX_DECIMAL_LENGTH)
+    }
+
+    /// Formats the value in decimal notation with a leading `-` for negative values, like `{}`.
+    pub fn signed(&mut self, value: i64) -> &str {
+        let mut start = self.write_decimal(value.unsigned_abs(), MAX_SIGNED_LENGTH);
+        if value < 0 {
+            start -= 1;
+            self.bytes[start] = b'-';
+        }
+        self.as_str(start, MAX_SIGNED_LENGTH)
+    }
+
+    /// Formats the value in lowercase hexadecimal notation with a `0x` prefix, like `{:#x}`.
+    pub fn hex(&mut self, mut value: u64) -> &str {
+        let mut start = MAX_HEX_LENGTH;
+        loop {
+            start -= 1;
+            self.bytes[start] = HEX_DIGITS[(value & 0xF) as usize];
+            value >>= 4;
+            if value == 0 {
+                break;
+            }
+        }
+        self.bytes[start - 2..start].copy_from_slice(b"0x");
+        self.as_str(start - 2, MAX_HEX_LENGTH)
+    }
+
+    /// Writes the digits right-aligned so they end at `end`. Returns the index of the first digit.
+    fn write_decimal(&mut self, mut value: u64, end: usize) -> usize {
+        let mut start = end;
+        loop {
+            start -= 1;
+            self.bytes[start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break start;
+            }
+        }
+    }
+
+    fn as_str(&self, start: usize, end: usize) -> &str {
+        // only ascii digits, signs and prefixes are written
+        unsafe { str::from_utf8_unchecked(&self.bytes[start..end]) }
+    }
+}
+
+impl Default for NumberBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
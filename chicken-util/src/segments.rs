@@ -0,0 +1,87 @@
+use bitflags::bitflags;
+
+use crate::{memory::VirtualAddress, PAGE_SIZE};
+
+/// Maximum amount of loadable segments of the kernel image, that the loader passes on.
+pub const MAX_KERNEL_SEGMENTS: usize = 8;
+
+bitflags! {
+    /// Permissions of a segment, as given by the flags of its elf program header.
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+    pub struct SegmentFlags: u32 {
+        const EXECUTE = 1 << 0;
+        const WRITE   = 1 << 1;
+        const READ    = 1 << 2;
+    }
+}
+
+/// Loadable segment of the kernel image, e.g. the code or the writable data.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct KernelSegment {
+    /// Virtual address the segment starts at
+    pub virtual_address: VirtualAddress,
+    /// Size of the segment in memory in bytes, including the zeroed part
+    pub size: u64,
+    pub flags: SegmentFlags,
+}
+
+impl KernelSegment {
+    /// Whether the segment covers part of the page containing the virtual address.
+    pub fn covers_page(&self, virtual_address: VirtualAddress) -> bool {
+        let page = virtual_address & !(PAGE_SIZE as u64 - 1);
+        self.virtual_address < page + PAGE_SIZE as u64 && page < self.virtual_address + self.size
+    }
+}
+
+/// Loadable segments of the kernel image, so the kernel can map each of them with its own permissions. Empty, if the loader has not passed them on.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct KernelSegments {
+    pub segments: [KernelSegment; MAX_KERNEL_SEGMENTS],
+    /// Amount of valid segments at the start of the array
+    pub count: u64,
+}
+
+impl KernelSegments {
+    pub const fn new() -> Self {
+        Self {
+            segments: [KernelSegment {
+                virtual_address: 0,
+                size: 0,
+                flags: SegmentFlags::empty(),
+            }; MAX_KERNEL_SEGMENTS],
+            count: 0,
+        }
+    }
+
+    /// Appends the segment. Fails, if there are [`MAX_KERNEL_SEGMENTS`] already.
+    pub fn push(&mut self, segment: KernelSegment) -> Result<(), KernelSegment> {
+        let Some(slot) = self.segments.get_mut(self.count as usize) else {
+            return Err(segment);
+        };
+        *slot = segment;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn segments(&self) -> &[KernelSegment] {
+        &self.segments[..(self.count as usize).min(MAX_KERNEL_SEGMENTS)]
+    }
+
+    /// Returns the flags of the page containing the virtual address. Pages shared by several segments get the permissions of all of them, pages outside of every segment are `None`.
+    pub fn page_flags(&self, virtual_address: VirtualAddress) -> Option<SegmentFlags> {
+        self.segments()
+            .iter()
+            .filter(|segment| segment.covers_page(virtual_address))
+            .map(|segment| segment.flags)
+            .reduce(|flags, other| flags | other)
+    }
+}
+
+impl Default for KernelSegments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
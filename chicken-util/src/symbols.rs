@@ -0,0 +1,68 @@
+use core::{slice, str};
+
+/// Function of the kernel image, resolved from the symbol table of the kernel file by the loader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct KernelSymbol {
+    /// Virtual address of the first instruction of the function
+    pub address: u64,
+    /// Size of the function in bytes, 0 if it is unknown
+    pub size: u64,
+    /// Offset of the demangled name in the string table
+    pub name_offset: u32,
+    /// Length of the demangled name in bytes
+    pub name_length: u32,
+}
+
+/// Functions of the kernel image sorted by address and the string table with their names, so the kernel can resolve addresses in backtraces.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct KernelSymbols {
+    /// Pointer to the symbols, sorted by address
+    pub symbols: *mut KernelSymbol,
+    /// Amount of symbols
+    pub symbols_len: u64,
+    /// Pointer to the names of the symbols, which are valid utf-8
+    pub strings: *mut u8,
+    /// Size of the string table in bytes
+    pub strings_size: u64,
+}
+
+impl KernelSymbols {
+    pub fn symbols(&self) -> &[KernelSymbol] {
+        if self.symbols.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.symbols, self.symbols_len as usize) }
+    }
+
+    pub fn strings(&self) -> &[u8] {
+        if self.strings.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.strings, self.strings_size as usize) }
+    }
+
+    /// Returns the name of the function containing the address and the offset of the address within the function.
+    pub fn resolve(&self, address: u64) -> Option<(&str, u64)> {
+        let symbols = self.symbols();
+        // the last function starting at or before the address
+        let index = symbols
+            .partition_point(|symbol| symbol.address <= address)
+            .checked_sub(1)?;
+        let symbol = &symbols[index];
+        let offset = address - symbol.address;
+        if symbol.size != 0 && offset >= symbol.size {
+            return None;
+        }
+
+        let start = symbol.name_offset as usize;
+        let name = self
+            .strings()
+            .get(start..start + symbol.name_length as usize)?;
+        Some((str::from_utf8(name).ok()?, offset))
+    }
+}
+
+unsafe impl Send for KernelSymbols {}
+unsafe impl Sync for KernelSymbols {}
@@ -0,0 +1,27 @@
+use core::arch::asm;
+
+/// Reads the time stamp counter of the current cpu. The loader and the kernel both use it, so their timestamps can be compared, as long as the counter is invariant.
+pub fn read_tsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Time stamp counter values recorded by the loader at the end of each boot phase.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LoaderTimestamps {
+    /// Entry of the loader.
+    pub start: u64,
+    /// Kernel file has been read from the filesystem.
+    pub kernel_file_loaded: u64,
+    /// Kernel elf has been parsed and loaded into memory.
+    pub kernel_elf_parsed: u64,
+    /// Boot services have been exited.
+    pub boot_services_exited: u64,
+    /// Address space for the kernel has been set up.
+    pub address_space_set_up: u64,
+}